@@ -0,0 +1,140 @@
+//! Tone adjustments of a finished image: exposure, contrast, brightness and
+//! gamma. They work on the colors alone, one channel at a time, so they can
+//! tune an image after it is colored, or any RGB buffer at all, without its
+//! escape times. Every channel value maps to one other, so the four are
+//! folded into a table of 256 values before the pixels are run through it.
+//!
+//! They apply in that order: exposure in linear light, as a camera's would,
+//! then contrast about middle gray, brightness and gamma on the sRGB values.
+
+use crate::palette::{decode_srgb, encode_srgb};
+
+/// How to adjust an image's tones. The default leaves them as they are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjustments {
+    /// Stops of light to add, or take away if negative, each doubling or
+    /// halving the light.
+    pub exposure: f64,
+    /// How much further from middle gray values are pulled: 1 keeps them,
+    /// 0 flattens the image to gray.
+    pub contrast: f64,
+    /// A share of white added to every value, from -1 to 1.
+    pub brightness: f64,
+    /// Raises values to `1 / gamma`, brightening the midtones above 1 and
+    /// darkening them below, which must be above 0.
+    pub gamma: f64,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Adjustments {
+            exposure: 0.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Adjustments {
+    /// Whether they change nothing.
+    pub fn is_identity(&self) -> bool {
+        *self == Adjustments::default()
+    }
+
+    /// What each channel value becomes.
+    pub fn table(&self) -> [u8; 256] {
+        let gain = self.exposure.exp2();
+        std::array::from_fn(|v| {
+            let v = encode_srgb(decode_srgb(v as u8) * gain) as f64 / 255.0;
+            let v = (v - 0.5) * self.contrast + 0.5 + self.brightness;
+            let v = v.clamp(0.0, 1.0).powf(1.0 / self.gamma);
+            (v * 255.0).round() as u8
+        })
+    }
+
+    /// Adjusts the channels of RGB `pixels` in place.
+    pub fn apply(&self, pixels: &mut [u8]) {
+        if self.is_identity() {
+            return;
+        }
+        let table = self.table();
+        for v in pixels {
+            *v = table[*v as usize];
+        }
+    }
+}
+
+#[test]
+fn test_adjustments() {
+    let identity = Adjustments::default();
+    assert!(identity.is_identity());
+    assert!(identity
+        .table()
+        .iter()
+        .enumerate()
+        .all(|(i, &v)| v == i as u8));
+    // One stop doubles the light.
+    let brighter = Adjustments {
+        exposure: 1.0,
+        ..identity
+    }
+    .table();
+    assert_eq!(brighter[0], 0);
+    assert_eq!(brighter[128], encode_srgb(decode_srgb(128) * 2.0));
+    assert_eq!(brighter[255], 255);
+    let flat = Adjustments {
+        contrast: 0.0,
+        ..identity
+    }
+    .table();
+    assert!(flat.iter().all(|&v| v == 128));
+    let lifted = Adjustments {
+        brightness: 0.2,
+        ..identity
+    }
+    .table();
+    assert_eq!((lifted[0], lifted[100], lifted[220]), (51, 151, 255));
+    let gamma = Adjustments {
+        gamma: 2.0,
+        ..identity
+    }
+    .table();
+    assert_eq!((gamma[0], gamma[64], gamma[255]), (0, 128, 255));
+
+    let mut pixels = vec![64, 0, 255];
+    Adjustments {
+        gamma: 2.0,
+        ..identity
+    }
+    .apply(&mut pixels);
+    assert_eq!(pixels, [128, 0, 255]);
+}
+
+#[test]
+fn test_render_adjusted() {
+    use crate::{RenderOptions, Renderer};
+    let options = RenderOptions {
+        bounds: (24, 16),
+        ..RenderOptions::default()
+    };
+    let adjustments = Adjustments {
+        exposure: -0.5,
+        contrast: 1.5,
+        ..Adjustments::default()
+    };
+    for antialias in [1, 2] {
+        let plain = RenderOptions {
+            antialias,
+            ..options.clone()
+        };
+        let mut expected = Renderer::new(plain.clone()).render();
+        adjustments.apply(&mut expected);
+        let renderer = Renderer::new(RenderOptions {
+            adjustments,
+            ..plain
+        });
+        assert_eq!(renderer.render(), expected);
+        assert_eq!(renderer.rows().flatten().collect::<Vec<_>>(), expected);
+    }
+}
@@ -0,0 +1,269 @@
+//! Zoom animations: a sequence of views stepping from one center and zoom
+//! to another, each rendered as a frame. Julia morphs instead hold the view
+//! and move the constant of a Julia set.
+
+use crate::{fixed, Fixed, Precision, RenderOptions};
+use num::Complex;
+
+/// A view of the plane by its center and its magnification relative to a
+/// view 4 high, as in the image metadata, which fits any image size.
+/// `flipped` draws it upside down, as `--flip` does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+    pub center: Complex<Fixed>,
+    pub zoom: f64,
+    pub flipped: bool,
+}
+
+impl View {
+    /// The upper left and lower right corners of the view in an image of
+    /// `bounds` pixels, to as many bits as its pixels need. Those of a
+    /// flipped view have the upper left corner below the lower right one.
+    pub fn corners(&self, bounds: (u32, u32)) -> (Complex<Fixed>, Complex<Fixed>) {
+        let height = 4.0 / self.zoom;
+        let pixel_size = height / bounds.1.max(1) as f64;
+        let bits = fixed::bits_for_pixel_size(pixel_size)
+            .max(self.center.re.bits())
+            .max(self.center.im.bits());
+        let re = self.center.re.with_bits(bits);
+        let im = self.center.im.with_bits(bits);
+        let half_width = Fixed::from_f64(pixel_size * bounds.0 as f64 / 2.0, bits);
+        let half_height = Fixed::from_f64(height / 2.0, bits);
+        let (top, bottom) = (&im + &half_height, &im - &half_height);
+        let (top, bottom) = match self.flipped {
+            true => (bottom, top),
+            false => (top, bottom),
+        };
+        (
+            Complex {
+                re: &re - &half_width,
+                im: top,
+            },
+            Complex {
+                re: &re + &half_width,
+                im: bottom,
+            },
+        )
+    }
+
+    /// Points `options` at this view, keeping its size. Perturbation gets
+    /// as many bits as the new pixel size needs.
+    pub fn apply(&self, options: &mut RenderOptions) {
+        let (upper_left, lower_right) = self.corners(options.bounds);
+        let to_f64 = |z: &Complex<Fixed>| Complex {
+            re: z.re.to_f64(),
+            im: z.im.to_f64(),
+        };
+        options.upper_left = to_f64(&upper_left);
+        options.lower_right = to_f64(&lower_right);
+        options.exact_corners = Some((upper_left, lower_right));
+        if let Precision::Perturbation(_) = options.precision {
+            options.precision =
+                Precision::Perturbation(fixed::bits_for_pixel_size(options.pixel_size()));
+        }
+    }
+}
+
+/// The `frames` views of a zoom from `start` to `end`, both included. The
+/// zoom changes by the same factor from one frame to the next, and the
+/// center moves in proportion to the change in view height. Together they
+/// zoom in about a single point, which holds still on screen, and end
+/// centered on `end`. Every view is flipped if `start` is.
+pub fn zoom_path(start: &View, end: &View, frames: u32) -> Vec<View> {
+    let (start_height, end_height) = (1.0 / start.zoom, 1.0 / end.zoom);
+    let bits = [
+        &start.center.re,
+        &start.center.im,
+        &end.center.re,
+        &end.center.im,
+    ]
+    .iter()
+    .map(|x| x.bits())
+    .max()
+    .unwrap();
+    let rescale = |z: &Complex<Fixed>| Complex {
+        re: z.re.with_bits(bits),
+        im: z.im.with_bits(bits),
+    };
+    let (from, to) = (rescale(&start.center), rescale(&end.center));
+    let offset = Complex {
+        re: &from.re - &to.re,
+        im: &from.im - &to.im,
+    };
+    (0..frames)
+        .map(|frame| {
+            let t = match frames {
+                1 => 0.0,
+                _ => frame as f64 / (frames - 1) as f64,
+            };
+            let zoom = start.zoom * (end.zoom / start.zoom).powf(t);
+            // How much of the way back from the end center this frame is:
+            // linear when only panning, else in step with the view height.
+            let remaining = if start_height == end_height {
+                1.0 - t
+            } else {
+                (1.0 / zoom - end_height) / (start_height - end_height)
+            };
+            let remaining = Fixed::from_f64(remaining.clamp(0.0, 1.0), bits);
+            View {
+                center: Complex {
+                    re: &to.re + &(&offset.re * &remaining),
+                    im: &to.im + &(&offset.im * &remaining),
+                },
+                zoom,
+                flipped: start.flipped,
+            }
+        })
+        .collect()
+}
+
+/// How the constant of a Julia set moves over a morph.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JuliaPath {
+    /// Once around the edge of the main cardioid, where the connected
+    /// Julia sets change shape the most. The path is a loop, so the last
+    /// frame leads back into the first.
+    Cardioid,
+    /// Straight lines from one constant to the next, at a steady speed.
+    Waypoints(Vec<Complex<f64>>),
+}
+
+impl JuliaPath {
+    /// The constants of `frames` evenly spaced frames along the path.
+    pub fn constants(&self, frames: u32) -> Vec<Complex<f64>> {
+        match self {
+            JuliaPath::Cardioid => (0..frames)
+                .map(|frame| {
+                    let angle = std::f64::consts::TAU * frame as f64 / frames as f64;
+                    let mu = Complex::from_polar(1.0, angle);
+                    mu / 2.0 - mu * mu / 4.0
+                })
+                .collect(),
+            JuliaPath::Waypoints(points) => {
+                let lengths = points
+                    .windows(2)
+                    .map(|pair| (pair[1] - pair[0]).norm())
+                    .collect::<Vec<_>>();
+                let total = lengths.iter().sum::<f64>();
+                (0..frames)
+                    .map(|frame| {
+                        let mut distance = match frames {
+                            1 => 0.0,
+                            _ => total * frame as f64 / (frames - 1) as f64,
+                        };
+                        for (pair, &length) in points.windows(2).zip(&lengths) {
+                            if distance <= length && length > 0.0 {
+                                return pair[0] + (pair[1] - pair[0]) * (distance / length);
+                            }
+                            distance -= length;
+                        }
+                        *points.last().expect("a path has at least one waypoint")
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[test]
+fn test_corners() {
+    let view = View {
+        center: Complex {
+            re: Fixed::parse("-0.6").unwrap(),
+            im: Fixed::parse("0").unwrap(),
+        },
+        zoom: 1.6,
+        flipped: false,
+    };
+    let (upper_left, lower_right) = view.corners((400, 200));
+    assert!((upper_left.re.to_f64() - -3.1).abs() < 1e-12);
+    assert!((upper_left.im.to_f64() - 1.25).abs() < 1e-12);
+    assert!((lower_right.re.to_f64() - 1.9).abs() < 1e-12);
+    assert!((lower_right.im.to_f64() - -1.25).abs() < 1e-12);
+    let flipped = View {
+        flipped: true,
+        ..view.clone()
+    };
+    let (upper_left, lower_right) = flipped.corners((400, 200));
+    assert!((upper_left.re.to_f64() - -3.1).abs() < 1e-12);
+    assert!((upper_left.im.to_f64() - -1.25).abs() < 1e-12);
+    assert!((lower_right.im.to_f64() - 1.25).abs() < 1e-12);
+
+    let mut options = RenderOptions {
+        bounds: (400, 200),
+        precision: Precision::Perturbation(0),
+        ..RenderOptions::default()
+    };
+    View { zoom: 1e20, ..view }.apply(&mut options);
+    match options.precision {
+        Precision::Perturbation(bits) => assert!(bits > 64 + 66, "{}", bits),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_zoom_path() {
+    let point = |re: &str, im: &str| Complex {
+        re: Fixed::parse(re).unwrap(),
+        im: Fixed::parse(im).unwrap(),
+    };
+    let start = View {
+        center: point("-0.5", "0"),
+        zoom: 1.0,
+        flipped: false,
+    };
+    let end = View {
+        center: point("-0.75", "0.1"),
+        zoom: 1000.0,
+        flipped: false,
+    };
+    let path = zoom_path(&start, &end, 4);
+    let zooms = path.iter().map(|v| v.zoom.round()).collect::<Vec<_>>();
+    assert_eq!(zooms, vec![1.0, 10.0, 100.0, 1000.0]);
+    assert!((path[0].center.re.to_f64() - -0.5).abs() < 1e-12);
+    assert!((path[3].center.re.to_f64() - -0.75).abs() < 1e-12);
+    assert!((path[3].center.im.to_f64() - 0.1).abs() < 1e-12);
+    // The point zoomed in on sits at the same place in every frame: its
+    // offset from each frame's center stays the same fraction of the view
+    // height.
+    let still = -0.75 - 0.25 * 0.001 / 0.999;
+    for view in &path {
+        let offset = (still - view.center.re.to_f64()) * view.zoom;
+        assert!((offset - -0.25 / 0.999).abs() < 1e-9, "{}", offset);
+    }
+
+    let pan = zoom_path(
+        &start,
+        &View {
+            zoom: 1.0,
+            ..end.clone()
+        },
+        3,
+    );
+    assert!((pan[1].center.re.to_f64() - -0.625).abs() < 1e-12);
+    let single = zoom_path(&start, &end, 1);
+    assert_eq!((single.len(), single[0].zoom), (1, 1.0));
+}
+
+#[test]
+fn test_julia_path() {
+    let close = |a: Complex<f64>, re: f64, im: f64| (a.re - re).abs() + (a.im - im).abs() < 1e-12;
+    let cardioid = JuliaPath::Cardioid.constants(4);
+    assert_eq!(cardioid.len(), 4);
+    assert!(close(cardioid[0], 0.25, 0.0), "{}", cardioid[0]);
+    assert!(close(cardioid[2], -0.75, 0.0), "{}", cardioid[2]);
+    assert!(close(cardioid[1], cardioid[3].re, -cardioid[3].im));
+
+    let path = JuliaPath::Waypoints(vec![
+        Complex { re: 0.0, im: 0.0 },
+        Complex { re: 1.0, im: 0.0 },
+        Complex { re: 1.0, im: 3.0 },
+    ]);
+    let constants = path.constants(5);
+    assert!(close(constants[0], 0.0, 0.0));
+    assert!(close(constants[1], 1.0, 0.0));
+    assert!(close(constants[2], 1.0, 1.0));
+    assert!(close(constants[4], 1.0, 3.0));
+    let still = JuliaPath::Waypoints(vec![Complex { re: 0.5, im: 0.5 }]);
+    assert_eq!(still.constants(2), vec![Complex { re: 0.5, im: 0.5 }; 2]);
+}
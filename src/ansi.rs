@@ -0,0 +1,83 @@
+//! Terminal previews: an image drawn with the upper half block character,
+//! whose foreground is one pixel and background the pixel below, in the 256
+//! colors nearly every terminal supports.
+
+use std::io::{self, Write};
+
+/// The six levels of each channel in the 6×6×6 color cube at index 16.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Draws an RGB pixel buffer, two rows of pixels to a line of text. An odd
+/// last row leaves the terminal's own background below it.
+pub fn encode<W: Write>(mut w: W, pixels: &[u8], bounds: (u32, u32)) -> io::Result<()> {
+    let width = bounds.0 as usize;
+    let color = |row: usize, column: usize| {
+        let i = 3 * (row * width + column);
+        color_index([pixels[i], pixels[i + 1], pixels[i + 2]])
+    };
+    for top in (0..bounds.1 as usize).step_by(2) {
+        for column in 0..width {
+            write!(w, "\x1b[38;5;{}m", color(top, column))?;
+            if top + 1 < bounds.1 as usize {
+                write!(w, "\x1b[48;5;{}m", color(top + 1, column))?;
+            }
+            w.write_all("▀".as_bytes())?;
+        }
+        w.write_all(b"\x1b[0m\n")?;
+    }
+    w.flush()
+}
+
+/// The closest of the 256 color palette's cube and gray ramp to `rgb`. The
+/// first 16 colors are left alone, since terminals theme them.
+pub fn color_index(rgb: [u8; 3]) -> u8 {
+    let level = |v: u8| match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (v - 35) / 40,
+    };
+    let [r, g, b] = rgb.map(level);
+    let cube = [CUBE[r as usize], CUBE[g as usize], CUBE[b as usize]];
+    let mean = rgb.iter().map(|&v| v as u32).sum::<u32>() / 3;
+    let step = (mean.saturating_sub(3) / 10).min(23) as u8;
+    let gray = 8 + 10 * step;
+    let distance = |color: [u8; 3]| {
+        color
+            .iter()
+            .zip(rgb)
+            .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+            .sum::<i32>()
+    };
+    if distance([gray; 3]) < distance(cube) {
+        232 + step
+    } else {
+        16 + 36 * r + 6 * g + b
+    }
+}
+
+#[test]
+fn test_color_index() {
+    assert_eq!(color_index([0, 0, 0]), 16);
+    assert_eq!(color_index([255, 255, 255]), 231);
+    assert_eq!(color_index([255, 0, 0]), 196);
+    assert_eq!(color_index([0, 95, 255]), 27);
+    assert_eq!(color_index([128, 128, 128]), 244);
+    assert_eq!(color_index([8, 8, 8]), 232);
+    assert_eq!(color_index([238, 238, 238]), 255);
+}
+
+#[test]
+fn test_encode() {
+    let white_over_black = [255, 255, 255, 0, 0, 0];
+    let mut out = Vec::new();
+    encode(&mut out, &white_over_black, (1, 2)).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\x1b[38;5;231m\x1b[48;5;16m▀\x1b[0m\n"
+    );
+    let mut out = Vec::new();
+    encode(&mut out, &[255, 0, 0, 255, 0, 0, 0, 0, 0], (1, 3)).unwrap();
+    assert!(String::from_utf8(out)
+        .unwrap()
+        .ends_with("\x1b[0m\n\x1b[38;5;16m▀\x1b[0m\n"));
+}
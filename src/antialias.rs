@@ -0,0 +1,303 @@
+//! Supersampled antialiasing. Each pixel is split into an `N`×`N` grid of
+//! cells and sampled once at a random spot inside every cell, so that edges
+//! are smoothed without the regular pattern of a plain grid showing through.
+//! The colors of the samples, not their escape times, are averaged.
+//!
+//! Most of an image is usually flat, so the adaptive variant renders one
+//! sample per pixel first and only supersamples pixels whose neighborhood
+//! varies.
+
+use crate::{colorize, Coloring, Renderer, UNRENDERED};
+
+/// Roughly how many samples are computed per batch of rows. Batches keep the
+/// memory for the samples bounded and let progress advance as rows finish.
+const BATCH_SAMPLES: usize = 1 << 18;
+
+/// The most cells a side a pixel is split into.
+pub const MAX_ANTIALIAS: u32 = 16;
+
+/// The samples per pixel for `antialias` cells a side.
+fn samples(antialias: u32) -> u32 {
+    let n = antialias.max(1);
+    n.checked_mul(n)
+        .expect("too many antialias samples per pixel")
+}
+
+/// Renders the image with `antialias`² samples per pixel, three row-major
+/// bytes per pixel like `Renderer::render`. A cancelled render stops after
+/// the batch of rows under way, and the rest are `UNRENDERED`.
+pub fn render(renderer: &Renderer) -> Vec<u8> {
+    let options = renderer.options();
+    let (width, height) = options.bounds;
+    let n = options.antialias.max(1);
+    let samples = samples(n) as usize;
+    // A histogram has to count the whole image before any of it is colored,
+    // and shading needs the slopes around each pixel, so both are taken from
+    // one sample per pixel.
+    let grid = if options.coloring == Coloring::Histogram || options.shading.is_some() {
+        renderer.escapes()
+    } else {
+        Vec::new()
+    };
+    let colorizer = renderer.colorizer(&grid);
+    let progress = renderer.progress();
+    progress.start(width as u64 * height as u64);
+    let batch_rows = (BATCH_SAMPLES / (width as usize * samples).max(1)).max(1) as u32;
+    let mut pixels = Vec::with_capacity(3 * width as usize * height as usize);
+    for top in (0..height).step_by(batch_rows as usize) {
+        if progress.is_cancelled() {
+            break;
+        }
+        let rows = top..(top + batch_rows).min(height);
+        let positions = rows
+            .clone()
+            .flat_map(|row| {
+                (0..width).flat_map(move |column| {
+                    (0..samples as u32).map(move |sample| sample_position(column, row, sample, n))
+                })
+            })
+            .collect::<Vec<_>>();
+        let escapes = renderer.render_points(&positions);
+        let mut colors = colorize(&escapes, &*colorizer);
+        renderer.color_interior_dithered(&mut colors, &escapes, |i| positions[i], None);
+        for pixel in colors.chunks(3 * samples) {
+            let index = pixels.len() / 3;
+            pixels.extend_from_slice(&round(renderer, average(pixel), index));
+        }
+        progress.add(rows.len() as u64 * width as u64);
+    }
+    renderer.shade(&mut pixels, &grid);
+    options.adjustments.apply(&mut pixels);
+    let rendered = pixels.len() / 3;
+    pixels.extend(UNRENDERED.repeat(width as usize * height as usize - rendered));
+    renderer.finished((rendered / width.max(1) as usize) as u32);
+    pixels
+}
+
+/// Renders the image at one sample per pixel, then redoes with `antialias`²
+/// samples just the pixels whose 3×3 neighborhood colors have a standard
+/// deviation above `threshold`. A cancelled render stops after the batch
+/// of pixels under way, leaving the rest at one sample, or `UNRENDERED` if
+/// it was cancelled before they all had that.
+pub fn render_adaptive(renderer: &Renderer, threshold: f64) -> Vec<u8> {
+    let options = renderer.options();
+    let n = options.antialias.max(1);
+    let samples = samples(n) as usize;
+    let escapes = renderer.escapes();
+    if escapes.len() < options.bounds.0 as usize * options.bounds.1 as usize {
+        renderer.finished(renderer.rows_in(&escapes));
+        return renderer.colorize(&escapes);
+    }
+    let colorizer = renderer.colorizer(&escapes);
+    let width = options.bounds.0.max(1) as usize;
+    let mut pixels = renderer.color_rows(&escapes, &*colorizer, 0);
+    renderer.color_interior(&mut pixels, &escapes, |i| {
+        ((i % width) as f64, (i / width) as f64)
+    });
+    let busy = busy_pixels(&pixels, options.bounds, threshold);
+    let progress = renderer.progress();
+    progress.extend(busy.len() as u64);
+    for batch in busy.chunks((BATCH_SAMPLES / samples).max(1)) {
+        if progress.is_cancelled() {
+            break;
+        }
+        let positions = batch
+            .iter()
+            .flat_map(|&index| {
+                let (column, row) = ((index % width) as u32, (index / width) as u32);
+                (0..samples as u32).map(move |sample| sample_position(column, row, sample, n))
+            })
+            .collect::<Vec<_>>();
+        let escapes = renderer.render_points(&positions);
+        let mut colors = colorize(&escapes, &*colorizer);
+        renderer.color_interior_dithered(&mut colors, &escapes, |i| positions[i], None);
+        for (&index, samples) in batch.iter().zip(colors.chunks(3 * samples)) {
+            pixels[3 * index..3 * index + 3].copy_from_slice(&round(
+                renderer,
+                average(samples),
+                index,
+            ));
+        }
+        progress.add(batch.len() as u64);
+    }
+    renderer.shade(&mut pixels, &escapes);
+    options.adjustments.apply(&mut pixels);
+    renderer.finished(options.bounds.1);
+    pixels
+}
+
+/// Indices of the pixels whose 3×3 neighborhood, cut off at the image
+/// edges, has a per-channel standard deviation above `threshold` on
+/// average.
+fn busy_pixels(pixels: &[u8], bounds: (u32, u32), threshold: f64) -> Vec<usize> {
+    let (width, height) = (bounds.0 as usize, bounds.1 as usize);
+    let mut busy = Vec::new();
+    for row in 0..height {
+        for column in 0..width {
+            let mut sums = [0.0; 3];
+            let mut squares = [0.0; 3];
+            let mut count = 0.0;
+            for y in row.saturating_sub(1)..(row + 2).min(height) {
+                for x in column.saturating_sub(1)..(column + 2).min(width) {
+                    let offset = 3 * (y * width + x);
+                    for k in 0..3 {
+                        let channel = pixels[offset + k] as f64;
+                        sums[k] += channel;
+                        squares[k] += channel * channel;
+                    }
+                    count += 1.0;
+                }
+            }
+            let variance = (0..3)
+                .map(|k| squares[k] / count - (sums[k] / count).powi(2))
+                .sum::<f64>()
+                / 3.0;
+            if variance > threshold * threshold {
+                busy.push(row * width + column);
+            }
+        }
+    }
+    busy
+}
+
+/// Where sample number `sample` of the `n`×`n` for a pixel lands, in pixels
+/// from the upper left corner of the image.
+pub fn sample_position(column: u32, row: u32, sample: u32, n: u32) -> (f64, f64) {
+    let (dx, dy) = jitter(column, row, sample);
+    (
+        column as f64 + ((sample % n) as f64 + dx) / n as f64,
+        row as f64 + ((sample / n) as f64 + dy) / n as f64,
+    )
+}
+
+/// A pseudo-random offset in `[0, 1)²`, the same on every run so renders
+/// are reproducible.
+fn jitter(column: u32, row: u32, sample: u32) -> (f64, f64) {
+    let mut state = (row as u64) << 40 ^ (column as u64) << 16 ^ sample as u64;
+    let mut next = || {
+        // SplitMix64.
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) >> 11
+    };
+    let unit = (1u64 << 53) as f64;
+    (next() as f64 / unit, next() as f64 / unit)
+}
+
+/// The mean of a run of RGB colors.
+fn average(colors: &[u8]) -> [f64; 3] {
+    let count = (colors.len() / 3).max(1) as u32;
+    let mut sums = [0u32; 3];
+    for color in colors.chunks(3) {
+        for (sum, &channel) in sums.iter_mut().zip(color) {
+            *sum += channel as u32;
+        }
+    }
+    sums.map(|sum| sum as f64 / count as f64)
+}
+
+/// Rounds the mean color of pixel `index` of `renderer`'s image to bytes,
+/// dithered if the render asks.
+fn round(renderer: &Renderer, color: [f64; 3], index: usize) -> [u8; 3] {
+    let options = renderer.options();
+    match options.dither {
+        Some(dither) => {
+            let width = options.bounds.0.max(1) as usize;
+            dither.quantize(color, ((index % width) as u32, (index / width) as u32))
+        }
+        None => color.map(|v| v.round() as u8),
+    }
+}
+
+#[test]
+fn test_sample_positions_stay_in_their_cells() {
+    for sample in 0..9 {
+        let (x, y) = sample_position(7, 3, sample, 3);
+        let cell = ((sample % 3) as f64, (sample / 3) as f64);
+        assert!((7.0 + cell.0 / 3.0..7.0 + (cell.0 + 1.0) / 3.0).contains(&x));
+        assert!((3.0 + cell.1 / 3.0..3.0 + (cell.1 + 1.0) / 3.0).contains(&y));
+    }
+    assert_ne!(sample_position(0, 0, 0, 2), sample_position(1, 0, 0, 2));
+    assert_eq!(sample_position(5, 5, 1, 2), sample_position(5, 5, 1, 2));
+    assert_eq!(average(&[0, 10, 255, 255, 11, 255]), [127.5, 10.5, 255.0]);
+}
+
+#[test]
+fn test_antialiased_render() {
+    use crate::{Palette, RenderOptions};
+    use num::Complex;
+
+    let options = RenderOptions {
+        bounds: (40, 30),
+        upper_left: Complex { re: -2.0, im: 1.2 },
+        lower_right: Complex { re: 0.6, im: -1.2 },
+        palette: Palette::named("grayscale").unwrap(),
+        smooth: false,
+        antialias: 3,
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let aliased = Renderer::new(RenderOptions {
+        antialias: 1,
+        ..options.clone()
+    })
+    .render();
+    let renderer = Renderer::new(options);
+    let smoothed = renderer.render();
+    assert_eq!(smoothed.len(), aliased.len());
+    assert_eq!(renderer.progress().done(), 40 * 30);
+    // Deep inside the set every sample is black either way, but along the
+    // edge the averaged colors differ from the single samples.
+    let center = 3 * (15 * 40 + 27);
+    assert_eq!(&smoothed[center..center + 3], &[0, 0, 0]);
+    assert!(smoothed != aliased);
+}
+
+#[test]
+fn test_busy_pixels() {
+    // A 4x3 image, black but for one white pixel at (3, 0).
+    let mut pixels = vec![0; 3 * 4 * 3];
+    pixels[9..12].copy_from_slice(&[255, 255, 255]);
+    assert_eq!(busy_pixels(&pixels, (4, 3), 10.0), vec![2, 3, 6, 7]);
+    assert_eq!(busy_pixels(&pixels, (4, 3), 200.0), vec![]);
+}
+
+#[test]
+fn test_adaptive_render() {
+    use crate::{Palette, RenderOptions};
+    use num::Complex;
+
+    let options = RenderOptions {
+        bounds: (40, 30),
+        upper_left: Complex { re: -2.0, im: 1.2 },
+        lower_right: Complex { re: 0.6, im: -1.2 },
+        palette: Palette::named("grayscale").unwrap(),
+        antialias: 3,
+        adaptive: Some(8.0),
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let aliased = Renderer::new(RenderOptions {
+        antialias: 1,
+        ..options.clone()
+    })
+    .render();
+    let renderer = Renderer::new(options.clone());
+    let adaptive = renderer.render();
+    assert!(renderer.progress().is_finished());
+    let busy = busy_pixels(&aliased, options.bounds, 8.0);
+    assert!(
+        !busy.is_empty() && busy.len() < 40 * 30 / 2,
+        "{}",
+        busy.len()
+    );
+    // Only the busy pixels were resampled.
+    for (index, (a, b)) in aliased.chunks(3).zip(adaptive.chunks(3)).enumerate() {
+        if a != b {
+            assert!(busy.contains(&index), "{}", index);
+        }
+    }
+    assert!(adaptive != aliased);
+}
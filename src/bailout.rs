@@ -0,0 +1,89 @@
+//! When an orbit counts as escaped. The usual test is `|z| > 2`, past
+//! which an orbit of `z² + c` is sure to run off to infinity, but escaping
+//! later, past a bigger radius, makes the smooth escape count and the
+//! colorings built on the final `z` more accurate. Other tests than the
+//! modulus give the escape bands other shapes.
+
+/// How far out an orbit has to get, and how that is measured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bailout {
+    pub radius: f64,
+    pub test: Test,
+}
+
+/// How the size of `z` is measured against the bailout radius.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Test {
+    /// `|z|`, compared squared.
+    Modulus,
+    /// `|Re z| + |Im z|`.
+    Manhattan,
+    /// `max(|Re z|, |Im z|)`.
+    Max,
+}
+
+/// Names accepted by `Test::named`, in the order they are listed in help.
+pub const NAMES: &[&str] = &["modulus", "manhattan", "max"];
+
+impl Default for Bailout {
+    /// `|z| > 2`.
+    fn default() -> Bailout {
+        Bailout {
+            radius: 2.0,
+            test: Test::Modulus,
+        }
+    }
+}
+
+impl Test {
+    /// Looks a test up by name, as given to `--bailout-test`.
+    pub fn named(name: &str) -> Option<Test> {
+        match name {
+            "modulus" => Some(Test::Modulus),
+            "manhattan" => Some(Test::Manhattan),
+            "max" => Some(Test::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Test::Modulus => "modulus",
+            Test::Manhattan => "manhattan",
+            Test::Max => "max",
+        }
+    }
+}
+
+impl Bailout {
+    /// The same test with a radius of at least `radius`, for fractals that
+    /// need more than 2 to be sure an orbit escapes.
+    pub fn at_least(self, radius: f64) -> Bailout {
+        Bailout {
+            radius: self.radius.max(radius),
+            ..self
+        }
+    }
+
+    /// Whether `re + i im` is past the bailout.
+    #[inline(always)]
+    pub fn escaped(&self, re: f64, im: f64) -> bool {
+        match self.test {
+            Test::Modulus => re * re + im * im > self.radius * self.radius,
+            Test::Manhattan => re.abs() + im.abs() > self.radius,
+            Test::Max => re.abs().max(im.abs()) > self.radius,
+        }
+    }
+}
+
+#[test]
+fn test_escaped() {
+    let at = |test, re, im| Bailout { radius: 2.0, test }.escaped(re, im);
+    assert!(!at(Test::Modulus, 1.5, 1.0));
+    assert!(at(Test::Manhattan, 1.5, 1.0));
+    assert!(!at(Test::Max, 1.5, 1.0));
+    assert!(at(Test::Max, 0.0, -2.5));
+    assert!(!at(Test::Modulus, -2.0, 0.0));
+    assert_eq!(Bailout::default().at_least(4.0).radius, 4.0);
+    assert_eq!(Bailout::default().at_least(1.0).radius, 2.0);
+}
@@ -0,0 +1,127 @@
+//! `bench`: times the escape times of a fixed set of views at a few sizes,
+//! so that thread counts, precisions and machines can be compared. The
+//! views cover the usual kinds of render: the whole set, a view where most
+//! pixels are inside and run to the iteration limit, one of fine filaments,
+//! and a zoom deep enough for perturbation.
+
+use crate::cli::Bench;
+use mandelbrot::{animation::View, Fixed, MandelbrotError, RenderOptions, Renderer};
+use num::Complex;
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// A view the benchmark renders, by its center and magnification.
+struct Case {
+    name: &'static str,
+    center: (&'static str, &'static str),
+    zoom: f64,
+    max_iter: u32,
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "shallow",
+        center: ("-0.5", "0"),
+        zoom: 1.0,
+        max_iter: 500,
+    },
+    Case {
+        name: "interior",
+        center: ("-0.12", "0.765"),
+        zoom: 25.0,
+        max_iter: 2000,
+    },
+    Case {
+        name: "filaments",
+        center: ("-0.7455", "0.1122"),
+        zoom: 1000.0,
+        max_iter: 2000,
+    },
+    // A Misiurewicz point, where the detail goes on without the iterations
+    // running away.
+    Case {
+        name: "deep",
+        center: ("0", "1"),
+        zoom: 1e30,
+        max_iter: 1000,
+    },
+];
+
+/// The sizes every view is rendered at.
+const SIZES: &[(u32, u32)] = &[(320, 240), (640, 480), (1280, 960)];
+
+/// Renders every case at every size, printing a table of the times.
+pub fn bench(bench: &Bench) -> Result<(), MandelbrotError> {
+    let options = &bench.frame.options;
+    let mut out = io::stdout().lock();
+    let mut report = || -> io::Result<()> {
+        writeln!(out, "threads: {}", options.threads)?;
+        writeln!(
+            out,
+            "{:<10} {:>9}  {:<18}  {:>9} {:>9}",
+            "view", "size", "precision", "time", "Mpixel/s"
+        )?;
+        let mut total = (Duration::ZERO, 0);
+        for case in CASES {
+            for &bounds in SIZES {
+                let mut options = RenderOptions {
+                    bounds,
+                    max_iter: case.max_iter,
+                    ..options.clone()
+                };
+                let coordinate = |s| Fixed::parse(s).expect("benchmark views are valid");
+                let view = View {
+                    center: Complex {
+                        re: coordinate(case.center.0),
+                        im: coordinate(case.center.1),
+                    },
+                    zoom: case.zoom,
+                    flipped: false,
+                };
+                view.apply(&mut options);
+                let precision = options.resolved_precision();
+                let renderer = Renderer::new(options);
+                let started = Instant::now();
+                renderer.render_escapes();
+                let elapsed = started.elapsed();
+                let pixels = bounds.0 as u64 * bounds.1 as u64;
+                writeln!(
+                    out,
+                    "{:<10} {:>9}  {:<18}  {:>9.3?} {:>9.2}",
+                    case.name,
+                    format!("{}x{}", bounds.0, bounds.1),
+                    precision.to_string(),
+                    elapsed,
+                    rate(pixels, elapsed)
+                )?;
+                total = (total.0 + elapsed, total.1 + pixels);
+            }
+        }
+        writeln!(
+            out,
+            "{:<10} {:>9}  {:<18}  {:>9.3?} {:>9.2}",
+            "total",
+            "",
+            "",
+            total.0,
+            rate(total.1, total.0)
+        )
+    };
+    report().map_err(|e| MandelbrotError::writing("the results", e))
+}
+
+/// Millions of pixels a second.
+fn rate(pixels: u64, elapsed: Duration) -> f64 {
+    pixels as f64 / elapsed.as_secs_f64().max(1e-9) / 1e6
+}
+
+#[test]
+fn test_cases_are_valid() {
+    for case in CASES {
+        assert!(Fixed::parse(case.center.0).is_some(), "{}", case.name);
+        assert!(Fixed::parse(case.center.1).is_some(), "{}", case.name);
+    }
+    assert_eq!(rate(2_000_000, Duration::from_secs(2)), 1.0);
+}
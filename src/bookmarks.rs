@@ -0,0 +1,218 @@
+//! Named views of the set. Bookmarks are kept in
+//! `~/.config/mandelbrot/bookmarks.toml`, one table per bookmark, alongside
+//! a few famous views that are built in:
+//!
+//! ```toml
+//! [seahorse-valley]
+//! center = "-0.7453,0.1127"
+//! zoom = 200
+//! max-iter = 1000
+//! ```
+//!
+//! `zoom` is the magnification relative to a view 4 high, as in the image
+//! metadata, so a bookmark fits any image size.
+
+use crate::config::{self, Value};
+use mandelbrot::{Fixed, MandelbrotError};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// A saved view. The numbers are kept as written, so that deep zoom
+/// centers keep every digit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub name: String,
+    /// The point at the center of the view, as `RE,IM`.
+    pub center: String,
+    pub zoom: String,
+    pub max_iter: Option<String>,
+}
+
+/// Name, center, zoom and maximum iterations of the bookmarks that come
+/// with the program. Saved bookmarks with the same name take precedence.
+const BUILT_IN: &[(&str, &str, &str, &str)] = &[
+    ("whole-set", "-0.6,0", "1.6", "255"),
+    ("seahorse-valley", "-0.7453,0.1127", "200", "1000"),
+    ("elephant-valley", "0.2821,0.01", "200", "1000"),
+    ("triple-spiral-valley", "-0.088,0.654", "100", "1000"),
+    ("mini-mandelbrot", "-1.7549,0", "80", "1000"),
+];
+
+/// The bookmarks that come with the program.
+pub fn built_in() -> Vec<Bookmark> {
+    BUILT_IN
+        .iter()
+        .map(|&(name, center, zoom, max_iter)| Bookmark {
+            name: name.to_string(),
+            center: center.to_string(),
+            zoom: zoom.to_string(),
+            max_iter: Some(max_iter.to_string()),
+        })
+        .collect()
+}
+
+/// Where saved bookmarks are kept: under `$XDG_CONFIG_HOME`, or
+/// `~/.config` if that isn't set.
+pub fn store_path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("mandelbrot").join("bookmarks.toml"))
+}
+
+/// Reads the saved bookmarks, if there are any.
+pub fn load(path: &Path) -> Result<Vec<Bookmark>, MandelbrotError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(MandelbrotError::reading(&path.to_string_lossy(), e)),
+    };
+    let error =
+        |message: String| MandelbrotError::Parse(format!("{}: {}", path.display(), message));
+    let mut bookmarks = Vec::new();
+    for (name, entries) in config::parse_tables(&text).map_err(error)? {
+        let get = |key: &str| {
+            let value = entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, value)| value.clone());
+            match value {
+                None => Ok(None),
+                Some(Value::String(value) | Value::Number(value)) => Ok(Some(value)),
+                Some(Value::Boolean(_)) => {
+                    Err(error(format!("[{}] {} is not a number", name, key)))
+                }
+            }
+        };
+        let bookmark = Bookmark {
+            center: get("center")?.ok_or_else(|| error(format!("[{}] has no center", name)))?,
+            zoom: get("zoom")?.ok_or_else(|| error(format!("[{}] has no zoom", name)))?,
+            max_iter: get("max-iter")?,
+            name,
+        };
+        if let Some((key, _)) = entries
+            .iter()
+            .find(|(k, _)| !matches!(k.as_str(), "center" | "zoom" | "max-iter"))
+        {
+            return Err(error(format!("[{}] unknown key '{}'", bookmark.name, key)));
+        }
+        check(&bookmark).map_err(error)?;
+        bookmarks.push(bookmark);
+    }
+    Ok(bookmarks)
+}
+
+/// Writes `bookmarks` to `path`, creating its directory if needed.
+pub fn save(path: &Path, bookmarks: &[Bookmark]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut text = String::from("# Written by `mandelbrot bookmark add`.\n");
+    for bookmark in bookmarks {
+        text.push_str(&format!(
+            "\n[{}]\ncenter = {}\nzoom = {}\n",
+            bookmark.name,
+            config::quote(&bookmark.center),
+            bookmark.zoom
+        ));
+        if let Some(max_iter) = &bookmark.max_iter {
+            text.push_str(&format!("max-iter = {}\n", max_iter));
+        }
+    }
+    fs::write(path, text)
+}
+
+/// Checks that a bookmark's name can be written as a table name and that
+/// its numbers are numbers.
+pub fn check(bookmark: &Bookmark) -> Result<(), String> {
+    let name = &bookmark.name;
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "invalid bookmark name '{}': use letters, digits, - and _",
+            name
+        ));
+    }
+    let center = bookmark.center.split_once(',');
+    if !center.is_some_and(|(re, im)| Fixed::parse(re).is_some() && Fixed::parse(im).is_some()) {
+        return Err(format!("[{}] invalid center '{}'", name, bookmark.center));
+    }
+    if !bookmark
+        .zoom
+        .parse::<f64>()
+        .is_ok_and(|zoom| zoom > 0.0 && zoom.is_finite())
+    {
+        return Err(format!("[{}] invalid zoom '{}'", name, bookmark.zoom));
+    }
+    if let Some(max_iter) = &bookmark.max_iter {
+        if !max_iter.parse::<u32>().is_ok_and(|n| n > 0) {
+            return Err(format!("[{}] invalid max-iter '{}'", name, max_iter));
+        }
+    }
+    Ok(())
+}
+
+/// The saved bookmarks followed by the built-in ones they don't replace.
+pub fn all(saved: Vec<Bookmark>) -> Vec<Bookmark> {
+    let mut bookmarks = saved;
+    for bookmark in built_in() {
+        if !bookmarks.iter().any(|b| b.name == bookmark.name) {
+            bookmarks.push(bookmark);
+        }
+    }
+    bookmarks
+}
+
+#[test]
+fn test_built_in() {
+    for bookmark in built_in() {
+        check(&bookmark).unwrap();
+    }
+}
+
+#[test]
+fn test_save_and_load() {
+    let path = std::env::temp_dir()
+        .join("mandelbrot_test_bookmarks")
+        .join("bookmarks.toml");
+    let _ = fs::remove_file(&path);
+    assert_eq!(load(&path), Ok(Vec::new()));
+    let saved = vec![
+        Bookmark {
+            name: "deep".to_string(),
+            center: "-0.74364388703715870475,0.13182590420531197050".to_string(),
+            zoom: "2.5e15".to_string(),
+            max_iter: Some("20000".to_string()),
+        },
+        Bookmark {
+            name: "seahorse-valley".to_string(),
+            center: "-0.75,0.1".to_string(),
+            zoom: "20".to_string(),
+            max_iter: None,
+        },
+    ];
+    save(&path, &saved).unwrap();
+    assert_eq!(load(&path), Ok(saved.clone()));
+
+    let merged = all(saved);
+    assert_eq!(merged.len(), 1 + BUILT_IN.len());
+    assert_eq!(merged[1].center, "-0.75,0.1");
+
+    for text in [
+        "[a]\ncenter = \"0,0\"",
+        "[a]\ncenter = \"0\"\nzoom = 1",
+        "[a]\ncenter = \"0,0\"\nzoom = 0",
+        "[a]\ncenter = \"0,0\"\nzoom = 1\npalette = \"fire\"",
+        "[a]\ncenter = \"0,0\"\nzoom = true",
+    ] {
+        fs::write(&path, text).unwrap();
+        assert!(load(&path).is_err(), "{}", text);
+    }
+}
@@ -0,0 +1,194 @@
+//! Mariani–Silver boundary tracing. The Mandelbrot set is connected, and so
+//! is each region of points that escape after more than a given number of
+//! iterations, so a rectangle whose whole border escapes alike (or doesn't
+//! escape at all) has the same inside, short of the entire set fitting
+//! within it. Such rectangles are filled in without iterating their insides;
+//! the others are split in two and their halves checked in turn.
+//!
+//! The rectangles are processed a generation at a time: the border pixels
+//! of every rectangle in a generation are computed together through
+//! `Renderer::render_points`, so every precision and the usual threads are
+//! used for them.
+
+use crate::{Escape, Renderer};
+
+/// A rectangle of pixels, border included: columns `left..=right` and rows
+/// `top..=bottom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rect {
+    left: u32,
+    top: u32,
+    right: u32,
+    bottom: u32,
+}
+
+impl Rect {
+    fn border(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let horizontal = (self.left..=self.right)
+            .flat_map(move |column| [(column, self.top), (column, self.bottom)]);
+        let vertical =
+            (self.top + 1..self.bottom).flat_map(move |row| [(self.left, row), (self.right, row)]);
+        horizontal.chain(vertical)
+    }
+
+    fn has_inside(&self) -> bool {
+        self.right > self.left + 1 && self.bottom > self.top + 1
+    }
+
+    /// Splits across the longer side, the two halves sharing the new line.
+    fn split(&self) -> [Rect; 2] {
+        if self.right - self.left >= self.bottom - self.top {
+            let middle = (self.left + self.right) / 2;
+            [
+                Rect {
+                    right: middle,
+                    ..*self
+                },
+                Rect {
+                    left: middle,
+                    ..*self
+                },
+            ]
+        } else {
+            let middle = (self.top + self.bottom) / 2;
+            [
+                Rect {
+                    bottom: middle,
+                    ..*self
+                },
+                Rect {
+                    top: middle,
+                    ..*self
+                },
+            ]
+        }
+    }
+}
+
+/// `Renderer::render_escapes` by boundary tracing. With smooth or distance
+/// coloring only rectangles bordered by the set itself are filled, since
+/// escaping pixels with equal counts still differ in their smooth values
+/// and distances.
+pub fn render(renderer: &Renderer) -> Vec<Option<Escape>> {
+    let options = renderer.options();
+    let (width, height) = options.bounds;
+    let mut escapes = vec![None; width as usize * height as usize];
+    let progress = renderer.progress();
+    progress.start(escapes.len() as u64);
+    if escapes.is_empty() {
+        return escapes;
+    }
+    let index = |(column, row): (u32, u32)| (row * width + column) as usize;
+    let mut known = vec![false; escapes.len()];
+    let mut rects = vec![Rect {
+        left: 0,
+        top: 0,
+        right: width - 1,
+        bottom: height - 1,
+    }];
+    while !rects.is_empty() {
+        let mut pixels = Vec::new();
+        for rect in &rects {
+            for pixel in rect.border() {
+                if !known[index(pixel)] {
+                    known[index(pixel)] = true;
+                    pixels.push(pixel);
+                }
+            }
+        }
+        let positions = pixels
+            .iter()
+            .map(|&(column, row)| (column as f64, row as f64))
+            .collect::<Vec<_>>();
+        for (&pixel, escape) in pixels.iter().zip(renderer.render_points(&positions)) {
+            escapes[index(pixel)] = escape;
+        }
+        progress.add(pixels.len() as u64);
+
+        let mut next = Vec::new();
+        for rect in rects.iter().filter(|rect| rect.has_inside()) {
+            let first = escapes[index((rect.left, rect.top))];
+            let exact = options.smooth || options.coloring.follows_orbits();
+            let uniform = (!exact || first.is_none())
+                && rect.border().all(|pixel| {
+                    escapes[index(pixel)].map(|e| e.iterations) == first.map(|e| e.iterations)
+                });
+            if !uniform {
+                next.extend(rect.split());
+                continue;
+            }
+            for row in rect.top + 1..rect.bottom {
+                for column in rect.left + 1..rect.right {
+                    escapes[index((column, row))] = first;
+                    known[index((column, row))] = true;
+                }
+            }
+            progress.add((rect.right - rect.left - 1) as u64 * (rect.bottom - rect.top - 1) as u64);
+        }
+        rects = next;
+    }
+    escapes
+}
+
+#[test]
+fn test_rect_border_and_split() {
+    let rect = Rect {
+        left: 2,
+        top: 1,
+        right: 5,
+        bottom: 3,
+    };
+    let mut border = rect.border().collect::<Vec<_>>();
+    border.sort();
+    assert_eq!(
+        border,
+        vec![
+            (2, 1),
+            (2, 2),
+            (2, 3),
+            (3, 1),
+            (3, 3),
+            (4, 1),
+            (4, 3),
+            (5, 1),
+            (5, 2),
+            (5, 3)
+        ]
+    );
+    assert!(rect.has_inside());
+    let [a, b] = rect.split();
+    assert_eq!((a.right, b.left), (3, 3));
+    assert!(!Rect { bottom: 2, ..rect }.has_inside());
+}
+
+#[test]
+fn test_border_trace_matches_scan() {
+    use crate::{Algorithm, RenderOptions};
+    use num::Complex;
+
+    for smooth in [false, true] {
+        let options = RenderOptions {
+            bounds: (61, 47),
+            upper_left: Complex { re: -2.0, im: 1.2 },
+            lower_right: Complex { re: 0.6, im: -1.2 },
+            smooth,
+            threads: 2,
+            ..RenderOptions::default()
+        };
+        let scan = Renderer::new(options.clone()).render_escapes();
+        let renderer = Renderer::new(RenderOptions {
+            algorithm: Algorithm::BorderTrace,
+            ..options
+        });
+        let traced = renderer.render_escapes();
+        let iterations = |escapes: &[Option<Escape>]| {
+            escapes
+                .iter()
+                .map(|e| e.map(|e| e.iterations))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(iterations(&traced), iterations(&scan));
+        assert!(renderer.progress().is_finished());
+        assert_eq!(renderer.progress().done(), 61 * 47);
+    }
+}
@@ -0,0 +1,297 @@
+//! Buddhabrot rendering. Instead of coloring each pixel by how the orbit
+//! of its own point escapes, random points `c` are sampled all over the
+//! set, and every point the orbits of the escaping ones pass through is
+//! counted in the pixel it lands in. Tone mapping the counts shows the
+//! ghostly figure the orbits trace out together.
+//!
+//! The samples are drawn in batches, each from its own seed, so that an
+//! image doesn't depend on how the batches were spread over threads. Each
+//! thread counts into a histogram of its own, and the histograms are added
+//! up at the end.
+//!
+//! A Nebulabrot counts the same orbits into three histograms, one per color
+//! channel, each of the orbits escaping within its own iteration limit.
+//! Low limits keep the broad, fast orbits and high ones the slow orbits
+//! near the set, which together give the look of a nebula.
+
+use crate::{in_main_bulbs, Palette, Progress};
+use num::Complex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// How many samples are drawn from each seed.
+const BATCH: u64 = 1 << 14;
+
+/// Samples are drawn from the square within this of 0 on both axes, which
+/// holds the whole set.
+const SAMPLE_RADIUS: f64 = 2.0;
+
+/// A Buddhabrot render: `samples` points are drawn, and the orbits of those
+/// that escape after `min_iter` to `max_iter` iterations are counted over
+/// an image of `bounds` pixels with the given corners.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buddhabrot {
+    pub bounds: (u32, u32),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    pub samples: u64,
+    /// Orbits escaping sooner aren't counted. Raising it leaves the slow
+    /// orbits of points near the set, which trace finer detail.
+    pub min_iter: u32,
+    pub max_iter: u32,
+    /// Picks the samples; the same seed gives the same image.
+    pub seed: u64,
+    pub threads: u32,
+}
+
+/// The channels of a Nebulabrot: the iteration limits of the orbits
+/// counted in red, green and blue, and how much each is brightened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nebula {
+    pub limits: [u32; 3],
+    pub exposure: [f64; 3],
+}
+
+impl Buddhabrot {
+    /// How many times orbits passed through each pixel, in row-major order.
+    /// `progress` counts samples.
+    pub fn density(&self, progress: &Progress) -> Vec<u32> {
+        self.densities(&[self.max_iter], progress).remove(0)
+    }
+
+    /// The density of the orbits escaping within each of `limits`, from one
+    /// set of samples; `max_iter` is ignored.
+    pub fn densities(&self, limits: &[u32], progress: &Progress) -> Vec<Vec<u32>> {
+        let pixels = self.bounds.0 as usize * self.bounds.1 as usize;
+        let batches = self.samples.div_ceil(BATCH);
+        progress.start(self.samples);
+        let next = AtomicU64::new(0);
+        let count = || {
+            let mut histograms = vec![vec![0u32; pixels]; limits.len()];
+            let mut orbit = Vec::new();
+            loop {
+                let batch = next.fetch_add(1, Ordering::Relaxed);
+                if batch >= batches {
+                    break histograms;
+                }
+                let samples = BATCH.min(self.samples - batch * BATCH);
+                self.sample(batch, samples, limits, &mut orbit, &mut histograms);
+                progress.add(samples);
+            }
+        };
+        if self.threads <= 1 {
+            return count();
+        }
+        let histograms = crossbeam::scope(|spawner| {
+            let threads = (0..(self.threads as u64).min(batches.max(1)))
+                .map(|_| spawner.spawn(|_| count()))
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        })
+        .unwrap();
+        let mut totals = vec![vec![0u32; pixels]; limits.len()];
+        for thread in histograms {
+            for (total, histogram) in totals.iter_mut().zip(thread) {
+                for (sum, count) in total.iter_mut().zip(histogram) {
+                    *sum = sum.saturating_add(count);
+                }
+            }
+        }
+        totals
+    }
+
+    /// Draws batch number `batch` of `samples` points and counts the orbits
+    /// escaping within each of `limits` into its histogram, keeping each
+    /// orbit in `orbit` until it is known to escape.
+    fn sample(
+        &self,
+        batch: u64,
+        samples: u64,
+        limits: &[u32],
+        orbit: &mut Vec<Complex<f64>>,
+        histograms: &mut [Vec<u32>],
+    ) {
+        let limit = limits.iter().copied().max().unwrap_or(0);
+        let mut random = SplitMix64(self.seed ^ batch.wrapping_mul(0x2545_f491_4f6c_dd1d));
+        for _ in 0..samples {
+            let c = Complex::new(
+                SAMPLE_RADIUS * (2.0 * random.unit() - 1.0),
+                SAMPLE_RADIUS * (2.0 * random.unit() - 1.0),
+            );
+            // Points in the main cardioid and bulb never escape.
+            if in_main_bulbs(c) {
+                continue;
+            }
+            orbit.clear();
+            let mut z = Complex::new(0.0, 0.0);
+            let mut escaped = false;
+            for _ in 0..limit {
+                z = z * z + c;
+                if z.norm_sqr() > 4.0 {
+                    escaped = true;
+                    break;
+                }
+                orbit.push(z);
+            }
+            if !escaped || orbit.len() < self.min_iter as usize {
+                continue;
+            }
+            for (&limit, histogram) in limits.iter().zip(histograms.iter_mut()) {
+                if orbit.len() >= limit as usize {
+                    continue;
+                }
+                for &z in orbit.iter() {
+                    if let Some(index) = self.pixel(z) {
+                        histogram[index] = histogram[index].saturating_add(1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The index of the pixel `z` lands in, the inverse of
+    /// `crate::pixel_to_point`, or `None` outside the image.
+    fn pixel(&self, z: Complex<f64>) -> Option<usize> {
+        let (width, height) = (
+            self.lower_right.re - self.upper_left.re,
+            self.upper_left.im - self.lower_right.im,
+        );
+        let x = ((z.re - self.upper_left.re) / width * self.bounds.0 as f64).floor();
+        let y = ((self.upper_left.im - z.im) / height * self.bounds.1 as f64).floor();
+        let inside =
+            (0.0..self.bounds.0 as f64).contains(&x) && (0.0..self.bounds.1 as f64).contains(&y);
+        inside.then(|| y as usize * self.bounds.0 as usize + x as usize)
+    }
+}
+
+/// Maps `density` counts onto `palette` by the square root of each count
+/// relative to the largest, which keeps the faint outer orbits visible
+/// next to the dense center. Three bytes per pixel, like
+/// `Renderer::render`.
+pub fn tone_map(density: &[u32], palette: &Palette) -> Vec<u8> {
+    let max = density.iter().copied().max().unwrap_or(0).max(1) as f64;
+    density
+        .iter()
+        .flat_map(|&count| palette.at((count as f64 / max).sqrt()))
+        .collect()
+}
+
+impl Nebula {
+    /// Maps the `densities` of the three channels from
+    /// `Buddhabrot::densities` onto red, green and blue, each by the square
+    /// root of its counts relative to its largest, times its exposure.
+    pub fn tone_map(&self, densities: &[Vec<u32>]) -> Vec<u8> {
+        let scales = std::array::from_fn::<_, 3, _>(|k| {
+            let max = densities[k].iter().copied().max().unwrap_or(0).max(1);
+            self.exposure[k] / (max as f64).sqrt()
+        });
+        (0..densities[0].len())
+            .flat_map(|i| {
+                std::array::from_fn::<_, 3, _>(|k| {
+                    let value = (densities[k][i] as f64).sqrt() * scales[k];
+                    (255.0 * value.min(1.0)).round() as u8
+                })
+            })
+            .collect()
+    }
+}
+
+/// The SplitMix64 generator, which `antialias` also jitters samples with.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+fn test_buddhabrot(threads: u32) -> Buddhabrot {
+    Buddhabrot {
+        bounds: (40, 40),
+        upper_left: Complex::new(-2.0, 2.0),
+        lower_right: Complex::new(2.0, -2.0),
+        samples: 3 * BATCH + 100,
+        min_iter: 0,
+        max_iter: 100,
+        seed: 7,
+        threads,
+    }
+}
+
+#[test]
+fn test_density() {
+    let progress = Progress::default();
+    let single = test_buddhabrot(1).density(&progress);
+    assert_eq!(progress.done(), 3 * BATCH + 100);
+    assert!(progress.is_finished());
+    assert!(single.iter().any(|&count| count > 0));
+    // The batches are the same however they are shared out.
+    assert_eq!(test_buddhabrot(3).density(&Progress::default()), single);
+    // No orbit escapes after more iterations than the limit.
+    let none = Buddhabrot {
+        min_iter: 101,
+        ..test_buddhabrot(2)
+    };
+    assert!(none.density(&Progress::default()).iter().all(|&c| c == 0));
+}
+
+#[test]
+fn test_densities() {
+    let buddhabrot = test_buddhabrot(2);
+    let densities = buddhabrot.densities(&[20, 100], &Progress::default());
+    assert_eq!(densities[1], buddhabrot.density(&Progress::default()));
+    // Every orbit escaping within 20 iterations also does within 100.
+    assert!(densities[0].iter().zip(&densities[1]).all(|(a, b)| a <= b));
+    assert!(densities[0] != densities[1]);
+}
+
+#[test]
+fn test_nebula_tone_map() {
+    let nebula = Nebula {
+        limits: [10, 100, 1000],
+        exposure: [1.0, 2.0, 0.5],
+    };
+    let densities = [vec![0, 4, 16], vec![1, 4, 16], vec![16, 4, 1]];
+    assert_eq!(
+        nebula.tone_map(&densities),
+        [0, 128, 128, 128, 255, 64, 255, 255, 32]
+    );
+}
+
+#[test]
+fn test_pixel() {
+    let buddhabrot = test_buddhabrot(1);
+    let (upper_left, lower_right) = (buddhabrot.upper_left, buddhabrot.lower_right);
+    for (column, row) in [(0, 0), (39, 0), (13, 27), (39, 39)] {
+        let corner = crate::pixel_to_point((40, 40), (column, row), upper_left, lower_right);
+        let center = corner + Complex::new(0.05, -0.05);
+        assert_eq!(
+            buddhabrot.pixel(center),
+            Some(row as usize * 40 + column as usize)
+        );
+    }
+    assert_eq!(buddhabrot.pixel(Complex::new(2.5, 0.0)), None);
+    assert_eq!(buddhabrot.pixel(Complex::new(0.0, -2.0)), None);
+}
+
+#[test]
+fn test_tone_map() {
+    let palette = Palette::named("grayscale").unwrap();
+    let pixels = tone_map(&[0, 4, 16], &palette);
+    assert_eq!(&pixels[..3], &palette.at(0.0));
+    assert_eq!(&pixels[3..6], &palette.at(0.5));
+    assert_eq!(&pixels[6..], &palette.at(1.0));
+    assert_eq!(tone_map(&[0], &palette), palette.at(0.0).to_vec());
+}
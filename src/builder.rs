@@ -0,0 +1,357 @@
+//! A builder of `RenderOptions`, for embedders who would rather name the
+//! settings they change than spell out the struct, and have them checked
+//! once at the end:
+//!
+//! ```
+//! let options = mandelbrot::RenderOptions::builder()
+//!     .size(1920, 1080)
+//!     .center(-0.75, 0.0)
+//!     .zoom(1e6)
+//!     .max_iter(5000)
+//!     .build()
+//!     .unwrap();
+//! assert_eq!(options.bounds, (1920, 1080));
+//! ```
+//!
+//! The view is either the corners, stretched to the image, or a center and
+//! a zoom as `animation::View` takes them, which keep pixels square and
+//! give deep zooms their exact corners. Anything left unset is as in
+//! `RenderOptions::default`.
+
+use crate::{
+    animation::View, antialias::MAX_ANTIALIAS, Adjustments, Algorithm, Bailout, Coloring, Dither,
+    Fixed, Fractal, Interior, Light, MandelbrotError, Palette, Polynomial, Precision,
+    RenderOptions, Shortcuts, Transfer,
+};
+use num::Complex;
+
+/// Settings for `build` to check and make `RenderOptions` of.
+#[derive(Debug, Clone)]
+pub struct RenderOptionsBuilder {
+    options: RenderOptions,
+    corners: Option<(Complex<f64>, Complex<f64>)>,
+    center: Option<Complex<Fixed>>,
+    zoom: Option<f64>,
+}
+
+impl RenderOptions {
+    /// A builder starting from the default options; see `builder`.
+    pub fn builder() -> RenderOptionsBuilder {
+        RenderOptionsBuilder {
+            options: RenderOptions::default(),
+            corners: None,
+            center: None,
+            zoom: None,
+        }
+    }
+}
+
+impl RenderOptionsBuilder {
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.options.bounds = (width, height);
+        self
+    }
+
+    /// The corners of the view, which are stretched to the image.
+    pub fn corners(mut self, upper_left: Complex<f64>, lower_right: Complex<f64>) -> Self {
+        self.corners = Some((upper_left, lower_right));
+        self
+    }
+
+    /// The center of the view, in the middle of the default view unless
+    /// set.
+    pub fn center(self, re: f64, im: f64) -> Self {
+        self.exact_center(Complex {
+            re: Fixed::from_f64(re, 64),
+            im: Fixed::from_f64(im, 64),
+        })
+    }
+
+    /// The center of the view, exactly, for zooms deeper than an `f64`
+    /// center can be placed.
+    pub fn exact_center(mut self, center: Complex<Fixed>) -> Self {
+        self.center = Some(center);
+        self
+    }
+
+    /// How far the view is zoomed in, 1 showing 4 units of the imaginary
+    /// axis, unless set.
+    pub fn zoom(mut self, zoom: f64) -> Self {
+        self.zoom = Some(zoom);
+        self
+    }
+
+    /// The center and zoom of `view`.
+    pub fn view(self, view: &View) -> Self {
+        self.exact_center(view.center.clone()).zoom(view.zoom)
+    }
+
+    pub fn max_iter(mut self, max_iter: u32) -> Self {
+        self.options.max_iter = max_iter;
+        self
+    }
+
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.options.precision = precision;
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.options.algorithm = algorithm;
+        self
+    }
+
+    pub fn shortcuts(mut self, shortcuts: Shortcuts) -> Self {
+        self.options.shortcuts = shortcuts;
+        self
+    }
+
+    pub fn bailout(mut self, bailout: Bailout) -> Self {
+        self.options.bailout = bailout;
+        self
+    }
+
+    /// Renders the Julia set of `c` instead.
+    pub fn julia(mut self, c: Complex<f64>) -> Self {
+        self.options.julia = Some(c);
+        self
+    }
+
+    pub fn fractal(mut self, fractal: Fractal) -> Self {
+        self.options.fractal = fractal;
+        self
+    }
+
+    /// Runs Newton's method for `polynomial` instead.
+    pub fn newton(mut self, polynomial: Polynomial) -> Self {
+        self.options.newton = Some(polynomial);
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.options.palette = palette;
+        self
+    }
+
+    /// How escape time coloring spreads escape values over the palette.
+    pub fn transfer(mut self, transfer: Transfer) -> Self {
+        self.options.transfer = transfer;
+        self
+    }
+
+    /// Starts the palette over every `iterations` iterations.
+    pub fn palette_cycle(mut self, iterations: f64) -> Self {
+        self.options.palette_cycle = Some(iterations);
+        self
+    }
+
+    pub fn smooth(mut self, smooth: bool) -> Self {
+        self.options.smooth = smooth;
+        self
+    }
+
+    pub fn coloring(mut self, coloring: Coloring) -> Self {
+        self.options.coloring = coloring;
+        self
+    }
+
+    pub fn interior(mut self, interior: Interior) -> Self {
+        self.options.interior = interior;
+        self
+    }
+
+    pub fn shading(mut self, light: Light) -> Self {
+        self.options.shading = Some(light);
+        self
+    }
+
+    /// Dithers colors as they are rounded to bytes.
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.options.dither = Some(dither);
+        self
+    }
+
+    /// Tunes the finished image.
+    pub fn adjustments(mut self, adjustments: Adjustments) -> Self {
+        self.options.adjustments = adjustments;
+        self
+    }
+
+    /// `samples`² samples per pixel.
+    pub fn antialias(mut self, samples: u32) -> Self {
+        self.options.antialias = samples;
+        self
+    }
+
+    /// Supersamples just the pixels whose neighborhood colors have a
+    /// standard deviation above `threshold`.
+    pub fn adaptive(mut self, threshold: f64) -> Self {
+        self.options.adaptive = Some(threshold);
+        self
+    }
+
+    pub fn threads(mut self, threads: u32) -> Self {
+        self.options.threads = threads;
+        self
+    }
+
+    /// The options, or what is wrong with them.
+    pub fn build(self) -> Result<RenderOptions, MandelbrotError> {
+        let invalid = |message: &str| Err(MandelbrotError::Parse(message.to_string()));
+        let mut options = self.options;
+        let (width, height) = options.bounds;
+        if width == 0 || height == 0 {
+            return invalid("the size must be at least 1x1");
+        }
+        if options.max_iter == 0 {
+            return invalid("max_iter must be at least 1");
+        }
+        if options.threads == 0 {
+            return invalid("threads must be at least 1");
+        }
+        if !(1..=MAX_ANTIALIAS).contains(&options.antialias) {
+            return Err(MandelbrotError::Parse(format!(
+                "antialias must be between 1 and {}",
+                MAX_ANTIALIAS
+            )));
+        }
+        if options
+            .adaptive
+            .is_some_and(|threshold| threshold.is_nan() || threshold < 0.0)
+        {
+            return invalid("the adaptive threshold must be at least 0");
+        }
+        if options
+            .palette_cycle
+            .is_some_and(|cycle| !(cycle > 0.0 && cycle.is_finite()))
+        {
+            return invalid("the palette cycle must be above 0");
+        }
+        let Adjustments {
+            exposure,
+            contrast,
+            brightness,
+            gamma,
+        } = options.adjustments;
+        if !(exposure.is_finite()
+            && contrast >= 0.0
+            && contrast.is_finite()
+            && (-1.0..=1.0).contains(&brightness)
+            && gamma > 0.0
+            && gamma.is_finite())
+        {
+            return invalid("the adjustments must be finite, with the gamma above 0");
+        }
+        if !options.bailout.radius.is_finite() {
+            return invalid("the bailout radius must be finite");
+        }
+        if options.bailout.radius < 2.0 {
+            return invalid("the bailout radius must be at least 2");
+        }
+        match (self.corners, self.center, self.zoom) {
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+                return invalid("give either the corners or a center and zoom, not both")
+            }
+            (Some((upper_left, lower_right)), None, None) => {
+                let finite = [upper_left.re, upper_left.im, lower_right.re, lower_right.im]
+                    .iter()
+                    .all(|x| x.is_finite());
+                if !finite || upper_left.re == lower_right.re || upper_left.im == lower_right.im {
+                    return invalid("the corners must be finite and span an area");
+                }
+                options.upper_left = upper_left;
+                options.lower_right = lower_right;
+            }
+            (None, None, None) => {}
+            (None, center, zoom) => {
+                let zoom = zoom.unwrap_or(1.0);
+                if !(zoom > 0.0 && zoom.is_finite()) {
+                    return invalid("the zoom must be above 0");
+                }
+                let center = center.unwrap_or_else(|| {
+                    let middle = (options.upper_left + options.lower_right) / 2.0;
+                    Complex {
+                        re: Fixed::from_f64(middle.re, 64),
+                        im: Fixed::from_f64(middle.im, 64),
+                    }
+                });
+                View {
+                    center,
+                    zoom,
+                    flipped: options.upper_left.im < options.lower_right.im,
+                }
+                .apply(&mut options);
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[test]
+fn test_builder() {
+    let options = RenderOptions::builder()
+        .size(400, 200)
+        .center(-0.75, 0.0)
+        .zoom(2.0)
+        .max_iter(500)
+        .threads(2)
+        .build()
+        .unwrap();
+    assert_eq!(
+        (options.bounds, options.max_iter, options.threads),
+        ((400, 200), 500, 2)
+    );
+    // 2 units high, and as wide as the image's aspect.
+    assert_eq!(options.upper_left, Complex::new(-2.75, 1.0));
+    assert_eq!(options.lower_right, Complex::new(1.25, -1.0));
+    assert!(options.exact_corners.is_some());
+    let stretched = RenderOptions::builder()
+        .corners(Complex::new(-2.0, 1.0), Complex::new(1.0, -1.0))
+        .build()
+        .unwrap();
+    assert_eq!(
+        stretched,
+        RenderOptions {
+            upper_left: Complex::new(-2.0, 1.0),
+            lower_right: Complex::new(1.0, -1.0),
+            ..RenderOptions::default()
+        }
+    );
+    assert_eq!(
+        RenderOptions::builder().build(),
+        Ok(RenderOptions::default())
+    );
+    let error = |builder: RenderOptionsBuilder| builder.build().unwrap_err().to_string();
+    assert_eq!(
+        error(RenderOptions::builder().size(0, 10)),
+        "the size must be at least 1x1"
+    );
+    assert_eq!(
+        error(RenderOptions::builder().max_iter(0)),
+        "max_iter must be at least 1"
+    );
+    assert_eq!(
+        error(RenderOptions::builder().zoom(-1.0)),
+        "the zoom must be above 0"
+    );
+    assert_eq!(
+        error(
+            RenderOptions::builder()
+                .corners(Complex::new(-2.0, 1.0), Complex::new(1.0, -1.0))
+                .zoom(3.0)
+        ),
+        "give either the corners or a center and zoom, not both"
+    );
+    assert_eq!(
+        error(RenderOptions::builder().antialias(65536)),
+        "antialias must be between 1 and 16"
+    );
+    assert_eq!(
+        error(RenderOptions::builder().palette_cycle(0.0)),
+        "the palette cycle must be above 0"
+    );
+    assert!(RenderOptions::builder()
+        .corners(Complex::new(0.0, 1.0), Complex::new(0.0, -1.0))
+        .build()
+        .is_err());
+}
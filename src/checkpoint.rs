@@ -0,0 +1,358 @@
+//! Checkpoints of long renders, so that one cut short by a crash or a
+//! reboot can carry on where it stopped instead of starting over. The
+//! escape times are computed in bands of rows, and each band is appended to
+//! the checkpoint file and synced to disk as soon as it is done. All numbers
+//! are little-endian:
+//!
+//! | bytes | contents                                                  |
+//! |-------|-----------------------------------------------------------|
+//! | 4     | magic `MBCP`                                              |
+//! | 1     | format version, 1                                         |
+//! | 3     | reserved, 0                                               |
+//! | 4     | length of the key, `u32`                                  |
+//! | ...   | the key, `key` of the render's options in UTF-8           |
+//!
+//! followed by a record for each band, in order from the top:
+//!
+//! | bytes | contents                                                  |
+//! |-------|-----------------------------------------------------------|
+//! | 4 + 4 | first row and number of rows, `u32`                       |
+//! | 8     | length of the escapes, `u64`                              |
+//! | ...   | the escapes, in row-major order                           |
+//!
+//! Each escape is a byte of flags, 0 for a point in the set, 1 for one that
+//! escaped, plus 2 with a derivative and 4 with a stripe average. Escaped
+//! points follow it with their iterations as a `u32` and `z` as two `f64`s,
+//! then the derivative as two `f64`s and the stripe average as one `f64`
+//! where they have them. A record cut short by the interruption is dropped
+//! on resuming.
+
+use crate::{
+    log::{self, Level},
+    metadata::SOFTWARE,
+    tiled::BAND_PIXELS,
+    Algorithm, Escape, RenderOptions, Renderer,
+};
+use num::Complex;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+};
+
+const MAGIC: &[u8; 4] = b"MBCP";
+const VERSION: u8 = 1;
+
+const ESCAPED: u8 = 1;
+const DERIVATIVE: u8 = 2;
+const STRIPE: u8 = 4;
+
+/// The most bytes `encode_escape` takes for one escape: the flags, the
+/// iterations and five `f64`s.
+pub(crate) const MAX_ESCAPE_BYTES: usize = 1 + 4 + 5 * 8;
+
+/// Identifies the escape times of a render: everything they depend on, and
+/// the version of the program that computed them. A checkpoint only
+/// resumes a render with the same key.
+pub fn key(options: &RenderOptions) -> String {
+    let corners = match &options.exact_corners {
+        Some(_) => format!("{:?}", options.exact_corners()),
+        None => format!("{:?}", (options.upper_left, options.lower_right)),
+    };
+    format!(
+        "{} {:?} {} {:?} {:?} {:?} {:?} {:?} {} {:?}",
+        SOFTWARE,
+        options.bounds,
+        corners,
+        options.resolved_precision(),
+        options.bailout,
+        options.julia,
+        options.fractal,
+        options.newton,
+        options.max_iter,
+        options.coloring,
+    )
+}
+
+/// Appends `escape` to `bytes` in the checkpoint's form.
+pub(crate) fn encode_escape(bytes: &mut Vec<u8>, escape: &Option<Escape>) {
+    let Some(escape) = escape else {
+        bytes.push(0);
+        return;
+    };
+    let mut flags = ESCAPED;
+    if escape.derivative.is_some() {
+        flags |= DERIVATIVE;
+    }
+    if escape.stripe.is_some() {
+        flags |= STRIPE;
+    }
+    bytes.push(flags);
+    bytes.extend(escape.iterations.to_le_bytes());
+    bytes.extend(escape.z.re.to_le_bytes());
+    bytes.extend(escape.z.im.to_le_bytes());
+    if let Some(derivative) = escape.derivative {
+        bytes.extend(derivative.re.to_le_bytes());
+        bytes.extend(derivative.im.to_le_bytes());
+    }
+    if let Some(stripe) = escape.stripe {
+        bytes.extend(stripe.to_le_bytes());
+    }
+}
+
+/// Reads `count` escapes back from `bytes`, or `None` if they don't hold
+/// exactly that many.
+pub(crate) fn decode_escapes(mut bytes: &[u8], count: usize) -> Option<Vec<Option<Escape>>> {
+    let mut take = |n: usize| -> Option<&[u8]> {
+        let (taken, rest) = bytes.split_at_checked(n)?;
+        bytes = rest;
+        Some(taken)
+    };
+    let mut escapes = Vec::with_capacity(count);
+    for _ in 0..count {
+        let flags = take(1)?[0];
+        if flags & ESCAPED == 0 {
+            escapes.push(None);
+            continue;
+        }
+        let iterations = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        let mut float = || Some(f64::from_le_bytes(take(8)?.try_into().unwrap()));
+        let z = Complex::new(float()?, float()?);
+        let derivative = match flags & DERIVATIVE {
+            0 => None,
+            _ => Some(Complex::new(float()?, float()?)),
+        };
+        let stripe = match flags & STRIPE {
+            0 => None,
+            _ => Some(float()?),
+        };
+        escapes.push(Some(Escape {
+            iterations,
+            z,
+            derivative,
+            stripe,
+        }));
+    }
+    bytes.is_empty().then_some(escapes)
+}
+
+/// Writes the header of a checkpoint of a render with `options`.
+pub fn write_header<W: Write>(mut w: W, options: &RenderOptions) -> io::Result<()> {
+    let key = key(options);
+    w.write_all(MAGIC)?;
+    w.write_all(&[VERSION, 0, 0, 0])?;
+    w.write_all(&(key.len() as u32).to_le_bytes())?;
+    w.write_all(key.as_bytes())
+}
+
+/// Writes the record of the band of rows starting at `top` holding
+/// `escapes`, which cover whole rows `width` pixels wide.
+pub fn write_band<W: Write>(
+    mut w: W,
+    top: u32,
+    width: u32,
+    escapes: &[Option<Escape>],
+) -> io::Result<()> {
+    let mut bytes = Vec::new();
+    for escape in escapes {
+        encode_escape(&mut bytes, escape);
+    }
+    let rows = (escapes.len() / width.max(1) as usize) as u32;
+    w.write_all(&top.to_le_bytes())?;
+    w.write_all(&rows.to_le_bytes())?;
+    w.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    w.write_all(&bytes)
+}
+
+/// Reads a checkpoint back for a render with `options`: the escape times of
+/// the rows it finished, from the top, and how many bytes of it hold them.
+/// Fails if it is of another render.
+pub fn read<R: Read>(mut r: R, options: &RenderOptions) -> io::Result<(Vec<Option<Escape>>, u64)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut header = [0; 12];
+    r.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid("not a checkpoint"));
+    }
+    if header[4] != VERSION {
+        return Err(invalid("unsupported checkpoint version"));
+    }
+    // The length is checked first, so that a corrupt one isn't allocated.
+    let expected = self::key(options);
+    let key_length = u32::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+    if key_length != expected.len() {
+        return Err(invalid("checkpoint is of another render"));
+    }
+    let mut key = vec![0; key_length];
+    r.read_exact(&mut key)?;
+    if key != expected.as_bytes() {
+        return Err(invalid("checkpoint is of another render"));
+    }
+    let mut length = (header.len() + key.len()) as u64;
+    let width = options.bounds.0 as usize;
+    let mut escapes = Vec::new();
+    loop {
+        let mut record = [0; 16];
+        if r.read_exact(&mut record).is_err() {
+            break;
+        }
+        let top = u32::from_le_bytes(record[..4].try_into().unwrap()) as usize;
+        let rows = u32::from_le_bytes(record[4..8].try_into().unwrap()) as usize;
+        let size = u64::from_le_bytes(record[8..].try_into().unwrap());
+        if top * width != escapes.len() || top + rows > options.bounds.1 as usize {
+            return Err(invalid("checkpoint bands are out of order"));
+        }
+        let mut bytes = Vec::new();
+        r.by_ref().take(size).read_to_end(&mut bytes)?;
+        let Some(band) = decode_escapes(&bytes, rows * width) else {
+            // Cut short, or not yet all written.
+            break;
+        };
+        escapes.extend(band);
+        length += record.len() as u64 + size;
+    }
+    Ok((escapes, length))
+}
+
+/// Computes the escape times of `renderer`'s image in bands, saving each to
+/// the checkpoint at `path` as it is done. With `resume`, the rows already
+/// in the checkpoint are taken from it rather than computed again;
+/// otherwise a new one is started. Scanning is the only algorithm, since
+/// boundary tracing needs the whole image at once.
+///
+/// A cancelled render stops after the rows under way, with the rows done
+/// so far, from the top, which the checkpoint then holds.
+pub fn render(renderer: &Renderer, path: &str, resume: bool) -> io::Result<Vec<Option<Escape>>> {
+    let options = renderer.options();
+    assert!(
+        options.algorithm == Algorithm::Scan,
+        "only scans can be checkpointed"
+    );
+    let width = options.bounds.0;
+    let (escapes, mut file) = if resume {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let (escapes, length) = read(BufReader::new(&mut file), options)?;
+        file.set_len(length)?;
+        file.seek(SeekFrom::End(0))?;
+        (escapes, file)
+    } else {
+        let mut file = File::create(path)?;
+        write_header(&mut file, options)?;
+        (Vec::new(), file)
+    };
+    render_bands(renderer, escapes, |top, band| {
+        write_band(&mut file, top, width, band)?;
+        file.sync_data()
+    })
+}
+
+/// Computes the escape times of `renderer`'s image in bands, after the
+/// rows already in `escapes`, handing each band to `save` with its first
+/// row as it is done. A cancelled render stops after the rows under way,
+/// with the rows done so far, the last of them in a band cut short.
+pub fn render_bands<F>(
+    renderer: &Renderer,
+    mut escapes: Vec<Option<Escape>>,
+    mut save: F,
+) -> io::Result<Vec<Option<Escape>>>
+where
+    F: FnMut(u32, &[Option<Escape>]) -> io::Result<()>,
+{
+    let (width, height) = renderer.options().bounds;
+    escapes.reserve(width as usize * height as usize - escapes.len());
+    let progress = renderer.progress();
+    progress.start(width as u64 * height as u64);
+    progress.add(escapes.len() as u64);
+    let rows = (BAND_PIXELS / width.max(1)).max(1);
+    let first = (escapes.len() / width.max(1) as usize) as u32;
+    for top in (first..height).step_by(rows as usize) {
+        if progress.is_cancelled() {
+            break;
+        }
+        let bottom = (top + rows).min(height);
+        let _span = log::span(
+            Level::Debug,
+            "band",
+            format_args!("rows={}..{}", top, bottom),
+        );
+        let band = renderer.render_row_escapes(top..bottom);
+        let cut_short = band.len() < width as usize * (bottom - top) as usize;
+        save(top, &band)?;
+        escapes.extend(band);
+        if cut_short {
+            break;
+        }
+    }
+    renderer.finished(renderer.rows_in(&escapes));
+    Ok(escapes)
+}
+
+#[test]
+fn test_checkpoint_round_trip() {
+    let options = RenderOptions {
+        bounds: (4, 3),
+        coloring: crate::Coloring::Distance,
+        ..RenderOptions::default()
+    };
+    let escape = |iterations, stripe| {
+        Some(Escape {
+            iterations,
+            z: Complex::new(2.5, -1.0),
+            derivative: Some(Complex::new(100.0, 3.0)),
+            stripe,
+        })
+    };
+    let top = [None, escape(3, None), escape(7, Some(0.25)), None];
+    let rest = [escape(1, None); 8];
+    let mut bytes = Vec::new();
+    write_header(&mut bytes, &options).unwrap();
+    write_band(&mut bytes, 0, 4, &top).unwrap();
+    let finished = bytes.len() as u64;
+    write_band(&mut bytes, 1, 4, &rest).unwrap();
+    let (escapes, length) = read(&bytes[..], &options).unwrap();
+    assert_eq!(escapes, [&top[..], &rest[..]].concat());
+    assert_eq!(length, bytes.len() as u64);
+    // A band cut short is left out.
+    let (escapes, length) = read(&bytes[..bytes.len() - 3], &options).unwrap();
+    assert_eq!(escapes, top);
+    assert_eq!(length, finished);
+    let other = RenderOptions {
+        max_iter: 1000,
+        ..options.clone()
+    };
+    assert!(read(&bytes[..], &other).is_err());
+    // A key length past anything the options could have.
+    bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+    let error = read(&bytes[..], &options).unwrap_err();
+    assert_eq!(error.to_string(), "checkpoint is of another render");
+}
+
+#[test]
+fn test_resume() {
+    let options = RenderOptions {
+        bounds: (60, 40),
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let path = std::env::temp_dir().join("mandelbrot_test_checkpoint.mbcp");
+    let path = path.to_str().unwrap();
+    let whole = Renderer::new(options.clone()).render_escapes();
+    let renderer = Renderer::new(options.clone());
+    assert_eq!(render(&renderer, path, false).unwrap(), whole);
+    assert!(renderer.progress().is_finished());
+    // Keep the header and the first 10 rows, as if it had stopped there.
+    let mut file = File::create(path).unwrap();
+    write_header(&mut file, &options).unwrap();
+    write_band(&mut file, 0, 60, &whole[..600]).unwrap();
+    file.write_all(&[1, 2, 3]).unwrap();
+    drop(file);
+    let renderer = Renderer::new(options.clone());
+    assert_eq!(render(&renderer, path, true).unwrap(), whole);
+    assert!(renderer.progress().is_finished());
+    // A cancelled render stops before the next band, and keeps what it has.
+    let renderer = Renderer::new(options.clone());
+    renderer.progress().cancel();
+    assert_eq!(render(&renderer, path, false).unwrap(), []);
+    let (escapes, _) = read(File::open(path).unwrap(), &options).unwrap();
+    assert_eq!(escapes, []);
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,4936 @@
+use crate::{
+    bookmarks::{self, Bookmark},
+    config::{self, Value},
+    server,
+};
+use mandelbrot::{
+    animation::{self, JuliaPath, View},
+    antialias,
+    bailout::{self, Bailout},
+    buddhabrot::{Buddhabrot, Nebula},
+    coloring, contour, dither, fixed,
+    formula::Formula,
+    fractal, gif, gradient, hdr, interior, jpeg, kfr,
+    log::Level,
+    lyapunov::{self, Lyapunov},
+    metadata,
+    palette::{self, ColorSpace},
+    par,
+    pyramid::{self, Pyramid},
+    webp, Adjustments, Algorithm, Coloring, Dither, Fixed, Format, Fractal, Interior, Light,
+    Palette, Polynomial, Precision, RenderOptions, Shortcuts, Transfer,
+};
+use num::Complex;
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path, str::FromStr, sync::Arc};
+
+/// A named command line option. Options with a `value` take an argument,
+/// either as the next word (`--size 800x600`) or inline (`--size=800x600`).
+struct Flag {
+    long: &'static str,
+    aliases: &'static [&'static str],
+    short: Option<char>,
+    value: Option<&'static str>,
+    help: &'static str,
+}
+
+const FLAGS: &[Flag] = &[
+    Flag {
+        long: "output",
+        aliases: &[],
+        short: Some('o'),
+        value: Some("FILE"),
+        help: "File to write the rendered image to, or - for standard output",
+    },
+    Flag {
+        long: "format",
+        aliases: &[],
+        short: Some('f'),
+        value: Some("png|jpeg|ppm|pgm|exr|sixel|tiff|webp|svg|stl|obj"),
+        help: "Image format [default: from the output file's extension, or png if it has none]",
+    },
+    Flag {
+        long: "quality",
+        aliases: &[],
+        short: None,
+        value: Some("1-100"),
+        help: "JPEG or WebP quality; WebPs are always lossless VP8L, exact at 100 and near \
+               lossless below [default: 90 for JPEG, 100 for WebP]",
+    },
+    Flag {
+        long: "plain",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Write PPM and PGM images as plain text instead of binary",
+    },
+    Flag {
+        long: "transparent-interior",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Write an RGBA PNG whose pixels inside the set are fully transparent, for \
+               compositing over other backgrounds",
+    },
+    Flag {
+        long: "hdr",
+        aliases: &[],
+        short: None,
+        value: Some("NITS"),
+        help: "Write a 16-bit HDR PNG in BT.2100 PQ, whose brightest colors reach NITS, from 203 \
+               for SDR white to 10000",
+    },
+    Flag {
+        long: "progressive",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Write an APNG that plays once through passes at 1/8, 1/4 and 1/2 the resolution \
+               to the full image",
+    },
+    Flag {
+        long: "contours",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "How many levels of escape time svg output traces lines along [default: 16]",
+    },
+    Flag {
+        long: "base-thickness",
+        aliases: &[],
+        short: None,
+        value: Some("UNITS"),
+        help: "How thick the base of stl and obj meshes is, in pixel widths [default: a fiftieth \
+               of the width]",
+    },
+    Flag {
+        long: "height-scale",
+        aliases: &[],
+        short: None,
+        value: Some("UNITS"),
+        help: "How high the top of stl and obj meshes, or the relief of --normal-map, rises \
+               above the base, in pixel widths [default: a tenth of the width]",
+    },
+    Flag {
+        long: "heightmap",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Also write the escape values to FILE as a 16-bit grayscale PNG, for displacement",
+    },
+    Flag {
+        long: "normal-map",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Also write a tangent-space normal map of the escape values to FILE as a PNG, with \
+               green up as in OpenGL",
+    },
+    Flag {
+        long: "dump-iters",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Also write the raw iteration counts to FILE, for recoloring without rendering again",
+    },
+    Flag {
+        long: "dump-npy",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Also write the iteration counts to FILE as a NumPy .npy array, of uint32 counts or \
+               float64 smooth ones",
+    },
+    Flag {
+        long: "tile",
+        aliases: &[],
+        short: None,
+        value: Some("ROWS"),
+        help: "Render a PNG or TIFF in bands of ROWS rows, writing each out as it is done, so that huge \
+               images never have to fit in memory; 0 picks about a million pixels a band",
+    },
+    Flag {
+        long: "checkpoint",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Save the rows done to FILE as the render goes, so that it can be carried on with \
+               --resume if it is cut short; removed once the image is written",
+    },
+    Flag {
+        long: "resume",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Carry on the render saved to checkpoint FILE, with the same view and settings, \
+               checkpointing to it as it goes",
+    },
+    Flag {
+        long: "stats",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Write metrics of the render to FILE as JSON: times, iterations, a histogram of \
+               escape times and peak memory",
+    },
+    Flag {
+        long: "no-sidecar",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Don't write the render's parameters to a .json file next to the image",
+    },
+    Flag {
+        long: "from-sidecar",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Render with the parameters in a .json sidecar; options given here win",
+    },
+    Flag {
+        long: "size",
+        aliases: &[],
+        short: Some('s'),
+        value: Some("WIDTHxHEIGHT"),
+        help: "Image size in pixels, e.g. 1000x750",
+    },
+    Flag {
+        long: "upper-left",
+        aliases: &[],
+        short: Some('u'),
+        value: Some("RE,IM"),
+        help: "Complex point at the upper left corner of the image [default: a view of the \
+               whole set]",
+    },
+    Flag {
+        long: "lower-right",
+        aliases: &[],
+        short: Some('l'),
+        value: Some("RE,IM"),
+        help: "Complex point at the lower right corner of the image [default: a view of the \
+               whole set]",
+    },
+    Flag {
+        long: "preserve-aspect",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Widen or heighten the view about its center to the shape of the image, so that \
+               circles stay round [default]",
+    },
+    Flag {
+        long: "stretch",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Stretch the view to the shape of the image as it is given",
+    },
+    Flag {
+        long: "flip",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Draw the view upside down, with the imaginary axis pointing down [default for \
+               the Burning Ship's whole view]",
+    },
+    Flag {
+        long: "location",
+        aliases: &[],
+        short: None,
+        value: Some("NAME"),
+        help: "Render a bookmarked view instead of giving the corners; see `bookmark list`",
+    },
+    Flag {
+        long: "import-kfr",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Render the view and iterations of a Kalles Fraktaler .kfr file instead of giving \
+               the corners",
+    },
+    Flag {
+        long: "import-par",
+        aliases: &[],
+        short: None,
+        value: Some("FILE NAME"),
+        help: "Render the view, iterations and fractal of the entry NAME of a Fractint .par file \
+               instead of giving the corners",
+    },
+    Flag {
+        long: "threads",
+        aliases: &[],
+        short: Some('t'),
+        value: Some("N"),
+        help: "Number of threads to render with [default: one per CPU]",
+    },
+    Flag {
+        long: "max-iter",
+        aliases: &["iterations"],
+        short: Some('i'),
+        value: Some("N"),
+        help: "Maximum number of iterations per point [default: 255]",
+    },
+    Flag {
+        long: "palette",
+        aliases: &[],
+        short: Some('p'),
+        value: Some("NAME"),
+        help: "Color palette: grayscale, fire, ocean, classic, viridis, magma, inferno or \
+               sepia; see `palette list` [default: grayscale]",
+    },
+    Flag {
+        long: "gradient",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Color with the gradient in FILE instead: CSV lines of position,r,g,b, or a GIMP \
+               .ggr gradient",
+    },
+    Flag {
+        long: "palette-from-image",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "Color with the dominant colors of the PNG or JPEG picture in FILE instead, from \
+               darkest to lightest",
+    },
+    Flag {
+        long: "color-space",
+        aliases: &[],
+        short: None,
+        value: Some("srgb|linear|oklab|lch"),
+        help: "Blend between palette colors in sRGB, in linear light, in the perceptually even \
+               Oklab, or by its lightness, chroma and hue [default: srgb]",
+    },
+    Flag {
+        long: "scale",
+        aliases: &[],
+        short: None,
+        value: Some("linear|log|sqrt|cbrt|power=P"),
+        help: "How escape time coloring spreads iterations over the palette: evenly, giving the \
+               first ones more of it by their logarithm or a root, or as their power P \
+               [default: linear]",
+    },
+    Flag {
+        long: "palette-cycle",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "Start the palette over every N iterations instead of spreading it up to \
+               --max-iter, for escape time coloring",
+    },
+    Flag {
+        long: "depth",
+        aliases: &[],
+        short: None,
+        value: Some("8|16"),
+        help:
+            "Bits per sample; 16 writes the escape values as grayscale for recoloring [default: 8]",
+    },
+    Flag {
+        long: "no-smooth",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Color by integer escape counts, showing the iteration bands",
+    },
+    Flag {
+        long: "coloring",
+        aliases: &[],
+        short: None,
+        value: Some("escape-time|histogram|distance|stripe-average|grayscale"),
+        help: "Spread the palette evenly by iterations, by how many pixels escape sooner, by \
+               distance to the set, or by the stripe average of orbits, the last two in f64; \
+               or shade iterations in gray without the palette [default: escape-time]",
+    },
+    Flag {
+        long: "interior",
+        aliases: &[],
+        short: None,
+        value: Some("flat|modulus|multiplier|convergence"),
+        help: "Color the inside of the set by the final |z|, by the multiplier of the cycle \
+               its orbits settle on, or by how soon they settle [default: flat]",
+    },
+    Flag {
+        long: "shade",
+        aliases: &[],
+        short: None,
+        value: Some("AZIMUTH,ELEVATION"),
+        help: "Emboss the image, lit from AZIMUTH degrees counterclockwise from the right and \
+               ELEVATION degrees above, e.g. 135,45",
+    },
+    Flag {
+        long: "dither",
+        aliases: &[],
+        short: None,
+        value: Some("bayer|blue-noise"),
+        help: "Dither colors as they are rounded to 8 bits, by a Bayer matrix or blue noise, \
+               to hide the bands of smooth gradients",
+    },
+    Flag {
+        long: "exposure",
+        aliases: &[],
+        short: None,
+        value: Some("STOPS"),
+        help: "Brighten the finished image by STOPS stops of light, or darken it below 0; \
+               buddhabrot --nebula takes R,G,B factors for its channels instead [default: 0]",
+    },
+    Flag {
+        long: "contrast",
+        aliases: &[],
+        short: None,
+        value: Some("FACTOR"),
+        help: "Pull the finished image's colors FACTOR times further from middle gray \
+               [default: 1]",
+    },
+    Flag {
+        long: "brightness",
+        aliases: &[],
+        short: None,
+        value: Some("AMOUNT"),
+        help: "Add AMOUNT of white, from -1 to 1, to the finished image's colors [default: 0]",
+    },
+    Flag {
+        long: "gamma",
+        aliases: &[],
+        short: None,
+        value: Some("GAMMA"),
+        help: "Raise the finished image's colors to 1/GAMMA, brightening the midtones above 1 \
+               [default: 1]",
+    },
+    Flag {
+        long: "aa",
+        aliases: &["antialias"],
+        short: Some('a'),
+        value: Some("N"),
+        help: "Average NxN jittered samples per pixel to smooth edges, N up to 16 [default: 1]",
+    },
+    Flag {
+        long: "adaptive",
+        aliases: &[],
+        short: None,
+        value: Some("STDDEV"),
+        help: "Only supersample pixels whose neighbors' colors vary by more than STDDEV (0-255)",
+    },
+    Flag {
+        long: "precision",
+        aliases: &[],
+        short: None,
+        value: Some("auto|f32|f64|dd|BITS"),
+        help: "Coordinate arithmetic: f64, f32 for quick previews, dd (double-double) for \
+               zooms a little too deep for f64, or fixed point with BITS fractional bits \
+               [default: auto]",
+    },
+    Flag {
+        long: "algorithm",
+        aliases: &[],
+        short: None,
+        value: Some("scan|border-trace"),
+        help: "Iterate every pixel, or fill in rectangles with uniform borders [default: scan]",
+    },
+    Flag {
+        long: "no-bulb-check",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Iterate points in the main cardioid and period-2 bulb instead of skipping them",
+    },
+    Flag {
+        long: "no-periodicity-check",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Iterate periodic orbits to the limit, for exact iteration counts",
+    },
+    Flag {
+        long: "bailout",
+        aliases: &[],
+        short: None,
+        value: Some("R"),
+        help: "Count orbits as escaped once past R, at least 2; larger radii smooth \
+               colorings better [default: 2]",
+    },
+    Flag {
+        long: "bailout-test",
+        aliases: &[],
+        short: None,
+        value: Some("modulus|manhattan|max"),
+        help: "How far out z is measured against the bailout: |z|, |Re z| + |Im z| or the \
+               larger of the two [default: modulus]",
+    },
+    Flag {
+        long: "fractal",
+        aliases: &[],
+        short: None,
+        value: Some("mandelbrot|burning-ship|multibrot|tricorn|phoenix"),
+        help: "The formula to iterate; any but mandelbrot in f64 [default: mandelbrot]",
+    },
+    Flag {
+        long: "exponent",
+        aliases: &[],
+        short: None,
+        value: Some("D"),
+        help: "Iterate z^D + c, for any D above 1; implies --fractal multibrot [default: 3]",
+    },
+    Flag {
+        long: "phoenix-p",
+        aliases: &[],
+        short: None,
+        value: Some("RE,IM"),
+        help: "Iterate z^2 + c + P z', z' being the z before; implies --fractal phoenix \
+               [default: -0.5,0]",
+    },
+    Flag {
+        long: "formula",
+        aliases: &[],
+        short: None,
+        value: Some("EXPR"),
+        help: "Iterate a formula in z and c, such as 'z^2 + c*sin(z)', in f64",
+    },
+    Flag {
+        long: "julia",
+        aliases: &[],
+        short: None,
+        value: Some("RE,IM"),
+        help: "Render the Julia set of the constant RE,IM instead, in f64",
+    },
+    Flag {
+        long: "newton",
+        aliases: &[],
+        short: None,
+        value: Some("A,B,.."),
+        help: "Color points by the root Newton's method takes them to for the polynomial with \
+               coefficients A,B,.., highest power first; 1,0,0,-1 is z^3 - 1",
+    },
+    Flag {
+        long: "frames",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "animate: Number of frames to write",
+    },
+    Flag {
+        long: "from",
+        aliases: &[],
+        short: None,
+        value: Some("RE,IM|NAME"),
+        help: "animate: Center or bookmark of the first frame",
+    },
+    Flag {
+        long: "from-zoom",
+        aliases: &[],
+        short: None,
+        value: Some("ZOOM"),
+        help: "animate: Magnification of the first frame relative to a view 4 high [default: 1]",
+    },
+    Flag {
+        long: "to",
+        aliases: &[],
+        short: None,
+        value: Some("RE,IM|NAME"),
+        help: "animate: Center or bookmark of the last frame [default: --from]",
+    },
+    Flag {
+        long: "to-zoom",
+        aliases: &[],
+        short: None,
+        value: Some("ZOOM"),
+        help: "animate: Magnification of the last frame [default: --from-zoom]",
+    },
+    Flag {
+        long: "julia-path",
+        aliases: &[],
+        short: None,
+        value: Some("cardioid|RE,IM/RE,IM/..."),
+        help: "animate: Hold the view and morph a Julia set, moving its constant once around the \
+               main cardioid or through the waypoints",
+    },
+    Flag {
+        long: "video",
+        aliases: &[],
+        short: None,
+        value: Some("FILE"),
+        help: "animate: Encode the frames as a video instead of writing images; .gif is built in, \
+               other formats need ffmpeg",
+    },
+    Flag {
+        long: "fps",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "animate: Frames per second of the video [default: 30]",
+    },
+    Flag {
+        long: "loop",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "animate: Times a GIF plays, or 0 to repeat forever [default: 0]",
+    },
+    Flag {
+        long: "colors",
+        aliases: &[],
+        short: None,
+        value: Some("2-256"),
+        help: "animate: Colors each GIF frame is reduced to [default: 256]",
+    },
+    Flag {
+        long: "samples",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "buddhabrot: Random points whose orbits are traced [default: 20 per pixel]",
+    },
+    Flag {
+        long: "min-iter",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "buddhabrot: Only trace orbits escaping after at least N iterations [default: 0]",
+    },
+    Flag {
+        long: "seed",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "buddhabrot: Picks the random points; the same seed gives the same image \
+               [default: 0]",
+    },
+    Flag {
+        long: "nebula",
+        aliases: &[],
+        short: None,
+        value: Some("R,G,B"),
+        help: "buddhabrot: Count the orbits escaping within R, G and B iterations into the red, \
+               green and blue channels instead of coloring with the palette, e.g. 5000,500,50",
+    },
+    Flag {
+        long: "sequence",
+        aliases: &[],
+        short: None,
+        value: Some("AB.."),
+        help: "lyapunov: The order the logistic map takes its rates A and B in, repeated \
+               [default: AB]",
+    },
+    Flag {
+        long: "preview-term",
+        aliases: &[],
+        short: None,
+        value: None,
+        help: "Print a small preview of the view in the terminal instead of writing the image",
+    },
+    Flag {
+        long: "port",
+        aliases: &[],
+        short: None,
+        value: Some("PORT"),
+        help: "serve: Port to listen on [default: 8080]",
+    },
+    Flag {
+        long: "listen",
+        aliases: &[],
+        short: None,
+        value: Some("ADDR"),
+        help: "worker: Address to take render jobs on, such as 0.0.0.0:7878",
+    },
+    Flag {
+        long: "workers",
+        aliases: &[],
+        short: None,
+        value: Some("HOSTS"),
+        help: "coordinate: Comma separated workers to share the render with, as HOST or \
+               HOST:PORT [default port: 7878]",
+    },
+    Flag {
+        long: "jobs",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "batch: How many jobs to render at once [default: 1]",
+    },
+    Flag {
+        long: "tile-size",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "pyramid: Width and height of each tile, without the overlap [default: 254]",
+    },
+    Flag {
+        long: "overlap",
+        aliases: &[],
+        short: None,
+        value: Some("N"),
+        help: "pyramid: How many pixels each tile shares with its neighbors [default: 1]",
+    },
+    Flag {
+        long: "config",
+        aliases: &[],
+        short: Some('c'),
+        value: Some("FILE"),
+        help: "Read options from a TOML file of `flag = value` lines; flags given here win",
+    },
+    Flag {
+        long: "quiet",
+        aliases: &[],
+        short: Some('q'),
+        value: None,
+        help: "Don't show a progress bar",
+    },
+    Flag {
+        long: "verbose",
+        aliases: &[],
+        short: Some('v'),
+        value: None,
+        help: "Print how the image is rendered, such as the precision picked for the zoom",
+    },
+    Flag {
+        long: "log-level",
+        aliases: &[],
+        short: None,
+        value: Some("LEVEL"),
+        help: "Log the phases of the work as they start and finish, with their times: off, \
+               error, warn, info for parsing, rendering and encoding, debug for each band and \
+               job, or trace [default: off]",
+    },
+    Flag {
+        long: "help",
+        aliases: &[],
+        short: Some('h'),
+        value: None,
+        help: "Print this help and exit",
+    },
+];
+
+/// The positional arguments accepted for compatibility with the original
+/// `FILE PIXELS UPPERLEFT LOWERRIGHT` command line, in order.
+const POSITIONALS: &[&str] = &["output", "size", "upper-left", "lower-right"];
+
+/// The flags only `animate` takes.
+const ANIMATE_FLAGS: &[&str] = &[
+    "frames",
+    "from",
+    "from-zoom",
+    "to",
+    "to-zoom",
+    "julia-path",
+    "video",
+    "fps",
+    "loop",
+    "colors",
+];
+
+/// The flags only `buddhabrot` takes.
+const BUDDHABROT_FLAGS: &[&str] = &["samples", "min-iter", "seed", "nebula"];
+
+/// The flags only `lyapunov` takes.
+const LYAPUNOV_FLAGS: &[&str] = &["sequence"];
+
+/// The flags only `worker` takes.
+const WORKER_FLAGS: &[&str] = &["listen"];
+
+/// The flags only `coordinate` takes.
+const COORDINATE_FLAGS: &[&str] = &["workers"];
+
+/// The flags only `batch` takes.
+const BATCH_FLAGS: &[&str] = &["jobs"];
+
+/// The flags only `pyramid` takes.
+const PYRAMID_FLAGS: &[&str] = &["tile-size", "overlap"];
+
+/// The flags only one subcommand takes, with the subcommand.
+const SUBCOMMAND_FLAGS: &[(&str, &[&str])] = &[
+    ("animate", ANIMATE_FLAGS),
+    ("buddhabrot", BUDDHABROT_FLAGS),
+    ("lyapunov", LYAPUNOV_FLAGS),
+    ("worker", WORKER_FLAGS),
+    ("coordinate", COORDINATE_FLAGS),
+    ("batch", BATCH_FLAGS),
+    ("pyramid", PYRAMID_FLAGS),
+];
+
+/// The flags that only make sense for escape time renders, which the
+/// `buddhabrot` and `lyapunov` images aren't.
+const ESCAPE_TIME_FLAGS: &[&str] = &[
+    "fractal",
+    "exponent",
+    "phoenix-p",
+    "formula",
+    "julia",
+    "newton",
+    "bailout",
+    "bailout-test",
+    "precision",
+    "algorithm",
+    "coloring",
+    "scale",
+    "palette-cycle",
+    "dither",
+    "interior",
+    "shade",
+    "no-smooth",
+    "aa",
+    "adaptive",
+    "depth",
+    "dump-iters",
+    "dump-npy",
+    "tile",
+    "checkpoint",
+    "resume",
+    "stats",
+    "preview-term",
+    "verbose",
+    "transparent-interior",
+    "hdr",
+    "progressive",
+    "contours",
+    "base-thickness",
+    "height-scale",
+    "heightmap",
+    "normal-map",
+];
+
+/// Parsed command line: where to write the image and how to render it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    pub output: String,
+    pub options: RenderOptions,
+    pub format: Format,
+    /// JPEG or WebP quality, from 1 to 100.
+    pub quality: u8,
+    /// Whether Netpbm images are written as text.
+    pub plain: bool,
+    /// 8 for a colored image, 16 for grayscale escape values.
+    pub depth: u32,
+    /// Whether to write an RGBA PNG with the points inside the set left
+    /// transparent.
+    pub transparent_interior: bool,
+    /// The peak brightness, in nits, of an HDR PNG to write; see
+    /// `mandelbrot::hdr`.
+    pub hdr: Option<f64>,
+    /// Whether to write an APNG of passes from coarse to whole; see
+    /// `mandelbrot::progressive`.
+    pub progressive: bool,
+    /// How many levels svg output traces contours at; see
+    /// `mandelbrot::contour`.
+    pub contours: u32,
+    /// How thick the base of a mesh is and how high its top rises, in
+    /// pixel widths; see `mandelbrot::mesh`.
+    pub base_thickness: f64,
+    pub height_scale: f64,
+    /// Where to write the iteration counts; see `mandelbrot::dump`.
+    pub dump_iters: Option<String>,
+    /// Where to write the iteration counts as a NumPy array.
+    pub dump_npy: Option<String>,
+    /// Where to write the escape values as heights and as a normal map;
+    /// see `mandelbrot::mesh`.
+    pub heightmap: Option<String>,
+    pub normal_map: Option<String>,
+    /// How many rows to render at a time, writing each band out as it is
+    /// done; see `mandelbrot::tiled`.
+    pub tile: Option<u32>,
+    /// Where to save the rows done as the render goes; see
+    /// `mandelbrot::checkpoint`.
+    pub checkpoint: Option<String>,
+    /// Whether to carry on from `checkpoint` rather than start it afresh.
+    pub resume: bool,
+    /// Where to write the render's metrics; see `mandelbrot::stats`.
+    pub stats: Option<String>,
+    /// Where to write the render's parameters, next to the image, if at
+    /// all; see `mandelbrot::metadata::write_json`.
+    pub sidecar: Option<String>,
+    /// The addresses of the workers to share the render with, if any; see
+    /// `mandelbrot::distributed`.
+    pub workers: Vec<String>,
+    /// Whether to only print a preview in the terminal.
+    pub preview: bool,
+    pub quiet: bool,
+    pub verbose: bool,
+    /// The most detailed spans to log, if any; see `mandelbrot::log`.
+    pub log_level: Option<Level>,
+}
+
+/// What the program should do after looking at its arguments.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Render(Box<Cli>),
+    Animate(Box<Animation>),
+    Serve(Box<Server>),
+    Worker(Box<Worker>),
+    Batch(Box<Batch>),
+    Bench(Box<Bench>),
+    Buddhabrot(Box<BuddhabrotRender>),
+    Lyapunov(Box<LyapunovRender>),
+    Pyramid(Box<PyramidRender>),
+    Bookmark(BookmarkCommand),
+    Repl(Box<Session>),
+    Explore(Box<Session>),
+    PaletteList,
+    Help,
+}
+
+impl Command {
+    /// The most detailed spans to log while running it. A batch logs as much
+    /// as its most detailed job.
+    pub fn log_level(&self) -> Option<Level> {
+        match self {
+            Command::Render(cli) => cli.log_level,
+            Command::Animate(animation) => animation.frame.log_level,
+            Command::Serve(server) => server.frame.log_level,
+            Command::Worker(worker) => worker.frame.log_level,
+            Command::Batch(batch) => batch.jobs.iter().filter_map(|(_, cli)| cli.log_level).max(),
+            Command::Bench(bench) => bench.frame.log_level,
+            Command::Buddhabrot(render) => render.frame.log_level,
+            Command::Lyapunov(render) => render.frame.log_level,
+            Command::Pyramid(render) => render.frame.log_level,
+            Command::Repl(session) | Command::Explore(session) => {
+                session.frame("-").ok().and_then(|cli| cli.log_level)
+            }
+            Command::Bookmark(_) | Command::PaletteList | Command::Help => None,
+        }
+    }
+}
+
+/// A zoom animation: one render per view.
+#[derive(Debug, PartialEq)]
+pub struct Animation {
+    /// How every frame is rendered. Its output and view are those of the
+    /// first frame, and are replaced for each of the others.
+    pub frame: Cli,
+    pub views: Vec<View>,
+    /// The Julia constant of each frame, for a morph; empty for a zoom.
+    pub julia: Vec<Complex<f64>>,
+    pub output: FrameOutput,
+}
+
+/// Where the frames of an animation go.
+#[derive(Debug, PartialEq)]
+pub enum FrameOutput {
+    /// One image file per frame, in `dir`.
+    Files { dir: String, paths: Vec<String> },
+    /// A video encoded by ffmpeg from the raw frames; see `video`.
+    Video { path: String, fps: u32 },
+    /// An animated GIF that plays `loops` times, or forever for 0, with
+    /// each frame reduced to `colors` colors; see `mandelbrot::gif`.
+    Gif {
+        path: String,
+        fps: u32,
+        loops: u16,
+        colors: usize,
+    },
+}
+
+/// A tile server: every tile is rendered like `frame`, at its own view.
+#[derive(Debug, PartialEq)]
+pub struct Server {
+    pub frame: Cli,
+    pub port: u16,
+}
+
+/// An interactive session: a view, which its commands move, and the flags
+/// to render it with, which they change; see `repl` and `explore`.
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    pub view: View,
+    flags: HashMap<&'static str, String>,
+}
+
+/// The furthest out a session zooms. Much further, and the width of a wide
+/// view overflows `f64`.
+const MIN_SESSION_ZOOM: f64 = 1e-290;
+
+/// The flags a session doesn't take: what it writes and where it looks
+/// are up to its commands.
+const SESSION_EXCLUDED_FLAGS: &[&str] = &[
+    "output",
+    "upper-left",
+    "lower-right",
+    "flip",
+    "location",
+    "import-kfr",
+    "import-par",
+    "format",
+    "preview-term",
+    "dump-iters",
+    "dump-npy",
+    "heightmap",
+    "normal-map",
+    "stats",
+    "checkpoint",
+    "resume",
+    "from-sidecar",
+];
+
+impl Session {
+    /// The render of the session's view to `output`, checked like a
+    /// command line, in the format its extension names.
+    pub fn frame(&self, output: &str) -> Result<Cli, String> {
+        let mut matches = self.flags.clone();
+        matches.insert("output", output.to_string());
+        insert_corners(&mut matches, &self.view)?;
+        match parse_matches(matches)? {
+            Command::Render(cli) => Ok(*cli),
+            _ => unreachable!("parse_matches only builds renders"),
+        }
+    }
+
+    /// Sets `--flag` to `value`, or to nothing for a switch, or unsets it
+    /// for `None`, as long as the session can still be rendered.
+    pub fn set(&mut self, flag: &str, value: Option<&str>) -> Result<(), String> {
+        let flag = FLAGS
+            .iter()
+            .find(|f| f.long == flag || f.aliases.contains(&flag))
+            .filter(|f| !SESSION_EXCLUDED_FLAGS.contains(&f.long))
+            .ok_or_else(|| format!("'--{}' can't be set in a session", flag))?;
+        let mut flags = self.flags.clone();
+        match (value, flag.value) {
+            (None, _) => {
+                flags.remove(flag.long);
+            }
+            (Some(value), Some(_)) => {
+                flags.insert(flag.long, value.to_string());
+            }
+            (Some(""), None) => {
+                flags.insert(flag.long, String::new());
+            }
+            (Some(_), None) => return Err(format!("'--{}' does not take a value", flag.long)),
+        }
+        let session = Session {
+            view: self.view.clone(),
+            flags,
+        };
+        session.frame("-")?;
+        *self = session;
+        Ok(())
+    }
+
+    /// Zooms the view in by `factor`, or out for a factor below 1, as long
+    /// as the zoom stays finite and its corners can still be worked out.
+    pub fn zoom_by(&mut self, factor: f64) -> Result<(), String> {
+        let zoom = self.view.zoom * factor;
+        if !(zoom.is_finite() && zoom >= MIN_SESSION_ZOOM) {
+            return Err(format!(
+                "the zoom must stay between {:e} and {:e}",
+                MIN_SESSION_ZOOM,
+                f64::MAX
+            ));
+        }
+        self.view.zoom = zoom;
+        Ok(())
+    }
+
+    /// The command line that renders the session's view to `output` as
+    /// `frame` does, quoted for a POSIX shell.
+    pub fn command_line(&self, output: &str) -> Result<String, String> {
+        let mut matches = self.flags.clone();
+        matches.insert("output", output.to_string());
+        insert_corners(&mut matches, &self.view)?;
+        let mut words = vec!["mandelbrot".to_string()];
+        for flag in FLAGS {
+            let Some(value) = matches.get(flag.long) else {
+                continue;
+            };
+            words.push(format!("--{}", flag.long));
+            // Values of several words go back as that many arguments.
+            if let Some(name) = flag.value {
+                words.extend(value.splitn(name.split(' ').count(), ' ').map(shell_quote));
+            }
+        }
+        Ok(words.join(" "))
+    }
+}
+
+/// `word` as a POSIX shell reads it back, in single quotes unless it's
+/// plain enough without.
+fn shell_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || ",._+-=/:@%".contains(c);
+    match !word.is_empty() && word.chars().all(plain) {
+        true => word.to_string(),
+        false => format!("'{}'", word.replace('\'', r"'\''")),
+    }
+}
+
+/// A render worker: every job is rendered like `frame`, at its own size,
+/// view and iteration limit; see `mandelbrot::distributed`.
+#[derive(Debug, PartialEq)]
+pub struct Worker {
+    pub frame: Cli,
+    pub listen: String,
+}
+
+/// Renders listed in a job file, by name, with `parallel` of them at once.
+#[derive(Debug, PartialEq)]
+pub struct Batch {
+    pub jobs: Vec<(String, Cli)>,
+    pub parallel: u32,
+}
+
+/// A benchmark: every view is rendered like `frame`, at its own size, view
+/// and iteration limit.
+#[derive(Debug, PartialEq)]
+pub struct Bench {
+    pub frame: Cli,
+}
+
+/// A Buddhabrot render, written like `frame` but with the tone mapped
+/// orbit counts of `buddhabrot` for colors, or of its `nebula` channels.
+#[derive(Debug, PartialEq)]
+pub struct BuddhabrotRender {
+    pub frame: Cli,
+    pub buddhabrot: Buddhabrot,
+    pub nebula: Option<Nebula>,
+}
+
+/// A Lyapunov render, written like `frame` but with the exponents of
+/// `lyapunov` for colors.
+#[derive(Debug, PartialEq)]
+pub struct LyapunovRender {
+    pub frame: Cli,
+    pub lyapunov: Lyapunov,
+}
+
+/// A Deep Zoom tile pyramid of the render `frame` describes, written to
+/// the `.dzi` descriptor at its output; see `mandelbrot::pyramid`.
+#[derive(Debug, PartialEq)]
+pub struct PyramidRender {
+    pub frame: Cli,
+    pub pyramid: Pyramid,
+}
+
+/// What to do with the bookmark store.
+#[derive(Debug, PartialEq)]
+pub enum BookmarkCommand {
+    /// Save a view, replacing any bookmark of the same name.
+    Add(Bookmark),
+    List,
+}
+
+/// The flags filled in from the `metadata::describe` keywords of an image
+/// by `rerender`.
+const METADATA_FLAGS: &[(&str, &str)] = &[
+    ("Size", "size"),
+    ("Upper left", "upper-left"),
+    ("Lower right", "lower-right"),
+    ("Max iterations", "max-iter"),
+    ("Fractal", "fractal"),
+    ("Exponent", "exponent"),
+    ("Phoenix", "phoenix-p"),
+    ("Formula", "formula"),
+    ("Julia", "julia"),
+    ("Newton", "newton"),
+    ("Bailout", "bailout"),
+    ("Bailout test", "bailout-test"),
+    ("Palette", "palette"),
+    ("Color space", "color-space"),
+    ("Coloring", "coloring"),
+    ("Scale", "scale"),
+    ("Palette cycle", "palette-cycle"),
+    ("Interior", "interior"),
+    ("Shading", "shade"),
+    ("Antialias", "aa"),
+    ("Adaptive", "adaptive"),
+    ("Precision", "precision"),
+    ("Algorithm", "algorithm"),
+    ("Dither", "dither"),
+    ("Depth", "depth"),
+    ("Exposure", "exposure"),
+    ("Contrast", "contrast"),
+    ("Brightness", "brightness"),
+    ("Gamma", "gamma"),
+    ("Samples", "samples"),
+    ("Min iterations", "min-iter"),
+    ("Seed", "seed"),
+    ("Nebula", "nebula"),
+];
+
+/// The `metadata::describe` keywords written as `true` or `false`, with the
+/// value that is given by a switch, and the switch.
+const METADATA_SWITCHES: &[(&str, &str, &str)] = &[
+    ("Smooth", "false", "no-smooth"),
+    ("Flip", "true", "flip"),
+    ("Stretch", "true", "stretch"),
+    ("Bulb check", "false", "no-bulb-check"),
+    ("Periodicity check", "false", "no-periodicity-check"),
+    ("Transparent interior", "true", "transparent-interior"),
+];
+
+/// The `metadata::describe` keywords that only describe the view for a
+/// reader, which `Upper left` and `Lower right` already fix.
+const METADATA_NOTES: &[&str] = &["Software", "Center", "Zoom"];
+
+/// Parses the arguments following the program name.
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let rest = args.get(1..).unwrap_or_default();
+    match args.first().map(String::as_str) {
+        Some("rerender") => parse_rerender(rest),
+        Some("bookmark") => parse_bookmark(rest),
+        Some("palette") => parse_palette(rest),
+        Some("animate") => parse_animate(rest),
+        Some("serve") => parse_serve(rest),
+        Some("worker") => parse_worker(rest),
+        Some("coordinate") => parse_coordinate(rest),
+        Some("batch") => parse_batch(rest),
+        Some("pyramid") => parse_pyramid(rest),
+        Some("bench") => parse_bench(rest),
+        Some("buddhabrot") => parse_buddhabrot(rest),
+        Some("lyapunov") => parse_lyapunov(rest),
+        Some("repl") => parse_session(rest, "repl", Command::Repl),
+        Some("explore") => parse_session(rest, "explore", Command::Explore),
+        _ => parse_render(args),
+    }
+}
+
+/// Parses a render, the command without a subcommand.
+fn parse_render(args: &[String]) -> Result<Command, String> {
+    let mut matches = match_flags(args)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, None)?;
+    apply_sidecar(&mut matches, None)?;
+    apply_location(&mut matches)?;
+    parse_matches(matches)
+}
+
+/// Rejects `ESCAPE_TIME_FLAGS` for `command`.
+fn reject_escape_time_flags(
+    matches: &HashMap<&'static str, String>,
+    command: &str,
+) -> Result<(), String> {
+    match ESCAPE_TIME_FLAGS
+        .iter()
+        .find(|flag| matches.contains_key(*flag))
+    {
+        Some(flag) => Err(format!("'--{}' can't be used with {}", flag, command)),
+        None => Ok(()),
+    }
+}
+
+/// Rejects the flags that only subcommands other than `command` take.
+fn reject_subcommand_flags(
+    matches: &HashMap<&'static str, String>,
+    command: Option<&str>,
+) -> Result<(), String> {
+    for &(subcommand, flags) in SUBCOMMAND_FLAGS {
+        if command == Some(subcommand) {
+            continue;
+        }
+        if let Some(flag) = flags.iter().find(|flag| matches.contains_key(*flag)) {
+            return Err(format!("'--{}' only applies to {}", flag, subcommand));
+        }
+    }
+    Ok(())
+}
+
+/// Parses `palette list`.
+fn parse_palette(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        Some("list") if args.len() == 1 => Ok(Command::PaletteList),
+        Some("-h" | "--help") => Ok(Command::Help),
+        _ => Err("expected 'palette list'".to_string()),
+    }
+}
+
+/// Parses `bookmark add NAME --upper-left RE,IM --lower-right RE,IM
+/// [--max-iter N]` and `bookmark list`. The view is saved by its center and
+/// zoom, so it can be rendered at any size.
+fn parse_bookmark(args: &[String]) -> Result<Command, String> {
+    let (name, rest) = match (args.first().map(String::as_str), args.get(1)) {
+        (Some("list"), _) if args.len() == 1 => {
+            return Ok(Command::Bookmark(BookmarkCommand::List))
+        }
+        (Some("add"), Some(name)) if !name.starts_with('-') => (name, &args[2..]),
+        (Some("-h" | "--help"), _) => return Ok(Command::Help),
+        (Some("add"), _) => return Err("bookmark add needs a name".to_string()),
+        _ => return Err("expected 'bookmark add NAME' or 'bookmark list'".to_string()),
+    };
+    let matches = match_flags(rest)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    if let Some(flag) = matches
+        .keys()
+        .find(|flag| !matches!(**flag, "upper-left" | "lower-right" | "max-iter"))
+    {
+        return Err(format!("'--{}' can't be bookmarked", flag));
+    }
+    let upper_left = required(&matches, "upper-left")?;
+    let lower_right = required(&matches, "lower-right")?;
+    let upper_left =
+        parse_exact_complex(upper_left).ok_or("error parsing upper left corner point")?;
+    let lower_right =
+        parse_exact_complex(lower_right).ok_or("error parsing lower right corner point")?;
+    check_corners(&upper_left, &lower_right)?;
+    let bits = upper_left.re.bits().max(lower_right.re.bits());
+    let bits = bits.max(upper_left.im.bits()).max(lower_right.im.bits());
+    let (upper_left, lower_right) = (rescale(&upper_left, bits), rescale(&lower_right, bits));
+    let height = (&upper_left.im - &lower_right.im).to_f64().abs();
+    if height == 0.0 {
+        return Err("the corners must differ in height".to_string());
+    }
+    // Enough digits to place every pixel of a view 1000 high to within a
+    // thousandth.
+    let digits = (-(height / 1e6).log10()).ceil().max(0.0) as usize;
+    let center_re = (&upper_left.re + &lower_right.re).scale(1, 2);
+    let center_im = (&upper_left.im + &lower_right.im).scale(1, 2);
+    let bookmark = Bookmark {
+        name: name.clone(),
+        center: format!(
+            "{},{}",
+            center_re.to_decimal(digits),
+            center_im.to_decimal(digits)
+        ),
+        zoom: format!("{:e}", 4.0 / height),
+        max_iter: matches.get("max-iter").cloned(),
+    };
+    bookmarks::check(&bookmark)?;
+    Ok(Command::Bookmark(BookmarkCommand::Add(bookmark)))
+}
+
+/// The flags that give the view in place of the corners.
+const LOCATION_FLAGS: &[&str] = &["location", "import-kfr", "import-par"];
+
+/// Replaces `--location NAME` with the corners of that bookmark's view at
+/// the requested size, and its iterations unless `--max-iter` is given;
+/// and likewise `--import-kfr FILE` with the view of that `.kfr` file, and
+/// `--import-par FILE NAME` with that of the `.par` entry, along with its
+/// fractal unless the options give one.
+fn apply_location(matches: &mut HashMap<&'static str, String>) -> Result<(), String> {
+    let given = LOCATION_FLAGS
+        .iter()
+        .filter(|flag| matches.contains_key(*flag))
+        .collect::<Vec<_>>();
+    let flag = match given[..] {
+        [] => return Ok(()),
+        [flag] => *flag,
+        [first, second, ..] => {
+            return Err(format!("--{} can't be combined with --{}", first, second))
+        }
+    };
+    let value = &matches[flag];
+    let read =
+        |path: &str| std::fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e));
+    let (view, max_iter) = match flag {
+        "location" => {
+            let bookmark = find_bookmark(value)?;
+            (bookmark_view(&bookmark)?, bookmark.max_iter)
+        }
+        "import-kfr" => {
+            let location = kfr::parse(&read(value)?).map_err(|e| format!("{}: {}", value, e))?;
+            let max_iter = location.max_iter.map(|max_iter| max_iter.to_string());
+            (location.view, max_iter)
+        }
+        _ => {
+            let (path, name) = value
+                .rsplit_once(' ')
+                .ok_or("--import-par needs a file and the name of an entry in it")?;
+            let text = read(path)?;
+            let entry = par::parse(&text, name).map_err(|e| match e.starts_with("no entry") {
+                true => format!("{}: {}; it has {}", path, e, par::names(&text).join(", ")),
+                false => format!("{}: {}: {}", path, name, e),
+            })?;
+            if let Some(julia) = entry.julia {
+                matches
+                    .entry("julia")
+                    .or_insert(format!("{},{}", julia.re, julia.im));
+            }
+            if let Some(exponent) = entry.exponent {
+                matches.entry("exponent").or_insert(exponent.to_string());
+            }
+            (
+                entry.view,
+                entry.max_iter.map(|max_iter| max_iter.to_string()),
+            )
+        }
+    };
+    if matches.contains_key("upper-left") || matches.contains_key("lower-right") {
+        return Err(format!("--{} can't be combined with the corners", flag));
+    }
+    insert_corners(matches, &view)?;
+    if let Some(max_iter) = max_iter {
+        matches.entry("max-iter").or_insert(max_iter);
+    }
+    Ok(())
+}
+
+/// Looks a bookmark up among the saved and built-in ones.
+fn find_bookmark(name: &str) -> Result<Bookmark, String> {
+    let saved = match bookmarks::store_path() {
+        Some(path) => bookmarks::load(&path).map_err(|e| e.to_string())?,
+        None => Vec::new(),
+    };
+    bookmarks::all(saved)
+        .into_iter()
+        .find(|b| b.name == name)
+        .ok_or_else(|| format!("no bookmark named '{}'; see 'bookmark list'", name))
+}
+
+fn bookmark_view(bookmark: &Bookmark) -> Result<View, String> {
+    Ok(View {
+        center: parse_exact_complex(&bookmark.center).ok_or("invalid bookmark center")?,
+        zoom: bookmark.zoom.parse().map_err(|_| "invalid bookmark zoom")?,
+        flipped: false,
+    })
+}
+
+/// Sets the corners to those of `view` at the requested size, with enough
+/// digits to place every pixel to within a thousandth. Those of a flipped
+/// view are set the right way up, with `--flip`.
+fn insert_corners(matches: &mut HashMap<&'static str, String>, view: &View) -> Result<(), String> {
+    let bounds = parse_size(matches)?;
+    let pixel_size = 4.0 / view.zoom / bounds.1.max(1) as f64;
+    let digits = (-(pixel_size / 1000.0).log10()).ceil().max(0.0) as usize;
+    let pair =
+        |z: Complex<Fixed>| format!("{},{}", z.re.to_decimal(digits), z.im.to_decimal(digits));
+    let upright = View {
+        flipped: false,
+        ..view.clone()
+    };
+    let (upper_left, lower_right) = upright.corners(bounds);
+    matches.insert("upper-left", pair(upper_left));
+    matches.insert("lower-right", pair(lower_right));
+    if view.flipped {
+        matches.insert("flip", String::new());
+    }
+    Ok(())
+}
+
+/// Parses `animate DIR --size WxH --frames N --from VIEW [--to VIEW]
+/// [OPTIONS]`: a zoom from one view to another, written as numbered frames
+/// in DIR, or as a video with `--video FILE` in place of DIR. A view is a
+/// bookmark name or an `RE,IM` center, whose zoom is given by `--from-zoom`
+/// or `--to-zoom`.
+fn parse_animate(args: &[String]) -> Result<Command, String> {
+    let (dir, flags) = match args.first() {
+        Some(dir) if !dir.starts_with('-') => (Some(dir.clone()), &args[1..]),
+        _ => (None, args),
+    };
+    let mut matches = match_flags(flags)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, Some("animate"))?;
+    let video = matches.get("video").cloned();
+    match (&dir, &video) {
+        (Some(_), Some(_)) => {
+            return Err("animate writes either a directory of frames or a --video".to_string())
+        }
+        (None, None) => {
+            return Err("animate needs a directory to write the frames to, or --video".to_string())
+        }
+        _ => {}
+    }
+    for flag in [
+        "output",
+        "upper-left",
+        "lower-right",
+        "location",
+        "import-kfr",
+        "import-par",
+        "dump-iters",
+        "dump-npy",
+        "preview-term",
+        "checkpoint",
+        "resume",
+        "stats",
+        "transparent-interior",
+        "hdr",
+        "progressive",
+        "contours",
+        "base-thickness",
+        "height-scale",
+        "heightmap",
+        "normal-map",
+    ] {
+        if matches.contains_key(flag) {
+            return Err(format!("'--{}' can't be used with animate", flag));
+        }
+    }
+    let frames = parse_number::<u32>(&matches, "frames", 0)?;
+    if frames == 0 {
+        return Err("animate needs --frames 1 or more".to_string());
+    }
+    let julia = match matches.get("julia-path") {
+        Some(path) => {
+            if let Some(flag) = ["julia", "to", "to-zoom"]
+                .iter()
+                .find(|f| matches.contains_key(*f))
+            {
+                return Err(format!("'--{}' can't be used with --julia-path", flag));
+            }
+            let constants = parse_julia_path(path)?.constants(frames);
+            matches.insert("julia", format!("{},{}", constants[0].re, constants[0].im));
+            constants
+        }
+        None => Vec::new(),
+    };
+    // Julia sets fit in a view 4 high about the origin.
+    let from = match matches.get("from") {
+        None if !julia.is_empty() => "0,0".to_string(),
+        _ => required(&matches, "from")?.to_string(),
+    };
+    let (mut start, start_iter) = parse_view(&from, matches.get("from-zoom"), "--from-zoom")?;
+    start.flipped = matches.contains_key("flip");
+    let to = matches.get("to").cloned();
+    let (end, end_iter) = match to {
+        Some(to) => parse_view(&to, matches.get("to-zoom"), "--to-zoom")?,
+        None => {
+            let zoom = match matches.get("to-zoom") {
+                Some(zoom) => parse_zoom(zoom, "--to-zoom")?,
+                None => start.zoom,
+            };
+            let end = View {
+                zoom,
+                ..start.clone()
+            };
+            (end, None)
+        }
+    };
+    if let Some(max_iter) = end_iter.or(start_iter) {
+        matches.entry("max-iter").or_insert(max_iter);
+    }
+    insert_corners(&mut matches, &start)?;
+    let output = match (dir, video) {
+        (Some(dir), _) => {
+            if matches.contains_key("fps") {
+                return Err("--fps needs --video".to_string());
+            }
+            let format = match matches.get("format") {
+                Some(name) => Format::named(name),
+                None => Some(Format::Png),
+            };
+            let extension = format.map_or("png", Format::extension);
+            let width = frames.to_string().len().max(4);
+            let paths = (1..=frames)
+                .map(|frame| {
+                    let name = format!("frame_{:0width$}.{}", frame, extension, width = width);
+                    Path::new(&dir).join(name).to_string_lossy().into_owned()
+                })
+                .collect::<Vec<_>>();
+            FrameOutput::Files { dir, paths }
+        }
+        (None, Some(path)) => {
+            for flag in ["format", "depth", "quality", "plain"] {
+                if matches.contains_key(flag) {
+                    return Err(format!("'--{}' can't be used with --video", flag));
+                }
+            }
+            let fps = parse_number(&matches, "fps", 30)?;
+            if fps == 0 {
+                return Err("--fps must be at least 1".to_string());
+            }
+            if path.to_ascii_lowercase().ends_with(".gif") {
+                let loops = parse_number(&matches, "loop", 0)?;
+                let colors = parse_number(&matches, "colors", gif::MAX_COLORS)?;
+                if !(2..=gif::MAX_COLORS).contains(&colors) {
+                    return Err("--colors must be between 2 and 256".to_string());
+                }
+                FrameOutput::Gif {
+                    path,
+                    fps,
+                    loops,
+                    colors,
+                }
+            } else {
+                if let Some(flag) = ["loop", "colors"].iter().find(|f| matches.contains_key(*f)) {
+                    return Err(format!("'--{}' needs --video with a .gif file", flag));
+                }
+                FrameOutput::Video { path, fps }
+            }
+        }
+        (None, None) => unreachable!("checked above"),
+    };
+    let first = match &output {
+        FrameOutput::Files { paths, .. } => paths[0].clone(),
+        // Frames are piped rather than written, but the file is still
+        // named so that the render options are complete.
+        FrameOutput::Video { path, .. } | FrameOutput::Gif { path, .. } => path.clone(),
+    };
+    if let FrameOutput::Video { .. } | FrameOutput::Gif { .. } = output {
+        matches.insert("format", "png".to_string());
+    }
+    matches.insert("output", first);
+    let frame = match parse_matches(matches)? {
+        Command::Render(cli) => *cli,
+        _ => unreachable!("parse_matches only builds renders"),
+    };
+    Ok(Command::Animate(Box::new(Animation {
+        frame,
+        views: animation::zoom_path(&start, &end, frames),
+        julia,
+        output,
+    })))
+}
+
+/// Parses `--julia-path`: `cardioid`, or waypoints separated by `/`.
+fn parse_julia_path(value: &str) -> Result<JuliaPath, String> {
+    if value == "cardioid" {
+        return Ok(JuliaPath::Cardioid);
+    }
+    value
+        .split('/')
+        .map(parse_complex)
+        .collect::<Option<Vec<_>>>()
+        .map(JuliaPath::Waypoints)
+        .ok_or_else(|| format!("invalid value '{}' for '--julia-path'", value))
+}
+
+/// Parses `serve [--port PORT] [OPTIONS]`. Tiles are PNG images of
+/// `server::TILE_SIZE` pixels, so the options only choose how they are
+/// rendered.
+fn parse_serve(args: &[String]) -> Result<Command, String> {
+    let mut matches = match_flags(args)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, None)?;
+    for flag in [
+        "output",
+        "size",
+        "upper-left",
+        "lower-right",
+        "flip",
+        "location",
+        "import-kfr",
+        "import-par",
+        "format",
+        "depth",
+        "quality",
+        "plain",
+        "dump-iters",
+        "dump-npy",
+        "preview-term",
+        "transparent-interior",
+        "hdr",
+        "progressive",
+        "contours",
+        "base-thickness",
+        "height-scale",
+        "heightmap",
+        "normal-map",
+    ] {
+        if matches.contains_key(flag) {
+            return Err(format!("'--{}' can't be used with serve", flag));
+        }
+    }
+    let port = parse_number(&matches, "port", 8080)?;
+    matches.remove("port");
+    let size = server::TILE_SIZE;
+    matches.insert("size", format!("{}x{}", size, size));
+    // The whole set, as tile 0/0/0; each tile's own view replaces it.
+    matches.insert("upper-left", "-2.5,2".to_string());
+    matches.insert("lower-right", "1.5,-2".to_string());
+    matches.insert("output", "-".to_string());
+    let frame = match parse_matches(matches)? {
+        Command::Render(cli) => *cli,
+        _ => unreachable!("parse_matches only builds renders"),
+    };
+    Ok(Command::Serve(Box::new(Server { frame, port })))
+}
+
+/// Parses `repl [--size WxH] [OPTIONS]` or `explore [--size WxH]
+/// [OPTIONS]`, as `command`: a session that starts at the view the options
+/// give, or the whole set, 800x600 unless they say otherwise.
+fn parse_session(
+    args: &[String],
+    command: &str,
+    wrap: fn(Box<Session>) -> Command,
+) -> Result<Command, String> {
+    let mut matches = match_flags(args)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, None)?;
+    // Corners and bookmarks only say where the session starts.
+    let start_flags = [
+        "upper-left",
+        "lower-right",
+        "flip",
+        "location",
+        "import-kfr",
+        "import-par",
+    ];
+    if let Some(flag) = SESSION_EXCLUDED_FLAGS
+        .iter()
+        .filter(|flag| !start_flags.contains(flag))
+        .find(|flag| matches.contains_key(*flag))
+    {
+        return Err(format!("'--{}' can't be used with {}", flag, command));
+    }
+    matches
+        .entry("size")
+        .or_insert_with(|| "800x600".to_string());
+    apply_location(&mut matches)?;
+    let mut start = matches.clone();
+    start.insert("output", "-".to_string());
+    let options = match parse_matches(start)? {
+        Command::Render(cli) => cli.options,
+        _ => unreachable!("parse_matches only builds renders"),
+    };
+    for flag in start_flags {
+        matches.remove(flag);
+    }
+    let (upper_left, lower_right) = options.exact_corners();
+    let height = (&upper_left.im - &lower_right.im).to_f64();
+    let view = View {
+        center: Complex {
+            re: (&upper_left.re + &lower_right.re).scale(1, 2),
+            im: (&upper_left.im + &lower_right.im).scale(1, 2),
+        },
+        zoom: 4.0 / height.abs(),
+        flipped: height < 0.0,
+    };
+    Ok(wrap(Box::new(Session {
+        view,
+        flags: matches,
+    })))
+}
+
+/// Parses `worker --listen ADDR [OPTIONS]`. Jobs come with their own size,
+/// view and iteration limit, and are sent back as escape times, so the
+/// options only choose how they are rendered.
+fn parse_worker(args: &[String]) -> Result<Command, String> {
+    let mut matches = match_flags(args)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, Some("worker"))?;
+    for flag in [
+        "output",
+        "size",
+        "upper-left",
+        "lower-right",
+        "flip",
+        "location",
+        "import-kfr",
+        "import-par",
+        "max-iter",
+        "format",
+        "depth",
+        "quality",
+        "plain",
+        "dump-iters",
+        "dump-npy",
+        "tile",
+        "checkpoint",
+        "resume",
+        "stats",
+        "preview-term",
+        "transparent-interior",
+        "hdr",
+        "progressive",
+        "contours",
+        "base-thickness",
+        "height-scale",
+        "heightmap",
+        "normal-map",
+    ] {
+        if matches.contains_key(flag) {
+            return Err(format!("'--{}' can't be used with worker", flag));
+        }
+    }
+    let listen = matches
+        .remove("listen")
+        .ok_or("worker needs an address to --listen on")?;
+    // Stand-ins for the view each job brings along.
+    matches.insert("size", "1x1".to_string());
+    matches.insert("output", "-".to_string());
+    let frame = match parse_matches(matches)? {
+        Command::Render(cli) => *cli,
+        _ => unreachable!("parse_matches only builds renders"),
+    };
+    Ok(Command::Worker(Box::new(Worker { frame, listen })))
+}
+
+/// Parses `coordinate --workers HOSTS [OPTIONS]`, a render like any other
+/// but for the workers that share it. They have to be started with the
+/// same options, bar the view and iterations.
+fn parse_coordinate(args: &[String]) -> Result<Command, String> {
+    let mut matches = match_flags(args)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, Some("coordinate"))?;
+    apply_sidecar(&mut matches, Some("coordinate"))?;
+    apply_location(&mut matches)?;
+    if matches
+        .get("workers")
+        .is_none_or(|workers| workers.split(',').all(str::is_empty))
+    {
+        return Err("coordinate needs the --workers to share the render with".to_string());
+    }
+    parse_matches(matches)
+}
+
+/// Parses `batch FILE [--jobs N] [OPTIONS]`: a render for each `[name]`
+/// table of FILE, whose keys are options like those of a config file.
+/// Options given on the command line apply to every job, over the file's.
+/// Every job is checked before any is rendered.
+fn parse_batch(args: &[String]) -> Result<Command, String> {
+    let matches = match_flags(args.get(1..).unwrap_or_default())?;
+    let path = match args.first() {
+        _ if matches.contains_key("help") => return Ok(Command::Help),
+        Some(path) if !path.starts_with('-') => path,
+        Some(path) if path == "-h" || path == "--help" => return Ok(Command::Help),
+        _ => return Err("batch needs a file of jobs".to_string()),
+    };
+    let mut matches = matches;
+    reject_subcommand_flags(&matches, Some("batch"))?;
+    let parallel = parse_number(&matches, "jobs", 1)?;
+    if parallel == 0 {
+        return Err("--jobs must be at least 1".to_string());
+    }
+    matches.remove("jobs");
+    let text = std::fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e))?;
+    let tables = config::parse_tables(&text).map_err(|e| format!("{}: {}", path, e))?;
+    if tables.is_empty() {
+        return Err(format!("{}: no [jobs] to render", path));
+    }
+    let mut jobs: Vec<(String, Cli)> = Vec::new();
+    for (name, entries) in tables {
+        let source = format!("{} [{}]", path, name);
+        let mut job = matches.clone();
+        apply_entries(&mut job, entries, &source)?;
+        let parsed = apply_config(&mut job)
+            .and_then(|()| reject_subcommand_flags(&job, None))
+            .and_then(|()| apply_sidecar(&mut job, None))
+            .and_then(|()| apply_location(&mut job))
+            .and_then(|()| parse_matches(job));
+        let mut cli = match parsed.map_err(|e| format!("{}: {}", source, e))? {
+            Command::Render(cli) => *cli,
+            _ => unreachable!("parse_matches only builds renders"),
+        };
+        if let Some((other, _)) = jobs
+            .iter()
+            .find(|(_, other)| other.output == cli.output && cli.output != "-")
+        {
+            return Err(format!(
+                "{}: [{}] also writes to {}",
+                source, other, cli.output
+            ));
+        }
+        // Progress bars side by side would garble each other.
+        cli.quiet |= parallel > 1;
+        jobs.push((name, cli));
+    }
+    Ok(Command::Batch(Box::new(Batch { jobs, parallel })))
+}
+
+/// Parses `bench [OPTIONS]`. The benchmark has its own views and times
+/// just the escape times, so the options only choose how they are computed.
+fn parse_bench(args: &[String]) -> Result<Command, String> {
+    let mut matches = match_flags(args)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, None)?;
+    for flag in [
+        "output",
+        "size",
+        "upper-left",
+        "lower-right",
+        "flip",
+        "location",
+        "import-kfr",
+        "import-par",
+        "max-iter",
+        "format",
+        "depth",
+        "quality",
+        "plain",
+        "aa",
+        "adaptive",
+        "dump-iters",
+        "dump-npy",
+        "tile",
+        "checkpoint",
+        "resume",
+        "stats",
+        "preview-term",
+        "transparent-interior",
+        "hdr",
+        "progressive",
+        "contours",
+        "base-thickness",
+        "height-scale",
+        "heightmap",
+        "normal-map",
+    ] {
+        if matches.contains_key(flag) {
+            return Err(format!("'--{}' can't be used with bench", flag));
+        }
+    }
+    // Stand-ins for the benchmark's own views.
+    matches.insert("size", "1x1".to_string());
+    matches.insert("output", "-".to_string());
+    let frame = match parse_matches(matches)? {
+        Command::Render(cli) => *cli,
+        _ => unreachable!("parse_matches only builds renders"),
+    };
+    Ok(Command::Bench(Box::new(Bench { frame })))
+}
+
+/// Parses `pyramid FILE.dzi PIXELS [UPPERLEFT LOWERRIGHT] [OPTIONS]`: a
+/// render of PIXELS cut into tiles at every level of detail, written as
+/// png, or jpeg with `--format`, next to the descriptor FILE.dzi.
+fn parse_pyramid(args: &[String]) -> Result<Command, String> {
+    let mut matches = match_flags(args)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, Some("pyramid"))?;
+    apply_sidecar(&mut matches, Some("pyramid"))?;
+    apply_location(&mut matches)?;
+    for flag in [
+        "depth",
+        "plain",
+        "dump-iters",
+        "dump-npy",
+        "tile",
+        "checkpoint",
+        "resume",
+        "stats",
+        "preview-term",
+        "transparent-interior",
+        "hdr",
+        "progressive",
+        "contours",
+        "base-thickness",
+        "height-scale",
+        "heightmap",
+        "normal-map",
+    ] {
+        if matches.contains_key(flag) {
+            return Err(format!("'--{}' can't be used with pyramid", flag));
+        }
+    }
+    let tile_size = parse_number(&matches, "tile-size", pyramid::DEFAULT_TILE_SIZE)?;
+    if tile_size == 0 {
+        return Err("--tile-size must be at least 1".to_string());
+    }
+    let overlap = parse_number(&matches, "overlap", pyramid::DEFAULT_OVERLAP)?;
+    for flag in PYRAMID_FLAGS {
+        matches.remove(flag);
+    }
+    // The tiles are png unless asked for otherwise, whatever the
+    // descriptor's extension.
+    matches.entry("format").or_insert("png".to_string());
+    let frame = match parse_matches(matches)? {
+        Command::Render(cli) => *cli,
+        _ => unreachable!("parse_matches only builds renders"),
+    };
+    if !frame.output.ends_with(".dzi") {
+        return Err(format!(
+            "pyramid writes a .dzi descriptor, not '{}'",
+            frame.output
+        ));
+    }
+    if !matches!(frame.format, Format::Png | Format::Jpeg) {
+        return Err("pyramid tiles can only be png or jpeg".to_string());
+    }
+    if frame.options.coloring == Coloring::Histogram {
+        return Err("pyramid can't use --coloring histogram".to_string());
+    }
+    let pyramid = Pyramid {
+        bounds: frame.options.bounds,
+        tile_size,
+        overlap,
+    };
+    Ok(Command::Pyramid(Box::new(PyramidRender { frame, pyramid })))
+}
+
+/// Parses `buddhabrot FILE PIXELS [UPPERLEFT LOWERRIGHT] [OPTIONS]`, with
+/// the whole set as the default view like a render.
+fn parse_buddhabrot(args: &[String]) -> Result<Command, String> {
+    let mut matches = match_flags(args)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, Some("buddhabrot"))?;
+    apply_sidecar(&mut matches, Some("buddhabrot"))?;
+    reject_escape_time_flags(&matches, "buddhabrot")?;
+    apply_location(&mut matches)?;
+    parse_buddhabrot_matches(matches)
+}
+
+/// Builds the buddhabrot command from matched flags.
+fn parse_buddhabrot_matches(mut matches: HashMap<&'static str, String>) -> Result<Command, String> {
+    let samples = if matches.contains_key("samples") {
+        Some(parse_number::<u64>(&matches, "samples", 0)?)
+    } else {
+        None
+    };
+    let min_iter = parse_number(&matches, "min-iter", 0)?;
+    let seed = parse_number(&matches, "seed", 0)?;
+    let nebula = match matches.get("nebula") {
+        Some(limits) => {
+            for flag in [
+                "max-iter",
+                "palette",
+                "gradient",
+                "palette-from-image",
+                "color-space",
+            ] {
+                if matches.contains_key(flag) {
+                    return Err(format!("'--{}' can't be used with --nebula", flag));
+                }
+            }
+            let limits = parse_triple::<u32>(limits)
+                .filter(|limits| limits.iter().all(|&limit| limit > min_iter))
+                .ok_or_else(|| {
+                    format!(
+                        "invalid value '{}' for '--nebula', expected three limits above \
+                         --min-iter",
+                        limits
+                    )
+                })?;
+            // A single exposure is in stops, for the finished image.
+            let exposure = match matches.get("exposure").filter(|e| e.contains(',')) {
+                Some(exposure) => {
+                    let factors = parse_triple::<f64>(exposure)
+                        .filter(|exposure| exposure.iter().all(|e| e.is_finite() && *e >= 0.0))
+                        .ok_or_else(|| format!("invalid value '{}' for '--exposure'", exposure))?;
+                    matches.remove("exposure");
+                    factors
+                }
+                None => [1.0; 3],
+            };
+            let max_iter = limits.iter().max().unwrap().to_string();
+            matches.insert("max-iter", max_iter);
+            Some(Nebula { limits, exposure })
+        }
+        None if matches.get("exposure").is_some_and(|e| e.contains(',')) => {
+            return Err("--exposure R,G,B needs --nebula".to_string())
+        }
+        None => None,
+    };
+    for flag in BUDDHABROT_FLAGS {
+        matches.remove(flag);
+    }
+    let frame = match parse_matches(matches)? {
+        Command::Render(cli) => *cli,
+        _ => unreachable!("parse_matches only builds renders"),
+    };
+    if frame.format == Format::Exr {
+        return Err("buddhabrot can't write exr".to_string());
+    }
+    if matches!(frame.format, Format::Svg | Format::Stl | Format::Obj) {
+        return Err(format!(
+            "buddhabrot can't write {}",
+            frame.format.extension()
+        ));
+    }
+    let options = &frame.options;
+    if min_iter >= options.max_iter {
+        return Err("--min-iter must be below --max-iter".to_string());
+    }
+    let pixels = options.bounds.0 as u64 * options.bounds.1 as u64;
+    let samples = samples.unwrap_or(20 * pixels);
+    let buddhabrot = Buddhabrot {
+        bounds: options.bounds,
+        upper_left: options.upper_left,
+        lower_right: options.lower_right,
+        samples,
+        min_iter,
+        max_iter: options.max_iter,
+        seed,
+        threads: options.threads,
+    };
+    Ok(Command::Buddhabrot(Box::new(BuddhabrotRender {
+        frame,
+        buddhabrot,
+        nebula,
+    })))
+}
+
+/// Parses `lyapunov FILE PIXELS [UPPERLEFT LOWERRIGHT] [OPTIONS]`, where
+/// the real axis holds the rate `a` and the imaginary axis `b`, from 2 to 4
+/// by default. `--max-iter` is the number of steps the exponent is
+/// averaged over.
+fn parse_lyapunov(args: &[String]) -> Result<Command, String> {
+    let mut matches = match_flags(args)?;
+    if matches.contains_key("help") {
+        return Ok(Command::Help);
+    }
+    apply_config(&mut matches)?;
+    reject_subcommand_flags(&matches, Some("lyapunov"))?;
+    reject_escape_time_flags(&matches, "lyapunov")?;
+    for flag in LOCATION_FLAGS {
+        if matches.contains_key(flag) {
+            return Err(format!("'--{}' can't be used with lyapunov", flag));
+        }
+    }
+    let sequence = match matches.remove("sequence") {
+        Some(text) => lyapunov::parse_sequence(&text)
+            .ok_or_else(|| format!("invalid value '{}' for '--sequence'", text))?,
+        None => lyapunov::parse_sequence("AB").unwrap(),
+    };
+    if !matches.contains_key("upper-left") && !matches.contains_key("lower-right") {
+        let bounds = parse_size(&matches)?;
+        let (upper_left, lower_right) = lyapunov::view(bounds);
+        matches.insert("upper-left", format!("{},{}", upper_left.re, upper_left.im));
+        matches.insert(
+            "lower-right",
+            format!("{},{}", lower_right.re, lower_right.im),
+        );
+    }
+    let mut frame = match parse_matches(matches)? {
+        Command::Render(cli) => *cli,
+        _ => unreachable!("parse_matches only builds renders"),
+    };
+    if frame.format == Format::Exr {
+        return Err("lyapunov can't write exr".to_string());
+    }
+    if matches!(frame.format, Format::Svg | Format::Stl | Format::Obj) {
+        return Err(format!("lyapunov can't write {}", frame.format.extension()));
+    }
+    frame.sidecar = None;
+    let options = &frame.options;
+    let lyapunov = Lyapunov {
+        bounds: options.bounds,
+        upper_left: options.upper_left,
+        lower_right: options.lower_right,
+        sequence,
+        iterations: options.max_iter,
+        threads: options.threads,
+    };
+    Ok(Command::Lyapunov(Box::new(LyapunovRender {
+        frame,
+        lyapunov,
+    })))
+}
+
+/// A view given as a bookmark name or an `RE,IM` center, along with the
+/// bookmark's iterations. The zoom, if given, replaces the bookmark's; a
+/// center on its own is at zoom 1.
+fn parse_view(
+    value: &str,
+    zoom: Option<&String>,
+    zoom_flag: &str,
+) -> Result<(View, Option<String>), String> {
+    let (mut view, max_iter) = match parse_exact_complex(value) {
+        Some(center) => (
+            View {
+                center,
+                zoom: 1.0,
+                flipped: false,
+            },
+            None,
+        ),
+        None => {
+            let bookmark = find_bookmark(value)?;
+            (bookmark_view(&bookmark)?, bookmark.max_iter)
+        }
+    };
+    if let Some(zoom) = zoom {
+        view.zoom = parse_zoom(zoom, zoom_flag)?;
+    }
+    Ok((view, max_iter))
+}
+
+fn parse_zoom(value: &str, flag: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(zoom) if zoom > 0.0 && zoom.is_finite() => Ok(zoom),
+        _ => Err(format!("invalid value '{}' for '{}'", value, flag)),
+    }
+}
+
+/// Checks that `upper_left` is above and left of `lower_right`, as corners
+/// swapped by mistake would otherwise come out mirrored, upside down or
+/// blank; `--flip` is how to ask for a view upside down.
+fn check_corners(upper_left: &Complex<Fixed>, lower_right: &Complex<Fixed>) -> Result<(), String> {
+    let pair = |z: &Complex<Fixed>| format!("{},{}", z.re.to_f64(), z.im.to_f64());
+    let axis = match (
+        upper_left.re == lower_right.re,
+        upper_left.im == lower_right.im,
+    ) {
+        (true, true) => Some("everywhere"),
+        (true, false) => Some("in width"),
+        (false, true) => Some("in height"),
+        (false, false) => None,
+    };
+    if let Some(axis) = axis {
+        return Err(format!(
+            "the corners {} and {} coincide {}, leaving nothing to draw",
+            pair(upper_left),
+            pair(lower_right),
+            axis
+        ));
+    }
+    let (left, above) = (
+        upper_left.re < lower_right.re,
+        upper_left.im > lower_right.im,
+    );
+    let (position, hint) = match (left, above) {
+        (true, true) => return Ok(()),
+        (false, false) => ("below and right of", "; are they swapped?"),
+        (false, true) => ("right of", ""),
+        (true, false) => ("below", "; give --flip for a view upside down"),
+    };
+    Err(format!(
+        "the upper left corner {} is {} the lower right corner {}{}",
+        pair(upper_left),
+        position,
+        pair(lower_right),
+        hint
+    ))
+}
+
+/// The corners of the view between `corners` widened or heightened about its
+/// center to the shape of an image of `bounds`, so that its pixels are
+/// square, or `None` if they already are.
+fn fit_aspect(
+    corners: &(Complex<Fixed>, Complex<Fixed>),
+    bounds: (u32, u32),
+) -> Option<(Complex<Fixed>, Complex<Fixed>)> {
+    let bits = [&corners.0.re, &corners.0.im, &corners.1.re, &corners.1.im]
+        .iter()
+        .map(|x| x.bits())
+        .max()
+        .unwrap();
+    let (mut upper_left, mut lower_right) = (rescale(&corners.0, bits), rescale(&corners.1, bits));
+    let width = &lower_right.re - &upper_left.re;
+    let height = &upper_left.im - &lower_right.im;
+    let (width_f64, height_f64) = (width.to_f64(), height.to_f64());
+    let aspect = bounds.0.max(1) as f64 / bounds.1.max(1) as f64;
+    let stretch = width_f64 / height_f64 / aspect;
+    // Corners given to a handful of digits rarely fit exactly, and a
+    // stretch of less than a hundredth of a pixel across the image isn't
+    // worth moving them for.
+    if (stretch - 1.0).abs() * (bounds.0.max(bounds.1) as f64) < 0.01 {
+        return None;
+    }
+    if stretch < 1.0 {
+        let wider = height.scale(bounds.0, bounds.1);
+        let grow = (&wider - &width).scale(1, 2);
+        upper_left.re = &upper_left.re - &grow;
+        lower_right.re = &lower_right.re + &grow;
+    } else {
+        let taller = width.scale(bounds.1, bounds.0);
+        let grow = (&taller - &height).scale(1, 2);
+        upper_left.im = &upper_left.im + &grow;
+        lower_right.im = &lower_right.im - &grow;
+    }
+    Some((upper_left, lower_right))
+}
+
+fn rescale(z: &Complex<Fixed>, bits: u32) -> Complex<Fixed> {
+    Complex {
+        re: z.re.with_bits(bits),
+        im: z.im.with_bits(bits),
+    }
+}
+
+/// Fills in the flags set in the `--config` file, if any, that weren't given
+/// on the command line. Keys are long flag names; flags without a value are
+/// set with `true`.
+fn apply_config(matches: &mut HashMap<&'static str, String>) -> Result<(), String> {
+    let path = match matches.get("config") {
+        Some(path) => path.clone(),
+        None => return Ok(()),
+    };
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("reading {}: {}", path, e))?;
+    let entries = config::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+    apply_entries(matches, entries, &path)
+}
+
+/// Fills in the flags not already given from the keys and values of a
+/// config file, or of a table of one; `source` names it in errors.
+fn apply_entries(
+    matches: &mut HashMap<&'static str, String>,
+    entries: Vec<(String, Value)>,
+    source: &str,
+) -> Result<(), String> {
+    for (key, value) in entries {
+        let flag = FLAGS
+            .iter()
+            .find(|f| f.long == key || f.aliases.contains(&key.as_str()))
+            .filter(|f| !matches!(f.long, "config" | "help"))
+            .ok_or_else(|| format!("{}: unknown option '{}'", source, key))?;
+        let value = match (flag.value, value) {
+            (Some(_), Value::String(value) | Value::Number(value)) => value,
+            (None, Value::Boolean(true)) => String::new(),
+            (None, Value::Boolean(false)) => continue,
+            (Some(_), Value::Boolean(_)) => {
+                return Err(format!(
+                    "{}: '{}' needs a value, not a boolean",
+                    source, key
+                ))
+            }
+            (None, _) => return Err(format!("{}: '{}' must be true or false", source, key)),
+        };
+        matches.entry(flag.long).or_insert(value);
+    }
+    Ok(())
+}
+
+/// Parses `rerender INPUT.png [OPTIONS]`: the render parameters stored in
+/// the image, overridden by any options given, rendered as a buddhabrot if
+/// it was one. The output defaults to the input's name with the new size
+/// appended.
+fn parse_rerender(args: &[String]) -> Result<Command, String> {
+    let matches = match_flags(args.get(1..).unwrap_or_default())?;
+    let input = match args.first() {
+        _ if matches.contains_key("help") => return Ok(Command::Help),
+        Some(input) if !input.starts_with('-') => input,
+        Some(input) if input == "-h" || input == "--help" => return Ok(Command::Help),
+        _ => return Err("rerender needs the image to render again".to_string()),
+    };
+    let mut matches = matches;
+    apply_config(&mut matches)?;
+    let text = File::open(input)
+        .map_err(|e| e.to_string())
+        .and_then(|file| metadata::read(BufReader::new(file)).map_err(|e| e.to_string()))
+        .map_err(|e| format!("reading {}: {}", input, e))?;
+    let stored = |keyword: &str| {
+        text.iter()
+            .find(|(k, _)| k == keyword)
+            .map(|(_, value)| value.clone())
+    };
+    if stored("Upper left").is_none() || stored("Lower right").is_none() {
+        return Err(format!("{} has no render parameters to reuse", input));
+    }
+    let command = stored("Samples").map(|_| "buddhabrot");
+    reject_subcommand_flags(&matches, command)?;
+    if command.is_some() {
+        reject_escape_time_flags(&matches, "buddhabrot")?;
+    }
+    apply_stored(&mut matches, &text).map_err(|e| format!("{}: {}", input, e))?;
+    if !matches.contains_key("output") {
+        let path = Path::new(input);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let size = required(&matches, "size")?;
+        let name = format!("{}-{}.png", stem, size);
+        let output = path.with_file_name(name).to_string_lossy().into_owned();
+        matches.insert("output", output);
+    }
+    if Path::new(&matches["output"]) == Path::new(input) {
+        return Err(format!("rerender would overwrite {}", input));
+    }
+    match command {
+        Some(_) => parse_buddhabrot_matches(matches),
+        None => parse_matches(matches),
+    }
+}
+
+/// Fills in the flags not already given from the render parameters `text`
+/// of an image or sidecar, by their `METADATA_FLAGS` and
+/// `METADATA_SWITCHES` keywords. A stored `Gradient` is kept as the hidden
+/// `stored-gradient` for `parse_colors`. Any other keyword is an error,
+/// rather than a render that quietly differs from the one stored.
+fn apply_stored(
+    matches: &mut HashMap<&'static str, String>,
+    text: &[(String, String)],
+) -> Result<(), String> {
+    // A palette given replaces the stored one, however either is given.
+    let palette_given = ["palette", "gradient", "palette-from-image"]
+        .iter()
+        .any(|flag| matches.contains_key(flag));
+    for (keyword, value) in text {
+        let keyword = keyword.as_str();
+        if METADATA_NOTES.contains(&keyword) {
+            continue;
+        }
+        if keyword == "Palette" || keyword == "Gradient" {
+            if !palette_given {
+                let flag = match keyword {
+                    "Palette" => "palette",
+                    _ => "stored-gradient",
+                };
+                matches.insert(flag, value.clone());
+            }
+            continue;
+        }
+        if let Some(&(_, flag)) = METADATA_FLAGS.iter().find(|(k, _)| *k == keyword) {
+            matches.entry(flag).or_insert_with(|| value.clone());
+            continue;
+        }
+        let &(_, on, flag) = METADATA_SWITCHES
+            .iter()
+            .find(|(k, _, _)| *k == keyword)
+            .ok_or_else(|| format!("'{}' can't be restored", keyword))?;
+        match value.as_str() {
+            "true" | "false" if value == on => {
+                matches.entry(flag).or_default();
+            }
+            "true" | "false" => {}
+            _ => return Err(format!("invalid value '{}' for '{}'", value, keyword)),
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `--from-sidecar FILE` with the render parameters in it, for
+/// the flags not already given, like `rerender` does with an image's. The
+/// parameters have to suit `command`, as in `reject_subcommand_flags`.
+fn apply_sidecar(
+    matches: &mut HashMap<&'static str, String>,
+    command: Option<&str>,
+) -> Result<(), String> {
+    let path = match matches.remove("from-sidecar") {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    if let Some(flag) = LOCATION_FLAGS
+        .iter()
+        .find(|flag| matches.contains_key(*flag))
+    {
+        return Err(format!("--from-sidecar can't be combined with --{}", flag));
+    }
+    let json = std::fs::read_to_string(&path).map_err(|e| format!("reading {}: {}", path, e))?;
+    let text = metadata::read_json(&json).map_err(|e| format!("{}: {}", path, e))?;
+    if !text.iter().any(|(keyword, _)| keyword == "Upper left") {
+        return Err(format!("{} has no render parameters to reuse", path));
+    }
+    apply_stored(matches, &text)
+        .and_then(|()| reject_subcommand_flags(matches, command))
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Builds the render command from matched flags.
+fn parse_matches(matches: HashMap<&'static str, String>) -> Result<Command, String> {
+    if matches.contains_key("port") {
+        return Err("'--port' only applies to serve".to_string());
+    }
+    if matches.contains_key("from-sidecar") {
+        return Err("'--from-sidecar' only applies to renders".to_string());
+    }
+    // Previews and sixels go to the terminal, so they need no file.
+    let sixel = matches.get("format").and_then(|name| Format::named(name)) == Some(Format::Sixel);
+    let output = match matches.get("output") {
+        None if sixel || matches.contains_key("preview-term") => "-".to_string(),
+        _ => required(&matches, "output")?.to_string(),
+    };
+    let options = parse_options(&matches)?;
+    let format = match matches.get("format") {
+        Some(name) => Format::named(name).ok_or_else(|| {
+            format!(
+                "unknown format '{}', expected: png, jpeg, ppm, pgm, exr, sixel, tiff, webp, \
+                 svg, stl, obj",
+                name
+            )
+        })?,
+        // Standard output and names without an extension get png.
+        None => match Path::new(&output).extension() {
+            Some(extension) => Format::from_path(&output).ok_or_else(|| {
+                format!(
+                    "no format is written to .{} files; give --format or another extension",
+                    extension.to_string_lossy()
+                )
+            })?,
+            None => Format::Png,
+        },
+    };
+    let quality = match format {
+        Format::Webp => parse_number(&matches, "quality", webp::LOSSLESS_QUALITY)?,
+        _ => parse_number(&matches, "quality", jpeg::DEFAULT_QUALITY)?,
+    };
+    // Both default to a share of the width, so meshes keep their shape
+    // at any size.
+    let relief = |flag: &str, share: f64| match matches.get(flag) {
+        Some(text) => match text.parse::<f64>() {
+            Ok(units) if units > 0.0 && units.is_finite() => Ok(units),
+            _ => Err(format!("--{} must be above 0", flag)),
+        },
+        None => Ok(options.bounds.0 as f64 / share),
+    };
+    let base_thickness = relief("base-thickness", 50.0)?;
+    let height_scale = relief("height-scale", 10.0)?;
+    let hdr = match matches.get("hdr") {
+        Some(text) => match text.parse::<f64>() {
+            Ok(peak) if (hdr::SDR_WHITE..=hdr::MAX_NITS).contains(&peak) => Some(peak),
+            _ => {
+                return Err(format!(
+                    "--hdr must be between {} and {} nits",
+                    hdr::SDR_WHITE,
+                    hdr::MAX_NITS
+                ))
+            }
+        },
+        None => None,
+    };
+    let tile = match matches.get("tile") {
+        Some(_) => Some(parse_number(&matches, "tile", 0)?),
+        None => None,
+    };
+    let resume = matches.get("resume");
+    let checkpoint = match (matches.get("checkpoint"), resume) {
+        (Some(checkpoint), Some(resume)) if checkpoint != resume => {
+            return Err("--checkpoint and --resume take the same file".to_string())
+        }
+        (checkpoint, resume) => checkpoint.or(resume).cloned(),
+    };
+    let workers = matches.get("workers").map_or(Vec::new(), |workers| {
+        workers
+            .split(',')
+            .filter(|worker| !worker.is_empty())
+            .map(str::to_string)
+            .collect()
+    });
+    let log_level = match matches.get("log-level").map(String::as_str) {
+        None | Some("off") => None,
+        Some(name) => Some(Level::named(name).ok_or_else(|| {
+            format!(
+                "unknown log level '{}', expected: off, error, warn, info, debug, trace",
+                name
+            )
+        })?),
+    };
+    // The parameters go next to the image, unless it goes to the terminal.
+    let sidecar = Some(Path::new(&output).with_extension("json"))
+        .filter(|_| output != "-" && !matches.contains_key("no-sidecar"))
+        .map(|path| path.to_string_lossy().into_owned())
+        .filter(|path| *path != output);
+    let cli = Cli {
+        output,
+        options,
+        format,
+        quality,
+        plain: matches.contains_key("plain"),
+        depth: parse_number(&matches, "depth", 8)?,
+        transparent_interior: matches.contains_key("transparent-interior"),
+        hdr,
+        progressive: matches.contains_key("progressive"),
+        contours: parse_number(&matches, "contours", contour::DEFAULT_LEVELS)?,
+        base_thickness,
+        height_scale,
+        dump_iters: matches.get("dump-iters").cloned(),
+        dump_npy: matches.get("dump-npy").cloned(),
+        heightmap: matches.get("heightmap").cloned(),
+        normal_map: matches.get("normal-map").cloned(),
+        tile,
+        checkpoint,
+        resume: resume.is_some(),
+        stats: matches.get("stats").cloned(),
+        sidecar,
+        workers,
+        preview: matches.contains_key("preview-term"),
+        quiet: matches.contains_key("quiet"),
+        verbose: matches.contains_key("verbose"),
+        log_level,
+    };
+    check_format(&matches, &cli)?;
+    check_modes(&matches, &cli)?;
+    check_outputs(&matches, &cli)?;
+    Ok(Command::Render(Box::new(cli)))
+}
+
+/// Fails with the first of `conflicts` that holds, as something `flag`
+/// can't be combined with.
+fn reject_conflicts(flag: &str, conflicts: &[(bool, &str)]) -> Result<(), String> {
+    match conflicts.iter().find(|(conflicts, _)| *conflicts) {
+        Some((_, conflict)) => Err(format!("{} can't be combined with {}", flag, conflict)),
+        None => Ok(()),
+    }
+}
+
+/// Builds the options of a render from matched flags.
+fn parse_options(matches: &HashMap<&'static str, String>) -> Result<RenderOptions, String> {
+    let bounds = parse_size(matches)?;
+    let (fractal, newton) = parse_fractal(matches)?;
+    let (upper_left, lower_right, exact_corners) =
+        parse_corners(matches, bounds, &fractal, newton.is_some())?;
+    let precision = match matches.get("precision").map(String::as_str) {
+        None | Some("auto") => Precision::Auto,
+        Some("f32") => Precision::Single,
+        Some("f64") => Precision::Double,
+        Some("dd") => Precision::DoubleDouble,
+        // The bits are filled in below, once the view is known.
+        Some("perturb") => Precision::Perturbation(0),
+        Some(bits) => match bits.parse() {
+            Ok(bits) if bits > 0 => Precision::Arbitrary(bits),
+            _ => return Err(format!("invalid value '{}' for '--precision'", bits)),
+        },
+    };
+    let julia = match matches.get("julia") {
+        Some(c) => {
+            Some(parse_complex(c).ok_or_else(|| format!("invalid value '{}' for '--julia'", c))?)
+        }
+        None => None,
+    };
+    if julia.is_some()
+        && !matches!(
+            precision,
+            Precision::Auto | Precision::Double | Precision::Single
+        )
+    {
+        return Err(
+            "--julia is rendered in f64 or f32 and can't take another --precision".to_string(),
+        );
+    }
+    if fractal != Fractal::Mandelbrot && !matches!(precision, Precision::Auto | Precision::Double) {
+        return Err(format!(
+            "--fractal {} is rendered in f64 and can't take another --precision",
+            fractal.name()
+        ));
+    }
+    let test_name = matches
+        .get("bailout-test")
+        .map_or("modulus", String::as_str);
+    let bailout = Bailout {
+        radius: parse_number(matches, "bailout", 2.0)?,
+        test: bailout::Test::named(test_name).ok_or_else(|| {
+            format!(
+                "unknown bailout test '{}', expected one of: {}",
+                test_name,
+                bailout::NAMES.join(", ")
+            )
+        })?,
+    };
+    if !bailout.radius.is_finite() {
+        return Err(format!(
+            "--bailout must be a finite radius, not {}",
+            bailout.radius
+        ));
+    }
+    if bailout.radius < 2.0 {
+        return Err("--bailout must be at least 2".to_string());
+    }
+    if bailout != Bailout::default() && !matches!(precision, Precision::Auto | Precision::Double) {
+        return Err("--bailout is rendered in f64 and can't take another --precision".to_string());
+    }
+    let algorithm = match matches.get("algorithm").map_or("scan", String::as_str) {
+        "scan" => Algorithm::Scan,
+        "border-trace" => Algorithm::BorderTrace,
+        other => {
+            return Err(format!(
+                "unknown algorithm '{}', expected: scan, border-trace",
+                other
+            ))
+        }
+    };
+    let threads = parse_number(matches, "threads", mandelbrot::available_threads())?;
+    if threads == 0 {
+        return Err("--threads must be at least 1".to_string());
+    }
+    let max_iter = parse_number(matches, "max-iter", 255)?;
+    if max_iter == 0 {
+        return Err("--max-iter must be at least 1".to_string());
+    }
+    let antialias = parse_number(matches, "aa", 1)?;
+    if !(1..=antialias::MAX_ANTIALIAS).contains(&antialias) {
+        return Err(format!(
+            "--aa must be between 1 and {}",
+            antialias::MAX_ANTIALIAS
+        ));
+    }
+    let adaptive = match matches.get("adaptive") {
+        None => None,
+        Some(value) => match value.parse::<f64>() {
+            Ok(threshold) if threshold >= 0.0 => Some(threshold),
+            _ => return Err(format!("invalid value '{}' for '--adaptive'", value)),
+        },
+    };
+    if adaptive.is_some() && antialias < 2 {
+        return Err("--adaptive needs --aa 2 or more".to_string());
+    }
+    let palette = parse_colors(matches)?;
+    let coloring_name = matches
+        .get("coloring")
+        .map_or("escape-time", String::as_str);
+    let coloring = Coloring::named(coloring_name).ok_or_else(|| {
+        format!(
+            "unknown coloring '{}', expected one of: {}",
+            coloring_name,
+            coloring::NAMES.join(", ")
+        )
+    })?;
+    if coloring.follows_orbits() && !matches!(precision, Precision::Auto | Precision::Double) {
+        return Err(format!(
+            "--coloring {} is rendered in f64 and can't take another --precision",
+            coloring.name()
+        ));
+    }
+    // The distance estimate relies on z² + c being complex differentiable.
+    if coloring == Coloring::Distance && fractal != Fractal::Mandelbrot {
+        return Err("--coloring distance needs --fractal mandelbrot".to_string());
+    }
+    let transfer = match matches.get("scale") {
+        Some(text) => Transfer::parse(text).ok_or_else(|| {
+            format!(
+                "invalid value '{}' for '--scale', expected linear, log, sqrt, cbrt or power=P",
+                text
+            )
+        })?,
+        None => Transfer::Linear,
+    };
+    let palette_cycle = match matches.get("palette-cycle") {
+        Some(text) => match text.parse::<f64>() {
+            Ok(cycle) if cycle > 0.0 && cycle.is_finite() => Some(cycle),
+            _ => return Err(format!("invalid value '{}' for '--palette-cycle'", text)),
+        },
+        None => None,
+    };
+    // The other colorings spread the palette their own way.
+    if coloring != Coloring::EscapeTime {
+        for flag in ["scale", "palette-cycle"] {
+            if matches.contains_key(flag) {
+                return Err(format!(
+                    "--{} only applies to --coloring escape-time, not {}",
+                    flag,
+                    coloring.name()
+                ));
+            }
+        }
+    }
+    let interior_name = matches.get("interior").map_or("flat", String::as_str);
+    let interior = Interior::named(interior_name).ok_or_else(|| {
+        format!(
+            "unknown interior coloring '{}', expected one of: {}",
+            interior_name,
+            interior::NAMES.join(", ")
+        )
+    })?;
+    // The multiplier is worked out from the degree, which only holds for
+    // formulas that are a power of z plus c.
+    if interior == Interior::Multiplier
+        && matches!(fractal, Fractal::Phoenix(_) | Fractal::Formula(_))
+    {
+        return Err(format!(
+            "--interior multiplier doesn't apply to --fractal {}",
+            fractal.name()
+        ));
+    }
+    let shading = match matches.get("shade") {
+        Some(light) => match parse_pair::<f64>(light, ',') {
+            Some((azimuth, elevation)) if (0.0..=90.0).contains(&elevation) => {
+                Some(Light { azimuth, elevation })
+            }
+            _ => return Err(format!("invalid value '{}' for '--shade'", light)),
+        },
+        None => None,
+    };
+    let dither = match matches.get("dither") {
+        Some(name) => Some(Dither::named(name).ok_or_else(|| {
+            format!(
+                "unknown dither '{}', expected one of: {}",
+                name,
+                dither::NAMES.join(", ")
+            )
+        })?),
+        None => None,
+    };
+    let mut options = RenderOptions {
+        bounds,
+        upper_left,
+        lower_right,
+        exact_corners: Some(exact_corners),
+        precision,
+        algorithm,
+        shortcuts: Shortcuts {
+            bulbs: !matches.contains_key("no-bulb-check"),
+            periodicity: !matches.contains_key("no-periodicity-check"),
+        },
+        bailout,
+        julia,
+        fractal,
+        newton,
+        max_iter,
+        palette,
+        transfer,
+        palette_cycle,
+        smooth: !matches.contains_key("no-smooth"),
+        coloring,
+        interior,
+        shading,
+        antialias,
+        dither,
+        adjustments: parse_adjustments(matches)?,
+        adaptive,
+        threads,
+    };
+    if options.precision == Precision::Perturbation(0) {
+        options.precision =
+            Precision::Perturbation(fixed::bits_for_pixel_size(options.pixel_size()));
+    }
+    Ok(options)
+}
+
+/// Parses `--fractal` and what tunes it, and `--newton`.
+fn parse_fractal(
+    matches: &HashMap<&'static str, String>,
+) -> Result<(Fractal, Option<Polynomial>), String> {
+    let fractal_name = matches.get("fractal").map_or("mandelbrot", String::as_str);
+    // `--fractal formula` is what images rendered with `--formula` record.
+    let mut fractal = match (fractal_name, matches.get("formula")) {
+        ("mandelbrot" | "formula", Some(text)) => {
+            let formula = Formula::parse(text)
+                .map_err(|error| format!("invalid formula '{}': {}", text, error))?;
+            Fractal::Formula(Arc::new(formula))
+        }
+        (_, Some(_)) => {
+            return Err(format!(
+                "--formula can't be combined with --fractal {}",
+                fractal_name
+            ))
+        }
+        ("formula", None) => return Err("--fractal formula needs --formula".to_string()),
+        (_, None) => Fractal::named(fractal_name).ok_or_else(|| {
+            format!(
+                "unknown fractal '{}', expected one of: {}",
+                fractal_name,
+                fractal::NAMES.join(", ")
+            )
+        })?,
+    };
+    if let Some(exponent) = matches.get("exponent") {
+        if !matches!(fractal, Fractal::Mandelbrot | Fractal::Multibrot(_)) {
+            return Err(format!(
+                "--exponent needs --fractal multibrot, not {}",
+                fractal.name()
+            ));
+        }
+        fractal = exponent
+            .parse()
+            .ok()
+            .and_then(Fractal::multibrot)
+            .ok_or_else(|| format!("invalid exponent '{}', expected a number above 1", exponent))?;
+    }
+    if let Some(p) = matches.get("phoenix-p") {
+        if !matches!(fractal, Fractal::Mandelbrot | Fractal::Phoenix(_)) {
+            return Err(format!(
+                "--phoenix-p needs --fractal phoenix, not {}",
+                fractal.name()
+            ));
+        }
+        let p =
+            parse_complex(p).ok_or_else(|| format!("invalid value '{}' for '--phoenix-p'", p))?;
+        fractal = Fractal::phoenix(p);
+    }
+    let newton = match matches.get("newton") {
+        Some(coefficients) => Some(
+            parse_polynomial(coefficients)
+                .ok_or_else(|| format!("invalid value '{}' for '--newton'", coefficients))?,
+        ),
+        None => None,
+    };
+    Ok((fractal, newton))
+}
+
+/// The corners of a view, in `f64` and exactly.
+type ParsedCorners = (Complex<f64>, Complex<f64>, (Complex<Fixed>, Complex<Fixed>));
+
+/// Parses the corners of the view, fitted to the aspect of `bounds` unless
+/// `--stretch` is given. Without either corner, the whole set is shown, or
+/// the roots of a Newton fractal's polynomial around 0.
+fn parse_corners(
+    matches: &HashMap<&'static str, String>,
+    bounds: (u32, u32),
+    fractal: &Fractal,
+    newton: bool,
+) -> Result<ParsedCorners, String> {
+    let mut flip = matches.contains_key("flip");
+    let (upper_left, lower_right) = match (matches.get("upper-left"), matches.get("lower-right")) {
+        (None, None) => {
+            // The corners are checked upright, and turned over below.
+            let (center, flipped) = match newton {
+                true => (Complex::new(0.0, 0.0), false),
+                false => fractal.center(),
+            };
+            flip |= flipped;
+            let (upper_left, lower_right) = fractal::view_around(center, false, bounds);
+            let pair = |z: Complex<f64>| format!("{},{}", z.re, z.im);
+            (pair(upper_left), pair(lower_right))
+        }
+        _ => (
+            required(matches, "upper-left")?.to_string(),
+            required(matches, "lower-right")?.to_string(),
+        ),
+    };
+    let (upper_left, lower_right) = (upper_left.as_str(), lower_right.as_str());
+    let exact_corners = (
+        parse_exact_complex(upper_left).ok_or("error parsing upper left corner point")?,
+        parse_exact_complex(lower_right).ok_or("error parsing lower right corner point")?,
+    );
+    check_corners(&exact_corners.0, &exact_corners.1)?;
+    if matches.contains_key("stretch") && matches.contains_key("preserve-aspect") {
+        return Err("--stretch and --preserve-aspect can't be combined".to_string());
+    }
+    let fitted = match matches.contains_key("stretch") {
+        true => None,
+        false => fit_aspect(&exact_corners, bounds),
+    };
+    let mut exact_corners = fitted.unwrap_or(exact_corners);
+    // Taken from the exact corners, so the ones stored in metadata give
+    // the same render.
+    let to_f64 = |z: &Complex<Fixed>| Complex::new(z.re.to_f64(), z.im.to_f64());
+    let (mut upper_left, mut lower_right) = (to_f64(&exact_corners.0), to_f64(&exact_corners.1));
+    if flip {
+        std::mem::swap(&mut upper_left.im, &mut lower_right.im);
+        std::mem::swap(&mut exact_corners.0.im, &mut exact_corners.1.im);
+    }
+    Ok((upper_left, lower_right, exact_corners))
+}
+
+/// Parses the palette, named or from `--gradient`, `--palette-from-image` or
+/// the gradient of a rerendered image, in the color space of
+/// `--color-space`.
+fn parse_colors(matches: &HashMap<&'static str, String>) -> Result<Palette, String> {
+    let palette = match (matches.get("gradient"), matches.get("palette-from-image")) {
+        (Some(_), Some(_)) => {
+            return Err("--gradient can't be combined with --palette-from-image".to_string())
+        }
+        (Some(_), _) if matches.contains_key("palette") => {
+            return Err("--gradient can't be combined with --palette".to_string())
+        }
+        (_, Some(_)) if matches.contains_key("palette") => {
+            return Err("--palette-from-image can't be combined with --palette".to_string())
+        }
+        (Some(path), None) => {
+            let text =
+                std::fs::read_to_string(path).map_err(|e| format!("reading {}: {}", path, e))?;
+            gradient::parse(path, &text).map_err(|e| format!("{}: {}", path, e))?
+        }
+        (None, Some(path)) => {
+            let bytes = std::fs::read(path).map_err(|e| format!("reading {}: {}", path, e))?;
+            gradient::from_image(&bytes).map_err(|e| format!("{}: {}", path, e))?
+        }
+        (None, None) if matches.contains_key("stored-gradient") => {
+            gradient::parse_csv(&matches["stored-gradient"])
+                .map_err(|e| format!("the stored gradient: {}", e))?
+        }
+        (None, None) => {
+            let palette_name = matches.get("palette").map_or("grayscale", String::as_str);
+            Palette::named(palette_name).ok_or_else(|| {
+                format!(
+                    "unknown palette '{}', expected one of: {}",
+                    palette_name,
+                    palette::NAMES.join(", ")
+                )
+            })?
+        }
+    };
+    let space_name = matches.get("color-space").map_or("srgb", String::as_str);
+    let space = ColorSpace::named(space_name).ok_or_else(|| {
+        format!(
+            "unknown color space '{}', expected one of: {}",
+            space_name,
+            palette::SPACE_NAMES.join(", ")
+        )
+    })?;
+    Ok(palette.in_space(space))
+}
+
+/// Checks that the render's format can hold it, and that the flags only
+/// some formats take are given with one of them.
+fn check_format(matches: &HashMap<&'static str, String>, cli: &Cli) -> Result<(), String> {
+    let (format, options) = (cli.format, &cli.options);
+    let bounds = options.bounds;
+    if !(1..=100).contains(&cli.quality) {
+        return Err("--quality must be between 1 and 100".to_string());
+    }
+    if format == Format::Webp && bounds.0.max(bounds.1) > webp::MAX_SIZE {
+        return Err(format!(
+            "webp images can be at most {} pixels a side",
+            webp::MAX_SIZE
+        ));
+    }
+    let mesh = matches!(format, Format::Stl | Format::Obj);
+    if (format == Format::Exr || format == Format::Svg || mesh) && options.antialias > 1 {
+        return Err(format!(
+            "{} output can't be combined with --aa",
+            format.extension()
+        ));
+    }
+    if !(1..=1000).contains(&cli.contours) {
+        return Err("--contours must be between 1 and 1000".to_string());
+    }
+    if matches.contains_key("contours") && format != Format::Svg {
+        return Err("--contours needs svg output".to_string());
+    }
+    if matches.contains_key("base-thickness") && !mesh {
+        return Err("--base-thickness needs stl or obj output".to_string());
+    }
+    if matches.contains_key("height-scale") && !mesh && cli.normal_map.is_none() {
+        return Err("--height-scale needs stl or obj output or --normal-map".to_string());
+    }
+    if mesh && bounds.0.min(bounds.1) < 2 {
+        return Err("stl and obj meshes need at least 2 pixels a side".to_string());
+    }
+    match cli.depth {
+        8 => {}
+        16 if options.antialias > 1 => {
+            return Err("--depth 16 can't be combined with --aa".to_string())
+        }
+        16 if !matches!(format, Format::Png | Format::Pgm) => {
+            return Err("--depth 16 needs png or pgm output".to_string())
+        }
+        16 => {}
+        depth => return Err(format!("invalid value '{}' for '--depth'", depth)),
+    }
+    // Escape values aren't colors to dither or adjust.
+    let finishing = ADJUSTMENT_FLAGS
+        .iter()
+        .filter(|_| !options.adjustments.is_identity())
+        .chain(options.dither.is_some().then_some(&"dither"))
+        .find(|flag| matches.contains_key(*flag));
+    if let Some(flag) = finishing {
+        reject_conflicts(
+            &format!("--{}", flag),
+            &[
+                (format == Format::Exr, "exr output"),
+                (format == Format::Svg, "svg output"),
+                (mesh, "mesh output"),
+                (cli.depth == 16, "--depth 16"),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Checks that the ways of rendering other than the usual, and of
+/// writing out the image, are only combined with what they can take.
+fn check_modes(matches: &HashMap<&'static str, String>, cli: &Cli) -> Result<(), String> {
+    let (format, options) = (cli.format, &cli.options);
+    let mesh = matches!(format, Format::Stl | Format::Obj);
+    // Newton fractals are colored by their roots alone, and have no escape
+    // values to write out.
+    if options.newton.is_some() {
+        reject_conflicts(
+            "--newton",
+            &[
+                (options.fractal != Fractal::Mandelbrot, "--fractal"),
+                (options.julia.is_some(), "--julia"),
+                (
+                    !matches!(options.precision, Precision::Auto | Precision::Double),
+                    "--precision",
+                ),
+                (options.coloring != Coloring::EscapeTime, "--coloring"),
+                (options.transfer != Transfer::Linear, "--scale"),
+                (options.palette_cycle.is_some(), "--palette-cycle"),
+                (options.interior != Interior::Flat, "--interior"),
+                (options.shading.is_some(), "--shade"),
+                (options.bailout != Bailout::default(), "--bailout"),
+                (format == Format::Exr, "exr output"),
+                (format == Format::Svg, "svg output"),
+                (mesh, "mesh output"),
+                (cli.depth == 16, "--depth 16"),
+                (cli.dump_iters.is_some(), "--dump-iters"),
+                (cli.dump_npy.is_some(), "--dump-npy"),
+                (cli.heightmap.is_some(), "--heightmap"),
+                (cli.normal_map.is_some(), "--normal-map"),
+            ],
+        )?;
+    }
+    // The alpha comes from one escape time per pixel, as inside or not.
+    if cli.transparent_interior {
+        reject_conflicts(
+            "--transparent-interior",
+            &[
+                (format != Format::Png, "other formats than png"),
+                (cli.depth == 16, "--depth 16"),
+                (options.antialias > 1, "--aa"),
+                (options.newton.is_some(), "--newton"),
+                (cli.tile.is_some(), "--tile"),
+                (cli.preview, "--preview-term"),
+            ],
+        )?;
+    }
+    // HDR colors are taken from the escape values of single samples,
+    // unshaded and unadjusted.
+    if cli.hdr.is_some() {
+        reject_conflicts(
+            "--hdr",
+            &[
+                (format != Format::Png, "other formats than png"),
+                (cli.depth == 16, "--depth 16"),
+                (options.antialias > 1, "--aa"),
+                (options.newton.is_some(), "--newton"),
+                (options.shading.is_some(), "--shade"),
+                (!options.adjustments.is_identity(), "tone adjustments"),
+                (options.dither.is_some(), "--dither"),
+                (cli.transparent_interior, "--transparent-interior"),
+                (cli.tile.is_some(), "--tile"),
+                (cli.preview, "--preview-term"),
+            ],
+        )?;
+    }
+    // Every pass is a whole image of its own, colored as the render asks.
+    if cli.progressive {
+        reject_conflicts(
+            "--progressive",
+            &[
+                (format != Format::Png, "other formats than png"),
+                (cli.depth == 16, "--depth 16"),
+                (cli.transparent_interior, "--transparent-interior"),
+                (cli.hdr.is_some(), "--hdr"),
+                (cli.tile.is_some(), "--tile"),
+                (cli.dump_iters.is_some(), "--dump-iters"),
+                (cli.dump_npy.is_some(), "--dump-npy"),
+                (cli.heightmap.is_some(), "--heightmap"),
+                (cli.normal_map.is_some(), "--normal-map"),
+                (matches.contains_key("checkpoint"), "--checkpoint"),
+                (matches.contains_key("resume"), "--resume"),
+                (cli.stats.is_some(), "--stats"),
+                (matches.contains_key("workers"), "--workers"),
+                (cli.preview, "--preview-term"),
+            ],
+        )?;
+    }
+    if cli.tile.is_some() {
+        reject_conflicts(
+            "--tile",
+            &[
+                (
+                    !matches!(format, Format::Png | Format::Tiff),
+                    "other formats than png or tiff",
+                ),
+                (cli.depth == 16, "--depth 16"),
+                (
+                    options.coloring == Coloring::Histogram,
+                    "--coloring histogram",
+                ),
+                (cli.dump_iters.is_some(), "--dump-iters"),
+                (cli.dump_npy.is_some(), "--dump-npy"),
+                (cli.heightmap.is_some(), "--heightmap"),
+                (cli.normal_map.is_some(), "--normal-map"),
+                (cli.preview, "--preview-term"),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Checks where the render's side files and escape times go: standard
+/// output is the image's alone, and checkpoints, workers and statistics
+/// need the escape times a band of rows at a time.
+fn check_outputs(matches: &HashMap<&'static str, String>, cli: &Cli) -> Result<(), String> {
+    // Standard output is the image's alone, so pipelines get nothing else.
+    for flag in ["dump-iters", "dump-npy", "heightmap", "normal-map", "stats"] {
+        if matches.get(flag).is_some_and(|path| path == "-") {
+            return Err(format!(
+                "--{} can't write to standard output, only the image can",
+                flag
+            ));
+        }
+    }
+    let options = &cli.options;
+    // Both only compute escape times, one band of whole rows at a time.
+    let banded = [
+        (cli.tile.is_some(), "--tile"),
+        (options.antialias > 1, "--aa"),
+        (
+            options.algorithm == Algorithm::BorderTrace,
+            "--algorithm border-trace",
+        ),
+        (cli.preview, "--preview-term"),
+    ];
+    if cli.checkpoint.is_some() {
+        reject_conflicts("checkpoints", &banded)?;
+    }
+    if !cli.workers.is_empty() {
+        reject_conflicts("coordinated renders", &banded)?;
+    }
+    if cli.checkpoint.is_some() && !cli.workers.is_empty() {
+        return Err("coordinated renders can't be checkpointed".to_string());
+    }
+    // The metrics are of the escape times, which are never all held at once
+    // otherwise.
+    if cli.stats.is_some() {
+        reject_conflicts(
+            "--stats",
+            &[
+                (cli.tile.is_some(), "--tile"),
+                (options.antialias > 1, "--aa"),
+                (cli.preview, "--preview-term"),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// The flags that tune the finished image; see `parse_adjustments`.
+const ADJUSTMENT_FLAGS: &[&str] = &["exposure", "contrast", "brightness", "gamma"];
+
+/// Parses `--exposure`, `--contrast`, `--brightness` and `--gamma`.
+fn parse_adjustments(matches: &HashMap<&'static str, String>) -> Result<Adjustments, String> {
+    if matches.get("exposure").is_some_and(|e| e.contains(',')) {
+        return Err("--exposure R,G,B only applies to buddhabrot --nebula".to_string());
+    }
+    let defaults = Adjustments::default();
+    let adjustments = Adjustments {
+        exposure: parse_number(matches, "exposure", defaults.exposure)?,
+        contrast: parse_number(matches, "contrast", defaults.contrast)?,
+        brightness: parse_number(matches, "brightness", defaults.brightness)?,
+        gamma: parse_number(matches, "gamma", defaults.gamma)?,
+    };
+    let valid = [
+        ("exposure", adjustments.exposure.is_finite()),
+        (
+            "contrast",
+            (0.0..f64::INFINITY).contains(&adjustments.contrast),
+        ),
+        ("brightness", (-1.0..=1.0).contains(&adjustments.brightness)),
+        (
+            "gamma",
+            adjustments.gamma > 0.0 && adjustments.gamma.is_finite(),
+        ),
+    ];
+    match valid.iter().find(|(_, valid)| !valid) {
+        Some((flag, _)) => Err(format!(
+            "invalid value '{}' for '--{}'",
+            matches[flag], flag
+        )),
+        None => Ok(adjustments),
+    }
+}
+
+/// Builds the `--help` text from the flag table.
+pub fn help(program: &str) -> String {
+    let mut text = format!(
+        "Render the Mandelbrot set to a PNG file.\n\n\
+         Usage: {program} [OPTIONS]\n       \
+         {program} FILE PIXELS UPPERLEFT LOWERRIGHT\n       \
+         {program} rerender INPUT.png [OUTPUT] [OPTIONS]\n       \
+         {program} animate DIR|--video FILE --size WxH --frames N --from VIEW [--to VIEW] [OPTIONS]\n       \
+         {program} serve [--port PORT] [OPTIONS]\n       \
+         {program} worker --listen ADDR [OPTIONS]\n       \
+         {program} coordinate --workers HOSTS [OPTIONS]\n       \
+         {program} batch FILE [--jobs N] [OPTIONS]\n       \
+         {program} bench [--threads N] [--precision P] [OPTIONS]\n       \
+         {program} buddhabrot FILE PIXELS [UPPERLEFT LOWERRIGHT] [OPTIONS]\n       \
+         {program} lyapunov FILE PIXELS [UPPERLEFT LOWERRIGHT] [--sequence AB..] [OPTIONS]\n       \
+         {program} pyramid FILE.dzi PIXELS [UPPERLEFT LOWERRIGHT] [--tile-size N] [OPTIONS]\n       \
+         {program} bookmark add NAME -u RE,IM -l RE,IM [-i N]\n       \
+         {program} bookmark list\n       \
+         {program} repl [--size WxH] [OPTIONS]\n       \
+         {program} explore [--size WxH] [OPTIONS]\n       \
+         {program} palette list\n\n\
+         Example: {program} mandel.png 1000x750 -1.20,0.35 -1,0.20\n\nOptions:\n"
+    );
+    let columns = FLAGS
+        .iter()
+        .map(|flag| {
+            let short = flag.short.map(|c| format!("-{}, ", c)).unwrap_or_default();
+            let value = flag.value.map(|v| format!(" <{}>", v)).unwrap_or_default();
+            (format!("{}--{}{}", short, flag.long, value), flag.help)
+        })
+        .collect::<Vec<_>>();
+    let width = columns.iter().map(|(c, _)| c.len()).max().unwrap_or(0);
+    for (column, help) in columns {
+        text.push_str(&format!("  {:width$}  {}\n", column, help, width = width));
+    }
+    text
+}
+
+#[test]
+fn test_help_lists_every_flag() {
+    let text = help("mandelbrot");
+    for flag in FLAGS {
+        assert!(text.contains(&format!("--{}", flag.long)));
+    }
+}
+
+/// Splits `args` into flag values keyed by long name, filling in any
+/// positional arguments in `POSITIONALS` order.
+fn match_flags(args: &[String]) -> Result<HashMap<&'static str, String>, String> {
+    let mut matches = HashMap::new();
+    let mut positionals = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let flag = FLAGS
+                .iter()
+                .find(|f| f.long == name || f.aliases.contains(&name))
+                .ok_or_else(|| format!("unexpected argument '--{}'", name))?;
+            (flag, inline)
+        } else if arg.len() > 1 && arg.starts_with('-') && !is_number_like(arg) {
+            let mut chars = arg[1..].chars();
+            let c = chars.next().unwrap();
+            let rest = chars.as_str();
+            let flag = FLAGS
+                .iter()
+                .find(|f| f.short == Some(c))
+                .ok_or_else(|| format!("unexpected argument '-{}'", c))?;
+            (flag, (!rest.is_empty()).then(|| rest.to_string()))
+        } else {
+            positionals.push(arg.clone());
+            continue;
+        };
+        let value = match (flag.value, inline) {
+            (None, None) => String::new(),
+            (None, Some(_)) => return Err(format!("'--{}' does not take a value", flag.long)),
+            (Some(_), Some(value)) => value,
+            // A value of several words, such as `FILE NAME`, takes as many
+            // arguments, and is kept with spaces between them.
+            (Some(name), None) => (0..name.split(' ').count())
+                .map(|_| args.next().cloned())
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("a value is required for '--{} <{}>'", flag.long, name))?
+                .join(" "),
+        };
+        if matches.insert(flag.long, value).is_some() {
+            return Err(format!("'--{}' cannot be used multiple times", flag.long));
+        }
+    }
+    if positionals.len() > POSITIONALS.len() {
+        return Err(format!(
+            "unexpected argument '{}'",
+            positionals[POSITIONALS.len()]
+        ));
+    }
+    for (name, value) in POSITIONALS.iter().zip(positionals) {
+        if matches.insert(name, value).is_some() {
+            return Err(format!(
+                "'--{}' was given both as a flag and positionally",
+                name
+            ));
+        }
+    }
+    Ok(matches)
+}
+
+/// Negative coordinates such as `-1.20,0.35` must not be mistaken for flags.
+fn is_number_like(arg: &str) -> bool {
+    arg[1..].starts_with(|c: char| c.is_ascii_digit() || c == '.')
+}
+
+/// Parses `--size`, which must have pixels to render.
+fn parse_size(matches: &HashMap<&'static str, String>) -> Result<(u32, u32), String> {
+    let size = required(matches, "size")?;
+    match parse_pair::<u32>(size, 'x') {
+        Some((0, _) | (_, 0)) => Err(format!("--size must be at least 1x1, not {}", size)),
+        Some(bounds) => Ok(bounds),
+        None => Err(format!("Unexpected dimensions: {}", size)),
+    }
+}
+
+fn required<'a>(matches: &'a HashMap<&'static str, String>, name: &str) -> Result<&'a str, String> {
+    matches.get(name).map(String::as_str).ok_or_else(|| {
+        format!(
+            "the following required argument was not provided: --{}",
+            name
+        )
+    })
+}
+
+fn parse_number<T: FromStr>(
+    matches: &HashMap<&'static str, String>,
+    name: &str,
+    default: T,
+) -> Result<T, String> {
+    match matches.get(name) {
+        None => Ok(default),
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("invalid value '{}' for '--{}'", value, name)),
+    }
+}
+
+#[cfg(test)]
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+#[test]
+fn test_parse_args_positional() {
+    assert_eq!(
+        parse_args(&args("mandel.png 1000x750 -1.20,0.35 -1,0.20")),
+        Ok(Command::Render(Box::new(Cli {
+            output: "mandel.png".to_string(),
+            options: RenderOptions {
+                bounds: (1000, 750),
+                upper_left: Complex { re: -1.2, im: 0.35 },
+                lower_right: Complex { re: -1.0, im: 0.2 },
+                exact_corners: Some((
+                    parse_exact_complex("-1.20,0.35").unwrap(),
+                    parse_exact_complex("-1,0.20").unwrap(),
+                )),
+                precision: Precision::Auto,
+                algorithm: Algorithm::Scan,
+                shortcuts: Shortcuts::default(),
+                bailout: Bailout::default(),
+                julia: None,
+                fractal: Fractal::Mandelbrot,
+                newton: None,
+                max_iter: 255,
+                palette: Palette::named("grayscale").unwrap(),
+                transfer: Transfer::Linear,
+                palette_cycle: None,
+                smooth: true,
+                coloring: Coloring::EscapeTime,
+                interior: Interior::Flat,
+                shading: None,
+                antialias: 1,
+                dither: None,
+                adjustments: Adjustments::default(),
+                adaptive: None,
+                threads: mandelbrot::available_threads(),
+            },
+            format: Format::Png,
+            quality: 90,
+            plain: false,
+            transparent_interior: false,
+            hdr: None,
+            progressive: false,
+            contours: 16,
+            base_thickness: 20.0,
+            height_scale: 100.0,
+            depth: 8,
+            dump_iters: None,
+            dump_npy: None,
+            heightmap: None,
+            normal_map: None,
+            tile: None,
+            checkpoint: None,
+            resume: false,
+            stats: None,
+            sidecar: Some("mandel.json".to_string()),
+            workers: Vec::new(),
+            preview: false,
+            quiet: false,
+            verbose: false,
+            log_level: None,
+        })))
+    );
+}
+
+#[test]
+fn test_parse_args_flags() {
+    let expected = parse_args(&args("mandel.png 1000x750 -1.20,0.35 -1,0.20 -t 4 -i 100"));
+    assert_eq!(
+        parse_args(&args(
+            "--size=1000x750 -o mandel.png --lower-right -1,0.20 -u -1.20,0.35 --threads 4 -i100"
+        )),
+        expected
+    );
+    assert_eq!(
+        parse_args(&args(
+            "mandel.png 1000x750 -1.20,0.35 -1,0.20 --threads 4 --iterations 100"
+        )),
+        expected
+    );
+    match expected {
+        Ok(Command::Render(cli)) => {
+            assert_eq!((cli.options.threads, cli.options.max_iter), (4, 100))
+        }
+        _ => panic!("expected a render command"),
+    }
+}
+
+#[test]
+fn test_parse_args_palette() {
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --palette fire")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.options.palette, Palette::named("fire").unwrap())
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --aa 4")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.options.antialias, 4),
+        other => panic!("unexpected {:?}", other),
+    }
+    for aa in ["0", "17", "65536"] {
+        let cli = format!("mandel.png 8x8 -1,1 1,-1 --aa {}", aa);
+        assert!(parse_args(&args(&cli)).is_err(), "{}", aa);
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --aa 4 --adaptive 2.5")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.options.adaptive, Some(2.5)),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args(
+        "mandel.png 10x10 -1,1 1,-1 --no-bulb-check --no-periodicity-check",
+    )) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.options.shortcuts, Shortcuts::NONE),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --depth 16")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.depth, 16),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.jpg 10x10 -1,1 1,-1 --quality 75")) {
+        Ok(Command::Render(cli)) => assert_eq!((cli.format, cli.quality), (Format::Jpeg, 75)),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.webp 10x10 -1,1 1,-1")) {
+        Ok(Command::Render(cli)) => assert_eq!((cli.format, cli.quality), (Format::Webp, 100)),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("mandel.webp 20000x10 -1,1 1,-1")).is_err());
+    match parse_args(&args("mandel.out 10x10 -1,1 1,-1 -f jpeg")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.format, Format::Jpeg),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("- 10x10 -1,1 1,-1 --format pgm --depth 16 --plain")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.output, "-");
+            assert_eq!(cli.format, Format::Pgm);
+            assert!(cli.plain);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.exr 10x10 -1,1 1,-1")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.format, Format::Exr),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("mandel.out 10x10 -1,1 1,-1")).is_err());
+    match parse_args(&args("mandel 10x10 -1,1 1,-1")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.format, Format::Png),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args(
+        "mandel.png 10x10 -1,1 1,-1 --dump-iters mandel.iters",
+    )) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.dump_iters.as_deref(), Some("mandel.iters")),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --dump-npy mandel.npy")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.dump_npy.as_deref(), Some("mandel.npy")),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("- 10x10 -1,1 1,-1 --dump-iters a.iters")) {
+        Ok(Command::Render(cli)) => assert_eq!((cli.output, cli.format), ("-".into(), Format::Png)),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --dump-npy -")).is_err());
+    assert!(parse_args(&args("- 10x10 -1,1 1,-1 --stats -")).is_err());
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 -q")) {
+        Ok(Command::Render(cli)) => assert!(cli.quiet),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --tile 64")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.tile, Some(64)),
+        other => panic!("unexpected {:?}", other),
+    }
+    for line in [
+        "mandel.jpg 10x10 -1,1 1,-1 --tile 64",
+        "mandel.png 10x10 -1,1 1,-1 --tile 64 --coloring histogram",
+        "mandel.png 10x10 -1,1 1,-1 --tile 64 --dump-iters a.iters",
+        "mandel.png 10x10 -1,1 1,-1 --tile 64 --dump-npy a.npy",
+        "mandel.png 10x10 -1,1 1,-1 --tile rows",
+    ] {
+        assert!(parse_args(&args(line)).is_err(), "{}", line);
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --resume a.mbcp")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.checkpoint.as_deref(), Some("a.mbcp"));
+            assert!(cli.resume);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    for line in [
+        "mandel.png 10x10 -1,1 1,-1 --checkpoint a.mbcp --resume b.mbcp",
+        "mandel.png 10x10 -1,1 1,-1 --checkpoint a.mbcp --aa 2",
+        "mandel.png 10x10 -1,1 1,-1 --checkpoint a.mbcp --tile 0",
+        "mandel.png 10x10 -1,1 1,-1 --stats a.json --aa 2",
+        "mandel.png 10x10 -1,1 1,-1 --stats a.json --tile 0",
+    ] {
+        assert!(parse_args(&args(line)).is_err(), "{}", line);
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --stats a.json")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.stats.as_deref(), Some("a.json")),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 -v")) {
+        Ok(Command::Render(cli)) => assert!(cli.verbose && !cli.quiet),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --log-level debug")) {
+        Ok(command) => assert_eq!(command.log_level(), Some(Level::Debug)),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --log-level off")) {
+        Ok(command) => assert_eq!(command.log_level(), None),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --log-level loud")).is_err());
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --no-smooth")) {
+        Ok(Command::Render(cli)) => assert!(!cli.options.smooth),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_aspect() {
+    let corners = |line: &str| match parse_args(&args(line)) {
+        Ok(Command::Render(cli)) => {
+            let (upper_left, lower_right) = cli.options.exact_corners.clone().unwrap();
+            assert_eq!(cli.options.upper_left.re, upper_left.re.to_f64());
+            assert_eq!(cli.options.lower_right.im, lower_right.im.to_f64());
+            (cli.options.upper_left, cli.options.lower_right)
+        }
+        other => panic!("unexpected {:?}", other),
+    };
+    let view = |a: (f64, f64), b: (f64, f64)| (Complex::new(a.0, a.1), Complex::new(b.0, b.1));
+    assert_eq!(
+        corners("a.png 20x10 -1,1 1,-1"),
+        view((-2.0, 1.0), (2.0, -1.0))
+    );
+    assert_eq!(
+        corners("a.png 10x20 -1,1 1,-1"),
+        view((-1.0, 2.0), (1.0, -2.0))
+    );
+    assert_eq!(
+        corners("a.png 10x20 -1,1 1,-1 --flip"),
+        view((-1.0, -2.0), (1.0, 2.0))
+    );
+    assert_eq!(
+        corners("a.png 20x10 -1,1 1,-1 --preserve-aspect"),
+        view((-2.0, 1.0), (2.0, -1.0))
+    );
+    assert_eq!(
+        corners("a.png 20x10 -1,1 1,-1 --stretch"),
+        view((-1.0, 1.0), (1.0, -1.0))
+    );
+    assert!(parse_args(&args("a.png 20x10 -1,1 1,-1 --stretch --preserve-aspect")).is_err());
+}
+
+#[test]
+fn test_parse_julia() {
+    match parse_args(&args("a.png 10x10 -2,2 2,-2 --julia -0.8,0.156")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(
+                cli.options.julia,
+                Some(Complex {
+                    re: -0.8,
+                    im: 0.156
+                })
+            );
+            assert_eq!(cli.options.resolved_precision(), Precision::Double);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args(
+        "a.png 10x10 -2,2 2,-2 --julia -0.8,0.156 --precision f64"
+    ))
+    .is_ok());
+    assert!(parse_args(&args(
+        "a.png 10x10 -2,2 2,-2 --julia -0.8,0.156 --precision 90"
+    ))
+    .is_err());
+    assert!(parse_args(&args("a.png 10x10 -2,2 2,-2 --julia -0.8")).is_err());
+}
+
+#[test]
+fn test_parse_args_precision() {
+    let precision = |line: &str| match parse_args(&args(line)) {
+        Ok(Command::Render(cli)) => cli.options.precision,
+        other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!(precision("a.png 10x10 -1,1 1,-1"), Precision::Auto);
+    assert_eq!(
+        precision("a.png 10x10 -1,1 1,-1 --precision f64"),
+        Precision::Double
+    );
+    assert_eq!(
+        precision("a.png 10x10 -1,1 1,-1 --precision f32"),
+        Precision::Single
+    );
+    assert_eq!(
+        precision("a.png 10x10 -1,1 1,-1 --precision dd"),
+        Precision::DoubleDouble
+    );
+    assert_eq!(
+        precision("a.png 10x10 -1,1 1,-1 --precision 200"),
+        Precision::Arbitrary(200)
+    );
+    assert_eq!(
+        precision("a.png 10x10 -1,1 1,-1 --precision perturb"),
+        Precision::Perturbation(fixed::bits_for_pixel_size(0.2))
+    );
+    assert_eq!(
+        match parse_args(&args("a.png 10x10 -1,1 1,-1 --algorithm border-trace")) {
+            Ok(Command::Render(cli)) => cli.options.algorithm,
+            other => panic!("unexpected {:?}", other),
+        },
+        Algorithm::BorderTrace
+    );
+    assert!(parse_args(&args("a.png 10x10 -1,1 1,-1 --algorithm flood")).is_err());
+    assert!(parse_args(&args("a.png 10x10 -1,1 1,-1 --precision 0")).is_err());
+    assert!(parse_args(&args("a.png 10x10 -1,1 1,-1 --precision lots")).is_err());
+}
+
+#[test]
+fn test_parse_config() {
+    let path = std::env::temp_dir().join("mandelbrot_test_config.toml");
+    std::fs::write(
+        &path,
+        "# Seahorse valley\n\
+         output = \"seahorse.png\"\n\
+         size = \"400x300\"\n\
+         upper-left = \"-0.76,0.12\"\n\
+         lower-right = \"-0.72,0.09\"\n\
+         iterations = 2000\n\
+         no-smooth = true\n\
+         quiet = false\n",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+    match parse_args(&args(&format!("--config {} -i 500 override.png", path))) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.output, "override.png");
+            assert_eq!(cli.options.bounds, (400, 300));
+            assert_eq!(
+                cli.options.upper_left,
+                Complex {
+                    re: -0.76,
+                    im: 0.12
+                }
+            );
+            assert_eq!(cli.options.max_iter, 500);
+            assert!(!cli.options.smooth);
+            assert!(!cli.quiet);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let bad = std::env::temp_dir().join("mandelbrot_test_bad_config.toml");
+    for text in [
+        "colour = \"red\"",
+        "no-smooth = 1",
+        "threads = true",
+        "help = true",
+    ] {
+        std::fs::write(&bad, text).unwrap();
+        let line = format!("-c {}", bad.to_str().unwrap());
+        assert!(parse_args(&args(&line)).is_err(), "{}", text);
+    }
+    assert!(parse_args(&args("--config /nonexistent/render.toml")).is_err());
+}
+
+#[test]
+fn test_parse_batch() {
+    let path = std::env::temp_dir().join("mandelbrot_test_batch.toml");
+    std::fs::write(
+        &path,
+        "[overview]\n\
+         output = \"overview.png\"\n\
+         size = \"400x300\"\n\
+         \n\
+         [valley]\n\
+         output = \"valley.jpg\"\n\
+         size = \"200x150\"\n\
+         upper-left = \"-0.76,0.12\"\n\
+         lower-right = \"-0.72,0.09\"\n\
+         iterations = 2000\n",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+    match parse_args(&args(&format!("batch {} -i 500 --jobs 2", path))) {
+        Ok(Command::Batch(batch)) => {
+            assert_eq!(batch.parallel, 2);
+            let names = batch.jobs.iter().map(|(name, _)| name.as_str());
+            assert_eq!(names.collect::<Vec<_>>(), ["overview", "valley"]);
+            let (_, valley) = &batch.jobs[1];
+            assert_eq!(valley.output, "valley.jpg");
+            assert_eq!(valley.format, Format::Jpeg);
+            assert_eq!(valley.options.bounds, (200, 150));
+            assert_eq!(valley.options.max_iter, 500);
+            assert!(valley.quiet);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args(&format!("batch {} --jobs 0", path))).is_err());
+    assert!(parse_args(&args("batch")).is_err());
+    assert!(parse_args(&args("a.png 10x10 -1,1 1,-1 --jobs 2")).is_err());
+
+    let bad = std::env::temp_dir().join("mandelbrot_test_bad_batch.toml");
+    for text in [
+        "output = \"a.png\"",
+        "[a]\noutput = \"a.png\"",
+        "[a]\noutput = \"a.png\"\nsize = \"9x9\"\nframes = 3",
+        "[a]\noutput = \"a.png\"\nsize = \"9x9\"\n[b]\noutput = \"a.png\"\nsize = \"9x9\"",
+        "",
+    ] {
+        std::fs::write(&bad, text).unwrap();
+        let line = format!("batch {}", bad.to_str().unwrap());
+        assert!(parse_args(&args(&line)).is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn test_parse_bookmark() {
+    match parse_args(&args("bookmark add valley -u -0.8,0.2 -l -0.7,0.1 -i 800")) {
+        Ok(Command::Bookmark(BookmarkCommand::Add(bookmark))) => {
+            assert_eq!(bookmark.name, "valley");
+            assert_eq!(bookmark.center, "-0.7500000,0.1500000");
+            assert_eq!(bookmark.zoom.parse::<f64>().unwrap().round(), 40.0);
+            assert_eq!(bookmark.max_iter.as_deref(), Some("800"));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(
+        parse_args(&args("bookmark list")),
+        Ok(Command::Bookmark(BookmarkCommand::List))
+    );
+    assert!(parse_args(&args("bookmark add")).is_err());
+    assert_eq!(parse_args(&args("palette list")), Ok(Command::PaletteList));
+    assert!(parse_args(&args("palette list fire")).is_err());
+    assert!(parse_args(&args("palette")).is_err());
+    assert!(parse_args(&args("bookmark add valley -u -0.8,0.2")).is_err());
+    assert!(parse_args(&args("bookmark add valley -u -0.8,0.2 -l -0.7,0.1 -p fire")).is_err());
+    assert!(parse_args(&args("bookmark add a.b -u -0.8,0.2 -l -0.7,0.1")).is_err());
+    assert!(parse_args(&args("bookmark remove valley")).is_err());
+}
+
+#[test]
+fn test_parse_location() {
+    match parse_args(&args("a.png 400x200 --location whole-set")) {
+        Ok(Command::Render(cli)) => {
+            let options = &cli.options;
+            assert!((options.upper_left.re - -3.1).abs() < 1e-9);
+            assert!((options.upper_left.im - 1.25).abs() < 1e-9);
+            assert!((options.lower_right.re - 1.9).abs() < 1e-9);
+            assert!((options.lower_right.im - -1.25).abs() < 1e-9);
+            assert_eq!(options.max_iter, 255);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("a.png 100x100 --location seahorse-valley -i 50")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.options.max_iter, 50),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("a.png 10x10 --location atlantis")).is_err());
+    assert!(parse_args(&args("a.png 10x10 -1,1 --location whole-set")).is_err());
+    assert!(parse_args(&args("a.png --location whole-set")).is_err());
+}
+
+#[test]
+fn test_parse_import_kfr() {
+    let path = std::env::temp_dir().join("mandelbrot_test_import.kfr");
+    std::fs::write(
+        &path,
+        "Re: -0.75\r\nIm: 0.1\r\nZoom: 2\r\nIterations: 3000\r\n",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+    match parse_args(&args(&format!("a.png 400x200 --import-kfr {}", path))) {
+        Ok(Command::Render(cli)) => {
+            let options = &cli.options;
+            assert!((options.upper_left.re - -2.75).abs() < 1e-9);
+            assert!((options.upper_left.im - 1.1).abs() < 1e-9);
+            assert!((options.lower_right.re - 1.25).abs() < 1e-9);
+            assert!((options.lower_right.im - -0.9).abs() < 1e-9);
+            assert_eq!(options.max_iter, 3000);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let with = |rest: &str| {
+        parse_args(&args(&format!(
+            "a.png 40x20 --import-kfr {} {}",
+            path, rest
+        )))
+    };
+    assert!(matches!(with("-i 50"), Ok(Command::Render(cli)) if cli.options.max_iter == 50));
+    assert!(with("-u -1,1").is_err());
+    assert!(with("--location whole-set").is_err());
+    assert!(parse_args(&args("a.png 10x10 --import-kfr /nonexistent.kfr")).is_err());
+}
+
+#[test]
+fn test_parse_import_par() {
+    let path = std::env::temp_dir().join("mandelbrot_test_import.par");
+    std::fs::write(
+        &path,
+        "Whole { corners=-2.5/1.5/-1.5/1.5 maxiter=150 }\n\
+         Dragon { type=julia center-mag=0/0/0.5 params=-0.8/0.156 }\n",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+    let with = |rest: &str| parse_args(&args(&format!("a.png 400x300 --import-par {}", rest)));
+    match with(&format!("{} whole", path)) {
+        Ok(Command::Render(cli)) => {
+            let options = &cli.options;
+            assert!((options.upper_left.re - -2.5).abs() < 1e-9);
+            assert!((options.upper_left.im - 1.5).abs() < 1e-9);
+            assert!((options.lower_right.re - 1.5).abs() < 1e-9);
+            assert!((options.lower_right.im - -1.5).abs() < 1e-9);
+            assert_eq!(options.max_iter, 150);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match with(&format!("{} Dragon -i 64", path)) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.options.julia, Some(Complex::new(-0.8, 0.156)));
+            assert!((cli.options.upper_left.im - 2.0).abs() < 1e-9);
+            assert_eq!(cli.options.max_iter, 64);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let missing = with(&format!("{} Atlantis", path)).unwrap_err();
+    assert!(missing.ends_with("no entry named 'Atlantis'; it has Whole, Dragon"));
+    assert!(with(path).is_err());
+    assert!(with(&format!("{} Whole --import-kfr x.kfr", path)).is_err());
+}
+
+#[test]
+fn test_parse_sidecar() {
+    let sidecar = |line: &str| match parse_args(&args(line)) {
+        Ok(Command::Render(cli)) => cli.sidecar,
+        other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!(
+        sidecar("out/a.ppm 10x10 -1,1 1,-1").as_deref(),
+        Some("out/a.json")
+    );
+    assert_eq!(sidecar("a.png 10x10 -1,1 1,-1 --no-sidecar"), None);
+    assert_eq!(sidecar("- 10x10 -1,1 1,-1"), None);
+
+    let path = std::env::temp_dir().join("mandelbrot_test_sidecar.json");
+    let options = RenderOptions {
+        bounds: (40, 30),
+        upper_left: Complex { re: -1.0, im: 0.75 },
+        lower_right: Complex { re: 1.0, im: -0.75 },
+        max_iter: 700,
+        palette: Palette::named("fire").unwrap(),
+        smooth: false,
+        ..RenderOptions::default()
+    };
+    let file = File::create(&path).unwrap();
+    metadata::write_json(file, &metadata::describe(&options)).unwrap();
+    let path = path.to_str().unwrap();
+    match parse_args(&args(&format!("b.png --from-sidecar {} -i 900", path))) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.options.bounds, (40, 30));
+            assert_eq!(cli.options.upper_left, options.upper_left);
+            assert_eq!(cli.options.lower_right, options.lower_right);
+            assert_eq!(cli.options.palette, options.palette);
+            assert!(!cli.options.smooth);
+            assert_eq!(cli.options.max_iter, 900);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let with = |rest: &str| parse_args(&args(&format!("{} --from-sidecar {}", rest, path)));
+    assert!(with("b.png --location whole-set").is_err());
+    assert!(with("animate out -s 4x3 --frames 2 --from 0,0").is_err());
+    // An escape time render's parameters don't make a buddhabrot.
+    assert!(with("buddhabrot b.png").is_err());
+    assert!(parse_args(&args("b.png --from-sidecar /nonexistent.json")).is_err());
+}
+
+#[test]
+fn test_parse_preview() {
+    match parse_args(&args("-s 800x600 -u -2,1.5 -l 2,-1.5 --preview-term")) {
+        Ok(Command::Render(cli)) => assert!(cli.preview),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("a.png 10x10 -1,1 1,-1")) {
+        Ok(Command::Render(cli)) => assert!(!cli.preview),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("-s 800x600 -u -2,1.5 -l 2,-1.5")).is_err());
+    assert!(parse_args(&args(
+        "animate out -s 4x3 --frames 2 --from 0,0 --preview-term"
+    ))
+    .is_err());
+}
+
+#[test]
+fn test_parse_serve() {
+    match parse_args(&args("serve --port 9000 -i 500 --julia -0.8,0.156")) {
+        Ok(Command::Serve(server)) => {
+            assert_eq!(server.port, 9000);
+            assert_eq!(server.frame.options.bounds, (256, 256));
+            assert_eq!(server.frame.options.max_iter, 500);
+            assert!(server.frame.options.julia.is_some());
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("serve")) {
+        Ok(Command::Serve(server)) => assert_eq!(server.port, 8080),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(parse_args(&args("serve -h")), Ok(Command::Help));
+    assert!(parse_args(&args("serve --port 70000")).is_err());
+    assert!(parse_args(&args("serve -s 100x100")).is_err());
+    assert!(parse_args(&args("serve --format jpeg")).is_err());
+    assert!(parse_args(&args("serve --frames 2")).is_err());
+    assert!(parse_args(&args("a.png 10x10 -1,1 1,-1 --port 80")).is_err());
+}
+
+#[test]
+fn test_parse_coordinate() {
+    match parse_args(&args("coordinate --workers a,b:7000 m.png 10x10 -1,1 1,-1")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.workers, ["a", "b:7000"]),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("coordinate m.png 10x10 -1,1 1,-1")).is_err());
+    assert!(parse_args(&args("coordinate --workers , m.png 10x10 -1,1 1,-1")).is_err());
+    assert!(parse_args(&args("coordinate --workers a m.png 10x10 -1,1 1,-1 --aa 2")).is_err());
+    assert!(parse_args(&args("m.png 10x10 -1,1 1,-1 --workers a")).is_err());
+}
+
+#[test]
+fn test_parse_bench() {
+    match parse_args(&args("bench -t 3 --precision f32")) {
+        Ok(Command::Bench(bench)) => {
+            assert_eq!(bench.frame.options.threads, 3);
+            assert_eq!(bench.frame.options.precision, Precision::Single);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(matches!(parse_args(&args("bench")), Ok(Command::Bench(_))));
+    assert!(parse_args(&args("bench -s 10x10")).is_err());
+    assert!(parse_args(&args("bench --aa 2")).is_err());
+}
+
+#[test]
+fn test_parse_pyramid() {
+    match parse_args(&args("pyramid deep.dzi 1000x750 --tile-size 510 -f jpeg")) {
+        Ok(Command::Pyramid(render)) => {
+            assert_eq!(render.frame.output, "deep.dzi");
+            assert_eq!(render.frame.format, Format::Jpeg);
+            assert_eq!(
+                render.pyramid,
+                Pyramid {
+                    bounds: (1000, 750),
+                    tile_size: 510,
+                    overlap: pyramid::DEFAULT_OVERLAP,
+                }
+            );
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(parse_args(&args("pyramid -h")), Ok(Command::Help));
+    assert!(parse_args(&args("pyramid deep.png 1000x750")).is_err());
+    assert!(parse_args(&args("pyramid deep.dzi 1000x750 -f ppm")).is_err());
+    assert!(parse_args(&args("pyramid deep.dzi 1000x750 --tile-size 0")).is_err());
+    assert!(parse_args(&args("pyramid deep.dzi 1000x750 --tile 100")).is_err());
+    assert!(parse_args(&args("pyramid deep.dzi 1000x750 --coloring histogram")).is_err());
+    assert!(parse_args(&args("deep.png 1000x750 --overlap 2")).is_err());
+}
+
+#[test]
+fn test_parse_worker() {
+    match parse_args(&args("worker --listen 0.0.0.0:7878 --coloring distance")) {
+        Ok(Command::Worker(worker)) => {
+            assert_eq!(worker.listen, "0.0.0.0:7878");
+            assert_eq!(worker.frame.options.coloring, Coloring::Distance);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(parse_args(&args("worker -h")), Ok(Command::Help));
+    assert!(parse_args(&args("worker")).is_err());
+    assert!(parse_args(&args("worker --listen :7878 -i 500")).is_err());
+    assert!(parse_args(&args("worker --listen :7878 --port 80")).is_err());
+    assert!(parse_args(&args("a.png 10x10 -1,1 1,-1 --listen :7878")).is_err());
+}
+
+#[test]
+fn test_parse_buddhabrot() {
+    match parse_args(&args(
+        "buddhabrot ghost.png 40x30 -i 1000 --min-iter 20 --seed 3 -p fire",
+    )) {
+        Ok(Command::Buddhabrot(render)) => {
+            let buddhabrot = &render.buddhabrot;
+            assert_eq!(render.frame.output, "ghost.png");
+            assert_eq!(
+                render.frame.options.palette,
+                Palette::named("fire").unwrap()
+            );
+            assert_eq!(buddhabrot.bounds, (40, 30));
+            let view = Fractal::Mandelbrot.view((40, 30));
+            assert_eq!((buddhabrot.upper_left, buddhabrot.lower_right), view);
+            assert_eq!(buddhabrot.samples, 20 * 40 * 30);
+            assert_eq!((buddhabrot.min_iter, buddhabrot.max_iter), (20, 1000));
+            assert_eq!(buddhabrot.seed, 3);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("buddhabrot b.png 10x10 -1,1 1,-1 --samples 99")) {
+        Ok(Command::Buddhabrot(render)) => assert_eq!(render.buddhabrot.samples, 99),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(parse_args(&args("buddhabrot -h")), Ok(Command::Help));
+    assert!(parse_args(&args("buddhabrot b.png 10x10 --min-iter 300")).is_err());
+    assert!(parse_args(&args("buddhabrot b.png 10x10 --samples lots")).is_err());
+    assert!(parse_args(&args("buddhabrot b.exr 10x10")).is_err());
+    assert!(parse_args(&args("buddhabrot b.png 10x10 --julia 0,1")).is_err());
+    assert!(parse_args(&args("buddhabrot b.png 10x10 --frames 3")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --samples 99")).is_err());
+    match parse_args(&args(
+        "buddhabrot n.png 10x10 --nebula 5000,500,50 --exposure 1,1.5,2",
+    )) {
+        Ok(Command::Buddhabrot(render)) => {
+            assert_eq!(render.buddhabrot.max_iter, 5000);
+            assert_eq!(
+                render.nebula,
+                Some(Nebula {
+                    limits: [5000, 500, 50],
+                    exposure: [1.0, 1.5, 2.0]
+                })
+            );
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("buddhabrot n.png 10x10 --nebula 500,50")).is_err());
+    assert!(parse_args(&args("buddhabrot n.png 10x10 --nebula 9,9,9 --min-iter 9")).is_err());
+    assert!(parse_args(&args("buddhabrot n.png 10x10 --nebula 9,9,9 -p fire")).is_err());
+    assert!(parse_args(&args(
+        "buddhabrot n.png 10x10 --nebula 9,9,9 --exposure 1,-1,1"
+    ))
+    .is_err());
+    assert!(parse_args(&args("buddhabrot n.png 10x10 --exposure 1,1,1")).is_err());
+    assert!(parse_args(&args("animate d -s 10x10 --frames 2 --from 0,0 --seed 1")).is_err());
+}
+
+#[test]
+fn test_parse_lyapunov() {
+    match parse_args(&args(
+        "lyapunov zz.png 20x10 --sequence bbbbbbaaaaaa -i 400",
+    )) {
+        Ok(Command::Lyapunov(render)) => {
+            let lyapunov = &render.lyapunov;
+            assert_eq!(lyapunov.sequence.len(), 12);
+            assert_eq!(lyapunov.iterations, 400);
+            let view = lyapunov::view((20, 10));
+            assert_eq!((lyapunov.upper_left, lyapunov.lower_right), view);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("lyapunov l.png 10x10 3.4,4 4,3.4")) {
+        Ok(Command::Lyapunov(render)) => {
+            assert_eq!(
+                render.lyapunov.sequence,
+                lyapunov::parse_sequence("AB").unwrap()
+            );
+            assert_eq!(render.lyapunov.upper_left, Complex::new(3.4, 4.0));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("lyapunov l.png 10x10 --sequence ABC")).is_err());
+    assert!(parse_args(&args("lyapunov l.png 10x10 --coloring histogram")).is_err());
+    assert!(parse_args(&args("lyapunov l.png 10x10 --samples 5")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --sequence AB")).is_err());
+}
+
+#[test]
+fn test_parse_animate() {
+    let line = "animate zoom --size 40x30 --frames 12 --from -0.5,0 --to seahorse-valley -t 2";
+    match parse_args(&args(line)) {
+        Ok(Command::Animate(animation)) => {
+            let paths = match &animation.output {
+                FrameOutput::Files { dir, paths } if dir == "zoom" => paths,
+                other => panic!("unexpected {:?}", other),
+            };
+            assert_eq!(animation.views.len(), 12);
+            assert_eq!(animation.views[0].zoom, 1.0);
+            assert!((animation.views[11].zoom - 200.0).abs() < 1e-9);
+            assert_eq!(paths[0], animation.frame.output);
+            assert_eq!(
+                Path::new(&paths[11]),
+                Path::new("zoom").join("frame_0012.png")
+            );
+            assert_eq!(animation.frame.options.max_iter, 1000);
+            assert_eq!(animation.frame.options.threads, 2);
+            assert!((animation.frame.options.upper_left.im - 2.0).abs() < 1e-9);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let line = "animate out -s 40x30 --frames 3 --from 0,0 --to-zoom 8 -f jpeg -i 80";
+    match parse_args(&args(line)) {
+        Ok(Command::Animate(animation)) => {
+            assert_eq!(animation.frame.format, Format::Jpeg);
+            match &animation.output {
+                FrameOutput::Files { paths, .. } => assert!(paths[2].ends_with("frame_0003.jpg")),
+                other => panic!("unexpected {:?}", other),
+            }
+            assert_eq!(animation.frame.options.max_iter, 80);
+            assert!((animation.views[1].zoom - 8f64.sqrt()).abs() < 1e-9);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let line = "animate --video zoom.mp4 --fps 24 -s 40x30 --frames 3 --from 0,0 --to-zoom 8";
+    match parse_args(&args(line)) {
+        Ok(Command::Animate(animation)) => {
+            assert_eq!(
+                animation.output,
+                FrameOutput::Video {
+                    path: "zoom.mp4".to_string(),
+                    fps: 24
+                }
+            );
+            assert_eq!(animation.frame.format, Format::Png);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let line = "animate --video zoom.GIF -s 40x30 --frames 3 --from 0,0 --loop 2 --colors 64";
+    match parse_args(&args(line)) {
+        Ok(Command::Animate(animation)) => assert_eq!(
+            animation.output,
+            FrameOutput::Gif {
+                path: "zoom.GIF".to_string(),
+                fps: 30,
+                loops: 2,
+                colors: 64
+            }
+        ),
+        other => panic!("unexpected {:?}", other),
+    }
+    let gif = "animate --video zoom.gif -s 40x30 --frames 3 --from 0,0";
+    assert!(parse_args(&args(&format!("{} --colors 1", gif))).is_err());
+    assert!(parse_args(&args(&format!("{} --loop -1", gif))).is_err());
+    let video = "animate --video zoom.mp4 -s 40x30 --frames 3 --from 0,0";
+    assert!(parse_args(&args(&format!("{} --loop 1", video))).is_err());
+    assert!(parse_args(&args(video)).is_ok());
+    assert!(parse_args(&args(&format!("{} --fps 0", video))).is_err());
+    assert!(parse_args(&args(&format!("{} --depth 16", video))).is_err());
+    assert!(parse_args(&args(&format!("{} -f jpeg", video))).is_err());
+    assert!(parse_args(&args(
+        "animate out --video z.mp4 -s 4x3 --frames 2 --from 0,0"
+    ))
+    .is_err());
+    assert!(parse_args(&args("animate out -s 4x3 --frames 2 --from 0,0 --fps 24")).is_err());
+    let morph = "animate out -s 40x30 --frames 3 --julia-path";
+    match parse_args(&args(&format!("{} -0.8,0.156/0.285,0.01", morph))) {
+        Ok(Command::Animate(animation)) => {
+            assert_eq!(animation.julia.len(), 3);
+            assert_eq!(
+                animation.julia[0],
+                Complex {
+                    re: -0.8,
+                    im: 0.156
+                }
+            );
+            assert_eq!(animation.frame.options.julia, Some(animation.julia[0]));
+            assert!(animation
+                .views
+                .iter()
+                .all(|view| view == &animation.views[0]));
+            assert_eq!(animation.views[0].zoom, 1.0);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args(&format!(
+        "{} cardioid --from 0.1,0 --from-zoom 2",
+        morph
+    ))) {
+        Ok(Command::Animate(animation)) => {
+            assert_eq!(animation.julia[0], Complex { re: 0.25, im: 0.0 });
+            assert_eq!(animation.views[2].zoom, 2.0);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args(&format!("{} cardioid --to 1,0", morph))).is_err());
+    assert!(parse_args(&args(&format!("{} cardioid --julia 0,0", morph))).is_err());
+    assert!(parse_args(&args(&format!("{} 0,0/spiral", morph))).is_err());
+    assert!(parse_args(&args("a.png -s 4x3 -u -1,1 -l 1,-1 --julia-path cardioid")).is_err());
+    assert_eq!(parse_args(&args("animate --help")), Ok(Command::Help));
+    assert!(parse_args(&args("animate")).is_err());
+    assert!(parse_args(&args("animate out -s 4x3 --from 0,0")).is_err());
+    assert!(parse_args(&args("animate out -s 4x3 --frames 2")).is_err());
+    assert!(parse_args(&args("animate out -s 4x3 --frames 2 --from atlantis")).is_err());
+    assert!(parse_args(&args(
+        "animate out -s 4x3 --frames 2 --from 0,0 --to-zoom 0"
+    ))
+    .is_err());
+    assert!(parse_args(&args("animate out -s 4x3 --frames 2 --from 0,0 -u 1,1")).is_err());
+    assert!(parse_args(&args("a.png 10x10 -1,1 1,-1 --frames 2")).is_err());
+}
+
+#[test]
+fn test_parse_gradient() {
+    let path = std::env::temp_dir().join("mandelbrot_test_gradient.csv");
+    std::fs::write(&path, "0,0,0,0\n1,255,0,0\n").unwrap();
+    let path = path.to_str().unwrap();
+    match parse_args(&args(&format!(
+        "a.png 10x10 -1,1 1,-1 --gradient {} --color-space linear",
+        path
+    ))) {
+        Ok(Command::Render(cli)) => {
+            let palette = &cli.options.palette;
+            assert_eq!(palette.space(), ColorSpace::Linear);
+            assert_eq!(palette.at(1.0), [255, 0, 0]);
+            assert_eq!(palette.at(0.5), [188, 0, 0]);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let both = format!("a.png 10x10 -1,1 1,-1 --gradient {} -p fire", path);
+    assert!(parse_args(&args(&both)).is_err());
+    assert!(parse_args(&args("a.png 10x10 -1,1 1,-1 --gradient /nonexistent.csv")).is_err());
+    assert!(parse_args(&args("a.png 10x10 -1,1 1,-1 --color-space cmyk")).is_err());
+    match parse_args(&args("a.png 10x10 -1,1 1,-1 -p fire --color-space lch")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.options.palette.space(), ColorSpace::Lch),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_parse_palette_from_image() {
+    let path = std::env::temp_dir().join("mandelbrot_test_palette.png");
+    let pixels = [[200, 40, 40], [200, 40, 40], [20, 20, 90]].concat();
+    mandelbrot::write_image(path.to_str().unwrap(), &pixels, (3, 1)).unwrap();
+    let path = path.to_str().unwrap();
+    match parse_args(&args(&format!(
+        "a.png 10x10 -1,1 1,-1 --palette-from-image {}",
+        path
+    ))) {
+        Ok(Command::Render(cli)) => {
+            let palette = &cli.options.palette;
+            assert_eq!(palette.at(0.0), [20, 20, 90]);
+            assert_eq!(palette.at(1.0), [200, 40, 40]);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let both = format!("a.png 10x10 --palette-from-image {} -p fire", path);
+    assert!(parse_args(&args(&both)).is_err());
+    let gradient = format!(
+        "a.png 10x10 --palette-from-image {} --gradient {}",
+        path, path
+    );
+    assert!(parse_args(&args(&gradient)).is_err());
+}
+
+#[test]
+fn test_parse_scale() {
+    match parse_args(&args(
+        "a.png 10x10 -1,1 1,-1 --scale power=0.5 --palette-cycle 64",
+    )) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.options.transfer, Transfer::Power(0.5));
+            assert_eq!(cli.options.palette_cycle, Some(64.0));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("a.png 10x10 -1,1 1,-1")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.options.transfer, Transfer::Linear);
+            assert_eq!(cli.options.palette_cycle, None);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    for bad in [
+        "--scale exp",
+        "--scale power=-1",
+        "--palette-cycle 0",
+        "--palette-cycle inf",
+        "--scale log --coloring histogram",
+        "--palette-cycle 10 --coloring distance",
+        "--scale sqrt --newton 1,0,0,-1",
+    ] {
+        let line = format!("a.png 10x10 -1,1 1,-1 {}", bad);
+        assert!(parse_args(&args(&line)).is_err(), "{}", bad);
+    }
+    assert!(parse_args(&args("buddhabrot a.png 10x10 --scale log")).is_err());
+}
+
+#[test]
+fn test_parse_adjustments() {
+    match parse_args(&args(
+        "a.png 10x10 -1,1 1,-1 --exposure -1.5 --contrast 1.2 --brightness 0.1 --gamma 2.2",
+    )) {
+        Ok(Command::Render(cli)) => assert_eq!(
+            cli.options.adjustments,
+            Adjustments {
+                exposure: -1.5,
+                contrast: 1.2,
+                brightness: 0.1,
+                gamma: 2.2,
+            }
+        ),
+        other => panic!("unexpected {:?}", other),
+    }
+    for bad in [
+        "--gamma 0",
+        "--gamma x",
+        "--contrast -1",
+        "--brightness 2",
+        "--exposure inf",
+        "--exposure 1,1,1",
+        "--gamma 2 --depth 16",
+        "--contrast 2 -f exr",
+    ] {
+        let line = format!("a.png 10x10 -1,1 1,-1 {}", bad);
+        assert!(parse_args(&args(&line)).is_err(), "{}", bad);
+    }
+    // A single exposure is in stops for buddhabrot too, with --nebula or not.
+    for line in [
+        "buddhabrot n.png 10x10 --exposure 1",
+        "buddhabrot n.png 10x10 --nebula 50,20,10 --exposure 1",
+    ] {
+        match parse_args(&args(line)) {
+            Ok(Command::Buddhabrot(render)) => {
+                assert_eq!(render.frame.options.adjustments.exposure, 1.0)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_parse_dither() {
+    match parse_args(&args("a.png 10x10 -1,1 1,-1 --dither blue-noise")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.options.dither, Some(Dither::BlueNoise)),
+        other => panic!("unexpected {:?}", other),
+    }
+    for bad in [
+        "a.png 10x10 -1,1 1,-1 --dither floyd",
+        "a.png 10x10 -1,1 1,-1 --dither bayer --depth 16",
+        "buddhabrot a.png 10x10 --dither bayer",
+    ] {
+        assert!(parse_args(&args(bad)).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_parse_contours() {
+    match parse_args(&args("lines.svg 10x10 -1,1 1,-1 --contours 40")) {
+        Ok(Command::Render(cli)) => assert_eq!((cli.format, cli.contours), (Format::Svg, 40)),
+        other => panic!("unexpected {:?}", other),
+    }
+    for bad in [
+        "lines.svg 10x10 --contours 0",
+        "lines.png 10x10 --contours 8",
+        "lines.svg 10x10 --aa 2",
+        "lines.svg 10x10 --newton 1,0,-1",
+        "lines.svg 10x10 --gamma 2",
+        "buddhabrot b.svg 10x10",
+        "animate --contours 8",
+    ] {
+        assert!(parse_args(&args(bad)).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_parse_mesh() {
+    match parse_args(&args("print.stl 100x80 -1,1 1,-1 --height-scale 25")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.format, Format::Stl);
+            assert_eq!((cli.base_thickness, cli.height_scale), (2.0, 25.0));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    for bad in [
+        "print.obj 10x10 --base-thickness 0",
+        "print.obj 10x10 --height-scale x",
+        "print.png 10x10 --height-scale 2",
+        "print.stl 10x1",
+        "print.stl 10x10 --aa 2",
+        "print.stl 10x10 --newton 1,0,-1",
+        "lyapunov l.obj 10x10",
+    ] {
+        assert!(parse_args(&args(bad)).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_parse_relief_maps() {
+    match parse_args(&args(
+        "a.png 100x80 --heightmap h.png --normal-map n.png --height-scale 4",
+    )) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.heightmap.as_deref(), Some("h.png"));
+            assert_eq!(cli.normal_map.as_deref(), Some("n.png"));
+            assert_eq!(cli.height_scale, 4.0);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    for bad in [
+        "a.png 10x10 --heightmap h.png --height-scale 4",
+        "a.png 10x10 --normal-map n.png --base-thickness 4",
+        "a.png 10x10 --normal-map n.png --newton 1,0,-1",
+        "a.png 10x10 --heightmap h.png --tile 4",
+        "a.png 10x10 --heightmap h.png --progressive",
+        "animate --normal-map n.png",
+    ] {
+        assert!(parse_args(&args(bad)).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_parse_progressive() {
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --progressive")) {
+        Ok(Command::Render(cli)) => assert!(cli.progressive),
+        other => panic!("unexpected {:?}", other),
+    }
+    for bad in [
+        "--format jpeg",
+        "--tile 4",
+        "--stats s.json",
+        "--checkpoint c.mbcp",
+    ] {
+        let line = format!("mandel.png 10x10 -1,1 1,-1 --progressive {}", bad);
+        assert!(parse_args(&args(&line)).is_err(), "{}", bad);
+    }
+    assert!(parse_args(&args("animate --progressive")).is_err());
+}
+
+#[test]
+fn test_parse_hdr() {
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --hdr 1000")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.hdr, Some(1000.0)),
+        other => panic!("unexpected {:?}", other),
+    }
+    for bad in [
+        "--hdr 100",
+        "--hdr 20000",
+        "--hdr 1000 --format jpeg",
+        "--hdr 1000 --aa 2",
+        "--hdr 1000 --shade 45,45",
+        "--hdr 1000 --gamma 2",
+        "--hdr 1000 --transparent-interior",
+    ] {
+        let line = format!("mandel.png 10x10 -1,1 1,-1 {}", bad);
+        assert!(parse_args(&args(&line)).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_parse_transparent_interior() {
+    match parse_args(&args("a.png 10x10 -1,1 1,-1 --transparent-interior")) {
+        Ok(Command::Render(cli)) => assert!(cli.transparent_interior),
+        other => panic!("unexpected {:?}", other),
+    }
+    for bad in [
+        "a.jpg 10x10 -1,1 1,-1 --transparent-interior",
+        "a.png 10x10 -1,1 1,-1 --transparent-interior --aa 2",
+        "a.png 10x10 -1,1 1,-1 --transparent-interior --tile 4",
+        "a.png 10x10 -1,1 1,-1 --transparent-interior --depth 16",
+        "lyapunov a.png 10x10 --transparent-interior",
+        "serve --transparent-interior",
+    ] {
+        assert!(parse_args(&args(bad)).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_parse_rerender() {
+    let dir = std::env::temp_dir();
+    let input = dir.join("mandelbrot_test_rerender.png");
+    let input = input.to_str().unwrap();
+    let original = match parse_args(&args(&format!(
+        "{} 40x30 -2,1.2 0.6,-1.2 -i 500 -p fire --color-space linear --no-smooth --scale cbrt \
+         --palette-cycle 48 --gamma 1.8 --dither bayer",
+        input
+    ))) {
+        Ok(Command::Render(cli)) => cli,
+        other => panic!("unexpected {:?}", other),
+    };
+    let text = metadata::describe(&original.options);
+    let file = std::io::BufWriter::new(File::create(input).unwrap());
+    mandelbrot::encode_image(file, &[0; 3 * 40 * 30], (40, 30), &text).unwrap();
+
+    match parse_args(&args(&format!("rerender {} --size 400x300 -t 2", input))) {
+        Ok(Command::Render(cli)) => {
+            let expected = dir.join("mandelbrot_test_rerender-400x300.png");
+            assert_eq!(cli.output, expected.to_str().unwrap());
+            assert_eq!(cli.options.bounds, (400, 300));
+            assert_eq!(cli.options.upper_left, original.options.upper_left);
+            assert_eq!(cli.options.lower_right, original.options.lower_right);
+            assert_eq!(cli.options.max_iter, 500);
+            assert_eq!(cli.options.palette, original.options.palette);
+            assert!(!cli.options.smooth);
+            assert_eq!(cli.options.transfer, Transfer::Cbrt);
+            assert_eq!(cli.options.palette_cycle, Some(48.0));
+            assert_eq!(cli.options.adjustments.gamma, 1.8);
+            assert_eq!(cli.options.dither, Some(Dither::Bayer));
+            assert_eq!(cli.options.threads, 2);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args(&format!("rerender {} big.png -i 2000", input))) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(
+                (cli.output.as_str(), cli.options.bounds),
+                ("big.png", (40, 30))
+            );
+            assert_eq!(cli.options.max_iter, 2000);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // Every option stored comes back, for the same render.
+    let gradient = dir.join("mandelbrot_test_rerender.csv");
+    std::fs::write(&gradient, "0,10,20,30\n0.3,200,100,0\n1,255,255,255\n").unwrap();
+    let original = match parse_args(&args(&format!(
+        "{} 40x30 -1,1 1,-0.5 --stretch --flip --gradient {} --precision dd -a 2 \
+         --adaptive 3 --no-bulb-check",
+        input,
+        gradient.to_str().unwrap()
+    ))) {
+        Ok(Command::Render(cli)) => cli,
+        other => panic!("unexpected {:?}", other),
+    };
+    let mut text = metadata::describe(&original.options);
+    let file = std::io::BufWriter::new(File::create(input).unwrap());
+    mandelbrot::encode_image(file, &[0; 3 * 40 * 30], (40, 30), &text).unwrap();
+    match parse_args(&args(&format!("rerender {}", input))) {
+        Ok(Command::Render(cli)) => {
+            // The corners are stored to as many bits as the finest of them.
+            assert_eq!(
+                cli.options.exact_corners(),
+                original.options.exact_corners()
+            );
+            let exact_corners = original.options.exact_corners.clone();
+            assert_eq!(
+                RenderOptions {
+                    exact_corners,
+                    ..cli.options
+                },
+                original.options
+            );
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // A buddhabrot is rendered as one again.
+    let original = match parse_args(&args(&format!(
+        "buddhabrot {} 40x30 --seed 7 --nebula 20,50,100 --exposure 1,2,3",
+        input
+    ))) {
+        Ok(Command::Buddhabrot(render)) => render,
+        other => panic!("unexpected {:?}", other),
+    };
+    let options = &original.frame.options;
+    let described =
+        metadata::describe_buddhabrot(options, &original.buddhabrot, original.nebula.as_ref());
+    let file = std::io::BufWriter::new(File::create(input).unwrap());
+    mandelbrot::encode_image(file, &[0; 3 * 40 * 30], (40, 30), &described).unwrap();
+    match parse_args(&args(&format!("rerender {} -o b.png", input))) {
+        Ok(Command::Buddhabrot(render)) => {
+            assert_eq!(render.buddhabrot, original.buddhabrot);
+            assert_eq!(render.nebula, original.nebula);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args(&format!("rerender {} -o b.png --julia 0,0", input))).is_err());
+    assert!(parse_args(&args(&format!("rerender {} -o b.png --seed 3", input))).is_ok());
+
+    text.push(("Sparkle", "3".to_string()));
+    let file = std::io::BufWriter::new(File::create(input).unwrap());
+    mandelbrot::encode_image(file, &[0; 3 * 40 * 30], (40, 30), &text).unwrap();
+    assert_eq!(
+        parse_args(&args(&format!("rerender {}", input))),
+        Err(format!("{}: 'Sparkle' can't be restored", input))
+    );
+
+    assert_eq!(parse_args(&args("rerender --help")), Ok(Command::Help));
+    assert!(parse_args(&args("rerender")).is_err());
+    assert!(parse_args(&args(&format!("rerender {} {}", input, input))).is_err());
+    assert!(parse_args(&args("rerender /nonexistent/mandel.png")).is_err());
+}
+
+#[test]
+fn test_session_command_line() {
+    let args = ["explore", "-s", "64x48", "-i", "300", "--palette", "fire"].map(String::from);
+    let Ok(Command::Explore(session)) = parse_args(&args) else {
+        panic!("explore didn't parse");
+    };
+    let mut session = *session;
+    session.view.zoom = 64.0;
+    session.view.center.re = Fixed::from_f64(-0.75, 64);
+    let line = session.command_line("view.png").unwrap();
+    assert!(line.starts_with("mandelbrot --output view.png --size 64x48 --upper-left -0.79"));
+    let args: Vec<String> = line.split(' ').skip(1).map(String::from).collect();
+    let Ok(Command::Render(cli)) = parse_args(&args) else {
+        panic!("{} didn't parse", line);
+    };
+    assert_eq!(*cli, session.frame("view.png").unwrap());
+
+    // The Burning Ship starts upside down, and stays so.
+    let args = ["repl", "-s", "40x20", "--fractal", "burning-ship"].map(String::from);
+    let Ok(Command::Repl(session)) = parse_args(&args) else {
+        panic!("repl didn't parse");
+    };
+    assert!(session.view.flipped && session.view.zoom > 0.0);
+    let cli = session.frame("ship.png").unwrap();
+    let view = Fractal::BurningShip.view((40, 20));
+    let close = |a: Complex<f64>, b: Complex<f64>| (a - b).norm() < 1e-12;
+    assert!(
+        close(cli.options.upper_left, view.0),
+        "{}",
+        cli.options.upper_left
+    );
+    assert!(
+        close(cli.options.lower_right, view.1),
+        "{}",
+        cli.options.lower_right
+    );
+    let line = session.command_line("ship.png").unwrap();
+    assert!(line.contains(" --flip"), "{}", line);
+    assert_eq!(shell_quote("z^2+c"), "'z^2+c'");
+    assert_eq!(shell_quote("it's"), r"'it'\''s'");
+}
+
+#[test]
+fn test_parse_args_errors() {
+    assert_eq!(parse_args(&args("-h")), Ok(Command::Help));
+    assert!(parse_args(&args("mandel.png 1000x750 -1.20,0.35")).is_err());
+    assert!(parse_args(&args("mandel.png 1000x750 -1.20,0.35 -1,0.20 -o x.png")).is_err());
+    assert!(parse_args(&args("mandel.png 1000x750 -1.20,0.35 -1,0.20 --bogus")).is_err());
+    assert!(parse_args(&args("mandel.png 1000x750 -1.20,0.35 -1,0.20 --threads")).is_err());
+    assert!(parse_args(&args("mandel.png 1000x750 -1.20,0.35 -1,0.20 -t 0")).is_err());
+    for size in ["0x5", "5x0", "0x0"] {
+        assert_eq!(
+            parse_args(&args(&format!("mandel.png {} -1,1 1,-1", size))),
+            Err(format!("--size must be at least 1x1, not {}", size))
+        );
+    }
+    assert!(parse_args(&args("mandel.png 1000x750 -1.20,0.35 -1,0.20 -i 0")).is_err());
+    assert!(parse_args(&args("mandel.png 1000x750 -1.20,0.35 -1,0.20 -p plaid")).is_err());
+    assert!(parse_args(&args("mandel.png 1000x750 -1.20,0.35 -1,0.20 --aa 0")).is_err());
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --adaptive 5")).is_err());
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --depth 12")).is_err());
+    assert!(parse_args(&args("mandel.jpg 10x10 -1,1 1,-1 --depth 16")).is_err());
+    assert!(parse_args(&args("mandel.ppm 10x10 -1,1 1,-1 --depth 16")).is_err());
+    assert!(parse_args(&args("mandel.exr 10x10 -1,1 1,-1 --aa 2")).is_err());
+    assert!(parse_args(&args("mandel.jpg 10x10 -1,1 1,-1 --quality 0")).is_err());
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --format gif")).is_err());
+    assert_eq!(
+        parse_args(&args("mandel.gif 10x10 -1,1 1,-1")),
+        Err("no format is written to .gif files; give --format or another extension".to_string())
+    );
+    assert!(parse_args(&args("mandel.gif 10x10 -1,1 1,-1 --format png")).is_ok());
+    assert!(parse_args(&args("out.d/mandel 10x10 -1,1 1,-1")).is_ok());
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --coloring rainbow")).is_err());
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --coloring distance")).is_ok());
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --coloring grayscale")).is_ok());
+    assert!(parse_args(&args(
+        "a.png 10x10 -1,1 1,-1 --coloring distance --precision 99"
+    ))
+    .is_err());
+    assert!(parse_args(&args(
+        "a.png 10x10 -1,1 1,-1 --coloring stripe-average --precision perturb"
+    ))
+    .is_err());
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --interior solid")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --fractal burning-ship --precision 99")).is_err());
+    assert!(parse_args(&args(
+        "a.png 10x10 --fractal burning-ship --coloring distance"
+    ))
+    .is_err());
+    assert!(parse_args(&args("a.png 10x10 --fractal mandelbox")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --fractal burning-ship --exponent 3")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --exponent 1")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --exponent three")).is_err());
+    let fractal = |line| match parse_args(&args(line)) {
+        Ok(Command::Render(cli)) => cli.options.fractal,
+        other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!(
+        fractal("a.png 10x10 --fractal multibrot"),
+        Fractal::Multibrot(3.0)
+    );
+    assert_eq!(fractal("a.png 10x10 --fractal tricorn"), Fractal::Tricorn);
+    assert_eq!(
+        fractal("a.png 10x10 --fractal phoenix"),
+        Fractal::Phoenix(Complex::new(-0.5, 0.0))
+    );
+    assert_eq!(
+        fractal("a.png 10x10 --phoenix-p 0.2,-0.1"),
+        Fractal::Phoenix(Complex::new(0.2, -0.1))
+    );
+    assert_eq!(
+        fractal("a.png 10x10 --fractal phoenix --phoenix-p 0,0"),
+        Fractal::Mandelbrot
+    );
+    assert!(parse_args(&args("a.png 10x10 --fractal tricorn --phoenix-p 0.5,0")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --phoenix-p 0.5")).is_err());
+    match parse_args(&args("a.png 10x10 --formula z^3+sin(c)")) {
+        Ok(Command::Render(cli)) => {
+            let Fractal::Formula(formula) = &cli.options.fractal else {
+                panic!("unexpected {:?}", cli.options.fractal);
+            };
+            assert_eq!(formula.text(), "z^3+sin(c)");
+            assert_eq!(formula.degree(), 3.0);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("a.png 10x10 --fractal formula --formula z^2+c")).is_ok());
+    assert!(parse_args(&args("a.png 10x10 --fractal formula")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --fractal tricorn --formula z^2+c")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --formula z^2+x")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --formula z^2+c --exponent 3")).is_err());
+    match parse_args(&args("a.png 10x10 --bailout 1e3 --bailout-test manhattan")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.options.bailout.radius, 1000.0);
+            assert_eq!(cli.options.bailout.test, bailout::Test::Manhattan);
+            assert_eq!(cli.options.resolved_precision(), Precision::Double);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(
+        parse_args(&args("a.png 10x10 --bailout 1")),
+        Err("--bailout must be at least 2".to_string())
+    );
+    for radius in ["inf", "-inf", "NaN", "1e400"] {
+        assert!(
+            parse_args(&args(&format!("a.png 10x10 --bailout {}", radius)))
+                .is_err_and(|error| error.starts_with("--bailout must be a finite radius"))
+        );
+    }
+    assert!(parse_args(&args("a.png 10x10 --bailout-test euclid")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --bailout 10 --precision 90")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --bailout 10 --newton 1,0,-1")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --fractal phoenix --interior multiplier")).is_err());
+    assert_eq!(
+        fractal("a.png 10x10 --exponent 4.5"),
+        Fractal::Multibrot(4.5)
+    );
+    assert_eq!(
+        fractal("a.png 10x10 --fractal multibrot --exponent 2"),
+        Fractal::Mandelbrot
+    );
+    assert!(parse_args(&args("a.png 10x10 -u -1,1")).is_err());
+    // Of the four ways round the corners can be given, only one is upright.
+    for (corners, error) in [
+        ("-2,1 1,-1", None),
+        (
+            "-2,-1 1,1",
+            Some(
+                "the upper left corner -2,-1 is below the lower right corner 1,1; give --flip \
+                 for a view upside down",
+            ),
+        ),
+        (
+            "1,1 -2,-1",
+            Some("the upper left corner 1,1 is right of the lower right corner -2,-1"),
+        ),
+        (
+            "1,-1 -2,1",
+            Some(
+                "the upper left corner 1,-1 is below and right of the lower right corner -2,1; \
+                 are they swapped?",
+            ),
+        ),
+        (
+            "-2,1 1,1",
+            Some("the corners -2,1 and 1,1 coincide in height, leaving nothing to draw"),
+        ),
+        (
+            "1,1 1,-1",
+            Some("the corners 1,1 and 1,-1 coincide in width, leaving nothing to draw"),
+        ),
+        (
+            "1,1 1,1",
+            Some("the corners 1,1 and 1,1 coincide everywhere, leaving nothing to draw"),
+        ),
+    ] {
+        let line = format!("a.png 10x10 {}", corners);
+        let result = parse_args(&args(&line)).err();
+        assert_eq!(result.as_deref(), error, "{}", line);
+    }
+    match parse_args(&args("a.png 20x10 -2,1 2,-1 --flip")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.options.upper_left, Complex::new(-2.0, -1.0));
+            assert_eq!(cli.options.lower_right, Complex::new(2.0, 1.0));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("a.png 20x10 --newton 1,0,0,-1")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.options.newton, Some(Polynomial::default()));
+            assert_eq!(cli.options.upper_left, Complex::new(-4.0, 2.0));
+            assert_eq!(cli.options.lower_right, Complex::new(4.0, -2.0));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("a.png 10x10 --newton 5")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --newton 1,x")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --newton 1,0,-1 --julia 0,1")).is_err());
+    assert!(parse_args(&args("a.png 10x10 --newton 1,0,-1 --coloring histogram")).is_err());
+    assert!(parse_args(&args("a.exr 10x10 --newton 1,0,-1")).is_err());
+    match parse_args(&args("a.png 40x20 --fractal burning-ship")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!(cli.options.fractal, Fractal::BurningShip);
+            let view = Fractal::BurningShip.view((40, 20));
+            assert_eq!((cli.options.upper_left, cli.options.lower_right), view);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --shade 135,95")).is_err());
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --shade up")).is_err());
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --shade 135,45")) {
+        Ok(Command::Render(cli)) => assert_eq!(
+            cli.options.shading,
+            Some(Light {
+                azimuth: 135.0,
+                elevation: 45.0
+            })
+        ),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --interior multiplier")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.options.interior, Interior::Multiplier),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("mandel.png 10x10 -1,1 1,-1 --coloring histogram")) {
+        Ok(Command::Render(cli)) => assert_eq!(cli.options.coloring, Coloring::Histogram),
+        other => panic!("unexpected {:?}", other),
+    }
+    match parse_args(&args("-s 80x60 -u -2,1.5 -l 2,-1.5 -f sixel")) {
+        Ok(Command::Render(cli)) => {
+            assert_eq!((cli.output, cli.format), ("-".into(), Format::Sixel))
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --depth 16 --aa 2")).is_err());
+    assert!(parse_args(&args("mandel.png 10x10 -1,1 1,-1 --aa 2 --adaptive -1")).is_err());
+}
+
+/// Parses comma separated polynomial coefficients, highest power first.
+fn parse_polynomial(s: &str) -> Option<Polynomial> {
+    let coefficients = s
+        .split(',')
+        .map(|c| c.trim().parse().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    Polynomial::new(&coefficients)
+}
+
+/// Parses three comma separated values.
+fn parse_triple<T: FromStr>(s: &str) -> Option<[T; 3]> {
+    let mut values = s.split(',').map(|value| value.trim().parse().ok());
+    let triple = [values.next()??, values.next()??, values.next()??];
+    values.next().is_none().then_some(triple)
+}
+
+fn parse_complex(s: &str) -> Option<Complex<f64>> {
+    parse_pair::<f64>(s, ',').map(|(re, im)| Complex { re, im })
+}
+
+/// Like `parse_complex`, but keeping every digit of both coordinates.
+pub fn parse_exact_complex(s: &str) -> Option<Complex<Fixed>> {
+    parse_pair::<Fixed>(s, ',').map(|(re, im)| Complex { re, im })
+}
+
+#[test]
+fn test_parse_exact_complex() {
+    let z = parse_exact_complex("-0.7436438870371587047521915061,1e-3").unwrap();
+    assert_eq!(z.re.to_f64(), -0.7436438870371587);
+    assert_eq!(z.im.to_f64(), 0.001);
+    assert_eq!(parse_exact_complex("1,"), None);
+}
+
+#[test]
+fn test_parse_complex() {
+    assert_eq!(
+        parse_complex("1.25,-0.0625"),
+        Some(Complex {
+            re: 1.25,
+            im: -0.0625
+        })
+    );
+    assert_eq!(parse_complex(",-0.0625"), None);
+}
+
+fn parse_pair<T: FromStr>(s: &str, seperator: char) -> Option<(T, T)> {
+    match s.find(seperator) {
+        None => None,
+        Some(index) => match (T::from_str(&s[..index]), T::from_str(&s[index + 1..])) {
+            (Ok(a), Ok(b)) => Some((a, b)),
+            _ => None,
+        },
+    }
+}
+
+#[test]
+fn test_parse_pair() {
+    assert_eq!(parse_pair::<i32>("", ','), None);
+    assert_eq!(parse_pair::<i32>("10,", ','), None);
+    assert_eq!(parse_pair::<i32>(",10", ','), None);
+    assert_eq!(parse_pair::<i32>("10,20", ','), Some((10, 20)));
+    assert_eq!(parse_pair::<i32>("10,20xy", ','), None);
+    assert_eq!(parse_pair::<f64>("0.5x", 'x'), None);
+    assert_eq!(parse_pair::<f64>("0.5x1.5", 'x'), Some((0.5, 1.5)));
+}
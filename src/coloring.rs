@@ -0,0 +1,512 @@
+//! How escape times are spread over the palette. Escape time coloring
+//! stretches the palette over `0..max_iter` iterations, so at deep zooms,
+//! where every point takes thousands of iterations, an image only shows a
+//! sliver of it. Histogram coloring instead places each point by the share
+//! of the image's escaped points that escape sooner, which uses the whole
+//! palette evenly at any depth. Distance coloring places points by their
+//! estimated distance to the set instead; see `distance`. Stripe average
+//! coloring places them by the stripe average of their orbits; see
+//! `stripe`. Grayscale coloring leaves the palette out, and shades points
+//! from black to white by their escape times as `gray16` does.
+//!
+//! Escape time coloring can spread escape values over the palette by a
+//! `Transfer` other than a straight line, and can wrap around the palette
+//! every so many iterations instead of stretching it over `max_iter`,
+//! which keeps the colors changing at deep zooms with huge iteration
+//! counts.
+//!
+//! Each image is colored by a `Colorizer`, built for it by
+//! `Renderer::colorizer`: a `Scale` on the palette, or `Grayscale`, unless
+//! the renderer was given one of its own.
+
+use crate::{Escape, Palette, Polynomial, RenderOptions};
+use std::fmt;
+
+/// The ways to map escape times onto the palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coloring {
+    EscapeTime,
+    Histogram,
+    /// Rendered in `f64`, since the derivative is.
+    Distance,
+    /// Rendered in `f64`, since the stripes are.
+    StripeAverage,
+    /// Escape times as shades of gray, without the palette.
+    Grayscale,
+}
+
+/// Names accepted by `Coloring::named`, in the order they are listed in
+/// help.
+pub const NAMES: &[&str] = &[
+    "escape-time",
+    "histogram",
+    "distance",
+    "stripe-average",
+    "grayscale",
+];
+
+/// The palette spans distances from a pixel to `2^DISTANCE_OCTAVES`
+/// pixels; points closer to the set than a pixel take its first color.
+const DISTANCE_OCTAVES: f64 = 12.0;
+
+impl Coloring {
+    /// Looks a coloring up by name, as given to `--coloring`.
+    pub fn named(name: &str) -> Option<Coloring> {
+        match name {
+            "escape-time" => Some(Coloring::EscapeTime),
+            "histogram" => Some(Coloring::Histogram),
+            "distance" => Some(Coloring::Distance),
+            "stripe-average" => Some(Coloring::StripeAverage),
+            "grayscale" => Some(Coloring::Grayscale),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Coloring::EscapeTime => "escape-time",
+            Coloring::Histogram => "histogram",
+            Coloring::Distance => "distance",
+            Coloring::StripeAverage => "stripe-average",
+            Coloring::Grayscale => "grayscale",
+        }
+    }
+
+    /// Whether escaping orbits are followed again for statistics, in
+    /// `f64`; see `orbit`.
+    pub fn follows_orbits(self) -> bool {
+        matches!(self, Coloring::Distance | Coloring::StripeAverage)
+    }
+}
+
+/// Turns how a point escaped into its color, three bytes like a pixel of
+/// `Renderer::render`. Give a renderer one of its own with
+/// `Renderer::with_colorizer`; colorizers that spread their colors by the
+/// whole image, as histograms do, are best built from its escapes, as
+/// `Scale::new` is.
+pub trait Colorizer: Send + Sync {
+    /// The color of a point, from how its orbit escaped, or `None` for a
+    /// point in the set.
+    fn color(&self, escape: Option<Escape>) -> [u8; 3];
+
+    /// The color like `color`, each channel from 0 to 255 before it is
+    /// rounded, for dithering. Unless implemented, the rounded color.
+    fn color_exact(&self, escape: Option<Escape>) -> [f64; 3] {
+        self.color(escape).map(f64::from)
+    }
+}
+
+/// How escape time coloring spreads escape values over the palette, from 0
+/// at 0 to 1 at the top of their range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+    Linear,
+    /// `ln(1 + x) / ln(1 + top)`, which gives the first iterations, where
+    /// most of an image escapes, most of the palette.
+    Log,
+    Sqrt,
+    Cbrt,
+    /// `(x / top)^p` for a `p` above 0; under 1 favors the first
+    /// iterations like the roots, over 1 the last.
+    Power(f64),
+}
+
+impl Transfer {
+    /// Parses a `--scale` value: `linear`, `log`, `sqrt`, `cbrt` or
+    /// `power=P`.
+    pub fn parse(text: &str) -> Option<Transfer> {
+        match text {
+            "linear" => Some(Transfer::Linear),
+            "log" => Some(Transfer::Log),
+            "sqrt" => Some(Transfer::Sqrt),
+            "cbrt" => Some(Transfer::Cbrt),
+            _ => {
+                let p = text.strip_prefix("power=")?.parse::<f64>().ok()?;
+                (p > 0.0 && p.is_finite()).then_some(Transfer::Power(p))
+            }
+        }
+    }
+
+    /// Where `value` falls between 0 and `top`.
+    pub fn apply(self, value: f64, top: f64) -> f64 {
+        let value = value.max(0.0);
+        match self {
+            Transfer::Linear => value / top,
+            Transfer::Log => value.ln_1p() / top.ln_1p(),
+            Transfer::Sqrt => (value / top).sqrt(),
+            Transfer::Cbrt => (value / top).cbrt(),
+            Transfer::Power(p) => (value / top).powf(p),
+        }
+    }
+}
+
+impl fmt::Display for Transfer {
+    /// The `--scale` value that picks it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transfer::Linear => write!(f, "linear"),
+            Transfer::Log => write!(f, "log"),
+            Transfer::Sqrt => write!(f, "sqrt"),
+            Transfer::Cbrt => write!(f, "cbrt"),
+            Transfer::Power(p) => write!(f, "power={}", p),
+        }
+    }
+}
+
+/// Palette positions for the escape times of one image, and the colors at
+/// them: escape time, histogram, distance or stripe average coloring.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    limit: u32,
+    smooth: bool,
+    /// The fractal's degree, which smooth counts are normalized by.
+    degree: f64,
+    transfer: Transfer,
+    /// How many iterations the palette spans before it starts over, if it
+    /// doesn't span `limit`.
+    cycle: Option<f64>,
+    /// For distance coloring, the distance between neighboring pixels.
+    pixel_size: Option<f64>,
+    stripes: bool,
+    /// For histogram coloring, each whole iteration count that points
+    /// escape at, in order, with how many points escape sooner, and the
+    /// number of points that escape at all.
+    histogram: Option<(Vec<(u32, u64)>, u64)>,
+    /// For Newton fractals, the polynomial, whose roots pick the colors
+    /// instead.
+    newton: Option<Polynomial>,
+    palette: Palette,
+}
+
+impl Scale {
+    /// The scale `options` asks for. A histogram counts the points of
+    /// `escapes`, which should cover the whole image; escape time coloring
+    /// doesn't look at them.
+    pub fn new(options: &RenderOptions, escapes: &[Option<Escape>]) -> Scale {
+        let mut scale = Scale {
+            limit: options.max_iter,
+            smooth: options.smooth,
+            degree: options.fractal.degree(),
+            transfer: options.transfer,
+            cycle: options.palette_cycle,
+            pixel_size: (options.coloring == Coloring::Distance).then(|| options.pixel_size()),
+            stripes: options.coloring == Coloring::StripeAverage,
+            histogram: None,
+            newton: options.newton.clone(),
+            palette: options.palette.clone(),
+        };
+        if options.coloring == Coloring::Histogram {
+            let mut bands = escapes
+                .iter()
+                .flatten()
+                .map(|escape| scale.band(scale.value(escape)))
+                .collect::<Vec<_>>();
+            bands.sort_unstable();
+            let mut histogram = Vec::<(u32, u64)>::new();
+            for (i, &band) in bands.iter().enumerate() {
+                if histogram.last().is_none_or(|&(last, _)| last != band) {
+                    histogram.push((band, i as u64));
+                }
+            }
+            scale.histogram = Some((histogram, bands.len() as u64));
+        }
+        scale
+    }
+
+    /// Where a point falls on the palette, from 0 to 1, or `None` for a
+    /// point in the set.
+    pub fn position(&self, escape: Option<Escape>) -> Option<f64> {
+        let escape = escape?;
+        if let Some(pixel_size) = self.pixel_size {
+            // Without a derivative there is no telling, so it counts as far.
+            let pixels = escape.distance().map_or(f64::INFINITY, |d| d / pixel_size);
+            return Some((pixels.log2() / DISTANCE_OCTAVES).clamp(0.0, 1.0));
+        }
+        if self.stripes {
+            return Some(escape.stripe.unwrap_or(0.5));
+        }
+        let value = self.value(&escape);
+        let (histogram, total) = match &self.histogram {
+            None => return Some(self.spread(value)),
+            Some((_, 0)) => return Some(0.0),
+            Some((histogram, total)) => (histogram, *total),
+        };
+        // Smooth values are spread through their band's share, whole
+        // counts take all of it so that the last band reaches the end.
+        let band = self.band(value);
+        let fraction = if self.smooth {
+            (value - band as f64).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let i = histogram.partition_point(|&(b, _)| b < band);
+        let sooner = histogram.get(i).map_or(total, |&(_, sooner)| sooner);
+        let count = match histogram.get(i) {
+            Some(&(b, _)) if b == band => {
+                histogram.get(i + 1).map_or(total, |&(_, later)| later) - sooner
+            }
+            _ => 0,
+        };
+        Some((sooner as f64 + fraction * count as f64) / total as f64)
+    }
+
+    /// Where an escape value falls on the palette for escape time
+    /// coloring: spread over `0..limit` by the transfer, or over each cycle
+    /// in turn.
+    fn spread(&self, value: f64) -> f64 {
+        match self.cycle {
+            Some(cycle) => self.transfer.apply(value.rem_euclid(cycle), cycle),
+            None => self.transfer.apply(value, self.limit.max(1) as f64),
+        }
+    }
+
+    fn value(&self, escape: &Escape) -> f64 {
+        if self.smooth {
+            escape.smooth_with(self.degree)
+        } else {
+            escape.iterations as f64
+        }
+    }
+
+    fn band(&self, value: f64) -> u32 {
+        value.floor().clamp(0.0, self.limit as f64) as u32
+    }
+}
+
+impl Colorizer for Scale {
+    /// The color of a point on the palette: at its `position`, or as
+    /// `Polynomial::color` has it for a Newton fractal.
+    fn color(&self, escape: Option<Escape>) -> [u8; 3] {
+        match &self.newton {
+            Some(polynomial) => polynomial.color(escape, &self.palette, self.smooth),
+            None => self.palette.color_at(self.position(escape)),
+        }
+    }
+
+    fn color_exact(&self, escape: Option<Escape>) -> [f64; 3] {
+        match &self.newton {
+            Some(polynomial) => polynomial
+                .color(escape, &self.palette, self.smooth)
+                .map(f64::from),
+            None => self.palette.color_at_exact(self.position(escape)),
+        }
+    }
+}
+
+/// Shades of gray for escape times, from black for points that escape
+/// straight away to white at `max_iter` iterations and for points in the
+/// set, as the 8-bit counterpart of `gray16`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grayscale {
+    limit: u32,
+    smooth: bool,
+    degree: f64,
+}
+
+impl Grayscale {
+    pub fn new(options: &RenderOptions) -> Grayscale {
+        Grayscale {
+            limit: options.max_iter,
+            smooth: options.smooth,
+            degree: options.fractal.degree(),
+        }
+    }
+}
+
+impl Colorizer for Grayscale {
+    fn color(&self, escape: Option<Escape>) -> [u8; 3] {
+        self.color_exact(escape).map(|v| v.round() as u8)
+    }
+
+    fn color_exact(&self, escape: Option<Escape>) -> [f64; 3] {
+        let Some(escape) = escape else {
+            return [255.0; 3];
+        };
+        let value = if self.smooth {
+            escape.smooth_with(self.degree)
+        } else {
+            escape.iterations as f64
+        };
+        [(value / self.limit.max(1) as f64 * 255.0).clamp(0.0, 255.0); 3]
+    }
+}
+
+#[test]
+fn test_escape_time_scale() {
+    let options = RenderOptions {
+        max_iter: 100,
+        smooth: false,
+        ..RenderOptions::default()
+    };
+    let scale = Scale::new(&options, &[]);
+    let escape = |iterations| {
+        Some(Escape {
+            iterations,
+            z: num::Complex { re: 2.0, im: 0.0 },
+            derivative: None,
+            stripe: None,
+        })
+    };
+    assert_eq!(scale.position(escape(25)), Some(0.25));
+    assert_eq!(scale.position(None), None);
+}
+
+#[test]
+fn test_transfer() {
+    let options = RenderOptions {
+        max_iter: 100,
+        smooth: false,
+        ..RenderOptions::default()
+    };
+    let escape = |iterations| {
+        Some(Escape {
+            iterations,
+            z: num::Complex { re: 2.0, im: 0.0 },
+            derivative: None,
+            stripe: None,
+        })
+    };
+    let position = |transfer, cycle, iterations| {
+        let scale = Scale::new(
+            &RenderOptions {
+                transfer,
+                palette_cycle: cycle,
+                ..options.clone()
+            },
+            &[],
+        );
+        scale.position(escape(iterations)).unwrap()
+    };
+    assert_eq!(position(Transfer::Linear, None, 25), 0.25);
+    assert_eq!(position(Transfer::Sqrt, None, 25), 0.5);
+    assert_eq!(position(Transfer::Power(2.0), None, 50), 0.25);
+    assert!((position(Transfer::Cbrt, None, 27) - 0.27f64.cbrt()).abs() < 1e-12);
+    assert!((position(Transfer::Log, None, 100) - 1.0).abs() < 1e-12);
+    assert!(position(Transfer::Log, None, 10) > 0.5);
+    // Every 40 iterations the palette starts over.
+    assert_eq!(position(Transfer::Linear, Some(40.0), 10), 0.25);
+    assert_eq!(position(Transfer::Linear, Some(40.0), 90), 0.25);
+    assert_eq!(position(Transfer::Sqrt, Some(40.0), 50), 0.5);
+
+    for text in ["linear", "log", "sqrt", "cbrt", "power=2.5"] {
+        assert_eq!(Transfer::parse(text).unwrap().to_string(), text);
+    }
+    assert_eq!(Transfer::parse("power=0"), None);
+    assert_eq!(Transfer::parse("power"), None);
+    assert_eq!(Transfer::parse("exp"), None);
+}
+
+#[test]
+fn test_distance_scale() {
+    let options = RenderOptions {
+        bounds: (100, 100),
+        upper_left: num::Complex { re: -2.0, im: 2.0 },
+        lower_right: num::Complex { re: 2.0, im: -2.0 },
+        coloring: Coloring::Distance,
+        ..RenderOptions::default()
+    };
+    let scale = Scale::new(&options, &[]);
+    // |z| = e makes the distance 2e / |dz|.
+    let at = |pixels: f64| {
+        let e = std::f64::consts::E;
+        scale.position(Some(Escape {
+            iterations: 5,
+            z: num::Complex { re: e, im: 0.0 },
+            derivative: Some(num::Complex {
+                re: 2.0 * e / (pixels * 0.04),
+                im: 0.0,
+            }),
+            stripe: None,
+        }))
+    };
+    assert_eq!(at(0.5), Some(0.0));
+    assert!((at(8.0).unwrap() - 0.25).abs() < 1e-12);
+    assert_eq!(at(1e6), Some(1.0));
+}
+
+#[test]
+fn test_histogram_scale() {
+    let escape = |iterations| {
+        Some(Escape {
+            iterations,
+            z: num::Complex { re: 2.0, im: 0.0 },
+            derivative: None,
+            stripe: None,
+        })
+    };
+    // Three points escape after 10 iterations and one after 1000: the
+    // first take three quarters of the palette, however far apart they are.
+    let escapes = [escape(10), escape(10), escape(1000), None, escape(10)];
+    let options = RenderOptions {
+        max_iter: 5000,
+        smooth: false,
+        coloring: Coloring::Histogram,
+        ..RenderOptions::default()
+    };
+    let scale = Scale::new(&options, &escapes);
+    assert_eq!(scale.position(escape(10)), Some(0.75));
+    assert_eq!(scale.position(escape(1000)), Some(1.0));
+    assert_eq!(scale.position(escape(500)), Some(0.75));
+    assert_eq!(scale.position(escape(5)), Some(0.0));
+    assert_eq!(scale.position(None), None);
+
+    // Smooth values move continuously through their band. |z| = 2 puts
+    // them at n + 1 - log2(ln 2), just over halfway into band n + 1.
+    let smooth = Scale::new(
+        &RenderOptions {
+            smooth: true,
+            ..options.clone()
+        },
+        &escapes,
+    );
+    let position = smooth.position(escape(10)).unwrap();
+    let fraction = -2f64.ln().log2();
+    assert!((position - 0.75 * fraction).abs() < 1e-12, "{}", position);
+
+    let empty = Scale::new(&options, &[None, None]);
+    assert_eq!(empty.position(escape(3)), Some(0.0));
+}
+
+#[test]
+fn test_colorizer() {
+    use crate::Renderer;
+    use std::sync::Arc;
+    let escape = |iterations| {
+        Some(Escape {
+            iterations,
+            z: num::Complex { re: 2.0, im: 0.0 },
+            derivative: None,
+            stripe: None,
+        })
+    };
+    let options = RenderOptions {
+        bounds: (12, 8),
+        max_iter: 100,
+        smooth: false,
+        coloring: Coloring::Grayscale,
+        ..RenderOptions::default()
+    };
+    let gray = Grayscale::new(&options);
+    assert_eq!(gray.color(escape(0)), [0; 3]);
+    assert_eq!(gray.color(escape(50)), [128; 3]);
+    assert_eq!(gray.color(escape(500)), [255; 3]);
+    assert_eq!(gray.color(None), [255; 3]);
+    let renderer = Renderer::new(options.clone());
+    let escapes = renderer.render_escapes();
+    assert_eq!(renderer.render(), crate::colorize(&escapes, &gray));
+    // A colorizer of one's own takes over from `coloring`.
+    struct Parity;
+    impl Colorizer for Parity {
+        fn color(&self, escape: Option<Escape>) -> [u8; 3] {
+            match escape {
+                Some(escape) if escape.iterations % 2 == 1 => [255, 255, 255],
+                _ => [0, 0, 0],
+            }
+        }
+    }
+    let renderer = Renderer::new(options).with_colorizer(Arc::new(Parity));
+    let pixels = renderer.render();
+    for (pixel, escape) in pixels.chunks(3).zip(&escapes) {
+        assert_eq!(pixel, Parity.color(*escape));
+    }
+}
@@ -0,0 +1,247 @@
+//! The subset of TOML that configuration files use: one `key = value` pair
+//! per line, with `#` comments. Values are strings (basic or literal),
+//! numbers or booleans. Options are a flat list, so tables are only read by
+//! `parse_tables`, for files such as the bookmark store that hold several
+//! named groups of keys; arrays and nested tables are rejected.
+
+/// A value from a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    /// A number, in its original digits less any `_` separators, so that
+    /// long coordinates keep their precision.
+    Number(String),
+    Boolean(bool),
+}
+
+/// A table's name and its keys and values, in order.
+pub type Table = (String, Vec<(String, Value)>);
+
+/// Parses a configuration file into its keys and values, in order.
+pub fn parse(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut tables = parse_document(text, false)?;
+    Ok(tables.remove(0).1)
+}
+
+/// Parses a file of `[name]` tables into each name and its keys and
+/// values, in order. Keys outside of a table are an error.
+pub fn parse_tables(text: &str) -> Result<Vec<Table>, String> {
+    let mut tables = parse_document(text, true)?;
+    if let Some((key, _)) = tables[0].1.first() {
+        return Err(format!("'{}' must be inside a [table]", key));
+    }
+    tables.remove(0);
+    Ok(tables)
+}
+
+/// Formats `s` as a basic string that `parse` reads back.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The keys before the first table, under the name `""`, followed by each
+/// table's.
+fn parse_document(text: &str, tables: bool) -> Result<Vec<Table>, String> {
+    let mut document = vec![(String::new(), Vec::<(String, Value)>::new())];
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            if !tables {
+                return Err(error("tables are not supported"));
+            }
+            let (name, rest) = header
+                .split_once(']')
+                .ok_or_else(|| error("expected `[name]`"))?;
+            let name = parse_key(name.trim()).ok_or_else(|| error("invalid table name"))?;
+            let rest = rest.trim_start();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(error("unexpected text after the table name"));
+            }
+            if document.iter().skip(1).any(|(n, _)| *n == name) {
+                return Err(error(&format!("[{}] is defined more than once", name)));
+            }
+            document.push((name, Vec::new()));
+            continue;
+        }
+        let (key, rest) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected `key = value`"))?;
+        let key = parse_key(key.trim()).ok_or_else(|| error("invalid key"))?;
+        let (value, rest) = parse_value(rest.trim_start()).map_err(|e| error(&e))?;
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(error("unexpected text after the value"));
+        }
+        let entries = &mut document.last_mut().unwrap().1;
+        if entries.iter().any(|(k, _)| *k == key) {
+            return Err(error(&format!("'{}' is set more than once", key)));
+        }
+        entries.push((key, value));
+    }
+    Ok(document)
+}
+
+/// A bare key, or a quoted one.
+fn parse_key(key: &str) -> Option<String> {
+    if let Ok((Value::String(key), "")) = parse_value(key) {
+        return Some(key);
+    }
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    bare.then(|| key.to_string())
+}
+
+/// Parses the value at the start of `s`, returning it and what follows.
+fn parse_value(s: &str) -> Result<(Value, &str), String> {
+    if let Some(rest) = s.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated string")?;
+        return Ok((Value::String(rest[..end].to_string()), &rest[end + 1..]));
+    }
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(value), &rest[i + 1..])),
+                '\\' => value.push(match chars.next().map(|(_, c)| c) {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    _ => return Err("unsupported escape in string".to_string()),
+                }),
+                c => value.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+    let end = s.find([' ', '\t', '#']).unwrap_or(s.len());
+    let (word, rest) = s.split_at(end);
+    let value = match word {
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        "" => return Err("missing value".to_string()),
+        _ if word.starts_with('[') || word.starts_with('{') => {
+            return Err("arrays and tables are not supported".to_string())
+        }
+        _ => {
+            let digits = word.replace('_', "");
+            if digits.parse::<f64>().is_err() || digits.contains(['i', 'n', 'I', 'N']) {
+                return Err(format!("invalid value '{}'", word));
+            }
+            Value::Number(digits)
+        }
+    };
+    Ok((value, rest))
+}
+
+#[test]
+fn test_parse() {
+    let text = r#"
+        # A deep zoom.
+        output = "deep.png"
+        size = '1000x750'   # 4:3
+        "upper-left" = "-0.743643887,0.131825904"
+        max-iter = 10_000
+        scale = -1.5e-3
+        no-smooth = true
+        quiet = false
+        note = "say \"hi\"\t# not a comment"
+    "#;
+    assert_eq!(
+        parse(text).unwrap(),
+        vec![
+            ("output".to_string(), Value::String("deep.png".to_string())),
+            ("size".to_string(), Value::String("1000x750".to_string())),
+            (
+                "upper-left".to_string(),
+                Value::String("-0.743643887,0.131825904".to_string())
+            ),
+            ("max-iter".to_string(), Value::Number("10000".to_string())),
+            ("scale".to_string(), Value::Number("-1.5e-3".to_string())),
+            ("no-smooth".to_string(), Value::Boolean(true)),
+            ("quiet".to_string(), Value::Boolean(false)),
+            (
+                "note".to_string(),
+                Value::String("say \"hi\"\t# not a comment".to_string())
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(
+        parse("size = 1\n[render]").unwrap_err(),
+        "line 2: tables are not supported"
+    );
+    assert!(parse("size").is_err());
+    assert!(parse("size = ").is_err());
+    assert!(parse("size = \"800x600").is_err());
+    assert!(parse("size = 800x600").is_err());
+    assert!(parse("size = [800, 600]").is_err());
+    assert!(parse("size = \"a\" \"b\"").is_err());
+    assert!(parse("max iter = 3").is_err());
+    assert!(parse("threads = 2\nthreads = 3").is_err());
+    assert!(parse("threads = inf").is_err());
+}
+
+#[test]
+fn test_parse_tables() {
+    let text = "# Bookmarks\n\
+                [seahorse-valley]\n\
+                center = \"-0.7453,0.1127\"\n\
+                zoom = 200 # or so\n\
+                \n\
+                [\"tiny spiral\"]\n\
+                zoom = 1e12\n";
+    assert_eq!(
+        parse_tables(text).unwrap(),
+        vec![
+            (
+                "seahorse-valley".to_string(),
+                vec![
+                    (
+                        "center".to_string(),
+                        Value::String("-0.7453,0.1127".to_string())
+                    ),
+                    ("zoom".to_string(), Value::Number("200".to_string())),
+                ]
+            ),
+            (
+                "tiny spiral".to_string(),
+                vec![("zoom".to_string(), Value::Number("1e12".to_string()))]
+            ),
+        ]
+    );
+    assert!(parse_tables("zoom = 2\n[a]").is_err());
+    assert!(parse_tables("[a]\n[a]").is_err());
+    assert!(parse_tables("[a").is_err());
+    assert!(parse_tables("[a] zoom = 2").is_err());
+}
+
+#[test]
+fn test_quote() {
+    let s = "say \"hi\"\t\\ there\n";
+    assert_eq!(
+        parse(&format!("s = {}", quote(s))).unwrap()[0].1,
+        Value::String(s.to_string())
+    );
+}
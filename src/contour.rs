@@ -0,0 +1,264 @@
+//! Contour line art: the lines along which escape values equal each of a
+//! set of levels, traced by marching squares and written as SVG paths, which
+//! stay sharp at any size.
+//!
+//! The escape value of every pixel is taken at its center. A square of four
+//! neighboring centers that has some corners above a level and some below
+//! is crossed by the level's line, which cuts each edge between them where
+//! the value would reach the level along it. The pieces of line each square
+//! gives are joined end to end into paths, closed where they come back
+//! around. Points in the set count as the iteration limit, so that the top
+//! levels outline it.
+//!
+//! Levels are spread evenly over `ln(1 + value)` between the lowest and
+//! highest escape values of the image, as escape values crowd together far
+//! from the set, and each is drawn in the color of the palette at its place
+//! in the spread.
+
+use crate::{Escape, RenderOptions};
+use std::{collections::HashMap, io::Write};
+
+/// How many levels are traced when none is asked for.
+pub const DEFAULT_LEVELS: u32 = 16;
+
+/// The escape value of every pixel of an image, with the points in the set
+/// at `limit`.
+pub fn values(escapes: &[Option<Escape>], limit: u32, smooth: bool) -> Vec<f64> {
+    escapes
+        .iter()
+        .map(|escape| match escape {
+            None => limit as f64,
+            Some(e) if smooth => e.smooth(),
+            Some(e) => e.iterations as f64,
+        })
+        .collect()
+}
+
+/// `count` levels spread over the escape values of the points of
+/// `escapes` outside the set, none for an image of the set alone.
+pub fn levels(escapes: &[Option<Escape>], count: u32, smooth: bool) -> Vec<f64> {
+    let escaped = values(escapes, 0, smooth)
+        .into_iter()
+        .zip(escapes)
+        .filter(|(_, escape)| escape.is_some())
+        .map(|(value, _)| value.max(0.0).ln_1p())
+        .collect::<Vec<_>>();
+    let low = escaped.iter().copied().fold(f64::INFINITY, f64::min);
+    let high = escaped.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if escaped.is_empty() {
+        return Vec::new();
+    }
+    (0..count)
+        .map(|i| (low + (high - low) * (i as f64 + 0.5) / count as f64).exp_m1())
+        .collect()
+}
+
+/// Where an edge between two pixel centers is crossed: the pixel it starts
+/// at, and whether it runs down from it rather than right.
+type EdgeKey = (u32, u32, bool);
+
+/// The lines along which `values`, row by row over `bounds`, equal
+/// `level`, as paths through points in pixels from the image's top left
+/// corner. A closed path ends where it starts.
+pub fn trace(values: &[f64], bounds: (u32, u32), level: f64) -> Vec<Vec<(f64, f64)>> {
+    let (width, height) = bounds;
+    let value = |x: u32, y: u32| values[(y * width + x) as usize];
+    // Where a line crosses the edge, by linear interpolation.
+    let crossing = |(x, y, down): EdgeKey| -> (f64, f64) {
+        let (a, b) = match down {
+            true => (value(x, y), value(x, y + 1)),
+            false => (value(x, y), value(x + 1, y)),
+        };
+        let t = ((level - a) / (b - a)).clamp(0.0, 1.0);
+        match down {
+            true => (x as f64 + 0.5, y as f64 + 0.5 + t),
+            false => (x as f64 + 0.5 + t, y as f64 + 0.5),
+        }
+    };
+    let mut segments: Vec<(EdgeKey, EdgeKey)> = Vec::new();
+    for y in 0..height.saturating_sub(1) {
+        for x in 0..width.saturating_sub(1) {
+            let corners = [
+                value(x, y),
+                value(x + 1, y),
+                value(x + 1, y + 1),
+                value(x, y + 1),
+            ];
+            let above = corners.map(|v| v >= level);
+            // The edges of the square, clockwise from the top, each
+            // between the corners of the same number and the next.
+            let edges = [
+                (x, y, false),
+                (x + 1, y, true),
+                (x, y + 1, false),
+                (x, y, true),
+            ];
+            let crossed = (0..4)
+                .filter(|&i| above[i] != above[(i + 1) % 4])
+                .collect::<Vec<_>>();
+            match crossed[..] {
+                [a, b] => segments.push((edges[a], edges[b])),
+                [_, _, _, _] => {
+                    // A saddle: the line keeps the corners that are alike
+                    // apart or together as the center of the square is.
+                    let center = corners.iter().sum::<f64>() / 4.0 >= level;
+                    let pairs = if center == above[0] {
+                        [(0, 1), (2, 3)]
+                    } else {
+                        [(3, 0), (1, 2)]
+                    };
+                    for (a, b) in pairs {
+                        segments.push((edges[a], edges[b]));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Every crossing belongs to at most two segments, so the segments form
+    // paths and loops, which are walked from their ends and then anywhere.
+    let mut at: HashMap<EdgeKey, Vec<usize>> = HashMap::new();
+    for (i, &(a, b)) in segments.iter().enumerate() {
+        at.entry(a).or_default().push(i);
+        at.entry(b).or_default().push(i);
+    }
+    let mut used = vec![false; segments.len()];
+    let mut paths = Vec::new();
+    let ends = (0..segments.len())
+        .flat_map(|i| [segments[i].0, segments[i].1])
+        .filter(|key| at[key].len() == 1)
+        .collect::<Vec<_>>();
+    let starts = ends
+        .into_iter()
+        .chain(segments.iter().map(|&(a, _)| a))
+        .collect::<Vec<_>>();
+    for start in starts {
+        let mut key = start;
+        let mut path = vec![crossing(key)];
+        while let Some(&i) = at[&key].iter().find(|&&i| !used[i]) {
+            used[i] = true;
+            let (a, b) = segments[i];
+            key = if a == key { b } else { a };
+            path.push(crossing(key));
+        }
+        if path.len() > 1 {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// Writes the contours of `escapes`, rendered with `options`, at `count`
+/// levels as an SVG colored from the palette, with `text` as keyword and
+/// value pairs in its description.
+pub fn encode_svg<W: Write>(
+    mut w: W,
+    escapes: &[Option<Escape>],
+    options: &RenderOptions,
+    count: u32,
+    text: &[(&str, String)],
+) -> std::io::Result<()> {
+    let bounds = options.bounds;
+    let (width, height) = bounds;
+    writeln!(
+        w,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+        width, height, width, height
+    )?;
+    if !text.is_empty() {
+        writeln!(w, "<desc>")?;
+        for (keyword, value) in text {
+            writeln!(w, "{}: {}", escape_xml(keyword), escape_xml(value))?;
+        }
+        writeln!(w, "</desc>")?;
+    }
+    let values = values(escapes, options.max_iter, options.smooth);
+    let levels = levels(escapes, count, options.smooth);
+    for (i, &level) in levels.iter().enumerate() {
+        let paths = trace(&values, bounds, level);
+        if paths.is_empty() {
+            continue;
+        }
+        let [r, g, b] = options.palette.at((i as f64 + 0.5) / levels.len() as f64);
+        write!(
+            w,
+            "<path fill=\"none\" stroke=\"#{:02x}{:02x}{:02x}\" d=\"",
+            r, g, b
+        )?;
+        for path in paths {
+            let closed = path.len() > 2 && path.first() == path.last();
+            for (j, (x, y)) in path.iter().enumerate().take(path.len() - closed as usize) {
+                write!(w, "{}{:.2} {:.2}", if j == 0 { "M" } else { "L" }, x, y)?;
+            }
+            if closed {
+                write!(w, "Z")?;
+            }
+        }
+        writeln!(w, "\"/>")?;
+    }
+    writeln!(w, "</svg>")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[test]
+fn test_trace() {
+    // A bump in the middle of a 5×5 grid: level 1.5 circles the center.
+    let mut values = vec![0.0; 25];
+    values[12] = 3.0;
+    let paths = trace(&values, (5, 5), 1.5);
+    assert_eq!(paths.len(), 1);
+    let path = &paths[0];
+    assert_eq!(path.first(), path.last());
+    assert_eq!(path.len(), 5);
+    for &(x, y) in path {
+        let distance = (x - 2.5).abs() + (y - 2.5).abs();
+        assert!((distance - 0.5).abs() < 1e-12, "{} {}", x, y);
+    }
+    // A ramp from left to right: one straight, open line down the image.
+    let values = (0..20).map(|i| (i % 4) as f64).collect::<Vec<_>>();
+    let paths = trace(&values, (4, 5), 1.25);
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0].len(), 5);
+    assert!(paths[0].iter().all(|&(x, _)| (x - 1.75).abs() < 1e-12));
+    assert!(trace(&values, (4, 5), 7.0).is_empty());
+}
+
+#[test]
+fn test_saddle() {
+    // High corners top left and bottom right, with a high center: one line
+    // cuts off each low corner.
+    let values = [1.0, 0.0, 0.0, 1.0];
+    let paths = trace(&values, (2, 2), 0.4);
+    assert_eq!(paths.len(), 2);
+    assert!(paths.iter().all(|path| path.len() == 2));
+    let low = trace(&values, (2, 2), 0.6);
+    assert_eq!(low.len(), 2);
+}
+
+#[test]
+fn test_encode_svg() {
+    use crate::Renderer;
+    let options = RenderOptions {
+        bounds: (60, 40),
+        ..RenderOptions::default()
+    };
+    let escapes = Renderer::new(options.clone()).render_escapes();
+    let levels = levels(&escapes, 8, true);
+    assert_eq!(levels.len(), 8);
+    assert!(levels.windows(2).all(|w| w[0] < w[1]));
+    let mut bytes = Vec::new();
+    let text = [("Software", "a < b & c".to_string())];
+    encode_svg(&mut bytes, &escapes, &options, 8, &text).unwrap();
+    let svg = String::from_utf8(bytes).unwrap();
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"60\""));
+    assert!(svg.contains("Software: a &lt; b &amp; c"));
+    assert!(svg.trim_end().ends_with("</svg>"));
+    assert_eq!(svg.matches("<path ").count(), 8);
+    assert!(svg.contains('Z'));
+}
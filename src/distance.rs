@@ -0,0 +1,82 @@
+//! Exterior distance estimation. Alongside `z`, the derivative `dz/dc` is
+//! iterated as `2 z dz + 1`, starting from 0, and gives an estimate of how
+//! far an escaping point is from the set. Coloring by it draws the thin
+//! filaments between the set's bulbs crisply, where escape times blur them.
+//! For Julia sets the derivative is `dz/dz0`, iterated as `2 z dz` from 1.
+//!
+//! The derivative is gathered by following the orbits again; see `orbit`.
+
+use crate::{orbit::OrbitStatistic, Escape, Fractal};
+use num::Complex;
+
+/// The derivative `dz` along an orbit.
+pub struct Derivative {
+    dz: Complex<f64>,
+    /// What each step adds: 1 for `dz/dc`, 0 for `dz/dz0`.
+    constant: f64,
+}
+
+impl OrbitStatistic for Derivative {
+    fn start(_fractal: &Fractal, julia: bool) -> Derivative {
+        if julia {
+            Derivative {
+                dz: Complex::new(1.0, 0.0),
+                constant: 0.0,
+            }
+        } else {
+            Derivative {
+                dz: Complex::new(0.0, 0.0),
+                constant: 1.0,
+            }
+        }
+    }
+
+    fn step(&mut self, z: Complex<f64>, _next: Complex<f64>) {
+        self.dz = 2.0 * z * self.dz + self.constant;
+    }
+
+    fn finish(self, escape: &mut Escape) {
+        escape.derivative = Some(self.dz);
+    }
+}
+
+#[test]
+fn test_derivative() {
+    // After one step z = c, after two z = c² + c, with derivative 2c + 1.
+    let derivative = |point, julia, iterations| {
+        crate::orbit::follow::<Derivative>(point, &crate::Fractal::Mandelbrot, julia, iterations).dz
+    };
+    let c = Complex::new(0.3, -0.4);
+    assert_eq!(derivative(c, None, 1), Complex::new(1.0, 0.0));
+    assert_eq!(derivative(c, None, 2), 2.0 * c + 1.0);
+    // For Julia sets z = z0² + c, with derivative 2 z0.
+    assert_eq!(derivative(c, Some(Complex::new(-1.0, 0.0)), 1), 2.0 * c);
+}
+
+#[test]
+fn test_distance() {
+    // The set reaches 0.25 on the real axis, and the estimate is within a
+    // factor of 4 of the distance to it.
+    let mut escapes = [crate::escape_time(Complex::new(0.5, 0.0), 100)];
+    crate::orbit::add::<Derivative>(
+        &[Complex::new(0.5, 0.0)],
+        &crate::Fractal::Mandelbrot,
+        None,
+        &mut escapes,
+    );
+    let distance = escapes[0].unwrap().distance().unwrap();
+    assert!(
+        (0.25 / 4.0..=0.25 * 4.0).contains(&distance),
+        "{}",
+        distance
+    );
+    // Points farther out are estimated farther away.
+    let mut far = [crate::escape_time(Complex::new(1.5, 0.0), 100)];
+    crate::orbit::add::<Derivative>(
+        &[Complex::new(1.5, 0.0)],
+        &crate::Fractal::Mandelbrot,
+        None,
+        &mut far,
+    );
+    assert!(far[0].unwrap().distance().unwrap() > distance);
+}
@@ -0,0 +1,652 @@
+//! Distributed rendering: the jobs a coordinator hands out to workers on
+//! other machines, and the escape times they send back. A job is a band of
+//! rows of an image, given by its size, corners and iteration limit; the
+//! worker renders the rest like its own options say, which have to match
+//! the coordinator's. The rows come out exactly as they would in one piece,
+//! like the bands of `tiled`. `render` shares an image out among workers
+//! and the local CPU.
+//!
+//! A job is a few lines of text, ended by an empty line:
+//!
+//! ```text
+//! MBJOB 1
+//! key <checkpoint::key of the whole render>
+//! size 800x600
+//! rows 100 200
+//! iterations 1000
+//! corners -2.0,1.5 1.0,-1.5
+//! exact 70 -2.000...,1.500... 1.000...,-1.500...
+//! ```
+//!
+//! The `f64` corners are written so that they read back exactly, as are
+//! the exact ones, to as many decimal places as they have fractional bits;
+//! the `exact` line is only there when the render has exact corners. The
+//! worker answers each job on the same connection, little-endian:
+//!
+//! | bytes | contents                                                  |
+//! |-------|-----------------------------------------------------------|
+//! | 4     | magic `MBES`                                              |
+//! | 1     | 0 for escapes, 1 for an error                             |
+//! | 3     | reserved, 0                                               |
+//! | 8     | length of the body, `u64`                                 |
+//! | ...   | the escapes of the rows, encoded like a `checkpoint` band |
+//! |       | and compressed with zlib, or the error in UTF-8           |
+
+use crate::{
+    checkpoint, fixed,
+    log::{self, Level},
+    Escape, Fixed, Precision, RenderOptions, Renderer,
+};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use num::Complex;
+use std::{
+    io::{self, BufRead, BufWriter, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    ops::Range,
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+const MAGIC: &str = "MBJOB";
+const VERSION: u32 = 1;
+const REPLY_MAGIC: &[u8; 4] = b"MBES";
+
+/// The port of a worker whose address has none.
+pub const DEFAULT_PORT: u16 = 7878;
+
+/// How many pixels a job holds, given enough rows. Small enough that the
+/// rows are shared out evenly, big enough that they're worth sending.
+pub const JOB_PIXELS: u32 = 1 << 16;
+
+/// The most pixels a worker renders for one job. Jobs hold `JOB_PIXELS`,
+/// or one row of a wider image; more is turned down before anything is
+/// allocated for it.
+const MAX_JOB_PIXELS: u64 = 16 * JOB_PIXELS as u64;
+
+/// The most iterations per point a worker takes a job with, since a
+/// reference orbit holds one value for each.
+const MAX_JOB_ITERATIONS: u32 = 1 << 24;
+
+/// The most fractional bits a job's exact corners may have, some 19,000
+/// decimal places.
+const MAX_JOB_BITS: u32 = 1 << 16;
+
+/// How many times in a row a worker may fail before it is given up on.
+const MAX_FAILURES: u32 = 3;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a worker may take over one job before it is taken for dead.
+const JOB_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// How long a worker waits on its coordinator, for the next job or to take
+/// an answer, before hanging up. As long as the coordinator waits on it.
+pub const WORKER_TIMEOUT: Duration = JOB_TIMEOUT;
+
+/// The most bytes of a job read, up to its empty line: enough for exact
+/// corners of `MAX_JOB_BITS`, four numbers of as many decimal places.
+const MAX_JOB_BYTES: u64 = 4 * MAX_JOB_BITS as u64 + 4096;
+
+/// A band of rows of an image for a worker to render.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    /// Identifies everything else the escape times depend on.
+    pub key: String,
+    pub bounds: (u32, u32),
+    pub rows: Range<u32>,
+    pub max_iter: u32,
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    pub exact_corners: Option<(Complex<Fixed>, Complex<Fixed>)>,
+}
+
+impl Job {
+    /// The job of rendering `rows` of the image of `options`.
+    pub fn new(options: &RenderOptions, rows: Range<u32>) -> Job {
+        Job {
+            key: checkpoint::key(options),
+            bounds: options.bounds,
+            rows,
+            max_iter: options.max_iter,
+            upper_left: options.upper_left,
+            lower_right: options.lower_right,
+            exact_corners: options
+                .exact_corners
+                .as_ref()
+                .map(|_| options.exact_corners()),
+        }
+    }
+
+    /// The options of the job's whole image: `options` with its size,
+    /// corners and iteration limit. Perturbation gets as many bits as the
+    /// pixel size needs, as it does from the command line. Fails unless
+    /// the result renders exactly like the coordinator's, if the rows
+    /// aren't in the image, or if the job is too big for a worker.
+    pub fn options(&self, options: &RenderOptions) -> Result<RenderOptions, String> {
+        if self.rows.start > self.rows.end || self.rows.end > self.bounds.1 {
+            return Err(format!(
+                "rows {}..{} are not in a {}x{} image",
+                self.rows.start, self.rows.end, self.bounds.0, self.bounds.1
+            ));
+        }
+        let pixels = self.bounds.0 as u64 * self.rows.len() as u64;
+        if pixels > MAX_JOB_PIXELS {
+            return Err(format!(
+                "a job of {} pixels is more than the {} a worker takes",
+                pixels, MAX_JOB_PIXELS
+            ));
+        }
+        if self.max_iter > MAX_JOB_ITERATIONS {
+            return Err(format!(
+                "{} iterations are more than the {} a worker takes",
+                self.max_iter, MAX_JOB_ITERATIONS
+            ));
+        }
+        let mut options = RenderOptions {
+            bounds: self.bounds,
+            max_iter: self.max_iter,
+            upper_left: self.upper_left,
+            lower_right: self.lower_right,
+            exact_corners: self.exact_corners.clone(),
+            ..options.clone()
+        };
+        if let Precision::Perturbation(_) = options.precision {
+            options.precision =
+                Precision::Perturbation(fixed::bits_for_pixel_size(options.pixel_size()));
+        }
+        if checkpoint::key(&options) != self.key {
+            return Err("the worker's options differ from the coordinator's".to_string());
+        }
+        Ok(options)
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let point = |z: &Complex<f64>| format!("{:?},{:?}", z.re, z.im);
+        writeln!(w, "{} {}", MAGIC, VERSION)?;
+        writeln!(w, "key {}", self.key)?;
+        writeln!(w, "size {}x{}", self.bounds.0, self.bounds.1)?;
+        writeln!(w, "rows {} {}", self.rows.start, self.rows.end)?;
+        writeln!(w, "iterations {}", self.max_iter)?;
+        writeln!(
+            w,
+            "corners {} {}",
+            point(&self.upper_left),
+            point(&self.lower_right)
+        )?;
+        if let Some((upper_left, lower_right)) = &self.exact_corners {
+            let bits = upper_left.re.bits() as usize;
+            let point =
+                |z: &Complex<Fixed>| format!("{},{}", z.re.to_decimal(bits), z.im.to_decimal(bits));
+            writeln!(
+                w,
+                "exact {} {} {}",
+                bits,
+                point(upper_left),
+                point(lower_right)
+            )?;
+        }
+        writeln!(w)?;
+        w.flush()
+    }
+
+    /// Reads the next job, or `None` once the coordinator has hung up.
+    pub fn read<R: BufRead>(r: R) -> io::Result<Option<Job>> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut r = r.take(MAX_JOB_BYTES);
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if r.read_line(&mut line)? == 0 {
+                if r.limit() == 0 {
+                    return Err(invalid("job is too long"));
+                }
+                if lines.is_empty() {
+                    return Ok(None);
+                }
+                return Err(invalid("job cut short"));
+            }
+            let line = line.trim_end_matches(['\r', '\n']);
+            if line.is_empty() {
+                break;
+            }
+            lines.push(line.to_string());
+        }
+        if lines.first().map(String::as_str) != Some(&format!("{} {}", MAGIC, VERSION)) {
+            return Err(invalid("not a render job"));
+        }
+        let field = |name: &str| {
+            lines[1..]
+                .iter()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        };
+        let required =
+            |name: &str| field(name).ok_or_else(|| invalid(&format!("job has no {}", name)));
+        let pair = |s: &str, separator: char| -> Option<(String, String)> {
+            let (a, b) = s.split_once(separator)?;
+            Some((a.to_string(), b.to_string()))
+        };
+        let malformed = |name: &str| invalid(&format!("malformed {} in job", name));
+        let (width, height) = pair(required("size")?, 'x').ok_or_else(|| malformed("size"))?;
+        let (top, end) = pair(required("rows")?, ' ').ok_or_else(|| malformed("rows"))?;
+        let number = |s: &str, name: &str| s.parse::<u32>().map_err(|_| malformed(name));
+        let point = |s: &str| -> Option<Complex<f64>> {
+            let (re, im) = pair(s, ',')?;
+            Some(Complex::new(re.parse().ok()?, im.parse().ok()?))
+        };
+        let (upper_left, lower_right) = pair(required("corners")?, ' ')
+            .and_then(|(a, b)| Some((point(&a)?, point(&b)?)))
+            .ok_or_else(|| malformed("corners"))?;
+        let exact_corners = match field("exact") {
+            None => None,
+            Some(exact) => {
+                let parse = || -> Option<(Complex<Fixed>, Complex<Fixed>)> {
+                    let mut parts = exact.split(' ');
+                    let bits = parts.next()?.parse::<u32>().ok()?;
+                    if bits > MAX_JOB_BITS {
+                        return None;
+                    }
+                    let mut point = || -> Option<Complex<Fixed>> {
+                        let (re, im) = pair(parts.next()?, ',')?;
+                        Some(Complex {
+                            re: Fixed::parse(&re)?.with_bits(bits),
+                            im: Fixed::parse(&im)?.with_bits(bits),
+                        })
+                    };
+                    let corners = (point()?, point()?);
+                    parts.next().is_none().then_some(corners)
+                };
+                Some(parse().ok_or_else(|| malformed("exact corners"))?)
+            }
+        };
+        Ok(Some(Job {
+            key: required("key")?.to_string(),
+            bounds: (number(&width, "size")?, number(&height, "size")?),
+            rows: number(&top, "rows")?..number(&end, "rows")?,
+            max_iter: number(required("iterations")?, "iterations")?,
+            upper_left,
+            lower_right,
+            exact_corners,
+        }))
+    }
+}
+
+/// Sends a worker's answer to a job: the escape times of its rows, or why
+/// it couldn't render them.
+pub fn write_escapes<W: Write>(
+    mut w: W,
+    escapes: Result<&[Option<Escape>], &str>,
+) -> io::Result<()> {
+    let (status, body) = match escapes {
+        Ok(escapes) => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+            let mut bytes = Vec::new();
+            for escape in escapes {
+                checkpoint::encode_escape(&mut bytes, escape);
+            }
+            encoder.write_all(&bytes)?;
+            (0, encoder.finish()?)
+        }
+        Err(message) => (1, message.as_bytes().to_vec()),
+    };
+    w.write_all(REPLY_MAGIC)?;
+    w.write_all(&[status, 0, 0, 0])?;
+    w.write_all(&(body.len() as u64).to_le_bytes())?;
+    w.write_all(&body)?;
+    w.flush()
+}
+
+/// Reads a worker's answer to a job of `count` pixels. An error the worker
+/// sent back comes out as an `io::Error` of kind `Other`.
+pub fn read_escapes<R: Read>(mut r: R, count: usize) -> io::Result<Vec<Option<Escape>>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut header = [0; 16];
+    r.read_exact(&mut header)?;
+    if &header[..4] != REPLY_MAGIC {
+        return Err(invalid("not an answer to a render job"));
+    }
+    let length = u64::from_le_bytes(header[8..].try_into().unwrap());
+    // Neither a longer answer nor one inflating to more could be the
+    // escapes: zlib stores what doesn't compress with a few bytes of
+    // overhead a block.
+    let decoded = (count * checkpoint::MAX_ESCAPE_BYTES) as u64;
+    if length > decoded + decoded / 1024 + 64 {
+        return Err(invalid("answer is too long for the job"));
+    }
+    let mut body = Vec::new();
+    r.take(length).read_to_end(&mut body)?;
+    if body.len() as u64 != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    match header[4] {
+        0 => {
+            let mut bytes = Vec::new();
+            ZlibDecoder::new(&body[..])
+                .take(decoded + 1)
+                .read_to_end(&mut bytes)?;
+            checkpoint::decode_escapes(&bytes, count)
+                .ok_or_else(|| invalid("wrong number of escapes for the job"))
+        }
+        _ => Err(io::Error::other(String::from_utf8_lossy(&body))),
+    }
+}
+
+/// The jobs of a render in progress.
+struct Queue {
+    /// Bands of rows yet to be rendered, the next one last.
+    pending: Vec<Range<u32>>,
+    /// How many bands are being rendered.
+    running: usize,
+    done: Vec<(u32, Vec<Option<Escape>>)>,
+}
+
+/// Shares out the bands of a render between the workers and the local CPU.
+struct Scheduler {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+}
+
+impl Scheduler {
+    /// The next band to render, waiting for one to come back from a failed
+    /// worker while any are running, or `None` once all are done.
+    fn take(&self) -> Option<Range<u32>> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if let Some(rows) = queue.pending.pop() {
+                queue.running += 1;
+                return Some(rows);
+            }
+            if queue.running == 0 {
+                return None;
+            }
+            queue = self.changed.wait(queue).unwrap();
+        }
+    }
+
+    fn finish(&self, rows: Range<u32>, escapes: Vec<Option<Escape>>) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.running -= 1;
+        queue.done.push((rows.start, escapes));
+        self.changed.notify_all();
+    }
+
+    /// Puts back a band a worker failed to render, for another to take.
+    fn give_back(&self, rows: Range<u32>) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.running -= 1;
+        queue.pending.push(rows);
+        self.changed.notify_all();
+    }
+}
+
+/// Computes the escape times of `renderer`'s image, sharing its rows out
+/// between the `workers`, by address, and the local CPU. A band a worker
+/// fails to render is handed to another, and `failed` is told why; after
+/// `MAX_FAILURES` in a row, or once it turns a job down, the worker gets
+/// no more. The local CPU never fails, so the render always finishes.
+///
+/// A cancelled render stops after the bands under way, and returns the
+/// rows of those finished from the top, like `Renderer::render_escapes`.
+/// The band the local CPU was on when cancelled is dropped.
+pub fn render<F>(renderer: &Renderer, workers: &[String], failed: F) -> Vec<Option<Escape>>
+where
+    F: Fn(&str, &io::Error) + Sync,
+{
+    let options = renderer.options();
+    let (width, height) = options.bounds;
+    let rows = (JOB_PIXELS / width.max(1)).max(1);
+    let scheduler = Scheduler {
+        queue: Mutex::new(Queue {
+            pending: (0..height)
+                .step_by(rows as usize)
+                .rev()
+                .map(|top| top..(top + rows).min(height))
+                .collect(),
+            running: 0,
+            done: Vec::new(),
+        }),
+        changed: Condvar::new(),
+    };
+    renderer.progress().start(width as u64 * height as u64);
+    std::thread::scope(|scope| {
+        for address in workers {
+            let (scheduler, failed) = (&scheduler, &failed);
+            scope.spawn(move || work_remotely(renderer, address, scheduler, failed));
+        }
+        while let Some(rows) = scheduler.take() {
+            let _span = log::span(Level::Debug, "band", format_args!("rows={:?}", rows));
+            let escapes = renderer.render_row_escapes(rows.clone());
+            if escapes.len() < width as usize * rows.len() {
+                scheduler.give_back(rows);
+                break;
+            }
+            scheduler.finish(rows, escapes);
+        }
+    });
+    let mut done = scheduler.queue.into_inner().unwrap().done;
+    done.sort_by_key(|(top, _)| *top);
+    let mut escapes = Vec::with_capacity(width as usize * height as usize);
+    for (top, band) in done {
+        if top as usize * width as usize != escapes.len() {
+            break;
+        }
+        escapes.extend(band);
+    }
+    renderer.finished(renderer.rows_in(&escapes));
+    escapes
+}
+
+/// Sends bands to the worker at `address` until there are none left or it
+/// has failed too often.
+fn work_remotely(
+    renderer: &Renderer,
+    address: &str,
+    scheduler: &Scheduler,
+    failed: &(impl Fn(&str, &io::Error) + Sync),
+) {
+    let options = renderer.options();
+    let mut connection = None;
+    let mut failures = 0;
+    while failures < MAX_FAILURES {
+        let Some(rows) = scheduler.take() else {
+            return;
+        };
+        if renderer.progress().is_cancelled() {
+            scheduler.give_back(rows);
+            return;
+        }
+        let count = options.bounds.0 as usize * rows.len();
+        let span = log::span(
+            Level::Debug,
+            "job",
+            format_args!("worker={} rows={:?}", address, rows),
+        );
+        let result = (|| {
+            let stream = match &connection {
+                Some(stream) => stream,
+                None => connection.insert(connect(address)?),
+            };
+            Job::new(options, rows.clone()).write(BufWriter::new(stream))?;
+            read_escapes(stream, count)
+        })();
+        drop(span);
+        match result {
+            Ok(escapes) => {
+                failures = 0;
+                renderer.progress().add(count as u64);
+                renderer.rows_complete(rows.start, &escapes);
+                scheduler.finish(rows, escapes);
+            }
+            Err(error) => {
+                scheduler.give_back(rows);
+                failed(address, &error);
+                connection = None;
+                failures += 1;
+                if error.kind() == io::ErrorKind::Other {
+                    // Turned down: it would only do so again.
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Connects to the worker at `address`, on `DEFAULT_PORT` unless it says.
+fn connect(address: &str) -> io::Result<TcpStream> {
+    let mut addresses = match address.to_socket_addrs() {
+        Ok(addresses) => addresses,
+        Err(_) => (address, DEFAULT_PORT).to_socket_addrs()?,
+    };
+    let address = addresses
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such host"))?;
+    let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(JOB_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+    Ok(stream)
+}
+
+#[test]
+fn test_job_round_trip() {
+    let exact = |s: &str| Fixed::parse(s).unwrap().with_bits(90);
+    let options = RenderOptions {
+        bounds: (30, 20),
+        upper_left: Complex::new(-0.1, 0.7),
+        lower_right: Complex::new(0.2, 0.5),
+        exact_corners: Some((
+            Complex {
+                re: exact("-0.1"),
+                im: exact("0.7"),
+            },
+            Complex {
+                re: exact("0.2"),
+                im: exact("0.5"),
+            },
+        )),
+        max_iter: 500,
+        ..RenderOptions::default()
+    };
+    let job = Job::new(&options, 5..12);
+    let mut bytes = Vec::new();
+    job.write(&mut bytes).unwrap();
+    job.write(&mut bytes).unwrap();
+    let mut r = &bytes[..];
+    assert_eq!(Job::read(&mut r).unwrap().as_ref(), Some(&job));
+    assert_eq!(Job::read(&mut r).unwrap().as_ref(), Some(&job));
+    assert_eq!(Job::read(&mut r).unwrap(), None);
+    // Whatever the worker was started with, the job's view wins.
+    let worker = RenderOptions::default();
+    assert_eq!(job.options(&worker).unwrap(), options);
+    let other = RenderOptions {
+        julia: Some(Complex::new(0.3, 0.0)),
+        ..worker.clone()
+    };
+    assert!(job.options(&other).is_err());
+    let huge = Job {
+        bounds: (u32::MAX, u32::MAX),
+        rows: 0..u32::MAX,
+        ..job.clone()
+    };
+    assert!(huge.options(&worker).unwrap_err().contains("pixels"));
+    let endless = Job {
+        max_iter: u32::MAX,
+        ..job.clone()
+    };
+    assert!(endless.options(&worker).unwrap_err().contains("iterations"));
+    let mut deep = Vec::new();
+    job.write(&mut deep).unwrap();
+    let deep = String::from_utf8(deep)
+        .unwrap()
+        .replace("exact 90", "exact 4000000000");
+    assert!(Job::read(deep.as_bytes()).is_err());
+    assert!(Job::read(&b"MBJOB 2\n\n"[..]).is_err());
+    assert!(Job::read(&b"MBJOB 1\nsize 3x3\n"[..]).is_err());
+    // A job never ending is cut off rather than read on.
+    let endless = io::BufReader::new(b"MBJOB 1\nsize ".chain(io::repeat(b'9')));
+    assert_eq!(
+        Job::read(endless).unwrap_err().to_string(),
+        "job is too long"
+    );
+}
+
+#[test]
+fn test_escapes_round_trip() {
+    let options = RenderOptions {
+        bounds: (40, 30),
+        coloring: crate::Coloring::Distance,
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let job = Job::new(&options, 10..17);
+    let renderer = crate::Renderer::new(job.options(&options).unwrap());
+    let escapes = renderer.render_row_escapes(job.rows.clone());
+    let whole = crate::Renderer::new(options).render_escapes();
+    assert_eq!(escapes, whole[400..680]);
+    let mut bytes = Vec::new();
+    write_escapes(&mut bytes, Ok(&escapes)).unwrap();
+    write_escapes(&mut bytes, Err("no")).unwrap();
+    let mut r = &bytes[..];
+    assert_eq!(read_escapes(&mut r, 280).unwrap(), escapes);
+    let error = read_escapes(&mut r, 280).unwrap_err();
+    assert_eq!(error.to_string(), "no");
+
+    // Answers too long for the job are turned down unread, and a body that
+    // inflates past any escapes is only inflated that far.
+    let answer =
+        |length: u64, body: &[u8]| [REPLY_MAGIC, &[0; 4][..], &length.to_le_bytes(), body].concat();
+    let error = read_escapes(&answer(u64::MAX, &[])[..], 280).unwrap_err();
+    assert_eq!(error.to_string(), "answer is too long for the job");
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&vec![0; 1 << 24]).unwrap();
+    let bomb = encoder.finish().unwrap();
+    let error = read_escapes(&answer(bomb.len() as u64, &bomb)[..], 1000).unwrap_err();
+    assert_eq!(error.to_string(), "wrong number of escapes for the job");
+}
+
+#[test]
+fn test_render_shares_out_rows() {
+    use std::net::TcpListener;
+    let options = RenderOptions {
+        bounds: (300, 700),
+        coloring: crate::Coloring::Distance,
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let worker = listener.local_addr().unwrap().to_string();
+    let own = options.clone();
+    let jobs = std::thread::spawn(move || {
+        // Take one connection's jobs, like `worker`.
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = io::BufReader::new(&stream);
+        let mut jobs = 0;
+        while let Some(job) = Job::read(&mut reader).unwrap() {
+            let options = job.options(&own).unwrap();
+            let escapes = Renderer::new(options).render_row_escapes(job.rows.clone());
+            write_escapes(&stream, Ok(&escapes)).unwrap();
+            jobs += 1;
+        }
+        jobs
+    });
+    // Nothing listens on port 1.
+    let workers = [worker, "127.0.0.1:1".to_string()];
+    let failures = Mutex::new(Vec::new());
+    let renderer = Renderer::new(options.clone());
+    let escapes = render(&renderer, &workers, |worker, _| {
+        failures.lock().unwrap().push(worker.to_string())
+    });
+    assert_eq!(escapes, Renderer::new(options).render_escapes());
+    assert!(renderer.progress().is_finished());
+    assert_eq!(
+        failures.into_inner().unwrap(),
+        vec!["127.0.0.1:1"; MAX_FAILURES as usize]
+    );
+    assert!(jobs.join().unwrap() > 0);
+    // A cancelled render hands out nothing.
+    let renderer = Renderer::new(RenderOptions {
+        bounds: (30, 70),
+        ..RenderOptions::default()
+    });
+    renderer.progress().cancel();
+    let escapes = render(&renderer, &workers[1..], |_, _| panic!("no job was sent"));
+    assert!(escapes.is_empty());
+}
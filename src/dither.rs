@@ -0,0 +1,248 @@
+//! Ordered dithering, which hides the bands that smooth gradients show in
+//! 8-bit channels. A color is rounded to bytes after a threshold that
+//! varies from pixel to pixel is added to it, so that a shade between two
+//! levels comes out as a fine mix of both, in the proportion of the shade.
+//!
+//! The thresholds are a Bayer matrix, whose regular crosshatch shows up
+//! close, or blue noise, which has no pattern to the eye: every stretch of
+//! it holds about as many high thresholds as low ones. The blue noise is
+//! made once by Ulichney's void and cluster method.
+
+use std::sync::OnceLock;
+
+/// The thresholds to dither with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// An 8×8 Bayer matrix.
+    Bayer,
+    /// A 64×64 tile of blue noise.
+    BlueNoise,
+}
+
+/// Names accepted by `Dither::named`, in the order they are listed in help.
+pub const NAMES: &[&str] = &["bayer", "blue-noise"];
+
+/// The side of the Bayer matrix.
+const BAYER_SIZE: u32 = 8;
+
+/// The side of the blue noise tile, which repeats across the image.
+const NOISE_SIZE: usize = 64;
+
+/// How far apart, in pixels, points of the blue noise push each other.
+const NOISE_SIGMA: f64 = 1.5;
+
+impl Dither {
+    /// Looks a dither up by name, as given to `--dither`.
+    pub fn named(name: &str) -> Option<Dither> {
+        match name {
+            "bayer" => Some(Dither::Bayer),
+            "blue-noise" => Some(Dither::BlueNoise),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dither::Bayer => "bayer",
+            Dither::BlueNoise => "blue-noise",
+        }
+    }
+
+    /// The threshold at pixel `(x, y)`, from -0.5 to 0.5.
+    pub fn threshold(self, (x, y): (u32, u32)) -> f64 {
+        let (rank, count) = match self {
+            Dither::Bayer => (
+                bayer(x % BAYER_SIZE, y % BAYER_SIZE),
+                BAYER_SIZE * BAYER_SIZE,
+            ),
+            Dither::BlueNoise => {
+                let index = (y as usize % NOISE_SIZE) * NOISE_SIZE + x as usize % NOISE_SIZE;
+                (blue_noise()[index] as u32, (NOISE_SIZE * NOISE_SIZE) as u32)
+            }
+        };
+        (rank as f64 + 0.5) / count as f64 - 0.5
+    }
+
+    /// Rounds `color`, each channel from 0 to 255, to bytes for pixel
+    /// `pixel`.
+    pub fn quantize(self, color: [f64; 3], pixel: (u32, u32)) -> [u8; 3] {
+        let threshold = self.threshold(pixel);
+        color.map(|v| (v + threshold).round().clamp(0.0, 255.0) as u8)
+    }
+}
+
+/// The rank of `(x, y)` in the Bayer matrix, built up from the 2×2 one
+/// with the lowest bits of the position most significant.
+fn bayer(x: u32, y: u32) -> u32 {
+    (0..BAYER_SIZE.trailing_zeros()).fold(0, |rank, bit| {
+        let (x, y) = ((x >> bit) & 1, (y >> bit) & 1);
+        rank << 2 | (2 * (x ^ y) + y)
+    })
+}
+
+/// The rank of every pixel of the blue noise tile, row by row, made the
+/// first time it is wanted.
+fn blue_noise() -> &'static [u16] {
+    static NOISE: OnceLock<Vec<u16>> = OnceLock::new();
+    NOISE.get_or_init(void_and_cluster)
+}
+
+/// Ranks the pixels of the tile so that those up to any rank are spread as
+/// evenly as they can be. Each pixel set pushes on the others by a Gaussian
+/// of its distance, wrapping around the edges; the set pixel pushed
+/// hardest is the tightest cluster and the unset one pushed least the
+/// largest void.
+fn void_and_cluster() -> Vec<u16> {
+    let n = NOISE_SIZE;
+    let kernel = (0..n * n)
+        .map(|i| {
+            let wrap = |d: usize| d.min(n - d) as f64;
+            let (dx, dy) = (wrap(i % n), wrap(i / n));
+            (-(dx * dx + dy * dy) / (2.0 * NOISE_SIGMA * NOISE_SIGMA)).exp()
+        })
+        .collect::<Vec<_>>();
+    let toggle = |energy: &mut [f64], set: &mut [bool], i: usize| {
+        set[i] = !set[i];
+        let sign = if set[i] { 1.0 } else { -1.0 };
+        let (x, y) = (i % n, i / n);
+        for (j, e) in energy.iter_mut().enumerate() {
+            let (dx, dy) = ((j % n + n - x) % n, (j / n + n - y) % n);
+            *e += sign * kernel[dy * n + dx];
+        }
+    };
+    let tightest = |energy: &[f64], set: &[bool]| {
+        (0..n * n)
+            .filter(|&i| set[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |energy: &[f64], set: &[bool]| {
+        (0..n * n)
+            .filter(|&i| !set[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // A tenth of the pixels, at random, moved one by one from the tightest
+    // cluster to the largest void until none is left to move.
+    let mut energy = vec![0.0; n * n];
+    let mut set = vec![false; n * n];
+    let mut state = 0u64;
+    let mut ones = 0;
+    while ones < n * n / 10 {
+        // SplitMix64.
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        let i = ((z ^ (z >> 31)) % (n * n) as u64) as usize;
+        if !set[i] {
+            toggle(&mut energy, &mut set, i);
+            ones += 1;
+        }
+    }
+    loop {
+        let cluster = tightest(&energy, &set);
+        toggle(&mut energy, &mut set, cluster);
+        let void = largest_void(&energy, &set);
+        toggle(&mut energy, &mut set, void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    // Those pixels take the ranks below `ones`, tightest last, and the rest
+    // the ranks above, filling the largest void first. Past half the tile,
+    // the largest void is the tightest cluster of the unset pixels.
+    let mut ranks = vec![0; n * n];
+    let (mut prototype, mut prototype_set) = (energy.clone(), set.clone());
+    for rank in (0..ones).rev() {
+        let cluster = tightest(&prototype, &prototype_set);
+        toggle(&mut prototype, &mut prototype_set, cluster);
+        ranks[cluster] = rank as u16;
+    }
+    for rank in ones..n * n {
+        let void = largest_void(&energy, &set);
+        toggle(&mut energy, &mut set, void);
+        ranks[void] = rank as u16;
+    }
+    ranks
+}
+
+#[test]
+fn test_bayer() {
+    let mut ranks = (0..64).map(|i| bayer(i % 8, i / 8)).collect::<Vec<_>>();
+    assert_eq!(ranks[..4], [0, 32, 8, 40]);
+    assert_eq!(ranks[8..12], [48, 16, 56, 24]);
+    ranks.sort();
+    assert_eq!(ranks, (0..64).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_blue_noise() {
+    let mut ranks = blue_noise().to_vec();
+    ranks.sort();
+    assert!(ranks
+        .iter()
+        .enumerate()
+        .all(|(i, &rank)| rank as usize == i));
+    // Any 8×8 block holds about as many thresholds above 0 as below, as
+    // white noise wouldn't.
+    for top in (0..NOISE_SIZE as u32).step_by(8) {
+        for left in (0..NOISE_SIZE as u32).step_by(8) {
+            let mean = (0..64)
+                .map(|i| Dither::BlueNoise.threshold((left + i % 8, top + i / 8)))
+                .sum::<f64>()
+                / 64.0;
+            assert!(mean.abs() < 0.05, "{} at {},{}", mean, left, top);
+        }
+    }
+}
+
+#[test]
+fn test_quantize() {
+    for dither in [Dither::Bayer, Dither::BlueNoise] {
+        // A shade a quarter of the way from 100 to 101 comes out as 101 in
+        // about a quarter of the pixels, and the levels stay put.
+        let mut high = 0;
+        for y in 0..64 {
+            for x in 0..64 {
+                let [r, g, b] = dither.quantize([100.25, 40.0, 255.0], (x, y));
+                assert!(r == 100 || r == 101);
+                assert_eq!((g, b), (40, 255));
+                high += (r == 101) as u32;
+            }
+        }
+        assert_eq!(high, 64 * 64 / 4, "{}", dither.name());
+    }
+}
+
+#[test]
+fn test_render_dithered() {
+    use crate::{Interior, Palette, RenderOptions, Renderer};
+    let options = RenderOptions {
+        bounds: (32, 24),
+        palette: Palette::named("fire").unwrap(),
+        interior: Interior::Modulus,
+        ..RenderOptions::default()
+    };
+    for antialias in [1, 2] {
+        let plain = RenderOptions {
+            antialias,
+            ..options.clone()
+        };
+        let rounded = Renderer::new(plain.clone()).render();
+        let renderer = Renderer::new(RenderOptions {
+            dither: Some(Dither::BlueNoise),
+            ..plain
+        });
+        let dithered = renderer.render();
+        assert_ne!(dithered, rounded);
+        assert!(dithered
+            .iter()
+            .zip(&rounded)
+            .all(|(&a, &b)| a.abs_diff(b) <= 1));
+        // Bands are dithered as the whole image is.
+        assert_eq!(renderer.rows().flatten().collect::<Vec<_>>(), dithered);
+    }
+}
@@ -0,0 +1,237 @@
+//! Double-double arithmetic for zooms somewhat too deep for `f64`.
+//!
+//! A `DoubleDouble` is the unevaluated sum of two `f64`s, the second no
+//! bigger than half a unit in the last place of the first, which together
+//! carry about 106 bits of mantissa. That covers pixels down to around
+//! `1e-30` apart at a fraction of the cost of `Fixed`, with nothing but
+//! `f64` operations whose rounding errors are recovered exactly.
+
+use crate::{Escape, Fixed};
+use num::Complex;
+use std::ops::{Add, Mul, Neg, Sub};
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct DoubleDouble {
+    hi: f64,
+    lo: f64,
+}
+
+/// Splits an `f64` into halves of 26 bits, so that their products are exact.
+const SPLITTER: f64 = 134_217_729.0;
+
+/// `a + b` and its rounding error.
+#[inline(always)]
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let v = s - a;
+    (s, (a - (s - v)) + (b - v))
+}
+
+/// `two_sum` for `|a| >= |b|`.
+#[inline(always)]
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    (s, b - (s - a))
+}
+
+#[inline(always)]
+fn split(a: f64) -> (f64, f64) {
+    let t = SPLITTER * a;
+    let hi = t - (t - a);
+    (hi, a - hi)
+}
+
+/// `a * b` and its rounding error, by Dekker's method.
+#[inline(always)]
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let p = a * b;
+    let (ah, al) = split(a);
+    let (bh, bl) = split(b);
+    (p, ((ah * bh - p) + ah * bl + al * bh) + al * bl)
+}
+
+impl DoubleDouble {
+    pub fn from_f64(x: f64) -> DoubleDouble {
+        DoubleDouble { hi: x, lo: 0.0 }
+    }
+
+    /// The nearest `DoubleDouble` to `x`, give or take the last bit.
+    pub fn from_fixed(x: &Fixed) -> DoubleDouble {
+        let hi = x.to_f64();
+        let lo = (x - &Fixed::from_f64(hi, x.bits())).to_f64();
+        let (hi, lo) = quick_two_sum(hi, lo);
+        DoubleDouble { hi, lo }
+    }
+
+    /// The nearest `f64`.
+    pub fn to_f64(self) -> f64 {
+        self.hi
+    }
+}
+
+impl Add for DoubleDouble {
+    type Output = DoubleDouble;
+
+    #[inline(always)]
+    fn add(self, other: DoubleDouble) -> DoubleDouble {
+        let (s, e) = two_sum(self.hi, other.hi);
+        let (t, f) = two_sum(self.lo, other.lo);
+        let (s, e) = quick_two_sum(s, e + t);
+        let (hi, lo) = quick_two_sum(s, e + f);
+        DoubleDouble { hi, lo }
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = DoubleDouble;
+
+    #[inline(always)]
+    fn sub(self, other: DoubleDouble) -> DoubleDouble {
+        self + -other
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = DoubleDouble;
+
+    #[inline(always)]
+    fn mul(self, other: DoubleDouble) -> DoubleDouble {
+        let (p, e) = two_product(self.hi, other.hi);
+        let (hi, lo) = quick_two_sum(p, e + (self.hi * other.lo + self.lo * other.hi));
+        DoubleDouble { hi, lo }
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = DoubleDouble;
+
+    #[inline(always)]
+    fn neg(self) -> DoubleDouble {
+        DoubleDouble {
+            hi: -self.hi,
+            lo: -self.lo,
+        }
+    }
+}
+
+#[test]
+fn test_double_double_arithmetic() {
+    let one = DoubleDouble::from_f64(1.0);
+    let tiny = DoubleDouble::from_f64(1e-20);
+    assert_eq!((one + tiny - one).to_f64(), 1e-20);
+    // (1 + 2^-60)² = 1 + 2^-59 + 2^-120, to 106 bits.
+    let a = one + DoubleDouble::from_f64(2f64.powi(-60));
+    let square = a * a - one;
+    assert_eq!(square.to_f64(), 2f64.powi(-59));
+    let third = Fixed::parse("0.333333333333333333333333333333333").unwrap();
+    let x = DoubleDouble::from_fixed(&third);
+    assert!(x.lo != 0.0);
+    let error = &third - &Fixed::from_f64(x.hi, third.bits());
+    assert!((error.to_f64() - x.lo).abs() < 1e-48);
+    assert_eq!((-x).to_f64(), -third.to_f64());
+}
+
+/// `crate::escape_time` in double-double arithmetic.
+pub fn escape_time(c: Complex<DoubleDouble>, limit: u32) -> Option<Escape> {
+    let zero = DoubleDouble::from_f64(0.0);
+    let (mut zr, mut zi) = (zero, zero);
+    for i in 0..limit {
+        let zr2 = zr * zr;
+        let zi2 = zi * zi;
+        if (zr2 + zi2).hi > 4.0 {
+            return Some(Escape {
+                iterations: i,
+                z: Complex {
+                    re: zr.to_f64(),
+                    im: zi.to_f64(),
+                },
+                derivative: None,
+                stripe: None,
+            });
+        }
+        let product = zr * zi;
+        zi = product + product + c.im;
+        zr = zr2 - zi2 + c.re;
+    }
+    None
+}
+
+#[test]
+fn test_double_double_escape_time_matches_f64() {
+    for &(re, im) in &[
+        (0.0, 0.0),
+        (-1.0, 0.0),
+        (1.0, 0.0),
+        (-0.75, 0.1),
+        (0.3, 0.5),
+    ] {
+        let c = Complex {
+            re: DoubleDouble::from_f64(re),
+            im: DoubleDouble::from_f64(im),
+        };
+        assert_eq!(
+            escape_time(c, 200).map(|e| e.iterations),
+            crate::escape_time(Complex { re, im }, 200).map(|e| e.iterations),
+            "{},{}",
+            re,
+            im
+        );
+    }
+}
+
+/// The point `position` pixels right of and below the upper left corner of
+/// a `bounds` view, like `fixed::position_to_point`.
+pub fn position_to_point(
+    bounds: (u32, u32),
+    position: (f64, f64),
+    upper_left: &Complex<Fixed>,
+    lower_right: &Complex<Fixed>,
+) -> Complex<DoubleDouble> {
+    let width = DoubleDouble::from_fixed(&(&lower_right.re - &upper_left.re));
+    let height = DoubleDouble::from_fixed(&(&upper_left.im - &lower_right.im));
+    let along = |x: f64, extent: u32| DoubleDouble::from_f64(x / extent.max(1) as f64);
+    Complex {
+        re: DoubleDouble::from_fixed(&upper_left.re) + width * along(position.0, bounds.0),
+        im: DoubleDouble::from_fixed(&upper_left.im) - height * along(position.1, bounds.1),
+    }
+}
+
+/// Whether double-double coordinates can still resolve pixels `pixel_size`
+/// apart near a point of magnitude `magnitude`, with the headroom
+/// `fixed::f64_suffices` leaves.
+pub fn suffices(pixel_size: f64, magnitude: f64) -> bool {
+    pixel_size.abs() > magnitude.abs().max(1.0) * f64::EPSILON * f64::EPSILON * 4096.0
+}
+
+#[test]
+fn test_double_double_deep_zoom_matches_fixed() {
+    let bits = 120;
+    let upper_left = Complex {
+        re: Fixed::parse("-0.74364388703715870475").unwrap(),
+        im: Fixed::parse("0.13182590420531197049").unwrap(),
+    };
+    let lower_right = Complex {
+        re: Fixed::parse("-0.74364388703715870465").unwrap(),
+        im: Fixed::parse("0.13182590420531197042").unwrap(),
+    };
+    let rescale = |z: &Complex<Fixed>| Complex {
+        re: z.re.with_bits(bits),
+        im: z.im.with_bits(bits),
+    };
+    let (upper_left, lower_right) = (rescale(&upper_left), rescale(&lower_right));
+    assert!(!suffices(1e-29, 0.75) && suffices(1e-27, 0.75));
+    assert!(!crate::fixed::f64_suffices(1e-20, 0.75));
+    // Points deep in the spiral escape after thousands of iterations.
+    for position in [(0.0, 0.0), (3.0, 1.0), (7.0, 5.0), (9.0, 6.0)] {
+        let exact = crate::fixed::position_to_point((10, 7), position, &upper_left, &lower_right);
+        let point = position_to_point((10, 7), position, &upper_left, &lower_right);
+        let expected = crate::fixed::escape_time(&exact, 10_000).map(|e| e.iterations);
+        assert!(expected.is_some());
+        assert_eq!(
+            escape_time(point, 10_000).map(|e| e.iterations),
+            expected,
+            "{:?}",
+            position
+        );
+    }
+}
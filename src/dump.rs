@@ -0,0 +1,244 @@
+//! A raw dump of a render's iteration counts, for recoloring or analyzing
+//! it later without computing it again. All numbers are little-endian:
+//!
+//! | bytes | contents                                                  |
+//! |-------|-----------------------------------------------------------|
+//! | 4     | magic `MBIT`                                              |
+//! | 1     | format version, 1                                         |
+//! | 1     | value kind: 0 for `u32` counts, 1 for `f64` smooth counts |
+//! | 2     | reserved, 0                                               |
+//! | 4 + 4 | width and height in pixels, `u32`                         |
+//! | 4     | maximum iterations, `u32`                                 |
+//! | 4 × 8 | upper left re, im and lower right re, im, `f64`           |
+//! | ...   | one value per pixel, in row-major order                   |
+//!
+//! Points in the set are stored as `u32::MAX`, or as -1 for smooth counts.
+//!
+//! The same values can be written as a NumPy `.npy` array instead, of
+//! `uint32` or `float64` in rows and columns, for `numpy.load`. It holds
+//! the values alone, without the view.
+
+use crate::{Escape, RenderOptions};
+use num::Complex;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"MBIT";
+const VERSION: u8 = 1;
+
+/// The per-pixel values of a dump.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Values {
+    Counts(Vec<u32>),
+    Smooth(Vec<f64>),
+}
+
+/// A dump read back from a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dump {
+    pub bounds: (u32, u32),
+    pub max_iter: u32,
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    pub values: Values,
+}
+
+impl Dump {
+    /// Builds the dump of a render's escape times: smooth counts if the
+    /// options ask for smooth coloring, whole ones otherwise.
+    pub fn new(escapes: &[Option<Escape>], options: &RenderOptions) -> Dump {
+        let values = if options.smooth {
+            Values::Smooth(
+                escapes
+                    .iter()
+                    .map(|escape| escape.map_or(-1.0, |e| e.smooth()))
+                    .collect(),
+            )
+        } else {
+            Values::Counts(
+                escapes
+                    .iter()
+                    .map(|escape| escape.map_or(u32::MAX, |e| e.iterations))
+                    .collect(),
+            )
+        };
+        Dump {
+            bounds: options.bounds,
+            max_iter: options.max_iter,
+            upper_left: options.upper_left,
+            lower_right: options.lower_right,
+            values,
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let kind = match self.values {
+            Values::Counts(_) => 0,
+            Values::Smooth(_) => 1,
+        };
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION, kind, 0, 0])?;
+        for value in [self.bounds.0, self.bounds.1, self.max_iter] {
+            w.write_all(&value.to_le_bytes())?;
+        }
+        for value in [
+            self.upper_left.re,
+            self.upper_left.im,
+            self.lower_right.re,
+            self.lower_right.im,
+        ] {
+            w.write_all(&value.to_le_bytes())?;
+        }
+        w.write_all(&self.value_bytes())
+    }
+
+    /// Writes the values as a version 1.0 `.npy` array, `height` rows of
+    /// `width`.
+    pub fn write_npy<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let descr = match self.values {
+            Values::Counts(_) => "<u4",
+            Values::Smooth(_) => "<f8",
+        };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': ({}, {}), }}",
+            descr, self.bounds.1, self.bounds.0
+        );
+        // The data starts 64-byte aligned, after a newline.
+        let length = 10 + header.len() + 1;
+        header.extend(std::iter::repeat_n(
+            ' ',
+            length.next_multiple_of(64) - length,
+        ));
+        header.push('\n');
+        w.write_all(b"\x93NUMPY\x01\x00")?;
+        w.write_all(&(header.len() as u16).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        w.write_all(&self.value_bytes())
+    }
+
+    fn value_bytes(&self) -> Vec<u8> {
+        match &self.values {
+            Values::Counts(counts) => counts.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            Values::Smooth(smooth) => smooth.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+
+    pub fn read<R: Read>(mut r: R) -> io::Result<Dump> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut header = [0; 52];
+        r.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not an iteration dump"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("unsupported iteration dump version"));
+        }
+        let word = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+        let float = |at: usize| f64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+        let bounds = (word(8), word(12));
+        let len = bounds.0 as usize * bounds.1 as usize;
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        let values = match header[5] {
+            0 if bytes.len() == 4 * len => Values::Counts(
+                bytes
+                    .chunks(4)
+                    .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+            ),
+            1 if bytes.len() == 8 * len => Values::Smooth(
+                bytes
+                    .chunks(8)
+                    .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+            ),
+            0 | 1 => return Err(invalid("iteration dump has the wrong length")),
+            _ => return Err(invalid("unknown iteration dump value kind")),
+        };
+        Ok(Dump {
+            bounds,
+            max_iter: word(16),
+            upper_left: Complex {
+                re: float(20),
+                im: float(28),
+            },
+            lower_right: Complex {
+                re: float(36),
+                im: float(44),
+            },
+            values,
+        })
+    }
+}
+
+#[test]
+fn test_dump_round_trip() {
+    let escapes = [
+        None,
+        Some(Escape {
+            iterations: 3,
+            z: Complex { re: 5.0, im: 0.0 },
+            derivative: None,
+            stripe: None,
+        }),
+    ];
+    let mut options = RenderOptions {
+        bounds: (2, 1),
+        max_iter: 40,
+        ..RenderOptions::default()
+    };
+    for smooth in [false, true] {
+        options.smooth = smooth;
+        let dump = Dump::new(&escapes, &options);
+        let mut bytes = Vec::new();
+        dump.write(&mut bytes).unwrap();
+        assert_eq!(
+            &bytes[..8],
+            &[b'M', b'B', b'I', b'T', 1, smooth as u8, 0, 0]
+        );
+        assert_eq!(Dump::read(&bytes[..]).unwrap(), dump);
+        if !smooth {
+            assert_eq!(bytes.len(), 52 + 8);
+            assert_eq!(dump.values, Values::Counts(vec![u32::MAX, 3]));
+        }
+        assert!(Dump::read(&bytes[..bytes.len() - 1]).is_err());
+    }
+    assert!(Dump::read(&b"PNG!"[..]).is_err());
+}
+
+#[test]
+fn test_write_npy() {
+    let escapes = [None, None, None]
+        .into_iter()
+        .chain([Some(Escape {
+            iterations: 7,
+            z: Complex { re: 5.0, im: 0.0 },
+            derivative: None,
+            stripe: None,
+        })])
+        .collect::<Vec<_>>();
+    let mut options = RenderOptions {
+        bounds: (2, 2),
+        smooth: false,
+        ..RenderOptions::default()
+    };
+    let mut bytes = Vec::new();
+    Dump::new(&escapes, &options).write_npy(&mut bytes).unwrap();
+    assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+    let length = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    assert_eq!((10 + length) % 64, 0);
+    let header = std::str::from_utf8(&bytes[10..10 + length]).unwrap();
+    assert!(header.starts_with("{'descr': '<u4', 'fortran_order': False, 'shape': (2, 2), }"));
+    assert!(header.ends_with(" \n"));
+    assert_eq!(bytes.len(), 10 + length + 4 * 4);
+    assert_eq!(bytes[bytes.len() - 4..], 7u32.to_le_bytes());
+
+    options.smooth = true;
+    let mut bytes = Vec::new();
+    Dump::new(&escapes, &options).write_npy(&mut bytes).unwrap();
+    let length = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    assert!(std::str::from_utf8(&bytes[10..10 + length])
+        .unwrap()
+        .contains("'<f8'"));
+    assert_eq!(bytes.len(), 10 + length + 4 * 8);
+    assert_eq!(bytes[10 + length..10 + length + 8], (-1.0f64).to_le_bytes());
+}
@@ -0,0 +1,46 @@
+//! The ways a command can fail, told apart so that callers can react to
+//! each, and so that the command line tool can exit with a distinct code
+//! for each. Every variant holds a message fit to show as it is, which
+//! names the file or option at fault.
+
+use std::{error::Error, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MandelbrotError {
+    /// Invalid options, from the command line, a file of them or a
+    /// `RenderOptionsBuilder`.
+    Parse(String),
+    /// A render that couldn't be carried out, such as a batch some of whose
+    /// jobs failed.
+    Render(String),
+    /// A file, stream or connection that couldn't be read or written.
+    Io(String),
+    /// A render cancelled before it was done, which tells what was kept of
+    /// it; see `Progress::cancel`.
+    Interrupted(String),
+}
+
+impl MandelbrotError {
+    /// The failure to write `path`, for `error`.
+    pub fn writing(path: &str, error: impl fmt::Display) -> MandelbrotError {
+        MandelbrotError::Io(format!("writing {}: {}", path, error))
+    }
+
+    /// The failure to read `path`, for `error`.
+    pub fn reading(path: &str, error: impl fmt::Display) -> MandelbrotError {
+        MandelbrotError::Io(format!("reading {}: {}", path, error))
+    }
+}
+
+impl fmt::Display for MandelbrotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MandelbrotError::Parse(message)
+            | MandelbrotError::Render(message)
+            | MandelbrotError::Io(message)
+            | MandelbrotError::Interrupted(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Error for MandelbrotError {}
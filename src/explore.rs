@@ -0,0 +1,599 @@
+//! `explore`: a terminal explorer. The view fills the terminal, drawn with
+//! half blocks as `--preview-term` draws it, above a status bar of where it
+//! is. The arrow keys pan, `+` and `-` zoom, `[` and `]` halve and double
+//! the iterations, and `r` renders the view in full, at the session's size,
+//! in the background while exploring goes on. `c` copies the command line
+//! that renders the view, through the terminal, and it's printed again on
+//! leaving, for terminals that don't let programs copy. Dragging with the
+//! mouse outlines a rectangle, of the shape of the session's size, which
+//! the view zooms to on letting go.
+//!
+//! The terminal is taken out of line mode with `stty` and drawn on with
+//! plain escape codes, so this needs a Unix terminal. The mouse is read
+//! from xterm's SGR reports, which most terminals send.
+
+use crate::cli::Session;
+use mandelbrot::{animation::View, ansi, fixed, Fixed, MandelbrotError, RenderOptions, Renderer};
+use num::Complex;
+use std::{
+    io::{self, BufWriter, IsTerminal, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+};
+
+const KEYS: &str =
+    "arrows pan  +/- zoom  drag zoom to box  [/] iterations  r render  c copy command  q quit";
+
+/// How far a press of an arrow key pans, in view heights.
+const PAN_STEP: f64 = 0.125;
+
+/// A key, or the left mouse button pressed, dragged or let go over the
+/// character cell at a column and row, counted from 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Char(char),
+    Press((u32, u32)),
+    Drag((u32, u32)),
+    Release((u32, u32)),
+}
+
+/// Left, top, width and height, in pixels of the preview.
+type Rectangle = (f64, f64, f64, f64);
+
+enum Event {
+    Key(Key),
+    Rendered(String, Result<(), MandelbrotError>),
+}
+
+/// What a key leaves to be done.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Nothing,
+    Redraw,
+    Render,
+    Copy,
+    Quit,
+}
+
+/// Explores until `q`, with the terminal put back as it was after.
+pub fn explore(mut session: Session) -> Result<(), MandelbrotError> {
+    let terminal = Terminal::enter()?;
+    let (sender, events) = mpsc::channel();
+    {
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let mut bytes = [0; 64];
+            while let Ok(read @ 1..) = io::stdin().lock().read(&mut bytes) {
+                for key in keys(&bytes[..read]) {
+                    if sender.send(Event::Key(key)).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+    }
+    let mut message = KEYS.to_string();
+    let mut renders = 1;
+    let mut copied = Vec::new();
+    let mut preview = Preview::render(&session)?;
+    // Where a drag with the mouse started and has got to, in pixels.
+    let mut drag = None;
+    preview.draw(&session, None, &message)?;
+    for event in events {
+        match event {
+            Event::Key(Key::Press(cell)) => {
+                drag = Some((point(cell), point(cell)));
+                continue;
+            }
+            Event::Key(Key::Drag(cell)) => match &mut drag {
+                Some((_, to)) => *to = point(cell),
+                None => continue,
+            },
+            Event::Key(Key::Release(cell)) => {
+                let Some((from, _)) = drag.take() else {
+                    continue;
+                };
+                // A click, rather than a drag, leaves the view be.
+                let picked = rectangle(from, point(cell), preview.aspect);
+                if picked.2 >= 1.0 {
+                    match zoom_to(&mut session, picked, preview.options.bounds) {
+                        Ok(()) => {
+                            message = KEYS.to_string();
+                            preview = Preview::render(&session)?;
+                        }
+                        Err(error) => message = error,
+                    }
+                }
+            }
+            Event::Key(key) => match press(&mut session, key) {
+                Ok(Action::Nothing) => continue,
+                Ok(Action::Redraw) => {
+                    message = KEYS.to_string();
+                    preview = Preview::render(&session)?;
+                }
+                Ok(Action::Render) => {
+                    let path = next_path(&mut renders);
+                    match session.frame(&path) {
+                        Ok(mut cli) => {
+                            cli.quiet = true;
+                            let sender = sender.clone();
+                            thread::spawn(move || {
+                                let rendered = crate::render(&cli);
+                                let _ = sender.send(Event::Rendered(cli.output, rendered));
+                            });
+                            message = format!("rendering {}", path);
+                        }
+                        Err(error) => message = error,
+                    }
+                }
+                Ok(Action::Copy) => match session.command_line("explore.png") {
+                    Ok(line) => {
+                        copy(&line)?;
+                        message = format!("copied {}", line);
+                        copied.push(line);
+                    }
+                    Err(error) => message = error,
+                },
+                Ok(Action::Quit) => break,
+                Err(error) => message = error,
+            },
+            Event::Rendered(path, Ok(())) => message = format!("saved {}", path),
+            Event::Rendered(_, Err(error)) => message = format!("error: {}", error),
+        }
+        let outline = drag.map(|(from, to)| rectangle(from, to, preview.aspect));
+        preview.draw(&session, outline, &message)?;
+    }
+    drop(terminal);
+    for line in copied {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// The keys in `bytes`, as a terminal out of line mode sends them. Escape
+/// sequences other than the arrows and the left mouse button are skipped.
+fn keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut bytes = bytes.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte != 0x1b || bytes.peek().is_none() {
+            keys.push(Key::Char(byte as char));
+            continue;
+        }
+        // CSI or SS3, then parameters, up to a final letter.
+        bytes.next();
+        let mut parameters = Vec::new();
+        let mut last = 0;
+        for byte in bytes.by_ref() {
+            last = byte;
+            if byte.is_ascii_alphabetic() || byte == b'~' {
+                break;
+            }
+            parameters.push(byte);
+        }
+        match last {
+            b'A' => keys.push(Key::Up),
+            b'B' => keys.push(Key::Down),
+            b'C' => keys.push(Key::Right),
+            b'D' => keys.push(Key::Left),
+            b'M' | b'm' => keys.extend(mouse(&parameters, last == b'm')),
+            _ => {}
+        }
+    }
+    keys
+}
+
+/// The left button's part in an SGR mouse report, `CSI < button ; column ;
+/// row` with the `parameters` between, ending in `m` if `released`. The
+/// modifier keys held down don't matter.
+fn mouse(parameters: &[u8], released: bool) -> Option<Key> {
+    let parameters = std::str::from_utf8(parameters.strip_prefix(b"<")?).ok()?;
+    let mut numbers = parameters.split(';').map(|n| n.parse::<u32>().ok());
+    let (button, column, row) = (numbers.next()??, numbers.next()??, numbers.next()??);
+    let cell = (column.checked_sub(1)?, row.checked_sub(1)?);
+    match (button & !0b11100, released) {
+        (0, false) => Some(Key::Press(cell)),
+        (32, false) => Some(Key::Drag(cell)),
+        (0, true) => Some(Key::Release(cell)),
+        _ => None,
+    }
+}
+
+/// The middle of the character cell at `column` and `row`, in pixels of
+/// the preview, which draws one across and two down in each.
+fn point((column, row): (u32, u32)) -> (f64, f64) {
+    (column as f64 + 0.5, 2.0 * row as f64 + 1.0)
+}
+
+/// The rectangle with a corner at `from` and the opposite one toward `to`,
+/// `aspect` times as wide as it is high and just big enough to reach both.
+fn rectangle(from: (f64, f64), to: (f64, f64), aspect: f64) -> Rectangle {
+    let (across, down) = (to.0 - from.0, to.1 - from.1);
+    let width = across.abs().max(down.abs() * aspect);
+    let height = width / aspect;
+    let left = if across < 0.0 { from.0 - width } else { from.0 };
+    let top = if down < 0.0 { from.1 - height } else { from.1 };
+    (left, top, width, height)
+}
+
+/// Zooms `session` to `rectangle` of its preview of `bounds` pixels, which
+/// fills the session's size if it is of its shape.
+fn zoom_to(session: &mut Session, rectangle: Rectangle, bounds: (u32, u32)) -> Result<(), String> {
+    let (left, top, width, height) = rectangle;
+    let (columns, rows) = (bounds.0 as f64, bounds.1 as f64);
+    let start = session.view.clone();
+    let right = (left + width / 2.0 - columns / 2.0) / rows;
+    let up = (rows / 2.0 - top - height / 2.0) / rows;
+    pan(&mut session.view, right, up);
+    session
+        .zoom_by(rows / height)
+        .inspect_err(|_| session.view = start)
+}
+
+/// What `key` does to `session`. A change that fails says why and leaves
+/// the session be.
+fn press(session: &mut Session, key: Key) -> Result<Action, String> {
+    match key {
+        Key::Up => pan(&mut session.view, 0.0, PAN_STEP),
+        Key::Down => pan(&mut session.view, 0.0, -PAN_STEP),
+        Key::Left => pan(&mut session.view, -PAN_STEP, 0.0),
+        Key::Right => pan(&mut session.view, PAN_STEP, 0.0),
+        Key::Char('+' | '=') => session.zoom_by(2.0)?,
+        Key::Char('-' | '_') => session.zoom_by(0.5)?,
+        Key::Char(c @ ('[' | ']')) => {
+            let max_iter = session.frame("-")?.options.max_iter;
+            let max_iter = match c {
+                ']' => max_iter.saturating_mul(2),
+                _ => (max_iter / 2).max(1),
+            };
+            session.set("max-iter", Some(&max_iter.to_string()))?;
+        }
+        // The mouse is followed by `explore`, which knows where it started.
+        Key::Press(_) | Key::Drag(_) | Key::Release(_) => return Ok(Action::Nothing),
+        Key::Char('r') => return Ok(Action::Render),
+        Key::Char('c') => return Ok(Action::Copy),
+        // Ctrl-C comes as a key, since the terminal sends no signals.
+        Key::Char('q' | '\x03') => return Ok(Action::Quit),
+        Key::Char(_) => return Ok(Action::Nothing),
+    }
+    Ok(Action::Redraw)
+}
+
+/// Moves the center of `view` by `right` and `up` view heights, as it is
+/// drawn, with the bits to place a pixel of it.
+fn pan(view: &mut View, right: f64, up: f64) {
+    let up = if view.flipped { -up } else { up };
+    let height = 4.0 / view.zoom;
+    let bits = fixed::bits_for_pixel_size(height / 10_000.0)
+        .max(view.center.re.bits())
+        .max(view.center.im.bits());
+    view.center = Complex {
+        re: &view.center.re.with_bits(bits) + &Fixed::from_f64(right * height, bits),
+        im: &view.center.im.with_bits(bits) + &Fixed::from_f64(up * height, bits),
+    };
+}
+
+/// The first `explore-NNN.png` from `n` on that doesn't exist yet, with `n`
+/// moved past it. Renders still running haven't made their files, so this
+/// keeps them from being given the same one.
+fn next_path(n: &mut u32) -> String {
+    loop {
+        let path = format!("explore-{:03}.png", n);
+        *n += 1;
+        if !Path::new(&path).exists() {
+            return path;
+        }
+    }
+}
+
+/// Puts `text` on the clipboard with an OSC 52 escape code, which the
+/// terminal may carry out or ignore.
+fn copy(text: &str) -> Result<(), MandelbrotError> {
+    let mut out = io::stdout().lock();
+    write!(out, "\x1b]52;c;{}\x07", base64(text.as_bytes()))
+        .and_then(|()| out.flush())
+        .map_err(|e| MandelbrotError::writing("stdout", e))
+}
+
+/// `bytes` in standard, padded base64.
+fn base64(bytes: &[u8]) -> String {
+    const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |group, (i, &byte)| group | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            text.push(match i <= chunk.len() {
+                true => DIGITS[(group >> (18 - 6 * i) & 63) as usize] as char,
+                false => '=',
+            });
+        }
+    }
+    text
+}
+
+/// The view rendered to fill the terminal, kept to outline a rectangle
+/// being dragged over without rendering it again.
+struct Preview {
+    pixels: Vec<u8>,
+    options: RenderOptions,
+    /// The width of the session's own size over its height.
+    aspect: f64,
+}
+
+impl Preview {
+    /// Renders the session's view at the size of the terminal.
+    fn render(session: &Session) -> Result<Preview, MandelbrotError> {
+        let (columns, rows) = Terminal::size();
+        let cli = session.frame("-").map_err(MandelbrotError::Parse)?;
+        let mut options = cli.options;
+        let aspect = options.bounds.0 as f64 / options.bounds.1 as f64;
+        options.bounds = (columns, 2 * rows.saturating_sub(1).max(1));
+        options.antialias = 1;
+        session.view.apply(&mut options);
+        let pixels = Renderer::new(options.clone()).render();
+        Ok(Preview {
+            pixels,
+            options,
+            aspect,
+        })
+    }
+
+    /// Draws the view over the whole terminal but its last line, with
+    /// `rectangle` outlined on it, and `message` in the status bar on that
+    /// line.
+    fn draw(
+        &self,
+        session: &Session,
+        rectangle: Option<Rectangle>,
+        message: &str,
+    ) -> Result<(), MandelbrotError> {
+        let options = &self.options;
+        let columns = options.bounds.0;
+        let pixels = match rectangle {
+            Some(rectangle) => outline(&self.pixels, options.bounds, rectangle),
+            None => self.pixels.clone(),
+        };
+        let (upper_left, lower_right) = options.exact_corners();
+        let digits = (-(options.pixel_size() / 10.0).log10()).ceil().max(0.0) as usize;
+        let status = format!(
+            " {} {}  zoom {:.3e}  iter {}  {}",
+            (&upper_left.re + &lower_right.re)
+                .scale(1, 2)
+                .to_decimal(digits),
+            (&upper_left.im + &lower_right.im)
+                .scale(1, 2)
+                .to_decimal(digits),
+            session.view.zoom,
+            options.max_iter,
+            message
+        );
+        let status = format!("{:width$}", status, width = columns as usize)
+            .chars()
+            .take(columns as usize)
+            .collect::<String>();
+        let mut out = BufWriter::new(io::stdout().lock());
+        write!(out, "\x1b[H")
+            .and_then(|()| ansi::encode(&mut out, &pixels, options.bounds))
+            .and_then(|()| write!(out, "\x1b[7m{}\x1b[0m", status))
+            .and_then(|()| out.flush())
+            .map_err(|e| MandelbrotError::writing("stdout", e))
+    }
+}
+
+/// `pixels` of an image of `bounds` with the edge of `rectangle` drawn on
+/// them, inverted to show up on any color. Edges off the image are drawn
+/// along its own.
+fn outline(pixels: &[u8], bounds: (u32, u32), rectangle: Rectangle) -> Vec<u8> {
+    let (left, top, width, height) = rectangle;
+    let within = |x: f64, size: u32| (x.max(0.0) as u32).min(size.max(1) - 1);
+    let (x0, x1) = (within(left, bounds.0), within(left + width, bounds.0));
+    let (y0, y1) = (within(top, bounds.1), within(top + height, bounds.1));
+    let mut pixels = pixels.to_vec();
+    for y in y0..=y1 {
+        for x in x0..=x1 {
+            if x == x0 || x == x1 || y == y0 || y == y1 {
+                let i = 3 * (y as usize * bounds.0 as usize + x as usize);
+                for channel in &mut pixels[i..i + 3] {
+                    *channel = 255 - *channel;
+                }
+            }
+        }
+    }
+    pixels
+}
+
+/// The terminal out of line mode, on its alternate screen and reporting
+/// the mouse, until this is dropped.
+struct Terminal {
+    saved: String,
+}
+
+impl Terminal {
+    fn enter() -> Result<Terminal, MandelbrotError> {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return Err(MandelbrotError::Parse(
+                "explore needs a terminal".to_string(),
+            ));
+        }
+        let terminal = Terminal {
+            saved: stty(&["-g"])?.trim().to_string(),
+        };
+        // From here on, dropping the terminal puts it back.
+        stty(&["-icanon", "-echo", "-isig", "min", "1"])?;
+        // Button events, for drags, in the SGR encoding.
+        print!("\x1b[?1049h\x1b[?25l\x1b[?1002h\x1b[?1006h");
+        Ok(terminal)
+    }
+
+    /// The columns and rows of the terminal, or 80 by 24 if it won't say.
+    fn size() -> (u32, u32) {
+        stty(&["size"])
+            .ok()
+            .and_then(|size| {
+                let (rows, columns) = size.trim().split_once(' ')?;
+                Some((columns.parse().ok()?, rows.parse().ok()?))
+            })
+            .filter(|&(columns, rows)| columns > 0 && rows > 1)
+            .unwrap_or((80, 24))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[?1006l\x1b[?1002l\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Runs `stty` on the terminal, for what it prints.
+fn stty(args: &[&str]) -> Result<String, MandelbrotError> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| MandelbrotError::Io(format!("running stty: {}", e)))?;
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => Err(MandelbrotError::Io(format!(
+            "stty {} failed",
+            args.join(" ")
+        ))),
+    }
+}
+
+#[test]
+fn test_keys() {
+    assert_eq!(
+        keys(b"\x1b[A\x1b[Bq\x1bOC\x1b[1;5D\x1b[3~+"),
+        [
+            Key::Up,
+            Key::Down,
+            Key::Char('q'),
+            Key::Right,
+            Key::Left,
+            Key::Char('+')
+        ]
+    );
+    assert_eq!(keys(b"\x1b"), [Key::Char('\x1b')]);
+    assert_eq!(
+        keys(b"\x1b[<0;3;2M\x1b[<32;10;4M\x1b[<2;1;1M\x1b[<40;11;5M\x1b[<0;11;5m"),
+        [
+            Key::Press((2, 1)),
+            Key::Drag((9, 3)),
+            Key::Drag((10, 4)),
+            Key::Release((10, 4))
+        ]
+    );
+    assert_eq!(keys(b"\x1b[<0;0;1M\x1b[<0;1M"), []);
+}
+
+#[test]
+fn test_rectangle() {
+    // Dragged further across than down for its shape, it grows downward.
+    assert_eq!(
+        rectangle((10.0, 10.0), (18.0, 11.0), 2.0),
+        (10.0, 10.0, 8.0, 4.0)
+    );
+    // Up and to the left, it grows up and left from where the drag began.
+    assert_eq!(
+        rectangle((10.0, 10.0), (9.0, 4.0), 2.0),
+        (-2.0, 4.0, 12.0, 6.0)
+    );
+    assert_eq!(point((3, 2)), (3.5, 5.0));
+
+    let pixels = outline(&[0; 3 * 5 * 4], (5, 4), (1.0, 1.0, 2.0, 5.0));
+    let inverted = |x: usize, y: usize| pixels[3 * (5 * y + x)] == 255;
+    assert!(inverted(1, 1) && inverted(3, 3) && inverted(1, 2));
+    assert!(!inverted(2, 2) && !inverted(0, 0) && !inverted(4, 1));
+}
+
+#[test]
+fn test_zoom_to() {
+    use crate::cli::{parse_args, Command};
+    let args = ["explore", "-s", "80x60"].map(String::from);
+    let Ok(Command::Explore(session)) = parse_args(&args) else {
+        panic!("explore didn't parse");
+    };
+    let mut session = *session;
+    let start = session.view.clone();
+    // The right half of a preview 80 by 40, at the middle height.
+    zoom_to(&mut session, (40.0, 5.0, 40.0, 30.0), (80, 40)).unwrap();
+    let height = 4.0 / start.zoom;
+    let moved = session.view.center.re.to_f64() - start.center.re.to_f64();
+    assert!((moved - 0.5 * height).abs() < 1e-12, "{}", moved);
+    assert_eq!(session.view.center.im, start.center.im);
+    assert!((session.view.zoom / start.zoom - 40.0 / 30.0).abs() < 1e-12);
+    // A zoom too deep is refused, leaving the view be.
+    let before = session.view.clone();
+    assert!(zoom_to(&mut session, (0.0, 0.0, 1e-310, 1e-310), (80, 40)).is_err());
+    assert_eq!(session.view, before);
+}
+
+#[test]
+fn test_press() {
+    use crate::cli::{parse_args, Command};
+    let args = ["explore", "-i", "100"].map(String::from);
+    let Ok(Command::Explore(session)) = parse_args(&args) else {
+        panic!("explore didn't parse");
+    };
+    let mut session = *session;
+    let start = session.view.clone();
+    assert_eq!(press(&mut session, Key::Right), Ok(Action::Redraw));
+    assert_eq!(press(&mut session, Key::Up), Ok(Action::Redraw));
+    let height = 4.0 / start.zoom;
+    let moved = session.view.center.re.to_f64() - start.center.re.to_f64();
+    assert!((moved - PAN_STEP * height).abs() < 1e-12);
+    let moved = session.view.center.im.to_f64() - start.center.im.to_f64();
+    assert!((moved - PAN_STEP * height).abs() < 1e-12);
+    press(&mut session, Key::Char('+')).unwrap();
+    press(&mut session, Key::Char('+')).unwrap();
+    press(&mut session, Key::Char('-')).unwrap();
+    assert_eq!(session.view.zoom, 2.0 * start.zoom);
+    // Zooming stops short of where the view can't be drawn.
+    let zoomed = session.view.clone();
+    for key in ['-', '+'] {
+        while press(&mut session, Key::Char(key)).is_ok() {}
+        press(&mut session, Key::Left).unwrap();
+        session.frame("far.png").unwrap();
+    }
+    session.view = zoomed;
+    // Upside down, up is still up on screen.
+    session.view.flipped = true;
+    let im = session.view.center.im.to_f64();
+    press(&mut session, Key::Up).unwrap();
+    assert!(session.view.center.im.to_f64() < im);
+    press(&mut session, Key::Char(']')).unwrap();
+    assert_eq!(session.frame("-").unwrap().options.max_iter, 200);
+    assert_eq!(press(&mut session, Key::Char('x')), Ok(Action::Nothing));
+    assert_eq!(press(&mut session, Key::Char('r')), Ok(Action::Render));
+    assert_eq!(press(&mut session, Key::Char('c')), Ok(Action::Copy));
+    assert_eq!(press(&mut session, Key::Char('\x03')), Ok(Action::Quit));
+}
+
+#[test]
+fn test_next_path() {
+    let mut n = 1_000_000;
+    assert_eq!(next_path(&mut n), "explore-1000000.png");
+    assert_eq!(next_path(&mut n), "explore-1000001.png");
+    assert_eq!(n, 1_000_002);
+}
+
+#[test]
+fn test_base64() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foo"), "Zm9v");
+    assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+}
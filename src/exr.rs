@@ -0,0 +1,175 @@
+//! OpenEXR output of the raw escape data rather than colors, for HDR
+//! post-processing. Files are single part, scanline, uncompressed and hold
+//! 32-bit float channels, which every EXR reader understands.
+
+use crate::Escape;
+use std::io::{self, Write};
+
+/// Names of the channels `encode_escapes` writes, in the alphabetical order
+/// EXR requires.
+pub const CHANNELS: [&str; 2] = ["escape", "potential"];
+
+/// Encodes the escape times of a render as two float channels: `escape`,
+/// the (smooth with `smooth`) iteration count, and `potential`, the
+/// continuous potential `ln |z| / 2^n`, which underflows to 0 for counts in
+/// the thousands. Points in the set have an `escape` of -1 and a `potential`
+/// of 0.
+pub fn encode_escapes<W: Write>(
+    w: W,
+    escapes: &[Option<Escape>],
+    bounds: (u32, u32),
+    smooth: bool,
+) -> io::Result<()> {
+    let escape = escapes
+        .iter()
+        .map(|escape| match escape {
+            None => -1.0,
+            Some(e) if smooth => e.smooth() as f32,
+            Some(e) => e.iterations as f32,
+        })
+        .collect::<Vec<_>>();
+    let potential = escapes
+        .iter()
+        .map(|escape| match escape {
+            None => 0.0,
+            Some(e) => {
+                let log_modulus = e.z.norm_sqr().ln() / 2.0;
+                (log_modulus * 2f64.powi(-(e.iterations.min(i32::MAX as u32) as i32))) as f32
+            }
+        })
+        .collect::<Vec<_>>();
+    encode(
+        w,
+        &[(CHANNELS[0], &escape), (CHANNELS[1], &potential)],
+        bounds,
+    )
+}
+
+/// Encodes float channels, each of `bounds.0 * bounds.1` row-major values,
+/// as an EXR image. Channels must be given sorted by name.
+pub fn encode<W: Write>(
+    mut w: W,
+    channels: &[(&str, &[f32])],
+    bounds: (u32, u32),
+) -> io::Result<()> {
+    let (width, height) = (bounds.0 as usize, bounds.1 as usize);
+    let invalid = |message| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    if width == 0 || height == 0 || width > i32::MAX as usize || height > i32::MAX as usize {
+        return invalid("EXR images need between 1 and 2^31 - 1 pixels a side");
+    }
+    if !channels.windows(2).all(|pair| pair[0].0 < pair[1].0) {
+        return invalid("EXR channels must be sorted by name");
+    }
+
+    let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    let mut list = Vec::new();
+    for (name, _) in channels {
+        list.extend_from_slice(name.as_bytes());
+        list.push(0);
+        // Pixel type FLOAT, not perceptually linear, no subsampling.
+        list.extend_from_slice(&2i32.to_le_bytes());
+        list.extend_from_slice(&[0, 0, 0, 0]);
+        list.extend_from_slice(&1i32.to_le_bytes());
+        list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    list.push(0);
+    attribute(&mut header, "channels", "chlist", &list);
+    attribute(&mut header, "compression", "compression", &[0]);
+    let window = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect::<Vec<_>>();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+    w.write_all(&header)?;
+
+    // One scanline per block: its row number, its size, then each channel's
+    // values for the row in turn.
+    let row_size = channels.len() * width * 4;
+    let first_block = header.len() + 8 * height;
+    for y in 0..height {
+        w.write_all(&((first_block + y * (8 + row_size)) as u64).to_le_bytes())?;
+    }
+    let mut block = Vec::with_capacity(8 + row_size);
+    for y in 0..height {
+        block.clear();
+        block.extend_from_slice(&(y as i32).to_le_bytes());
+        block.extend_from_slice(&(row_size as i32).to_le_bytes());
+        for (_, values) in channels {
+            for value in &values[y * width..(y + 1) * width] {
+                block.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        w.write_all(&block)?;
+    }
+    Ok(())
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[test]
+fn test_encode() {
+    let a = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+    let b = [0.5; 6];
+    let mut bytes = Vec::new();
+    encode(&mut bytes, &[("a", &a), ("b", &b)], (3, 2)).unwrap();
+    assert_eq!(&bytes[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+    // The offsets table points at each scanline block in turn.
+    let row_size = 2 * 3 * 4;
+    let offset = |y: usize| {
+        let at = bytes.len() - 2 * (8 + row_size) - 16 + 8 * y;
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize
+    };
+    assert_eq!(offset(1) - offset(0), 8 + row_size);
+    let second = offset(1);
+    assert_eq!(&bytes[second..second + 4], &1i32.to_le_bytes());
+    let value = |at: usize| f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    assert_eq!(value(second + 8), 4.0);
+    assert_eq!(value(second + 8 + 12), 0.5);
+    assert_eq!(offset(1) + 8 + row_size, bytes.len());
+    assert!(encode(Vec::new(), &[("b", &b), ("a", &a)], (3, 2)).is_err());
+}
+
+#[test]
+fn test_encode_escapes() {
+    use num::Complex;
+
+    let escapes = [
+        None,
+        Some(Escape {
+            iterations: 3,
+            z: Complex { re: 5.0, im: 0.0 },
+            derivative: None,
+            stripe: None,
+        }),
+    ];
+    let mut bytes = Vec::new();
+    encode_escapes(&mut bytes, &escapes, (2, 1), false).unwrap();
+    let value = |at: usize| f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    let data = bytes.len() - 16;
+    assert_eq!([value(data), value(data + 4)], [-1.0, 3.0]);
+    assert_eq!(value(data + 8), 0.0);
+    assert!((value(data + 12) - (5f32.ln() / 8.0)).abs() < 1e-6);
+}
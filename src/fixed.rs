@@ -0,0 +1,449 @@
+//! Arbitrary precision fixed point arithmetic for zooms too deep for `f64`.
+//!
+//! A `Fixed` is an integer scaled by `2^-bits`. All the values taking part in
+//! one render share the same `bits`, so the hot loop is plain big integer
+//! multiplication and shifting with no exponent bookkeeping.
+
+use crate::Escape;
+use num::{bigint::Sign, BigInt, Complex, ToPrimitive, Zero};
+use std::{
+    fmt,
+    ops::{Add, Mul, Neg, Sub},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixed {
+    raw: BigInt,
+    bits: u32,
+}
+
+/// Fractional bits kept beyond those needed to represent the input exactly.
+const GUARD_BITS: u32 = 64;
+
+impl Fixed {
+    /// Parses a decimal number such as `-0.743643887037158704752191506114774`
+    /// or `1.5e-30`, keeping enough fractional bits to represent every digit.
+    pub fn parse(s: &str) -> Option<Fixed> {
+        let (mantissa, exponent) = match s.find(['e', 'E']) {
+            Some(index) => (&s[..index], s[index + 1..].parse::<i32>().ok()?),
+            None => (s, 0),
+        };
+        let (negative, mantissa) = match mantissa.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+        };
+        let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if whole.is_empty() && fraction.is_empty()
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        let digits = BigInt::parse_bytes(format!("0{}{}", whole, fraction).as_bytes(), 10)?;
+        let exponent = exponent - fraction.len() as i32;
+        let digits = if negative { -digits } else { digits };
+        if exponent >= 0 {
+            Some(Fixed {
+                raw: (digits * BigInt::from(10).pow(exponent as u32)) << GUARD_BITS,
+                bits: GUARD_BITS,
+            })
+        } else {
+            let scale = -exponent as u32;
+            let bits = (scale as f64 * std::f64::consts::LOG2_10).ceil() as u32 + GUARD_BITS;
+            Some(Fixed {
+                raw: div_round(digits << bits, &BigInt::from(10).pow(scale)),
+                bits,
+            })
+        }
+    }
+
+    /// Converts `x` exactly if `bits` is large enough, otherwise rounding
+    /// towards negative infinity.
+    pub fn from_f64(x: f64, bits: u32) -> Fixed {
+        assert!(
+            x.is_finite(),
+            "cannot represent {} as a fixed point number",
+            x
+        );
+        if x == 0.0 {
+            return Fixed::zero(bits);
+        }
+        let raw_bits = x.to_bits();
+        let exponent = ((raw_bits >> 52) & 0x7ff) as i64;
+        let fraction = raw_bits & ((1 << 52) - 1);
+        let (mantissa, exponent) = if exponent == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | (1 << 52), exponent - 1075)
+        };
+        let mantissa = BigInt::from(mantissa);
+        let mantissa = if x < 0.0 { -mantissa } else { mantissa };
+        let shift = exponent + bits as i64;
+        let raw = if shift >= 0 {
+            mantissa << shift as u64
+        } else {
+            mantissa >> (-shift) as u64
+        };
+        Fixed { raw, bits }
+    }
+
+    pub fn zero(bits: u32) -> Fixed {
+        Fixed {
+            raw: BigInt::zero(),
+            bits,
+        }
+    }
+
+    /// Number of fractional bits.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Rescales to `bits` fractional bits, rounding towards negative infinity
+    /// when bits are dropped.
+    pub fn with_bits(&self, bits: u32) -> Fixed {
+        let raw = if bits >= self.bits {
+            &self.raw << (bits - self.bits)
+        } else {
+            &self.raw >> (self.bits - bits)
+        };
+        Fixed { raw, bits }
+    }
+
+    /// The nearest `f64`, give or take the last bit.
+    pub fn to_f64(&self) -> f64 {
+        let excess = self.raw.bits().saturating_sub(64);
+        let mantissa = (&self.raw >> excess).to_f64().unwrap_or(0.0);
+        mantissa * (excess as f64 - self.bits as f64).exp2()
+    }
+
+    /// Multiplies by a small integer and divides by another, as needed to
+    /// step across the pixels of a view.
+    pub fn scale(&self, numerator: u32, denominator: u32) -> Fixed {
+        Fixed {
+            raw: div_round(&self.raw * numerator, &BigInt::from(denominator)),
+            bits: self.bits,
+        }
+    }
+
+    /// Formats as a decimal number rounded to `digits` places after the
+    /// point, which `parse` reads back.
+    pub fn to_decimal(&self, digits: usize) -> String {
+        let scaled = div_round(
+            &self.raw * BigInt::from(10).pow(digits as u32),
+            &(BigInt::from(1) << self.bits),
+        );
+        let sign = if scaled.sign() == Sign::Minus {
+            "-"
+        } else {
+            ""
+        };
+        let magnitude = format!("{:0>1$}", scaled.magnitude(), digits + 1);
+        let (whole, fraction) = magnitude.split_at(magnitude.len() - digits);
+        if digits == 0 {
+            format!("{}{}", sign, whole)
+        } else {
+            format!("{}{}.{}", sign, whole, fraction)
+        }
+    }
+
+    /// Formats as the decimal number that `parse` reads back as exactly this
+    /// one, bits and all. One with bits no number of digits gives `parse`
+    /// is written with all of them, which reads back as the same value.
+    pub fn to_exact_decimal(&self) -> String {
+        let digits = self.bits.saturating_sub(GUARD_BITS) as f64 / std::f64::consts::LOG2_10;
+        let digits = digits.floor() as usize;
+        for digits in digits.saturating_sub(1)..=digits + 1 {
+            let decimal = self.to_decimal(digits);
+            if Fixed::parse(&decimal).as_ref() == Some(self) {
+                return decimal;
+            }
+        }
+        match self.bits {
+            0 => self.to_decimal(0),
+            bits => {
+                let decimal = self.to_decimal(bits as usize);
+                decimal
+                    .trim_end_matches('0')
+                    .trim_end_matches('.')
+                    .to_string()
+            }
+        }
+    }
+}
+
+/// Returned when a string is not a decimal number.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseFixedError;
+
+impl fmt::Display for ParseFixedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid decimal number")
+    }
+}
+
+impl std::error::Error for ParseFixedError {}
+
+impl FromStr for Fixed {
+    type Err = ParseFixedError;
+    fn from_str(s: &str) -> Result<Fixed, ParseFixedError> {
+        Fixed::parse(s).ok_or(ParseFixedError)
+    }
+}
+
+fn div_round(numerator: BigInt, denominator: &BigInt) -> BigInt {
+    let half: BigInt = denominator / 2;
+    if numerator.sign() == Sign::Minus {
+        let magnitude: BigInt = (-numerator + half) / denominator;
+        -magnitude
+    } else {
+        (numerator + half) / denominator
+    }
+}
+
+impl Add for &Fixed {
+    type Output = Fixed;
+    fn add(self, other: &Fixed) -> Fixed {
+        assert_eq!(self.bits, other.bits);
+        Fixed {
+            raw: &self.raw + &other.raw,
+            bits: self.bits,
+        }
+    }
+}
+
+impl Sub for &Fixed {
+    type Output = Fixed;
+    fn sub(self, other: &Fixed) -> Fixed {
+        assert_eq!(self.bits, other.bits);
+        Fixed {
+            raw: &self.raw - &other.raw,
+            bits: self.bits,
+        }
+    }
+}
+
+impl Mul for &Fixed {
+    type Output = Fixed;
+    fn mul(self, other: &Fixed) -> Fixed {
+        assert_eq!(self.bits, other.bits);
+        Fixed {
+            raw: (&self.raw * &other.raw) >> self.bits,
+            bits: self.bits,
+        }
+    }
+}
+
+impl Neg for &Fixed {
+    type Output = Fixed;
+    fn neg(self) -> Fixed {
+        Fixed {
+            raw: -&self.raw,
+            bits: self.bits,
+        }
+    }
+}
+
+impl PartialOrd for Fixed {
+    fn partial_cmp(&self, other: &Fixed) -> Option<std::cmp::Ordering> {
+        let bits = self.bits.max(other.bits);
+        let (a, b) = (self.with_bits(bits), other.with_bits(bits));
+        Some(a.raw.cmp(&b.raw))
+    }
+}
+
+#[test]
+fn test_parse_fixed() {
+    assert_eq!(Fixed::parse("1.25").unwrap().to_f64(), 1.25);
+    assert_eq!(Fixed::parse("-0.0625").unwrap().to_f64(), -0.0625);
+    assert_eq!(Fixed::parse("+3").unwrap().to_f64(), 3.0);
+    assert_eq!(Fixed::parse("2e3").unwrap().to_f64(), 2000.0);
+    assert_eq!(Fixed::parse(".5").unwrap().to_f64(), 0.5);
+    assert_eq!(Fixed::parse("-1.5e-30").unwrap().to_f64(), -1.5e-30);
+    assert_eq!(Fixed::parse(""), None);
+    assert_eq!(Fixed::parse("-"), None);
+    assert_eq!(Fixed::parse("1.2.3"), None);
+    assert_eq!(Fixed::parse("0x10"), None);
+    // Digits far beyond f64 precision are kept.
+    let a = Fixed::parse("-0.7436438870371587047521915061").unwrap();
+    let b = Fixed::parse("-0.7436438870371587047521915062").unwrap();
+    assert!(b < a);
+    assert!(((&a.with_bits(200) - &b.with_bits(200)).to_f64() - 1e-28).abs() < 1e-40);
+}
+
+#[test]
+fn test_fixed_to_decimal() {
+    let a = Fixed::parse("-0.7436438870371587047521915061").unwrap();
+    assert_eq!(a.to_decimal(28), "-0.7436438870371587047521915061");
+    assert_eq!(a.to_decimal(3), "-0.744");
+    assert_eq!(Fixed::from_f64(0.004, 64).to_decimal(2), "0.00");
+    assert_eq!(Fixed::from_f64(-12.5, 64).to_decimal(0), "-13");
+    assert_eq!(Fixed::from_f64(0.0625, 8).to_decimal(6), "0.062500");
+
+    for text in [
+        "-0.7436438870371587047521915061",
+        "-2.6666666666666665",
+        "3",
+        "0.10",
+    ] {
+        let a = Fixed::parse(text).unwrap();
+        assert_eq!(a.to_exact_decimal(), text);
+    }
+    let third = Fixed::from_f64(1.0 / 3.0, 80);
+    let decimal = third.to_exact_decimal();
+    assert!(decimal.starts_with("0.33333333333333331482961625624739"));
+    assert_eq!(Fixed::parse(&decimal).unwrap().with_bits(80), third);
+    assert_eq!(Fixed::from_f64(-12.5, 64).to_exact_decimal(), "-12.5");
+    assert_eq!(Fixed::from_f64(100.0, 0).to_exact_decimal(), "100");
+}
+
+#[test]
+fn test_fixed_arithmetic() {
+    let bits = 80;
+    let a = Fixed::from_f64(1.5, bits);
+    let b = Fixed::from_f64(-0.25, bits);
+    assert_eq!((&a + &b).to_f64(), 1.25);
+    assert_eq!((&a - &b).to_f64(), 1.75);
+    assert_eq!((&a * &b).to_f64(), -0.375);
+    assert_eq!((-&a).to_f64(), -1.5);
+    assert_eq!(a.scale(3, 4).to_f64(), 1.125);
+    assert_eq!(Fixed::from_f64(1e-300, 1100).to_f64(), 1e-300);
+    assert_eq!(Fixed::from_f64(-2.75, 4).with_bits(1).to_f64(), -3.0);
+}
+
+/// `escape_time` in fixed point arithmetic with `c`'s precision.
+pub fn escape_time(c: &Complex<Fixed>, limit: u32) -> Option<Escape> {
+    let bits = c.re.bits;
+    let (cr, ci) = (&c.re.raw, &c.im.raw);
+    let four = BigInt::from(4) << (2 * bits);
+    let mut zr = BigInt::zero();
+    let mut zi = BigInt::zero();
+    for i in 0..limit {
+        let zr2 = &zr * &zr;
+        let zi2 = &zi * &zi;
+        if &zr2 + &zi2 > four {
+            let z = |raw: &BigInt| {
+                Fixed {
+                    raw: raw.clone(),
+                    bits,
+                }
+                .to_f64()
+            };
+            return Some(Escape {
+                iterations: i,
+                z: Complex {
+                    re: z(&zr),
+                    im: z(&zi),
+                },
+                derivative: None,
+                stripe: None,
+            });
+        }
+        zi = ((&zr * &zi) >> (bits - 1)) + ci;
+        zr = ((zr2 - zi2) >> bits) + cr;
+    }
+    None
+}
+
+#[test]
+fn test_fixed_escape_time_matches_f64() {
+    for &(re, im) in &[
+        (0.0, 0.0),
+        (-1.0, 0.0),
+        (1.0, 0.0),
+        (-0.75, 0.1),
+        (0.3, 0.5),
+    ] {
+        let c = Complex {
+            re: Fixed::from_f64(re, 120),
+            im: Fixed::from_f64(im, 120),
+        };
+        assert_eq!(
+            escape_time(&c, 200).map(|e| e.iterations),
+            crate::escape_time(Complex { re, im }, 200).map(|e| e.iterations),
+            "{},{}",
+            re,
+            im
+        );
+    }
+}
+
+/// `render` for a view given to `bits` fractional bits.
+pub fn render(
+    escapes: &mut [Option<Escape>],
+    bounds: (u32, u32),
+    upper_left: &Complex<Fixed>,
+    lower_right: &Complex<Fixed>,
+    limit: u32,
+    bits: u32,
+) {
+    let upper_left = Complex {
+        re: upper_left.re.with_bits(bits),
+        im: upper_left.im.with_bits(bits),
+    };
+    let width = &lower_right.re.with_bits(bits) - &upper_left.re;
+    let height = &upper_left.im - &lower_right.im.with_bits(bits);
+    for row in 0..bounds.1 {
+        let im = &upper_left.im - &height.scale(row, bounds.1);
+        for column in 0..bounds.0 {
+            let c = Complex {
+                re: &upper_left.re + &width.scale(column, bounds.0),
+                im: im.clone(),
+            };
+            escapes[(row * bounds.0 + column) as usize] = escape_time(&c, limit);
+        }
+    }
+}
+
+/// The point `position` pixels right of and below the upper left corner of
+/// a `bounds` view, whose corners must share one number of fractional bits.
+/// Fractional positions fall between pixels.
+pub fn position_to_point(
+    bounds: (u32, u32),
+    position: (f64, f64),
+    upper_left: &Complex<Fixed>,
+    lower_right: &Complex<Fixed>,
+) -> Complex<Fixed> {
+    let bits = upper_left.re.bits();
+    let width = &lower_right.re - &upper_left.re;
+    let height = &upper_left.im - &lower_right.im;
+    let along = |x: f64, extent: u32| Fixed::from_f64(x / extent.max(1) as f64, bits);
+    Complex {
+        re: &upper_left.re + &(&width * &along(position.0, bounds.0)),
+        im: &upper_left.im - &(&height * &along(position.1, bounds.1)),
+    }
+}
+
+/// Number of fractional bits needed to tell apart neighboring pixels that
+/// are `pixel_size` apart, with some to spare for the rounding errors that
+/// iteration amplifies.
+pub fn bits_for_pixel_size(pixel_size: f64) -> u32 {
+    let needed = -pixel_size.abs().max(f64::MIN_POSITIVE).log2();
+    needed.max(0.0).ceil() as u32 + GUARD_BITS
+}
+
+/// Whether `f64` coordinates can still resolve pixels `pixel_size` apart
+/// near a point of magnitude `magnitude`, leaving a dozen bits of headroom.
+pub fn f64_suffices(pixel_size: f64, magnitude: f64) -> bool {
+    pixel_size.abs() > magnitude.abs().max(1.0) * f64::EPSILON * 4096.0
+}
+
+/// `f64_suffices` for `f32`, leaving eight bits of headroom, as its
+/// previews can afford a little more rounding.
+pub fn f32_suffices(pixel_size: f64, magnitude: f64) -> bool {
+    pixel_size.abs() > magnitude.abs().max(1.0) * f32::EPSILON as f64 * 256.0
+}
+
+#[test]
+fn test_precision_thresholds() {
+    assert!(f64_suffices(3.0 / 1000.0, 2.0));
+    assert!(f64_suffices(1e-12, 0.75));
+    assert!(!f64_suffices(1e-14, 0.75));
+    assert!(f32_suffices(3.0 / 1000.0, 2.0));
+    assert!(!f32_suffices(1e-5, 0.75));
+    assert!(bits_for_pixel_size(1e-30) >= 100 + GUARD_BITS);
+    assert_eq!(bits_for_pixel_size(0.5), 1 + GUARD_BITS);
+}
@@ -0,0 +1,559 @@
+//! Formulas given as text, to try out new fractals without recompiling.
+//! A formula is an expression in `z`, the point the orbit is at, and `c`,
+//! which gives the orbit's next point, such as `z^2 + c*sin(z)`. It may
+//! use numbers, `i`, `pi` and `e`, the operators `+ - * / ^`, parentheses,
+//! and the functions in `FUNCTIONS`.
+//!
+//! The text is parsed once into a tree, whose constant parts are worked
+//! out in advance, and the tree is compiled into a short program for a
+//! stack machine, which every step of every orbit runs.
+
+use num::Complex;
+
+/// The functions a formula can call, as they are written.
+pub const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "sinh", "cosh", "tanh", "exp", "log", "sqrt", "conj", "abs", "re", "im",
+];
+
+/// The deepest the stack of a formula's program may get. Formulas that
+/// nest deeper than this are refused rather than slowing every step down.
+const STACK: usize = 16;
+
+/// A parsed formula, ready to iterate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    text: String,
+    program: Vec<Op>,
+    degree: f64,
+}
+
+/// One instruction of a formula's program. Operands are popped off the
+/// stack and the result pushed back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Z,
+    C,
+    Constant(Complex<f64>),
+    Negate,
+    /// Combines the two values on top of the stack, the lower one first.
+    Binary(Binary),
+    /// Raises to an integer power, by repeated multiplication.
+    PowI(i32),
+    /// Raises to a real power.
+    PowF(f64),
+    Call(Function),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Exp,
+    Log,
+    Sqrt,
+    Conj,
+    Abs,
+    Re,
+    Im,
+}
+
+impl Function {
+    fn named(name: &str) -> Option<Function> {
+        match name {
+            "sin" => Some(Function::Sin),
+            "cos" => Some(Function::Cos),
+            "tan" => Some(Function::Tan),
+            "sinh" => Some(Function::Sinh),
+            "cosh" => Some(Function::Cosh),
+            "tanh" => Some(Function::Tanh),
+            "exp" => Some(Function::Exp),
+            "log" => Some(Function::Log),
+            "sqrt" => Some(Function::Sqrt),
+            "conj" => Some(Function::Conj),
+            "abs" => Some(Function::Abs),
+            "re" => Some(Function::Re),
+            "im" => Some(Function::Im),
+            _ => None,
+        }
+    }
+
+    fn apply(self, z: Complex<f64>) -> Complex<f64> {
+        match self {
+            Function::Sin => z.sin(),
+            Function::Cos => z.cos(),
+            Function::Tan => z.tan(),
+            Function::Sinh => z.sinh(),
+            Function::Cosh => z.cosh(),
+            Function::Tanh => z.tanh(),
+            Function::Exp => z.exp(),
+            Function::Log => z.ln(),
+            Function::Sqrt => z.sqrt(),
+            Function::Conj => z.conj(),
+            Function::Abs => Complex::new(z.norm(), 0.0),
+            Function::Re => Complex::new(z.re, 0.0),
+            Function::Im => Complex::new(z.im, 0.0),
+        }
+    }
+}
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Z,
+    C,
+    Constant(Complex<f64>),
+    Negate(Box<Expr>),
+    Binary(Binary, Box<Expr>, Box<Expr>),
+    Call(Function, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Binary {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+impl Binary {
+    fn apply(self, a: Complex<f64>, b: Complex<f64>) -> Complex<f64> {
+        match self {
+            Binary::Add => a + b,
+            Binary::Subtract => a - b,
+            Binary::Multiply => a * b,
+            Binary::Divide => a / b,
+            Binary::Power => a.powc(b),
+        }
+    }
+}
+
+impl Formula {
+    /// Parses `text`, or says what is wrong with it.
+    pub fn parse(text: &str) -> Result<Formula, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            next: 0,
+        };
+        let expr = parser.sum()?;
+        if let Some(token) = parser.tokens.get(parser.next) {
+            return Err(format!("unexpected {}", token.describe()));
+        }
+        let expr = fold(expr);
+        let mut program = Vec::new();
+        if compile(&expr, &mut program, 0) > STACK {
+            return Err("the formula nests too deeply".to_string());
+        }
+        let degree = match degree(&expr) {
+            Some(degree) if degree > 1.0 && degree.is_finite() => degree,
+            _ => 2.0,
+        };
+        Ok(Formula {
+            text: text.trim().to_string(),
+            program,
+            degree,
+        })
+    }
+
+    /// The text the formula was parsed from.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The power of `z` the formula grows like, for smooth coloring and
+    /// the escape radius: worked out from the formula where it is a power
+    /// of `z`, such as `z^3 + c` or `z*z*conj(z) + c`, and 2 where it
+    /// isn't, such as `exp(z) + c`.
+    pub fn degree(&self) -> f64 {
+        self.degree
+    }
+
+    /// One step of the orbit from `z`.
+    #[inline]
+    pub fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        let mut stack = [Complex::new(0.0, 0.0); STACK];
+        let mut top = 0;
+        for &op in &self.program {
+            let value = match op {
+                Op::Z => z,
+                Op::C => c,
+                Op::Constant(value) => value,
+                Op::Negate => -stack[top - 1],
+                Op::Binary(op) => {
+                    top -= 1;
+                    op.apply(stack[top - 1], stack[top])
+                }
+                Op::PowI(n) => stack[top - 1].powi(n),
+                Op::PowF(x) => stack[top - 1].powf(x),
+                Op::Call(function) => function.apply(stack[top - 1]),
+            };
+            if matches!(op, Op::Z | Op::C | Op::Constant(_)) {
+                top += 1;
+            }
+            stack[top - 1] = value;
+        }
+        stack[0]
+    }
+}
+
+/// Works out `expr` at `z` and `c` straight from the tree, which is how
+/// constant parts are folded.
+fn evaluate(expr: &Expr, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+    match expr {
+        Expr::Z => z,
+        Expr::C => c,
+        Expr::Constant(value) => *value,
+        Expr::Negate(a) => -evaluate(a, z, c),
+        Expr::Call(function, a) => function.apply(evaluate(a, z, c)),
+        Expr::Binary(op, a, b) => op.apply(evaluate(a, z, c), evaluate(b, z, c)),
+    }
+}
+
+/// Replaces the parts of `expr` that use neither `z` nor `c` by their
+/// values.
+fn fold(expr: Expr) -> Expr {
+    let expr = match expr {
+        Expr::Negate(a) => Expr::Negate(Box::new(fold(*a))),
+        Expr::Call(function, a) => Expr::Call(function, Box::new(fold(*a))),
+        Expr::Binary(op, a, b) => Expr::Binary(op, Box::new(fold(*a)), Box::new(fold(*b))),
+        leaf => leaf,
+    };
+    let constant = match &expr {
+        Expr::Negate(a) | Expr::Call(_, a) => matches!(**a, Expr::Constant(_)),
+        Expr::Binary(_, a, b) => {
+            matches!(**a, Expr::Constant(_)) && matches!(**b, Expr::Constant(_))
+        }
+        _ => false,
+    };
+    if constant {
+        let zero = Complex::new(0.0, 0.0);
+        Expr::Constant(evaluate(&expr, zero, zero))
+    } else {
+        expr
+    }
+}
+
+/// Appends the program for `expr` to `program`, with `depth` values
+/// already on the stack, and returns the deepest the stack gets.
+fn compile(expr: &Expr, program: &mut Vec<Op>, depth: usize) -> usize {
+    match expr {
+        Expr::Z => {
+            program.push(Op::Z);
+            depth + 1
+        }
+        Expr::C => {
+            program.push(Op::C);
+            depth + 1
+        }
+        Expr::Constant(value) => {
+            program.push(Op::Constant(*value));
+            depth + 1
+        }
+        Expr::Negate(a) => {
+            let deepest = compile(a, program, depth);
+            program.push(Op::Negate);
+            deepest
+        }
+        Expr::Call(function, a) => {
+            let deepest = compile(a, program, depth);
+            program.push(Op::Call(*function));
+            deepest
+        }
+        Expr::Binary(op, a, b) => match (op, &**b) {
+            // Real powers, the usual case, skip the logarithm `powc` takes.
+            (Binary::Power, &Expr::Constant(k)) if k.im == 0.0 => {
+                let deepest = compile(a, program, depth);
+                if k.re.fract() == 0.0 && k.re.abs() <= i32::MAX as f64 {
+                    program.push(Op::PowI(k.re as i32));
+                } else {
+                    program.push(Op::PowF(k.re));
+                }
+                deepest
+            }
+            _ => {
+                let deepest = compile(a, program, depth).max(compile(b, program, depth + 1));
+                program.push(Op::Binary(*op));
+                deepest
+            }
+        },
+    }
+}
+
+/// The power of `z` that `expr` grows like, or `None` if it doesn't grow
+/// like a power, as `exp(z)` doesn't.
+fn degree(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Z => Some(1.0),
+        Expr::C | Expr::Constant(_) => Some(0.0),
+        Expr::Negate(a) => degree(a),
+        Expr::Call(Function::Conj | Function::Abs | Function::Re | Function::Im, a) => degree(a),
+        Expr::Call(Function::Sqrt, a) => Some(degree(a)? / 2.0),
+        Expr::Call(_, a) => (degree(a)? == 0.0).then_some(0.0),
+        Expr::Binary(op, a, b) => {
+            let exponent = match **b {
+                Expr::Constant(k) if k.im == 0.0 => Some(k.re),
+                _ => None,
+            };
+            let (a, b) = (degree(a)?, degree(b)?);
+            match op {
+                Binary::Add | Binary::Subtract => Some(a.max(b)),
+                Binary::Multiply => Some(a + b),
+                Binary::Divide => Some(a - b),
+                Binary::Power => match exponent {
+                    Some(k) => Some(a * k),
+                    None => (a == 0.0 && b == 0.0).then_some(0.0),
+                },
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Symbol(char),
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Number(value) => format!("number {}", value),
+            Token::Name(name) => format!("'{}'", name),
+            Token::Symbol(symbol) => format!("'{}'", symbol),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        match chars[i] {
+            c if c.is_whitespace() => i += 1,
+            c if c.is_ascii_digit() || c == '.' => {
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                // An exponent, as in 1e-3, but not the constant e in 2e.
+                if i < chars.len() && matches!(chars[i], 'e' | 'E') {
+                    let digits = match chars.get(i + 1) {
+                        Some('+' | '-') => i + 2,
+                        _ => i + 1,
+                    };
+                    if chars.get(digits).is_some_and(char::is_ascii_digit) {
+                        i = digits;
+                        while i < chars.len() && chars[i].is_ascii_digit() {
+                            i += 1;
+                        }
+                    }
+                }
+                let number = chars[start..i].iter().collect::<String>();
+                let value = number
+                    .parse()
+                    .map_err(|_| format!("invalid number '{}'", number))?;
+                tokens.push(Token::Number(value));
+            }
+            c if c.is_alphabetic() => {
+                while i < chars.len() && chars[i].is_alphanumeric() {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            }
+            c @ ('+' | '-' | '*' | '/' | '^' | '(' | ')') => {
+                tokens.push(Token::Symbol(c));
+                i += 1;
+            }
+            c => return Err(format!("unexpected '{}'", c)),
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser, one method per level of precedence.
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    /// Takes the next token if it is `symbol`.
+    fn eat(&mut self, symbol: char) -> bool {
+        let found = self.peek() == Some(&Token::Symbol(symbol));
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    /// Terms joined by `+` and `-`.
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        loop {
+            let op = if self.eat('+') {
+                Binary::Add
+            } else if self.eat('-') {
+                Binary::Subtract
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+    }
+
+    /// Factors joined by `*` and `/`.
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                Binary::Multiply
+            } else if self.eat('/') {
+                Binary::Divide
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    /// A factor with any number of signs before it.
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            Ok(Expr::Negate(Box::new(self.unary()?)))
+        } else if self.eat('+') {
+            self.unary()
+        } else {
+            self.power()
+        }
+    }
+
+    /// An atom, raised to a power if `^` follows. Powers group to the
+    /// right, and bind tighter than a sign before them: `-z^2` is
+    /// `-(z^2)`.
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(
+                Binary::Power,
+                Box::new(base),
+                Box::new(exponent),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned().ok_or("unexpected end of formula")?;
+        self.next += 1;
+        match token {
+            Token::Number(value) => Ok(Expr::Constant(Complex::new(value, 0.0))),
+            Token::Symbol('(') => {
+                let expr = self.sum()?;
+                if !self.eat(')') {
+                    return Err("missing ')'".to_string());
+                }
+                Ok(expr)
+            }
+            Token::Name(name) => match name.as_str() {
+                "z" => Ok(Expr::Z),
+                "c" => Ok(Expr::C),
+                "i" => Ok(Expr::Constant(Complex::new(0.0, 1.0))),
+                "pi" => Ok(Expr::Constant(Complex::new(std::f64::consts::PI, 0.0))),
+                "e" => Ok(Expr::Constant(Complex::new(std::f64::consts::E, 0.0))),
+                _ => {
+                    let function = Function::named(&name).ok_or_else(|| {
+                        format!(
+                            "unknown name '{}', expected z, c, i, pi, e or one of: {}",
+                            name,
+                            FUNCTIONS.join(", ")
+                        )
+                    })?;
+                    if !self.eat('(') {
+                        return Err(format!("expected '(' after '{}'", name));
+                    }
+                    let argument = self.sum()?;
+                    if !self.eat(')') {
+                        return Err("missing ')'".to_string());
+                    }
+                    Ok(Expr::Call(function, Box::new(argument)))
+                }
+            },
+            token => Err(format!("unexpected {}", token.describe())),
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let z = Complex::new(0.5, -0.25);
+    let c = Complex::new(0.1, 0.2);
+    let step = |text: &str| Formula::parse(text).unwrap().step(z, c);
+    let close = |a: Complex<f64>, b: Complex<f64>| (a - b).norm() < 1e-12;
+    assert!(close(step("z^2 + c"), z * z + c));
+    assert!(close(step("z*z*z - 2*z + c"), z * z * z - 2.0 * z + c));
+    assert!(close(step("z^2 + c*sin(z)"), z * z + c * z.sin()));
+    assert!(close(step("-z^2 + c"), -(z * z) + c));
+    assert!(close(step("2^3^2 + 0*z"), Complex::new(512.0, 0.0)));
+    assert!(close(
+        step("(1 + i) * z / 2 - c"),
+        Complex::new(1.0, 1.0) * z / 2.0 - c
+    ));
+    assert!(close(step("z^1.5 + c"), z.powf(1.5) + c));
+    assert!(close(step("z^(1 + i)"), z.powc(Complex::new(1.0, 1.0))));
+    assert!(close(
+        step("conj(z)^2 + 1e-1 + 2*e*c"),
+        z.conj() * z.conj() + 0.1 + 2.0 * std::f64::consts::E * c
+    ));
+    assert!(close(
+        step("abs(z) + re(c) + im(c)"),
+        Complex::new(z.norm() + 0.3, 0.0)
+    ));
+    for bad in [
+        "", "z +", "z^2 + x", "sin z", "(z", "z)", "z $ c", "1..2", "z c", "2z",
+    ] {
+        assert!(Formula::parse(bad).is_err(), "{}", bad);
+    }
+    let deep = format!("{}z{}", "(1 + ".repeat(20), ")".repeat(20));
+    assert!(Formula::parse(&deep).is_err());
+}
+
+#[test]
+fn test_fold() {
+    // Constant parts are worked out in advance, and real powers skip powc.
+    let formula = Formula::parse("z^(4/2) + c*(2 - 1)").unwrap();
+    assert_eq!(
+        formula.program,
+        [
+            Op::Z,
+            Op::PowI(2),
+            Op::C,
+            Op::Constant(Complex::new(1.0, 0.0)),
+            Op::Binary(Binary::Multiply),
+            Op::Binary(Binary::Add)
+        ]
+    );
+}
+
+#[test]
+fn test_degree() {
+    let degree = |text| Formula::parse(text).unwrap().degree();
+    assert_eq!(degree("z^2 + c"), 2.0);
+    assert_eq!(degree("z^3 - z + c"), 3.0);
+    assert_eq!(degree("z*z*conj(z) + c"), 3.0);
+    assert_eq!(degree("z^2.5 / 2 + c"), 2.5);
+    assert_eq!(degree("(z^2 + c)^2"), 4.0);
+    // Where the formula isn't a power of z, 2 is assumed.
+    assert_eq!(degree("exp(z) + c"), 2.0);
+    assert_eq!(degree("z + c"), 2.0);
+}
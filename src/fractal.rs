@@ -0,0 +1,592 @@
+//! The formulas iterated besides the Mandelbrot set's `z² + c`. They are
+//! rendered in `f64` by a scalar kernel; the vector kernel, the bulb
+//! shortcut, perturbation and fixed point arithmetic all assume `z² + c`.
+//!
+//! Each step goes from one `State` of the orbit to the next, which besides
+//! `z` holds what the formulas that look further back need.
+//!
+//! Families of fractals from outside the crate implement `Family`, which
+//! the built-in ones do through `Fractal`, and are rendered as
+//! `Fractal::Custom` like the others, with their Julia sets, colorings and
+//! outputs.
+
+use crate::{formula::Formula, in_main_bulbs, iterate_with, Bailout, Escape, Shortcuts};
+use num::Complex;
+use std::{fmt, ptr, sync::Arc};
+
+/// Which formula is iterated.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fractal {
+    /// `z² + c`.
+    Mandelbrot,
+    /// `(|Re z| + i |Im z|)² + c`, whose ships are drawn upright with the
+    /// imaginary axis pointing down.
+    BurningShip,
+    /// `z^d + c` for a degree `d` above 1 other than 2; see `multibrot`.
+    Multibrot(f64),
+    /// `conj(z)² + c`, also known as the Mandelbar set.
+    Tricorn,
+    /// `z² + c + p z'`, where `z'` is the point the orbit was at the step
+    /// before; see `phoenix`.
+    Phoenix(Complex<f64>),
+    /// A formula given as text; see `formula`.
+    Formula(Arc<Formula>),
+    /// A family of fractals defined outside the crate. Two are the same
+    /// only if they are the same `Arc`.
+    Custom(Arc<dyn Family>),
+}
+
+/// A family of fractals: how an orbit starts, steps and escapes. Julia sets
+/// come with every family, by starting the orbit of a point under a fixed
+/// `c` instead; see `RenderOptions::julia`. Families are iterated in `f64`
+/// by the scalar kernel, as the built-in ones besides the Mandelbrot set
+/// are.
+pub trait Family: fmt::Debug + Send + Sync {
+    /// The name the family goes by in metadata.
+    fn name(&self) -> &str;
+
+    /// Where the orbit of `c` starts, unless it is a point of a Julia set:
+    /// 0, unless implemented.
+    fn initial_z(&self, c: Complex<f64>) -> Complex<f64> {
+        let _ = c;
+        Complex::new(0.0, 0.0)
+    }
+
+    /// One step of the orbit from `state`.
+    fn step(&self, state: State, c: Complex<f64>) -> State;
+
+    /// Whether the orbit has escaped once at `z`: past `bailout`, which is
+    /// already at least `escape_radius`, unless implemented.
+    fn escaped(&self, z: Complex<f64>, bailout: Bailout) -> bool {
+        bailout.escaped(z.re, z.im)
+    }
+
+    /// Whether `c` is known never to escape without following its orbit,
+    /// as the Mandelbrot set's main bulbs are, taken with
+    /// `Shortcuts::bulbs`. Never, unless implemented.
+    fn inside(&self, c: Complex<f64>) -> bool {
+        let _ = c;
+        false
+    }
+
+    /// The power `z` is raised to each step, which smooth coloring and
+    /// shading go by: 2, unless implemented.
+    fn degree(&self) -> f64 {
+        2.0
+    }
+
+    /// Where a view of the whole set is centered, and whether it is drawn
+    /// upside down: 0 and upright, unless implemented.
+    fn center(&self) -> (Complex<f64>, bool) {
+        (Complex::new(0.0, 0.0), false)
+    }
+}
+
+impl PartialEq for dyn Family {
+    fn eq(&self, other: &dyn Family) -> bool {
+        ptr::addr_eq(self, other)
+    }
+}
+
+/// Where an orbit is between steps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct State {
+    pub z: Complex<f64>,
+    /// Where the orbit was the step before, which the Phoenix formula uses.
+    pub previous: Complex<f64>,
+}
+
+impl State {
+    /// An orbit starting at `z`, with 0 before it.
+    pub fn new(z: Complex<f64>) -> State {
+        State {
+            z,
+            previous: Complex::new(0.0, 0.0),
+        }
+    }
+}
+
+/// Names accepted by `Fractal::named`, in the order they are listed in
+/// help.
+pub const NAMES: &[&str] = &[
+    "mandelbrot",
+    "burning-ship",
+    "multibrot",
+    "tricorn",
+    "phoenix",
+];
+
+/// Integer degrees up to this are raised to by repeated multiplication,
+/// others with `powf`.
+const MAX_INTEGER_DEGREE: f64 = 16.0;
+
+impl Fractal {
+    /// Looks a fractal up by name, as given to `--fractal`. The multibrot
+    /// set is cubic until given another degree, and the Phoenix set takes
+    /// `p = -0.5` until given another.
+    pub fn named(name: &str) -> Option<Fractal> {
+        match name {
+            "mandelbrot" => Some(Fractal::Mandelbrot),
+            "burning-ship" => Some(Fractal::BurningShip),
+            "multibrot" => Some(Fractal::Multibrot(3.0)),
+            "tricorn" | "mandelbar" => Some(Fractal::Tricorn),
+            "phoenix" => Some(Fractal::Phoenix(Complex::new(-0.5, 0.0))),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Fractal::Mandelbrot => "mandelbrot",
+            Fractal::BurningShip => "burning-ship",
+            Fractal::Multibrot(_) => "multibrot",
+            Fractal::Tricorn => "tricorn",
+            Fractal::Phoenix(_) => "phoenix",
+            Fractal::Formula(_) => "formula",
+            Fractal::Custom(family) => family.name(),
+        }
+    }
+
+    /// The multibrot set of `degree`, which for 2 is the Mandelbrot set, or
+    /// `None` for a degree of 1 or less, whose orbits escape or settle
+    /// without a boundary between them.
+    pub fn multibrot(degree: f64) -> Option<Fractal> {
+        match degree {
+            2.0 => Some(Fractal::Mandelbrot),
+            d if d > 1.0 && d.is_finite() => Some(Fractal::Multibrot(d)),
+            _ => None,
+        }
+    }
+
+    /// The Phoenix set with the constant `p`, which for 0 is the Mandelbrot
+    /// set.
+    pub fn phoenix(p: Complex<f64>) -> Fractal {
+        if p == Complex::new(0.0, 0.0) {
+            Fractal::Mandelbrot
+        } else {
+            Fractal::Phoenix(p)
+        }
+    }
+
+    /// The power `z` is raised to each step.
+    pub fn degree(&self) -> f64 {
+        match self {
+            Fractal::Mandelbrot | Fractal::BurningShip | Fractal::Tricorn | Fractal::Phoenix(_) => {
+                2.0
+            }
+            Fractal::Multibrot(degree) => *degree,
+            Fractal::Formula(formula) => formula.degree(),
+            Fractal::Custom(family) => family.degree(),
+        }
+    }
+
+    /// How far out an orbit has to get to be sure to escape: 2, or more
+    /// for degrees under 2, which pull points back in more weakly.
+    pub fn escape_radius(&self) -> f64 {
+        2f64.max(2f64.powf(1.0 / (self.degree() - 1.0)))
+    }
+
+    /// Where the orbit of `point` starts, and the `c` it is iterated with:
+    /// `point` itself, from `initial_z`, or for a point of the Julia set of
+    /// `julia`, `point` under it.
+    pub fn start(
+        &self,
+        point: Complex<f64>,
+        julia: Option<Complex<f64>>,
+    ) -> (Complex<f64>, Complex<f64>) {
+        match (julia, self) {
+            (Some(c), _) => (point, c),
+            (None, Fractal::Custom(family)) => (family.initial_z(point), point),
+            (None, _) => (Complex::new(0.0, 0.0), point),
+        }
+    }
+
+    /// One step of the orbit from `state`.
+    #[inline(always)]
+    pub fn step(&self, state: State, c: Complex<f64>) -> State {
+        if let Fractal::Custom(family) = self {
+            return family.step(state, c);
+        }
+        let z = state.z;
+        let next = match self {
+            Fractal::Mandelbrot => z * z + c,
+            Fractal::BurningShip => {
+                let z = Complex::new(z.re.abs(), z.im.abs());
+                z * z + c
+            }
+            &Fractal::Multibrot(degree) => {
+                if degree.fract() == 0.0 && degree <= MAX_INTEGER_DEGREE {
+                    z.powi(degree as i32) + c
+                } else {
+                    z.powf(degree) + c
+                }
+            }
+            Fractal::Tricorn => {
+                let z = z.conj();
+                z * z + c
+            }
+            Fractal::Phoenix(p) => z * z + c + p * state.previous,
+            Fractal::Formula(formula) => formula.step(z, c),
+            Fractal::Custom(_) => unreachable!(),
+        };
+        State {
+            z: next,
+            previous: z,
+        }
+    }
+
+    /// Returns how the orbit of `point` escapes past `bailout`, or past the
+    /// escape radius if that is further, as `escape_time_with` does for the
+    /// Mandelbrot set, or as `escape_time_julia` does for the points of a
+    /// Julia set with `julia` set.
+    pub fn escape_time(
+        &self,
+        point: Complex<f64>,
+        julia: Option<Complex<f64>>,
+        limit: u32,
+        shortcuts: Shortcuts,
+        bailout: Bailout,
+    ) -> Option<Escape> {
+        let (z, c) = self.start(point, julia);
+        let bailout = bailout.at_least(self.escape_radius());
+        if julia.is_none() && shortcuts.bulbs && self.inside(c) {
+            return None;
+        }
+        let escaped = |z: Complex<f64>| bailout.escaped(z.re, z.im);
+        match self {
+            Fractal::Mandelbrot => iterate_with(z, c, limit, shortcuts, escaped, |z, c| z * z + c),
+            Fractal::Custom(family) => {
+                let mut state = State::new(z);
+                let escaped = |z| family.escaped(z, bailout);
+                iterate_with(z, c, limit, shortcuts, escaped, |_, c| {
+                    state = family.step(state, c);
+                    state.z
+                })
+            }
+            fractal => {
+                let mut state = State::new(z);
+                iterate_with(z, c, limit, shortcuts, escaped, |_, c| {
+                    state = fractal.step(state, c);
+                    state.z
+                })
+            }
+        }
+    }
+
+    /// Whether `c` is known never to escape: in the main bulbs, for the
+    /// Mandelbrot set.
+    pub fn inside(&self, c: Complex<f64>) -> bool {
+        match self {
+            Fractal::Mandelbrot => in_main_bulbs(c),
+            Fractal::Custom(family) => family.inside(c),
+            _ => false,
+        }
+    }
+
+    /// The corners of a view of the whole set for an image of `bounds`
+    /// pixels: 4 high, or 4 wide for an image taller than it is wide.
+    pub fn view(&self, bounds: (u32, u32)) -> (Complex<f64>, Complex<f64>) {
+        let (center, flipped) = self.center();
+        view_around(center, flipped, bounds)
+    }
+
+    /// Where a view of the whole set is centered, and whether it is drawn
+    /// upside down.
+    pub fn center(&self) -> (Complex<f64>, bool) {
+        match self {
+            Fractal::Mandelbrot => (Complex::new(-0.5, 0.0), false),
+            Fractal::BurningShip => (Complex::new(-0.4, -0.5), true),
+            Fractal::Multibrot(_) => (Complex::new(0.0, 0.0), false),
+            Fractal::Tricorn => (Complex::new(-0.3, 0.0), false),
+            Fractal::Phoenix(_) => (Complex::new(-0.4, 0.0), false),
+            Fractal::Formula(_) => (Complex::new(0.0, 0.0), false),
+            Fractal::Custom(family) => family.center(),
+        }
+    }
+}
+
+/// The built-in fractals, as a family.
+impl Family for Fractal {
+    fn name(&self) -> &str {
+        Fractal::name(self)
+    }
+
+    fn initial_z(&self, c: Complex<f64>) -> Complex<f64> {
+        self.start(c, None).0
+    }
+
+    fn step(&self, state: State, c: Complex<f64>) -> State {
+        Fractal::step(self, state, c)
+    }
+
+    fn escaped(&self, z: Complex<f64>, bailout: Bailout) -> bool {
+        match self {
+            Fractal::Custom(family) => family.escaped(z, bailout),
+            _ => bailout.escaped(z.re, z.im),
+        }
+    }
+
+    fn inside(&self, c: Complex<f64>) -> bool {
+        Fractal::inside(self, c)
+    }
+
+    fn degree(&self) -> f64 {
+        Fractal::degree(self)
+    }
+
+    fn center(&self) -> (Complex<f64>, bool) {
+        Fractal::center(self)
+    }
+}
+
+/// The corners of a view 4 high, or 4 wide for an image taller than it is
+/// wide, around `center`, upside down if `flipped`.
+pub fn view_around(
+    center: Complex<f64>,
+    flipped: bool,
+    bounds: (u32, u32),
+) -> (Complex<f64>, Complex<f64>) {
+    let aspect = bounds.0.max(1) as f64 / bounds.1.max(1) as f64;
+    let (width, height) = (4f64.max(4.0 * aspect), 4f64.max(4.0 / aspect));
+    let down = if flipped { -1.0 } else { 1.0 };
+    let half = Complex::new(width / 2.0, down * height / 2.0);
+    (center - half.conj(), center + half.conj())
+}
+
+#[test]
+fn test_burning_ship() {
+    let ship = |re, im| {
+        Fractal::BurningShip.escape_time(
+            Complex::new(re, im),
+            None,
+            500,
+            Shortcuts::NONE,
+            Bailout::default(),
+        )
+    };
+    // On the real axis the ship's orbits are the Mandelbrot set's.
+    assert_eq!(ship(-1.0, 0.0), None);
+    assert_eq!(ship(-1.8, 0.0), None);
+    assert!(ship(0.3, 0.0).is_some());
+    // Folding z into the first quadrant sends -0.5 + 0.5i, inside the
+    // Mandelbrot set's cardioid, off to infinity.
+    let c = Complex::new(-0.5, 0.5);
+    assert_eq!(crate::escape_time_with(c, 500, Shortcuts::NONE), None);
+    assert!(ship(-0.5, 0.5).is_some());
+    let state = State::new(Complex::new(-1.0, -2.0));
+    assert_eq!(
+        Fractal::BurningShip.step(state, Complex::new(0.0, 0.0)).z,
+        Complex::new(1.0, 2.0) * Complex::new(1.0, 2.0)
+    );
+}
+
+#[test]
+fn test_multibrot() {
+    assert_eq!(Fractal::multibrot(2.0), Some(Fractal::Mandelbrot));
+    assert_eq!(Fractal::multibrot(1.0), None);
+    assert_eq!(Fractal::multibrot(f64::NAN), None);
+    assert_eq!(Fractal::Multibrot(3.0).escape_radius(), 2.0);
+    assert_eq!(Fractal::Multibrot(1.5).escape_radius(), 4.0);
+    let z = Complex::new(0.5, -0.25);
+    let c = Complex::new(0.1, 0.2);
+    let integer = Fractal::Multibrot(4.0).step(State::new(z), c).z;
+    let real = Fractal::Multibrot(4.0 + 1e-12).step(State::new(z), c).z;
+    assert!((integer - (z * z * z * z + c)).norm() < 1e-15);
+    assert!((integer - real).norm() < 1e-10);
+    // The cubic set is symmetric about 0: ±0.5i stay, ±1.5 leave.
+    let cubic = |re, im| {
+        Fractal::Multibrot(3.0).escape_time(
+            Complex::new(re, im),
+            None,
+            500,
+            Shortcuts::NONE,
+            Bailout::default(),
+        )
+    };
+    assert_eq!(cubic(0.0, 0.5), None);
+    assert_eq!(cubic(0.0, -0.5), None);
+    assert!(cubic(1.5, 0.0).is_some());
+    assert!(cubic(-1.5, 0.0).is_some());
+}
+
+#[test]
+fn test_tricorn() {
+    let tricorn = |re, im| {
+        Fractal::Tricorn.escape_time(
+            Complex::new(re, im),
+            None,
+            500,
+            Shortcuts::NONE,
+            Bailout::default(),
+        )
+    };
+    // On the real axis the tricorn's orbits are the Mandelbrot set's.
+    assert_eq!(tricorn(0.0, 0.0), None);
+    assert_eq!(tricorn(-1.0, 0.0), None);
+    assert!(tricorn(0.5, 0.0).is_some());
+    assert!(tricorn(-2.1, 0.0).is_some());
+    // The set has threefold symmetry, so -1 turned a third of the way
+    // round is inside too.
+    let turned =
+        Complex::new(-1.0, 0.0) * Complex::from_polar(1.0, 2.0 * std::f64::consts::PI / 3.0);
+    assert_eq!(tricorn(turned.re, turned.im), None);
+    assert_eq!(tricorn(turned.re, -turned.im), None);
+    // -0.5 + 0.5i is inside the Mandelbrot set's cardioid but not here.
+    assert_eq!(
+        crate::escape_time_with(Complex::new(-0.5, 0.5), 500, Shortcuts::NONE),
+        None
+    );
+    assert!(tricorn(-0.5, 0.5).is_some());
+    assert_eq!(Fractal::named("mandelbar"), Some(Fractal::Tricorn));
+}
+
+#[test]
+fn test_view() {
+    let (upper_left, lower_right) = Fractal::Mandelbrot.view((400, 200));
+    assert_eq!(upper_left, Complex::new(-4.5, 2.0));
+    assert_eq!(lower_right, Complex::new(3.5, -2.0));
+    // The ship's view is upside down.
+    let (upper_left, lower_right) = Fractal::BurningShip.view((100, 200));
+    assert_eq!(upper_left, Complex::new(-2.4, -4.5));
+    assert_eq!(lower_right, Complex::new(1.6, 3.5));
+}
+
+#[test]
+fn test_phoenix() {
+    let p = Complex::new(-0.5, 0.0);
+    let c = Complex::new(0.25, 0.5);
+    // Each step brings the point before it along.
+    let state = State {
+        z: Complex::new(1.0, 1.0),
+        previous: Complex::new(2.0, 0.0),
+    };
+    let next = Fractal::Phoenix(p).step(state, c);
+    assert_eq!(next.z, Complex::new(0.0, 2.0) + c + p * 2.0);
+    assert_eq!(next.previous, state.z);
+    assert_eq!(
+        Fractal::phoenix(Complex::new(0.0, 0.0)),
+        Fractal::Mandelbrot
+    );
+    // Ushiki's Phoenix: the Julia set of 0.5667 with p = -0.5.
+    let julia = |re, im| {
+        Fractal::Phoenix(p).escape_time(
+            Complex::new(re, im),
+            Some(Complex::new(0.5667, 0.0)),
+            500,
+            Shortcuts::NONE,
+            Bailout::default(),
+        )
+    };
+    // Its bird faces along the imaginary axis, which 0 is between.
+    assert_eq!(julia(-0.1, 0.7), None);
+    assert_eq!(julia(-0.1, -0.7), None);
+    assert!(julia(0.0, 0.0).is_some());
+    // Looking back a step changes the set: -1.5 is in the Mandelbrot set
+    // but not here, and 0.4 the other way round.
+    let mandelbrot = |re| crate::escape_time_with(Complex::new(re, 0.0), 500, Shortcuts::NONE);
+    let phoenix = |re| {
+        Fractal::Phoenix(p).escape_time(
+            Complex::new(re, 0.0),
+            None,
+            500,
+            Shortcuts::NONE,
+            Bailout::default(),
+        )
+    };
+    assert_eq!(mandelbrot(-1.5), None);
+    assert!(phoenix(-1.5).is_some());
+    assert!(mandelbrot(0.4).is_some());
+    assert_eq!(phoenix(0.4), None);
+}
+
+#[test]
+fn test_custom() {
+    use crate::{Precision, RenderOptions, Renderer};
+    // The Mandelbrot set over again, and the same set starting a step on.
+    #[derive(Debug)]
+    struct Square(bool);
+    impl Family for Square {
+        fn name(&self) -> &str {
+            "square"
+        }
+        fn initial_z(&self, c: Complex<f64>) -> Complex<f64> {
+            if self.0 {
+                c
+            } else {
+                Complex::new(0.0, 0.0)
+            }
+        }
+        fn step(&self, state: State, c: Complex<f64>) -> State {
+            State {
+                z: state.z * state.z + c,
+                previous: state.z,
+            }
+        }
+        fn inside(&self, c: Complex<f64>) -> bool {
+            in_main_bulbs(c)
+        }
+        fn center(&self) -> (Complex<f64>, bool) {
+            (Complex::new(-0.5, 0.0), false)
+        }
+    }
+    let square = Fractal::Custom(Arc::new(Square(false)));
+    assert_eq!(square.name(), "square");
+    assert_eq!(square.view((40, 30)), Fractal::Mandelbrot.view((40, 30)));
+    assert_eq!(square, square.clone());
+    assert_ne!(square, Fractal::Custom(Arc::new(Square(false))));
+    let options = RenderOptions {
+        bounds: (40, 30),
+        precision: Precision::Double,
+        ..RenderOptions::default()
+    };
+    let render = |fractal: Fractal| {
+        Renderer::new(RenderOptions {
+            fractal,
+            ..options.clone()
+        })
+        .render()
+    };
+    assert_eq!(render(square), render(Fractal::Mandelbrot));
+    let ahead = Fractal::Custom(Arc::new(Square(true)));
+    for re in [0.5, 1.0, -2.1] {
+        let escape = |fractal: &Fractal| {
+            fractal
+                .escape_time(
+                    Complex::new(re, 0.1),
+                    None,
+                    500,
+                    Shortcuts::NONE,
+                    Bailout::default(),
+                )
+                .unwrap()
+                .iterations
+        };
+        assert_eq!(escape(&ahead) + 1, escape(&Fractal::Mandelbrot));
+    }
+    // A test of its own for when the orbit has escaped.
+    #[derive(Debug)]
+    struct Strip;
+    impl Family for Strip {
+        fn name(&self) -> &str {
+            "strip"
+        }
+        fn step(&self, state: State, c: Complex<f64>) -> State {
+            Fractal::Mandelbrot.step(state, c)
+        }
+        fn escaped(&self, z: Complex<f64>, _: Bailout) -> bool {
+            z.im.abs() > 1.0
+        }
+    }
+    let strip = Fractal::Custom(Arc::new(Strip));
+    let escape = |im| {
+        strip.escape_time(
+            Complex::new(0.0, im),
+            None,
+            10,
+            Shortcuts::NONE,
+            Bailout::default(),
+        )
+    };
+    assert_eq!(escape(1.5).map(|e| e.iterations), Some(1));
+    assert_eq!(escape(0.0), None);
+}
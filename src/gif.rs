@@ -0,0 +1,352 @@
+//! An animated GIF encoder. Each frame gets its own color table of at most
+//! 256 colors, chosen by median cut, and is LZW compressed as the format
+//! requires. GIFs are large and banded next to video, but play anywhere,
+//! which suits short shallow zooms.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// The most colors a GIF color table holds.
+pub const MAX_COLORS: usize = 256;
+
+/// LZW codes are at most 12 bits.
+const MAX_CODES: u16 = 4096;
+
+/// Writes frames of an animated GIF one at a time.
+pub struct Encoder<W: Write> {
+    w: W,
+    bounds: (u16, u16),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes the header for `bounds` sized frames, shown `loops` times
+    /// over, or forever for 0.
+    pub fn new(mut w: W, bounds: (u32, u32), loops: u16) -> io::Result<Encoder<W>> {
+        let (width, height) = bounds;
+        if width > u16::MAX as u32 || height > u16::MAX as u32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "GIF images are limited to 65535 pixels a side",
+            ));
+        }
+        let bounds = (width as u16, height as u16);
+        w.write_all(b"GIF89a")?;
+        w.write_all(&bounds.0.to_le_bytes())?;
+        w.write_all(&bounds.1.to_le_bytes())?;
+        // No global color table, background color 0, square pixels.
+        w.write_all(&[0, 0, 0])?;
+        // The NETSCAPE2.0 application extension, which sets the loop count.
+        w.write_all(&[0x21, 0xff, 11])?;
+        w.write_all(b"NETSCAPE2.0")?;
+        w.write_all(&[3, 1])?;
+        w.write_all(&loops.to_le_bytes())?;
+        w.write_all(&[0])?;
+        Ok(Encoder { w, bounds })
+    }
+
+    /// Adds a frame of three bytes (red, green, blue) per pixel, reduced to
+    /// at most `colors` colors and shown for `delay` hundredths of a
+    /// second.
+    pub fn write_frame(&mut self, pixels: &[u8], colors: usize, delay: u16) -> io::Result<()> {
+        let (palette, indices) = quantize(pixels, colors);
+        // The color table holds a power of two entries, at least 2.
+        let table_bits = (palette.len().max(2) as u32)
+            .next_power_of_two()
+            .trailing_zeros();
+        let w = &mut self.w;
+        // Graphic control extension: no transparency, frames replace each
+        // other.
+        w.write_all(&[0x21, 0xf9, 4, 1 << 2])?;
+        w.write_all(&delay.to_le_bytes())?;
+        w.write_all(&[0, 0])?;
+        // Image descriptor covering the whole screen, with a local color
+        // table.
+        w.write_all(&[0x2c, 0, 0, 0, 0])?;
+        w.write_all(&self.bounds.0.to_le_bytes())?;
+        w.write_all(&self.bounds.1.to_le_bytes())?;
+        w.write_all(&[0x80 | (table_bits - 1) as u8])?;
+        for i in 0..1 << table_bits {
+            w.write_all(&palette.get(i).copied().unwrap_or([0; 3]))?;
+        }
+        let min_code_size = table_bits.max(2) as u8;
+        w.write_all(&[min_code_size])?;
+        let data = lzw_encode(&indices, min_code_size);
+        for block in data.chunks(255) {
+            w.write_all(&[block.len() as u8])?;
+            w.write_all(block)?;
+        }
+        w.write_all(&[0])
+    }
+
+    /// Writes the trailer and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.w.write_all(&[0x3b])?;
+        Ok(self.w)
+    }
+}
+
+/// Reduces an RGB pixel buffer to a palette of at most `colors` colors and
+/// an index into it for every pixel. Frames with few enough distinct colors
+/// keep them exactly; others are split by median cut, repeatedly halving
+/// the group of colors that spans the widest range of one channel, and each
+/// group is replaced by its average.
+pub fn quantize(pixels: &[u8], colors: usize) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let colors = colors.clamp(1, MAX_COLORS);
+    let mut counts = HashMap::<[u8; 3], u32>::new();
+    for pixel in pixels.chunks_exact(3) {
+        *counts.entry([pixel[0], pixel[1], pixel[2]]).or_default() += 1;
+    }
+    let mut distinct = counts.into_iter().collect::<Vec<_>>();
+    // A fixed order, so that the same frame always gets the same palette.
+    distinct.sort_unstable();
+    let mut groups = vec![distinct];
+    while groups.len() < colors {
+        let widest = groups
+            .iter()
+            .enumerate()
+            .filter(|(_, group)| group.len() > 1)
+            .map(|(i, group)| {
+                let (channel, range) = widest_channel(group);
+                (range, i, channel)
+            })
+            .max();
+        let (_, i, channel) = match widest {
+            Some(widest) => widest,
+            None => break,
+        };
+        let mut group = groups.swap_remove(i);
+        group.sort_unstable_by_key(|(color, _)| color[channel]);
+        // Split where half the pixels fall on either side.
+        let total = group.iter().map(|(_, count)| *count as u64).sum::<u64>();
+        let mut seen = 0;
+        let middle = group
+            .iter()
+            .position(|(_, count)| {
+                seen += *count as u64;
+                seen * 2 >= total
+            })
+            .unwrap_or(0);
+        let upper = group.split_off((middle + 1).min(group.len() - 1));
+        groups.push(group);
+        groups.push(upper);
+    }
+    let mut palette = Vec::with_capacity(groups.len());
+    let mut index = HashMap::new();
+    for (i, group) in groups.iter().enumerate() {
+        let total = group.iter().map(|(_, count)| *count as u64).sum::<u64>();
+        let mut sums = [0u64; 3];
+        for (color, count) in group {
+            for (sum, &value) in sums.iter_mut().zip(color) {
+                *sum += value as u64 * *count as u64;
+            }
+        }
+        palette.push(sums.map(|sum| ((sum + total / 2) / total.max(1)) as u8));
+        for (color, _) in group {
+            index.insert(*color, i as u8);
+        }
+    }
+    let indices = pixels
+        .chunks_exact(3)
+        .map(|pixel| index[&[pixel[0], pixel[1], pixel[2]]])
+        .collect();
+    (palette, indices)
+}
+
+/// The channel along which a group of colors varies most, and by how much.
+fn widest_channel(group: &[([u8; 3], u32)]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = group.iter().map(|(color, _)| color[channel]);
+            let range = values.clone().max().unwrap() - values.min().unwrap();
+            (channel, range)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+/// Compresses color indices with GIF's variable length LZW, starting at
+/// `min_code_size` + 1 bits a code. The table is cleared just before it
+/// fills up, so decoders never need to handle a full one.
+fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut bits = BitPacker::default();
+    let mut table = HashMap::<(u16, u8), u16>::new();
+    let mut code_size = min_code_size as u32 + 1;
+    let mut next = end + 1;
+    bits.push(clear, code_size);
+    let mut indices = indices.iter();
+    let mut prefix = match indices.next() {
+        Some(&first) => first as u16,
+        None => {
+            bits.push(end, code_size);
+            return bits.finish();
+        }
+    };
+    for &index in indices {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        bits.push(prefix, code_size);
+        table.insert((prefix, index), next);
+        next += 1;
+        if next == MAX_CODES {
+            bits.push(clear, code_size);
+            table.clear();
+            code_size = min_code_size as u32 + 1;
+            next = end + 1;
+        } else if next > 1 << code_size {
+            code_size += 1;
+        }
+        prefix = index as u16;
+    }
+    bits.push(prefix, code_size);
+    bits.push(end, code_size);
+    bits.finish()
+}
+
+/// Packs codes into bytes least significant bit first.
+#[derive(Default)]
+struct BitPacker {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitPacker {
+    fn push(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Decodes `lzw_encode`'s output the way a GIF reader does, to check it.
+#[cfg(test)]
+fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut table: Vec<Vec<u8>> = Vec::new();
+    let reset = |table: &mut Vec<Vec<u8>>| {
+        table.clear();
+        table.extend((0..clear).map(|i| vec![i as u8]));
+        table.push(Vec::new());
+        table.push(Vec::new());
+    };
+    reset(&mut table);
+    let mut code_size = min_code_size as u32 + 1;
+    let (mut position, mut output, mut previous) = (0usize, Vec::new(), None::<Vec<u8>>);
+    loop {
+        let mut code = 0u16;
+        for bit in 0..code_size {
+            let (byte, shift) = (position / 8, position % 8);
+            code |= (((data[byte] >> shift) & 1) as u16) << bit;
+            position += 1;
+        }
+        if code == clear {
+            reset(&mut table);
+            code_size = min_code_size as u32 + 1;
+            previous = None;
+            continue;
+        }
+        if code == end {
+            return output;
+        }
+        let entry = match (table.get(code as usize), &previous) {
+            (Some(entry), _) => entry.clone(),
+            (None, Some(previous)) => {
+                let mut entry = previous.clone();
+                entry.push(previous[0]);
+                entry
+            }
+            (None, None) => panic!("undefined code {}", code),
+        };
+        output.extend(&entry);
+        if let Some(mut previous) = previous {
+            if table.len() < MAX_CODES as usize {
+                previous.push(entry[0]);
+                table.push(previous);
+                if table.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+        }
+        previous = Some(entry);
+    }
+}
+
+#[test]
+fn test_lzw_round_trip() {
+    let patterns: Vec<Vec<u8>> = vec![
+        vec![],
+        vec![1],
+        vec![0; 10_000],
+        (0..20_000).map(|i| (i * 7 % 13) as u8).collect(),
+        (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect(),
+    ];
+    for indices in patterns {
+        let max = indices.iter().copied().max().unwrap_or(0) as u32 + 1;
+        let min_code_size = (32 - (max - 1).leading_zeros()).max(2) as u8;
+        let data = lzw_encode(&indices, min_code_size);
+        assert_eq!(lzw_decode(&data, min_code_size), indices);
+    }
+}
+
+#[test]
+fn test_quantize() {
+    let pixels = [[255, 0, 0], [0, 0, 255], [255, 0, 0], [0, 255, 0]].concat();
+    let (palette, indices) = quantize(&pixels, 256);
+    assert_eq!(palette.len(), 3);
+    for (pixel, &index) in pixels.chunks(3).zip(&indices) {
+        assert_eq!(palette[index as usize], [pixel[0], pixel[1], pixel[2]]);
+    }
+
+    // A gradient of 1000 grays down to 16 levels, each within a step of
+    // the pixels it stands for.
+    let pixels = (0..1000)
+        .flat_map(|i| [(i * 255 / 999) as u8; 3])
+        .collect::<Vec<_>>();
+    let (palette, indices) = quantize(&pixels, 16);
+    assert_eq!(palette.len(), 16);
+    for (pixel, &index) in pixels.chunks(3).zip(&indices) {
+        let error = (palette[index as usize][0] as i32 - pixel[0] as i32).abs();
+        assert!(error <= 255 / 16, "{} for {}", error, pixel[0]);
+    }
+}
+
+#[test]
+fn test_encode() {
+    let pixels = (0..16 * 8)
+        .flat_map(|i| [(i * 2) as u8, 0, 255 - i as u8])
+        .collect::<Vec<_>>();
+    let mut encoder = Encoder::new(Vec::new(), (16, 8), 0).unwrap();
+    encoder.write_frame(&pixels, 256, 4).unwrap();
+    encoder.write_frame(&pixels, 8, 4).unwrap();
+    let bytes = encoder.finish().unwrap();
+    assert_eq!(&bytes[..6], b"GIF89a");
+    assert_eq!(&bytes[6..10], &[16, 0, 8, 0]);
+    assert_eq!(&bytes[16..27], b"NETSCAPE2.0");
+    assert_eq!(bytes.last(), Some(&0x3b));
+    // The first frame's graphic control extension, then its descriptor
+    // with a 128 entry color table.
+    assert_eq!(&bytes[32..40], &[0x21, 0xf9, 4, 4, 4, 0, 0, 0]);
+    assert_eq!(bytes[40], 0x2c);
+    assert_eq!(bytes[49], 0x80 | 6);
+    assert!(Encoder::new(Vec::new(), (70_000, 1), 0).is_err());
+}
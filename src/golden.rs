@@ -0,0 +1,218 @@
+//! Golden image tests: small renders of canonical views, compared against
+//! reference images checked in under `testdata/golden`. Each view is also
+//! rendered with the optimizations that must not change the image, such as
+//! boundary tracing, the interior shortcuts and more threads, which are
+//! held to the same reference.
+//!
+//! The comparison allows a few pixels to differ, as a point right on the
+//! boundary of the set can come out either way with floating point that
+//! is rounded differently, but not a change anyone could see across the
+//! image. To write the references afresh after a change that is meant to
+//! alter them, run the tests with `MANDELBROT_BLESS=1` and look them over.
+
+use crate::{
+    animation::View, encode_image, render_to_buffer, Algorithm, Coloring, Fixed, Light, Palette,
+    RenderOptions, Shortcuts,
+};
+use num::Complex;
+use std::path::PathBuf;
+
+/// The size of every golden image.
+const BOUNDS: (u32, u32) = (64, 48);
+
+/// How far apart two colors may be, by `distance`, and still be the same.
+const TOLERANCE: f64 = 12.0;
+
+/// The share of pixels that may be further apart than `TOLERANCE`.
+const MAX_DIFFERING: f64 = 0.01;
+
+/// The canonical views, by the name of their reference image.
+fn cases() -> Vec<(&'static str, RenderOptions)> {
+    let base = RenderOptions {
+        bounds: BOUNDS,
+        palette: Palette::named("classic").unwrap(),
+        threads: 1,
+        ..RenderOptions::default()
+    };
+    let view = |re: &str, im: &str, zoom: f64, options: RenderOptions| {
+        let mut options = options;
+        View {
+            center: Complex {
+                re: Fixed::parse(re).unwrap(),
+                im: Fixed::parse(im).unwrap(),
+            },
+            zoom,
+            flipped: false,
+        }
+        .apply(&mut options);
+        options
+    };
+    vec![
+        ("whole", view("-0.75", "0", 1.0, base.clone())),
+        (
+            "filaments",
+            view(
+                "-0.7455",
+                "0.1122",
+                1000.0,
+                RenderOptions {
+                    max_iter: 1000,
+                    palette: Palette::named("fire").unwrap(),
+                    ..base.clone()
+                },
+            ),
+        ),
+        (
+            "shaded",
+            view(
+                "-0.12",
+                "0.765",
+                25.0,
+                RenderOptions {
+                    max_iter: 500,
+                    palette: Palette::named("grayscale").unwrap(),
+                    coloring: Coloring::Distance,
+                    shading: Some(Light {
+                        azimuth: 45.0,
+                        elevation: 45.0,
+                    }),
+                    ..base.clone()
+                },
+            ),
+        ),
+        (
+            "julia",
+            RenderOptions {
+                julia: Some(Complex::new(-0.8, 0.156)),
+                upper_left: Complex::new(-1.6, 1.2),
+                lower_right: Complex::new(1.6, -1.2),
+                palette: Palette::named("ocean").unwrap(),
+                ..base.clone()
+            },
+        ),
+        (
+            "deep",
+            view(
+                "0",
+                "1",
+                1e20,
+                RenderOptions {
+                    max_iter: 1000,
+                    coloring: Coloring::Histogram,
+                    ..base.clone()
+                },
+            ),
+        ),
+        (
+            "antialiased",
+            view(
+                "-0.75",
+                "0",
+                1.0,
+                RenderOptions {
+                    antialias: 3,
+                    ..base
+                },
+            ),
+        ),
+    ]
+}
+
+/// The ways of rendering `options` that must give its image, as a name
+/// for the failure message and the options.
+fn variants(options: &RenderOptions) -> Vec<(&'static str, RenderOptions)> {
+    vec![
+        ("as given", options.clone()),
+        (
+            "on 3 threads",
+            RenderOptions {
+                threads: 3,
+                ..options.clone()
+            },
+        ),
+        (
+            "without shortcuts",
+            RenderOptions {
+                shortcuts: Shortcuts::NONE,
+                ..options.clone()
+            },
+        ),
+        (
+            "boundary traced",
+            RenderOptions {
+                algorithm: Algorithm::BorderTrace,
+                ..options.clone()
+            },
+        ),
+    ]
+}
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("testdata/golden")
+        .join(format!("{}.png", name))
+}
+
+/// How different two colors look: the distance between them in RGB, with
+/// each channel weighted by how much the eye makes of it ("redmean").
+fn distance(a: &[u8], b: &[u8]) -> f64 {
+    let mean_red = (a[0] as f64 + b[0] as f64) / 2.0;
+    let [dr, dg, db] = [0, 1, 2].map(|k| a[k] as f64 - b[k] as f64);
+    ((2.0 + mean_red / 256.0) * dr * dr
+        + 4.0 * dg * dg
+        + (2.0 + (255.0 - mean_red) / 256.0) * db * db)
+        .sqrt()
+}
+
+/// How many pixels of `actual` look different from `expected`.
+fn differing(actual: &[u8], expected: &[u8]) -> usize {
+    actual
+        .chunks_exact(3)
+        .zip(expected.chunks_exact(3))
+        .filter(|(a, b)| distance(a, b) > TOLERANCE)
+        .count()
+}
+
+#[test]
+fn test_golden_images() {
+    let bless = std::env::var_os("MANDELBROT_BLESS").is_some();
+    let pixels = (BOUNDS.0 * BOUNDS.1) as usize;
+    for (name, options) in cases() {
+        let path = reference_path(name);
+        if bless {
+            let file = std::fs::File::create(&path).unwrap();
+            encode_image(file, &render_to_buffer(options.clone()), BOUNDS, &[]).unwrap();
+        }
+        let bytes = std::fs::read(&path)
+            .unwrap_or_else(|e| panic!("{}: {}; write it with MANDELBROT_BLESS=1", name, e));
+        let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+        let mut expected = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut expected).unwrap();
+        assert_eq!((info.width, info.height), BOUNDS, "{}", name);
+        for (variant, options) in variants(&options) {
+            let actual = render_to_buffer(options);
+            let differing = differing(&actual, &expected);
+            assert!(
+                differing as f64 <= MAX_DIFFERING * pixels as f64,
+                "{} {}: {} of {} pixels differ",
+                name,
+                variant,
+                differing,
+                pixels
+            );
+        }
+    }
+}
+
+#[test]
+fn test_render_to_buffer_is_deterministic() {
+    let (_, options) = &cases()[0];
+    assert_eq!(
+        render_to_buffer(options.clone()),
+        render_to_buffer(options.clone())
+    );
+    let black = [0, 0, 0];
+    assert_eq!(distance(&black, &black), 0.0);
+    assert!(distance(&black, &[0, 16, 0]) > TOLERANCE);
+    assert!(distance(&black, &[4, 4, 4]) < TOLERANCE);
+}
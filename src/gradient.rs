@@ -0,0 +1,310 @@
+//! Gradient files, for palettes beyond the built-in ones. Two formats are
+//! read:
+//!
+//! - CSV, one stop per line as `position,r,g,b`, with positions from 0 to 1
+//!   in order and channels from 0 to 255. A line `interior,r,g,b` sets the
+//!   color of points inside the set, black by default. Blank lines and
+//!   lines starting with `#` are skipped.
+//! - GIMP's `.ggr` gradients. Each segment is sampled into stops, following
+//!   its midpoint and blending function; segments blended in HSV are
+//!   blended in RGB instead, and opacity is ignored.
+//!
+//! Palettes can also be taken from pictures, PNG or baseline JPEG, to theme
+//! renders after them: their dominant colors, from darkest to lightest.
+
+use crate::{jpeg, Palette};
+use std::f64::consts::PI;
+
+/// How many stops each `.ggr` segment is sampled into.
+const SEGMENT_STOPS: usize = 16;
+
+/// How many of a picture's dominant colors a palette taken from it has at
+/// most.
+const IMAGE_COLORS: usize = 8;
+
+/// Reads a gradient file's text, as `.ggr` if `name` ends with it and as
+/// CSV otherwise.
+pub fn parse(name: &str, text: &str) -> Result<Palette, String> {
+    if name.to_lowercase().ends_with(".ggr") {
+        parse_ggr(text)
+    } else {
+        parse_csv(text)
+    }
+}
+
+/// Reads a CSV gradient.
+pub fn parse_csv(text: &str) -> Result<Palette, String> {
+    let mut stops = Vec::<(f64, [u8; 3])>::new();
+    let mut interior = [0, 0, 0];
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split(',').map(str::trim).collect::<Vec<_>>();
+        let [position, r, g, b] = fields[..] else {
+            return Err(error("expected position,r,g,b"));
+        };
+        let mut color = [0; 3];
+        for (channel, field) in color.iter_mut().zip([r, g, b]) {
+            *channel = field
+                .parse()
+                .map_err(|_| error(&format!("invalid channel '{}'", field)))?;
+        }
+        if position == "interior" {
+            interior = color;
+            continue;
+        }
+        let position = position
+            .parse::<f64>()
+            .ok()
+            .filter(|p| (0.0..=1.0).contains(p))
+            .ok_or_else(|| error(&format!("invalid position '{}'", position)))?;
+        if stops.last().is_some_and(|&(last, _)| position < last) {
+            return Err(error("stops must be in order"));
+        }
+        stops.push((position, color));
+    }
+    if stops.is_empty() {
+        return Err("a gradient needs at least one stop".to_string());
+    }
+    Ok(Palette::new(stops, interior))
+}
+
+/// Takes a palette from a picture's bytes, PNG or baseline JPEG; see
+/// `dominant_colors`.
+pub fn from_image(bytes: &[u8]) -> Result<Palette, String> {
+    let pixels = if bytes.starts_with(b"\x89PNG") {
+        decode_png(bytes).map_err(|e| e.to_string())?
+    } else if bytes.starts_with(&[0xff, 0xd8]) {
+        jpeg::decode(bytes)?.0
+    } else {
+        return Err("expected a PNG or JPEG image".to_string());
+    };
+    dominant_colors(&pixels)
+}
+
+/// The RGB pixels of a PNG, whatever its color type, without its alpha.
+fn decode_png(bytes: &[u8]) -> Result<Vec<u8>, png::DecodingError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    buffer.truncate(info.buffer_size());
+    let channels = info.color_type.samples();
+    Ok(buffer
+        .chunks_exact(channels)
+        .flat_map(|pixel| match pixel.len() {
+            1 | 2 => [pixel[0]; 3],
+            _ => [pixel[0], pixel[1], pixel[2]],
+        })
+        .collect())
+}
+
+/// A palette of the colors most of an RGB pixel buffer's pixels are, from
+/// darkest to lightest at even spaces. Colors are counted in boxes 32
+/// levels of each channel wide, each standing for the average of its
+/// pixels, and boxes of under a hundredth of the pixels are left out.
+pub fn dominant_colors(pixels: &[u8]) -> Result<Palette, String> {
+    let mut boxes = vec![(0u64, [0u64; 3]); 512];
+    for pixel in pixels.chunks_exact(3) {
+        let i = pixel.iter().fold(0, |i, &v| i << 3 | (v >> 5) as usize);
+        boxes[i].0 += 1;
+        for (sum, &v) in boxes[i].1.iter_mut().zip(pixel) {
+            *sum += v as u64;
+        }
+    }
+    let total = (pixels.len() / 3) as u64;
+    if total == 0 {
+        return Err("the image has no pixels".to_string());
+    }
+    boxes.sort_by_key(|&(count, _)| std::cmp::Reverse(count));
+    let mut colors = boxes
+        .iter()
+        .take(IMAGE_COLORS)
+        .enumerate()
+        .filter(|&(i, &(count, _))| i == 0 || count * 100 >= total)
+        .map(|(_, (count, sums))| sums.map(|sum| (sum as f64 / *count as f64).round() as u8))
+        .collect::<Vec<_>>();
+    colors.sort_by_key(|&[r, g, b]| 299 * r as u32 + 587 * g as u32 + 114 * b as u32);
+    let last = (colors.len() - 1).max(1) as f64;
+    let stops = colors
+        .into_iter()
+        .enumerate()
+        .map(|(i, color)| (i as f64 / last, color))
+        .collect();
+    Ok(Palette::new(stops, [0, 0, 0]))
+}
+
+/// Reads a GIMP gradient.
+pub fn parse_ggr(text: &str) -> Result<Palette, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()));
+    if lines.next().map(|(_, line)| line) != Some("GIMP Gradient") {
+        return Err("not a GIMP gradient".to_string());
+    }
+    let mut lines = lines.skip_while(|(_, line)| line.starts_with("Name:"));
+    let count = lines
+        .next()
+        .and_then(|(_, line)| line.parse::<usize>().ok())
+        .ok_or("missing segment count")?;
+    let mut stops = Vec::new();
+    for _ in 0..count {
+        let (number, line) = lines.next().ok_or("missing segments")?;
+        let error = |message: &str| format!("line {}: {}", number, message);
+        let fields = line
+            .split_whitespace()
+            .map(str::parse::<f64>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| error("invalid number"))?;
+        if fields.len() < 13 {
+            return Err(error("expected at least 13 fields"));
+        }
+        let (left, middle, right) = (fields[0], fields[1], fields[2]);
+        if !(0.0 <= left && left <= middle && middle <= right && right <= 1.0) {
+            return Err(error("invalid segment bounds"));
+        }
+        if stops.last().is_some_and(|&(last, _)| left < last) {
+            return Err(error("segments must be in order"));
+        }
+        let blend = fields[11] as u32;
+        if blend > 4 {
+            return Err(error(&format!("unknown blending function {}", blend)));
+        }
+        let channel = |v: f64| (v.clamp(0.0, 1.0) * 255.0).round();
+        let (start, end) = (&fields[3..6], &fields[7..10]);
+        for k in 0..=SEGMENT_STOPS {
+            let position = left + (right - left) * k as f64 / SEGMENT_STOPS as f64;
+            let f = blended(blend, position, left, middle, right);
+            let color = std::array::from_fn(|i| channel(start[i] + (end[i] - start[i]) * f) as u8);
+            stops.push((position, color));
+        }
+    }
+    if stops.is_empty() {
+        return Err("a gradient needs at least one segment".to_string());
+    }
+    Ok(Palette::new(stops, [0, 0, 0]))
+}
+
+/// How far from its left color to its right a segment is at `position`,
+/// for GIMP's blending functions: linear, curved, sine, and spherical
+/// increasing and decreasing. The middle point takes the halfway color.
+fn blended(blend: u32, position: f64, left: f64, middle: f64, right: f64) -> f64 {
+    let length = right - left;
+    if length <= 0.0 {
+        return 0.0;
+    }
+    let t = (position - left) / length;
+    let m = (middle - left) / length;
+    // Linear from the left to the middle, then from the middle to the right.
+    let linear = if t <= m {
+        if m > 0.0 {
+            0.5 * t / m
+        } else {
+            0.0
+        }
+    } else if m < 1.0 {
+        0.5 + 0.5 * (t - m) / (1.0 - m)
+    } else {
+        1.0
+    };
+    match blend {
+        1 => t.powf(0.5f64.ln() / m.max(1e-10).ln()),
+        2 => ((PI * linear - PI / 2.0).sin() + 1.0) / 2.0,
+        3 => (1.0 - (linear - 1.0).powi(2)).sqrt(),
+        4 => 1.0 - (1.0 - linear * linear).sqrt(),
+        _ => linear,
+    }
+}
+
+#[test]
+fn test_parse_csv() {
+    let palette = parse_csv(
+        "# sunset\n\
+         0, 0, 0, 64\n\
+         0.5, 255, 128, 0\n\
+         \n\
+         1, 255, 255, 255\n\
+         interior, 1, 2, 3\n",
+    )
+    .unwrap();
+    assert_eq!(palette.at(0.0), [0, 0, 64]);
+    assert_eq!(palette.at(0.25), [128, 64, 32]);
+    assert_eq!(palette.at(1.0), [255, 255, 255]);
+    assert_eq!(palette.color_at(None), [1, 2, 3]);
+
+    assert!(parse_csv("").is_err());
+    assert!(parse_csv("0.5,1,2").is_err());
+    assert!(parse_csv("0.5,1,2,300").is_err());
+    assert!(parse_csv("1.5,1,2,3").is_err());
+    assert_eq!(
+        parse_csv("0.5,1,2,3\n0.25,1,2,3"),
+        Err("line 2: stops must be in order".to_string())
+    );
+}
+
+#[test]
+fn test_parse_ggr() {
+    // Black to white, linear with its middle at a quarter, then white to
+    // red, sine.
+    let palette = parse(
+        "sunrise.ggr",
+        "GIMP Gradient\n\
+         Name: Sunrise\n\
+         2\n\
+         0 0.125 0.5 0 0 0 1 1 1 1 1 0 0\n\
+         0.5 0.75 1 1 1 1 1 1 0 0 1 2 0 0 0\n",
+    )
+    .unwrap();
+    assert_eq!(palette.at(0.0), [0, 0, 0]);
+    assert_eq!(palette.at(0.125), [128, 128, 128]);
+    assert_eq!(palette.at(0.5), [255, 255, 255]);
+    assert_eq!(palette.at(0.75), [255, 128, 128]);
+    assert_eq!(palette.at(1.0), [255, 0, 0]);
+
+    assert!(parse_ggr("GIMP Palette\n").is_err());
+    assert!(parse_ggr("GIMP Gradient\n2\n0 0.5 1 0 0 0 1 1 1 1 1 0 0\n").is_err());
+    assert!(parse_ggr("GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1 7 0\n").is_err());
+}
+
+#[test]
+fn test_blended() {
+    for blend in 0..=4 {
+        assert_eq!(blended(blend, 0.0, 0.0, 0.5, 1.0), 0.0);
+        assert!((blended(blend, 1.0, 0.0, 0.5, 1.0) - 1.0).abs() < 1e-12);
+    }
+    // All but the spherical ones are halfway at the middle.
+    for blend in 0..=2 {
+        assert!((blended(blend, 0.3, 0.2, 0.3, 1.0) - 0.5).abs() < 1e-12);
+    }
+    assert!(blended(3, 0.5, 0.0, 0.5, 1.0) > 0.5);
+    assert!(blended(4, 0.5, 0.0, 0.5, 1.0) < 0.5);
+}
+
+#[test]
+fn test_dominant_colors() {
+    // Mostly dark blue, then orange, with a stray pixel of green.
+    let mut pixels = [[10, 20, 120]; 120].concat();
+    pixels.extend([[250, 160, 20]; 79].concat());
+    pixels.extend([0, 255, 0]);
+    let palette = dominant_colors(&pixels).unwrap();
+    assert_eq!(palette.at(0.0), [10, 20, 120]);
+    assert_eq!(palette.at(1.0), [250, 160, 20]);
+    assert_eq!(palette.at(0.5), [130, 90, 70]);
+    assert!(dominant_colors(&[]).is_err());
+    let one = dominant_colors(&[1, 2, 3, 3, 2, 1]).unwrap();
+    assert_eq!(one.at(0.7), [2, 2, 2]);
+
+    let mut png = Vec::new();
+    crate::encode_image(&mut png, &pixels, (20, 10), &[]).unwrap();
+    assert_eq!(from_image(&png).unwrap(), palette);
+    let mut jpeg = Vec::new();
+    jpeg::encode(&mut jpeg, &pixels, (20, 10), 100).unwrap();
+    let from_jpeg = from_image(&jpeg).unwrap();
+    assert!(from_jpeg.at(0.0)[2] > 100 && from_jpeg.at(1.0)[0] > 200);
+    assert!(from_image(b"GIF89a").is_err());
+}
@@ -0,0 +1,146 @@
+//! HDR images: 16-bit RGB PNGs in BT.2100's PQ encoding, marked as such by
+//! a `cICP` chunk, which HDR displays light past the white of a page. The
+//! colors of escaped points are taken from their escape values before they
+//! are rounded to bytes, so that gradients keep the precision 16 bits give
+//! them, and are converted to BT.2020's primaries.
+//!
+//! Colors stay where an SDR display would put them, with sRGB white at the
+//! 203 nits of BT.2408's reference white, until their luminance nears the
+//! top, from where they are stretched up to the peak asked for: the bright
+//! bands of a palette glow while the rest of it looks as it always does.
+
+use crate::{add_text, palette::decode_srgb_exact, Colorizer, Escape};
+use png::{chunk::ChunkType, EncodingError};
+use std::io::Write;
+
+/// The brightness of white in an SDR image, in nits.
+pub const SDR_WHITE: f64 = 203.0;
+
+/// The brightest PQ can encode, in nits.
+pub const MAX_NITS: f64 = 10_000.0;
+
+/// The peak brightness when none is asked for.
+pub const DEFAULT_PEAK: f64 = 1000.0;
+
+/// BT.709 linear light to BT.2020's, from BT.2087.
+const BT709_TO_BT2020: [[f64; 3]; 3] = [
+    [0.6274, 0.3293, 0.0433],
+    [0.0691, 0.9195, 0.0114],
+    [0.0164, 0.0880, 0.8956],
+];
+
+/// BT.2020's primaries, the PQ transfer function, RGB and full range.
+const CICP: [u8; 4] = [9, 16, 0, 1];
+
+/// The PQ encoding of a brightness in nits, from 0 to 1.
+pub fn pq(nits: f64) -> f64 {
+    const M1: f64 = 2610.0 / 16384.0;
+    const M2: f64 = 2523.0 / 4096.0 * 128.0;
+    const C1: f64 = 3424.0 / 4096.0;
+    const C2: f64 = 2413.0 / 4096.0 * 32.0;
+    const C3: f64 = 2392.0 / 4096.0 * 32.0;
+    let y = (nits / MAX_NITS).clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+/// The PQ samples of an image, three per pixel: the escaped points colored
+/// by `colorizer` from their escape values, and the rest as in `colors`,
+/// with white at `peak` nits.
+pub fn samples(
+    colors: &[u8],
+    escapes: &[Option<Escape>],
+    colorizer: &dyn Colorizer,
+    peak: f64,
+) -> Vec<u16> {
+    let stretch = peak / SDR_WHITE - 1.0;
+    escapes
+        .iter()
+        .zip(colors.chunks_exact(3))
+        .flat_map(|(&escape, color)| {
+            let srgb = match escape {
+                Some(_) => colorizer.color_exact(escape),
+                None => [color[0], color[1], color[2]].map(f64::from),
+            };
+            let linear = srgb.map(|v| decode_srgb_exact(v / 255.0));
+            let rgb = BT709_TO_BT2020.map(|row| (0..3).map(|c| row[c] * linear[c]).sum::<f64>());
+            let luminance = 0.2627 * rgb[0] + 0.6780 * rgb[1] + 0.0593 * rgb[2];
+            let gain = SDR_WHITE * (1.0 + stretch * luminance * luminance);
+            rgb.map(|v| (pq(v * gain) * u16::MAX as f64).round() as u16)
+        })
+        .collect()
+}
+
+/// Encodes PQ samples as a 16-bit RGB PNG marked as HDR, with text chunks
+/// like `encode_image`.
+pub fn encode<W: Write>(
+    w: W,
+    samples: &[u16],
+    bounds: (u32, u32),
+    text: &[(&str, String)],
+) -> Result<(), EncodingError> {
+    let mut encoder = png::Encoder::new(w, bounds.0, bounds.1);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    add_text(&mut encoder, text)?;
+    let mut writer = encoder.write_header()?;
+    writer.write_chunk(ChunkType(*b"cICP"), &CICP)?;
+    let mut stream = writer.stream_writer()?;
+    let mut bytes = Vec::with_capacity(6 * bounds.0 as usize);
+    for row in samples.chunks(3 * bounds.0.max(1) as usize) {
+        bytes.clear();
+        bytes.extend(row.iter().flat_map(|sample| sample.to_be_bytes()));
+        stream.write_all(&bytes)?;
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+#[test]
+fn test_pq() {
+    assert!(pq(0.0) < 1e-6);
+    assert!((pq(MAX_NITS) - 1.0).abs() < 1e-12);
+    // Reference white and 1000 nits, from BT.2408's table.
+    assert!((pq(SDR_WHITE) - 0.58).abs() < 0.005);
+    assert!((pq(1000.0) - 0.75).abs() < 0.005);
+}
+
+#[test]
+fn test_samples() {
+    use crate::{Palette, RenderOptions, Renderer};
+    let options = RenderOptions {
+        bounds: (40, 30),
+        palette: Palette::named("fire").unwrap(),
+        ..RenderOptions::default()
+    };
+    let renderer = Renderer::new(options.clone());
+    let escapes = renderer.render_escapes();
+    let colors = renderer.colorize(&escapes);
+    let colorizer = renderer.colorizer(&escapes);
+    let sdr = samples(&colors, &escapes, &*colorizer, SDR_WHITE);
+    let hdr = samples(&colors, &escapes, &*colorizer, DEFAULT_PEAK);
+    assert_eq!(sdr.len(), colors.len());
+    // At an SDR peak, white is reference white, and black is black.
+    let white = (pq(SDR_WHITE) * u16::MAX as f64).round() as u16;
+    assert!(sdr.iter().all(|&v| v <= white + 1));
+    let interior = escapes.iter().position(Option::is_none).unwrap();
+    assert!(sdr[3 * interior..3 * interior + 3].iter().all(|&v| v < 100));
+    // Brighter colors are stretched further.
+    assert!(hdr.iter().zip(&sdr).all(|(&h, &s)| h >= s));
+    assert!(hdr.iter().max() > sdr.iter().max());
+
+    let mut bytes = Vec::new();
+    encode(&mut bytes, &hdr, options.bounds, &[]).unwrap();
+    let decoder = png::Decoder::new(&bytes[..]);
+    let mut reader = decoder.read_info().unwrap();
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut buffer).unwrap();
+    assert_eq!(reader.info().bit_depth, png::BitDepth::Sixteen);
+    let decoded = buffer
+        .chunks(2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, hdr);
+    let at = bytes.windows(4).position(|w| w == b"cICP").unwrap();
+    assert_eq!(bytes[at + 4..at + 8], CICP);
+    assert!(at < bytes.windows(4).position(|w| w == b"IDAT").unwrap());
+}
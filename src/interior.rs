@@ -0,0 +1,144 @@
+//! Coloring the inside of the set. Points inside never escape, so nothing
+//! about their escape tells them apart; instead their orbits are iterated
+//! again, in `f64`, to see where they settle. Almost every one is drawn to
+//! an attracting cycle, and how fast and how strongly it pulls them in
+//! varies smoothly across each bulb.
+
+use crate::{fractal::State, Fractal};
+use num::Complex;
+
+/// How points inside the set are colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interior {
+    /// The palette's interior color.
+    Flat,
+    /// `|z|` after the last iteration, relative to the escape radius.
+    Modulus,
+    /// The size of the multiplier of the cycle the orbit is drawn to,
+    /// which is 0 at the center of a bulb and 1 on its edge.
+    Multiplier,
+    /// How many iterations the orbit takes to reach its cycle, relative to
+    /// the maximum.
+    Convergence,
+}
+
+/// Names accepted by `Interior::named`, in the order they are listed in
+/// help.
+pub const NAMES: &[&str] = &["flat", "modulus", "multiplier", "convergence"];
+
+/// Orbit points closer together than this, squared, count as the same
+/// point of a cycle.
+const CYCLE_TOLERANCE: f64 = 1e-18;
+
+/// The longest cycle looked for.
+const MAX_PERIOD: u32 = 1024;
+
+impl Interior {
+    /// Looks an interior coloring up by name, as given to `--interior`.
+    pub fn named(name: &str) -> Option<Interior> {
+        match name {
+            "flat" => Some(Interior::Flat),
+            "modulus" => Some(Interior::Modulus),
+            "multiplier" => Some(Interior::Multiplier),
+            "convergence" => Some(Interior::Convergence),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Interior::Flat => "flat",
+            Interior::Modulus => "modulus",
+            Interior::Multiplier => "multiplier",
+            Interior::Convergence => "convergence",
+        }
+    }
+
+    /// Where on the palette, from 0 to 1, the point at `point` goes, for a
+    /// point that doesn't escape `fractal` in `limit` iterations. `None`
+    /// leaves it in the interior color, as for orbits not yet settled on a
+    /// cycle. The multiplier's size is the product of `d |z|^(d - 1)`
+    /// around the cycle for a fractal of degree `d`, which the folds of `z`
+    /// in some formulas don't change; it doesn't apply to the Phoenix set,
+    /// whose steps depend on more than `z`.
+    pub fn position(
+        self,
+        point: Complex<f64>,
+        fractal: &Fractal,
+        julia: Option<Complex<f64>>,
+        limit: u32,
+    ) -> Option<f64> {
+        let (start, c) = fractal.start(point, julia);
+        let step = |state: State| fractal.step(state, c);
+        let mut state = State::new(start);
+        for _ in 0..limit {
+            state = step(state);
+        }
+        match self {
+            Interior::Flat => None,
+            Interior::Modulus => Some((state.z.norm() / 2.0).min(1.0)),
+            Interior::Multiplier => {
+                let period = period(state, step)?;
+                let degree = fractal.degree();
+                let mut multiplier = 1.0;
+                for _ in 0..period {
+                    multiplier *= degree * state.z.norm().powf(degree - 1.0);
+                    state = step(state);
+                }
+                Some(multiplier.min(1.0))
+            }
+            Interior::Convergence => {
+                let period = period(state, step)?;
+                // Follow the orbit alongside itself a period ahead until
+                // the two meet.
+                let mut ahead = State::new(start);
+                for _ in 0..period {
+                    ahead = step(ahead);
+                }
+                let mut behind = State::new(start);
+                let settled = (0..limit).position(|_| {
+                    let met = (ahead.z - behind.z).norm_sqr() < CYCLE_TOLERANCE;
+                    behind = step(behind);
+                    ahead = step(ahead);
+                    met
+                })?;
+                Some(settled as f64 / limit.max(1) as f64)
+            }
+        }
+    }
+}
+
+/// The period of the cycle `state` is on, if it comes back within
+/// `MAX_PERIOD` steps.
+fn period(state: State, step: impl Fn(State) -> State) -> Option<u32> {
+    let mut w = state;
+    (1..=MAX_PERIOD).find(|_| {
+        w = step(w);
+        (w.z - state.z).norm_sqr() < CYCLE_TOLERANCE
+    })
+}
+
+#[test]
+fn test_interior_position() {
+    let at = |interior: Interior, re: f64, im: f64| {
+        interior.position(Complex::new(re, im), &Fractal::Mandelbrot, None, 1000)
+    };
+    // 0 is its own cycle, with multiplier 0, from the first iteration.
+    assert_eq!(at(Interior::Multiplier, 0.0, 0.0), Some(0.0));
+    assert_eq!(at(Interior::Convergence, 0.0, 0.0), Some(0.0));
+    assert_eq!(at(Interior::Modulus, 0.0, 0.0), Some(0.0));
+    assert_eq!(at(Interior::Flat, 0.0, 0.0), None);
+    // c = -1 is the center of the period 2 bulb: 0 and -1 alternate.
+    assert_eq!(at(Interior::Multiplier, -1.0, 0.0), Some(0.0));
+    // c = 0.2 is drawn to a fixed point z with z² + c = z, whose
+    // multiplier is 2z.
+    let fixed = (1.0 - (1.0f64 - 0.8).sqrt()) / 2.0;
+    let multiplier = at(Interior::Multiplier, 0.2, 0.0).unwrap();
+    assert!((multiplier - 2.0 * fixed).abs() < 1e-6, "{}", multiplier);
+    let modulus = at(Interior::Modulus, 0.2, 0.0).unwrap();
+    assert!((modulus - fixed / 2.0).abs() < 1e-6, "{}", modulus);
+    // Nearer the edge of the cardioid, orbits take longer to settle.
+    let near = at(Interior::Convergence, 0.2, 0.0).unwrap();
+    let far = at(Interior::Convergence, 0.24, 0.0).unwrap();
+    assert!(0.0 < near && near < far, "{} {}", near, far);
+}
@@ -0,0 +1,98 @@
+//! Ctrl-C during a render. The first asks the renders being watched to
+//! stop after the bands under way, so that the rows done can be saved and
+//! carried on from later; a second, or one while nothing is watched, quits
+//! at once as usual.
+
+use mandelbrot::Progress;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The exit code of a program stopped by Ctrl-C, by the shells' convention
+/// of 128 plus the signal.
+pub const EXIT_CODE: i32 = 130;
+
+/// Whether Ctrl-C has been pressed during a watched render.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// How many renders are being watched.
+static WATCHING: AtomicUsize = AtomicUsize::new(0);
+
+/// Takes over Ctrl-C for the rest of the program.
+#[cfg(unix)]
+pub fn install() {
+    const SIGINT: i32 = 2;
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        fn _exit(status: i32) -> !;
+    }
+    extern "C" fn on_interrupt(_: i32) {
+        // Nothing but atomics and _exit is safe in a signal handler.
+        if WATCHING.load(Ordering::SeqCst) == 0 || INTERRUPTED.swap(true, Ordering::SeqCst) {
+            unsafe { _exit(EXIT_CODE) }
+        }
+    }
+    unsafe {
+        signal(SIGINT, on_interrupt);
+    }
+}
+
+/// Leaves Ctrl-C to quit at once where signals can't be caught this way.
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Whether Ctrl-C has been pressed during a watched render.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Cancels a render on Ctrl-C for as long as it is kept.
+pub struct Watch {
+    done: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+/// Watches for Ctrl-C during the render of `progress`, which must stop
+/// when cancelled; see `Progress::cancel`.
+pub fn watch(progress: Arc<Progress>) -> Watch {
+    WATCHING.fetch_add(1, Ordering::SeqCst);
+    let done = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let done = done.clone();
+        // The signal handler can't reach the render, so this looks in on
+        // it for the handler.
+        thread::spawn(move || {
+            while !done.load(Ordering::SeqCst) {
+                if interrupted() {
+                    eprintln!(
+                        "\rinterrupted: finishing the rows under way; \
+                         press Ctrl-C again to quit now"
+                    );
+                    progress.cancel();
+                    break;
+                }
+                thread::park_timeout(Duration::from_millis(50));
+            }
+        })
+    };
+    Watch {
+        done,
+        thread: Some(thread),
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        WATCHING.fetch_sub(1, Ordering::SeqCst);
+        self.done.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
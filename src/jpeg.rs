@@ -0,0 +1,859 @@
+//! A baseline JPEG encoder: 8×8 DCT blocks of full resolution YCbCr, scaled
+//! quantization tables and the standard Huffman tables from annex K of the
+//! specification. Nothing fancy, but renders with large smooth gradients
+//! come out a fraction of the size of a PNG.
+//!
+//! Baseline JPEGs are also decoded, subsampled or not, so that palettes can
+//! be taken from photographs; see `gradient::from_image`.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+/// The example luminance quantization table from the specification, in
+/// natural (row-major) order.
+const LUMA_QUANT: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// The example chrominance quantization table, in natural order.
+const CHROMA_QUANT: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// The natural index of each coefficient, in the zigzag order they are
+/// stored in.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// A Huffman table as stored in the file: the number of codes of each
+/// length from 1 to 16 bits, then the symbols in order of their codes.
+struct HuffmanSpec {
+    counts: [u8; 16],
+    symbols: &'static [u8],
+}
+
+const LUMA_DC: HuffmanSpec = HuffmanSpec {
+    counts: [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    symbols: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+const CHROMA_DC: HuffmanSpec = HuffmanSpec {
+    counts: [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    symbols: &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+const LUMA_AC: HuffmanSpec = HuffmanSpec {
+    counts: [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d],
+    symbols: &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61,
+        0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52,
+        0xd1, 0xf0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25,
+        0x26, 0x27, 0x28, 0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45,
+        0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64,
+        0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83,
+        0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99,
+        0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6,
+        0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3,
+        0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8,
+        0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+    ],
+};
+
+const CHROMA_AC: HuffmanSpec = HuffmanSpec {
+    counts: [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    symbols: &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61,
+        0x71, 0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33,
+        0x52, 0xf0, 0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18,
+        0x19, 0x1a, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44,
+        0x45, 0x46, 0x47, 0x48, 0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63,
+        0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a,
+        0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97,
+        0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4,
+        0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca,
+        0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7,
+        0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9, 0xfa,
+    ],
+};
+
+/// The quality used when none is given.
+pub const DEFAULT_QUALITY: u8 = 90;
+
+/// Writes an RGB pixel buffer to `filename` as a JPEG of the given quality,
+/// from 1 (smallest) to 100 (best).
+pub fn write_image(
+    filename: &str,
+    pixels: &[u8],
+    bounds: (u32, u32),
+    quality: u8,
+) -> io::Result<()> {
+    let file = File::create(filename)?;
+    let mut w = BufWriter::new(file);
+    encode(&mut w, pixels, bounds, quality)?;
+    w.flush()
+}
+
+/// Encodes an RGB pixel buffer as a baseline JPEG.
+pub fn encode<W: Write>(
+    w: &mut W,
+    pixels: &[u8],
+    bounds: (u32, u32),
+    quality: u8,
+) -> io::Result<()> {
+    let (width, height) = bounds;
+    if width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "JPEG images are limited to 65535 pixels a side",
+        ));
+    }
+    let tables = [
+        scaled_table(&LUMA_QUANT, quality),
+        scaled_table(&CHROMA_QUANT, quality),
+    ];
+
+    w.write_all(&[0xff, 0xd8])?;
+    // JFIF header, version 1.1, no density or thumbnail.
+    segment(
+        w,
+        0xe0,
+        &[b'J', b'F', b'I', b'F', 0, 1, 1, 0, 0, 1, 0, 1, 0, 0],
+    )?;
+    for (id, table) in tables.iter().enumerate() {
+        let mut data = vec![id as u8];
+        data.extend(ZIGZAG.iter().map(|&i| table[i] as u8));
+        segment(w, 0xdb, &data)?;
+    }
+    let mut frame = vec![8];
+    frame.extend_from_slice(&(height as u16).to_be_bytes());
+    frame.extend_from_slice(&(width as u16).to_be_bytes());
+    frame.extend_from_slice(&[3, 1, 0x11, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    segment(w, 0xc0, &frame)?;
+    for (class_id, spec) in [
+        (0x00, &LUMA_DC),
+        (0x10, &LUMA_AC),
+        (0x01, &CHROMA_DC),
+        (0x11, &CHROMA_AC),
+    ] {
+        let mut data = vec![class_id];
+        data.extend_from_slice(&spec.counts);
+        data.extend_from_slice(spec.symbols);
+        segment(w, 0xc4, &data)?;
+    }
+    segment(w, 0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0])?;
+
+    let codes = [
+        (Huffman::new(&LUMA_DC), Huffman::new(&LUMA_AC)),
+        (Huffman::new(&CHROMA_DC), Huffman::new(&CHROMA_AC)),
+    ];
+    let mut bits = BitWriter::new(w);
+    let mut previous_dc = [0i32; 3];
+    for block_row in 0..height.div_ceil(8) {
+        for block_column in 0..width.div_ceil(8) {
+            let samples: [[f32; 3]; 64] = std::array::from_fn(|i| {
+                // Blocks hanging off the edge repeat the last row or column.
+                let x = (block_column * 8 + i as u32 % 8).min(width - 1);
+                let y = (block_row * 8 + i as u32 / 8).min(height - 1);
+                let offset = 3 * (y as usize * width as usize + x as usize);
+                let [r, g, b] = [0, 1, 2].map(|k| pixels[offset + k] as f32);
+                [
+                    0.299 * r + 0.587 * g + 0.114 * b - 128.0,
+                    -0.168_736 * r - 0.331_264 * g + 0.5 * b,
+                    0.5 * r - 0.418_688 * g - 0.081_312 * b,
+                ]
+            });
+            let blocks: [[f32; 64]; 3] =
+                std::array::from_fn(|k| std::array::from_fn(|i| samples[i][k]));
+            for (component, block) in blocks.iter().enumerate() {
+                let table = &tables[(component > 0) as usize];
+                let (dc, ac) = &codes[(component > 0) as usize];
+                let coefficients = forward_dct(block);
+                let quantized: [i32; 64] = std::array::from_fn(|k| {
+                    let i = ZIGZAG[k];
+                    (coefficients[i] / table[i] as f32).round() as i32
+                });
+                let difference = quantized[0] - previous_dc[component];
+                previous_dc[component] = quantized[0];
+                let (size, value) = magnitude(difference);
+                bits.write(dc.codes[size as usize])?;
+                bits.write((value, size))?;
+                let mut zeros = 0;
+                for &coefficient in &quantized[1..] {
+                    if coefficient == 0 {
+                        zeros += 1;
+                        continue;
+                    }
+                    while zeros > 15 {
+                        bits.write(ac.codes[0xf0])?;
+                        zeros -= 16;
+                    }
+                    let (size, value) = magnitude(coefficient);
+                    bits.write(ac.codes[(zeros << 4 | size) as usize])?;
+                    bits.write((value, size))?;
+                    zeros = 0;
+                }
+                if zeros > 0 {
+                    bits.write(ac.codes[0x00])?;
+                }
+            }
+        }
+    }
+    bits.flush()?;
+    w.write_all(&[0xff, 0xd9])
+}
+
+/// Writes a marker segment with its length.
+fn segment<W: Write>(w: &mut W, marker: u8, data: &[u8]) -> io::Result<()> {
+    w.write_all(&[0xff, marker])?;
+    w.write_all(&(data.len() as u16 + 2).to_be_bytes())?;
+    w.write_all(data)
+}
+
+/// Scales a quantization table the way libjpeg does for a given quality.
+fn scaled_table(base: &[u16; 64], quality: u8) -> [u16; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - 2 * quality
+    };
+    base.map(|q| ((q as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+/// The two-dimensional DCT-II of a block, scaled as the specification
+/// expects.
+fn forward_dct(block: &[f32; 64]) -> [f32; 64] {
+    let cosines: [[f32; 8]; 8] = std::array::from_fn(|u| {
+        std::array::from_fn(|x| ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos())
+    });
+    let weight = |u: usize| {
+        if u == 0 {
+            std::f32::consts::FRAC_1_SQRT_2
+        } else {
+            1.0
+        }
+    };
+    let mut rows = [0.0f32; 64];
+    for y in 0..8 {
+        for u in 0..8 {
+            rows[y * 8 + u] = (0..8)
+                .map(|x| block[y * 8 + x] * cosines[u][x])
+                .sum::<f32>();
+        }
+    }
+    std::array::from_fn(|i| {
+        let (v, u) = (i / 8, i % 8);
+        let sum = (0..8).map(|y| rows[y * 8 + u] * cosines[v][y]).sum::<f32>();
+        0.25 * weight(u) * weight(v) * sum
+    })
+}
+
+/// The bit length of a coefficient and the bits that encode it, negative
+/// values being stored as their one's complement.
+fn magnitude(value: i32) -> (u32, u32) {
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    let bits = if value < 0 {
+        (value - 1) as u32 & ((1 << size) - 1)
+    } else {
+        value as u32
+    };
+    (size, bits)
+}
+
+/// The code and its length in bits for each symbol of a Huffman table.
+struct Huffman {
+    codes: [(u32, u32); 256],
+}
+
+impl Huffman {
+    fn new(spec: &HuffmanSpec) -> Huffman {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0;
+        let mut symbols = spec.symbols.iter();
+        for (length, &count) in (1..=16).zip(&spec.counts) {
+            for _ in 0..count {
+                codes[*symbols.next().unwrap() as usize] = (code, length);
+                code += 1;
+            }
+            code <<= 1;
+        }
+        Huffman { codes }
+    }
+}
+
+/// Packs codes most significant bit first, stuffing a zero byte after every
+/// 0xff as entropy coded data requires.
+struct BitWriter<'a, W: Write> {
+    w: &'a mut W,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a, W: Write> BitWriter<'a, W> {
+    fn new(w: &'a mut W) -> BitWriter<'a, W> {
+        BitWriter {
+            w,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Writes the low `length` bits of `bits`.
+    fn write(&mut self, (bits, length): (u32, u32)) -> io::Result<()> {
+        for i in (0..length).rev() {
+            self.buffer = self.buffer << 1 | (bits >> i & 1);
+            self.count += 1;
+            if self.count == 8 {
+                let byte = self.buffer as u8;
+                self.w.write_all(&[byte])?;
+                if byte == 0xff {
+                    self.w.write_all(&[0])?;
+                }
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pads the last byte with ones.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.count > 0 {
+            let padding = 8 - self.count;
+            self.write(((1 << padding) - 1, padding))?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes a baseline JPEG, as cameras and most software write them, into
+/// an RGB pixel buffer and its size. Progressive, lossless and arithmetic
+/// coded files aren't read, and neither are CMYK ones.
+pub fn decode(bytes: &[u8]) -> Result<(Vec<u8>, (u32, u32)), String> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.u16()? != 0xffd8 {
+        return Err("not a JPEG file".to_string());
+    }
+    let mut tables = [[0u16; 64]; 4];
+    let mut huffman: [Option<HuffmanDecoder>; 8] = Default::default();
+    let mut frame: Option<Frame> = None;
+    let mut restart = 0;
+    loop {
+        if r.u8()? != 0xff {
+            return Err("expected a marker".to_string());
+        }
+        let mut marker = r.u8()?;
+        while marker == 0xff {
+            marker = r.u8()?;
+        }
+        if marker == 0xd9 {
+            break;
+        }
+        let length = r.u16()? as usize;
+        let mut segment = Reader {
+            bytes: r.take(length.checked_sub(2).ok_or("a segment is too short")?)?,
+            pos: 0,
+        };
+        match marker {
+            0xc0 | 0xc1 => frame = Some(Frame::parse(&mut segment)?),
+            0xc2..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                return Err("only baseline JPEGs can be read".to_string())
+            }
+            0xc4 => {
+                while segment.pos < segment.bytes.len() {
+                    let class_id = segment.u8()?;
+                    let counts: [u8; 16] = segment.take(16)?.try_into().unwrap();
+                    let total = counts.iter().map(|&c| c as usize).sum();
+                    let symbols = segment.take(total)?;
+                    let index = (class_id >> 4 & 1) as usize * 4 + (class_id & 3) as usize;
+                    huffman[index] = Some(HuffmanDecoder::new(&counts, symbols));
+                }
+            }
+            0xdb => {
+                while segment.pos < segment.bytes.len() {
+                    let precision_id = segment.u8()?;
+                    let table = &mut tables[(precision_id & 3) as usize];
+                    for &i in &ZIGZAG {
+                        table[i] = if precision_id >> 4 == 0 {
+                            segment.u8()? as u16
+                        } else {
+                            segment.u16()?
+                        };
+                    }
+                }
+            }
+            0xdd => restart = segment.u16()? as usize,
+            0xda => {
+                let frame = frame
+                    .as_mut()
+                    .ok_or("a scan comes before the frame header")?;
+                let count = segment.u8()? as usize;
+                let mut scan = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let (id, tables) = (segment.u8()?, segment.u8()?);
+                    let component = frame
+                        .components
+                        .iter()
+                        .position(|c| c.id == id)
+                        .ok_or("a scan names a component the frame doesn't have")?;
+                    scan.push((component, (tables >> 4 & 3) as usize, (tables & 3) as usize));
+                }
+                r.pos = frame.decode_scan(r.bytes, r.pos, &scan, &huffman, &tables, restart)?;
+            }
+            _ => {}
+        }
+    }
+    let frame = frame.ok_or("the JPEG has no frame")?;
+    Ok((frame.to_rgb(), (frame.width as u32, frame.height as u32)))
+}
+
+/// Reads big-endian values out of a JPEG's bytes.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let taken = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or("the JPEG ends early")?;
+        self.pos += n;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+}
+
+/// A Huffman table for decoding: for each code length, the highest code of
+/// that length, if there are any, and where its symbols start.
+struct HuffmanDecoder {
+    max_code: [i32; 17],
+    offsets: [i32; 17],
+    symbols: Vec<u8>,
+}
+
+impl HuffmanDecoder {
+    fn new(counts: &[u8; 16], symbols: &[u8]) -> HuffmanDecoder {
+        let mut max_code = [-1; 17];
+        let mut offsets = [0; 17];
+        let (mut code, mut index) = (0, 0);
+        for (length, &count) in (1..=16).zip(counts) {
+            // Symbol `index + k` has code `code + k`.
+            offsets[length] = index - code;
+            if count > 0 {
+                max_code[length] = code + count as i32 - 1;
+            }
+            code = (code + count as i32) << 1;
+            index += count as i32;
+        }
+        HuffmanDecoder {
+            max_code,
+            offsets,
+            symbols: symbols.to_vec(),
+        }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u8, String> {
+        let mut code = 0;
+        for length in 1..=16 {
+            code = code << 1 | bits.bit() as i32;
+            if code <= self.max_code[length] {
+                let index = (code + self.offsets[length]) as usize;
+                return self.symbols.get(index).copied().ok_or(BAD_CODE.to_string());
+            }
+        }
+        Err(BAD_CODE.to_string())
+    }
+}
+
+const BAD_CODE: &str = "the JPEG's data is corrupt";
+
+/// Reads entropy coded data most significant bit first, dropping the zero
+/// byte stuffed after every 0xff. At a marker it reads zeros instead, as
+/// libjpeg does for data cut short.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> u32 {
+        if self.count == 0 {
+            self.buffer = match self.bytes.get(self.pos..self.pos + 2) {
+                Some([0xff, 0]) => {
+                    self.pos += 2;
+                    0xff
+                }
+                Some([0xff, _]) => 0,
+                _ if self.pos < self.bytes.len() => {
+                    self.pos += 1;
+                    self.bytes[self.pos - 1] as u32
+                }
+                _ => 0,
+            };
+            self.count = 8;
+        }
+        self.count -= 1;
+        self.buffer >> self.count & 1
+    }
+
+    /// `length` bits, as a coefficient with that bit length, undoing
+    /// `magnitude`.
+    fn value(&mut self, length: u32) -> i32 {
+        let bits = (0..length).fold(0, |bits, _| bits << 1 | self.bit()) as i32;
+        if length > 0 && bits < 1 << (length - 1) {
+            bits - (1 << length) + 1
+        } else {
+            bits
+        }
+    }
+
+    /// Skips to the restart marker that should come next.
+    fn restart(&mut self) -> Result<(), String> {
+        self.count = 0;
+        match self.bytes.get(self.pos..self.pos + 2) {
+            Some([0xff, 0xd0..=0xd7]) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => Err("a restart marker is missing".to_string()),
+        }
+    }
+}
+
+/// One channel of a frame: its sampling factors, quantization table and
+/// samples, in blocks of 8×8 covering whole MCUs.
+struct FrameComponent {
+    id: u8,
+    h: usize,
+    v: usize,
+    table: usize,
+    /// The samples' width, a whole number of blocks.
+    stride: usize,
+    samples: Vec<u8>,
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    components: Vec<FrameComponent>,
+    /// The largest sampling factors, which make an MCU `8 h` × `8 v`.
+    h: usize,
+    v: usize,
+}
+
+impl Frame {
+    fn parse(segment: &mut Reader) -> Result<Frame, String> {
+        if segment.u8()? != 8 {
+            return Err("only JPEGs of 8 bits a sample can be read".to_string());
+        }
+        let height = segment.u16()? as usize;
+        let width = segment.u16()? as usize;
+        let count = segment.u8()? as usize;
+        if width == 0 || height == 0 {
+            return Err("the JPEG has no pixels".to_string());
+        }
+        if count != 1 && count != 3 {
+            return Err("only grayscale and YCbCr JPEGs can be read".to_string());
+        }
+        let mut components = Vec::with_capacity(count);
+        for _ in 0..count {
+            let (id, sampling, table) = (segment.u8()?, segment.u8()?, segment.u8()?);
+            let (h, v) = ((sampling >> 4) as usize, (sampling & 15) as usize);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) || table > 3 {
+                return Err("a component's sampling factors are out of range".to_string());
+            }
+            components.push(FrameComponent {
+                id,
+                h,
+                v,
+                table: table as usize,
+                stride: 0,
+                samples: Vec::new(),
+            });
+        }
+        let h = components.iter().map(|c| c.h).max().unwrap();
+        let v = components.iter().map(|c| c.v).max().unwrap();
+        let (mcus_wide, mcus_high) = (width.div_ceil(8 * h), height.div_ceil(8 * v));
+        for component in &mut components {
+            component.stride = mcus_wide * component.h * 8;
+            component.samples = vec![0; component.stride * mcus_high * component.v * 8];
+        }
+        Ok(Frame {
+            width,
+            height,
+            components,
+            h,
+            v,
+        })
+    }
+
+    /// Decodes the entropy coded data of a scan of the `scan` components,
+    /// each with its DC and AC tables, starting at `pos` in `bytes`.
+    /// Returns where the data ends.
+    fn decode_scan(
+        &mut self,
+        bytes: &[u8],
+        pos: usize,
+        scan: &[(usize, usize, usize)],
+        huffman: &[Option<HuffmanDecoder>; 8],
+        tables: &[[u16; 64]; 4],
+        restart: usize,
+    ) -> Result<usize, String> {
+        let mut bits = BitReader {
+            bytes,
+            pos,
+            buffer: 0,
+            count: 0,
+        };
+        let missing = || "a scan uses a Huffman table that isn't defined".to_string();
+        let coders = scan
+            .iter()
+            .map(|&(_, dc, ac)| {
+                Ok((
+                    huffman[dc].as_ref().ok_or_else(missing)?,
+                    huffman[4 + ac].as_ref().ok_or_else(missing)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        // A scan of one component goes through its blocks one at a time,
+        // leaving out those only there to fill the last MCUs.
+        let (mcus_wide, mcus_high, single) = if let [(component, _, _)] = scan {
+            let component = &self.components[*component];
+            let wide = (self.width * component.h).div_ceil(8 * self.h);
+            let high = (self.height * component.v).div_ceil(8 * self.v);
+            (wide, high, true)
+        } else {
+            (
+                self.width.div_ceil(8 * self.h),
+                self.height.div_ceil(8 * self.v),
+                false,
+            )
+        };
+        let mut predictors = vec![0; scan.len()];
+        for mcu in 0..mcus_wide * mcus_high {
+            if restart > 0 && mcu > 0 && mcu % restart == 0 {
+                bits.restart()?;
+                predictors.fill(0);
+            }
+            let (mcu_x, mcu_y) = (mcu % mcus_wide, mcu / mcus_wide);
+            for (s, &(index, _, _)) in scan.iter().enumerate() {
+                let component = &mut self.components[index];
+                let (h, v) = if single {
+                    (1, 1)
+                } else {
+                    (component.h, component.v)
+                };
+                for block in 0..h * v {
+                    let (dc, ac) = coders[s];
+                    let mut coefficients = [0i32; 64];
+                    let size = dc.decode(&mut bits)? as u32;
+                    if size > 11 {
+                        return Err(BAD_CODE.to_string());
+                    }
+                    predictors[s] += bits.value(size);
+                    coefficients[0] = predictors[s];
+                    let mut k = 1;
+                    while k < 64 {
+                        let symbol = ac.decode(&mut bits)?;
+                        let (zeros, size) = ((symbol >> 4) as usize, (symbol & 15) as u32);
+                        if size == 0 {
+                            if zeros != 15 {
+                                break;
+                            }
+                            k += 16;
+                            continue;
+                        }
+                        k += zeros;
+                        if k > 63 {
+                            return Err(BAD_CODE.to_string());
+                        }
+                        coefficients[ZIGZAG[k]] = bits.value(size);
+                        k += 1;
+                    }
+                    let table = &tables[component.table];
+                    let block_x = mcu_x * h + block % h;
+                    let block_y = mcu_y * v + block / h;
+                    let samples = inverse_dct(&std::array::from_fn(|i| {
+                        (coefficients[i] * table[i] as i32) as f32
+                    }));
+                    for (i, sample) in samples.iter().enumerate() {
+                        let offset = (block_y * 8 + i / 8) * component.stride + block_x * 8 + i % 8;
+                        component.samples[offset] =
+                            (sample + 128.0).round().clamp(0.0, 255.0) as u8;
+                    }
+                }
+            }
+        }
+        // Skip to the marker after the data.
+        let marker = |pair: &[u8]| pair[0] == 0xff && !matches!(pair[1], 0 | 0xd0..=0xd7);
+        Ok(bytes[bits.pos..]
+            .windows(2)
+            .position(marker)
+            .map_or(bytes.len(), |i| bits.pos + i))
+    }
+
+    /// The decoded samples as RGB pixels, upsampling components stored at
+    /// less than full resolution.
+    fn to_rgb(&self) -> Vec<u8> {
+        let sample = |component: &FrameComponent, x: usize, y: usize| {
+            let (x, y) = (x * component.h / self.h, y * component.v / self.v);
+            component.samples[y * component.stride + x] as f32
+        };
+        let mut pixels = Vec::with_capacity(3 * self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                match &self.components[..] {
+                    [gray] => pixels.extend([sample(gray, x, y) as u8; 3]),
+                    [luma, cb, cr] => {
+                        let (luma, cb, cr) = (
+                            sample(luma, x, y),
+                            sample(cb, x, y) - 128.0,
+                            sample(cr, x, y) - 128.0,
+                        );
+                        pixels.extend(
+                            [
+                                luma + 1.402 * cr,
+                                luma - 0.344_136 * cb - 0.714_136 * cr,
+                                luma + 1.772 * cb,
+                            ]
+                            .map(|v| v.round().clamp(0.0, 255.0) as u8),
+                        );
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+        pixels
+    }
+}
+
+/// The inverse of `forward_dct`.
+fn inverse_dct(coefficients: &[f32; 64]) -> [f32; 64] {
+    let cosines: [[f32; 8]; 8] = std::array::from_fn(|u| {
+        std::array::from_fn(|x| ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / 16.0).cos())
+    });
+    let weight = |u: usize| {
+        if u == 0 {
+            std::f32::consts::FRAC_1_SQRT_2
+        } else {
+            1.0
+        }
+    };
+    let mut columns = [0.0f32; 64];
+    for v in 0..8 {
+        for x in 0..8 {
+            columns[v * 8 + x] = (0..8)
+                .map(|u| weight(u) * coefficients[v * 8 + u] * cosines[u][x])
+                .sum::<f32>();
+        }
+    }
+    std::array::from_fn(|i| {
+        let (y, x) = (i / 8, i % 8);
+        let sum = (0..8)
+            .map(|v| weight(v) * columns[v * 8 + x] * cosines[v][y])
+            .sum::<f32>();
+        0.25 * sum
+    })
+}
+
+#[test]
+fn test_magnitude() {
+    assert_eq!(magnitude(0), (0, 0));
+    assert_eq!(magnitude(1), (1, 1));
+    assert_eq!(magnitude(-1), (1, 0));
+    assert_eq!(magnitude(5), (3, 5));
+    assert_eq!(magnitude(-5), (3, 2));
+    assert_eq!(magnitude(-1023), (10, 0));
+}
+
+#[test]
+fn test_huffman_codes() {
+    let dc = Huffman::new(&LUMA_DC);
+    assert_eq!(dc.codes[0], (0b00, 2));
+    assert_eq!(dc.codes[1], (0b010, 3));
+    assert_eq!(dc.codes[5], (0b110, 3));
+    assert_eq!(dc.codes[11], (0b1_1111_1110, 9));
+    let ac = Huffman::new(&LUMA_AC);
+    assert_eq!(ac.codes[0x00], (0b1010, 4));
+    assert_eq!(ac.codes[0xf0], (0b111_1111_1001, 11));
+}
+
+#[test]
+fn test_dct_of_flat_block() {
+    let coefficients = forward_dct(&[10.0; 64]);
+    assert!((coefficients[0] - 80.0).abs() < 1e-3);
+    assert!(coefficients[1..].iter().all(|c| c.abs() < 1e-3));
+}
+
+#[test]
+fn test_encode() {
+    let bounds = (37, 21);
+    let pixels = (0..bounds.0 * bounds.1)
+        .flat_map(|i| [(i % 37 * 7) as u8, (i / 37 * 12) as u8, 128])
+        .collect::<Vec<_>>();
+    let encoded = |quality| {
+        let mut bytes = Vec::new();
+        encode(&mut bytes, &pixels, bounds, quality).unwrap();
+        bytes
+    };
+    let best = encoded(100);
+    let small = encoded(20);
+    assert_eq!(&best[..4], &[0xff, 0xd8, 0xff, 0xe0]);
+    assert_eq!(&best[best.len() - 2..], &[0xff, 0xd9]);
+    assert!(small.len() < best.len());
+    // The frame header carries the size.
+    let sof = best.windows(2).position(|w| w == [0xff, 0xc0]).unwrap();
+    assert_eq!(&best[sof + 5..sof + 9], &[0, 21, 0, 37]);
+    // Entropy coded data never contains a marker.
+    let sos = best.windows(2).position(|w| w == [0xff, 0xda]).unwrap();
+    let data = &best[sos + 14..best.len() - 2];
+    assert!(data.windows(2).all(|w| w[0] != 0xff || w[1] == 0));
+}
+
+#[test]
+fn test_decode() {
+    let bounds = (37, 21);
+    let pixels = (0..bounds.0 * bounds.1)
+        .flat_map(|i| [(i % 37 * 7) as u8, (i / 37 * 12) as u8, 128])
+        .collect::<Vec<_>>();
+    let mut bytes = Vec::new();
+    encode(&mut bytes, &pixels, bounds, 100).unwrap();
+    let (decoded, size) = decode(&bytes).unwrap();
+    assert_eq!(size, bounds);
+    let error = pixels
+        .iter()
+        .zip(&decoded)
+        .map(|(&a, &b)| (a as i32 - b as i32).abs())
+        .max()
+        .unwrap();
+    assert!(error <= 4, "off by {}", error);
+    assert!(decode(&bytes[..bytes.len() / 2]).is_err());
+    assert!(decode(b"GIF89a").is_err());
+    // A progressive frame header.
+    let sof = bytes.windows(2).position(|w| w == [0xff, 0xc0]).unwrap();
+    bytes[sof + 1] = 0xc2;
+    assert_eq!(
+        decode(&bytes).unwrap_err(),
+        "only baseline JPEGs can be read"
+    );
+}
@@ -0,0 +1,112 @@
+//! Kalles Fraktaler's `.kfr` parameter files, which many deep zoom
+//! locations are published as. They are lines of `Key: value`, of which
+//! the center `Re` and `Im`, the `Zoom` and the `Iterations` are read; the
+//! rest are KF's own coloring settings, and are skipped. KF measures zoom
+//! as `View` does, with 1 showing 4 units of the imaginary axis.
+//!
+//! ```text
+//! Re: -1.7685736562667927302
+//! Im: 0.00188946646896
+//! Zoom: 4.6E12
+//! Iterations: 12000
+//! ```
+
+use crate::{animation::View, Fixed};
+use num::Complex;
+
+/// The view and iterations of a `.kfr` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub view: View,
+    pub max_iter: Option<u32>,
+}
+
+/// Reads a `.kfr` file's text. Locations of other fractals than the
+/// Mandelbrot set, or of rotated or stretched views, are refused rather
+/// than rendered as something else.
+pub fn parse(text: &str) -> Result<Location, String> {
+    let (mut re, mut im, mut zoom, mut max_iter) = (None, None, None, None);
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "Re" => re = Some(Fixed::parse(value).ok_or_else(|| error("invalid Re"))?),
+            "Im" => im = Some(Fixed::parse(value).ok_or_else(|| error("invalid Im"))?),
+            "Zoom" => {
+                let value = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|zoom| *zoom > 0.0)
+                    .ok_or_else(|| error("invalid Zoom"))?;
+                if value.is_infinite() {
+                    return Err(error("the zoom is too deep to render"));
+                }
+                zoom = Some(value);
+            }
+            "Iterations" => {
+                let value = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&iterations| iterations > 0)
+                    .ok_or_else(|| error("invalid Iterations"))?;
+                max_iter = Some(value);
+            }
+            "FractalType" if value != "0" => {
+                return Err(error("only the Mandelbrot set, type 0, can be imported"))
+            }
+            "Power" if value != "2" => {
+                return Err(error("only the Mandelbrot set of power 2 can be imported"))
+            }
+            "RotateAngle" | "StretchAmount" if value.parse::<f64>() != Ok(0.0) => {
+                return Err(error("rotated or stretched views can't be imported"))
+            }
+            _ => {}
+        }
+    }
+    let missing = |key: &str| format!("missing {}", key);
+    Ok(Location {
+        view: View {
+            center: Complex {
+                re: re.ok_or_else(|| missing("Re"))?,
+                im: im.ok_or_else(|| missing("Im"))?,
+            },
+            zoom: zoom.ok_or_else(|| missing("Zoom"))?,
+            flipped: false,
+        },
+        max_iter,
+    })
+}
+
+#[test]
+fn test_parse_kfr() {
+    let text = "Re: -1.76857365626679273021\r\n\
+                Im: 0.00188946646896\r\n\
+                Zoom: 4.6E12\r\n\
+                Iterations: 12000\r\n\
+                IterDiv: 0.010000\r\n\
+                Colors: 255,255,255,128,0,64,\r\n\
+                RotateAngle: 0\r\n\
+                FractalType: 0\r\n\
+                Power: 2\r\n";
+    let location = parse(text).unwrap();
+    assert_eq!(
+        location.view.center.re,
+        Fixed::parse("-1.76857365626679273021").unwrap()
+    );
+    assert_eq!(location.view.center.im.to_f64(), 0.00188946646896);
+    assert_eq!(location.view.zoom, 4.6e12);
+    assert_eq!(location.max_iter, Some(12000));
+    let without_iterations = parse("Re: 0\nIm: 1\nZoom: 1").unwrap();
+    assert_eq!(without_iterations.max_iter, None);
+    assert_eq!(parse("Re: 0\nZoom: 1").unwrap_err(), "missing Im");
+    assert_eq!(
+        parse("Re: 0\nIm: 1\nZoom: -1").unwrap_err(),
+        "line 3: invalid Zoom"
+    );
+    assert!(parse("Re: 0\nIm: 1\nZoom: 1E400").is_err());
+    assert!(parse("Re: 0\nIm: 1\nZoom: 1\nFractalType: 1").is_err());
+    assert!(parse("Re: 0\nIm: 1\nZoom: 1\nRotateAngle: 45").is_err());
+}
@@ -0,0 +1,1990 @@
+use crossbeam::deque::{Injector, Steal};
+use num::Complex;
+use png::EncodingError;
+use std::{
+    fmt,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+pub mod adjust;
+pub mod animation;
+pub mod ansi;
+pub mod antialias;
+pub mod bailout;
+pub mod border_trace;
+pub mod buddhabrot;
+pub mod builder;
+pub mod checkpoint;
+pub mod coloring;
+pub mod contour;
+pub mod distance;
+pub mod distributed;
+pub mod dither;
+pub mod double_double;
+pub mod dump;
+pub mod error;
+pub mod exr;
+pub mod fixed;
+pub mod formula;
+pub mod fractal;
+pub mod gif;
+#[cfg(test)]
+mod golden;
+pub mod gradient;
+pub mod hdr;
+pub mod interior;
+pub mod jpeg;
+pub mod kfr;
+pub mod log;
+pub mod lyapunov;
+pub mod mesh;
+pub mod metadata;
+pub mod netpbm;
+pub mod newton;
+pub mod observer;
+pub mod orbit;
+pub mod palette;
+pub mod par;
+pub mod perturbation;
+pub mod progress;
+pub mod progressive;
+pub mod pyramid;
+pub mod shading;
+pub mod simd;
+pub mod sixel;
+pub mod stats;
+pub mod stripe;
+pub mod task;
+pub mod tiff;
+pub mod tiled;
+pub mod webp;
+
+pub use adjust::Adjustments;
+pub use bailout::Bailout;
+pub use builder::RenderOptionsBuilder;
+pub use coloring::{Coloring, Colorizer, Transfer};
+use coloring::{Grayscale, Scale};
+pub use dither::Dither;
+pub use double_double::DoubleDouble;
+pub use error::MandelbrotError;
+pub use fixed::Fixed;
+pub use fractal::{Family, Fractal};
+pub use interior::Interior;
+use log::Level;
+pub use newton::Polynomial;
+pub use observer::RenderObserver;
+pub use palette::Palette;
+pub use progress::Progress;
+pub use shading::Light;
+
+/// Everything needed to describe a single render: the image size in pixels,
+/// the rectangle of the complex plane it covers, how many iterations to try
+/// per point, how to color the result and how many threads to use. With
+/// `smooth` set, colors are interpolated from the fractional escape count
+/// instead of the integer one, which avoids visible bands; `coloring` says
+/// how escape counts are spread over the palette, `transfer` and
+/// `palette_cycle` how escape time coloring maps them onto it, and
+/// `interior` how the points inside the set are colored. With `shading`
+/// set, the image is lit from that light as if the escape counts were
+/// heights. With `antialias` above 1, each pixel averages `antialias`²
+/// jittered samples; with `adaptive` set as well, only the pixels whose
+/// neighborhood colors have a standard deviation above it are
+/// supersampled. With `dither` set, colors are dithered as they are rounded
+/// to bytes. Last, they are tuned by `adjustments`.
+///
+/// For deep zooms the corners may also be given exactly in `exact_corners`,
+/// which then take precedence over the `f64` ones; `precision` decides how
+/// the coordinates are computed with. `algorithm` picks which pixels are
+/// iterated at all, and `shortcuts` how points are let off early.
+/// `bailout` says when an orbit has escaped; any but the usual `|z| > 2` is
+/// rendered in `f64`. With `julia` set, the Julia set of that constant is
+/// rendered instead, always in `f64`: each pixel is where an orbit starts
+/// rather than its `c`. `fractal` picks the formula iterated, which may be
+/// a `Family` of one's own; any but the Mandelbrot set's is likewise
+/// rendered in `f64`. With `newton` set, Newton's method for that
+/// polynomial is run from each pixel instead, also in `f64`; see `newton`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    pub bounds: (u32, u32),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    pub exact_corners: Option<(Complex<Fixed>, Complex<Fixed>)>,
+    pub precision: Precision,
+    pub algorithm: Algorithm,
+    pub shortcuts: Shortcuts,
+    pub bailout: Bailout,
+    pub julia: Option<Complex<f64>>,
+    pub fractal: Fractal,
+    pub newton: Option<Polynomial>,
+    pub max_iter: u32,
+    pub palette: Palette,
+    pub transfer: Transfer,
+    pub palette_cycle: Option<f64>,
+    pub smooth: bool,
+    pub coloring: Coloring,
+    pub interior: Interior,
+    pub shading: Option<Light>,
+    pub antialias: u32,
+    pub dither: Option<Dither>,
+    pub adjustments: Adjustments,
+    pub adaptive: Option<f64>,
+    pub threads: u32,
+}
+
+impl Default for RenderOptions {
+    /// The whole set at 800x600, in grayscale.
+    fn default() -> RenderOptions {
+        RenderOptions {
+            bounds: (800, 600),
+            upper_left: Complex { re: -2.2, im: 1.2 },
+            lower_right: Complex { re: 1.0, im: -1.2 },
+            exact_corners: None,
+            precision: Precision::Auto,
+            algorithm: Algorithm::Scan,
+            shortcuts: Shortcuts::default(),
+            bailout: Bailout::default(),
+            julia: None,
+            fractal: Fractal::Mandelbrot,
+            newton: None,
+            max_iter: 255,
+            palette: Palette::named("grayscale").unwrap(),
+            transfer: Transfer::Linear,
+            palette_cycle: None,
+            smooth: true,
+            coloring: Coloring::EscapeTime,
+            interior: Interior::Flat,
+            shading: None,
+            antialias: 1,
+            dither: None,
+            adjustments: Adjustments::default(),
+            adaptive: None,
+            threads: available_threads(),
+        }
+    }
+}
+
+/// The arithmetic used for coordinates and iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    /// `Double` unless neighboring pixels are too close together for `f64`
+    /// to tell apart, in which case as many bits as they need.
+    Auto,
+    Double,
+    /// `f32`, for quick previews of shallow views. Resolves to `Double` where
+    /// neighboring pixels are too close together for `f32` to tell apart.
+    Single,
+    /// Pairs of `f64`s, for zooms down to pixels around `1e-28` apart, a
+    /// little too deep for `f64`; see `double_double`.
+    DoubleDouble,
+    /// Fixed point with the given number of fractional bits.
+    Arbitrary(u32),
+    /// `f64` offsets from reference orbits computed in fixed point with the
+    /// given number of fractional bits.
+    Perturbation(u32),
+}
+
+impl fmt::Display for Precision {
+    /// The `--precision` value that picks it, and the bits taken.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Precision::Auto => write!(f, "auto"),
+            Precision::Double => write!(f, "f64"),
+            Precision::Single => write!(f, "f32"),
+            Precision::DoubleDouble => write!(f, "dd"),
+            Precision::Arbitrary(bits) => write!(f, "{} bits", bits),
+            Precision::Perturbation(bits) => write!(f, "perturb ({} bits)", bits),
+        }
+    }
+}
+
+/// How the pixels of a render are visited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Every pixel is iterated.
+    Scan,
+    /// Rectangles with a uniform border are filled in without iterating
+    /// their insides; see `border_trace`.
+    BorderTrace,
+}
+
+impl Algorithm {
+    /// The `--algorithm` value that picks it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Scan => "scan",
+            Algorithm::BorderTrace => "border-trace",
+        }
+    }
+}
+
+/// Tests that let `escape_time_with` stop early on points known to be in
+/// the set. They never change a result, only the time spent finding it, and
+/// are all on by default; turn them off to compare against plain iteration.
+/// Only the `f64` kernels take them, since deep zooms rarely land on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shortcuts {
+    /// Skip points inside the main cardioid or the period-2 bulb.
+    pub bulbs: bool,
+    /// Stop once the orbit comes back to where it was, which only happens
+    /// to points in the set. Orbits that merely come within
+    /// `PERIOD_TOLERANCE` count, so turn this off to dump exact iteration
+    /// counts.
+    pub periodicity: bool,
+}
+
+impl Shortcuts {
+    /// Plain iteration for every point.
+    pub const NONE: Shortcuts = Shortcuts {
+        bulbs: false,
+        periodicity: false,
+    };
+}
+
+impl Default for Shortcuts {
+    fn default() -> Shortcuts {
+        Shortcuts {
+            bulbs: true,
+            periodicity: true,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// The corners to full precision, sharing one number of fractional bits.
+    pub fn exact_corners(&self) -> (Complex<Fixed>, Complex<Fixed>) {
+        match &self.exact_corners {
+            Some((upper_left, lower_right)) => {
+                let bits = [
+                    &upper_left.re,
+                    &upper_left.im,
+                    &lower_right.re,
+                    &lower_right.im,
+                ]
+                .iter()
+                .map(|x| x.bits())
+                .max()
+                .unwrap();
+                let rescale = |z: &Complex<Fixed>| Complex {
+                    re: z.re.with_bits(bits),
+                    im: z.im.with_bits(bits),
+                };
+                (rescale(upper_left), rescale(lower_right))
+            }
+            None => {
+                let bits = 1074 + 64;
+                let exact = |z: Complex<f64>| Complex {
+                    re: Fixed::from_f64(z.re, bits),
+                    im: Fixed::from_f64(z.im, bits),
+                };
+                (exact(self.upper_left), exact(self.lower_right))
+            }
+        }
+    }
+
+    /// The distance between neighboring pixels, along whichever axis they
+    /// are closer together.
+    pub fn pixel_size(&self) -> f64 {
+        let (width, height) = match &self.exact_corners {
+            Some(_) => {
+                let (upper_left, lower_right) = self.exact_corners();
+                (
+                    (&lower_right.re - &upper_left.re).to_f64(),
+                    (&upper_left.im - &lower_right.im).to_f64(),
+                )
+            }
+            None => (
+                self.lower_right.re - self.upper_left.re,
+                self.upper_left.im - self.lower_right.im,
+            ),
+        };
+        (width / self.bounds.0.max(1) as f64)
+            .abs()
+            .min((height / self.bounds.1.max(1) as f64).abs())
+    }
+
+    /// Resolves `Precision::Auto` from the distance between neighboring
+    /// pixels: zooms too deep for `f64` are rendered in double-double, and
+    /// those too deep for that by perturbation. `Single` and `DoubleDouble`
+    /// fall back to `Double` and perturbation on zooms too deep for them.
+    /// `Single` is never picked by itself, as it still shifts the odd pixel
+    /// on the boundary of views it resolves. Fractals
+    /// other than the Mandelbrot set, Newton fractals, other bailouts than
+    /// the usual one and colorings that follow orbits are always `Double`,
+    /// and so are Julia sets unless they are `Single`.
+    pub fn resolved_precision(&self) -> Precision {
+        if self.fractal != Fractal::Mandelbrot
+            || self.newton.is_some()
+            || self.bailout != Bailout::default()
+            || self.coloring.follows_orbits()
+            || (self.julia.is_some() && self.precision != Precision::Single)
+        {
+            return Precision::Double;
+        }
+        if self.precision == Precision::Single {
+            let magnitude = self.upper_left.re.abs().max(self.upper_left.im.abs());
+            return if fixed::f32_suffices(self.pixel_size(), magnitude) {
+                Precision::Single
+            } else {
+                Precision::Double
+            };
+        }
+        if !matches!(self.precision, Precision::Auto | Precision::DoubleDouble) {
+            return self.precision;
+        }
+        let upper_left = match &self.exact_corners {
+            Some((upper_left, _)) => Complex {
+                re: upper_left.re.to_f64(),
+                im: upper_left.im.to_f64(),
+            },
+            None => self.upper_left,
+        };
+        let pixel_size = self.pixel_size();
+        let magnitude = upper_left.re.abs().max(upper_left.im.abs());
+        if self.precision == Precision::Auto && fixed::f64_suffices(pixel_size, magnitude) {
+            Precision::Double
+        } else if double_double::suffices(pixel_size, magnitude) {
+            Precision::DoubleDouble
+        } else {
+            Precision::Perturbation(fixed::bits_for_pixel_size(pixel_size))
+        }
+    }
+}
+
+#[test]
+fn test_resolved_precision() {
+    let options = RenderOptions::default();
+    assert_eq!(options.resolved_precision(), Precision::Double);
+    let deep = RenderOptions {
+        exact_corners: Some((
+            Complex {
+                re: Fixed::parse("-0.743643887037158704752191506114774").unwrap(),
+                im: Fixed::parse("0.131825904205311970493132056385139").unwrap(),
+            },
+            Complex {
+                re: Fixed::parse("-0.743643887037158704752191506114764").unwrap(),
+                im: Fixed::parse("0.131825904205311970493132056385132").unwrap(),
+            },
+        )),
+        ..RenderOptions::default()
+    };
+    match deep.resolved_precision() {
+        Precision::Perturbation(bits) => assert!(bits > 120, "{}", bits),
+        other => panic!("expected perturbation, got {:?}", other),
+    }
+    let forced = RenderOptions {
+        precision: Precision::Arbitrary(100),
+        ..RenderOptions::default()
+    };
+    assert_eq!(forced.resolved_precision(), Precision::Arbitrary(100));
+    let double_double = RenderOptions {
+        precision: Precision::DoubleDouble,
+        ..deep.clone()
+    };
+    match double_double.resolved_precision() {
+        Precision::Perturbation(bits) => assert!(bits > 120, "{}", bits),
+        other => panic!("expected perturbation, got {:?}", other),
+    }
+    let intermediate = RenderOptions {
+        exact_corners: Some((
+            Complex {
+                re: Fixed::parse("-0.74364388703715870475").unwrap(),
+                im: Fixed::parse("0.13182590420531197049").unwrap(),
+            },
+            Complex {
+                re: Fixed::parse("-0.74364388703715870465").unwrap(),
+                im: Fixed::parse("0.13182590420531197042").unwrap(),
+            },
+        )),
+        ..double_double
+    };
+    assert_eq!(intermediate.resolved_precision(), Precision::DoubleDouble);
+    let auto = RenderOptions {
+        precision: Precision::Auto,
+        ..intermediate
+    };
+    assert_eq!(auto.resolved_precision(), Precision::DoubleDouble);
+    assert_eq!(
+        RenderOptions {
+            precision: Precision::DoubleDouble,
+            ..RenderOptions::default()
+        }
+        .resolved_precision(),
+        Precision::DoubleDouble
+    );
+    let single = RenderOptions {
+        precision: Precision::Single,
+        ..RenderOptions::default()
+    };
+    assert_eq!(single.resolved_precision(), Precision::Single);
+    let shallow = RenderOptions {
+        upper_left: Complex::new(-0.75, 0.1),
+        lower_right: Complex::new(-0.75 + 0.1, 0.1 - 0.1),
+        ..single.clone()
+    };
+    assert_eq!(shallow.resolved_precision(), Precision::Single);
+    let narrow = RenderOptions {
+        upper_left: Complex::new(-0.75, 0.1),
+        lower_right: Complex::new(-0.75 + 1e-3, 0.1 - 1e-3),
+        ..single
+    };
+    assert_eq!(narrow.resolved_precision(), Precision::Double);
+}
+
+/// The color of the pixels a cancelled render didn't get to, a magenta no
+/// built-in palette has; see `Progress::cancel`.
+pub const UNRENDERED: [u8; 3] = [255, 0, 255];
+
+/// Renders the Mandelbrot set into an RGB pixel buffer as described by its
+/// `RenderOptions`.
+pub struct Renderer {
+    options: RenderOptions,
+    progress: Arc<Progress>,
+    observer: Option<Arc<dyn RenderObserver>>,
+    colorizer: Option<Arc<dyn Colorizer>>,
+}
+
+impl Renderer {
+    pub fn new(options: RenderOptions) -> Renderer {
+        Renderer {
+            options,
+            progress: Arc::default(),
+            observer: None,
+            colorizer: None,
+        }
+    }
+
+    /// The renderer, telling `observer` of each piece of its renders as it
+    /// is done.
+    pub fn with_observer(self, observer: Arc<dyn RenderObserver>) -> Renderer {
+        Renderer {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    /// The renderer, coloring its images with `colorizer` rather than as
+    /// `coloring` asks.
+    pub fn with_colorizer(self, colorizer: Arc<dyn Colorizer>) -> Renderer {
+        Renderer {
+            colorizer: Some(colorizer),
+            ..self
+        }
+    }
+
+    /// What colors the image whose points are `escapes`: the renderer's own
+    /// colorizer if it was given one, or the `coloring` asked for. Histogram
+    /// coloring counts `escapes`, which should cover the whole image; the
+    /// others don't look at them.
+    pub fn colorizer(&self, escapes: &[Option<Escape>]) -> Arc<dyn Colorizer> {
+        match (&self.colorizer, self.options.coloring) {
+            (Some(colorizer), _) => colorizer.clone(),
+            (None, Coloring::Grayscale) => Arc::new(Grayscale::new(&self.options)),
+            (None, _) => Arc::new(Scale::new(&self.options, escapes)),
+        }
+    }
+
+    pub fn options(&self) -> &RenderOptions {
+        &self.options
+    }
+
+    /// A handle for watching how far along the current render is, and for
+    /// cancelling it from another thread; see `Progress::cancel`.
+    pub fn progress(&self) -> Arc<Progress> {
+        self.progress.clone()
+    }
+
+    /// Renders the whole image, three row-major bytes (red, green, blue) per
+    /// pixel. The pixels a cancelled render didn't get to are `UNRENDERED`.
+    pub fn render(&self) -> Vec<u8> {
+        if self.options.antialias > 1 {
+            return match self.options.adaptive {
+                Some(threshold) => antialias::render_adaptive(self, threshold),
+                None => antialias::render(self),
+            };
+        }
+        let escapes = self.escapes();
+        let colors = self.colorize(&escapes);
+        self.finished(self.rows_in(&escapes));
+        colors
+    }
+
+    /// Renders the whole image like `render` on a thread of its own, as a
+    /// future, so that async code can await it without blocking its
+    /// executor; see `task`. Dropping it before it is ready cancels the
+    /// render, which can also be watched and cancelled through `progress`,
+    /// taken beforehand.
+    pub fn render_async(self) -> task::Task<Vec<u8>> {
+        let progress = self.progress();
+        task::spawn(move || self.render()).cancelling(progress)
+    }
+
+    /// The rows of the image, colored as `render` colors them, from the
+    /// top, each band of them rendered as it is wanted; see `tiled::rows`.
+    /// A cancelled render's end after the rows under way.
+    pub fn rows(&self) -> tiled::Rows<'_> {
+        tiled::rows(self, 0)
+    }
+
+    /// Renders the whole image with four bytes per pixel, the colors of
+    /// `render` followed by an opaque alpha, as a browser's `ImageData`
+    /// takes them.
+    pub fn render_rgba(&self) -> Vec<u8> {
+        self.render()
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+            .collect()
+    }
+
+    /// Colors escape times from `render_escapes` with the render's palette.
+    /// Those of a cancelled render, which stop short, are followed by
+    /// `UNRENDERED` to the end of the image.
+    pub fn colorize(&self, escapes: &[Option<Escape>]) -> Vec<u8> {
+        let mut colors = self.color_rows(escapes, &*self.colorizer(escapes), 0);
+        let width = self.options.bounds.0.max(1) as usize;
+        self.color_interior(&mut colors, escapes, |i| {
+            ((i % width) as f64, (i / width) as f64)
+        });
+        self.shade(&mut colors, escapes);
+        self.options.adjustments.apply(&mut colors);
+        let (width, height) = self.options.bounds;
+        colors.resize(3 * width as usize * height as usize, 0);
+        for pixel in colors[3 * escapes.len()..].chunks_exact_mut(3) {
+            pixel.copy_from_slice(&UNRENDERED);
+        }
+        colors
+    }
+
+    /// Colors the escape times of whole rows of the image from row `top`
+    /// with `colorizer`, dithered if the render asks.
+    pub fn color_rows(
+        &self,
+        escapes: &[Option<Escape>],
+        colorizer: &dyn Colorizer,
+        top: u32,
+    ) -> Vec<u8> {
+        match self.options.dither {
+            Some(dither) => {
+                let width = self.options.bounds.0.max(1) as usize;
+                let pixel = |i: usize| ((i % width) as u32, top + (i / width) as u32);
+                colorize_dithered(escapes, colorizer, dither, pixel)
+            }
+            None => colorize(escapes, colorizer),
+        }
+    }
+
+    /// Lights `pixels` by the slopes of `escapes` from `render_escapes`, if
+    /// the render asks for shading. Only the rows both cover are lit, as
+    /// if the image ended there, so that a cancelled render's can be.
+    pub fn shade(&self, pixels: &mut [u8], escapes: &[Option<Escape>]) {
+        if let Some(light) = self.options.shading {
+            let width = self.options.bounds.0;
+            let rows = escapes.len().min(pixels.len() / 3) / width.max(1) as usize;
+            let count = width as usize * rows;
+            let degree = self.options.fractal.degree();
+            shading::shade(
+                &mut pixels[..3 * count],
+                &escapes[..count],
+                (width, rows as u32),
+                degree,
+                light,
+            );
+        }
+    }
+
+    /// Recolors the points of `escapes` inside the set as the render's
+    /// `interior` asks, if it isn't flat. `position(i)` is where point `i`
+    /// is, in pixels as for `render_points`.
+    pub fn color_interior(
+        &self,
+        colors: &mut [u8],
+        escapes: &[Option<Escape>],
+        position: impl Fn(usize) -> (f64, f64) + Sync,
+    ) {
+        self.color_interior_dithered(colors, escapes, position, self.options.dither);
+    }
+
+    /// Recolors the points inside the set like `color_interior`, dithered
+    /// by `dither` rather than as the render asks, for samples that are
+    /// dithered once they are averaged.
+    pub(crate) fn color_interior_dithered(
+        &self,
+        colors: &mut [u8],
+        escapes: &[Option<Escape>],
+        position: impl Fn(usize) -> (f64, f64) + Sync,
+        dither: Option<Dither>,
+    ) {
+        let RenderOptions {
+            bounds,
+            upper_left,
+            lower_right,
+            ref fractal,
+            julia,
+            interior,
+            max_iter,
+            threads,
+            ..
+        } = self.options;
+        if interior == Interior::Flat {
+            return;
+        }
+        let inside = (0..escapes.len())
+            .filter(|&i| escapes[i].is_none())
+            .collect::<Vec<_>>();
+        let mut positions = vec![None; inside.len()];
+        parallel_chunks(&mut positions, POINT_CHUNK, threads, |start, chunk| {
+            for (&i, t) in inside[start..].iter().zip(chunk) {
+                let point = position_to_point(bounds, position(i), upper_left, lower_right);
+                *t = interior.position(point, fractal, julia, max_iter);
+            }
+        });
+        for (&i, t) in inside.iter().zip(positions) {
+            if let Some(t) = t {
+                let color = match dither {
+                    Some(dither) => {
+                        let (x, y) = position(i);
+                        dither.quantize(self.options.palette.at_exact(t), (x as u32, y as u32))
+                    }
+                    None => self.options.palette.at(t),
+                };
+                colors[3 * i..3 * i + 3].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// Renders the escape values as EXR float channels; see
+    /// `exr::encode_escapes`. The pixels a cancelled render didn't get to
+    /// are left inside the set.
+    pub fn render_exr<W: Write>(&self, w: W) -> std::io::Result<()> {
+        exr::encode_escapes(
+            w,
+            &self.render_whole_escapes(),
+            self.options.bounds,
+            self.options.smooth,
+        )
+    }
+
+    /// Renders the escape values as 16-bit grayscale samples; see `gray16`.
+    /// The pixels a cancelled render didn't get to are left inside the set.
+    pub fn render_gray16(&self) -> Vec<u16> {
+        gray16(
+            &self.render_whole_escapes(),
+            self.options.max_iter,
+            self.options.smooth,
+        )
+    }
+
+    /// `render_escapes`, with any rows a cancelled render didn't get to
+    /// inside the set.
+    fn render_whole_escapes(&self) -> Vec<Option<Escape>> {
+        let (width, height) = self.options.bounds;
+        let mut escapes = self.render_escapes();
+        escapes.resize(width as usize * height as usize, None);
+        escapes
+    }
+
+    /// Computes the escape time of every pixel in row-major order, spreading
+    /// the rows across threads.
+    ///
+    /// A cancelled scan stops promptly, finishing just the rows under way,
+    /// and returns the rows finished from the top, so fewer escape times
+    /// than pixels. Boundary tracing runs to the end.
+    pub fn render_escapes(&self) -> Vec<Option<Escape>> {
+        let escapes = self.escapes();
+        self.finished(self.rows_in(&escapes));
+        escapes
+    }
+
+    /// `render_escapes`, without telling the observer that the render is
+    /// over, for renders that go on to do more with them.
+    pub(crate) fn escapes(&self) -> Vec<Option<Escape>> {
+        if self.options.algorithm == Algorithm::BorderTrace {
+            return border_trace::render(self);
+        }
+        let (width, height) = self.options.bounds;
+        self.progress.start(width as u64 * height as u64);
+        self.render_row_escapes(0..height)
+    }
+
+    /// How many whole rows of the image `escapes` hold.
+    pub(crate) fn rows_in(&self, escapes: &[Option<Escape>]) -> u32 {
+        (escapes.len() / self.options.bounds.0.max(1) as usize) as u32
+    }
+
+    /// Tells the observer of the rows of `escapes`, which start at row
+    /// `top`.
+    pub(crate) fn rows_complete(&self, top: u32, escapes: &[Option<Escape>]) {
+        if let Some(observer) = &self.observer {
+            let width = self.options.bounds.0.max(1) as usize;
+            for (i, row) in escapes.chunks(width).enumerate() {
+                observer.on_row_complete(top + i as u32, row);
+            }
+        }
+    }
+
+    /// Tells the observer of the `pixels` of the band of `rows`.
+    pub(crate) fn tile_complete(&self, rows: Range<u32>, pixels: &[u8]) {
+        if let Some(observer) = &self.observer {
+            observer.on_tile_complete(rows, pixels);
+        }
+    }
+
+    /// Tells the observer that the render is over, with `rows` done.
+    pub(crate) fn finished(&self, rows: u32) {
+        if let Some(observer) = &self.observer {
+            observer.on_finish(rows);
+        }
+    }
+
+    /// Computes the escape times of just `rows` of the image, as
+    /// `render_escapes` would for them, adding them to the progress of a
+    /// render that has already been started. Boundary tracing needs the
+    /// whole image, so every pixel is scanned.
+    ///
+    /// Once the render is cancelled no more rows are started, and just the
+    /// rows finished from the first are returned. With perturbation, which
+    /// doesn't go row by row, every row is finished.
+    pub fn render_row_escapes(&self, rows: Range<u32>) -> Vec<Option<Escape>> {
+        let RenderOptions {
+            bounds,
+            upper_left,
+            lower_right,
+            ref fractal,
+            julia,
+            max_iter,
+            shortcuts,
+            bailout,
+            threads,
+            ..
+        } = self.options;
+        let precision = self.options.resolved_precision();
+        let coloring = self.options.coloring;
+        let mut escapes = vec![None; bounds.0 as usize * rows.len()];
+        let width = bounds.0.max(1) as usize;
+        let progress = &*self.progress;
+        let finished = (0..rows.len())
+            .map(|_| AtomicBool::new(false))
+            .collect::<Vec<_>>();
+        let finish = |start: usize, row: &[Option<Escape>]| {
+            progress.add(row.len() as u64);
+            finished[start / width].store(true, Ordering::Relaxed);
+            self.rows_complete(rows.start + (start / width) as u32, row);
+        };
+        match precision {
+            Precision::Auto | Precision::Double | Precision::Single => {
+                progress.add_busy(&parallel_chunks(
+                    &mut escapes,
+                    width,
+                    threads,
+                    |start, row| {
+                        if progress.is_cancelled() {
+                            return;
+                        }
+                        let top = rows.start + (start / width) as u32;
+                        let row_upper_left =
+                            pixel_to_point(bounds, (0, top), upper_left, lower_right);
+                        let row_lower_right =
+                            pixel_to_point(bounds, (bounds.0, top + 1), upper_left, lower_right);
+                        let points = || {
+                            (0..bounds.0)
+                                .map(|column| {
+                                    let pixel = (column, 0);
+                                    pixel_to_point(
+                                        (bounds.0, 1),
+                                        pixel,
+                                        row_upper_left,
+                                        row_lower_right,
+                                    )
+                                })
+                                .collect::<Vec<_>>()
+                        };
+                        match (&self.options.newton, precision) {
+                            (Some(polynomial), _) => newton::render(
+                                row,
+                                (bounds.0, 1),
+                                row_upper_left,
+                                row_lower_right,
+                                polynomial,
+                                max_iter,
+                            ),
+                            (None, Precision::Single) => {
+                                simd::escape_times_f32(&points(), julia, max_iter, shortcuts, row)
+                            }
+                            (None, _) => render(
+                                row,
+                                (bounds.0, 1),
+                                row_upper_left,
+                                row_lower_right,
+                                fractal,
+                                julia,
+                                max_iter,
+                                shortcuts,
+                                bailout,
+                            ),
+                        }
+                        if coloring.follows_orbits() {
+                            orbit::add_statistics(&points(), fractal, julia, coloring, row);
+                        }
+                        finish(start, row);
+                    },
+                ));
+            }
+            Precision::DoubleDouble => {
+                let (upper_left, lower_right) = self.options.exact_corners();
+                progress.add_busy(&parallel_chunks(
+                    &mut escapes,
+                    width,
+                    threads,
+                    |start, row| {
+                        if progress.is_cancelled() {
+                            return;
+                        }
+                        let top = (rows.start as usize + start / width) as f64;
+                        for (column, escape) in row.iter_mut().enumerate() {
+                            let c = double_double::position_to_point(
+                                bounds,
+                                (column as f64, top),
+                                &upper_left,
+                                &lower_right,
+                            );
+                            *escape = double_double::escape_time(c, max_iter);
+                        }
+                        finish(start, row);
+                    },
+                ));
+            }
+            Precision::Arbitrary(bits) => {
+                let (upper_left, lower_right) = self.options.exact_corners();
+                let height = &upper_left.im - &lower_right.im;
+                progress.add_busy(&parallel_chunks(
+                    &mut escapes,
+                    width,
+                    threads,
+                    |start, row| {
+                        if progress.is_cancelled() {
+                            return;
+                        }
+                        let top = rows.start + (start / width) as u32;
+                        let row_upper_left = Complex {
+                            re: upper_left.re.clone(),
+                            im: &upper_left.im - &height.scale(top, bounds.1),
+                        };
+                        let row_lower_right = Complex {
+                            re: lower_right.re.clone(),
+                            im: &upper_left.im - &height.scale(top + 1, bounds.1),
+                        };
+                        fixed::render(
+                            row,
+                            (bounds.0, 1),
+                            &row_upper_left,
+                            &row_lower_right,
+                            max_iter,
+                            bits,
+                        );
+                        finish(start, row);
+                    },
+                ));
+            }
+            Precision::Perturbation(bits) => {
+                let (upper_left, lower_right) = self.options.exact_corners();
+                let positions = rows
+                    .clone()
+                    .flat_map(|row| (0..bounds.0).map(move |column| (column as f64, row as f64)))
+                    .collect::<Vec<_>>();
+                perturbation::render(
+                    &mut escapes,
+                    &positions,
+                    bounds,
+                    &upper_left,
+                    &lower_right,
+                    max_iter,
+                    bits,
+                    threads,
+                    progress,
+                );
+                for row in &finished {
+                    row.store(true, Ordering::Relaxed);
+                }
+                self.rows_complete(rows.start, &escapes);
+            }
+        }
+        let done = finished
+            .iter()
+            .take_while(|row| row.load(Ordering::Relaxed))
+            .count();
+        escapes.truncate(done * bounds.0 as usize);
+        escapes
+    }
+
+    /// Computes the escape time at each of `positions`, given in pixels
+    /// right of and below the upper left corner; `(x, y)` is the point
+    /// `render_escapes` uses for the pixel in column `x` and row `y`, and
+    /// fractional positions fall between pixels. Progress isn't reported.
+    pub fn render_points(&self, positions: &[(f64, f64)]) -> Vec<Option<Escape>> {
+        let RenderOptions {
+            bounds,
+            upper_left,
+            lower_right,
+            ref fractal,
+            julia,
+            max_iter,
+            shortcuts,
+            bailout,
+            threads,
+            ..
+        } = self.options;
+        let mut escapes = vec![None; positions.len()];
+        let precision = self.options.resolved_precision();
+        match precision {
+            Precision::Auto | Precision::Double | Precision::Single => {
+                parallel_chunks(&mut escapes, POINT_CHUNK, threads, |start, chunk| {
+                    let points = positions[start..start + chunk.len()]
+                        .iter()
+                        .map(|&position| {
+                            position_to_point(bounds, position, upper_left, lower_right)
+                        })
+                        .collect::<Vec<_>>();
+                    match (&self.options.newton, precision) {
+                        (Some(polynomial), _) => {
+                            for (escape, &point) in chunk.iter_mut().zip(&points) {
+                                *escape = polynomial.converge(point, max_iter);
+                            }
+                        }
+                        (None, Precision::Single) => {
+                            simd::escape_times_f32(&points, julia, max_iter, shortcuts, chunk)
+                        }
+                        (None, _) => simd::escape_times(
+                            &points, fractal, julia, max_iter, shortcuts, bailout, chunk,
+                        ),
+                    }
+                    if self.options.coloring.follows_orbits() {
+                        orbit::add_statistics(
+                            &points,
+                            fractal,
+                            julia,
+                            self.options.coloring,
+                            chunk,
+                        );
+                    }
+                });
+            }
+            Precision::DoubleDouble => {
+                let (upper_left, lower_right) = self.options.exact_corners();
+                parallel_chunks(&mut escapes, POINT_CHUNK, threads, |start, chunk| {
+                    let positions = &positions[start..start + chunk.len()];
+                    for (escape, &position) in chunk.iter_mut().zip(positions) {
+                        let c = double_double::position_to_point(
+                            bounds,
+                            position,
+                            &upper_left,
+                            &lower_right,
+                        );
+                        *escape = double_double::escape_time(c, max_iter);
+                    }
+                });
+            }
+            Precision::Arbitrary(bits) => {
+                let (upper_left, lower_right) = self.options.exact_corners();
+                let rescale = |z: &Complex<Fixed>| Complex {
+                    re: z.re.with_bits(bits),
+                    im: z.im.with_bits(bits),
+                };
+                let (upper_left, lower_right) = (rescale(&upper_left), rescale(&lower_right));
+                parallel_chunks(&mut escapes, 1, threads, |start, chunk| {
+                    let c = fixed::position_to_point(
+                        bounds,
+                        positions[start],
+                        &upper_left,
+                        &lower_right,
+                    );
+                    chunk[0] = fixed::escape_time(&c, max_iter);
+                });
+            }
+            Precision::Perturbation(bits) => {
+                let (upper_left, lower_right) = self.options.exact_corners();
+                perturbation::render(
+                    &mut escapes,
+                    positions,
+                    bounds,
+                    &upper_left,
+                    &lower_right,
+                    max_iter,
+                    bits,
+                    threads,
+                    &Progress::default(),
+                );
+            }
+        }
+        escapes
+    }
+}
+
+/// How many points `Renderer::render_points` hands a thread at a time.
+const POINT_CHUNK: usize = 1024;
+
+/// Runs `f` over consecutive `chunk_len` sized chunks of `items` on up to
+/// `threads` threads, passing each chunk's offset into `items`. The chunks
+/// are queued up front and each thread keeps taking the next one until none
+/// are left, so threads that land on cheap chunks simply do more of them
+/// instead of sitting idle. With one thread the chunks are run in order on
+/// the calling thread, without spawning any, so rendering also works where
+/// threads aren't available, as on `wasm32`.
+///
+/// Returns how long each thread spent working on its chunks, which is
+/// nothing with one thread, where time may not be available either.
+pub fn parallel_chunks<T, F>(items: &mut [T], chunk_len: usize, threads: u32, f: F) -> Vec<Duration>
+where
+    T: Send,
+    F: Fn(usize, &mut [T]) + Sync,
+{
+    let chunk_len = chunk_len.max(1);
+    if threads <= 1 {
+        for (i, chunk) in items.chunks_mut(chunk_len).enumerate() {
+            f(i * chunk_len, chunk);
+        }
+        return Vec::new();
+    }
+    let count = items.len().div_ceil(chunk_len);
+    let chunks = Injector::new();
+    for (i, chunk) in items.chunks_mut(chunk_len).enumerate() {
+        chunks.push((i * chunk_len, chunk));
+    }
+    let (chunks, f) = (&chunks, &f);
+    crossbeam::scope(|spawner| {
+        let workers = (0..(threads as usize).clamp(1, count.max(1)))
+            .map(|thread| {
+                spawner.spawn(move |_| {
+                    let _span = log::span(Level::Trace, "thread", format_args!("{}", thread));
+                    let mut busy = Duration::ZERO;
+                    loop {
+                        match chunks.steal() {
+                            Steal::Success((start, chunk)) => {
+                                let started = Instant::now();
+                                f(start, chunk);
+                                busy += started.elapsed();
+                            }
+                            Steal::Retry => continue,
+                            Steal::Empty => break busy,
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect()
+    })
+    .unwrap()
+}
+
+/// How many threads render by default: one for each CPU the process may
+/// use, or just one where that can't be told.
+pub fn available_threads() -> u32 {
+    std::thread::available_parallelism().map_or(1, |threads| threads.get() as u32)
+}
+
+#[test]
+fn test_parallel_chunks_covers_every_item_once() {
+    // Thread counts that divide the chunks exactly, that don't, and that
+    // outnumber them.
+    for (len, chunk_len, threads) in [(12, 3, 4), (12, 3, 2), (10, 3, 4), (10, 3, 7), (0, 3, 4)] {
+        let mut items = vec![0; len];
+        parallel_chunks(&mut items, chunk_len, threads, |start, chunk| {
+            for (i, item) in chunk.iter_mut().enumerate() {
+                *item += start + i + 1;
+            }
+        });
+        assert_eq!(items, (1..=len).collect::<Vec<_>>(), "{:?}", (len, threads));
+    }
+    assert!(available_threads() >= 1);
+}
+
+#[test]
+fn test_render_times_each_thread() {
+    let mut items = vec![0; 10];
+    assert!(parallel_chunks(&mut items, 3, 1, |_, _| {}).is_empty());
+    assert_eq!(parallel_chunks(&mut items, 3, 3, |_, _| {}).len(), 3);
+    assert_eq!(parallel_chunks(&mut items, 5, 3, |_, _| {}).len(), 2);
+    let renderer = Renderer::new(RenderOptions {
+        bounds: (40, 30),
+        threads: 3,
+        ..RenderOptions::default()
+    });
+    renderer.render_escapes();
+    let busy = renderer.progress().busy();
+    assert_eq!(busy.len(), 3);
+    assert!(busy.iter().any(|busy| !busy.is_zero()));
+}
+
+#[test]
+fn test_single_thread_stays_in_place() {
+    let caller = std::thread::current().id();
+    let mut items = vec![0; 10];
+    parallel_chunks(&mut items, 3, 1, |start, chunk| {
+        assert_eq!(std::thread::current().id(), caller);
+        for (i, item) in chunk.iter_mut().enumerate() {
+            *item = start + i;
+        }
+    });
+    assert_eq!(items, (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_render_rgba() {
+    let renderer = Renderer::new(RenderOptions {
+        bounds: (8, 6),
+        threads: 1,
+        ..RenderOptions::default()
+    });
+    let rgb = renderer.render();
+    let rgba = renderer.render_rgba();
+    assert_eq!(rgba.len(), 8 * 6 * 4);
+    for (rgb, rgba) in rgb.chunks(3).zip(rgba.chunks(4)) {
+        assert_eq!((rgb, rgba[3]), (&rgba[..3], 255));
+    }
+}
+
+#[test]
+fn test_transparent_interior() {
+    let renderer = Renderer::new(RenderOptions {
+        bounds: (8, 6),
+        threads: 1,
+        ..RenderOptions::default()
+    });
+    let escapes = renderer.render_escapes();
+    let colors = renderer.colorize(&escapes);
+    let rgba = transparent_interior(&colors, &escapes);
+    assert!(escapes.contains(&None) && escapes.iter().any(Option::is_some));
+    for ((rgb, rgba), escape) in colors.chunks(3).zip(rgba.chunks(4)).zip(&escapes) {
+        let alpha = if escape.is_some() { 255 } else { 0 };
+        assert_eq!((rgb, rgba[3]), (&rgba[..3], alpha));
+    }
+    // The rows a cancelled render didn't get to stay opaque.
+    let rgba = transparent_interior(&colors, &escapes[..8]);
+    assert!(rgba[4 * 8..].chunks(4).all(|pixel| pixel[3] == 255));
+    let mut bytes = Vec::new();
+    encode_rgba_image(&mut bytes, &rgba, (8, 6), &[]).unwrap();
+    let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+    assert_eq!(reader.info().color_type, png::ColorType::Rgba);
+    let mut decoded = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut decoded).unwrap();
+    assert_eq!(decoded, rgba);
+}
+
+#[test]
+fn test_cancelled_render() {
+    let options = RenderOptions {
+        bounds: (8, 6),
+        threads: 2,
+        shading: Some(Light {
+            azimuth: 45.0,
+            elevation: 45.0,
+        }),
+        ..RenderOptions::default()
+    };
+    let whole = Renderer::new(options.clone()).render_escapes();
+    let renderer = Renderer::new(options.clone());
+    renderer.progress().cancel();
+    assert!(renderer.render_escapes().is_empty());
+    assert_eq!(renderer.render(), UNRENDERED.repeat(8 * 6));
+    assert_eq!(renderer.render_gray16(), vec![GRAY16_INTERIOR; 8 * 6]);
+    // The rows a render got done are colored as they would be in the band
+    // of just them.
+    let colors = renderer.colorize(&whole[..8 * 2]);
+    let band = Renderer::new(tiled::band(&options, 0, 2)).render();
+    assert_eq!(colors[..8 * 2 * 3], band[..]);
+    assert_eq!(colors[8 * 2 * 3..], UNRENDERED.repeat(8 * 4));
+    let antialiased = Renderer::new(RenderOptions {
+        antialias: 2,
+        ..options
+    });
+    antialiased.progress().cancel();
+    assert_eq!(antialiased.render(), UNRENDERED.repeat(8 * 6));
+}
+
+#[test]
+fn test_renderer_matches_single_threaded_render() {
+    let options = RenderOptions {
+        bounds: (64, 48),
+        upper_left: Complex { re: -1.2, im: 0.35 },
+        lower_right: Complex { re: -1.0, im: 0.2 },
+        palette: Palette::named("fire").unwrap(),
+        threads: 3,
+        ..RenderOptions::default()
+    };
+    let mut expected = vec![None; 64 * 48];
+    render(
+        &mut expected,
+        options.bounds,
+        options.upper_left,
+        options.lower_right,
+        &Fractal::Mandelbrot,
+        None,
+        options.max_iter,
+        options.shortcuts,
+        options.bailout,
+    );
+    let renderer = Renderer::new(options);
+    let escapes = renderer.render_escapes();
+    let iterations = |escapes: &[Option<Escape>]| {
+        escapes
+            .iter()
+            .map(|e| e.map(|e| e.iterations))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(iterations(&escapes), iterations(&expected));
+    let positions = (0..64 * 48)
+        .map(|i| ((i % 64) as f64, (i / 64) as f64))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        iterations(&renderer.render_points(&positions)),
+        iterations(&expected)
+    );
+    assert!(renderer.progress().is_finished());
+    assert_eq!(renderer.progress().done(), 64 * 48);
+    assert_eq!(
+        renderer.render(),
+        colorize(&escapes, &Scale::new(renderer.options(), &[]))
+    );
+}
+
+#[test]
+fn test_distance_coloring_tracks_derivatives() {
+    let options = RenderOptions {
+        bounds: (32, 24),
+        coloring: Coloring::Distance,
+        precision: Precision::Arbitrary(80),
+        ..RenderOptions::default()
+    };
+    assert_eq!(options.resolved_precision(), Precision::Double);
+    let renderer = Renderer::new(options);
+    let escapes = renderer.render_escapes();
+    assert!(escapes.iter().flatten().all(|e| e.derivative.is_some()));
+    assert!(escapes.iter().any(Option::is_some));
+    let positions = (0..24)
+        .flat_map(|row| (0..32).map(move |column| (column as f64, row as f64)))
+        .collect::<Vec<_>>();
+    let points = renderer.render_points(&positions);
+    let distances = |escapes: &[Option<Escape>]| {
+        escapes
+            .iter()
+            .map(|e| e.and_then(|e| e.distance()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(distances(&points), distances(&escapes));
+}
+
+#[test]
+fn test_arbitrary_precision_matches_f64() {
+    let options = RenderOptions {
+        bounds: (24, 18),
+        upper_left: Complex { re: -2.0, im: 1.2 },
+        lower_right: Complex { re: 0.6, im: -1.2 },
+        max_iter: 100,
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let double = Renderer::new(options.clone()).render_escapes();
+    let arbitrary = Renderer::new(RenderOptions {
+        precision: Precision::Arbitrary(96),
+        ..options
+    })
+    .render_escapes();
+    let agree = double
+        .iter()
+        .zip(&arbitrary)
+        .filter(|(a, b)| a.map(|e| e.iterations) == b.map(|e| e.iterations))
+        .count();
+    // Rounding differences can flip a point right at an iteration boundary.
+    assert!(
+        agree >= double.len() * 98 / 100,
+        "{} of {}",
+        agree,
+        double.len()
+    );
+}
+
+#[test]
+fn test_perturbation_matches_arbitrary_precision() {
+    let corner = |re: &str, im: &str| Complex {
+        re: Fixed::parse(re).unwrap(),
+        im: Fixed::parse(im).unwrap(),
+    };
+    let options = RenderOptions {
+        bounds: (16, 12),
+        exact_corners: Some((
+            corner("-0.7436438870371620", "0.1318259042053200"),
+            corner("-0.7436438870371540", "0.1318259042053140"),
+        )),
+        max_iter: 6000,
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let iterations = |precision| {
+        Renderer::new(RenderOptions {
+            precision,
+            ..options.clone()
+        })
+        .render_escapes()
+        .iter()
+        .map(|e| e.map(|e| e.iterations))
+        .collect::<Vec<_>>()
+    };
+    let exact = iterations(Precision::Arbitrary(128));
+    let perturbed = iterations(Precision::Perturbation(128));
+    let agree = exact.iter().zip(&perturbed).filter(|(a, b)| a == b).count();
+    assert!(
+        agree >= exact.len() * 95 / 100,
+        "{} of {}",
+        agree,
+        exact.len()
+    );
+}
+
+/// Computes the escape times of a rectangle of `fractal`, or of its Julia
+/// set of `julia`, into `escapes`, which holds `bounds.0 * bounds.1` values
+/// in row-major order. Each point is iterated at most `limit` times, or
+/// until it passes `bailout`.
+#[allow(clippy::too_many_arguments)]
+pub fn render(
+    escapes: &mut [Option<Escape>],
+    bounds: (u32, u32),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    fractal: &Fractal,
+    julia: Option<Complex<f64>>,
+    limit: u32,
+    shortcuts: Shortcuts,
+    bailout: Bailout,
+) {
+    let mut points = Vec::with_capacity(bounds.0 as usize);
+    for (row, escapes) in escapes
+        .chunks_mut(bounds.0.max(1) as usize)
+        .take(bounds.1 as usize)
+        .enumerate()
+    {
+        points.clear();
+        points.extend(
+            (0..bounds.0).map(|column| {
+                pixel_to_point(bounds, (column, row as u32), upper_left, lower_right)
+            }),
+        );
+        simd::escape_times(&points, fractal, julia, limit, shortcuts, bailout, escapes);
+    }
+}
+
+/// Maps escape times into an RGB pixel buffer, with the colors `colorizer`
+/// gives them.
+pub fn colorize(escapes: &[Option<Escape>], colorizer: &dyn Colorizer) -> Vec<u8> {
+    escapes
+        .iter()
+        .flat_map(|&escape| colorizer.color(escape))
+        .collect()
+}
+
+/// Maps escape times into an RGB pixel buffer like `colorize`, dithered by
+/// `dither` as they are rounded to bytes. `pixel(i)` is where escape `i`
+/// is in the image.
+pub fn colorize_dithered(
+    escapes: &[Option<Escape>],
+    colorizer: &dyn Colorizer,
+    dither: Dither,
+    pixel: impl Fn(usize) -> (u32, u32),
+) -> Vec<u8> {
+    escapes
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &escape)| dither.quantize(colorizer.color_exact(escape), pixel(i)))
+        .collect()
+}
+
+/// Adds an alpha channel to the RGB `colors` of `escapes`, opaque for the
+/// points that escape and fully transparent for those in the set, so that
+/// the image can be laid over a background. Pixels past the escapes, such
+/// as the `UNRENDERED` ones of a cancelled render, stay opaque.
+pub fn transparent_interior(colors: &[u8], escapes: &[Option<Escape>]) -> Vec<u8> {
+    colors
+        .chunks_exact(3)
+        .enumerate()
+        .flat_map(|(i, rgb)| {
+            let inside = matches!(escapes.get(i), Some(None));
+            [rgb[0], rgb[1], rgb[2], if inside { 0 } else { u8::MAX }]
+        })
+        .collect()
+}
+
+/// The 16-bit sample `gray16` stores for points in the set.
+pub const GRAY16_INTERIOR: u16 = u16::MAX;
+
+/// Maps escape values linearly onto 16-bit samples, from 0 for points that
+/// escape straight away to `GRAY16_INTERIOR - 1` at `limit` iterations, so
+/// that they can be recolored later with far more than 256 levels. Points in
+/// the set get `GRAY16_INTERIOR`.
+pub fn gray16(escapes: &[Option<Escape>], limit: u32, smooth: bool) -> Vec<u16> {
+    let top = (GRAY16_INTERIOR - 1) as f64;
+    escapes
+        .iter()
+        .map(|escape| match escape {
+            None => GRAY16_INTERIOR,
+            Some(e) => {
+                let value = if smooth {
+                    e.smooth()
+                } else {
+                    e.iterations as f64
+                };
+                (value / limit.max(1) as f64 * top).clamp(0.0, top).round() as u16
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_gray16() {
+    let escape = |iterations| {
+        Some(Escape {
+            iterations,
+            z: Complex { re: 3.0, im: 0.0 },
+            derivative: None,
+            stripe: None,
+        })
+    };
+    assert_eq!(
+        gray16(
+            &[None, escape(0), escape(500), escape(1000), escape(4000)],
+            1000,
+            false
+        ),
+        vec![GRAY16_INTERIOR, 0, 32767, 65534, 65534]
+    );
+    let smooth = gray16(&[escape(500), escape(501)], 1000, true);
+    assert!((65..=66).contains(&(smooth[1] - smooth[0])));
+}
+
+/// Maps a pixel position to the corresponding point on the complex plane.
+pub fn pixel_to_point(
+    bounds: (u32, u32),
+    pixel: (u32, u32),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> Complex<f64> {
+    position_to_point(
+        bounds,
+        (pixel.0 as f64, pixel.1 as f64),
+        upper_left,
+        lower_right,
+    )
+}
+
+/// `pixel_to_point` for a position that may fall between pixels.
+pub fn position_to_point(
+    bounds: (u32, u32),
+    position: (f64, f64),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> Complex<f64> {
+    let (width, height) = (
+        lower_right.re - upper_left.re,
+        upper_left.im - lower_right.im,
+    );
+    Complex {
+        re: upper_left.re + position.0 * width / (bounds.0 as f64),
+        im: upper_left.im - position.1 * height / (bounds.1 as f64),
+    }
+}
+
+#[test]
+fn test_pixel_to_point() {
+    assert_eq!(
+        pixel_to_point(
+            (100, 100),
+            (25, 75),
+            Complex { re: -1.0, im: 1.0 },
+            Complex { re: 1.0, im: -1.0 }
+        ),
+        Complex { re: -0.5, im: -0.5 }
+    );
+    assert_eq!(
+        pixel_to_point(
+            (100, 100),
+            (100, 0),
+            Complex { re: -1.0, im: 1.0 },
+            Complex { re: 1.0, im: -1.0 }
+        ),
+        Complex { re: 1.0, im: 1.0 }
+    );
+}
+
+/// How a point escaped: the number of iterations it took to pass the
+/// bailout, leaving the circle of radius 2 unless told otherwise, and the
+/// value of `z` once it had. `derivative` is `dz/dc` at
+/// that point, or `dz/dz0` for Julia sets, if it was asked for; see
+/// `distance`. `stripe` is the stripe average of the orbit, likewise; see
+/// `stripe`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Escape {
+    pub iterations: u32,
+    pub z: Complex<f64>,
+    pub derivative: Option<Complex<f64>>,
+    pub stripe: Option<f64>,
+}
+
+impl Escape {
+    /// The normalized iteration count `n + 1 - log2(ln |z|)`, which varies
+    /// continuously across the boundaries between integer escape counts.
+    pub fn smooth(&self) -> f64 {
+        self.smooth_with(2.0)
+    }
+
+    /// `smooth` for an orbit of `z^degree + c`: `n + 1 - log_degree(ln |z|)`.
+    pub fn smooth_with(&self, degree: f64) -> f64 {
+        let log_modulus = self.z.norm_sqr().ln() / 2.0;
+        self.iterations as f64 + 1.0 - log_modulus.ln() / degree.ln()
+    }
+
+    /// The estimated distance from the point to the set, if the derivative
+    /// was tracked: `2 |z| ln |z| / |dz|`, which is at most 4 times the
+    /// true distance either way.
+    pub fn distance(&self) -> Option<f64> {
+        let modulus = self.z.norm();
+        Some(2.0 * modulus * modulus.ln() / self.derivative?.norm())
+    }
+}
+
+/// Returns how `c` escapes the circle of radius 2, or `None` if it stays
+/// inside for `limit` iterations.
+pub fn escape_time(c: Complex<f64>, limit: u32) -> Option<Escape> {
+    escape_time_with(c, limit, Shortcuts::default())
+}
+
+/// `escape_time`, taking only the given `shortcuts`.
+pub fn escape_time_with(c: Complex<f64>, limit: u32, shortcuts: Shortcuts) -> Option<Escape> {
+    if shortcuts.bulbs && in_main_bulbs(c) {
+        return None;
+    }
+    iterate(Complex { re: 0.0, im: 0.0 }, c, limit, shortcuts)
+}
+
+/// Returns how the orbit of `z` under `z * z + c` escapes, as for a point
+/// of the Julia set of `c`. The bulb shortcut only applies to the
+/// Mandelbrot set and is ignored.
+pub fn escape_time_julia(
+    z: Complex<f64>,
+    c: Complex<f64>,
+    limit: u32,
+    shortcuts: Shortcuts,
+) -> Option<Escape> {
+    iterate(z, c, limit, shortcuts)
+}
+
+fn iterate(z: Complex<f64>, c: Complex<f64>, limit: u32, shortcuts: Shortcuts) -> Option<Escape> {
+    let bailout = Bailout::default();
+    iterate_with(
+        z,
+        c,
+        limit,
+        shortcuts,
+        |z| bailout.escaped(z.re, z.im),
+        |z, c| z * z + c,
+    )
+}
+
+/// Iterates `step` from `z` until the orbit has `escaped`.
+fn iterate_with(
+    mut z: Complex<f64>,
+    c: Complex<f64>,
+    limit: u32,
+    shortcuts: Shortcuts,
+    escaped: impl Fn(Complex<f64>) -> bool,
+    mut step: impl FnMut(Complex<f64>, Complex<f64>) -> Complex<f64>,
+) -> Option<Escape> {
+    let mut saved = z;
+    for i in 0..limit {
+        if escaped(z) {
+            return Some(Escape {
+                iterations: i,
+                z,
+                derivative: None,
+                stripe: None,
+            });
+        }
+        z = step(z, c);
+        if shortcuts.periodicity {
+            if (z - saved).norm_sqr() < PERIOD_TOLERANCE {
+                return None;
+            }
+            // Brent's method: comparing against a value saved at every
+            // power of two catches cycles of any length soon after the
+            // orbit settles into them.
+            if (i + 1).is_power_of_two() {
+                saved = z;
+            }
+        }
+    }
+    None
+}
+
+/// How close, as a squared distance, an orbit has to return to a saved
+/// value to be taken as periodic.
+pub const PERIOD_TOLERANCE: f64 = 1e-24;
+
+#[test]
+fn test_escape_time() {
+    assert_eq!(escape_time(Complex { re: 0.0, im: 0.0 }, 1000), None);
+    assert_eq!(escape_time(Complex { re: -1.0, im: 0.0 }, 1000), None);
+    let escape = escape_time(Complex { re: 1.0, im: 0.0 }, 1000).unwrap();
+    assert_eq!(escape.iterations, 3);
+    assert_eq!(escape.z, Complex { re: 5.0, im: 0.0 });
+}
+
+/// Whether `c` lies in the main cardioid or the period-2 bulb, which
+/// between them hold most of the set's area and never escape.
+pub fn in_main_bulbs(c: Complex<f64>) -> bool {
+    let x = c.re - 0.25;
+    let y2 = c.im * c.im;
+    let q = x * x + y2;
+    let in_cardioid = q * (q + x) <= 0.25 * y2;
+    let in_bulb = (c.re + 1.0) * (c.re + 1.0) + y2 <= 0.0625;
+    in_cardioid || in_bulb
+}
+
+#[test]
+fn test_escape_time_julia() {
+    // Starting from 0, the Julia set of c follows the Mandelbrot orbit of c.
+    for c in [
+        Complex { re: 0.3, im: 0.5 },
+        Complex {
+            re: -0.8,
+            im: 0.156,
+        },
+        Complex { re: 1.0, im: 0.0 },
+    ] {
+        assert_eq!(
+            escape_time_julia(Complex { re: 0.0, im: 0.0 }, c, 500, Shortcuts::NONE),
+            escape_time_with(c, 500, Shortcuts::NONE)
+        );
+    }
+    // The Julia set of 0 is the unit disk.
+    let zero = Complex { re: 0.0, im: 0.0 };
+    let julia = |re| escape_time_julia(Complex { re, im: 0.0 }, zero, 500, Shortcuts::default());
+    assert_eq!(julia(0.99), None);
+    assert!(julia(1.01).is_some());
+    assert_eq!(julia(3.0).map(|e| e.iterations), Some(0));
+}
+
+#[test]
+fn test_bulb_shortcut() {
+    assert!(in_main_bulbs(Complex { re: 0.0, im: 0.0 }));
+    assert!(in_main_bulbs(Complex { re: -1.0, im: 0.2 }));
+    assert!(in_main_bulbs(Complex { re: 0.24, im: 0.0 }));
+    assert!(!in_main_bulbs(Complex { re: 0.26, im: 0.0 }));
+    assert!(!in_main_bulbs(Complex { re: -1.3, im: 0.0 }));
+    assert!(!in_main_bulbs(Complex { re: -0.1, im: 0.9 }));
+    for i in 0..400 {
+        let c = Complex {
+            re: -2.0 + (i % 20) as f64 * 0.13,
+            im: -1.2 + (i / 20) as f64 * 0.12,
+        };
+        assert_eq!(
+            escape_time_with(c, 500, Shortcuts::default()),
+            escape_time_with(c, 500, Shortcuts::NONE),
+            "{}",
+            c
+        );
+    }
+}
+
+#[test]
+fn test_periodicity_shortcut() {
+    let periodicity = Shortcuts {
+        bulbs: false,
+        periodicity: true,
+    };
+    let mut cycles = 0;
+    for i in 0..400 {
+        let c = Complex {
+            re: -2.0 + (i % 20) as f64 * 0.13,
+            im: -1.2 + (i / 20) as f64 * 0.12,
+        };
+        let plain = escape_time_with(c, 2000, Shortcuts::NONE);
+        assert_eq!(escape_time_with(c, 2000, periodicity), plain, "{}", c);
+        cycles += plain.is_none() as u32;
+    }
+    assert!(cycles > 50);
+    // The period-3 bulb is outside both closed-form tests.
+    let c = Complex {
+        re: -0.12,
+        im: 0.75,
+    };
+    assert!(!in_main_bulbs(c));
+    assert_eq!(escape_time_with(c, u32::MAX, periodicity), None);
+}
+
+#[test]
+fn test_smooth_escape_is_continuous() {
+    // Walking along the real axis away from the set, the integer count drops
+    // in whole steps while the smooth count only ever moves by a fraction.
+    let mut previous = escape_time(Complex { re: 0.5, im: 0.0 }, 1000)
+        .unwrap()
+        .smooth();
+    for i in 1..1000 {
+        let c = Complex {
+            re: 0.5 + i as f64 * 0.002,
+            im: 0.0,
+        };
+        let smooth = escape_time(c, 1000).unwrap().smooth();
+        assert!((previous - smooth).abs() < 0.5, "jump at {}", c);
+        previous = smooth;
+    }
+}
+
+/// The image file formats renders can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    /// Binary or plain Netpbm color, see `netpbm`.
+    Ppm,
+    /// Binary or plain Netpbm grayscale.
+    Pgm,
+    /// Float escape values rather than colors, see `exr`.
+    Exr,
+    /// Terminal graphics, see `sixel`.
+    Sixel,
+    /// Uncompressed TIFF, or BigTIFF past 4 GB, see `tiff`.
+    Tiff,
+    /// Lossless or near lossless WebP, see `webp`.
+    Webp,
+    /// Contour lines of equal escape value, see `contour`.
+    Svg,
+    /// A heightmap mesh in binary STL, see `mesh`.
+    Stl,
+    /// A heightmap mesh in OBJ, see `mesh`.
+    Obj,
+}
+
+impl Format {
+    /// Looks a format up by name, as given to `--format`.
+    pub fn named(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(Format::Png),
+            "jpeg" | "jpg" => Some(Format::Jpeg),
+            "ppm" => Some(Format::Ppm),
+            "pgm" => Some(Format::Pgm),
+            "exr" => Some(Format::Exr),
+            "sixel" | "six" => Some(Format::Sixel),
+            "tiff" | "tif" => Some(Format::Tiff),
+            "webp" => Some(Format::Webp),
+            "svg" => Some(Format::Svg),
+            "stl" => Some(Format::Stl),
+            "obj" => Some(Format::Obj),
+            _ => None,
+        }
+    }
+
+    /// The usual file name extension.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Jpeg => "jpg",
+            Format::Ppm => "ppm",
+            Format::Pgm => "pgm",
+            Format::Exr => "exr",
+            Format::Sixel => "six",
+            Format::Tiff => "tif",
+            Format::Webp => "webp",
+            Format::Svg => "svg",
+            Format::Stl => "stl",
+            Format::Obj => "obj",
+        }
+    }
+
+    /// Guesses the format from a file name's extension.
+    pub fn from_path(path: &str) -> Option<Format> {
+        let (_, extension) = path.rsplit_once('.')?;
+        Format::named(extension)
+    }
+}
+
+#[test]
+fn test_format_from_path() {
+    assert_eq!(Format::from_path("mandel.png"), Some(Format::Png));
+    assert_eq!(Format::from_path("out/deep.JPG"), Some(Format::Jpeg));
+    assert_eq!(Format::from_path("a.jpeg"), Some(Format::Jpeg));
+    assert_eq!(Format::from_path("frame.pgm"), Some(Format::Pgm));
+    assert_eq!(Format::from_path("preview.six"), Some(Format::Sixel));
+    assert_eq!(Format::from_path("print.tif"), Some(Format::Tiff));
+    assert_eq!(Format::from_path("giga.TIFF"), Some(Format::Tiff));
+    assert_eq!(Format::from_path("web.webp"), Some(Format::Webp));
+    assert_eq!(Format::from_path("lines.svg"), Some(Format::Svg));
+    assert_eq!(Format::from_path("print.STL"), Some(Format::Stl));
+    assert_eq!(Format::from_path("relief.obj"), Some(Format::Obj));
+    assert_eq!(Format::from_path("mandel"), None);
+    assert_eq!(Format::from_path("mandel.bmp"), None);
+}
+
+/// Writes an RGB pixel buffer to `filename` as a PNG.
+pub fn write_image(
+    filename: &str,
+    pixels: &[u8],
+    bounds: (u32, u32),
+) -> Result<(), MandelbrotError> {
+    let file = File::create(filename).map_err(|e| MandelbrotError::writing(filename, e))?;
+    encode_image(&mut BufWriter::new(file), pixels, bounds, &[])
+        .map_err(|e| MandelbrotError::writing(filename, e))
+}
+
+/// Renders the image of `options`, three row-major bytes per pixel like
+/// `Renderer::render`. The same options always give the same bytes, on any
+/// number of threads, which the golden image tests hold it to.
+pub fn render_to_buffer(options: RenderOptions) -> Vec<u8> {
+    Renderer::new(options).render()
+}
+
+/// Encodes an RGB pixel buffer as a PNG, with `text` as keyword and value
+/// text chunks, such as `metadata::describe` gives. The pixels are streamed
+/// through the encoder, which compresses and writes them out as it goes
+/// instead of keeping a filtered copy of the whole image.
+pub fn encode_image<W: Write>(
+    w: W,
+    pixels: &[u8],
+    bounds: (u32, u32),
+    text: &[(&str, String)],
+) -> Result<(), EncodingError> {
+    encode_color_image(w, pixels, bounds, text, png::ColorType::Rgb)
+}
+
+/// Encodes an RGBA pixel buffer, such as `transparent_interior` gives, as
+/// a PNG like `encode_image`.
+pub fn encode_rgba_image<W: Write>(
+    w: W,
+    pixels: &[u8],
+    bounds: (u32, u32),
+    text: &[(&str, String)],
+) -> Result<(), EncodingError> {
+    encode_color_image(w, pixels, bounds, text, png::ColorType::Rgba)
+}
+
+fn encode_color_image<W: Write>(
+    w: W,
+    pixels: &[u8],
+    bounds: (u32, u32),
+    text: &[(&str, String)],
+    color: png::ColorType,
+) -> Result<(), EncodingError> {
+    let mut encoder = png::Encoder::new(w, bounds.0, bounds.1);
+    encoder.set_color(color);
+    add_text(&mut encoder, text)?;
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+    stream.write_all(pixels)?;
+    stream.finish()?;
+    Ok(())
+}
+
+/// Writes 16-bit grayscale samples to `filename` as a PNG.
+pub fn write_gray16_image(
+    filename: &str,
+    samples: &[u16],
+    bounds: (u32, u32),
+) -> Result<(), EncodingError> {
+    let file = File::create(filename)?;
+    encode_gray16_image(&mut BufWriter::new(file), samples, bounds, &[])
+}
+
+/// Encodes 16-bit grayscale samples as a PNG, with text chunks like
+/// `encode_image`, streaming them a row at a time.
+pub fn encode_gray16_image<W: Write>(
+    w: W,
+    samples: &[u16],
+    bounds: (u32, u32),
+    text: &[(&str, String)],
+) -> Result<(), EncodingError> {
+    let mut encoder = png::Encoder::new(w, bounds.0, bounds.1);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    add_text(&mut encoder, text)?;
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+    let mut bytes = Vec::with_capacity(2 * bounds.0 as usize);
+    for row in samples.chunks(bounds.0.max(1) as usize) {
+        bytes.clear();
+        bytes.extend(row.iter().flat_map(|sample| sample.to_be_bytes()));
+        stream.write_all(&bytes)?;
+    }
+    stream.finish()?;
+    Ok(())
+}
+
+/// Adds `tEXt` chunks, or `zTXt` ones for long values.
+pub(crate) fn add_text<W: Write>(
+    encoder: &mut png::Encoder<W>,
+    text: &[(&str, String)],
+) -> Result<(), EncodingError> {
+    for (keyword, value) in text {
+        if value.len() > metadata::COMPRESS_OVER {
+            encoder.add_ztxt_chunk(keyword.to_string(), value.clone())?;
+        } else {
+            encoder.add_text_chunk(keyword.to_string(), value.clone())?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_gray16_to_file() {
+    let file_name = std::env::temp_dir().join("mandelbrot_test_write_gray16.png");
+    let samples = (0..64 * 32).map(|i| i as u16 * 31).collect::<Vec<_>>();
+    write_gray16_image(file_name.to_str().unwrap(), &samples, (64, 32)).unwrap();
+    let decoder = png::Decoder::new(File::open(&file_name).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut bytes = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut bytes).unwrap();
+    assert_eq!(
+        (info.color_type, info.bit_depth),
+        (png::ColorType::Grayscale, png::BitDepth::Sixteen)
+    );
+    assert_eq!(&bytes[..4], &[0, 0, 0, 31]);
+    assert_eq!(
+        u16::from_be_bytes([bytes[2 * 2047], bytes[2 * 2047 + 1]]),
+        2047 * 31
+    );
+}
+
+#[test]
+fn test_write_to_file() {
+    let file_name = std::env::temp_dir().join("mandelbrot_test_write_to_file.png");
+    let bounds: (u32, u32) = (1000, 1000);
+    let mut pixels = vec![255; 3 * bounds.0 as usize * bounds.1 as usize];
+    for i in 0..(bounds.0 / 2) {
+        for j in 0..bounds.1 {
+            let offset = 3 * (i * bounds.1 + j) as usize;
+            pixels[offset..offset + 3].copy_from_slice(&[0, 0, 0]);
+        }
+    }
+    write_image(file_name.to_str().unwrap(), &pixels, bounds).unwrap();
+}
+
+#[test]
+fn test_png_text_chunks() {
+    let long = "1".repeat(metadata::COMPRESS_OVER + 1);
+    let text = [("Size", "2x1".to_string()), ("Center", long.clone())];
+    let mut bytes = Vec::new();
+    encode_image(&mut bytes, &[0; 6], (2, 1), &text).unwrap();
+    let reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+    assert_eq!(reader.info().uncompressed_latin1_text.len(), 1);
+    assert_eq!(reader.info().compressed_latin1_text.len(), 1);
+    assert_eq!(
+        metadata::read(&bytes[..]).unwrap(),
+        vec![
+            ("Size".to_string(), "2x1".to_string()),
+            ("Center".to_string(), long)
+        ]
+    );
+}
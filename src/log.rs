@@ -0,0 +1,149 @@
+//! Logging of the phases of a render, so that a long one can be watched
+//! and the slow parts of it found. Each phase is a span: it is logged to
+//! stderr, with its fields, when it starts and again with how long it took
+//! when it ends, if its level is at or under the one set with `set_level`.
+//! Nothing is logged until a level is set.
+//!
+//! ```text
+//! [   0.004s  INFO] render{output=mandel.png}: started
+//! [   0.004s DEBUG] band{rows=0..1310}: started
+//! [   0.610s DEBUG] band{rows=0..1310}: done in 606.12ms
+//! ```
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// How much detail a span is of, from the least to the most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    /// The phases of a whole command: parsing, rendering and encoding.
+    Info,
+    /// The pieces a render is split into, such as bands and jobs.
+    Debug,
+    /// The threads each piece is worked on by.
+    Trace,
+}
+
+impl Level {
+    /// The level called `name`, as in `--log-level`.
+    pub fn named(name: &str) -> Option<Level> {
+        Some(match name {
+            "error" => Level::Error,
+            "warn" => Level::Warn,
+            "info" => Level::Info,
+            "debug" => Level::Debug,
+            "trace" => Level::Trace,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+/// The most detailed level logged, or 0 for none.
+static LEVEL: AtomicU8 = AtomicU8::new(0);
+
+/// When logging started, which every line is timed from.
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// Logs spans up to `level`, or none for `None`.
+pub fn set_level(level: Option<Level>) {
+    if level.is_some() {
+        STARTED.get_or_init(Instant::now);
+    }
+    LEVEL.store(level.map_or(0, |level| level as u8), Ordering::Relaxed);
+}
+
+/// Whether spans at `level` are logged.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// A phase being logged, which is logged as done when dropped.
+#[must_use = "a span ends as soon as it is dropped"]
+pub struct Span {
+    /// The level, name, fields and start of a span that is logged.
+    logged: Option<(Level, &'static str, String, Instant)>,
+}
+
+/// Starts a span of the phase `name` at `level`, with `fields` such as
+/// `format_args!("rows={:?}", rows)`, which are only formatted if it is
+/// logged.
+pub fn span(level: Level, name: &'static str, fields: fmt::Arguments) -> Span {
+    if !enabled(level) {
+        return Span { logged: None };
+    }
+    let fields = fields.to_string();
+    write(level, name, &fields, format_args!("started"));
+    Span {
+        logged: Some((level, name, fields, Instant::now())),
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some((level, name, fields, started)) = &self.logged {
+            done(*level, name, format_args!("{}", fields), started.elapsed());
+        }
+    }
+}
+
+/// Logs a phase that has already ended after `elapsed`, as a span of it
+/// would have when it was dropped; for one that ran before logging could
+/// be set up.
+pub fn done(level: Level, name: &str, fields: fmt::Arguments, elapsed: Duration) {
+    if enabled(level) {
+        write(
+            level,
+            name,
+            &fields.to_string(),
+            format_args!("done in {:.2?}", elapsed),
+        );
+    }
+}
+
+fn write(level: Level, name: &str, fields: &str, message: fmt::Arguments) {
+    let at = STARTED.get().map_or(Duration::ZERO, Instant::elapsed);
+    let fields = match fields {
+        "" => String::new(),
+        fields => format!("{{{}}}", fields),
+    };
+    // Starting at the beginning of the line draws over a progress bar,
+    // which is drawn again below.
+    eprintln!(
+        "\r[{:>8.3}s {:>5}] {}{}: {}",
+        at.as_secs_f64(),
+        level.name(),
+        name,
+        fields,
+        message
+    );
+}
+
+#[test]
+fn test_levels() {
+    assert_eq!(Level::named("debug"), Some(Level::Debug));
+    assert_eq!(Level::named("verbose"), None);
+    assert!(Level::Error < Level::Trace);
+    // Nothing is logged until a level is set.
+    assert!(!enabled(Level::Error));
+    assert!(span(Level::Error, "test", format_args!(""))
+        .logged
+        .is_none());
+}
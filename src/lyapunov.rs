@@ -0,0 +1,155 @@
+//! Lyapunov fractals. Each point `(a, b)` of the plane sets the two growth
+//! rates of the logistic map `x → r x (1 - x)`, which takes `r` from `a` or
+//! `b` in turn as a forcing sequence of `A`s and `B`s says. Points are
+//! colored by the map's Lyapunov exponent, the average of `ln |r (1 - 2x)|`
+//! along the orbit: negative where the orbit settles into a cycle, taking
+//! the palette, and positive where it is chaotic, taking the interior
+//! color. The sequence `BBBBBBAAAAAA` gives the Zircon Zity image.
+
+use crate::{parallel_chunks, pixel_to_point, Palette, Progress};
+use num::Complex;
+
+/// Steps taken before the exponent is averaged, so that it measures where
+/// the orbit ends up rather than how it gets there.
+const WARMUP: u32 = 50;
+
+/// Where the logistic map starts.
+const START: f64 = 0.5;
+
+/// Which growth rate a step of the forcing sequence takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rate {
+    A,
+    B,
+}
+
+/// Reads a forcing sequence of `A`s and `B`s, in either case, or `None` if
+/// it is empty or has other letters.
+pub fn parse_sequence(text: &str) -> Option<Vec<Rate>> {
+    let sequence = text
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            'A' => Some(Rate::A),
+            'B' => Some(Rate::B),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    (!sequence.is_empty()).then_some(sequence)
+}
+
+/// A Lyapunov render: the exponents of the points of an image of `bounds`
+/// pixels with the given corners, the real axis holding `a` and the
+/// imaginary axis `b`, averaged over `iterations` steps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lyapunov {
+    pub bounds: (u32, u32),
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    pub sequence: Vec<Rate>,
+    pub iterations: u32,
+    pub threads: u32,
+}
+
+impl Lyapunov {
+    /// The exponent of every pixel in row-major order, spreading the rows
+    /// across threads.
+    pub fn exponents(&self, progress: &Progress) -> Vec<f64> {
+        let mut exponents = vec![0.0; self.bounds.0 as usize * self.bounds.1 as usize];
+        let width = self.bounds.0.max(1) as usize;
+        progress.start(exponents.len() as u64);
+        parallel_chunks(&mut exponents, width, self.threads, |start, row| {
+            let top = (start / width) as u32;
+            for (column, lambda) in row.iter_mut().enumerate() {
+                let pixel = (column as u32, top);
+                let point = pixel_to_point(self.bounds, pixel, self.upper_left, self.lower_right);
+                *lambda = exponent(point.re, point.im, &self.sequence, self.iterations);
+            }
+            progress.add(row.len() as u64);
+        });
+        exponents
+    }
+}
+
+/// The Lyapunov exponent of the logistic map forced by `sequence` between
+/// the rates `a` and `b`, over `iterations` steps after the warm-up.
+pub fn exponent(a: f64, b: f64, sequence: &[Rate], iterations: u32) -> f64 {
+    let mut rates = sequence
+        .iter()
+        .map(|rate| match rate {
+            Rate::A => a,
+            Rate::B => b,
+        })
+        .cycle();
+    let mut x = START;
+    for rate in rates.by_ref().take(WARMUP as usize) {
+        x = rate * x * (1.0 - x);
+    }
+    let mut sum = 0.0;
+    for rate in rates.take(iterations as usize) {
+        // A derivative of 0 is a superstable cycle, as negative as it gets.
+        sum += (rate * (1.0 - 2.0 * x)).abs().max(f64::MIN_POSITIVE).ln();
+        x = rate * x * (1.0 - x);
+    }
+    sum / iterations.max(1) as f64
+}
+
+/// Maps exponents onto `palette` into an RGB pixel buffer. Negative ones
+/// run from the start of the palette at 0 to its end as they fall towards
+/// minus infinity; the others take the interior color.
+pub fn colorize(exponents: &[f64], palette: &Palette) -> Vec<u8> {
+    exponents
+        .iter()
+        .flat_map(|&lambda| {
+            let position = (lambda < 0.0).then(|| 1.0 - lambda.exp());
+            palette.color_at(position)
+        })
+        .collect()
+}
+
+/// The corners of a view of the rates from 2 to 4 for an image of
+/// `bounds` pixels, widened to its shape, where the familiar shapes are.
+pub fn view(bounds: (u32, u32)) -> (Complex<f64>, Complex<f64>) {
+    let center = Complex::new(3.0, 3.0);
+    let (upper_left, lower_right) = crate::fractal::view_around(center, false, bounds);
+    // That view is 4 across its shorter side; this one is 2.
+    (
+        center + (upper_left - center) / 2.0,
+        center + (lower_right - center) / 2.0,
+    )
+}
+
+#[test]
+fn test_parse_sequence() {
+    assert_eq!(parse_sequence("aBb"), Some(vec![Rate::A, Rate::B, Rate::B]));
+    assert_eq!(parse_sequence(""), None);
+    assert_eq!(parse_sequence("ABC"), None);
+}
+
+#[test]
+fn test_exponent() {
+    let ab = parse_sequence("AB").unwrap();
+    // With both rates at 2.5 the map settles on the fixed point 0.6, where
+    // the derivative is -0.5.
+    assert!((exponent(2.5, 2.5, &ab, 1000) - 0.5f64.ln()).abs() < 1e-9);
+    // At 3.9 it is chaotic, with an exponent of about 1/2.
+    assert!((exponent(3.9, 3.9, &ab, 100_000) - 0.5).abs() < 0.05);
+    // At 2 the fixed point 0.5 is superstable.
+    assert!(exponent(2.0, 2.0, &ab, 100) < -100.0);
+}
+
+#[test]
+fn test_colorize() {
+    let palette = Palette::named("fire").unwrap();
+    let pixels = colorize(&[0.5, 0.0, 2f64.ln() - 1.0, f64::NEG_INFINITY], &palette);
+    assert_eq!(&pixels[..3], &palette.color_at(None));
+    assert_eq!(&pixels[3..6], &palette.color_at(None));
+    assert_eq!(&pixels[6..9], &palette.at(1.0 - 2.0 / std::f64::consts::E));
+    assert_eq!(&pixels[9..], &palette.at(1.0));
+}
+
+#[test]
+fn test_view() {
+    let (upper_left, lower_right) = view((200, 100));
+    assert_eq!(upper_left, Complex::new(1.0, 4.0));
+    assert_eq!(lower_right, Complex::new(5.0, 2.0));
+}
@@ -1,174 +1,1025 @@
-use num::Complex;
-use png::EncodingError;
-use std::{fs::File, io::BufWriter, str::FromStr};
+mod bench;
+mod bookmarks;
+mod cli;
+mod config;
+mod explore;
+mod interrupt;
+mod progress_bar;
+mod repl;
+mod server;
+mod video;
+mod worker;
+
+use cli::{
+    Animation, Batch, BookmarkCommand, BuddhabrotRender, Cli, Command, FrameOutput, LyapunovRender,
+    PyramidRender,
+};
+use mandelbrot::{
+    ansi, buddhabrot, checkpoint, contour, distributed,
+    dump::Dump,
+    encode_gray16_image, encode_image, encode_rgba_image, exr, gif, gray16, hdr, jpeg,
+    log::{self, Level},
+    lyapunov,
+    mesh::{self, Mesh},
+    metadata, netpbm,
+    palette::{self, Palette},
+    progressive,
+    pyramid::{self, Tile},
+    sixel,
+    stats::Stats,
+    tiff, tiled, transparent_interior, webp, write_gray16_image, write_image, Algorithm, Coloring,
+    Escape, Format, MandelbrotError, Progress, RenderOptions, Renderer,
+};
+use progress_bar::ProgressBar;
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 fn main() {
     let args = std::env::args().collect::<Vec<String>>();
-    if args.len() != 5 {
-        eprintln!("Usage: {} FILE PIXELS UPPERLEFT LOWERRIGHT", args[0]);
+    let program = args.first().map(String::as_str).unwrap_or("mandelbrot");
+    let started = Instant::now();
+    let command = cli::parse_args(&args[1..]);
+    if let Ok(command) = &command {
+        log::set_level(command.log_level());
+        log::done(Level::Info, "parse", format_args!(""), started.elapsed());
+    }
+    let cli = match command {
+        Ok(Command::Render(cli)) => *cli,
+        Ok(Command::Help) => {
+            print!("{}", cli::help(program));
+            return;
+        }
+        Ok(Command::Animate(animation)) => {
+            if let Err(error) = animate(&animation) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Serve(server)) => {
+            if let Err(error) = server::serve(&server) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Worker(worker)) => {
+            if let Err(error) = worker::work(&worker) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Bench(command)) => {
+            if let Err(error) = bench::bench(&command) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Batch(batch)) => {
+            interrupt::install();
+            if let Err(error) = run_batch(&batch) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Buddhabrot(render)) => {
+            if let Err(error) = buddhabrot(&render) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Lyapunov(render)) => {
+            if let Err(error) = lyapunov(&render) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Pyramid(render)) => {
+            if let Err(error) = render_pyramid(&render) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Bookmark(command)) => {
+            if let Err(error) = bookmark(command) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Repl(session)) => {
+            if let Err(error) = repl::repl(*session) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::Explore(session)) => {
+            if let Err(error) = explore::explore(*session) {
+                fail(error);
+            }
+            return;
+        }
+        Ok(Command::PaletteList) => {
+            list_palettes();
+            return;
+        }
+        Err(message) => fail(MandelbrotError::Parse(message)),
+    };
+
+    interrupt::install();
+    if let Err(error) = render(&cli) {
+        fail(error);
+    }
+}
+
+/// Prints `error` and exits with its code: 2 for invalid options, 3 for a
+/// file that couldn't be read or written, 130 for a render stopped by
+/// Ctrl-C, and 1 for any other failure.
+fn fail(error: MandelbrotError) -> ! {
+    eprintln!("error: {}", error);
+    let code = match error {
+        MandelbrotError::Parse(_) => {
+            eprintln!("\nFor more information, try '--help'.");
+            2
+        }
+        MandelbrotError::Io(_) => 3,
+        MandelbrotError::Interrupted(_) => interrupt::EXIT_CODE,
+        MandelbrotError::Render(_) => 1,
+    };
+    std::process::exit(code);
+}
+
+/// Renders the image and writes it to the file, or to standard output for
+/// `-`. Errors name the file that couldn't be written.
+fn render(cli: &Cli) -> Result<(), MandelbrotError> {
+    if cli.verbose {
         eprintln!(
-            "Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
-            args[0]
+            "precision: {}, for pixels {:.3e} apart",
+            cli.options.resolved_precision(),
+            cli.options.pixel_size()
         );
-        std::process::exit(1);
-    }
-
-    let bounds =
-        parse_pair::<u32>(&args[2], 'x').expect(&format!("Unexpected dimensions: {}", &args[2]));
-    let upper_left = parse_complex(&args[3]).expect("error parsing upper left corner point");
-    let lower_right = parse_complex(&args[4]).expect("error parsing lower right corner point");
-    let mut pixels = vec![255; bounds.0 as usize * bounds.1 as usize];
-    let filename = &args[1];
-    let threads = 8;
-    let rows_per_band = bounds.1 / threads + 1;
-    let bands = pixels
-        .chunks_mut((rows_per_band * bounds.0) as usize)
-        .collect::<Vec<_>>();
-    crossbeam::scope(|spawner| {
-        for (i, band) in bands.into_iter().enumerate() {
-            let top = rows_per_band as usize * i;
-            let height = band.len() / bounds.0 as usize;
-            let band_upper_left = pixel_to_point(bounds, (0, top as u32), upper_left, lower_right);
-            let band_lower_right = pixel_to_point(
-                bounds,
-                (bounds.0, (top + height) as u32),
-                upper_left,
-                lower_right,
-            );
-            let band_bounds = (bounds.0, height as u32);
-            spawner.spawn(move |_| {
-                render(band, band_bounds, band_upper_left, band_lower_right);
-            });
+    }
+    if cli.preview {
+        return preview(cli);
+    }
+    let _span = log::span(Level::Info, "render", format_args!("output={}", cli.output));
+    let mut out = create_image(cli)?;
+    if cli.progressive {
+        render_progressive(cli, out)?;
+        return write_sidecar(cli, &describe(cli));
+    }
+    let options = &cli.options;
+    // Plain PNGs and TIFFs come out the same streamed in bands, without
+    // ever holding every escape time at once.
+    let streamed = matches!(cli.format, Format::Png | Format::Tiff)
+        && cli.depth == 8
+        && !cli.transparent_interior
+        && cli.hdr.is_none()
+        && cli.dump_iters.is_none()
+        && cli.dump_npy.is_none()
+        && cli.heightmap.is_none()
+        && cli.normal_map.is_none()
+        && options.coloring != Coloring::Histogram
+        && options.antialias <= 1
+        && options.algorithm == Algorithm::Scan
+        && cli.checkpoint.is_none()
+        && cli.stats.is_none()
+        && cli.workers.is_empty();
+    if let Some(rows) = cli.tile.or(streamed.then_some(0)) {
+        render_tiled(cli, out, rows)?;
+        return write_sidecar(cli, &describe(cli));
+    }
+    let bounds = options.bounds;
+    let renderer = Renderer::new(options.clone());
+    let bar = (!cli.quiet && io::stderr().is_terminal())
+        .then(|| ProgressBar::start(renderer.progress(), bounds.0));
+    // Ctrl-C can stop a scan between rows and keep the rows done.
+    let scan = options.antialias <= 1 && options.algorithm == Algorithm::Scan;
+    let watch = scan.then(|| interrupt::watch(renderer.progress()));
+    // Everything but antialiased colors comes from one escape time per pixel,
+    // which are computed just once and shared with the iteration dump.
+    let started = Instant::now();
+    let span = log::span(Level::Info, "escapes", format_args!(""));
+    let escapes = match &cli.checkpoint {
+        Some(path) => Some(
+            checkpoint::render(&renderer, path, cli.resume)
+                .map_err(|e| MandelbrotError::Io(format!("{}: {}", path, e)))?,
+        ),
+        None if !cli.workers.is_empty() => {
+            Some(distributed::render(&renderer, &cli.workers, |worker, e| {
+                if !cli.quiet {
+                    eprintln!("\rworker {}: {}; its rows go to the others", worker, e);
+                }
+            }))
+        }
+        None if scan => Some(
+            checkpoint::render_bands(&renderer, Vec::new(), |_, _| Ok(()))
+                .expect("bands that aren't saved can't fail"),
+        ),
+        None => (options.antialias <= 1).then(|| renderer.render_escapes()),
+    };
+    drop(span);
+    drop(watch);
+    if let Some(escapes) = escapes.as_deref() {
+        if escapes.len() < bounds.0 as usize * bounds.1 as usize {
+            if let Some(bar) = bar {
+                bar.finish();
+            }
+            return save_interrupted(cli, out, escapes);
+        }
+    }
+    // Antialiased colors aren't timed.
+    let rendered = escapes.is_some().then(|| started.elapsed());
+    let colors = || match &escapes {
+        Some(escapes) => renderer.colorize(escapes),
+        None => renderer.render(),
+    };
+    let text = describe(cli);
+    let raw = || escapes.as_deref().expect("raw output is never antialiased");
+    let span = log::span(
+        Level::Info,
+        "encode",
+        format_args!("format={:?}", cli.format),
+    );
+    let written: Result<(), Box<dyn Error>> = match (cli.format, cli.depth) {
+        (Format::Png, 16) => {
+            let samples = gray16(raw(), options.max_iter, options.smooth);
+            encode_gray16_image(&mut out, &samples, bounds, &text).map_err(Into::into)
         }
-    }).unwrap();
-    write_image(&filename, &pixels, bounds).expect("Error writing png to the file");
-}
-
-fn render(
-    pixels: &mut [u8],
-    bounds: (u32, u32),
-    upper_left: Complex<f64>,
-    lower_right: Complex<f64>,
-) {
-    for row in 0..bounds.1 {
-        for column in 0..bounds.0 {
-            let point = pixel_to_point(bounds, (column, row), upper_left, lower_right);
-            pixels[(row * bounds.0 + column) as usize] = match escape_time(point, 255) {
-                None => 0,
-                Some(x) => 255 - x as u8,
+        (Format::Pgm, 16) => {
+            let samples = gray16(raw(), options.max_iter, options.smooth);
+            netpbm::encode_pgm(&mut out, &samples, u16::MAX, bounds, cli.plain).map_err(Into::into)
+        }
+        (Format::Exr, _) => {
+            exr::encode_escapes(&mut out, raw(), bounds, options.smooth).map_err(Into::into)
+        }
+        (Format::Svg, _) => {
+            contour::encode_svg(&mut out, raw(), options, cli.contours, &text).map_err(Into::into)
+        }
+        (Format::Stl | Format::Obj, _) => write_mesh(&mut out, cli, raw(), &text),
+        (Format::Png, _) if cli.transparent_interior => {
+            let pixels = transparent_interior(&colors(), raw());
+            encode_rgba_image(&mut out, &pixels, bounds, &text).map_err(Into::into)
+        }
+        (Format::Png, _) if cli.hdr.is_some() => {
+            let colorizer = renderer.colorizer(raw());
+            let samples = hdr::samples(&colors(), raw(), &*colorizer, cli.hdr.unwrap());
+            hdr::encode(&mut out, &samples, bounds, &text).map_err(Into::into)
+        }
+        _ => encode_colors(&mut out, cli, &colors(), &text),
+    };
+    drop(span);
+    let stats = cli
+        .stats
+        .as_ref()
+        .zip(escapes.as_deref())
+        .map(|(path, escapes)| {
+            let busy = renderer.progress().busy();
+            let elapsed = rendered.unwrap_or_default();
+            let stats = Stats::new(options, escapes, elapsed, busy, peak_memory());
+            File::create(path)
+                .and_then(|file| {
+                    let mut file = BufWriter::new(file);
+                    stats.write_json(&mut file)?;
+                    file.flush()
+                })
+                .map_err(|e| MandelbrotError::writing(path, e))
+        });
+    let relief =
+        (cli.heightmap.is_some() || cli.normal_map.is_some()).then(|| match escapes.as_deref() {
+            Some(escapes) => write_relief_maps(cli, escapes),
+            None => write_relief_maps(cli, &renderer.render_escapes()),
+        });
+    let dumped = (cli.dump_iters.is_some() || cli.dump_npy.is_some()).then(|| {
+        let escapes = escapes.unwrap_or_else(|| renderer.render_escapes());
+        let dump = Dump::new(&escapes, options);
+        let write = |path: &String, npy: bool| {
+            File::create(path)
+                .and_then(|file| {
+                    let mut file = BufWriter::new(file);
+                    match npy {
+                        true => dump.write_npy(&mut file)?,
+                        false => dump.write(&mut file)?,
+                    }
+                    file.flush()
+                })
+                .map_err(|e| MandelbrotError::writing(path, e))
+        };
+        cli.dump_iters
+            .iter()
+            .map(|path| write(path, false))
+            .chain(cli.dump_npy.iter().map(|path| write(path, true)))
+            .collect::<Result<(), _>>()
+    });
+    if let Some(bar) = bar {
+        bar.finish();
+    }
+    if let Some(rendered) = rendered.filter(|_| cli.verbose) {
+        report_busy(&renderer.progress(), rendered);
+    }
+    written
+        .and_then(|()| out.flush().map_err(Into::into))
+        .map_err(|e| MandelbrotError::writing(&cli.output, e))?;
+    dumped.unwrap_or(Ok(()))?;
+    relief.unwrap_or(Ok(()))?;
+    stats.unwrap_or(Ok(()))?;
+    write_sidecar(cli, &text)?;
+    // The image is safely written, so there is nothing left to resume.
+    if let Some(path) = &cli.checkpoint {
+        std::fs::remove_file(path).map_err(|e| MandelbrotError::writing(path, e))?;
+    }
+    Ok(())
+}
+
+/// The parameters of `cli`'s render, for its metadata and sidecar: those
+/// of its options, and of the image they don't cover.
+fn describe(cli: &Cli) -> Vec<(&'static str, String)> {
+    let mut text = metadata::describe(&cli.options);
+    if cli.depth != 8 {
+        text.push(("Depth", cli.depth.to_string()));
+    }
+    if cli.transparent_interior {
+        text.push(("Transparent interior", true.to_string()));
+    }
+    text
+}
+
+/// Writes `text`, the parameters of `cli`'s render, to its sidecar, if it
+/// has one.
+fn write_sidecar(cli: &Cli, text: &[(&str, String)]) -> Result<(), MandelbrotError> {
+    let Some(path) = &cli.sidecar else {
+        return Ok(());
+    };
+    File::create(path)
+        .and_then(|file| {
+            let mut file = BufWriter::new(file);
+            metadata::write_json(&mut file, text)?;
+            file.flush()
+        })
+        .map_err(|e| MandelbrotError::writing(path, e))
+}
+
+/// Saves what an interrupted render got done: the rows of `escapes` in the
+/// image, with the rest `UNRENDERED`, or inside the set for formats of
+/// escape values, and in a checkpoint to carry on from.
+fn save_interrupted(
+    cli: &Cli,
+    mut out: Box<dyn Write>,
+    escapes: &[Option<Escape>],
+) -> Result<(), MandelbrotError> {
+    let options = &cli.options;
+    let bounds = options.bounds;
+    let rows = (escapes.len() / bounds.0.max(1) as usize) as u32;
+    let checkpoint = match &cli.checkpoint {
+        // It has every row already.
+        Some(path) => path.clone(),
+        None => {
+            let path = match cli.output.as_str() {
+                "-" => "mandelbrot.mbcp".to_string(),
+                output => {
+                    let path = std::path::Path::new(output).with_extension("mbcp");
+                    path.to_string_lossy().into_owned()
+                }
             };
+            File::create(&path)
+                .and_then(|file| {
+                    let mut file = BufWriter::new(file);
+                    checkpoint::write_header(&mut file, options)?;
+                    checkpoint::write_band(&mut file, 0, bounds.0, escapes)?;
+                    file.flush()
+                })
+                .map_err(|e| MandelbrotError::writing(&path, e))?;
+            path
+        }
+    };
+    let mut filled = escapes.to_vec();
+    filled.resize(bounds.0 as usize * bounds.1 as usize, None);
+    let text = describe(cli);
+    let written: Result<(), Box<dyn Error>> = match (cli.format, cli.depth) {
+        (Format::Png, 16) => {
+            let samples = gray16(&filled, options.max_iter, options.smooth);
+            encode_gray16_image(&mut out, &samples, bounds, &text).map_err(Into::into)
+        }
+        (Format::Pgm, 16) => {
+            let samples = gray16(&filled, options.max_iter, options.smooth);
+            netpbm::encode_pgm(&mut out, &samples, u16::MAX, bounds, cli.plain).map_err(Into::into)
+        }
+        (Format::Exr, _) => {
+            exr::encode_escapes(&mut out, &filled, bounds, options.smooth).map_err(Into::into)
         }
+        (Format::Svg, _) => {
+            contour::encode_svg(&mut out, &filled, options, cli.contours, &text).map_err(Into::into)
+        }
+        (Format::Stl | Format::Obj, _) => write_mesh(&mut out, cli, &filled, &text),
+        (Format::Png, _) if cli.hdr.is_some() => {
+            let renderer = Renderer::new(options.clone());
+            let colorizer = renderer.colorizer(&filled);
+            let colors = renderer.colorize(&filled);
+            let samples = hdr::samples(&colors, &filled, &*colorizer, cli.hdr.unwrap());
+            hdr::encode(&mut out, &samples, bounds, &text).map_err(Into::into)
+        }
+        _ => {
+            let colors = Renderer::new(options.clone()).colorize(escapes);
+            if cli.transparent_interior {
+                let pixels = transparent_interior(&colors, escapes);
+                encode_rgba_image(&mut out, &pixels, bounds, &text).map_err(Into::into)
+            } else {
+                encode_colors(&mut out, cli, &colors, &text)
+            }
+        }
+    };
+    written
+        .and_then(|()| out.flush().map_err(Into::into))
+        .map_err(|e| MandelbrotError::writing(&cli.output, e))?;
+    Err(MandelbrotError::Interrupted(format!(
+        "interrupted after {} of {} rows, which are in {}; carry on with --resume {}",
+        rows, bounds.1, cli.output, checkpoint
+    )))
+}
+
+/// Prints how much of `elapsed` each thread of a render spent busy, for
+/// `--verbose`. A thread that is often idle means the work wasn't spread
+/// evenly, or was held up by something else.
+fn report_busy(progress: &Progress, elapsed: Duration) {
+    let busy = progress.busy();
+    if busy.is_empty() {
+        return;
     }
+    let shares = busy
+        .iter()
+        .map(|busy| format!("{:.0}%", 100.0 * busy.as_secs_f64() / elapsed.as_secs_f64()))
+        .collect::<Vec<_>>();
+    eprintln!("threads busy: {} of {:.2?}", shares.join(" "), elapsed);
 }
 
-fn pixel_to_point(
-    bounds: (u32, u32),
-    pixel: (u32, u32),
-    upper_left: Complex<f64>,
-    lower_right: Complex<f64>,
-) -> Complex<f64> {
-    let (width, height) = (
-        lower_right.re - upper_left.re,
-        upper_left.im - lower_right.im,
-    );
-    Complex {
-        re: upper_left.re + pixel.0 as f64 * width / (bounds.0 as f64),
-        im: upper_left.im - pixel.1 as f64 * height / (bounds.1 as f64),
+/// The most memory the process has held at once, in bytes, where the
+/// system tells: Linux's high water mark of resident memory.
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kilobytes = line["VmHWM:".len()..].trim().strip_suffix("kB")?;
+    Some(kilobytes.trim().parse::<u64>().ok()? * 1024)
+}
+
+/// Renders the jobs of a batch, `batch.parallel` at a time, carrying on
+/// past any that fail.
+fn run_batch(batch: &Batch) -> Result<(), MandelbrotError> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..batch.parallel.min(batch.jobs.len() as u32) {
+            scope.spawn(|| {
+                while let Some((name, cli)) = batch.jobs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if interrupt::interrupted() {
+                        break;
+                    }
+                    if !cli.quiet {
+                        eprintln!("{}: rendering {}", name, cli.output);
+                    }
+                    if let Err(error) = render(cli) {
+                        eprintln!("error: {}: {}", name, error);
+                        failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+    });
+    match failed.into_inner() {
+        0 => Ok(()),
+        failed => Err(MandelbrotError::Render(format!(
+            "{} of {} jobs failed",
+            failed,
+            batch.jobs.len()
+        ))),
     }
 }
 
-#[test]
-fn test_pixel_to_point() {
-    assert_eq!(
-        pixel_to_point(
-            (100, 100),
-            (25, 75),
-            Complex { re: -1.0, im: 1.0 },
-            Complex { re: 1.0, im: -1.0 }
-        ),
-        Complex { re: -0.5, im: -0.5 }
-    );
-    assert_eq!(
-        pixel_to_point(
-            (100, 100),
-            (100, 0),
-            Complex { re: -1.0, im: 1.0 },
-            Complex { re: 1.0, im: -1.0 }
-        ),
-        Complex { re: 1.0, im: 1.0 }
-    );
+/// Renders the image in bands of `rows` rows, writing each into `out` as
+/// soon as it is done.
+fn render_tiled(cli: &Cli, mut out: Box<dyn Write>, rows: u32) -> Result<(), MandelbrotError> {
+    let renderer = Renderer::new(cli.options.clone());
+    let bar = (!cli.quiet && io::stderr().is_terminal())
+        .then(|| ProgressBar::start(renderer.progress(), cli.options.bounds.0));
+    let text = describe(cli);
+    let started = Instant::now();
+    let watch = interrupt::watch(renderer.progress());
+    let written = match cli.format {
+        Format::Tiff => tiled::encode_tiff(&mut out, &renderer, rows, &text).map_err(Into::into),
+        _ => tiled::encode_png(&mut out, &renderer, rows, &text).map_err(Box::<dyn Error>::from),
+    };
+    drop(watch);
+    if let Some(bar) = bar {
+        bar.finish();
+    }
+    if cli.verbose {
+        report_busy(&renderer.progress(), started.elapsed());
+    }
+    let done = written.map_err(|e| MandelbrotError::writing(&cli.output, e))?;
+    out.flush()
+        .map_err(|e| MandelbrotError::writing(&cli.output, e))?;
+    // Only the escape times could be saved to carry on from, and a band
+    // at a time keeps none of them.
+    let height = cli.options.bounds.1;
+    if done < height && interrupt::interrupted() {
+        return Err(MandelbrotError::Interrupted(format!(
+            "interrupted after {} of {} rows, which are in {}; render with --checkpoint to be \
+             able to carry on",
+            done, height, cli.output
+        )));
+    }
+    if done < height {
+        return Err(MandelbrotError::Render(format!(
+            "only {} of {} rows were rendered into {}",
+            done, height, cli.output
+        )));
+    }
+    Ok(())
+}
+
+/// Renders the image in passes from coarse to whole, writing each into
+/// `out` as a frame of an APNG as soon as it is done.
+fn render_progressive(cli: &Cli, mut out: Box<dyn Write>) -> Result<(), MandelbrotError> {
+    let bounds = cli.options.bounds;
+    let text = describe(cli);
+    let mut last: Option<Vec<u8>> = None;
+    let frames = progressive::PASSES.iter().map(|&factor| {
+        // Once interrupted, the passes left repeat the last one done.
+        if let (true, Some(frame)) = (interrupt::interrupted(), &last) {
+            return frame.clone();
+        }
+        let renderer = Renderer::new(progressive::pass(&cli.options, factor));
+        let bar = (!cli.quiet && io::stderr().is_terminal())
+            .then(|| ProgressBar::start(renderer.progress(), renderer.options().bounds.0));
+        let watch = interrupt::watch(renderer.progress());
+        let pixels = renderer.render();
+        drop(watch);
+        if let Some(bar) = bar {
+            bar.finish();
+        }
+        let frame = progressive::upscale(&pixels, factor, bounds);
+        last = Some(frame.clone());
+        frame
+    });
+    progressive::encode_apng(&mut out, bounds, &text, frames)
+        .map_err(|e| MandelbrotError::writing(&cli.output, e))?;
+    out.flush()
+        .map_err(|e| MandelbrotError::writing(&cli.output, e))?;
+    if interrupt::interrupted() {
+        return Err(MandelbrotError::Interrupted(format!(
+            "interrupted; the passes left in {} repeat the last one done",
+            cli.output
+        )));
+    }
+    Ok(())
 }
 
-fn escape_time(c: Complex<f64>, limit: u32) -> Option<u32> {
-    let mut z = Complex { re: 0.0, im: 0.0 };
-    for i in 0..limit {
-        if z.norm_sqr() > 4.0 {
-            return Some(i);
+/// Opens the file at `path` for writing, or standard output for `-`.
+fn create(path: &str) -> Result<Box<dyn Write>, MandelbrotError> {
+    Ok(match path {
+        // Standard output flushes at every newline, which binary images
+        // are full of.
+        "-" => Box::new(BufWriter::new(io::stdout().lock())),
+        path => Box::new(BufWriter::new(
+            File::create(path).map_err(|e| MandelbrotError::writing(path, e))?,
+        )),
+    })
+}
+
+/// Opens `cli`'s output like `create`, but won't send binary images to a
+/// terminal, where they would only garble it.
+fn create_image(cli: &Cli) -> Result<Box<dyn Write>, MandelbrotError> {
+    let text = match cli.format {
+        Format::Sixel | Format::Svg | Format::Obj => true,
+        Format::Ppm | Format::Pgm => cli.plain,
+        _ => false,
+    };
+    if cli.output == "-" && !text && io::stdout().is_terminal() {
+        return Err(MandelbrotError::Io(format!(
+            "not writing a {} image to a terminal; redirect standard output to a file or a \
+             program",
+            cli.format.extension()
+        )));
+    }
+    create(&cli.output)
+}
+
+/// Writes an image of `colors` in the format `cli` asks for, with `text`
+/// in PNG's text chunks. Formats other than escape values only.
+fn encode_colors(
+    mut out: impl Write,
+    cli: &Cli,
+    colors: &[u8],
+    text: &[(&str, String)],
+) -> Result<(), Box<dyn Error>> {
+    let bounds = cli.options.bounds;
+    match cli.format {
+        Format::Png => encode_image(&mut out, colors, bounds, text).map_err(Into::into),
+        Format::Jpeg => jpeg::encode(&mut out, colors, bounds, cli.quality).map_err(Into::into),
+        Format::Ppm => netpbm::encode_ppm(&mut out, colors, bounds, cli.plain).map_err(Into::into),
+        Format::Sixel => sixel::encode(&mut out, colors, bounds).map_err(Into::into),
+        Format::Pgm => {
+            let samples = netpbm::luma(colors);
+            netpbm::encode_pgm(&mut out, &samples, 255, bounds, cli.plain).map_err(Into::into)
         }
-        z = z * z + c;
+        Format::Tiff => tiff::encode(&mut out, colors, bounds, text).map_err(Into::into),
+        Format::Webp => webp::encode(&mut out, colors, bounds, cli.quality).map_err(Into::into),
+        Format::Exr => unreachable!("exr holds escape values, not colors"),
+        Format::Svg => unreachable!("svg holds contours, not colors"),
+        Format::Stl | Format::Obj => unreachable!("meshes hold heights, not colors"),
     }
-    None
 }
 
-fn write_image(filename: &str, pixels: &[u8], bounds: (u32, u32)) -> Result<(), EncodingError> {
-    let file = File::create(filename).unwrap();
-    let ref mut w = BufWriter::new(file);
-    let mut encoder = png::Encoder::new(w, bounds.0 as u32, bounds.1 as u32); // Width is 2 pixels and height is 1.
-    encoder.set_color(png::ColorType::Grayscale);
-    let mut writer = encoder.write_header()?;
-    writer.write_image_data(pixels)?;
+/// Writes the heightmap and normal map `cli` asks for, of `escapes`.
+fn write_relief_maps(cli: &Cli, escapes: &[Option<Escape>]) -> Result<(), MandelbrotError> {
+    let options = &cli.options;
+    let heights = mesh::heights(escapes, options.max_iter, options.smooth);
+    if let Some(path) = &cli.heightmap {
+        let samples = heights
+            .iter()
+            .map(|h| (h * u16::MAX as f64).round() as u16)
+            .collect::<Vec<_>>();
+        write_gray16_image(path, &samples, options.bounds)
+            .map_err(|e| MandelbrotError::writing(path, e))?;
+    }
+    if let Some(path) = &cli.normal_map {
+        let pixels = mesh::normal_map(&heights, options.bounds, cli.height_scale);
+        write_image(path, &pixels, options.bounds)
+            .map_err(|e| MandelbrotError::writing(path, e))?;
+    }
     Ok(())
 }
 
-#[test]
-fn test_write_to_file() {
-    let file_name = "test.png";
-    let bounds: (u32, u32) = (1000, 1000);
-    let mut pixels = vec![255; bounds.0 as usize * bounds.1 as usize];
-    for i in 0..(bounds.0 / 2) {
-        for j in 0..bounds.1 {
-            pixels[(i * bounds.1 + j) as usize] = 0
+/// Writes `escapes` as a heightmap mesh in the format `cli` asks for.
+fn write_mesh(
+    mut out: impl Write,
+    cli: &Cli,
+    escapes: &[Option<Escape>],
+    text: &[(&str, String)],
+) -> Result<(), Box<dyn Error>> {
+    let options = &cli.options;
+    let heights = mesh::heights(escapes, options.max_iter, options.smooth);
+    let mesh = Mesh::heightmap(
+        &heights,
+        options.bounds,
+        cli.base_thickness,
+        cli.height_scale,
+    );
+    match cli.format {
+        Format::Stl => mesh.write_stl(&mut out)?,
+        _ => mesh.write_obj(&mut out, text)?,
+    }
+    Ok(())
+}
+
+/// Counts the orbits of a Buddhabrot, with a progress bar in samples, and
+/// writes them tone mapped like a render.
+fn buddhabrot(render: &BuddhabrotRender) -> Result<(), MandelbrotError> {
+    let frame = &render.frame;
+    let _span = log::span(
+        Level::Info,
+        "render",
+        format_args!("output={}", frame.output),
+    );
+    let mut out = create_image(frame)?;
+    let progress = Arc::new(Progress::default());
+    // The bar's rate counts a row's share of the samples as a row.
+    let row_samples = render.buddhabrot.samples / frame.options.bounds.1.max(1) as u64;
+    let bar = (!frame.quiet && io::stderr().is_terminal())
+        .then(|| ProgressBar::start(progress.clone(), row_samples.max(1) as u32));
+    let mut colors = match &render.nebula {
+        Some(nebula) => nebula.tone_map(&render.buddhabrot.densities(&nebula.limits, &progress)),
+        None => {
+            let density = render.buddhabrot.density(&progress);
+            buddhabrot::tone_map(&density, &frame.options.palette)
         }
+    };
+    if let Some(bar) = bar {
+        bar.finish();
     }
-    write_image(file_name, &pixels, bounds).unwrap();
+    frame.options.adjustments.apply(&mut colors);
+    let text =
+        metadata::describe_buddhabrot(&frame.options, &render.buddhabrot, render.nebula.as_ref());
+    encode_colors(&mut out, frame, &colors, &text)
+        .and_then(|()| out.flush().map_err(Into::into))
+        .map_err(|e| MandelbrotError::writing(&frame.output, e))?;
+    write_sidecar(frame, &text)
 }
 
-fn parse_complex(s: &str) -> Option<Complex<f64>> {
-    match parse_pair::<f64>(s, ',') {
-        Some((re, im)) => Some(Complex { re, im }),
-        None => None,
+/// Computes the exponents of a Lyapunov fractal, with a progress bar, and
+/// writes them colored like a render.
+fn lyapunov(render: &LyapunovRender) -> Result<(), MandelbrotError> {
+    let frame = &render.frame;
+    let _span = log::span(
+        Level::Info,
+        "render",
+        format_args!("output={}", frame.output),
+    );
+    let mut out = create_image(frame)?;
+    let progress = Arc::new(Progress::default());
+    let bar = (!frame.quiet && io::stderr().is_terminal())
+        .then(|| ProgressBar::start(progress.clone(), frame.options.bounds.0));
+    let exponents = render.lyapunov.exponents(&progress);
+    if let Some(bar) = bar {
+        bar.finish();
     }
+    let mut colors = lyapunov::colorize(&exponents, &frame.options.palette);
+    frame.options.adjustments.apply(&mut colors);
+    encode_colors(&mut out, frame, &colors, &[])
+        .and_then(|()| out.flush().map_err(Into::into))
+        .map_err(|e| MandelbrotError::writing(&frame.output, e))
 }
 
-#[test]
-fn test_parse_complex() {
-    assert_eq!(
-        parse_complex("1.25,-0.0625"),
-        Some(Complex {
-            re: 1.25,
-            im: -0.0625
-        })
+/// Renders every level of a tile pyramid, with a progress bar over all of
+/// them, writing the tiles of `NAME.dzi` under `NAME_files/LEVEL/` and
+/// then the descriptor, so that a viewer never finds one without its tiles.
+fn render_pyramid(render: &PyramidRender) -> Result<(), MandelbrotError> {
+    let frame = &render.frame;
+    let _span = log::span(
+        Level::Info,
+        "render",
+        format_args!("output={}", frame.output),
     );
-    assert_eq!(parse_complex(",-0.0625"), None);
+    let dir = format!("{}_files", frame.output.trim_end_matches(".dzi"));
+    let extension = frame.format.extension();
+    let renderer = Renderer::new(frame.options.clone());
+    let bar = (!frame.quiet && io::stderr().is_terminal())
+        .then(|| ProgressBar::start(renderer.progress(), frame.options.bounds.0));
+    let written = pyramid::render(&renderer, &render.pyramid, |tile, pixels| {
+        let level = format!("{}/{}", dir, tile.level);
+        if tile.column == 0 && tile.row == 0 {
+            std::fs::create_dir_all(&level)
+                .map_err(|e| io::Error::other(MandelbrotError::writing(&level, e)))?;
+        }
+        let path = format!("{}/{}_{}.{}", level, tile.column, tile.row, extension);
+        write_tile(&path, frame, tile, pixels).map_err(io::Error::other)
+    })
+    .map_err(|e| MandelbrotError::Io(e.to_string()));
+    if let Some(bar) = bar {
+        bar.finish();
+    }
+    written?;
+    std::fs::write(&frame.output, render.pyramid.descriptor(extension))
+        .map_err(|e| MandelbrotError::writing(&frame.output, e))?;
+    write_sidecar(frame, &describe(frame))
 }
 
-fn parse_pair<T: FromStr>(s: &str, seperator: char) -> Option<(T, T)> {
-    match s.find(seperator) {
-        None => None,
-        Some(index) => match (T::from_str(&s[..index]), T::from_str(&s[index + 1..])) {
-            (Ok(a), Ok(b)) => Some((a, b)),
-            _ => None,
-        },
+/// Writes one tile of a pyramid to `path`, as png or jpeg like `frame`.
+fn write_tile(path: &str, frame: &Cli, tile: Tile, pixels: &[u8]) -> Result<(), MandelbrotError> {
+    let mut out = create(path)?;
+    let written: Result<(), Box<dyn Error>> = match frame.format {
+        Format::Jpeg => {
+            jpeg::encode(&mut out, pixels, tile.bounds, frame.quality).map_err(Into::into)
+        }
+        _ => encode_image(&mut out, pixels, tile.bounds, &[]).map_err(Into::into),
+    };
+    written
+        .and_then(|()| out.flush().map_err(Into::into))
+        .map_err(|e| MandelbrotError::writing(path, e))
+}
+
+/// Prints the view at the width of the terminal, or 80 columns, keeping
+/// the shape of the full size image. Each line of text is two rows of
+/// pixels.
+fn preview(cli: &Cli) -> Result<(), MandelbrotError> {
+    let (width, height) = cli.options.bounds;
+    let columns = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse::<u32>().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(80)
+        .min(width.max(1));
+    let rows = (columns as f64 * height as f64 / width.max(1) as f64).round() as u32;
+    let options = RenderOptions {
+        bounds: (columns, rows.max(1)),
+        antialias: 1,
+        ..cli.options.clone()
+    };
+    let pixels = Renderer::new(options.clone()).render();
+    ansi::encode(io::stdout().lock(), &pixels, options.bounds)
+        .map_err(|e| MandelbrotError::writing("stdout", e))
+}
+
+/// Where `animate` streams the frames that aren't written as images.
+enum Stream {
+    Video(video::Video),
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        colors: usize,
+        delay: u16,
+    },
+}
+
+/// Renders each frame of an animation in turn, counting them off on stderr
+/// in place of the progress bar. Frames are written as images, or streamed
+/// into a video as they are rendered.
+fn animate(animation: &Animation) -> Result<(), MandelbrotError> {
+    let show = !animation.frame.quiet && io::stderr().is_terminal();
+    // A sidecar for every frame would double the files for little use.
+    let mut frame = Cli {
+        quiet: true,
+        verbose: false,
+        sidecar: None,
+        ..animation.frame.clone()
+    };
+    let bounds = frame.options.bounds;
+    let (paths, mut stream) = match &animation.output {
+        FrameOutput::Files { dir, paths } => {
+            std::fs::create_dir_all(dir).map_err(|e| MandelbrotError::writing(dir, e))?;
+            (paths.as_slice(), None)
+        }
+        FrameOutput::Video { path, fps } => {
+            let video = video::Video::start(path, bounds, *fps)
+                .map_err(|e| MandelbrotError::writing(path, e))?;
+            (&[][..], Some(Stream::Video(video)))
+        }
+        FrameOutput::Gif {
+            path,
+            fps,
+            loops,
+            colors,
+        } => {
+            let encoder = File::create(path)
+                .and_then(|file| gif::Encoder::new(BufWriter::new(file), bounds, *loops))
+                .map_err(|e| MandelbrotError::writing(path, e))?;
+            // GIF delays are in hundredths of a second, and many viewers
+            // slow down anything under 2.
+            let delay = (100.0 / *fps as f64).round().max(2.0) as u16;
+            let stream = Stream::Gif {
+                encoder,
+                colors: *colors,
+                delay,
+            };
+            (&[][..], Some(stream))
+        }
+    };
+    let count = animation.views.len();
+    for (i, view) in animation.views.iter().enumerate() {
+        if show {
+            eprint!("\rframe {}/{}", i + 1, count);
+        }
+        view.apply(&mut frame.options);
+        if let Some(&c) = animation.julia.get(i) {
+            frame.options.julia = Some(c);
+        }
+        let written = match &mut stream {
+            None => {
+                frame.output = paths[i].clone();
+                render(&frame)?;
+                Ok(())
+            }
+            Some(stream) => {
+                let pixels = Renderer::new(frame.options.clone()).render();
+                match stream {
+                    Stream::Video(video) => video.write_frame(&pixels),
+                    Stream::Gif {
+                        encoder,
+                        colors,
+                        delay,
+                    } => encoder.write_frame(&pixels, *colors, *delay),
+                }
+            }
+        };
+        written.map_err(|e| MandelbrotError::writing(&frame.output, e))?;
+    }
+    if show {
+        eprintln!();
     }
+    let finished = match stream {
+        None => Ok(()),
+        Some(Stream::Video(video)) => video.finish(),
+        Some(Stream::Gif { encoder, .. }) => encoder.finish().and_then(|mut w| w.flush()),
+    };
+    finished.map_err(|e| MandelbrotError::writing(&frame.output, e))
+}
+
+/// How many colors a palette's swatch shows in `palette list`.
+const SWATCH_COLORS: usize = 32;
+
+/// Prints the names of the built-in palettes, each followed by a swatch of
+/// its colors when printing to a terminal.
+fn list_palettes() {
+    let swatches = io::stdout().is_terminal();
+    let width = palette::NAMES
+        .iter()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0);
+    for name in palette::NAMES {
+        let mut line = format!("{:width$}", name, width = width);
+        if swatches {
+            let palette = Palette::named(name).unwrap();
+            line.push_str("  ");
+            for i in 0..SWATCH_COLORS {
+                let color = palette.at(i as f64 / (SWATCH_COLORS - 1) as f64);
+                line.push_str(&format!("\x1b[48;5;{}m ", ansi::color_index(color)));
+            }
+            line.push_str("\x1b[0m");
+        }
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Saves a bookmark to the store, or lists the saved and built-in ones.
+fn bookmark(command: BookmarkCommand) -> Result<(), MandelbrotError> {
+    let path = bookmarks::store_path()
+        .ok_or_else(|| MandelbrotError::Io("can't find the home directory".to_string()))?;
+    let mut saved = bookmarks::load(&path)?;
+    match command {
+        BookmarkCommand::Add(bookmark) => {
+            let name = bookmark.name.clone();
+            match saved.iter_mut().find(|b| b.name == name) {
+                Some(existing) => *existing = bookmark,
+                None => saved.push(bookmark),
+            }
+            bookmarks::save(&path, &saved)
+                .map_err(|e| MandelbrotError::writing(&path.to_string_lossy(), e))?;
+            eprintln!("saved '{}' to {}", name, path.display());
+        }
+        BookmarkCommand::List => {
+            let count = saved.len();
+            let all = bookmarks::all(saved);
+            let width = all.iter().map(|b| b.name.len()).max().unwrap_or(0);
+            for (i, bookmark) in all.iter().enumerate() {
+                let origin = if i < count { "" } else { "  (built in)" };
+                println!(
+                    "{:width$}  center {}  zoom {}{}",
+                    bookmark.name,
+                    bookmark.center,
+                    bookmark.zoom,
+                    origin,
+                    width = width
+                );
+            }
+        }
+    }
+    Ok(())
 }
 
 #[test]
-fn test_parse_pair() {
-    assert_eq!(parse_pair::<i32>("", ','), None);
-    assert_eq!(parse_pair::<i32>("10,", ','), None);
-    assert_eq!(parse_pair::<i32>(",10", ','), None);
-    assert_eq!(parse_pair::<i32>("10,20", ','), Some((10, 20)));
-    assert_eq!(parse_pair::<i32>("10,20xy", ','), None);
-    assert_eq!(parse_pair::<f64>("0.5x", 'x'), None);
-    assert_eq!(parse_pair::<f64>("0.5x1.5", 'x'), Some((0.5, 1.5)));
+fn test_render_from_sidecar() {
+    let dir = std::env::temp_dir();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    std::fs::write(
+        path("mandelbrot_test_from_sidecar.csv"),
+        "0,0,0,80\n1,255,200,0\n",
+    )
+    .unwrap();
+    let parse = |line: String| {
+        let args = line.split(' ').map(String::from).collect::<Vec<_>>();
+        cli::parse_args(&args).unwrap()
+    };
+    let pixels = |name: &str| {
+        let file = File::open(path(name)).unwrap();
+        let mut reader = png::Decoder::new(io::BufReader::new(file))
+            .read_info()
+            .unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        pixels
+    };
+    let Command::Render(original) = parse(format!(
+        "{} 48x32 -1.5,0.2 -1.2,-0.3 --stretch --flip --gradient {} -i 400 --aa 2 -q",
+        path("mandelbrot_test_from_sidecar.png"),
+        path("mandelbrot_test_from_sidecar.csv")
+    )) else {
+        panic!("the render didn't parse");
+    };
+    render(&original).unwrap();
+    let Command::Render(again) = parse(format!(
+        "{} --from-sidecar {} -q",
+        path("mandelbrot_test_from_sidecar-2.png"),
+        path("mandelbrot_test_from_sidecar.json")
+    )) else {
+        panic!("the render from the sidecar didn't parse");
+    };
+    render(&again).unwrap();
+    assert_eq!(
+        pixels("mandelbrot_test_from_sidecar.png"),
+        pixels("mandelbrot_test_from_sidecar-2.png")
+    );
+
+    let Command::Buddhabrot(original) = parse(format!(
+        "buddhabrot {} 32x24 -i 60 --seed 9 -q",
+        path("mandelbrot_test_from_sidecar-b.png")
+    )) else {
+        panic!("the buddhabrot didn't parse");
+    };
+    buddhabrot(&original).unwrap();
+    let Command::Buddhabrot(again) = parse(format!(
+        "buddhabrot {} --from-sidecar {} -q",
+        path("mandelbrot_test_from_sidecar-b2.png"),
+        path("mandelbrot_test_from_sidecar-b.json")
+    )) else {
+        panic!("the buddhabrot from the sidecar didn't parse");
+    };
+    buddhabrot(&again).unwrap();
+    assert_eq!(
+        pixels("mandelbrot_test_from_sidecar-b.png"),
+        pixels("mandelbrot_test_from_sidecar-b2.png")
+    );
 }
@@ -0,0 +1,274 @@
+//! Heightmap meshes: the escape values of an image as the elevation of a
+//! solid, for 3D printing, written as binary STL or as OBJ.
+//!
+//! Every pixel center is a vertex of the top surface, one unit from its
+//! neighbors, raised above a flat base by its escape value. The sides drop
+//! from the edges of the surface to the bottom, which is a fan of triangles
+//! around its middle, so the mesh is closed: every edge is shared by two
+//! triangles, facing out. Slicers scale it to size.
+//!
+//! Escape values are spread over `ln(1 + value)`, as they crowd together
+//! far from the set, with points in the set at the iteration limit, on top.
+//!
+//! The same relief makes textures for renderers and game engines: the
+//! heights themselves as a displacement map, and the slopes of the surface
+//! as a tangent-space normal map, with green up the image as OpenGL and
+//! Blender have it.
+
+use crate::{contour, Escape};
+use std::io::{self, Write};
+
+/// A mesh of triangles, each three indexes into `vertices`, counter
+/// clockwise seen from outside.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<[f32; 3]>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/// The elevation of every pixel of an image, from 0 for its lowest escape
+/// value to 1 for its highest, with points in the set at `limit`.
+pub fn heights(escapes: &[Option<Escape>], limit: u32, smooth: bool) -> Vec<f64> {
+    let values = contour::values(escapes, limit, smooth)
+        .into_iter()
+        .map(|value| value.max(0.0).ln_1p())
+        .collect::<Vec<_>>();
+    let low = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let high = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = high - low;
+    values
+        .iter()
+        .map(|value| match range > 0.0 {
+            true => (value - low) / range,
+            false => 0.0,
+        })
+        .collect()
+}
+
+/// The tangent-space normals of the surface whose top is `heights`, row by
+/// row over `bounds`, scaled by `scale` pixel widths, as RGB pixels: each
+/// axis from -1 at 0 to 1 at 255, with flat ground at (128, 128, 255).
+pub fn normal_map(heights: &[f64], bounds: (u32, u32), scale: f64) -> Vec<u8> {
+    let (width, height) = (bounds.0 as usize, bounds.1 as usize);
+    let at = |x: usize, y: usize| scale * heights[y * width + x];
+    // Slopes by central differences, one sided at the edges.
+    let slope = |low: (usize, usize), high: (usize, usize), span: usize| match span {
+        0 => 0.0,
+        span => (at(high.0, high.1) - at(low.0, low.1)) / span as f64,
+    };
+    let mut pixels = Vec::with_capacity(3 * width * height);
+    for y in 0..height {
+        let (up, down) = (y.saturating_sub(1), (y + 1).min(height - 1));
+        for x in 0..width {
+            let (left, right) = (x.saturating_sub(1), (x + 1).min(width - 1));
+            let dx = slope((left, y), (right, y), right - left);
+            // Rows run down the image, and green up it.
+            let dy = -slope((x, up), (x, down), down - up);
+            let length = (dx * dx + dy * dy + 1.0).sqrt();
+            let normal = [-dx / length, -dy / length, 1.0 / length];
+            pixels.extend(normal.map(|v| ((v + 1.0) * 127.5).round() as u8));
+        }
+    }
+    pixels
+}
+
+impl Mesh {
+    /// The solid whose top is `heights`, row by row over `bounds`, scaled
+    /// by `scale` above a base `base` thick. Image rows run towards -y, so
+    /// that the top looks like the image from above. Both sides of
+    /// `bounds` must be at least 2.
+    pub fn heightmap(heights: &[f64], bounds: (u32, u32), base: f64, scale: f64) -> Mesh {
+        let (width, height) = bounds;
+        assert!(width >= 2 && height >= 2, "a heightmap needs 2×2 pixels");
+        let top = |x: u32, y: u32| y * width + x;
+        let mut vertices = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let z = base + scale * heights[top(x, y) as usize];
+                [x as f32, (height - 1 - y) as f32, z as f32]
+            })
+            .collect::<Vec<_>>();
+        let mut triangles = Vec::new();
+        for y in 0..height - 1 {
+            for x in 0..width - 1 {
+                let (a, b) = (top(x, y), top(x + 1, y));
+                let (c, d) = (top(x + 1, y + 1), top(x, y + 1));
+                triangles.push([a, d, c]);
+                triangles.push([a, c, b]);
+            }
+        }
+
+        // The edge of the top, counter clockwise from above, starting at
+        // the bottom left corner of the image.
+        let edge = (0..width - 1)
+            .map(|x| (x, height - 1))
+            .chain((1..height).rev().map(|y| (width - 1, y)))
+            .chain((1..width).rev().map(|x| (x, 0)))
+            .chain((0..height - 1).map(|y| (0, y)))
+            .collect::<Vec<_>>();
+        let floor = vertices.len() as u32;
+        for &(x, y) in &edge {
+            let [x, y, _] = vertices[top(x, y) as usize];
+            vertices.push([x, y, 0.0]);
+        }
+        let middle = vertices.len() as u32;
+        vertices.push([(width - 1) as f32 / 2.0, (height - 1) as f32 / 2.0, 0.0]);
+        for i in 0..edge.len() {
+            let j = (i + 1) % edge.len();
+            let (p, q) = (top(edge[i].0, edge[i].1), top(edge[j].0, edge[j].1));
+            let (p_floor, q_floor) = (floor + i as u32, floor + j as u32);
+            triangles.push([p_floor, q_floor, q]);
+            triangles.push([p_floor, q, p]);
+            triangles.push([middle, q_floor, p_floor]);
+        }
+        Mesh {
+            vertices,
+            triangles,
+        }
+    }
+
+    /// The unit normal of a triangle, or zero if it has no area.
+    fn normal(&self, [a, b, c]: [u32; 3]) -> [f32; 3] {
+        let [a, b, c] = [a, b, c].map(|i| self.vertices[i as usize]);
+        let (u, v) = (
+            [0, 1, 2].map(|i| b[i] - a[i]),
+            [0, 1, 2].map(|i| c[i] - a[i]),
+        );
+        let n = [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ];
+        let length = n.iter().map(|v| v * v).sum::<f32>().sqrt();
+        match length > 0.0 {
+            true => n.map(|v| v / length),
+            false => [0.0; 3],
+        }
+    }
+
+    /// Writes the mesh as binary STL.
+    pub fn write_stl<W: Write>(&self, mut w: W) -> io::Result<()> {
+        // A header starting "solid" would pass for ASCII STL.
+        let mut header = [0; 80];
+        let title = b"mandelbrot heightmap";
+        header[..title.len()].copy_from_slice(title);
+        w.write_all(&header)?;
+        w.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+        for &triangle in &self.triangles {
+            let points = triangle.map(|i| self.vertices[i as usize]);
+            for point in std::iter::once(self.normal(triangle)).chain(points) {
+                for v in point {
+                    w.write_all(&v.to_le_bytes())?;
+                }
+            }
+            w.write_all(&[0, 0])?;
+        }
+        Ok(())
+    }
+
+    /// Writes the mesh as OBJ, with `text` as keyword and value pairs in
+    /// comments.
+    pub fn write_obj<W: Write>(&self, mut w: W, text: &[(&str, String)]) -> io::Result<()> {
+        for (keyword, value) in text {
+            writeln!(w, "# {}: {}", keyword, value)?;
+        }
+        writeln!(w, "o mandelbrot")?;
+        for [x, y, z] in &self.vertices {
+            writeln!(w, "v {} {} {}", x, y, z)?;
+        }
+        for [a, b, c] in &self.triangles {
+            writeln!(w, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_normal_map() {
+    // A ramp rising to the right by half a unit a pixel, on a flat row.
+    let heights = [0.0, 0.5, 1.0, 0.0, 0.5, 1.0];
+    let pixels = normal_map(&heights, (3, 2), 1.0);
+    assert_eq!(pixels.len(), 18);
+    assert!(pixels.chunks(3).all(|normal| normal == [70, 128, 242]));
+    // Rising up the image tilts the normal down it, green below 128.
+    let pixels = normal_map(&[1.0, 0.0], (1, 2), 1.0);
+    assert_eq!(pixels, [128, 37, 218, 128, 37, 218]);
+    assert_eq!(normal_map(&[0.3; 4], (2, 2), 5.0)[..3], [128, 128, 255]);
+}
+
+#[cfg(test)]
+fn volume(mesh: &Mesh) -> f64 {
+    mesh.triangles
+        .iter()
+        .map(|triangle| {
+            let [a, b, c] = triangle.map(|i| mesh.vertices[i as usize].map(f64::from));
+            (a[0] * (b[1] * c[2] - b[2] * c[1]) - a[1] * (b[0] * c[2] - b[2] * c[0])
+                + a[2] * (b[0] * c[1] - b[1] * c[0]))
+                / 6.0
+        })
+        .sum()
+}
+
+#[test]
+fn test_heightmap_closed() {
+    use std::collections::HashMap;
+    let heights = (0..5 * 4).map(|i| (i % 7) as f64 / 6.0).collect::<Vec<_>>();
+    let mesh = Mesh::heightmap(&heights, (5, 4), 1.0, 3.0);
+    assert_eq!(mesh.vertices.len(), 5 * 4 + 2 * (4 + 3) + 1);
+    assert_eq!(mesh.triangles.len(), 2 * 4 * 3 + 3 * 2 * (4 + 3));
+    // Closed and facing one way: every edge is crossed once each way.
+    let mut edges = HashMap::new();
+    for &[a, b, c] in &mesh.triangles {
+        for edge in [(a, b), (b, c), (c, a)] {
+            *edges.entry(edge).or_insert(0) += 1;
+        }
+    }
+    for (&(a, b), &count) in &edges {
+        assert_eq!(count, 1);
+        assert_eq!(edges.get(&(b, a)), Some(&1));
+    }
+    // Facing out: a flat top makes a box of positive volume.
+    let flat = Mesh::heightmap(&[0.5; 20], (5, 4), 1.0, 2.0);
+    assert!((volume(&flat) - 4.0 * 3.0 * 2.0).abs() < 1e-4);
+    assert!(volume(&mesh) > 4.0 * 3.0);
+    // The top of the first pixel is the top left corner.
+    assert_eq!(mesh.vertices[0], [0.0, 3.0, 1.0]);
+}
+
+#[test]
+fn test_write_stl() {
+    use crate::{RenderOptions, Renderer};
+    let options = RenderOptions {
+        bounds: (12, 8),
+        ..RenderOptions::default()
+    };
+    let escapes = Renderer::new(options.clone()).render_escapes();
+    let heights = heights(&escapes, options.max_iter, options.smooth);
+    assert!(heights.iter().all(|h| (0.0..=1.0).contains(h)));
+    let interior = escapes.iter().position(Option::is_none).unwrap();
+    assert_eq!(heights[interior], 1.0);
+    let mesh = Mesh::heightmap(&heights, options.bounds, 2.0, 5.0);
+
+    let mut bytes = Vec::new();
+    mesh.write_stl(&mut bytes).unwrap();
+    assert!(!bytes.starts_with(b"solid"));
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    assert_eq!(count, mesh.triangles.len());
+    assert_eq!(bytes.len(), 84 + 50 * count);
+    // The first triangle is on top, facing up more than not.
+    let float = |at: usize| f32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    assert!(float(84 + 8) > 0.0);
+    assert_eq!(float(84 + 12 + 8), mesh.vertices[0][2]);
+
+    let mut obj = Vec::new();
+    mesh.write_obj(&mut obj, &[("Software", "mandelbrot".to_string())])
+        .unwrap();
+    let obj = String::from_utf8(obj).unwrap();
+    assert!(obj.starts_with("# Software: mandelbrot\no mandelbrot\n"));
+    assert_eq!(
+        obj.lines().filter(|l| l.starts_with("v ")).count(),
+        mesh.vertices.len()
+    );
+    assert_eq!(obj.lines().filter(|l| l.starts_with("f ")).count(), count);
+    assert!(obj.lines().any(|l| l == "f 1 13 14"));
+}
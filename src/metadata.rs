@@ -0,0 +1,494 @@
+//! The render parameters written into PNG text chunks, so that an image
+//! describes the view it shows and how to render it again. They are also
+//! written to a JSON sidecar next to the image, for formats that have no
+//! room for them, as an object of the same keywords and values:
+//!
+//! ```json
+//! {
+//!   "Software": "mandelbrot 0.1.0",
+//!   "Size": "800x600",
+//!   "Upper left": "-2.5,1.25",
+//!   ...
+//! }
+//! ```
+
+use crate::{
+    buddhabrot::{Buddhabrot, Nebula},
+    palette, Adjustments, Algorithm, Bailout, Fixed, Fractal, Palette, Precision, RenderOptions,
+    Transfer,
+};
+use num::Complex;
+use std::io::{self, Read, Write};
+
+/// The `Software` entry of every image.
+pub const SOFTWARE: &str = concat!("mandelbrot ", env!("CARGO_PKG_VERSION"));
+
+/// Values longer than this, such as deep zoom coordinates, go in compressed
+/// `zTXt` chunks rather than `tEXt` ones.
+pub const COMPRESS_OVER: usize = 256;
+
+/// The keywords and values describing a render, in the order they are
+/// written. Corners and center are in the `RE,IM` form the command line
+/// takes, the corners to every digit, so that a render from them lands on
+/// the same pixels. `Zoom` is the magnification relative to a view 4 high,
+/// following Kalles Fraktaler. Options left at their defaults are left out;
+/// `Julia` is only written for Julia sets, and a palette without a name is
+/// written as the CSV `Gradient` it is. The corners of a view upside down
+/// are written the right way up, with `Flip` to turn them over.
+pub fn describe(options: &RenderOptions) -> Vec<(&'static str, String)> {
+    let mut text = describe_view(options);
+    text.push(("Max iterations", options.max_iter.to_string()));
+    text.push(("Fractal", options.fractal.name().to_string()));
+    if let Fractal::Multibrot(degree) = options.fractal {
+        text.push(("Exponent", degree.to_string()));
+    }
+    if let Fractal::Phoenix(p) = options.fractal {
+        text.push(("Phoenix", format!("{},{}", p.re, p.im)));
+    }
+    if let Fractal::Formula(formula) = &options.fractal {
+        text.push(("Formula", formula.text().to_string()));
+    }
+    if let Some(c) = options.julia {
+        text.push(("Julia", format!("{},{}", c.re, c.im)));
+    }
+    if let Some(polynomial) = &options.newton {
+        let coefficients = polynomial
+            .coefficients()
+            .iter()
+            .map(f64::to_string)
+            .collect::<Vec<_>>();
+        text.push(("Newton", coefficients.join(",")));
+    }
+    if options.bailout != Bailout::default() {
+        text.push(("Bailout", options.bailout.radius.to_string()));
+        text.push(("Bailout test", options.bailout.test.name().to_string()));
+    }
+    // The bits of perturbation follow from the view, so aren't written.
+    let precision = match options.precision {
+        Precision::Auto => None,
+        Precision::Single => Some("f32".to_string()),
+        Precision::Double => Some("f64".to_string()),
+        Precision::DoubleDouble => Some("dd".to_string()),
+        Precision::Perturbation(_) => Some("perturb".to_string()),
+        Precision::Arbitrary(bits) => Some(bits.to_string()),
+    };
+    if let Some(precision) = precision {
+        text.push(("Precision", precision));
+    }
+    if options.algorithm != Algorithm::Scan {
+        text.push(("Algorithm", options.algorithm.name().to_string()));
+    }
+    if !options.shortcuts.bulbs {
+        text.push(("Bulb check", false.to_string()));
+    }
+    if !options.shortcuts.periodicity {
+        text.push(("Periodicity check", false.to_string()));
+    }
+    describe_palette(options, &mut text);
+    text.push(("Smooth", options.smooth.to_string()));
+    text.push(("Coloring", options.coloring.name().to_string()));
+    if options.transfer != Transfer::Linear {
+        text.push(("Scale", options.transfer.to_string()));
+    }
+    if let Some(cycle) = options.palette_cycle {
+        text.push(("Palette cycle", cycle.to_string()));
+    }
+    text.push(("Interior", options.interior.name().to_string()));
+    if let Some(light) = options.shading {
+        text.push(("Shading", format!("{},{}", light.azimuth, light.elevation)));
+    }
+    text.push(("Antialias", options.antialias.to_string()));
+    if let Some(threshold) = options.adaptive {
+        text.push(("Adaptive", threshold.to_string()));
+    }
+    if let Some(dither) = options.dither {
+        text.push(("Dither", dither.name().to_string()));
+    }
+    describe_adjustments(&options.adjustments, &mut text);
+    text
+}
+
+/// The keywords and values describing a Buddhabrot, or a Nebulabrot with
+/// `nebula`, as `describe` does a render: its view, the orbits sampled and
+/// how they were colored.
+pub fn describe_buddhabrot(
+    options: &RenderOptions,
+    buddhabrot: &Buddhabrot,
+    nebula: Option<&Nebula>,
+) -> Vec<(&'static str, String)> {
+    let mut text = describe_view(options);
+    text.push(("Samples", buddhabrot.samples.to_string()));
+    text.push(("Min iterations", buddhabrot.min_iter.to_string()));
+    text.push(("Seed", buddhabrot.seed.to_string()));
+    match nebula {
+        // The limits stand in for the iterations, and the channels for the
+        // palette.
+        Some(nebula) => {
+            let [red, green, blue] = nebula.limits;
+            text.push(("Nebula", format!("{},{},{}", red, green, blue)));
+            if nebula.exposure != [1.0; 3] {
+                let [red, green, blue] = nebula.exposure;
+                text.push(("Exposure", format!("{},{},{}", red, green, blue)));
+            }
+        }
+        None => {
+            text.push(("Max iterations", buddhabrot.max_iter.to_string()));
+            describe_palette(options, &mut text);
+        }
+    }
+    describe_adjustments(&options.adjustments, &mut text);
+    text
+}
+
+/// The size and view of a render, with `Stretch` if its pixels aren't
+/// square.
+fn describe_view(options: &RenderOptions) -> Vec<(&'static str, String)> {
+    let (mut upper_left, mut lower_right) = options.exact_corners();
+    let flipped = upper_left.im < lower_right.im;
+    if flipped {
+        std::mem::swap(&mut upper_left.im, &mut lower_right.im);
+    }
+    // Enough digits to place the center to within a thousandth of a pixel.
+    let digits = (-(options.pixel_size() / 1000.0).log10()).ceil().max(0.0) as usize;
+    let rounded = |x: Fixed| match x.to_decimal(digits) {
+        decimal if decimal.contains('.') => decimal
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string(),
+        decimal => decimal,
+    };
+    let center_re = (&upper_left.re + &lower_right.re).scale(1, 2);
+    let center_im = (&upper_left.im + &lower_right.im).scale(1, 2);
+    let exact =
+        |z: &Complex<Fixed>| format!("{},{}", z.re.to_exact_decimal(), z.im.to_exact_decimal());
+    let height = options.pixel_size() * options.bounds.1 as f64;
+    let mut text = vec![
+        ("Software", SOFTWARE.to_string()),
+        ("Size", format!("{}x{}", options.bounds.0, options.bounds.1)),
+        ("Upper left", exact(&upper_left)),
+        ("Lower right", exact(&lower_right)),
+        (
+            "Center",
+            format!("{},{}", rounded(center_re), rounded(center_im)),
+        ),
+        ("Zoom", format!("{:e}", 4.0 / height)),
+    ];
+    if flipped {
+        text.push(("Flip", true.to_string()));
+    }
+    // Within a hundredth of a pixel across the image, as `--preserve-aspect`
+    // leaves them.
+    let width = (&lower_right.re - &upper_left.re).to_f64();
+    let height = (&upper_left.im - &lower_right.im).to_f64();
+    let (columns, rows) = (
+        options.bounds.0.max(1) as f64,
+        options.bounds.1.max(1) as f64,
+    );
+    let stretch = width / height / (columns / rows);
+    if (stretch - 1.0).abs() * columns.max(rows) >= 0.01 {
+        text.push(("Stretch", true.to_string()));
+    }
+    text
+}
+
+/// Adds the palette, by name if it has one and as a CSV `Gradient` if not,
+/// and the color space it is blended in.
+fn describe_palette(options: &RenderOptions, text: &mut Vec<(&'static str, String)>) {
+    match palette_name(&options.palette) {
+        Some(name) => text.push(("Palette", name.to_string())),
+        None => text.push(("Gradient", options.palette.to_csv())),
+    }
+    text.push(("Color space", options.palette.space().name().to_string()));
+}
+
+/// Adds the adjustments that aren't at their defaults.
+fn describe_adjustments(adjustments: &Adjustments, text: &mut Vec<(&'static str, String)>) {
+    let defaults = Adjustments::default();
+    for (keyword, value, default) in [
+        ("Exposure", adjustments.exposure, defaults.exposure),
+        ("Contrast", adjustments.contrast, defaults.contrast),
+        ("Brightness", adjustments.brightness, defaults.brightness),
+        ("Gamma", adjustments.gamma, defaults.gamma),
+    ] {
+        if value != default {
+            text.push((keyword, value.to_string()));
+        }
+    }
+}
+
+/// Reads back the text chunks of a PNG, compressed or not, as keyword and
+/// value pairs.
+pub fn read<R: Read>(r: R) -> Result<Vec<(String, String)>, png::DecodingError> {
+    let reader = png::Decoder::new(r).read_info()?;
+    let info = reader.info();
+    let mut text = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .collect::<Vec<_>>();
+    for chunk in &info.compressed_latin1_text {
+        text.push((chunk.keyword.clone(), chunk.get_text()?));
+    }
+    for chunk in &info.utf8_text {
+        text.push((chunk.keyword.clone(), chunk.get_text()?));
+    }
+    Ok(text)
+}
+
+/// Writes the keywords and values of `describe` as a sidecar.
+pub fn write_json<W: Write>(mut w: W, text: &[(&str, String)]) -> io::Result<()> {
+    writeln!(w, "{{")?;
+    for (i, (keyword, value)) in text.iter().enumerate() {
+        let comma = if i + 1 < text.len() { "," } else { "" };
+        writeln!(
+            w,
+            "  {}: {}{}",
+            json_string(keyword),
+            json_string(value),
+            comma
+        )?;
+    }
+    writeln!(w, "}}")
+}
+
+/// Reads back the keywords and values of a sidecar: a JSON object whose
+/// values are all strings.
+pub fn read_json(json: &str) -> Result<Vec<(String, String)>, String> {
+    let mut chars = json.chars().peekable();
+    let skip_space = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+    let expect = |chars: &mut std::iter::Peekable<std::str::Chars>, expected: char| {
+        skip_space(chars);
+        match chars.next() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{}', found '{}'", expected, c)),
+            None => Err(format!("expected '{}', found the end", expected)),
+        }
+    };
+    expect(&mut chars, '{')?;
+    let mut text = Vec::new();
+    skip_space(&mut chars);
+    if chars.next_if_eq(&'}').is_none() {
+        loop {
+            expect(&mut chars, '"')?;
+            let keyword = read_json_string(&mut chars)?;
+            expect(&mut chars, ':')?;
+            expect(&mut chars, '"')
+                .map_err(|_| format!("the value of \"{}\" must be a string", keyword))?;
+            let value = read_json_string(&mut chars)?;
+            text.push((keyword, value));
+            skip_space(&mut chars);
+            match chars.next() {
+                Some(',') => {}
+                Some('}') => break,
+                _ => return Err("expected ',' or '}'".to_string()),
+            }
+        }
+    }
+    skip_space(&mut chars);
+    match chars.next() {
+        None => Ok(text),
+        Some(_) => Err("unexpected text after the object".to_string()),
+    }
+}
+
+/// Reads the rest of a JSON string whose opening quote has been read.
+fn read_json_string(chars: &mut impl Iterator<Item = char>) -> Result<String, String> {
+    let mut string = String::new();
+    let hex = |chars: &mut dyn Iterator<Item = char>| {
+        let digits = chars.take(4).collect::<String>();
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .filter(|_| digits.len() == 4)
+            .ok_or_else(|| format!("invalid escape '\\u{}'", digits))
+    };
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(string),
+            '\\' => string.push(match chars.next().ok_or("unterminated string")? {
+                '"' => '"',
+                '\\' => '\\',
+                '/' => '/',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => {
+                    let mut code = hex(chars)?;
+                    // Characters beyond the first plane come as two halves.
+                    if (0xd800..0xdc00).contains(&code) {
+                        let low = match (chars.next(), chars.next()) {
+                            (Some('\\'), Some('u')) => hex(chars)?,
+                            _ => return Err("unpaired surrogate".to_string()),
+                        };
+                        code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00));
+                    }
+                    char::from_u32(code).ok_or("invalid character")?
+                }
+                c => return Err(format!("invalid escape '\\{}'", c)),
+            }),
+            c => string.push(c),
+        }
+    }
+}
+
+/// `s` as a JSON string literal.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn palette_name(palette: &Palette) -> Option<&'static str> {
+    palette::NAMES.iter().copied().find(|name| {
+        Palette::named(name)
+            .map(|named| named.in_space(palette.space()))
+            .as_ref()
+            == Some(palette)
+    })
+}
+
+#[test]
+fn test_describe() {
+    use crate::Fixed;
+    use num::Complex;
+
+    let options = RenderOptions {
+        bounds: (400, 300),
+        upper_left: Complex { re: -2.0, im: 1.5 },
+        lower_right: Complex { re: 2.0, im: -1.5 },
+        max_iter: 1000,
+        palette: Palette::named("fire").unwrap(),
+        ..RenderOptions::default()
+    };
+    let text = describe(&options);
+    let get = |text: &[(&str, String)], key: &str| {
+        text.iter().find(|(k, _)| *k == key).map(|(_, v)| v.clone())
+    };
+    assert_eq!(get(&text, "Size").as_deref(), Some("400x300"));
+    assert_eq!(get(&text, "Upper left").as_deref(), Some("-2,1.5"));
+    assert_eq!(get(&text, "Center").as_deref(), Some("0,0"));
+    assert_eq!(get(&text, "Zoom").as_deref(), Some("1.3333333333333333e0"));
+    assert_eq!(get(&text, "Max iterations").as_deref(), Some("1000"));
+    assert_eq!(get(&text, "Palette").as_deref(), Some("fire"));
+    let custom = describe(&RenderOptions {
+        palette: Palette::new(vec![(0.0, [1, 2, 3])], [0, 0, 0]),
+        ..options.clone()
+    });
+    assert_eq!(get(&custom, "Palette"), None);
+    assert_eq!(get(&text, "Flip"), None);
+    let flipped = describe(&RenderOptions {
+        upper_left: Complex { re: -2.0, im: -1.5 },
+        lower_right: Complex { re: 2.0, im: 1.5 },
+        ..options.clone()
+    });
+    assert_eq!(get(&flipped, "Upper left").as_deref(), Some("-2,1.5"));
+    assert_eq!(get(&flipped, "Flip").as_deref(), Some("true"));
+
+    // Exact corners keep more digits than f64 could.
+    let exact = |s: &str| Fixed::parse(s).unwrap();
+    let deep = describe(&RenderOptions {
+        bounds: (100, 100),
+        exact_corners: Some((
+            Complex {
+                re: exact("-0.74364388703715870475"),
+                im: exact("0.13182590420531197050"),
+            },
+            Complex {
+                re: exact("-0.74364388703715870375"),
+                im: exact("0.13182590420531196950"),
+            },
+        )),
+        ..options.clone()
+    });
+    assert_eq!(
+        get(&deep, "Upper left").as_deref(),
+        Some("-0.74364388703715870475,0.13182590420531197050")
+    );
+    assert_eq!(
+        get(&deep, "Center").as_deref(),
+        Some("-0.74364388703715870425,0.13182590420531197")
+    );
+    assert_eq!(get(&deep, "Stretch"), None);
+
+    // Options away from their defaults are all written.
+    let gradient = Palette::new(vec![(0.0, [1, 2, 3]), (0.75, [4, 5, 6])], [7, 8, 9]);
+    let custom = describe(&RenderOptions {
+        bounds: (400, 100),
+        palette: gradient.clone(),
+        precision: Precision::Perturbation(120),
+        algorithm: Algorithm::BorderTrace,
+        shortcuts: crate::Shortcuts {
+            bulbs: false,
+            ..crate::Shortcuts::default()
+        },
+        ..options.clone()
+    });
+    assert_eq!(get(&custom, "Palette"), None);
+    let csv = get(&custom, "Gradient").unwrap();
+    assert_eq!(crate::gradient::parse_csv(&csv), Ok(gradient));
+    assert_eq!(get(&custom, "Stretch").as_deref(), Some("true"));
+    assert_eq!(get(&custom, "Precision").as_deref(), Some("perturb"));
+    assert_eq!(get(&custom, "Algorithm").as_deref(), Some("border-trace"));
+    assert_eq!(get(&custom, "Bulb check").as_deref(), Some("false"));
+    assert_eq!(get(&custom, "Periodicity check"), None);
+
+    let buddhabrot = Buddhabrot {
+        bounds: (400, 300),
+        upper_left: options.upper_left,
+        lower_right: options.lower_right,
+        samples: 5000,
+        min_iter: 20,
+        max_iter: 1000,
+        seed: 7,
+        threads: 1,
+    };
+    let text = describe_buddhabrot(&options, &buddhabrot, None);
+    assert_eq!(get(&text, "Seed").as_deref(), Some("7"));
+    assert_eq!(get(&text, "Samples").as_deref(), Some("5000"));
+    assert_eq!(get(&text, "Palette").as_deref(), Some("fire"));
+    assert_eq!(get(&text, "Fractal"), None);
+    let nebula = Nebula {
+        limits: [50, 500, 5000],
+        exposure: [1.0, 2.0, 1.0],
+    };
+    let text = describe_buddhabrot(&options, &buddhabrot, Some(&nebula));
+    assert_eq!(get(&text, "Nebula").as_deref(), Some("50,500,5000"));
+    assert_eq!(get(&text, "Exposure").as_deref(), Some("1,2,1"));
+    assert_eq!(get(&text, "Max iterations"), None);
+    assert_eq!(get(&text, "Palette"), None);
+}
+
+#[test]
+fn test_json() {
+    let text = [
+        ("Software", SOFTWARE.to_string()),
+        ("Formula", "z^2 + c \"quoted\" \\ back\nslash".to_string()),
+    ];
+    let mut json = Vec::new();
+    write_json(&mut json, &text).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.starts_with("{\n  \"Software\": \"mandelbrot "));
+    let read = read_json(&json).unwrap();
+    assert_eq!(read.len(), 2);
+    assert_eq!((read[1].0.as_str(), &read[1].1), (text[1].0, &text[1].1));
+    assert_eq!(read_json(" { } ").unwrap(), []);
+    assert_eq!(
+        read_json(r#"{"a": "\u00e9\ud83d\ude00\/"}"#).unwrap(),
+        [("a".to_string(), "\u{e9}\u{1f600}/".to_string())]
+    );
+    assert!(read_json(r#"{"a": 1}"#).is_err());
+    assert!(read_json(r#"{"a": "b""#).is_err());
+    assert!(read_json(r#"{"a": "b"} x"#).is_err());
+    assert!(read_json(r#"{"a": "\ud83d"}"#).is_err());
+}
@@ -0,0 +1,115 @@
+//! Netpbm images: PPM for color and PGM for grayscale, in either the binary
+//! or the plain text variant. They have no compression to speak of, which
+//! makes them the cheapest way to hand a render to another program.
+
+use std::io::{self, Write};
+
+/// Encodes an RGB pixel buffer as a PPM.
+pub fn encode_ppm<W: Write>(
+    mut w: W,
+    pixels: &[u8],
+    bounds: (u32, u32),
+    plain: bool,
+) -> io::Result<()> {
+    let magic = if plain { "P3" } else { "P6" };
+    write!(w, "{}\n{} {}\n255\n", magic, bounds.0, bounds.1)?;
+    if plain {
+        write_plain(
+            &mut w,
+            pixels.iter().map(|&p| p as u16),
+            3 * bounds.0 as usize,
+        )
+    } else {
+        w.write_all(pixels)
+    }
+}
+
+/// Encodes grayscale samples up to `max` as a PGM. Samples take two bytes
+/// each in the binary variant once `max` is over 255.
+pub fn encode_pgm<W: Write>(
+    mut w: W,
+    samples: &[u16],
+    max: u16,
+    bounds: (u32, u32),
+    plain: bool,
+) -> io::Result<()> {
+    let magic = if plain { "P2" } else { "P5" };
+    write!(w, "{}\n{} {}\n{}\n", magic, bounds.0, bounds.1, max)?;
+    if plain {
+        write_plain(&mut w, samples.iter().copied(), bounds.0 as usize)
+    } else if max > 255 {
+        let bytes = samples
+            .iter()
+            .flat_map(|sample| sample.to_be_bytes())
+            .collect::<Vec<_>>();
+        w.write_all(&bytes)
+    } else {
+        let bytes = samples
+            .iter()
+            .map(|&sample| sample as u8)
+            .collect::<Vec<_>>();
+        w.write_all(&bytes)
+    }
+}
+
+/// Writes values as decimal text, one image row per line.
+fn write_plain<W: Write>(
+    w: &mut W,
+    values: impl Iterator<Item = u16>,
+    per_line: usize,
+) -> io::Result<()> {
+    for (i, value) in values.enumerate() {
+        let separator = if (i + 1) % per_line.max(1) == 0 {
+            '\n'
+        } else {
+            ' '
+        };
+        write!(w, "{}{}", value, separator)?;
+    }
+    Ok(())
+}
+
+/// The luma of each pixel of an RGB buffer, for writing colored renders as
+/// grayscale.
+pub fn luma(pixels: &[u8]) -> Vec<u16> {
+    pixels
+        .chunks(3)
+        .map(|p| (0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64).round() as u16)
+        .collect()
+}
+
+#[test]
+fn test_encode_ppm() {
+    let pixels = [255, 0, 0, 0, 255, 0, 0, 0, 255, 10, 20, 30];
+    let mut binary = Vec::new();
+    encode_ppm(&mut binary, &pixels, (2, 2), false).unwrap();
+    assert_eq!(&binary[..11], b"P6\n2 2\n255\n");
+    assert_eq!(&binary[11..], &pixels);
+    let mut plain = Vec::new();
+    encode_ppm(&mut plain, &pixels, (2, 2), true).unwrap();
+    assert_eq!(
+        String::from_utf8(plain).unwrap(),
+        "P3\n2 2\n255\n255 0 0 0 255 0\n0 0 255 10 20 30\n"
+    );
+}
+
+#[test]
+fn test_encode_pgm() {
+    let mut wide = Vec::new();
+    encode_pgm(&mut wide, &[1, 65535, 256], 65535, (3, 1), false).unwrap();
+    assert_eq!(&wide[..13], b"P5\n3 1\n65535\n");
+    assert_eq!(&wide[13..], &[0, 1, 255, 255, 1, 0]);
+    let mut narrow = Vec::new();
+    encode_pgm(
+        &mut narrow,
+        &luma(&[255, 255, 255, 0, 0, 0]),
+        255,
+        (2, 1),
+        false,
+    )
+    .unwrap();
+    assert_eq!(&narrow[11..], &[255, 0]);
+    let mut plain = Vec::new();
+    encode_pgm(&mut plain, &[7, 8], 255, (1, 2), true).unwrap();
+    assert_eq!(String::from_utf8(plain).unwrap(), "P2\n1 2\n255\n7\n8\n");
+}
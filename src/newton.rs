@@ -0,0 +1,278 @@
+//! Newton fractals. Instead of iterating a formula until it escapes, each
+//! point is taken as a first guess at a root of a polynomial and improved
+//! by Newton's method, `z - p(z) / p'(z)`, until it lands on one. Points
+//! are colored by the root they converge to, each root taking its own spot
+//! on the palette, and darkened the longer they take, so that the basins of
+//! the roots show with their fractal borders, where convergence is slow.
+//!
+//! Converging points are stored as `Escape`s, counting the steps taken to
+//! come within `TOLERANCE` of a root and ending at the `z` that did, so
+//! that they go through the same rendering as escape times; points that
+//! never converge are left as `None`, like points in the set.
+
+use crate::{pixel_to_point, Escape, Palette};
+use num::Complex;
+
+/// How close to a root a point has to come to count as converged.
+pub const TOLERANCE: f64 = 1e-6;
+
+/// How much each step of Newton's method darkens a point: a point that
+/// takes `n` steps keeps `e^(-n DARKENING)` of its root's color.
+const DARKENING: f64 = 0.05;
+
+/// The steps `Polynomial::new` takes at most to find the roots.
+const ROOT_STEPS: u32 = 1000;
+
+/// A polynomial with real coefficients, together with its roots.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polynomial {
+    /// The coefficients, highest power first, without leading zeros.
+    coefficients: Vec<f64>,
+    roots: Vec<Complex<f64>>,
+}
+
+impl Default for Polynomial {
+    /// `z³ - 1`, whose roots are the cube roots of unity.
+    fn default() -> Polynomial {
+        Polynomial::new(&[1.0, 0.0, 0.0, -1.0]).unwrap()
+    }
+}
+
+impl Polynomial {
+    /// The polynomial with `coefficients`, highest power first, or `None`
+    /// if it is constant or a coefficient isn't finite.
+    pub fn new(coefficients: &[f64]) -> Option<Polynomial> {
+        if coefficients.iter().any(|c| !c.is_finite()) {
+            return None;
+        }
+        let first = coefficients.iter().position(|&c| c != 0.0)?;
+        let coefficients = coefficients[first..].to_vec();
+        if coefficients.len() < 2 {
+            return None;
+        }
+        let roots = roots(&coefficients);
+        Some(Polynomial {
+            coefficients,
+            roots,
+        })
+    }
+
+    /// The coefficients, highest power first.
+    pub fn coefficients(&self) -> &[f64] {
+        &self.coefficients
+    }
+
+    /// The complex roots, as many as the degree, with repeated roots
+    /// repeated.
+    pub fn roots(&self) -> &[Complex<f64>] {
+        &self.roots
+    }
+
+    /// `p(z)` and `p'(z)`, by Horner's method.
+    fn evaluate(&self, z: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+        let mut value = Complex::new(0.0, 0.0);
+        let mut slope = Complex::new(0.0, 0.0);
+        for &c in &self.coefficients {
+            slope = slope * z + value;
+            value = value * z + c;
+        }
+        (value, slope)
+    }
+
+    /// Runs Newton's method from `z` for at most `limit` steps. Returns how
+    /// many steps it took to come within `TOLERANCE` of a root, or `None`
+    /// if it didn't or ran into a critical point.
+    pub fn converge(&self, mut z: Complex<f64>, limit: u32) -> Option<Escape> {
+        for iterations in 0..limit {
+            if self
+                .roots
+                .iter()
+                .any(|&root| (z - root).norm_sqr() < TOLERANCE * TOLERANCE)
+            {
+                return Some(Escape {
+                    iterations,
+                    z,
+                    derivative: None,
+                    stripe: None,
+                });
+            }
+            let (value, slope) = self.evaluate(z);
+            if slope.norm_sqr() == 0.0 {
+                return None;
+            }
+            z -= value / slope;
+        }
+        None
+    }
+
+    /// The index of the root nearest to `z`, and how far it is.
+    fn nearest_root(&self, z: Complex<f64>) -> (usize, f64) {
+        self.roots
+            .iter()
+            .map(|&root| (z - root).norm())
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0, 0.0))
+    }
+
+    /// The step count of a point that converged, continued between whole
+    /// steps with `smooth`. Near a simple root each step squares the
+    /// distance to it, so `log2(ln d / ln TOLERANCE)` of the final distance
+    /// `d` is how far past the tolerance the last step went.
+    pub fn steps(&self, escape: &Escape, smooth: bool) -> f64 {
+        let n = escape.iterations as f64;
+        if !smooth || escape.iterations == 0 {
+            return n;
+        }
+        let (_, distance) = self.nearest_root(escape.z);
+        let past = (distance.ln() / TOLERANCE.ln()).log2();
+        n - past.clamp(0.0, 1.0)
+    }
+
+    /// The color of a point that converged to `escape`, or of one that
+    /// didn't for `None`: its root's spot on `palette`, darkened by the
+    /// steps it took.
+    pub fn color(&self, escape: Option<Escape>, palette: &Palette, smooth: bool) -> [u8; 3] {
+        let Some(escape) = escape else {
+            return palette.color_at(None);
+        };
+        let (root, _) = self.nearest_root(escape.z);
+        let position = (root as f64 + 0.5) / self.roots.len() as f64;
+        let brightness = (-DARKENING * self.steps(&escape, smooth)).exp();
+        palette
+            .at(position)
+            .map(|channel| (channel as f64 * brightness).round() as u8)
+    }
+}
+
+/// The roots of the polynomial with `coefficients`, by the Durand–Kerner
+/// method: every root is improved at once, each as if the others were
+/// already right.
+fn roots(coefficients: &[f64]) -> Vec<Complex<f64>> {
+    let lead = coefficients[0];
+    let monic = coefficients.iter().map(|c| c / lead).collect::<Vec<_>>();
+    let value = |z: Complex<f64>| {
+        monic
+            .iter()
+            .fold(Complex::new(0.0, 0.0), |value, &c| value * z + c)
+    };
+    // Powers of a number that is neither real nor on the unit circle keep
+    // the first guesses apart.
+    let seed = Complex::new(0.4, 0.9);
+    let mut roots = (0..monic.len() - 1)
+        .map(|k| seed.powu(k as u32))
+        .collect::<Vec<_>>();
+    for _ in 0..ROOT_STEPS {
+        let mut moved = 0f64;
+        for i in 0..roots.len() {
+            let others = (0..roots.len())
+                .filter(|&j| j != i)
+                .fold(Complex::new(1.0, 0.0), |product, j| {
+                    product * (roots[i] - roots[j])
+                });
+            if others.norm_sqr() == 0.0 {
+                continue;
+            }
+            let step = value(roots[i]) / others;
+            roots[i] -= step;
+            moved = moved.max(step.norm());
+        }
+        if moved < 1e-15 {
+            break;
+        }
+    }
+    roots
+}
+
+/// Runs Newton's method for `polynomial` from every point of a rectangle,
+/// into `escapes`, which holds `bounds.0 * bounds.1` values in row-major
+/// order, as `crate::render` does for escape times.
+pub fn render(
+    escapes: &mut [Option<Escape>],
+    bounds: (u32, u32),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+    polynomial: &Polynomial,
+    limit: u32,
+) {
+    for (row, escapes) in escapes
+        .chunks_mut(bounds.0.max(1) as usize)
+        .take(bounds.1 as usize)
+        .enumerate()
+    {
+        for (column, escape) in escapes.iter_mut().enumerate() {
+            let pixel = (column as u32, row as u32);
+            let point = pixel_to_point(bounds, pixel, upper_left, lower_right);
+            *escape = polynomial.converge(point, limit);
+        }
+    }
+}
+
+#[test]
+fn test_roots() {
+    let cubic = Polynomial::default();
+    assert_eq!(cubic.roots().len(), 3);
+    for k in 0..3 {
+        let root = Complex::from_polar(1.0, 2.0 * std::f64::consts::PI * k as f64 / 3.0);
+        assert!(
+            cubic.roots().iter().any(|&r| (r - root).norm() < 1e-12),
+            "{:?}",
+            cubic.roots()
+        );
+    }
+    // (z - 2)(z + 3) with leading zeros.
+    let quadratic = Polynomial::new(&[0.0, 2.0, 2.0, -12.0]).unwrap();
+    assert_eq!(quadratic.coefficients(), &[2.0, 2.0, -12.0]);
+    let mut roots = quadratic.roots().iter().map(|r| r.re).collect::<Vec<_>>();
+    roots.sort_by(f64::total_cmp);
+    assert!((roots[0] + 3.0).abs() < 1e-12 && (roots[1] - 2.0).abs() < 1e-12);
+
+    assert_eq!(Polynomial::new(&[0.0, 5.0]), None);
+    assert_eq!(Polynomial::new(&[1.0, f64::NAN]), None);
+}
+
+#[test]
+fn test_converge() {
+    let cubic = Polynomial::default();
+    // Points near a root converge to it, quickly.
+    let escape = cubic.converge(Complex::new(2.0, 0.1), 100).unwrap();
+    assert!((escape.z - Complex::new(1.0, 0.0)).norm() < TOLERANCE);
+    assert!(escape.iterations < 10);
+    let escape = cubic.converge(Complex::new(-1.0, -1.5), 100).unwrap();
+    assert_eq!(
+        cubic.nearest_root(escape.z).0,
+        cubic.nearest_root(Complex::new(-0.5, -0.87)).0
+    );
+    // 0 is a critical point, where the method can't take a step.
+    assert_eq!(cubic.converge(Complex::new(0.0, 0.0), 100), None);
+    // A root converges at once.
+    assert_eq!(
+        cubic
+            .converge(Complex::new(1.0, 0.0), 100)
+            .unwrap()
+            .iterations,
+        0
+    );
+}
+
+#[test]
+fn test_color() {
+    let cubic = Polynomial::default();
+    let palette = Palette::named("grayscale").unwrap();
+    assert_eq!(cubic.color(None, &palette, true), palette.color_at(None));
+    // Slower points are darker.
+    let fast = cubic.converge(Complex::new(1.2, 0.0), 100);
+    let slow = cubic.converge(Complex::new(-0.6, 0.01), 100);
+    let (fast_root, _) = cubic.nearest_root(fast.unwrap().z);
+    let (slow_root, _) = cubic.nearest_root(slow.unwrap().z);
+    assert!(fast.unwrap().iterations < slow.unwrap().iterations);
+    let lightness = |escape, root: usize| {
+        let full = palette.at((root as f64 + 0.5) / 3.0)[0] as f64;
+        cubic.color(escape, &palette, true)[0] as f64 / full
+    };
+    assert!(lightness(fast, fast_root) > lightness(slow, slow_root));
+    // Smoothing keeps within the last whole step.
+    let escape = fast.unwrap();
+    let steps = cubic.steps(&escape, true);
+    assert!(steps <= escape.iterations as f64 && steps >= escape.iterations as f64 - 1.0);
+}
@@ -0,0 +1,92 @@
+//! Hooks for frontends that show a render as it goes, such as a window
+//! drawing rows as they come in, which are told of each piece as it is
+//! done rather than polling `Progress`. Give one to a renderer with
+//! `Renderer::with_observer`.
+
+use crate::Escape;
+#[cfg(test)]
+use crate::{tiled, RenderOptions, Renderer};
+use std::ops::Range;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+
+/// Told of the pieces of a render as they are done. Every method does
+/// nothing unless implemented, and is called from whichever thread did the
+/// piece, so it should be quick.
+pub trait RenderObserver: Send + Sync {
+    /// Row `row` of the image has been scanned, to `escapes`. Rows come in
+    /// the order threads finish them, not from the top, and with shading
+    /// the rows at the edges of `tiled::encode_png`'s bands come twice, as
+    /// they are rendered twice. Boundary tracing and antialiasing don't go
+    /// row by row, and report none.
+    fn on_row_complete(&self, row: u32, escapes: &[Option<Escape>]) {
+        let _ = (row, escapes);
+    }
+
+    /// A band of `rows`, rendered by `Renderer::rows` or
+    /// `tiled::encode_png`, has been colored to `pixels`, three bytes each
+    /// like `Renderer::render`. Bands come from the top, in order.
+    fn on_tile_complete(&self, rows: Range<u32>, pixels: &[u8]) {
+        let _ = (rows, pixels);
+    }
+
+    /// The render is over, with `rows` rows done from the top: all of them,
+    /// unless it was cancelled.
+    fn on_finish(&self, rows: u32) {
+        let _ = rows;
+    }
+}
+
+/// Writes down everything it is told.
+#[cfg(test)]
+#[derive(Default)]
+struct Recorder {
+    rows: Mutex<Vec<(u32, Vec<Option<Escape>>)>>,
+    tiles: Mutex<Vec<(Range<u32>, usize)>>,
+    finished: Mutex<Vec<u32>>,
+}
+
+#[cfg(test)]
+impl RenderObserver for Recorder {
+    fn on_row_complete(&self, row: u32, escapes: &[Option<Escape>]) {
+        self.rows.lock().unwrap().push((row, escapes.to_vec()));
+    }
+
+    fn on_tile_complete(&self, rows: Range<u32>, pixels: &[u8]) {
+        self.tiles.lock().unwrap().push((rows, pixels.len()));
+    }
+
+    fn on_finish(&self, rows: u32) {
+        self.finished.lock().unwrap().push(rows);
+    }
+}
+
+#[test]
+fn test_observer() {
+    let options = RenderOptions {
+        bounds: (8, 6),
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let recorder = Arc::new(Recorder::default());
+    let renderer = Renderer::new(options).with_observer(recorder.clone());
+    let escapes = renderer.render_escapes();
+    let mut rows = std::mem::take(&mut *recorder.rows.lock().unwrap());
+    rows.sort_by_key(|(row, _)| *row);
+    assert_eq!(rows.len(), 6);
+    for ((row, escapes), (i, expected)) in rows.iter().zip(escapes.chunks(8).enumerate()) {
+        assert_eq!((*row as usize, &escapes[..]), (i, expected));
+    }
+    assert_eq!(*recorder.finished.lock().unwrap(), [6]);
+    assert!(recorder.tiles.lock().unwrap().is_empty());
+    let mut bytes = Vec::new();
+    tiled::encode_png(&mut bytes, &renderer, 4, &[]).unwrap();
+    assert_eq!(
+        *recorder.tiles.lock().unwrap(),
+        [(0..4, 8 * 4 * 3), (4..6, 8 * 2 * 3)]
+    );
+    assert_eq!(*recorder.finished.lock().unwrap(), [6, 6]);
+    renderer.progress().cancel();
+    renderer.render();
+    assert_eq!(*recorder.finished.lock().unwrap(), [6, 6, 0]);
+}
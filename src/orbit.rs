@@ -0,0 +1,73 @@
+//! Statistics gathered over the orbits of escaping points, for colorings
+//! that need more than the escape time and final `z`. The escape time
+//! kernels don't gather them, to keep renders that don't need them as fast
+//! as they are; the points that escaped are iterated again instead, the
+//! same number of times, in `f64`, with each step handed to an
+//! `OrbitStatistic`.
+
+use crate::{
+    distance::Derivative, fractal::State, stripe::StripeAverage, Coloring, Escape, Fractal,
+};
+use num::Complex;
+
+/// Something accumulated step by step over the orbit of an escaping point.
+pub trait OrbitStatistic {
+    /// A fresh statistic, for `fractal` or, with `julia`, for one of its
+    /// Julia sets, where each point is where the orbit starts.
+    fn start(fractal: &Fractal, julia: bool) -> Self;
+
+    /// Takes one step of the orbit, from `z` to `next`.
+    fn step(&mut self, z: Complex<f64>, next: Complex<f64>);
+
+    /// Stores the result in `escape`, the escape of the orbit.
+    fn finish(self, escape: &mut Escape);
+}
+
+/// Gathers the statistics `coloring` needs, if any, for every escaped point
+/// in `escapes`, the escape times of `points` under `fractal`.
+pub fn add_statistics(
+    points: &[Complex<f64>],
+    fractal: &Fractal,
+    julia: Option<Complex<f64>>,
+    coloring: Coloring,
+    escapes: &mut [Option<Escape>],
+) {
+    match coloring {
+        Coloring::EscapeTime | Coloring::Histogram | Coloring::Grayscale => {}
+        Coloring::Distance => add::<Derivative>(points, fractal, julia, escapes),
+        Coloring::StripeAverage => add::<StripeAverage>(points, fractal, julia, escapes),
+    }
+}
+
+/// Follows the orbit of each escaped point again to gather an `S`.
+pub fn add<S: OrbitStatistic>(
+    points: &[Complex<f64>],
+    fractal: &Fractal,
+    julia: Option<Complex<f64>>,
+    escapes: &mut [Option<Escape>],
+) {
+    for (&point, escape) in points.iter().zip(escapes) {
+        if let Some(escape) = escape {
+            follow::<S>(point, fractal, julia, escape.iterations).finish(escape);
+        }
+    }
+}
+
+/// An `S` gathered over `iterations` steps of the orbit of `point` under
+/// `fractal`.
+pub fn follow<S: OrbitStatistic>(
+    point: Complex<f64>,
+    fractal: &Fractal,
+    julia: Option<Complex<f64>>,
+    iterations: u32,
+) -> S {
+    let (z, c) = fractal.start(point, julia);
+    let mut state = State::new(z);
+    let mut statistic = S::start(fractal, julia.is_some());
+    for _ in 0..iterations {
+        let next = fractal.step(state, c);
+        statistic.step(state.z, next.z);
+        state = next;
+    }
+    statistic
+}
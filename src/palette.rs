@@ -0,0 +1,422 @@
+use std::f64::consts::PI;
+
+/// A color gradient used to turn escape times into RGB pixels. The gradient
+/// is a list of stops, each a position in `0.0..=1.0` and the color at that
+/// position; colors between stops are linearly interpolated in `space`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    stops: Vec<(f64, [u8; 3])>,
+    interior: [u8; 3],
+    space: ColorSpace,
+}
+
+/// Names accepted by `Palette::named`, in the order they are listed in help.
+pub const NAMES: &[&str] = &[
+    "grayscale",
+    "fire",
+    "ocean",
+    "classic",
+    "viridis",
+    "magma",
+    "inferno",
+    "sepia",
+];
+
+/// A position on a gradient and the color there.
+type Stop = (f64, [u8; 3]);
+
+/// The stops of the built-in palettes, by name. Viridis, magma and inferno
+/// are matplotlib's perceptually uniform maps, sampled at ninths, and
+/// classic is Ultra Fractal's default blue and gold, which wraps around.
+const STOPS: &[(&str, &[Stop])] = &[
+    ("grayscale", &[(0.0, [255, 255, 255]), (1.0, [0, 0, 0])]),
+    (
+        "fire",
+        &[
+            (0.0, [0, 0, 0]),
+            (0.3, [160, 16, 0]),
+            (0.6, [255, 140, 0]),
+            (0.85, [255, 230, 60]),
+            (1.0, [255, 255, 255]),
+        ],
+    ),
+    (
+        "ocean",
+        &[
+            (0.0, [0, 8, 32]),
+            (0.35, [0, 60, 130]),
+            (0.7, [0, 170, 200]),
+            (1.0, [220, 255, 255]),
+        ],
+    ),
+    (
+        "classic",
+        &[
+            (0.0, [0, 7, 100]),
+            (0.16, [32, 107, 203]),
+            (0.42, [237, 255, 255]),
+            (0.6425, [255, 170, 0]),
+            (0.8575, [0, 2, 0]),
+            (1.0, [0, 7, 100]),
+        ],
+    ),
+    (
+        "viridis",
+        &[
+            (0.0, [68, 1, 84]),
+            (0.125, [71, 45, 123]),
+            (0.25, [59, 82, 139]),
+            (0.375, [44, 114, 142]),
+            (0.5, [33, 144, 140]),
+            (0.625, [39, 173, 129]),
+            (0.75, [93, 200, 99]),
+            (0.875, [170, 220, 50]),
+            (1.0, [253, 231, 37]),
+        ],
+    ),
+    (
+        "magma",
+        &[
+            (0.0, [0, 0, 4]),
+            (0.125, [29, 17, 71]),
+            (0.25, [81, 18, 124]),
+            (0.375, [130, 38, 129]),
+            (0.5, [182, 54, 121]),
+            (0.625, [230, 81, 100]),
+            (0.75, [251, 136, 97]),
+            (0.875, [254, 194, 135]),
+            (1.0, [252, 253, 191]),
+        ],
+    ),
+    (
+        "inferno",
+        &[
+            (0.0, [0, 0, 4]),
+            (0.125, [27, 12, 66]),
+            (0.25, [75, 12, 107]),
+            (0.375, [120, 28, 109]),
+            (0.5, [165, 44, 96]),
+            (0.625, [207, 68, 70]),
+            (0.75, [237, 105, 37]),
+            (0.875, [251, 155, 6]),
+            (1.0, [252, 255, 164]),
+        ],
+    ),
+    (
+        "sepia",
+        &[
+            (0.0, [28, 16, 8]),
+            (0.35, [112, 66, 20]),
+            (0.7, [196, 160, 110]),
+            (1.0, [250, 240, 220]),
+        ],
+    ),
+];
+
+/// The color spaces gradients are interpolated in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// The stored sRGB values, as most image editors blend them.
+    Srgb,
+    /// Linear light, which keeps blends between saturated colors from
+    /// darkening in the middle.
+    Linear,
+    /// Oklab, whose lightness and hues change evenly to the eye, so blends
+    /// neither muddy nor brighten in the middle.
+    Oklab,
+    /// Oklab's lightness, chroma and hue, blending hues the short way
+    /// round the color wheel, which keeps blends between far apart hues
+    /// saturated.
+    Lch,
+}
+
+/// Names accepted by `ColorSpace::named`, in the order they are listed in
+/// help.
+pub const SPACE_NAMES: &[&str] = &["srgb", "linear", "oklab", "lch"];
+
+impl ColorSpace {
+    /// Looks a color space up by name, as given to `--color-space`.
+    pub fn named(name: &str) -> Option<ColorSpace> {
+        match name {
+            "srgb" => Some(ColorSpace::Srgb),
+            "linear" => Some(ColorSpace::Linear),
+            "oklab" => Some(ColorSpace::Oklab),
+            "lch" => Some(ColorSpace::Lch),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "srgb",
+            ColorSpace::Linear => "linear",
+            ColorSpace::Oklab => "oklab",
+            ColorSpace::Lch => "lch",
+        }
+    }
+
+    /// Blends `c0` towards `c1` by `f`, before it is rounded to bytes.
+    fn mix(self, c0: [u8; 3], c1: [u8; 3], f: f64) -> [f64; 3] {
+        let lerp = |a: f64, b: f64| a + (b - a) * f;
+        match self {
+            ColorSpace::Srgb => std::array::from_fn(|k| lerp(c0[k] as f64, c1[k] as f64)),
+            ColorSpace::Linear => std::array::from_fn(|k| {
+                encode_srgb_exact(lerp(decode_srgb(c0[k]), decode_srgb(c1[k])))
+            }),
+            ColorSpace::Oklab => {
+                let (lab0, lab1) = (to_oklab(c0), to_oklab(c1));
+                from_oklab(std::array::from_fn(|k| lerp(lab0[k], lab1[k])))
+            }
+            ColorSpace::Lch => {
+                let ([l0, a0, b0], [l1, a1, b1]) = (to_oklab(c0), to_oklab(c1));
+                let (chroma0, chroma1) = (a0.hypot(b0), a1.hypot(b1));
+                let (mut hue0, mut hue1) = (b0.atan2(a0), b1.atan2(a1));
+                // A gray has no hue of its own, and takes the other color's.
+                if chroma0 < GRAY_CHROMA {
+                    hue0 = hue1;
+                } else if chroma1 < GRAY_CHROMA {
+                    hue1 = hue0;
+                }
+                let turn = (hue1 - hue0 + PI).rem_euclid(2.0 * PI) - PI;
+                let (chroma, hue) = (lerp(chroma0, chroma1), hue0 + turn * f);
+                from_oklab([lerp(l0, l1), chroma * hue.cos(), chroma * hue.sin()])
+            }
+        }
+    }
+}
+
+/// How little chroma a color can have for its hue to be ignored in blends.
+const GRAY_CHROMA: f64 = 1e-4;
+
+/// An sRGB color in Oklab, by Björn Ottosson's matrices.
+fn to_oklab(color: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = color.map(decode_srgb);
+    let l = (0.412_221_470_8 * r + 0.536_332_536_3 * g + 0.051_445_992_9 * b).cbrt();
+    let m = (0.211_903_498_2 * r + 0.680_699_545_1 * g + 0.107_396_956_6 * b).cbrt();
+    let s = (0.088_302_461_9 * r + 0.281_718_837_6 * g + 0.629_978_700_5 * b).cbrt();
+    [
+        0.210_454_255_3 * l + 0.793_617_785_0 * m - 0.004_072_046_8 * s,
+        1.977_998_495_1 * l - 2.428_592_205_0 * m + 0.450_593_709_9 * s,
+        0.025_904_037_1 * l + 0.782_771_766_2 * m - 0.808_675_766_0 * s,
+    ]
+}
+
+/// An Oklab color back in sRGB, clipped to its gamut, before it is rounded
+/// to bytes.
+fn from_oklab([lightness, a, b]: [f64; 3]) -> [f64; 3] {
+    let l = (lightness + 0.396_337_777_4 * a + 0.215_803_757_3 * b).powi(3);
+    let m = (lightness - 0.105_561_345_8 * a - 0.063_854_172_8 * b).powi(3);
+    let s = (lightness - 0.089_484_177_5 * a - 1.291_485_548_0 * b).powi(3);
+    [
+        4.076_741_662_1 * l - 3.307_711_591_3 * m + 0.230_969_929_2 * s,
+        -1.268_438_004_6 * l + 2.609_757_401_1 * m - 0.341_319_396_5 * s,
+        -0.004_196_086_3 * l - 0.703_418_614_7 * m + 1.707_614_701_0 * s,
+    ]
+    .map(encode_srgb_exact)
+}
+
+/// An sRGB channel as linear light, from 0 to 1.
+pub(crate) fn decode_srgb(v: u8) -> f64 {
+    decode_srgb_exact(v as f64 / 255.0)
+}
+
+/// An sRGB channel from 0 to 1, not rounded to a byte, as linear light.
+pub(crate) fn decode_srgb_exact(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear light back to an sRGB channel.
+pub(crate) fn encode_srgb(v: f64) -> u8 {
+    encode_srgb_exact(v).round() as u8
+}
+
+/// Linear light back to an sRGB channel from 0 to 255, not yet rounded.
+fn encode_srgb_exact(v: f64) -> f64 {
+    let v = v.clamp(0.0, 1.0);
+    let v = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    v * 255.0
+}
+
+impl Palette {
+    /// Builds a palette from gradient stops, which must be sorted by position.
+    /// Points inside the set are drawn in `interior`.
+    pub fn new(stops: Vec<(f64, [u8; 3])>, interior: [u8; 3]) -> Palette {
+        assert!(!stops.is_empty(), "a palette needs at least one stop");
+        Palette {
+            stops,
+            interior,
+            space: ColorSpace::Srgb,
+        }
+    }
+
+    /// The same palette, interpolated in `space`.
+    pub fn in_space(self, space: ColorSpace) -> Palette {
+        Palette { space, ..self }
+    }
+
+    pub fn space(&self) -> ColorSpace {
+        self.space
+    }
+
+    /// The stops and interior color as a CSV gradient, which
+    /// `gradient::parse_csv` reads back as this palette in sRGB.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for (position, [r, g, b]) in &self.stops {
+            csv.push_str(&format!("{},{},{},{}\n", position, r, g, b));
+        }
+        let [r, g, b] = self.interior;
+        csv.push_str(&format!("interior,{},{},{}\n", r, g, b));
+        csv
+    }
+
+    /// Looks up one of the built-in palettes listed in `NAMES`.
+    pub fn named(name: &str) -> Option<Palette> {
+        let &(_, stops) = STOPS.iter().find(|&&(n, _)| n == name)?;
+        Some(Palette::new(stops.to_vec(), [0, 0, 0]))
+    }
+
+    /// Returns the gradient color at `t`, clamped to `0.0..=1.0`.
+    pub fn at(&self, t: f64) -> [u8; 3] {
+        self.at_exact(t).map(|v| v.round() as u8)
+    }
+
+    /// The color at `t` like `at`, each channel from 0 to 255 before it is
+    /// rounded, for dithering.
+    pub fn at_exact(&self, t: f64) -> [f64; 3] {
+        let t = t.clamp(0.0, 1.0);
+        let next = self.stops.iter().position(|&(position, _)| position >= t);
+        match next {
+            Some(0) => self.stops[0].1.map(f64::from),
+            None => self.stops[self.stops.len() - 1].1.map(f64::from),
+            Some(i) => {
+                let (p0, c0) = self.stops[i - 1];
+                let (p1, c1) = self.stops[i];
+                let f = if p1 > p0 { (t - p0) / (p1 - p0) } else { 0.0 };
+                self.space.mix(c0, c1, f)
+            }
+        }
+    }
+
+    /// Colors a point by its (possibly fractional) escape count, stretching
+    /// the gradient over `0..limit` iterations.
+    pub fn color(&self, escape: Option<f64>, limit: u32) -> [u8; 3] {
+        self.color_at(escape.map(|x| x / limit.max(1) as f64))
+    }
+
+    /// Colors a point by its position on the gradient, or in the interior
+    /// color for points in the set.
+    pub fn color_at(&self, position: Option<f64>) -> [u8; 3] {
+        match position {
+            None => self.interior,
+            Some(t) => self.at(t),
+        }
+    }
+
+    /// The color of `color_at` before it is rounded.
+    pub fn color_at_exact(&self, position: Option<f64>) -> [f64; 3] {
+        match position {
+            None => self.interior.map(f64::from),
+            Some(t) => self.at_exact(t),
+        }
+    }
+}
+
+#[test]
+fn test_named_palettes() {
+    for name in NAMES {
+        assert!(Palette::named(name).is_some(), "{}", name);
+    }
+    assert_eq!(STOPS.len(), NAMES.len());
+    for (_, stops) in STOPS {
+        assert!(stops.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!((stops[0].0, stops[stops.len() - 1].0), (0.0, 1.0));
+    }
+    assert_eq!(Palette::named("plaid"), None);
+    // Stops are blended smoothly between.
+    let viridis = Palette::named("viridis").unwrap();
+    assert_eq!(viridis.at(0.0625), [70, 23, 104]);
+}
+
+#[test]
+fn test_palette_interpolation() {
+    let palette = Palette::new(
+        vec![
+            (0.0, [0, 0, 0]),
+            (0.5, [200, 100, 0]),
+            (1.0, [200, 100, 250]),
+        ],
+        [1, 2, 3],
+    );
+    assert_eq!(palette.at(-1.0), [0, 0, 0]);
+    assert_eq!(palette.at(0.25), [100, 50, 0]);
+    assert_eq!(palette.at(0.5), [200, 100, 0]);
+    assert_eq!(palette.at(0.75), [200, 100, 125]);
+    assert_eq!(palette.at(2.0), [200, 100, 250]);
+    assert_eq!(palette.color(None, 100), [1, 2, 3]);
+}
+
+#[test]
+fn test_linear_interpolation() {
+    let palette = Palette::new(vec![(0.0, [0, 0, 0]), (1.0, [255, 0, 255])], [0, 0, 0])
+        .in_space(ColorSpace::Linear);
+    assert_eq!(palette.at(0.0), [0, 0, 0]);
+    assert_eq!(palette.at(1.0), [255, 0, 255]);
+    // Half the light is well above half the sRGB value.
+    assert_eq!(palette.at(0.5), [188, 0, 188]);
+    for v in 0..=255 {
+        assert_eq!(encode_srgb(decode_srgb(v)), v);
+    }
+}
+
+#[test]
+fn test_perceptual_interpolation() {
+    let red_blue = |space| {
+        Palette::new(vec![(0.0, [255, 0, 0]), (1.0, [0, 0, 255])], [0, 0, 0]).in_space(space)
+    };
+    for space in [ColorSpace::Oklab, ColorSpace::Lch] {
+        let palette = red_blue(space);
+        assert_eq!(palette.at(0.0), [255, 0, 0]);
+        assert_eq!(palette.at(1.0), [0, 0, 255]);
+    }
+    // sRGB blends red and blue into a dark purple, Oklab into a lighter
+    // one, and LCh goes round through magenta at full strength.
+    assert_eq!(red_blue(ColorSpace::Srgb).at(0.5), [128, 0, 128]);
+    assert_eq!(red_blue(ColorSpace::Oklab).at(0.5), [140, 83, 162]);
+    assert_eq!(red_blue(ColorSpace::Lch).at(0.5), [186, 0, 194]);
+    // Grays stay gray, whatever the hue they start with.
+    let gray = Palette::new(vec![(0.0, [0, 0, 0]), (1.0, [255, 255, 255])], [0, 0, 0]);
+    for space in [ColorSpace::Oklab, ColorSpace::Lch] {
+        let [r, g, b] = gray.clone().in_space(space).at(0.5);
+        assert!(r.abs_diff(g) <= 1 && g.abs_diff(b) <= 1, "{:?}", space);
+    }
+    for color in [[0, 0, 0], [255, 255, 255], [12, 200, 99], [255, 0, 255]] {
+        assert_eq!(from_oklab(to_oklab(color)).map(|v| v.round() as u8), color);
+    }
+}
+
+#[test]
+fn test_grayscale_palette() {
+    let gray = Palette::named("grayscale").unwrap();
+    assert_eq!(gray.color(None, 255), [0, 0, 0]);
+    assert_eq!(gray.color(Some(0.0), 255), [255, 255, 255]);
+    assert_eq!(gray.color(Some(10.0), 255), [245, 245, 245]);
+    assert_eq!(gray.color(Some(0.0), 5000), [255, 255, 255]);
+    assert_eq!(gray.color(Some(2500.0), 5000), [128, 128, 128]);
+    assert_eq!(gray.color(Some(4999.0), 5000), [0, 0, 0]);
+}
+
+#[test]
+fn test_fractional_escape_counts() {
+    let gray = Palette::named("grayscale").unwrap();
+    assert_eq!(gray.color(Some(127.5), 255), [128, 128, 128]);
+    assert_eq!(gray.color(Some(300.0), 255), [0, 0, 0]);
+}
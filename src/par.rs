@@ -0,0 +1,214 @@
+//! Fractint's `.par` parameter files, which hold decades of published
+//! locations. A file holds named entries of `key=value` parameters:
+//!
+//! ```text
+//! Seahorse { ; a comment runs to the end of the line
+//!   reset=2004 type=mandel corners=-0.7522/-0.7422/0.1/0.1075
+//!   maxiter=1500 inside=0 colors=@default.map
+//!   }
+//! ```
+//!
+//! The view is read from `corners=XMIN/XMAX/YMIN/YMAX` or
+//! `center-mag=X/Y/MAG`, along with `maxiter` and the fractal `type`, of
+//! which the Mandelbrot set, its Julia sets and their quartic forms can be
+//! rendered. The coloring parameters are Fractint's own, and are skipped.
+
+use crate::{animation::View, Fixed};
+use num::Complex;
+
+/// The parameters of one entry that carry over to a render.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The view, at the height of the entry's; the width follows the image.
+    pub view: View,
+    pub max_iter: Option<u32>,
+    /// The constant of a Julia set.
+    pub julia: Option<Complex<f64>>,
+    /// The exponent of a multibrot, for the quartic types.
+    pub exponent: Option<u32>,
+}
+
+/// The names of the entries in a `.par` file's text, in order.
+pub fn names(text: &str) -> Vec<String> {
+    entries(text).into_iter().map(|(name, _)| name).collect()
+}
+
+/// Reads the entry called `name`, ignoring case as Fractint does, from a
+/// `.par` file's text.
+pub fn parse(text: &str, name: &str) -> Result<Entry, String> {
+    let (_, body) = entries(text)
+        .into_iter()
+        .find(|(entry, _)| entry.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("no entry named '{}'", name))?;
+    let mut view = None;
+    let mut max_iter = None;
+    let mut kind = "mandel".to_string();
+    let mut params = Vec::new();
+    for parameter in body.split_whitespace() {
+        let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+        let error = |message: &str| format!("{}: {}", key, message);
+        match key.to_ascii_lowercase().as_str() {
+            "corners" => {
+                let corners = numbers(value).ok_or_else(|| error("expected four numbers"))?;
+                let [x_min, x_max, y_min, y_max] = &corners[..] else {
+                    return Err(error("rotated or skewed views can't be imported"));
+                };
+                let height = (y_max - y_min).to_f64().abs();
+                if height == 0.0 || (x_max - x_min).to_f64() == 0.0 {
+                    return Err(error("the view is empty"));
+                }
+                view = Some(View {
+                    center: Complex {
+                        re: (x_min + x_max).scale(1, 2),
+                        im: (y_min + y_max).scale(1, 2),
+                    },
+                    zoom: 4.0 / height,
+                    flipped: false,
+                });
+            }
+            "center-mag" => {
+                let values = numbers(value).ok_or_else(|| error("expected X/Y/MAG"))?;
+                let (center, rest) = values.split_at(values.len().min(2));
+                let [re, im] = center else {
+                    return Err(error("expected X/Y/MAG"));
+                };
+                let rest = rest.iter().map(Fixed::to_f64).collect::<Vec<_>>();
+                // Fractint's magnification of 1 shows 2 units of height.
+                let magnification = match rest[..] {
+                    [] => 1.0,
+                    [magnification, ref stretch @ ..] => {
+                        if !matches!(stretch, [] | [1.0] | [1.0, 0.0] | [1.0, 0.0, 0.0]) {
+                            return Err(error("rotated or stretched views can't be imported"));
+                        }
+                        magnification
+                    }
+                };
+                if !(magnification > 0.0 && magnification.is_finite()) {
+                    return Err(error("invalid magnification"));
+                }
+                view = Some(View {
+                    center: Complex {
+                        re: re.clone(),
+                        im: im.clone(),
+                    },
+                    zoom: 2.0 * magnification,
+                    flipped: false,
+                });
+            }
+            "maxiter" => {
+                let value = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&iterations| iterations > 0)
+                    .ok_or_else(|| error("invalid value"))?;
+                max_iter = Some(value);
+            }
+            "type" => kind = value.to_ascii_lowercase(),
+            "params" => {
+                params = value
+                    .split('/')
+                    .map(str::parse::<f64>)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| error("invalid value"))?;
+            }
+            _ => {}
+        }
+    }
+    let view = view.ok_or("missing corners or center-mag")?;
+    let param = |i: usize| params.get(i).copied().unwrap_or(0.0);
+    let constant = Complex::new(param(0), param(1));
+    let (julia, exponent) = match kind.trim_end_matches("fp") {
+        "mandel" => (None, None),
+        "mandel4" => (None, Some(4)),
+        "julia" => (Some(constant), None),
+        "julia4" => (Some(constant), Some(4)),
+        _ => return Err(format!("type={} can't be imported", kind)),
+    };
+    if julia.is_none() && constant != Complex::new(0.0, 0.0) {
+        return Err("params: perturbed starting points can't be imported".to_string());
+    }
+    Ok(Entry {
+        view,
+        max_iter,
+        julia,
+        exponent,
+    })
+}
+
+/// `X/Y/...` as exact numbers, all to the same bits.
+fn numbers(value: &str) -> Option<Vec<Fixed>> {
+    let numbers = value
+        .split('/')
+        .map(Fixed::parse)
+        .collect::<Option<Vec<_>>>()?;
+    let bits = numbers.iter().map(Fixed::bits).max()?;
+    Some(numbers.iter().map(|x| x.with_bits(bits)).collect())
+}
+
+/// The name and body of each entry, without comments, with continued
+/// lines joined.
+fn entries(text: &str) -> Vec<(String, String)> {
+    let mut joined = String::new();
+    for line in text.lines() {
+        let line = line.split(';').next().unwrap();
+        match line.trim_end().strip_suffix('\\') {
+            Some(continued) => joined.push_str(continued.trim_start()),
+            None => {
+                joined.push_str(line.trim_start());
+                joined.push('\n');
+            }
+        }
+    }
+    let mut entries = Vec::new();
+    let mut rest = joined.as_str();
+    while let Some((head, tail)) = rest.split_once('{') {
+        let Some((body, tail)) = tail.split_once('}') else {
+            break;
+        };
+        if let Some(name) = head.split_whitespace().last() {
+            entries.push((name.to_string(), body.to_string()));
+        }
+        rest = tail;
+    }
+    entries
+}
+
+#[test]
+fn test_parse_par() {
+    let text = "Seahorse { ; the valley\r\n\
+                \x20 reset=2004 type=mandel corners=-0.75/-0.74/0.1/0.1075\r\n\
+                \x20 maxiter=1500 inside=0 colors=000<3>zzz\\\r\n\
+                \x20   <4>000\r\n\
+                \x20 }\r\n\
+                \r\n\
+                Dragon {\n\
+                \x20 type=juliafp center-mag=0/0/0.8/1/0/0 params=-0.8/0.156\n\
+                }\n\
+                Quad { type=mandel4 center-mag=0/0 }\n\
+                Formula { type=formula corners=-2/2/-2/2 }\n";
+    assert_eq!(names(text), ["Seahorse", "Dragon", "Quad", "Formula"]);
+    let seahorse = parse(text, "seahorse").unwrap();
+    assert_eq!(seahorse.view.center.re.to_f64(), -0.745);
+    assert_eq!(seahorse.view.center.im.to_f64(), 0.10375);
+    assert!((seahorse.view.zoom - 4.0 / 0.0075).abs() < 1e-6);
+    assert_eq!(seahorse.max_iter, Some(1500));
+    assert_eq!((seahorse.julia, seahorse.exponent), (None, None));
+    let dragon = parse(text, "Dragon").unwrap();
+    assert_eq!(dragon.view.zoom, 1.6);
+    assert_eq!(dragon.julia, Some(Complex::new(-0.8, 0.156)));
+    assert_eq!(dragon.max_iter, None);
+    let quad = parse(text, "Quad").unwrap();
+    assert_eq!((quad.view.zoom, quad.exponent), (2.0, Some(4)));
+    assert_eq!(
+        parse(text, "Formula").unwrap_err(),
+        "type=formula can't be imported"
+    );
+    assert_eq!(
+        parse(text, "Atlantis").unwrap_err(),
+        "no entry named 'Atlantis'"
+    );
+    assert!(parse("Tilted { corners=-2/2/-2/2/-1/1 }", "Tilted").is_err());
+    assert!(parse("Tilted { center-mag=0/0/1/1/45 }", "Tilted").is_err());
+    assert!(parse("Flat { corners=-2/2/1/1 }", "Flat").is_err());
+    assert!(parse("Nowhere { maxiter=10 }", "Nowhere").is_err());
+}
@@ -0,0 +1,369 @@
+//! Perturbation rendering for deep zooms.
+//!
+//! Only one point, the reference, is iterated in arbitrary precision. Every
+//! other pixel `c = C + dc` iterates its small difference from the reference
+//! orbit, `dz' = 2·Z·dz + dz² + dc`, which `f64` handles fine no matter how
+//! deep the zoom. Where that difference stops being small relative to the
+//! orbit the pixel is "glitched" and has to be redone against a reference
+//! closer to it.
+
+use crate::{
+    fixed::{self, Fixed},
+    log::{self, Level},
+    parallel_chunks, Escape, Progress,
+};
+use num::Complex;
+use std::sync::{Arc, Mutex};
+
+/// A reference orbit `Z_0, Z_1, ...` computed in arbitrary precision and
+/// rounded to `f64`. It ends with the first value outside the escape radius,
+/// or after `limit` iterations.
+#[derive(Debug, Clone)]
+pub struct ReferenceOrbit {
+    orbit: Vec<Complex<f64>>,
+}
+
+impl ReferenceOrbit {
+    pub fn compute(c: &Complex<Fixed>, limit: u32) -> ReferenceOrbit {
+        let _span = log::span(
+            Level::Debug,
+            "reference orbit",
+            format_args!("bits={} limit={}", c.re.bits(), limit),
+        );
+        let bits = c.re.bits();
+        let c = Complex {
+            re: c.re.clone(),
+            im: c.im.with_bits(bits),
+        };
+        let four = Fixed::from_f64(4.0, bits);
+        let mut z = Complex {
+            re: Fixed::zero(bits),
+            im: Fixed::zero(bits),
+        };
+        let mut orbit = Vec::with_capacity(limit as usize + 1);
+        for _ in 0..limit {
+            orbit.push(Complex {
+                re: z.re.to_f64(),
+                im: z.im.to_f64(),
+            });
+            let re2 = &z.re * &z.re;
+            let im2 = &z.im * &z.im;
+            if &re2 + &im2 > four {
+                return ReferenceOrbit { orbit };
+            }
+            let two_re_im = &(&z.re * &z.im) + &(&z.re * &z.im);
+            z = Complex {
+                re: &(&re2 - &im2) + &c.re,
+                im: &two_re_im + &c.im,
+            };
+        }
+        orbit.push(Complex {
+            re: z.re.to_f64(),
+            im: z.im.to_f64(),
+        });
+        ReferenceOrbit { orbit }
+    }
+
+    /// `compute`, or the same orbit from a recent render, such as another
+    /// band of a tiled one or another job of a batch at the same view.
+    pub fn cached(c: &Complex<Fixed>, limit: u32) -> Arc<ReferenceOrbit> {
+        let mut orbits = ORBITS.lock().unwrap();
+        let found = orbits
+            .iter()
+            .position(|(point, l, _)| point == c && *l == limit);
+        if let Some(index) = found {
+            let entry = orbits.remove(index);
+            let orbit = entry.2.clone();
+            orbits.push(entry);
+            return orbit;
+        }
+        drop(orbits);
+        let orbit = Arc::new(ReferenceOrbit::compute(c, limit));
+        let mut orbits = ORBITS.lock().unwrap();
+        if orbits.len() >= CACHED_ORBITS {
+            orbits.remove(0);
+        }
+        orbits.push((c.clone(), limit, orbit.clone()));
+        orbit
+    }
+
+    /// Number of orbit values available.
+    pub fn len(&self) -> usize {
+        self.orbit.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orbit.is_empty()
+    }
+}
+
+/// How many reference orbits `ReferenceOrbit::cached` keeps.
+const CACHED_ORBITS: usize = 4;
+
+/// A reference orbit with its point and iteration limit.
+type CachedOrbit = (Complex<Fixed>, u32, Arc<ReferenceOrbit>);
+
+/// The orbits `ReferenceOrbit::cached` keeps, the most recently used last.
+static ORBITS: Mutex<Vec<CachedOrbit>> = Mutex::new(Vec::new());
+
+/// The outcome of iterating one pixel against a reference orbit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Perturbed {
+    Done(Option<Escape>),
+    /// The pixel drifted too far from the reference for `f64` deltas to be
+    /// trusted, or outlived the reference orbit, after this many iterations.
+    Glitched(u32),
+}
+
+/// Pauldelbrot's criterion: a pixel is glitched once `|Z + dz|` drops below
+/// this fraction of `|Z|`, squared here to compare against norms.
+const GLITCH_TOLERANCE: f64 = 1e-6;
+
+/// `escape_time` for the point `dc` away from the reference.
+pub fn escape_time(reference: &ReferenceOrbit, dc: Complex<f64>, limit: u32) -> Perturbed {
+    let mut dz = Complex { re: 0.0, im: 0.0 };
+    for i in 0..limit {
+        let big_z = match reference.orbit.get(i as usize) {
+            Some(&big_z) => big_z,
+            None => return Perturbed::Glitched(i),
+        };
+        let z = big_z + dz;
+        let norm = z.norm_sqr();
+        if norm > 4.0 {
+            return Perturbed::Done(Some(Escape {
+                iterations: i,
+                z,
+                derivative: None,
+                stripe: None,
+            }));
+        }
+        if norm < GLITCH_TOLERANCE * big_z.norm_sqr() {
+            return Perturbed::Glitched(i);
+        }
+        dz = big_z * dz * 2.0 + dz * dz + dc;
+    }
+    Perturbed::Done(None)
+}
+
+#[test]
+fn test_perturbation_matches_direct_iteration() {
+    let reference = Complex { re: -0.1, im: 0.1 };
+    let orbit = ReferenceOrbit::compute(
+        &Complex {
+            re: Fixed::from_f64(reference.re, 100),
+            im: Fixed::from_f64(reference.im, 100),
+        },
+        1000,
+    );
+    assert_eq!(orbit.len(), 1001);
+    // Small offsets stay close to the reference and must finish; larger
+    // ones may glitch, but must agree when they don't.
+    for &(dre, dim, close) in &[
+        (0.0, 0.0, true),
+        (1e-3, 0.0, true),
+        (0.0, -2e-3, true),
+        (0.01, 0.02, false),
+        (0.5, 0.4, false),
+    ] {
+        let dc = Complex { re: dre, im: dim };
+        let expected = crate::escape_time(reference + dc, 1000).map(|e| e.iterations);
+        match escape_time(&orbit, dc, 1000) {
+            Perturbed::Done(escape) => assert_eq!(escape.map(|e| e.iterations), expected),
+            Perturbed::Glitched(i) => assert!(!close, "{} {} glitched at {}", dre, dim, i),
+        }
+    }
+    // Outliving an escaping reference is a glitch.
+    let escaping = ReferenceOrbit::compute(
+        &Complex {
+            re: Fixed::from_f64(1.0, 64),
+            im: Fixed::from_f64(0.0, 64),
+        },
+        1000,
+    );
+    assert_eq!(escaping.len(), 4);
+    assert_eq!(
+        escape_time(&escaping, Complex { re: -1.5, im: 0.0 }, 1000),
+        Perturbed::Glitched(4)
+    );
+}
+
+#[test]
+fn test_cached_orbit() {
+    let c = Complex {
+        re: Fixed::from_f64(-0.75, 80),
+        im: Fixed::from_f64(0.1, 80),
+    };
+    let orbit = ReferenceOrbit::cached(&c, 777);
+    assert_eq!(orbit.orbit, ReferenceOrbit::compute(&c, 777).orbit);
+    assert!(Arc::ptr_eq(&orbit, &ReferenceOrbit::cached(&c, 777)));
+    assert!(!Arc::ptr_eq(&orbit, &ReferenceOrbit::cached(&c, 778)));
+}
+
+/// The upper bound on references tried before the remaining glitched pixels
+/// are iterated directly in arbitrary precision.
+const MAX_REFERENCES: usize = 16;
+
+/// Computes the escape time at each of `positions`, given in pixels from the
+/// upper left corner of a `bounds` view between the exact corners, with the
+/// reference orbits computed to `bits` fractional bits, on `threads` threads.
+#[allow(clippy::too_many_arguments)]
+pub fn render(
+    escapes: &mut [Option<Escape>],
+    positions: &[(f64, f64)],
+    bounds: (u32, u32),
+    upper_left: &Complex<Fixed>,
+    lower_right: &Complex<Fixed>,
+    limit: u32,
+    bits: u32,
+    threads: u32,
+    progress: &Progress,
+) {
+    let upper_left = Complex {
+        re: upper_left.re.with_bits(bits),
+        im: upper_left.im.with_bits(bits),
+    };
+    let lower_right = Complex {
+        re: lower_right.re.with_bits(bits),
+        im: lower_right.im.with_bits(bits),
+    };
+    let exact_point =
+        |position| fixed::position_to_point(bounds, position, &upper_left, &lower_right);
+    let pixel_width = (&lower_right.re - &upper_left.re).to_f64() / bounds.0.max(1) as f64;
+    let pixel_height = (&upper_left.im - &lower_right.im).to_f64() / bounds.1.max(1) as f64;
+    let offset = |position: (f64, f64), reference: (f64, f64)| Complex {
+        re: (position.0 - reference.0) * pixel_width,
+        im: -(position.1 - reference.1) * pixel_height,
+    };
+
+    let chunk_len = bounds.0.max(1) as usize;
+    let mut results = vec![Perturbed::Glitched(0); escapes.len()];
+    let mut pending = (0..escapes.len()).collect::<Vec<_>>();
+    let mut reference = ((bounds.0 / 2) as f64, (bounds.1 / 2) as f64);
+    for round in 0..MAX_REFERENCES {
+        if pending.is_empty() {
+            break;
+        }
+        // The first reference, at the center of the view, is the same for
+        // every render of it.
+        let orbit = match round {
+            0 => ReferenceOrbit::cached(&exact_point(reference), limit),
+            _ => Arc::new(ReferenceOrbit::compute(&exact_point(reference), limit)),
+        };
+        let mut batch = vec![Perturbed::Glitched(0); pending.len()];
+        let busy = parallel_chunks(&mut batch, chunk_len, threads, |start, chunk| {
+            for (k, result) in chunk.iter_mut().enumerate() {
+                let position = positions[pending[start + k]];
+                *result = escape_time(&orbit, offset(position, reference), limit);
+            }
+            let done = chunk.iter().filter(|r| matches!(r, Perturbed::Done(_)));
+            progress.add(done.count() as u64);
+        });
+        progress.add_busy(&busy);
+        let mut still_pending = Vec::new();
+        let mut deepest = (0, 0);
+        for (&index, result) in pending.iter().zip(batch) {
+            match result {
+                Perturbed::Glitched(iterations) => {
+                    still_pending.push(index);
+                    deepest = deepest.max((iterations, index));
+                }
+                done => results[index] = done,
+            }
+        }
+        // Most glitches come from pixels outliving an escaping reference, so
+        // the next reference is the glitched pixel that got furthest.
+        reference = positions[deepest.1];
+        pending = still_pending;
+    }
+
+    let mut exact = vec![Perturbed::Glitched(0); pending.len()];
+    let busy = parallel_chunks(&mut exact, 1, threads, |start, chunk| {
+        for (k, result) in chunk.iter_mut().enumerate() {
+            let position = positions[pending[start + k]];
+            *result = Perturbed::Done(fixed::escape_time(&exact_point(position), limit));
+        }
+        progress.add(chunk.len() as u64);
+    });
+    progress.add_busy(&busy);
+    for (&index, result) in pending.iter().zip(exact) {
+        results[index] = result;
+    }
+
+    for (escape, result) in escapes.iter_mut().zip(results) {
+        if let Perturbed::Done(result) = result {
+            *escape = result;
+        }
+    }
+}
+
+#[test]
+fn test_render_resolves_glitches() {
+    // The center of the view, 0.3, escapes after a few iterations, while
+    // the left of it is in the set and outlives that reference.
+    let bits = 64;
+    let point = |re, im| Complex {
+        re: Fixed::from_f64(re, bits),
+        im: Fixed::from_f64(im, bits),
+    };
+    let (upper_left, lower_right) = (point(-0.3, 0.1), point(0.9, -0.1));
+    let bounds = (16, 4);
+    let pixel = (1.2 / 16.0, 0.2 / 4.0);
+    let limit = 500;
+    let positions = (0..bounds.1)
+        .flat_map(|y| (0..bounds.0).map(move |x| (x as f64 + 0.5, y as f64 + 0.5)))
+        .collect::<Vec<_>>();
+    let orbit = |reference| {
+        ReferenceOrbit::compute(
+            &fixed::position_to_point(bounds, reference, &upper_left, &lower_right),
+            limit,
+        )
+    };
+    let against = |orbit: &ReferenceOrbit, reference: (f64, f64), (x, y): (f64, f64)| {
+        let dc = Complex {
+            re: (x - reference.0) * pixel.0,
+            im: -(y - reference.1) * pixel.1,
+        };
+        escape_time(orbit, dc, limit)
+    };
+    let first = orbit((8.0, 2.0));
+    let glitched = positions
+        .iter()
+        .filter_map(|&position| match against(&first, (8.0, 2.0), position) {
+            Perturbed::Glitched(i) => Some((i, position)),
+            Perturbed::Done(_) => None,
+        })
+        .collect::<Vec<_>>();
+    assert!(!glitched.is_empty());
+    // The glitched pixel that got furthest, the second reference, finishes
+    // the others without going to arbitrary precision.
+    let (_, second) = glitched.iter().copied().max_by_key(|&(i, _)| i).unwrap();
+    let second_orbit = orbit(second);
+    let finished = glitched.iter().filter(|&&(_, position)| {
+        matches!(against(&second_orbit, second, position), Perturbed::Done(_))
+    });
+    assert_eq!(finished.count(), glitched.len());
+
+    let mut escapes = vec![None; positions.len()];
+    let progress = Progress::default();
+    render(
+        &mut escapes,
+        &positions,
+        bounds,
+        &upper_left,
+        &lower_right,
+        limit,
+        bits,
+        1,
+        &progress,
+    );
+    for (&position, escape) in positions.iter().zip(&escapes) {
+        let c = fixed::position_to_point(bounds, position, &upper_left, &lower_right);
+        let expected = crate::escape_time(Complex::new(c.re.to_f64(), c.im.to_f64()), limit);
+        assert_eq!(
+            escape.map(|e| e.iterations),
+            expected.map(|e| e.iterations),
+            "{:?}",
+            position
+        );
+    }
+}
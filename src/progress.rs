@@ -0,0 +1,83 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Counts the pixels a render has finished, so that another thread can
+/// report on it while it runs. Share it with `Renderer::progress`.
+#[derive(Debug, Default)]
+pub struct Progress {
+    done: AtomicU64,
+    total: AtomicU64,
+    busy: Mutex<Vec<Duration>>,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    /// Pixels finished so far.
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    /// Pixels in the render, or 0 before it has started.
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// Whether a render has started and every pixel has been finished.
+    pub fn is_finished(&self) -> bool {
+        let total = self.total();
+        total > 0 && self.done() >= total
+    }
+
+    /// How long each thread has spent rendering so far, as far as it has
+    /// been timed; see `parallel_chunks`. Threads are numbered afresh for
+    /// each batch of work they are handed, such as each band of a tiled
+    /// render, whose times add up by number.
+    pub fn busy(&self) -> Vec<Duration> {
+        self.busy.lock().unwrap().clone()
+    }
+
+    /// Asks the render to stop early, from any thread, for good: a render
+    /// started with it afterwards stops at once. Scans finish the rows
+    /// under way and return what they got done, the rows finished from the
+    /// top; see `Renderer::render_escapes`. Boundary tracing runs to the
+    /// end.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether `cancel` has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn start(&self, total: u64) {
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+        self.busy.lock().unwrap().clear();
+    }
+
+    /// Adds work discovered after the render started.
+    pub(crate) fn extend(&self, pixels: u64) {
+        self.total.fetch_add(pixels, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add(&self, pixels: u64) {
+        self.done.fetch_add(pixels, Ordering::Relaxed);
+    }
+
+    /// Adds the time each thread spent on a batch of work.
+    pub(crate) fn add_busy(&self, busy: &[Duration]) {
+        let mut totals = self.busy.lock().unwrap();
+        if totals.len() < busy.len() {
+            totals.resize(busy.len(), Duration::ZERO);
+        }
+        for (total, busy) in totals.iter_mut().zip(busy) {
+            *total += *busy;
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use mandelbrot::Progress;
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+const BAR_WIDTH: usize = 30;
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// Redraws a progress bar on stderr for a render running on other threads.
+pub struct ProgressBar {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ProgressBar {
+    /// Starts drawing `progress`, reporting speed in rows of `row_width`
+    /// pixels.
+    pub fn start(progress: Arc<Progress>, row_width: u32) -> ProgressBar {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let started = Instant::now();
+                let mut stderr = std::io::stderr();
+                while !stop.load(Ordering::Relaxed) {
+                    let line = format_line(
+                        progress.done(),
+                        progress.total(),
+                        row_width,
+                        started.elapsed(),
+                    );
+                    let _ = write!(stderr, "\r{}", line);
+                    std::thread::sleep(REFRESH);
+                }
+                let line = format_line(
+                    progress.done(),
+                    progress.total(),
+                    row_width,
+                    started.elapsed(),
+                );
+                let _ = writeln!(stderr, "\r{}", line);
+            })
+        };
+        ProgressBar {
+            stop,
+            thread: Some(thread),
+        }
+    }
+
+    /// Draws the bar one last time and stops.
+    pub fn finish(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Formats one redraw of the bar, e.g.
+/// `[=============>                ]  45%  120 rows/s  ETA 0:04`.
+fn format_line(done: u64, total: u64, row_width: u32, elapsed: Duration) -> String {
+    let fraction = if total == 0 {
+        0.0
+    } else {
+        (done as f64 / total as f64).min(1.0)
+    };
+    let filled = (fraction * BAR_WIDTH as f64) as usize;
+    let bar = match filled {
+        BAR_WIDTH => "=".repeat(BAR_WIDTH),
+        _ => format!(
+            "{}>{}",
+            "=".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled - 1)
+        ),
+    };
+    let seconds = elapsed.as_secs_f64();
+    let rows = done as f64 / row_width.max(1) as f64;
+    let rate = if seconds > 0.0 { rows / seconds } else { 0.0 };
+    let eta = if done == 0 || done >= total {
+        "--:--".to_string()
+    } else {
+        let remaining = seconds * (total - done) as f64 / done as f64;
+        format_duration(remaining.round() as u64)
+    };
+    format!(
+        "[{}] {:3.0}%  {:.0} rows/s  ETA {}",
+        bar,
+        fraction * 100.0,
+        rate,
+        eta
+    )
+}
+
+fn format_duration(seconds: u64) -> String {
+    match seconds {
+        0..=3599 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        _ => format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ),
+    }
+}
+
+#[test]
+fn test_format_line() {
+    assert_eq!(
+        format_line(0, 1000, 10, Duration::ZERO),
+        format!("[>{}]   0%  0 rows/s  ETA --:--", " ".repeat(BAR_WIDTH - 1))
+    );
+    assert_eq!(
+        format_line(500, 1000, 10, Duration::from_secs(5)),
+        format!(
+            "[{}>{}]  50%  10 rows/s  ETA 0:05",
+            "=".repeat(15),
+            " ".repeat(14)
+        )
+    );
+    assert_eq!(
+        format_line(1000, 1000, 10, Duration::from_secs(4)),
+        format!("[{}] 100%  25 rows/s  ETA --:--", "=".repeat(BAR_WIDTH))
+    );
+    assert_eq!(format_duration(3725), "1:02:05");
+}
@@ -0,0 +1,153 @@
+//! Progressive refinement: the image rendered coarse first, then at twice
+//! the resolution each pass until it is whole, and the passes written as
+//! the frames of an APNG that plays once. A browser shows the render
+//! coming into focus, from a single file, and ends on the full image.
+//!
+//! Each pass covers the pixels of the whole image with blocks of them, so
+//! a coarse pass is a render of the same view at a fraction of the size,
+//! blown back up. Where the size doesn't divide, the last blocks reach past
+//! the edge of the image and are cut off.
+
+use crate::{add_text, pixel_to_point, RenderOptions};
+use num::Complex;
+use png::EncodingError;
+use std::io::Write;
+
+/// How many pixels across a block of each pass is, coarsest first.
+pub const PASSES: [u32; 4] = [8, 4, 2, 1];
+
+/// How long each pass is shown for, in milliseconds.
+pub const PASS_DELAY: u16 = 500;
+
+/// The options for the pass whose blocks are `factor` pixels across, in
+/// the precision the whole image would take.
+pub fn pass(options: &RenderOptions, factor: u32) -> RenderOptions {
+    let (width, height) = options.bounds;
+    let bounds = (width.div_ceil(factor), height.div_ceil(factor));
+    let covered = (bounds.0 * factor, bounds.1 * factor);
+    let exact_corners = options.exact_corners.as_ref().map(|_| {
+        let (upper_left, lower_right) = options.exact_corners();
+        let size = (
+            (&lower_right.re - &upper_left.re).scale(covered.0, width),
+            (&upper_left.im - &lower_right.im).scale(covered.1, height),
+        );
+        let lower_right = Complex {
+            re: &upper_left.re + &size.0,
+            im: &upper_left.im - &size.1,
+        };
+        (upper_left, lower_right)
+    });
+    RenderOptions {
+        bounds,
+        lower_right: pixel_to_point(
+            options.bounds,
+            covered,
+            options.upper_left,
+            options.lower_right,
+        ),
+        exact_corners,
+        precision: options.resolved_precision(),
+        ..options.clone()
+    }
+}
+
+/// Blows the RGB pixels of a pass whose blocks are `factor` pixels across
+/// up to the image's `bounds`.
+pub fn upscale(pixels: &[u8], factor: u32, bounds: (u32, u32)) -> Vec<u8> {
+    let (width, height) = (bounds.0 as usize, bounds.1 as usize);
+    let (factor, columns) = (factor as usize, bounds.0.div_ceil(factor) as usize);
+    let mut upscaled = Vec::with_capacity(3 * width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = 3 * ((y / factor) * columns + x / factor);
+            upscaled.extend_from_slice(&pixels[i..i + 3]);
+        }
+    }
+    upscaled
+}
+
+/// Encodes `frames`, RGB pixel buffers of `bounds` pixels, into an APNG
+/// that shows each for `PASS_DELAY` and stops on the last, with text
+/// chunks like `encode_image`. The frames are taken one at a time, so a
+/// pass can be rendered as it is wanted.
+pub fn encode_apng<W: Write>(
+    w: W,
+    bounds: (u32, u32),
+    text: &[(&str, String)],
+    frames: impl ExactSizeIterator<Item = Vec<u8>>,
+) -> Result<(), EncodingError> {
+    let mut encoder = png::Encoder::new(w, bounds.0, bounds.1);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_animated(frames.len() as u32, 1)?;
+    encoder.set_frame_delay(PASS_DELAY, 1000)?;
+    add_text(&mut encoder, text)?;
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.write_image_data(&frame)?;
+    }
+    writer.finish()
+}
+
+#[test]
+fn test_pass() {
+    use crate::Fixed;
+    let options = RenderOptions {
+        bounds: (100, 60),
+        upper_left: Complex::new(-2.0, 1.0),
+        lower_right: Complex::new(1.0, -0.8),
+        exact_corners: Some((
+            Complex::new(Fixed::from_f64(-2.0, 64), Fixed::from_f64(1.0, 64)),
+            Complex::new(Fixed::from_f64(1.0, 64), Fixed::from_f64(-0.8, 64)),
+        )),
+        ..RenderOptions::default()
+    };
+    assert_eq!(pass(&options, 1).bounds, options.bounds);
+    assert_eq!(pass(&options, 1).lower_right, options.lower_right);
+    // 100 pixels take 13 blocks of 8, 104 pixels' worth.
+    let coarse = pass(&options, 8);
+    assert_eq!(coarse.bounds, (13, 8));
+    assert!((coarse.pixel_size() - 8.0 * options.pixel_size()).abs() < 1e-12);
+    assert!((coarse.lower_right.re - 1.12).abs() < 1e-12);
+    assert!((coarse.lower_right.im + 0.92).abs() < 1e-12);
+    let (_, lower_right) = coarse.exact_corners.clone().unwrap();
+    assert!((lower_right.re.to_f64() - 1.12).abs() < 1e-12);
+    assert!((lower_right.im.to_f64() + 0.92).abs() < 1e-12);
+}
+
+#[test]
+fn test_upscale() {
+    let pixels = (0..3 * 2 * 2).collect::<Vec<u8>>();
+    let upscaled = upscale(&pixels, 2, (3, 3));
+    let at = |x: usize, y: usize| &upscaled[3 * (y * 3 + x)..3 * (y * 3 + x) + 3];
+    assert_eq!(at(0, 0), [0, 1, 2]);
+    assert_eq!(at(1, 1), [0, 1, 2]);
+    assert_eq!(at(2, 0), [3, 4, 5]);
+    assert_eq!(at(0, 2), [6, 7, 8]);
+    assert_eq!(at(2, 2), [9, 10, 11]);
+}
+
+#[test]
+fn test_encode_apng() {
+    use crate::Renderer;
+    let options = RenderOptions {
+        bounds: (37, 21),
+        ..RenderOptions::default()
+    };
+    let frames = PASSES.iter().map(|&factor| {
+        let pixels = Renderer::new(pass(&options, factor)).render();
+        upscale(&pixels, factor, options.bounds)
+    });
+    let expected = frames.clone().collect::<Vec<_>>();
+    let mut bytes = Vec::new();
+    encode_apng(&mut bytes, options.bounds, &[], frames).unwrap();
+    assert_eq!(expected[3], Renderer::new(options).render());
+
+    let mut reader = png::Decoder::new(&bytes[..]).read_info().unwrap();
+    let control = reader.info().animation_control.unwrap();
+    assert_eq!((control.num_frames, control.num_plays), (4, 1));
+    for frame in &expected {
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(&pixels, frame);
+    }
+}
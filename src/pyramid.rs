@@ -0,0 +1,240 @@
+//! Deep Zoom tile pyramids, so that a render far too big to open at once
+//! can be panned and zoomed in a browser with OpenSeadragon, served as
+//! plain files with nothing computed on the server. The layout is Deep
+//! Zoom's (DZI): a descriptor `NAME.dzi`, and a directory `NAME_files` with
+//! a directory of tiles for each level, from `0`, a single pixel, up to the
+//! full image at the top. Each level is half the size of the one above it,
+//! rounded up, and is cut into tiles `tile_size` pixels square, named
+//! `COLUMN_ROW.png`, which overlap their neighbors by `overlap` pixels.
+//!
+//! Every level is rendered afresh at its own size, rather than scaled down
+//! from the one above, one row of tiles at a time, so that no more than a
+//! row of tiles is ever held at once.
+
+use crate::{
+    log::{self, Level},
+    tiled, Coloring, RenderOptions, Renderer,
+};
+use std::io;
+
+/// The shape of a pyramid: the size of its full image and of its tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pyramid {
+    pub bounds: (u32, u32),
+    pub tile_size: u32,
+    pub overlap: u32,
+}
+
+/// The usual tile size, which with the overlap on both sides makes tiles
+/// of 256 pixels.
+pub const DEFAULT_TILE_SIZE: u32 = 254;
+
+pub const DEFAULT_OVERLAP: u32 = 1;
+
+impl Pyramid {
+    /// The number of the top level, which holds the full image.
+    pub fn top(&self) -> u32 {
+        let longest = self.bounds.0.max(self.bounds.1).max(1);
+        u32::BITS - (longest - 1).leading_zeros()
+    }
+
+    /// The size of the image at `level`.
+    pub fn level_bounds(&self, level: u32) -> (u32, u32) {
+        let shift = self.top() - level;
+        let halve = |n: u32| ((n as u64 + (1 << shift) - 1) >> shift) as u32;
+        (halve(self.bounds.0), halve(self.bounds.1))
+    }
+
+    /// How many columns and rows of tiles `level` is cut into.
+    pub fn tiles(&self, level: u32) -> (u32, u32) {
+        let (width, height) = self.level_bounds(level);
+        (
+            width.div_ceil(self.tile_size),
+            height.div_ceil(self.tile_size),
+        )
+    }
+
+    /// The pixels of the tile in column or row `index` along a side of
+    /// `length` pixels, with the overlap.
+    pub fn span(&self, index: u32, length: u32) -> std::ops::Range<u32> {
+        let start = (index * self.tile_size).saturating_sub(self.overlap);
+        let end = ((index + 1) * self.tile_size + self.overlap).min(length);
+        start..end
+    }
+
+    /// The XML of the `.dzi` descriptor, for tiles with the extension
+    /// `format`.
+    pub fn descriptor(&self, format: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"{}\" \
+             Overlap=\"{}\" TileSize=\"{}\">\n  \
+             <Size Width=\"{}\" Height=\"{}\"/>\n\
+             </Image>\n",
+            format, self.overlap, self.tile_size, self.bounds.0, self.bounds.1
+        )
+    }
+}
+
+/// A tile of a pyramid: its level, column and row, and its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub level: u32,
+    pub column: u32,
+    pub row: u32,
+    pub bounds: (u32, u32),
+}
+
+/// Renders every tile of `pyramid` for `renderer`'s view, from the top
+/// level down, handing each to `write` with its pixels, three bytes each
+/// like `Renderer::render`. The renderer's size is replaced by each
+/// level's, and its progress counts the pixels of every level. A
+/// cancelled render stops after the row of tiles under way, failing with
+/// `io::ErrorKind::Interrupted`.
+pub fn render<F>(renderer: &Renderer, pyramid: &Pyramid, mut write: F) -> io::Result<()>
+where
+    F: FnMut(Tile, &[u8]) -> io::Result<()>,
+{
+    let options = renderer.options();
+    assert!(
+        options.coloring != Coloring::Histogram,
+        "histogram coloring needs the whole image"
+    );
+    let progress = renderer.progress();
+    let total = (0..=pyramid.top())
+        .map(|level| {
+            let (width, height) = pyramid.level_bounds(level);
+            width as u64 * height as u64
+        })
+        .sum();
+    progress.start(total);
+    let margin = options.shading.is_some() as u32;
+    let cancelled = || io::Error::new(io::ErrorKind::Interrupted, "the render was cancelled");
+    for level in (0..=pyramid.top()).rev() {
+        let bounds = pyramid.level_bounds(level);
+        let _span = log::span(
+            Level::Debug,
+            "level",
+            format_args!("level={} size={}x{}", level, bounds.0, bounds.1),
+        );
+        let level_renderer = Renderer {
+            options: RenderOptions {
+                bounds,
+                ..options.clone()
+            },
+            progress: progress.clone(),
+            observer: None,
+            colorizer: renderer.colorizer.clone(),
+        };
+        let (columns, rows) = pyramid.tiles(level);
+        let row_bytes = bounds.0 as usize * 3;
+        for row in 0..rows {
+            if progress.is_cancelled() {
+                return Err(cancelled());
+            }
+            let span = pyramid.span(row, bounds.1);
+            let top = span.start.saturating_sub(margin);
+            let bottom = (span.end + margin).min(bounds.1);
+            // The overlap and the spare rows are rendered twice.
+            let fresh = ((row + 1) * pyramid.tile_size).min(bounds.1) - row * pyramid.tile_size;
+            progress.extend((bottom - top - fresh) as u64 * bounds.0 as u64);
+            let pixels = tiled::render_rows(&level_renderer, top..bottom);
+            if pixels.len() < (bottom - top) as usize * row_bytes {
+                return Err(cancelled());
+            }
+            let pixels =
+                &pixels[(span.start - top) as usize * row_bytes..][..span.len() * row_bytes];
+            for column in 0..columns {
+                let across = pyramid.span(column, bounds.0);
+                let tile = pixels
+                    .chunks_exact(row_bytes)
+                    .flat_map(|line| &line[across.start as usize * 3..across.end as usize * 3])
+                    .copied()
+                    .collect::<Vec<u8>>();
+                write(
+                    Tile {
+                        level,
+                        column,
+                        row,
+                        bounds: (across.len() as u32, span.len() as u32),
+                    },
+                    &tile,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_pyramid_layout() {
+    let pyramid = Pyramid {
+        bounds: (1000, 750),
+        tile_size: DEFAULT_TILE_SIZE,
+        overlap: DEFAULT_OVERLAP,
+    };
+    assert_eq!(pyramid.top(), 10);
+    assert_eq!(pyramid.level_bounds(10), (1000, 750));
+    assert_eq!(pyramid.level_bounds(9), (500, 375));
+    assert_eq!(pyramid.level_bounds(8), (250, 188));
+    assert_eq!(pyramid.level_bounds(0), (1, 1));
+    assert_eq!(pyramid.tiles(10), (4, 3));
+    assert_eq!(pyramid.tiles(0), (1, 1));
+    assert_eq!(pyramid.span(0, 1000), 0..255);
+    assert_eq!(pyramid.span(1, 1000), 253..509);
+    assert_eq!(pyramid.span(3, 1000), 761..1000);
+    let square = Pyramid {
+        bounds: (256, 256),
+        ..pyramid
+    };
+    assert_eq!(square.top(), 8);
+    assert!(pyramid.descriptor("png").contains(
+        "Format=\"png\" Overlap=\"1\" TileSize=\"254\">\n  <Size Width=\"1000\" Height=\"750\"/>"
+    ));
+}
+
+#[test]
+fn test_render_pyramid() {
+    let options = RenderOptions {
+        bounds: (50, 30),
+        shading: Some(crate::Light {
+            azimuth: 45.0,
+            elevation: 45.0,
+        }),
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let whole = Renderer::new(options.clone()).render();
+    let pyramid = Pyramid {
+        bounds: options.bounds,
+        tile_size: 16,
+        overlap: 2,
+    };
+    let renderer = Renderer::new(options);
+    let mut tiles = Vec::new();
+    render(&renderer, &pyramid, |tile, pixels| {
+        assert_eq!(
+            pixels.len(),
+            tile.bounds.0 as usize * tile.bounds.1 as usize * 3
+        );
+        tiles.push((tile, pixels.to_vec()));
+        Ok(())
+    })
+    .unwrap();
+    assert!(renderer.progress().is_finished());
+    let count = (0..=pyramid.top())
+        .map(|level| pyramid.tiles(level).0 * pyramid.tiles(level).1)
+        .sum::<u32>();
+    assert_eq!(tiles.len(), count as usize);
+    // The top level's tiles are pieces of the whole image.
+    for (tile, pixels) in tiles.iter().filter(|(tile, _)| tile.level == pyramid.top()) {
+        let (across, down) = (pyramid.span(tile.column, 50), pyramid.span(tile.row, 30));
+        for (y, line) in down.zip(pixels.chunks_exact(tile.bounds.0 as usize * 3)) {
+            let start = (y as usize * 50 + across.start as usize) * 3;
+            assert_eq!(line, &whole[start..][..line.len()], "{:?}", tile);
+        }
+    }
+    renderer.progress().cancel();
+    let error = render(&renderer, &pyramid, |_, _| Ok(())).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::Interrupted);
+}
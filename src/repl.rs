@@ -0,0 +1,206 @@
+//! `repl`: an interactive session, which keeps a view and the options to
+//! render it with between commands, so that exploring takes a word or two
+//! at a time instead of a whole command line. Previews are drawn in the
+//! terminal, like `--preview-term`, and `save` renders the view in full.
+
+use crate::cli::{parse_exact_complex, Cli, Session};
+use mandelbrot::MandelbrotError;
+use num::Complex;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+const HELP: &str = "\
+commands:
+  center RE IM      move the view's center to RE,IM
+  zoom FACTOR       magnify the view FACTOR times, or zoom out below 1
+  iter N            iterate at most N times per point
+  size WxH          render WxH pixels
+  set FLAG [VALUE]  set any render option, such as 'set palette fire'
+  unset FLAG        go back to the option's default
+  render preview    draw the view in the terminal
+  save FILE         render the view to FILE, in the format its extension names
+  show              print the view and its size
+  quit              leave the session";
+
+/// What a command leaves to be done.
+#[derive(Debug, PartialEq)]
+enum Step {
+    Nothing,
+    Print(String),
+    Preview(Box<Cli>),
+    Save(Box<Cli>),
+    Quit,
+}
+
+/// Runs commands from standard input until `quit` or the end of input.
+/// A command that fails says why and leaves the session as it was.
+pub fn repl(mut session: Session) -> Result<(), MandelbrotError> {
+    let prompt = io::stdin().is_terminal();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        if prompt {
+            print!("mandelbrot> ");
+            io::stdout()
+                .flush()
+                .map_err(|e| MandelbrotError::writing("stdout", e))?;
+        }
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line.map_err(|e| MandelbrotError::reading("stdin", e))?;
+        let done = match step(&mut session, &line) {
+            Ok(Step::Nothing) => Ok(()),
+            Ok(Step::Print(text)) => {
+                println!("{}", text);
+                Ok(())
+            }
+            Ok(Step::Preview(cli)) => crate::preview(&cli),
+            Ok(Step::Save(cli)) => crate::render(&cli).map(|()| {
+                if !cli.quiet {
+                    eprintln!("saved {}", cli.output);
+                }
+            }),
+            Ok(Step::Quit) => return Ok(()),
+            Err(message) => Err(MandelbrotError::Parse(message)),
+        };
+        if let Err(error) = done {
+            eprintln!("error: {}", error);
+        }
+    }
+}
+
+/// Carries out one command line on `session`.
+fn step(session: &mut Session, line: &str) -> Result<Step, String> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    Ok(match words[..] {
+        [] => Step::Nothing,
+        ["center", re, im] => return step(session, &format!("center {},{}", re, im)),
+        ["center", point] => {
+            session.view.center =
+                parse_exact_complex(point).ok_or_else(|| format!("invalid center '{}'", point))?;
+            Step::Nothing
+        }
+        ["zoom", factor] => {
+            let factor = factor
+                .parse::<f64>()
+                .ok()
+                .filter(|factor| *factor > 0.0)
+                .ok_or_else(|| format!("invalid zoom factor '{}'", factor))?;
+            session.zoom_by(factor)?;
+            Step::Nothing
+        }
+        ["iter", n] => {
+            session.set("max-iter", Some(n))?;
+            Step::Nothing
+        }
+        ["size", size] => {
+            session.set("size", Some(size))?;
+            Step::Nothing
+        }
+        ["set", flag, ref value @ ..] => {
+            session.set(flag, Some(&value.join(" ")))?;
+            Step::Nothing
+        }
+        ["unset", flag] => {
+            session.set(flag, None)?;
+            Step::Nothing
+        }
+        ["render", "preview"] | ["preview"] => Step::Preview(Box::new(session.frame("-")?)),
+        ["save", path] => Step::Save(Box::new(session.frame(path)?)),
+        ["show"] => Step::Print(show(&session.frame("-")?)),
+        ["help"] => Step::Print(HELP.to_string()),
+        ["quit" | "exit"] => Step::Quit,
+        [command @ ("center" | "zoom" | "iter" | "size" | "set" | "unset" | "render" | "save"
+        | "show"), ..] => return Err(format!("wrong arguments to '{}'; try 'help'", command)),
+        [command, ..] => return Err(format!("unknown command '{}'; try 'help'", command)),
+    })
+}
+
+/// The view of a render and its size, for `show`.
+fn show(cli: &Cli) -> String {
+    let options = &cli.options;
+    let (upper_left, lower_right) = options.exact_corners();
+    // Enough digits to place every pixel to within a thousandth.
+    let digits = (-(options.pixel_size() / 1000.0).log10()).ceil().max(0.0) as usize;
+    let center = Complex {
+        re: (&upper_left.re + &lower_right.re).scale(1, 2),
+        im: (&upper_left.im + &lower_right.im).scale(1, 2),
+    };
+    let height = (&upper_left.im - &lower_right.im).to_f64();
+    format!(
+        "center {} {}\nzoom {:e}\nsize {}x{}\niter {}",
+        center.re.to_decimal(digits),
+        center.im.to_decimal(digits),
+        4.0 / height.abs(),
+        options.bounds.0,
+        options.bounds.1,
+        options.max_iter
+    )
+}
+
+#[test]
+fn test_step() {
+    use crate::cli::{parse_args, Command};
+    let args = ["repl", "--size", "40x30", "-i", "100"].map(String::from);
+    let Ok(Command::Repl(session)) = parse_args(&args) else {
+        panic!("repl didn't parse");
+    };
+    let mut session = *session;
+    let pixel_size = session.frame("-").unwrap().options.pixel_size();
+    let mut run = |line: &str| step(&mut session, line);
+    assert_eq!(run("  "), Ok(Step::Nothing));
+    run("center -0.75 0.1").unwrap();
+    run("zoom 5").unwrap();
+    run("zoom 2").unwrap();
+    run("iter 2000").unwrap();
+    run("set palette fire").unwrap();
+    let Ok(Step::Print(text)) = run("show") else {
+        panic!("show printed nothing");
+    };
+    assert!(text.starts_with("center -0.75"), "{}", text);
+    assert!(text.contains("\nzoom 1"), "{}", text);
+    assert!(text.ends_with("size 40x30\niter 2000"), "{}", text);
+    let Ok(Step::Save(cli)) = run("save deep.jpg") else {
+        panic!("save didn't render");
+    };
+    assert_eq!(cli.output, "deep.jpg");
+    assert_eq!(cli.format, mandelbrot::Format::Jpeg);
+    assert_eq!(cli.options.max_iter, 2000);
+    assert_eq!(
+        cli.options.palette,
+        mandelbrot::Palette::named("fire").unwrap()
+    );
+    assert!((cli.options.pixel_size() * 10.0 / pixel_size - 1.0).abs() < 1e-9);
+    // Commands that fail leave the session be.
+    for bad in [
+        "zoom 0",
+        "zoom -1",
+        "zoom 1e400",
+        "zoom 1e-300",
+        "iter x",
+        "center a b",
+        "set output a.png",
+        "fly",
+        "save",
+    ] {
+        assert!(run(bad).is_err(), "{}", bad);
+    }
+    run("unset palette").unwrap();
+    assert!(matches!(run("render preview"), Ok(Step::Preview(_))));
+    assert_eq!(run("quit"), Ok(Step::Quit));
+
+    // The Burning Ship is upside down, but still at a positive zoom.
+    let args = ["repl", "--size", "40x30", "--fractal", "burning-ship"].map(String::from);
+    let Ok(Command::Repl(session)) = parse_args(&args) else {
+        panic!("repl didn't parse");
+    };
+    let mut session = *session;
+    let Ok(Step::Print(text)) = step(&mut session, "show") else {
+        panic!("show printed nothing");
+    };
+    assert!(text.contains("\nzoom 1e0"), "{}", text);
+    step(&mut session, "zoom 2").unwrap();
+    assert!(matches!(
+        step(&mut session, "save ship.png"),
+        Ok(Step::Save(_))
+    ));
+}
@@ -0,0 +1,315 @@
+//! A small HTTP server handing out XYZ map tiles of the set, so that it can
+//! be explored with Leaflet or OpenLayers. Tile `0/0/0` shows the whole set,
+//! 4 wide and 4 high about -0.5; every zoom level splits each tile in four.
+//! `/` serves a page with a Leaflet map of the tiles.
+
+use crate::cli::Server;
+use mandelbrot::{animation::View, encode_image, metadata, Fixed, MandelbrotError, Renderer};
+use num::Complex;
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+/// Width and height of a tile in pixels.
+pub const TILE_SIZE: u32 = 256;
+
+/// The deepest zoom level served. Tile corners are exact in `f64` up to
+/// here, and the render itself switches to arbitrary precision as needed.
+pub const MAX_ZOOM: u32 = 52;
+
+/// How many encoded tiles are kept in memory.
+const CACHE_TILES: usize = 1024;
+
+/// How many connections are answered at once; more wait their turn.
+const CONNECTIONS: usize = 8;
+
+/// How long a client may take to send a request or to take the answer,
+/// before its connection is dropped to make way for others.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most bytes of a request read, from the request line to the end of
+/// the headers. A tile request needs a fraction of this.
+const MAX_REQUEST: u64 = 16 * 1024;
+
+/// The position of a tile: zoom level, column and row from the top left.
+type Tile = (u32, u64, u64);
+
+const INDEX: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>mandelbrot</title>
+<link rel="stylesheet" href="https://unpkg.com/leaflet@1.9.4/dist/leaflet.css">
+<script src="https://unpkg.com/leaflet@1.9.4/dist/leaflet.js"></script>
+<style>html, body, #map { height: 100%; margin: 0; background: #000; }</style>
+</head>
+<body>
+<div id="map"></div>
+<script>
+const bounds = [[-256, 0], [0, 256]];
+const map = L.map("map", { crs: L.CRS.Simple, maxZoom: 40 });
+L.tileLayer("/tiles/{z}/{x}/{y}.png", { noWrap: true, bounds, maxZoom: 40 }).addTo(map);
+map.fitBounds(bounds);
+</script>
+</body>
+</html>
+"#;
+
+/// Serves tiles on localhost until the process is stopped.
+pub fn serve(server: &Server) -> Result<(), MandelbrotError> {
+    let address = format!("127.0.0.1:{}", server.port);
+    let listener = TcpListener::bind(&address)
+        .map_err(|e| MandelbrotError::Io(format!("{}: {}", address, e)))?;
+    if !server.frame.quiet {
+        eprintln!("serving tiles on http://{}/", address);
+    }
+    let cache = Mutex::new(Cache::new(CACHE_TILES));
+    let (sender, streams) = crossbeam::channel::bounded::<TcpStream>(CONNECTIONS);
+    thread::scope(|scope| {
+        for _ in 0..CONNECTIONS {
+            let (streams, cache) = (streams.clone(), &cache);
+            scope.spawn(move || {
+                for stream in streams {
+                    // A client going away mid-request is its own business.
+                    let _ = respond(stream, server, cache);
+                }
+            });
+        }
+        for stream in listener.incoming().flatten() {
+            let _ = sender.send(stream);
+        }
+    });
+    Ok(())
+}
+
+/// What a request asks for.
+#[derive(Debug, PartialEq)]
+enum Route {
+    Index,
+    Tile(Tile),
+    NotFound,
+}
+
+/// Routes the path of a `GET` request, ignoring any query string.
+fn route(path: &str) -> Route {
+    let path = path.split('?').next().unwrap_or_default();
+    if path == "/" {
+        return Route::Index;
+    }
+    let tile = path
+        .strip_prefix("/tiles/")
+        .and_then(|rest| rest.strip_suffix(".png"))
+        .and_then(|rest| {
+            let mut parts = rest.split('/');
+            let tile = (
+                parts.next()?.parse::<u32>().ok()?,
+                parts.next()?.parse::<u64>().ok()?,
+                parts.next()?.parse::<u64>().ok()?,
+            );
+            parts.next().is_none().then_some(tile)
+        });
+    match tile {
+        Some((z, x, y)) if z <= MAX_ZOOM && x >> z == 0 && y >> z == 0 => Route::Tile((z, x, y)),
+        _ => Route::NotFound,
+    }
+}
+
+/// The view of a tile: the one at zoom level `z` is `2^z` times closer
+/// than the whole set.
+fn tile_view((z, x, y): Tile) -> View {
+    // Tile centers are odd multiples of half a tile, which is 2^(1 - z).
+    let half = (1.0 - z as f64).exp2();
+    let bits = z + 2;
+    let coordinate = |origin: f64, offset: f64| {
+        &Fixed::from_f64(origin, bits) + &Fixed::from_f64(offset * half, bits)
+    };
+    View {
+        center: Complex {
+            re: coordinate(-2.5, (2 * x + 1) as f64),
+            im: coordinate(2.0, -((2 * y + 1) as f64)),
+        },
+        zoom: (z as f64).exp2(),
+        flipped: false,
+    }
+}
+
+/// Reads the request line of a request and skips its headers, or `None`
+/// if the request line runs past `MAX_REQUEST`.
+fn read_request<R: Read>(r: R) -> io::Result<Option<String>> {
+    let mut reader = BufReader::new(r.take(MAX_REQUEST));
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    if !request.ends_with('\n') {
+        return Ok(None);
+    }
+    // The headers say nothing a tile depends on.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    Ok(Some(request))
+}
+
+fn respond(stream: TcpStream, server: &Server, cache: &Mutex<Cache>) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut out = &stream;
+    let Some(request) = read_request(&stream)? else {
+        return write_response(
+            &mut out,
+            "414 URI Too Long",
+            "text/plain",
+            b"request too long\n",
+        );
+    };
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or_default(), parts.next());
+    match (method, path.map(route)) {
+        ("GET", Some(Route::Index)) => {
+            write_response(&mut out, "200 OK", "text/html", INDEX.as_bytes())
+        }
+        ("GET", Some(Route::Tile(tile))) => {
+            let cached = cache.lock().unwrap().get(tile).map(<[u8]>::to_vec);
+            // Rendered unlocked, so that other tiles needn't wait for it.
+            let png = match cached {
+                Some(png) => png,
+                None => {
+                    let png = render_tile(server, tile)?;
+                    cache.lock().unwrap().insert(tile, png.clone());
+                    png
+                }
+            };
+            write_response(&mut out, "200 OK", "image/png", &png)
+        }
+        ("GET", _) => write_response(&mut out, "404 Not Found", "text/plain", b"not found\n"),
+        _ => write_response(
+            &mut out,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"only GET is supported\n",
+        ),
+    }
+}
+
+fn render_tile(server: &Server, tile: Tile) -> io::Result<Vec<u8>> {
+    let mut options = server.frame.options.clone();
+    tile_view(tile).apply(&mut options);
+    let colors = Renderer::new(options.clone()).render();
+    let mut png = Vec::new();
+    encode_image(
+        &mut png,
+        &colors,
+        options.bounds,
+        &metadata::describe(&options),
+    )
+    .map_err(io::Error::other)?;
+    Ok(png)
+}
+
+fn write_response<W: Write>(w: &mut W, status: &str, kind: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        w,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        kind,
+        body.len()
+    )?;
+    w.write_all(body)?;
+    w.flush()
+}
+
+/// Encoded tiles, dropping the least recently used once full.
+struct Cache {
+    capacity: usize,
+    tiles: HashMap<Tile, (Vec<u8>, u64)>,
+    clock: u64,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Cache {
+        Cache {
+            capacity,
+            tiles: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, tile: Tile) -> Option<&[u8]> {
+        self.clock += 1;
+        let (png, used) = self.tiles.get_mut(&tile)?;
+        *used = self.clock;
+        Some(png)
+    }
+
+    fn insert(&mut self, tile: Tile, png: Vec<u8>) {
+        if self.tiles.len() >= self.capacity && !self.tiles.contains_key(&tile) {
+            let oldest = self.tiles.iter().min_by_key(|(_, (_, used))| *used);
+            if let Some(&oldest) = oldest.map(|(tile, _)| tile) {
+                self.tiles.remove(&oldest);
+            }
+        }
+        self.clock += 1;
+        self.tiles.insert(tile, (png, self.clock));
+    }
+}
+
+#[test]
+fn test_route() {
+    assert_eq!(route("/"), Route::Index);
+    assert_eq!(route("/tiles/0/0/0.png"), Route::Tile((0, 0, 0)));
+    assert_eq!(route("/tiles/3/7/2.png?v=1"), Route::Tile((3, 7, 2)));
+    assert_eq!(route("/tiles/3/8/2.png"), Route::NotFound);
+    assert_eq!(route("/tiles/3/7/2.jpg"), Route::NotFound);
+    assert_eq!(route("/tiles/3/7/2/1.png"), Route::NotFound);
+    assert_eq!(route("/tiles/99/0/0.png"), Route::NotFound);
+    assert_eq!(route("/favicon.ico"), Route::NotFound);
+}
+
+#[test]
+fn test_tile_view() {
+    let center = |tile| {
+        let view = tile_view(tile);
+        (view.center.re.to_f64(), view.center.im.to_f64(), view.zoom)
+    };
+    assert_eq!(center((0, 0, 0)), (-0.5, 0.0, 1.0));
+    assert_eq!(center((1, 0, 0)), (-1.5, 1.0, 2.0));
+    assert_eq!(center((1, 1, 1)), (0.5, -1.0, 2.0));
+    assert_eq!(center((3, 2, 5)), (-1.25, -0.75, 8.0));
+    // Deep tiles are still exactly in place.
+    let deep = tile_view((MAX_ZOOM, 1, 0));
+    let tile = 4.0 / (MAX_ZOOM as f64).exp2();
+    assert_eq!(
+        (&deep.center.re - &Fixed::from_f64(-2.5, MAX_ZOOM + 2)).to_f64(),
+        tile * 1.5
+    );
+}
+
+#[test]
+fn test_cache() {
+    let mut cache = Cache::new(2);
+    cache.insert((0, 0, 0), vec![0]);
+    cache.insert((1, 0, 0), vec![1]);
+    assert_eq!(cache.get((0, 0, 0)), Some(&[0][..]));
+    cache.insert((1, 1, 0), vec![2]);
+    assert_eq!(cache.get((1, 0, 0)), None);
+    assert_eq!(cache.get((0, 0, 0)), Some(&[0][..]));
+    assert_eq!(cache.get((1, 1, 0)), Some(&[2][..]));
+}
+
+#[test]
+fn test_read_request() {
+    let request = "GET /tiles/0/0/0.png HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    assert_eq!(
+        read_request(request.as_bytes()).unwrap().as_deref(),
+        Some("GET /tiles/0/0/0.png HTTP/1.1\r\n")
+    );
+    // An endless request line is cut off rather than read on.
+    assert_eq!(read_request(io::repeat(b'a')).unwrap(), None);
+    let endless = request.as_bytes().chain(io::repeat(b'a'));
+    assert!(read_request(endless).unwrap().is_some());
+}
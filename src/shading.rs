@@ -0,0 +1,132 @@
+//! Slope shading. The smooth escape counts of the pixels are taken as the
+//! heights of a surface, rising towards the set, whose normals come from
+//! the differences between neighboring pixels. Lighting it with a diffuse
+//! and a specular term from a distant light embosses the image, so that
+//! the bands and filaments around the set stand out in relief. Points
+//! inside the set have no height and keep their colors.
+
+use crate::Escape;
+
+/// The share of each color that is lit whatever the slope.
+const AMBIENT: f64 = 0.35;
+/// The share of each color lit by how directly the surface faces the light.
+const DIFFUSE: f64 = 0.65;
+/// How bright highlights get, as a share of white.
+const SPECULAR: f64 = 0.3;
+/// How tight highlights are; higher is glossier.
+const SHININESS: i32 = 16;
+
+/// Where the light comes from: `azimuth` degrees counterclockwise from the
+/// right of the image, `elevation` degrees above it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub azimuth: f64,
+    pub elevation: f64,
+}
+
+impl Light {
+    /// The unit vector towards the light, with `x` to the right, `y` down
+    /// the image and `z` out of it.
+    fn direction(&self) -> [f64; 3] {
+        let (azimuth, elevation) = (self.azimuth.to_radians(), self.elevation.to_radians());
+        [
+            elevation.cos() * azimuth.cos(),
+            -elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+        ]
+    }
+}
+
+/// Lights the RGB `pixels` of a `bounds` image by the slopes of `escapes`,
+/// one per pixel in the same order, of a fractal of `degree`.
+pub fn shade(
+    pixels: &mut [u8],
+    escapes: &[Option<Escape>],
+    bounds: (u32, u32),
+    degree: f64,
+    light: Light,
+) {
+    let (width, height) = (bounds.0 as usize, bounds.1 as usize);
+    let heights = escapes
+        .iter()
+        .map(|escape| escape.map(|e| e.smooth_with(degree)))
+        .collect::<Vec<_>>();
+    let to_light = light.direction();
+    // Halfway between the light and a viewer straight above.
+    let halfway = normalize([to_light[0], to_light[1], to_light[2] + 1.0]);
+    for row in 0..height {
+        for column in 0..width {
+            let index = row * width + column;
+            let Some(center) = heights[index] else {
+                continue;
+            };
+            // Neighbors outside the image or inside the set take the
+            // center's height.
+            let at = |x: Option<usize>, y: Option<usize>| match (x, y) {
+                (Some(x), Some(y)) if x < width && y < height => {
+                    heights[y * width + x].unwrap_or(center)
+                }
+                _ => center,
+            };
+            let slope_x =
+                (at(Some(column + 1), Some(row)) - at(column.checked_sub(1), Some(row))) / 2.0;
+            let slope_y =
+                (at(Some(column), Some(row + 1)) - at(Some(column), row.checked_sub(1))) / 2.0;
+            let normal = normalize([-slope_x, -slope_y, 1.0]);
+            let diffuse = dot(normal, to_light).max(0.0);
+            let specular = dot(normal, halfway).max(0.0).powi(SHININESS);
+            for channel in &mut pixels[3 * index..3 * index + 3] {
+                let lit =
+                    *channel as f64 * (AMBIENT + DIFFUSE * diffuse) + 255.0 * SPECULAR * specular;
+                *channel = lit.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f64; 3]) -> [f64; 3] {
+    let length = dot(v, v).sqrt();
+    v.map(|x| x / length)
+}
+
+#[test]
+fn test_shade() {
+    let escape = |iterations| {
+        Some(Escape {
+            iterations,
+            z: num::Complex::new(2.0, 0.0),
+            derivative: None,
+            stripe: None,
+        })
+    };
+    // A ramp rising to the right up to a point of the set, lit from the
+    // right and from the left.
+    let escapes = [escape(1), escape(11), escape(21), None];
+    let light = |azimuth| Light {
+        azimuth,
+        elevation: 30.0,
+    };
+    let mut from_right = [100; 12];
+    shade(&mut from_right, &escapes, (4, 1), 2.0, light(0.0));
+    let mut from_left = [100; 12];
+    shade(&mut from_left, &escapes, (4, 1), 2.0, light(180.0));
+    assert_eq!(&from_right[9..], &[100; 3]);
+    // Slopes facing away from the light are darker.
+    assert!(
+        from_left[3] > from_right[3],
+        "{:?} {:?}",
+        from_left,
+        from_right
+    );
+
+    // A flat surface is lit by the elevation alone.
+    let mut flat = [200; 3];
+    shade(&mut flat, &[escape(5)], (1, 1), 2.0, light(0.0));
+    let diffuse = 200.0 * (AMBIENT + DIFFUSE * 0.5);
+    let specular = 255.0 * SPECULAR * 30f64.to_radians().cos().powi(SHININESS);
+    assert_eq!(flat[0], (diffuse + specular).round() as u8);
+}
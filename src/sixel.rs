@@ -0,0 +1,92 @@
+//! Sixel graphics, which terminals such as xterm, mlterm and WezTerm draw
+//! inline, even over SSH. The image is reduced to a palette of up to 256
+//! colors, then sent in bands six pixels high: for each color in the band,
+//! one character per column whose bits say which of the six pixels take it.
+
+use crate::gif;
+use std::io::{self, Write};
+
+/// Encodes an RGB pixel buffer as a sixel sequence.
+pub fn encode<W: Write>(mut w: W, pixels: &[u8], bounds: (u32, u32)) -> io::Result<()> {
+    let (width, height) = (bounds.0 as usize, bounds.1 as usize);
+    let (palette, indices) = gif::quantize(pixels, gif::MAX_COLORS);
+    // Square pixels, and a raster size so that the terminal can make room.
+    write!(w, "\x1bP0;1;0q\"1;1;{};{}", width, height)?;
+    for (i, [r, g, b]) in palette.iter().enumerate() {
+        let percent = |v: &u8| (*v as u32 * 100 + 127) / 255;
+        write!(w, "#{};2;{};{};{}", i, percent(r), percent(g), percent(b))?;
+    }
+    let mut sixels = vec![0u8; width];
+    for top in (0..height).step_by(6) {
+        let rows = top..(top + 6).min(height);
+        let mut used = vec![false; palette.len()];
+        for row in rows.clone() {
+            for &index in &indices[row * width..(row + 1) * width] {
+                used[index as usize] = true;
+            }
+        }
+        let mut first = true;
+        for color in (0..palette.len()).filter(|&color| used[color]) {
+            sixels.fill(0);
+            for row in rows.clone() {
+                let line = &indices[row * width..(row + 1) * width];
+                for (sixel, &index) in sixels.iter_mut().zip(line) {
+                    if index as usize == color {
+                        *sixel |= 1 << (row - top);
+                    }
+                }
+            }
+            // Each color after the first goes back over the same band.
+            if !first {
+                w.write_all(b"$")?;
+            }
+            first = false;
+            write!(w, "#{}", color)?;
+            write_runs(&mut w, &sixels)?;
+        }
+        w.write_all(b"-")?;
+    }
+    w.write_all(b"\x1b\\")?;
+    w.flush()
+}
+
+/// Writes one color's sixels for a band, run length encoded.
+fn write_runs<W: Write>(w: &mut W, sixels: &[u8]) -> io::Result<()> {
+    let mut rest = sixels;
+    while let Some(&sixel) = rest.first() {
+        let run = rest.iter().take_while(|&&s| s == sixel).count();
+        let char = (b'?' + sixel) as char;
+        if run > 3 {
+            write!(w, "!{}{}", run, char)?;
+        } else {
+            write!(w, "{}", char.to_string().repeat(run))?;
+        }
+        rest = &rest[run..];
+    }
+    Ok(())
+}
+
+#[test]
+fn test_encode() {
+    // Two columns, seven rows: red on the left, blue on the right, so the
+    // second band holds just the last row.
+    let mut pixels = Vec::new();
+    for _ in 0..7 {
+        pixels.extend_from_slice(&[255, 0, 0, 0, 0, 255]);
+    }
+    let mut out = Vec::new();
+    encode(&mut out, &pixels, (2, 7)).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.starts_with("\x1bP0;1;0q\"1;1;2;7"));
+    assert!(text.ends_with("\x1b\\"));
+    assert!(text.contains("#0;2;100;0;0#1;2;0;0;100"), "{}", text);
+    // Full sixels are '~', the seventh row alone is '@'.
+    assert!(text.contains("#0~?$#1?~-#0@?$#1?@-"), "{}", text);
+}
+
+#[test]
+fn test_write_runs() {
+    let mut out = Vec::new();
+    write_runs(&mut out, &[63, 63, 63, 63, 63, 0, 1, 1]).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "!5~?@@");
+}
@@ -0,0 +1,188 @@
+//! Machine-readable metrics of a render, for profiling and for tracking
+//! performance across versions. They are written as a JSON object:
+//!
+//! ```json
+//! {
+//!   "software": "mandelbrot 0.1.0",
+//!   "size": [800, 600],
+//!   "precision": "f64",
+//!   "max_iter": 1000,
+//!   "wall_seconds": 0.412,
+//!   "thread_seconds": [0.405, 0.398],
+//!   "iterations": 52113204,
+//!   "iterations_per_second": 126488359.2,
+//!   "histogram": { "bin_width": 10, "escaped": [5120, 2210, ...], "inside": 61203 },
+//!   "peak_memory_bytes": 14680064
+//! }
+//! ```
+//!
+//! `thread_seconds` is empty for renders on one thread, and
+//! `peak_memory_bytes` is `null` where the system doesn't tell.
+
+use crate::{
+    metadata::{json_string, SOFTWARE},
+    Escape, RenderOptions,
+};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// How many bins the histogram of escape times has at most.
+pub const BINS: u32 = 100;
+
+/// The metrics of one render.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub bounds: (u32, u32),
+    pub precision: String,
+    pub max_iter: u32,
+    /// How long the escape times took to compute.
+    pub wall: Duration,
+    /// How long each thread spent computing; see `Progress::busy`.
+    pub busy: Vec<Duration>,
+    /// The iterations the escape times stand for: each escaped point's, and
+    /// the limit for each point in the set. The bulb and periodicity checks
+    /// let some points in the set stop early, so this can be more than were
+    /// actually run.
+    pub iterations: u64,
+    /// How many iterations each bin of `escaped` spans.
+    pub bin_width: u32,
+    /// How many points escaped after each `bin_width` iterations.
+    pub escaped: Vec<u64>,
+    /// How many points are in the set.
+    pub inside: u64,
+    /// The most memory the process held at once, in bytes, if known.
+    pub peak_memory: Option<u64>,
+}
+
+impl Stats {
+    /// The metrics of the render of `escapes` with `options`, which took
+    /// `wall`, with `busy` from its progress.
+    pub fn new(
+        options: &RenderOptions,
+        escapes: &[Option<Escape>],
+        wall: Duration,
+        busy: Vec<Duration>,
+        peak_memory: Option<u64>,
+    ) -> Stats {
+        let max_iter = options.max_iter;
+        let bin_width = max_iter.div_ceil(BINS).max(1);
+        let mut escaped = vec![0; max_iter.div_ceil(bin_width).max(1) as usize];
+        let last = escaped.len() - 1;
+        let mut inside = 0;
+        let mut iterations = 0;
+        for escape in escapes {
+            match escape {
+                Some(escape) => {
+                    let bin = (escape.iterations / bin_width) as usize;
+                    escaped[bin.min(last)] += 1;
+                    iterations += escape.iterations as u64;
+                }
+                None => {
+                    inside += 1;
+                    iterations += max_iter as u64;
+                }
+            }
+        }
+        Stats {
+            bounds: options.bounds,
+            precision: options.resolved_precision().to_string(),
+            max_iter,
+            wall,
+            busy,
+            iterations,
+            bin_width,
+            escaped,
+            inside,
+            peak_memory,
+        }
+    }
+
+    /// Iterations a second over the wall time.
+    pub fn iterations_per_second(&self) -> f64 {
+        self.iterations as f64 / self.wall.as_secs_f64().max(1e-9)
+    }
+
+    /// Writes the metrics as JSON, laid out as in the module documentation.
+    pub fn write_json<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let list = |items: Vec<String>| items.join(", ");
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"software\": {},", json_string(SOFTWARE))?;
+        writeln!(w, "  \"size\": [{}, {}],", self.bounds.0, self.bounds.1)?;
+        writeln!(w, "  \"precision\": {},", json_string(&self.precision))?;
+        writeln!(w, "  \"max_iter\": {},", self.max_iter)?;
+        writeln!(w, "  \"wall_seconds\": {},", self.wall.as_secs_f64())?;
+        writeln!(
+            w,
+            "  \"thread_seconds\": [{}],",
+            list(
+                self.busy
+                    .iter()
+                    .map(|busy| busy.as_secs_f64().to_string())
+                    .collect()
+            )
+        )?;
+        writeln!(w, "  \"iterations\": {},", self.iterations)?;
+        writeln!(
+            w,
+            "  \"iterations_per_second\": {:.1},",
+            self.iterations_per_second()
+        )?;
+        writeln!(
+            w,
+            "  \"histogram\": {{ \"bin_width\": {}, \"escaped\": [{}], \"inside\": {} }},",
+            self.bin_width,
+            list(self.escaped.iter().map(u64::to_string).collect()),
+            self.inside
+        )?;
+        match self.peak_memory {
+            Some(bytes) => writeln!(w, "  \"peak_memory_bytes\": {}", bytes)?,
+            None => writeln!(w, "  \"peak_memory_bytes\": null")?,
+        }
+        writeln!(w, "}}")
+    }
+}
+
+#[test]
+fn test_stats() {
+    let options = RenderOptions {
+        bounds: (2, 2),
+        max_iter: 250,
+        ..RenderOptions::default()
+    };
+    let escape = |iterations| {
+        Some(Escape {
+            iterations,
+            z: num::Complex::new(3.0, 0.0),
+            derivative: None,
+            stripe: None,
+        })
+    };
+    let escapes = [escape(1), escape(4), None, escape(249)];
+    let stats = Stats::new(
+        &options,
+        &escapes,
+        Duration::from_millis(500),
+        vec![Duration::from_millis(250); 2],
+        None,
+    );
+    assert_eq!(stats.iterations, 1 + 4 + 250 + 249);
+    assert_eq!(stats.iterations_per_second(), 1008.0);
+    assert_eq!(stats.bin_width, 3);
+    assert_eq!(stats.escaped.len(), 84);
+    assert_eq!(
+        (stats.escaped[0], stats.escaped[1], stats.escaped[83]),
+        (1, 1, 1)
+    );
+    assert_eq!(stats.inside, 1);
+    let mut json = Vec::new();
+    stats.write_json(&mut json).unwrap();
+    let json = String::from_utf8(json).unwrap();
+    assert!(json.contains("\"size\": [2, 2],"));
+    assert!(json.contains("\"thread_seconds\": [0.25, 0.25],"));
+    assert!(json.contains("\"iterations_per_second\": 1008.0,"));
+    assert!(json.contains("\"inside\": 1 },"));
+    assert!(json.ends_with("\"peak_memory_bytes\": null\n}\n"));
+    assert_eq!(json_string("a \"b\"\\\n"), "\"a \\\"b\\\"\\\\\\u000a\"");
+}
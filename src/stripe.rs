@@ -0,0 +1,80 @@
+//! Stripe average coloring. Each step of an escaping orbit adds
+//! `sin(k arg z) / 2 + 1/2` to a running average, which winds through the
+//! palette as the orbit turns and gives the woven look of stripes
+//! following the set's filaments. The average is blended with the one
+//! before the last step by the fractional part of the smooth escape count,
+//! so that the stripes run on across the escape count bands.
+
+use crate::{orbit::OrbitStatistic, Escape, Fractal};
+use num::Complex;
+
+/// The `k` stripes go through for each turn of `z` around 0.
+pub const DENSITY: f64 = 5.0;
+
+/// The running average of an orbit's stripes.
+#[derive(Debug)]
+pub struct StripeAverage {
+    sum: f64,
+    last: f64,
+    count: u32,
+    /// The fractal's degree, for its smooth escape counts.
+    degree: f64,
+}
+
+impl OrbitStatistic for StripeAverage {
+    fn start(fractal: &Fractal, _julia: bool) -> StripeAverage {
+        StripeAverage {
+            sum: 0.0,
+            last: 0.0,
+            count: 0,
+            degree: fractal.degree(),
+        }
+    }
+
+    fn step(&mut self, _z: Complex<f64>, next: Complex<f64>) {
+        self.last = (DENSITY * next.arg()).sin() / 2.0 + 0.5;
+        self.sum += self.last;
+        self.count += 1;
+    }
+
+    fn finish(self, escape: &mut Escape) {
+        if self.count == 0 {
+            return;
+        }
+        let average = self.sum / self.count as f64;
+        let before = if self.count > 1 {
+            (self.sum - self.last) / (self.count - 1) as f64
+        } else {
+            average
+        };
+        let fraction = (escape.smooth_with(self.degree) - escape.iterations as f64).clamp(0.0, 1.0);
+        escape.stripe = Some(before + fraction * (average - before));
+    }
+}
+
+#[test]
+fn test_stripe_average() {
+    // 3i escapes after one step, a quarter turn around 0: the top of a
+    // stripe.
+    let c = Complex::new(0.0, 3.0);
+    let mut escapes = [crate::escape_time(c, 100)];
+    crate::orbit::add::<StripeAverage>(&[c], &crate::Fractal::Mandelbrot, None, &mut escapes);
+    assert!((escapes[0].unwrap().stripe.unwrap() - 1.0).abs() < 1e-12);
+
+    // ln |z| = √2 puts the smooth count halfway into the band, halfway
+    // between 0 before the last step and 1/2 after it.
+    let mut escape = Escape {
+        iterations: 2,
+        z: Complex::new(2f64.sqrt().exp(), 0.0),
+        derivative: None,
+        stripe: None,
+    };
+    let average = StripeAverage {
+        sum: 1.0,
+        last: 1.0,
+        count: 2,
+        degree: 2.0,
+    };
+    average.finish(&mut escape);
+    assert!((escape.stripe.unwrap() - 0.25).abs() < 1e-12);
+}
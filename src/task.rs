@@ -0,0 +1,140 @@
+//! Renders as futures, for async frontends such as servers and windows
+//! that can't block their executor while a render runs. A task runs on a
+//! thread of its own and wakes whoever awaits it when it is done, so it
+//! works with any executor, and needs no runtime of its own. Rendering
+//! itself still spreads over `RenderOptions::threads` threads.
+
+use crate::Progress;
+use std::{
+    any::Any,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+/// Work running on a thread of its own, as a future of its result. It
+/// panics when awaited if the work did.
+pub struct Task<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+    /// The render to cancel if the task is dropped before it is done.
+    progress: Option<Arc<Progress>>,
+}
+
+struct Shared<T> {
+    result: Option<Result<T, Box<dyn Any + Send>>>,
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// Runs `work` on a thread of its own.
+pub fn spawn<T, F>(work: F) -> Task<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        done: false,
+        waker: None,
+    }));
+    let theirs = shared.clone();
+    thread::spawn(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(work));
+        let mut shared = theirs.lock().unwrap();
+        shared.result = Some(result);
+        shared.done = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+    Task {
+        shared,
+        progress: None,
+    }
+}
+
+impl<T> Task<T> {
+    /// The task, cancelling the render of `progress` if it is dropped
+    /// before it is done, as futures are when no longer wanted.
+    pub fn cancelling(mut self, progress: Arc<Progress>) -> Task<T> {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Whether the work is done, so that awaiting it won't wait.
+    pub fn is_done(&self) -> bool {
+        self.shared.lock().unwrap().done
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.result.take() {
+            Some(Ok(result)) => Poll::Ready(result),
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        if let Some(progress) = &self.progress {
+            if !self.shared.lock().unwrap().done {
+                progress.cancel();
+            }
+        }
+    }
+}
+
+/// Runs `future` to the end on the calling thread, sleeping while it waits.
+#[cfg(test)]
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(thread::Thread);
+    impl std::task::Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn test_render_async() {
+    use crate::{RenderOptions, Renderer};
+    let options = RenderOptions {
+        bounds: (30, 20),
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let whole = Renderer::new(options.clone()).render();
+    let task = Renderer::new(options.clone()).render_async();
+    assert_eq!(block_on(task), whole);
+    let renderer = Renderer::new(RenderOptions {
+        bounds: (2000, 2000),
+        ..options
+    });
+    let progress = renderer.progress();
+    let task = renderer.render_async();
+    drop(task);
+    assert!(progress.is_cancelled());
+    let failed = spawn(|| panic!("no render"));
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| block_on(failed))).is_err());
+}
@@ -0,0 +1,377 @@
+//! TIFF images, which many print workflows want, and which can go past the
+//! 4 GB that PNG readers give up at. They are RGB, 8 bits a sample and
+//! uncompressed, so that where every strip of rows lands is known before a
+//! pixel is rendered: the header points past the pixels to the image file
+//! directory, and the rows stream out in between as they are done.
+//!
+//! A classic TIFF's offsets are 32 bits; a file that won't fit under 4 GB
+//! is written as a BigTIFF instead, whose offsets are 64 bits, as libtiff
+//! and the programs built on it read. The text of a render goes in the
+//! `ImageDescription`, a `keyword: value` line each, but for `Software`,
+//! which has a tag of its own.
+
+use std::io::{self, Write};
+
+/// About how many bytes a strip holds, when its rows are short enough.
+const STRIP_BYTES: u64 = 1 << 16;
+
+/// The tags written, in the ascending order a directory lists them in.
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const IMAGE_DESCRIPTION: u16 = 270;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+const SOFTWARE: u16 = 305;
+
+/// The values of a tag.
+enum Field {
+    Ascii(String),
+    Short(Vec<u16>),
+    Long(Vec<u32>),
+    /// BigTIFF's 64-bit unsigned integers.
+    Long8(Vec<u64>),
+}
+
+impl Field {
+    /// The field's type, its count of values and the values in little
+    /// endian.
+    fn encode(&self) -> (u16, u64, Vec<u8>) {
+        match self {
+            Field::Ascii(text) => {
+                let mut bytes = text.as_bytes().to_vec();
+                bytes.push(0);
+                (2, bytes.len() as u64, bytes)
+            }
+            Field::Short(values) => (
+                3,
+                values.len() as u64,
+                values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            ),
+            Field::Long(values) => (
+                4,
+                values.len() as u64,
+                values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            ),
+            Field::Long8(values) => (
+                16,
+                values.len() as u64,
+                values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            ),
+        }
+    }
+}
+
+/// Writes a TIFF a few rows at a time, so that an image never has to be
+/// held whole; see `encode` for one that is.
+pub struct Encoder<W: Write> {
+    w: W,
+    /// The bytes of pixels the image has, and has been given so far.
+    pixels: u64,
+    written: u64,
+    /// The directory, padded to start on a word boundary, to write after
+    /// the pixels.
+    directory: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes the header of a TIFF of `bounds` pixels, a BigTIFF if it
+    /// needs to be, with `text` as keyword and value pairs such as
+    /// `metadata::describe` gives. The rows follow with `write_rows`.
+    pub fn new(w: W, bounds: (u32, u32), text: &[(&str, String)]) -> io::Result<Encoder<W>> {
+        let end = directory_offset(bounds, false) + directory(bounds, text, false).len() as u64;
+        Encoder::start(w, bounds, text, end > u32::MAX as u64)
+    }
+
+    /// Writes the header of a TIFF, or a BigTIFF if `big`.
+    fn start(
+        mut w: W,
+        bounds: (u32, u32),
+        text: &[(&str, String)],
+        big: bool,
+    ) -> io::Result<Encoder<W>> {
+        let pixels = 3 * bounds.0 as u64 * bounds.1 as u64;
+        let offset = directory_offset(bounds, big);
+        if big {
+            w.write_all(b"II\x2b\0\x08\0\0\0")?;
+            w.write_all(&offset.to_le_bytes())?;
+        } else {
+            w.write_all(b"II\x2a\0")?;
+            w.write_all(&(offset as u32).to_le_bytes())?;
+        }
+        let mut directory = vec![0; (offset - header_len(big) - pixels) as usize];
+        directory.extend(self::directory(bounds, text, big));
+        Ok(Encoder {
+            w,
+            pixels,
+            written: 0,
+            directory,
+        })
+    }
+
+    /// Writes the next rows of the image, three bytes per pixel.
+    pub fn write_rows(&mut self, pixels: &[u8]) -> io::Result<()> {
+        self.written += pixels.len() as u64;
+        self.w.write_all(pixels)
+    }
+
+    /// Writes the directory, once all the rows are in.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.written != self.pixels {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} bytes of pixels written of {}",
+                    self.written, self.pixels
+                ),
+            ));
+        }
+        self.w.write_all(&self.directory)
+    }
+}
+
+/// Encodes an RGB pixel buffer as a TIFF, or a BigTIFF if it needs to be,
+/// with text as `Encoder::new` takes it.
+pub fn encode<W: Write>(
+    w: W,
+    pixels: &[u8],
+    bounds: (u32, u32),
+    text: &[(&str, String)],
+) -> io::Result<()> {
+    let mut encoder = Encoder::new(w, bounds, text)?;
+    encoder.write_rows(pixels)?;
+    encoder.finish()
+}
+
+fn header_len(big: bool) -> u64 {
+    if big {
+        16
+    } else {
+        8
+    }
+}
+
+/// Where the directory starts: after the pixels, on a word boundary.
+fn directory_offset(bounds: (u32, u32), big: bool) -> u64 {
+    let end = header_len(big) + 3 * bounds.0 as u64 * bounds.1 as u64;
+    end + end % 2
+}
+
+/// How many rows each strip holds.
+fn rows_per_strip((width, height): (u32, u32)) -> u32 {
+    (STRIP_BYTES / (3 * width as u64).max(1)).clamp(1, height.max(1) as u64) as u32
+}
+
+/// The image file directory of an image of `bounds` pixels, followed by
+/// the values too long to fit in its entries.
+fn directory(bounds: (u32, u32), text: &[(&str, String)], big: bool) -> Vec<u8> {
+    let (width, height) = bounds;
+    let rows = rows_per_strip(bounds);
+    let row_bytes = 3 * width as u64;
+    let counts = (0..height)
+        .step_by(rows as usize)
+        .map(|top| row_bytes * rows.min(height - top) as u64)
+        .collect::<Vec<_>>();
+    let offsets = counts
+        .iter()
+        .scan(header_len(big), |offset, count| {
+            *offset += count;
+            Some(*offset - count)
+        })
+        .collect::<Vec<_>>();
+    let long = |values: Vec<u64>| match big {
+        true => Field::Long8(values),
+        false => Field::Long(values.into_iter().map(|v| v as u32).collect()),
+    };
+    let mut fields = vec![
+        (IMAGE_WIDTH, Field::Long(vec![width])),
+        (IMAGE_LENGTH, Field::Long(vec![height])),
+        (BITS_PER_SAMPLE, Field::Short(vec![8; 3])),
+        (COMPRESSION, Field::Short(vec![1])),
+        (PHOTOMETRIC_INTERPRETATION, Field::Short(vec![2])),
+        (STRIP_OFFSETS, long(offsets)),
+        (SAMPLES_PER_PIXEL, Field::Short(vec![3])),
+        (ROWS_PER_STRIP, Field::Long(vec![rows])),
+        (STRIP_BYTE_COUNTS, long(counts)),
+        (PLANAR_CONFIGURATION, Field::Short(vec![1])),
+    ];
+    let description = text
+        .iter()
+        .filter(|(keyword, _)| *keyword != "Software")
+        .map(|(keyword, value)| format!("{}: {}\n", keyword, value))
+        .collect::<String>();
+    if !description.is_empty() {
+        fields.push((IMAGE_DESCRIPTION, Field::Ascii(description)));
+    }
+    if let Some((_, software)) = text.iter().find(|(keyword, _)| *keyword == "Software") {
+        fields.push((SOFTWARE, Field::Ascii(software.clone())));
+    }
+    fields.sort_by_key(|&(tag, _)| tag);
+
+    // Entries hold their values when they fit in 4 bytes, or 8 in a
+    // BigTIFF, and point to them otherwise.
+    let (count_len, entry_len, inline) = if big { (8, 20, 8) } else { (2, 12, 4) };
+    let integer = |v: u64| match big {
+        true => v.to_le_bytes().to_vec(),
+        false => (v as u32).to_le_bytes().to_vec(),
+    };
+    let extra_offset =
+        directory_offset(bounds, big) + count_len + entry_len * fields.len() as u64 + inline as u64;
+    let mut entries = integer(fields.len() as u64);
+    entries.truncate(count_len as usize);
+    let mut extra = Vec::new();
+    for (tag, field) in &fields {
+        let (kind, count, mut value) = field.encode();
+        entries.extend(tag.to_le_bytes());
+        entries.extend(kind.to_le_bytes());
+        entries.extend(integer(count));
+        if value.len() > inline {
+            let offset = extra_offset + extra.len() as u64;
+            value.resize(value.len() + value.len() % 2, 0);
+            extra.append(&mut value);
+            value = integer(offset);
+        }
+        value.resize(inline, 0);
+        entries.extend(value);
+    }
+    // The offset of the next directory, of which there is none.
+    entries.extend(vec![0; inline]);
+    entries.extend(extra);
+    entries
+}
+
+/// Reads back a file as written here: its pixels, its bounds and its
+/// `ImageDescription` and `Software`, from the strips the directory lists.
+#[cfg(test)]
+fn decode(bytes: &[u8]) -> (Vec<u8>, (u32, u32), String, String) {
+    let read = |at: u64, len: usize| {
+        let mut buf = [0; 8];
+        buf[..len].copy_from_slice(&bytes[at as usize..at as usize + len]);
+        u64::from_le_bytes(buf)
+    };
+    assert_eq!(&bytes[..2], b"II");
+    let big = match read(2, 2) {
+        42 => false,
+        43 => true,
+        magic => panic!("not a TIFF: {}", magic),
+    };
+    let (count_len, entry_len, inline) = if big { (8, 20, 8) } else { (2, 12, 4) };
+    let directory = if big { read(8, 8) } else { read(4, 4) };
+    assert_eq!(directory % 2, 0);
+    let mut tags = std::collections::BTreeMap::new();
+    let mut last = 0;
+    for i in 0..read(directory, count_len) {
+        let entry = directory + count_len as u64 + i * entry_len;
+        let (tag, kind, count) = (read(entry, 2), read(entry + 2, 2), read(entry + 4, inline));
+        assert!(tag > last, "tags out of order");
+        last = tag;
+        let size = match kind {
+            2 => 1,
+            3 => 2,
+            4 => 4,
+            16 => 8,
+            _ => panic!("type {}", kind),
+        };
+        let value = entry + 4 + inline as u64;
+        let at = match count as usize * size > inline {
+            true => read(value, inline),
+            false => value,
+        };
+        let values = (0..count)
+            .map(|j| read(at + j * size as u64, size))
+            .collect::<Vec<_>>();
+        tags.insert(tag as u16, values);
+    }
+    assert_eq!(
+        read(
+            directory + count_len as u64 + tags.len() as u64 * entry_len,
+            inline
+        ),
+        0
+    );
+    let text = |tag| {
+        let chars = tags.get(&tag).cloned().unwrap_or_else(|| vec![0]);
+        assert_eq!(chars.last(), Some(&0));
+        chars[..chars.len() - 1]
+            .iter()
+            .map(|&c| c as u8 as char)
+            .collect::<String>()
+    };
+    assert_eq!(tags[&BITS_PER_SAMPLE], [8, 8, 8]);
+    assert_eq!(tags[&PHOTOMETRIC_INTERPRETATION], [2]);
+    let pixels = tags[&STRIP_OFFSETS]
+        .iter()
+        .zip(&tags[&STRIP_BYTE_COUNTS])
+        .flat_map(|(&offset, &count)| &bytes[offset as usize..(offset + count) as usize])
+        .copied()
+        .collect();
+    let bounds = (tags[&IMAGE_WIDTH][0] as u32, tags[&IMAGE_LENGTH][0] as u32);
+    (pixels, bounds, text(IMAGE_DESCRIPTION), text(SOFTWARE))
+}
+
+#[test]
+fn test_encode() {
+    // An odd number of bytes of pixels, in more than one strip.
+    let bounds = (129, 171);
+    let pixels = (0..3 * 129 * 171)
+        .map(|i| (i * 7 % 251) as u8)
+        .collect::<Vec<_>>();
+    assert!(rows_per_strip(bounds) < 171);
+    let text = [
+        ("Software", "mandelbrot".to_string()),
+        ("Center", "-0.5+0i".to_string()),
+        ("Zoom", "1".to_string()),
+    ];
+    let mut bytes = Vec::new();
+    encode(&mut bytes, &pixels, bounds, &text).unwrap();
+    assert_eq!(&bytes[..4], b"II\x2a\0");
+    let (decoded, size, description, software) = decode(&bytes);
+    assert_eq!(size, bounds);
+    assert_eq!(decoded, pixels);
+    assert_eq!(description, "Center: -0.5+0i\nZoom: 1\n");
+    assert_eq!(software, "mandelbrot");
+
+    let mut bytes = Vec::new();
+    encode(&mut bytes, &[1, 2, 3], (1, 1), &[]).unwrap();
+    assert_eq!(
+        decode(&bytes),
+        (vec![1, 2, 3], (1, 1), String::new(), String::new())
+    );
+}
+
+#[test]
+fn test_bigtiff() {
+    let bounds = (300, 250);
+    let pixels = (0..3 * 300 * 250)
+        .map(|i| (i % 256) as u8)
+        .collect::<Vec<_>>();
+    let text = [("Software", "mandelbrot".to_string())];
+    let mut bytes = Vec::new();
+    let mut encoder = Encoder::start(&mut bytes, bounds, &text, true).unwrap();
+    for row in pixels.chunks(3 * 300 * 7) {
+        encoder.write_rows(row).unwrap();
+    }
+    encoder.finish().unwrap();
+    assert_eq!(&bytes[..8], b"II\x2b\0\x08\0\0\0");
+    assert_eq!(
+        decode(&bytes),
+        (pixels, bounds, String::new(), "mandelbrot".to_string())
+    );
+    // Past 4 GB a TIFF has to be a BigTIFF.
+    let end = |bounds| directory_offset(bounds, false) + directory(bounds, &[], false).len() as u64;
+    assert!(end((40_000, 40_000)) > u32::MAX as u64);
+    assert!(end((30_000, 30_000)) < u32::MAX as u64);
+}
+
+#[test]
+fn test_encoder_short() {
+    let mut bytes = Vec::new();
+    let mut encoder = Encoder::new(&mut bytes, (4, 4), &[]).unwrap();
+    encoder.write_rows(&[0; 3 * 4 * 3]).unwrap();
+    assert!(encoder.finish().is_err());
+}
@@ -0,0 +1,382 @@
+//! Tiled rendering for images too big to hold in memory. The image is
+//! rendered in bands of whole rows, and every band is encoded into the PNG
+//! as soon as it is done, so that only one band's pixels are ever held at a
+//! time. Renders to PNG stream through here whenever nothing needs the
+//! whole image at once, and `rows` hands the rows out one by one for other
+//! uses.
+//!
+//! A band's points are those of the whole image, so that it comes out
+//! exactly as it would in one piece, and it is rendered with a row to spare
+//! above and below, where the image has them, so that shading sees the same
+//! neighbors. Antialiased bands are renders of their own, with their own
+//! corners, whose jittered samples can't be told from the whole image's.
+//! Histogram coloring can't be tiled, as it spreads the palette by the
+//! escape times of the whole image, so `rows` renders it in one band.
+
+use crate::{
+    fixed::Fixed,
+    log::{self, Level},
+    pixel_to_point, shading, tiff, Coloring, RenderOptions, Renderer, UNRENDERED,
+};
+use num::Complex;
+use png::EncodingError;
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
+
+/// How many pixels a band holds, given enough rows, when none is asked for.
+pub const BAND_PIXELS: u32 = 1 << 20;
+
+/// Renders `rows` of `renderer`'s image, three bytes per pixel like
+/// `Renderer::render`, adding them to the progress of its render. A
+/// cancelled scan stops short, as `Renderer::render_row_escapes` does.
+pub fn render_rows(renderer: &Renderer, rows: Range<u32>) -> Vec<u8> {
+    let options = renderer.options();
+    let width = options.bounds.0;
+    if options.antialias > 1 {
+        let mut band = Renderer::new(band(options, rows.start, rows.len() as u32));
+        band.colorizer = renderer.colorizer.clone();
+        let pixels = band.render();
+        renderer.progress().add(width as u64 * rows.len() as u64);
+        return pixels;
+    }
+    let escapes = renderer.render_row_escapes(rows.clone());
+    let mut colors = renderer.color_rows(&escapes, &*renderer.colorizer(&escapes), rows.start);
+    let columns = width.max(1) as usize;
+    renderer.color_interior(&mut colors, &escapes, |i| {
+        (
+            (i % columns) as f64,
+            (rows.start as usize + i / columns) as f64,
+        )
+    });
+    if let Some(light) = options.shading {
+        let degree = options.fractal.degree();
+        let rendered = (escapes.len() / columns) as u32;
+        shading::shade(&mut colors, &escapes, (width, rendered), degree, light);
+    }
+    options.adjustments.apply(&mut colors);
+    colors
+}
+
+/// The options for the band of `rows` rows of `options`'s image starting
+/// at row `top`, in the precision the whole image would take.
+pub fn band(options: &RenderOptions, top: u32, rows: u32) -> RenderOptions {
+    let bounds = options.bounds;
+    let exact_corners = options.exact_corners.as_ref().map(|_| {
+        let (upper_left, lower_right) = options.exact_corners();
+        let height = &upper_left.im - &lower_right.im;
+        let at = |row: u32| -> Fixed { &upper_left.im - &height.scale(row, bounds.1) };
+        (
+            Complex {
+                re: upper_left.re.clone(),
+                im: at(top),
+            },
+            Complex {
+                re: lower_right.re.clone(),
+                im: at(top + rows),
+            },
+        )
+    });
+    RenderOptions {
+        bounds: (bounds.0, rows),
+        upper_left: pixel_to_point(bounds, (0, top), options.upper_left, options.lower_right),
+        lower_right: pixel_to_point(
+            bounds,
+            (bounds.0, top + rows),
+            options.upper_left,
+            options.lower_right,
+        ),
+        exact_corners,
+        precision: options.resolved_precision(),
+        ..options.clone()
+    }
+}
+
+/// The colored rows of a render, from the top, each band of them rendered
+/// when its first row is wanted; see `rows`.
+pub struct Rows<'a> {
+    renderer: &'a Renderer,
+    /// How many rows a band has.
+    band_rows: u32,
+    /// The rows of the band rendered last, and the first of them.
+    band: Vec<u8>,
+    band_top: u32,
+    /// The next row to hand out.
+    next: u32,
+    /// Whether the observer has been told that the render is over.
+    finished: bool,
+}
+
+/// The rows of `renderer`'s image, three bytes per pixel like
+/// `Renderer::render`, rendered in bands of `rows` rows, or about
+/// `BAND_PIXELS` pixels' worth for 0, as they are needed, so that just one
+/// band is held at a time. Histogram coloring needs the whole image, and
+/// renders it as one band.
+///
+/// A cancelled render ends after the rows under way.
+pub fn rows(renderer: &Renderer, rows: u32) -> Rows<'_> {
+    let options = renderer.options();
+    let (width, height) = options.bounds;
+    let band_rows = match rows {
+        _ if options.coloring == Coloring::Histogram => height.max(1),
+        0 => (BAND_PIXELS / width.max(1)).max(1),
+        rows => rows,
+    };
+    renderer.progress().start(width as u64 * height as u64);
+    Rows {
+        renderer,
+        band_rows,
+        band: Vec::new(),
+        band_top: 0,
+        next: 0,
+        finished: false,
+    }
+}
+
+impl Rows<'_> {
+    /// Renders the band starting at row `top`, with the spare rows around
+    /// it left out, or as much of it as a cancelled render got done.
+    fn render_band(&mut self, top: u32) {
+        let options = self.renderer.options();
+        let (width, height) = options.bounds;
+        let margin = options.shading.is_some() as u32;
+        let above = top.min(margin);
+        let bottom = (top + self.band_rows).min(height);
+        let below = (height - bottom).min(margin);
+        let _span = log::span(
+            Level::Debug,
+            "band",
+            format_args!("rows={}..{}", top, bottom),
+        );
+        // The spare rows are rendered twice.
+        let progress = self.renderer.progress();
+        progress.extend(width as u64 * (above + below) as u64);
+        let mut pixels = render_rows(self.renderer, top - above..bottom + below);
+        let row_bytes = width as usize * 3;
+        let rendered = (pixels.len() / row_bytes.max(1))
+            .saturating_sub(above as usize)
+            .min((bottom - top) as usize);
+        pixels.truncate((above as usize + rendered) * row_bytes);
+        pixels.drain(..above as usize * row_bytes);
+        if rendered > 0 {
+            self.renderer
+                .tile_complete(top..top + rendered as u32, &pixels);
+        }
+        self.band = pixels;
+        self.band_top = top;
+    }
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let (width, height) = self.renderer.options().bounds;
+        let row_bytes = width as usize * 3;
+        let band_end = self.band_top as usize + self.band.len() / row_bytes.max(1);
+        if self.next as usize >= band_end
+            && self.next < height
+            && !self.renderer.progress().is_cancelled()
+        {
+            self.render_band(self.next);
+        }
+        let start = (self.next - self.band_top) as usize * row_bytes;
+        match self.band.get(start..start + row_bytes) {
+            Some(row) if self.next < height && row_bytes > 0 => {
+                self.next += 1;
+                Some(row.to_vec())
+            }
+            _ => {
+                if !self.finished {
+                    self.finished = true;
+                    self.renderer.finished(self.next);
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Renders `renderer`'s image in bands of `rows` rows, or about
+/// `BAND_PIXELS` pixels' worth for 0, and encodes them into a PNG as they
+/// are done, with text chunks like `encode_image`.
+///
+/// A cancelled render stops after the rows under way, and the rows after
+/// them are filled with `UNRENDERED`. Returns how many rows were rendered.
+pub fn encode_png<W: Write>(
+    w: W,
+    renderer: &Renderer,
+    rows: u32,
+    text: &[(&str, String)],
+) -> Result<u32, EncodingError> {
+    let options = renderer.options();
+    assert!(
+        options.coloring != Coloring::Histogram,
+        "histogram coloring needs the whole image"
+    );
+    let (width, height) = options.bounds;
+    let mut encoder = png::Encoder::new(w, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    crate::add_text(&mut encoder, text)?;
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+    let mut done = 0;
+    for row in self::rows(renderer, rows) {
+        stream.write_all(&row)?;
+        done += 1;
+    }
+    let unrendered = UNRENDERED.repeat(width as usize);
+    for _ in done..height {
+        stream.write_all(&unrendered)?;
+    }
+    stream.finish()?;
+    Ok(done)
+}
+
+/// Renders `renderer`'s image in bands like `encode_png`, and writes them
+/// into a TIFF as they are done, with text as `tiff::Encoder::new` takes
+/// it. A cancelled render is filled out the same way.
+pub fn encode_tiff<W: Write>(
+    w: W,
+    renderer: &Renderer,
+    rows: u32,
+    text: &[(&str, String)],
+) -> io::Result<u32> {
+    let options = renderer.options();
+    assert!(
+        options.coloring != Coloring::Histogram,
+        "histogram coloring needs the whole image"
+    );
+    let (width, height) = options.bounds;
+    let mut encoder = tiff::Encoder::new(w, (width, height), text)?;
+    let mut done = 0;
+    for row in self::rows(renderer, rows) {
+        encoder.write_rows(&row)?;
+        done += 1;
+    }
+    let unrendered = UNRENDERED.repeat(width as usize);
+    for _ in done..height {
+        encoder.write_rows(&unrendered)?;
+    }
+    encoder.finish()?;
+    Ok(done)
+}
+
+#[cfg(test)]
+fn decode(bytes: &[u8]) -> Vec<u8> {
+    let mut reader = png::Decoder::new(bytes).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut pixels).unwrap();
+    pixels
+}
+
+#[test]
+fn test_tiled_matches_whole() {
+    // Row 24 lies on the real axis, where a point off by a last bit would
+    // escape.
+    let options = RenderOptions {
+        bounds: (64, 48),
+        shading: Some(crate::Light {
+            azimuth: 45.0,
+            elevation: 45.0,
+        }),
+        threads: 2,
+        ..RenderOptions::default()
+    };
+    let whole = Renderer::new(options.clone()).render();
+    for rows in [1, 7, 48, 100] {
+        let mut bytes = Vec::new();
+        let renderer = Renderer::new(options.clone());
+        encode_png(&mut bytes, &renderer, rows, &[]).unwrap();
+        assert!(renderer.progress().is_finished());
+        assert_eq!(decode(&bytes), whole, "{} rows", rows);
+    }
+    let antialiased = RenderOptions {
+        antialias: 2,
+        ..options
+    };
+    let mut bytes = Vec::new();
+    let renderer = Renderer::new(antialiased);
+    encode_png(&mut bytes, &renderer, 10, &[]).unwrap();
+    assert!(renderer.progress().is_finished());
+    assert_eq!(decode(&bytes).len(), whole.len());
+}
+
+#[test]
+fn test_tiled_tiff() {
+    let options = RenderOptions {
+        bounds: (33, 21),
+        ..RenderOptions::default()
+    };
+    let whole = Renderer::new(options.clone()).render();
+    let mut expected = Vec::new();
+    tiff::encode(&mut expected, &whole, options.bounds, &[]).unwrap();
+    let mut bytes = Vec::new();
+    let renderer = Renderer::new(options);
+    assert_eq!(encode_tiff(&mut bytes, &renderer, 4, &[]).unwrap(), 21);
+    assert_eq!(bytes, expected);
+}
+
+#[test]
+fn test_rows() {
+    let options = RenderOptions {
+        bounds: (20, 15),
+        shading: Some(crate::Light {
+            azimuth: 30.0,
+            elevation: 40.0,
+        }),
+        ..RenderOptions::default()
+    };
+    let whole = Renderer::new(options.clone()).render();
+    let renderer = Renderer::new(options.clone());
+    let banded = rows(&renderer, 4).collect::<Vec<_>>();
+    assert_eq!(banded.len(), 15);
+    assert_eq!(banded.concat(), whole);
+    assert!(renderer.progress().is_finished());
+    // Histograms are taken over the whole image, in one band.
+    let histogram = Renderer::new(RenderOptions {
+        coloring: Coloring::Histogram,
+        ..options
+    });
+    assert_eq!(
+        histogram.rows().flatten().collect::<Vec<_>>(),
+        histogram.render()
+    );
+    histogram.progress().cancel();
+    assert_eq!(histogram.rows().count(), 0);
+}
+
+#[test]
+fn test_tiled_cancelled() {
+    let renderer = Renderer::new(RenderOptions {
+        bounds: (8, 6),
+        ..RenderOptions::default()
+    });
+    renderer.progress().cancel();
+    let mut bytes = Vec::new();
+    assert_eq!(encode_png(&mut bytes, &renderer, 2, &[]).unwrap(), 0);
+    assert_eq!(decode(&bytes), UNRENDERED.repeat(8 * 6));
+}
+
+#[test]
+fn test_band() {
+    let options = RenderOptions {
+        bounds: (100, 50),
+        upper_left: Complex::new(-2.0, 1.0),
+        lower_right: Complex::new(2.0, -1.0),
+        exact_corners: Some((
+            Complex::new(Fixed::from_f64(-2.0, 64), Fixed::from_f64(1.0, 64)),
+            Complex::new(Fixed::from_f64(2.0, 64), Fixed::from_f64(-1.0, 64)),
+        )),
+        ..RenderOptions::default()
+    };
+    let band = band(&options, 10, 5);
+    assert_eq!(band.bounds, (100, 5));
+    assert_eq!(band.upper_left, Complex::new(-2.0, 0.6));
+    assert_eq!(band.lower_right, Complex::new(2.0, 0.4));
+    let (upper_left, lower_right) = band.exact_corners.clone().unwrap();
+    assert_eq!(upper_left.im.to_f64(), 0.6);
+    assert_eq!(lower_right.im.to_f64(), 0.4);
+    assert_eq!(band.pixel_size(), options.pixel_size());
+}
@@ -0,0 +1,93 @@
+use std::{
+    io::{self, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+/// Encodes frames into a video by piping them, as raw RGB bytes, into an
+/// `ffmpeg` child process. ffmpeg picks the container and codec from the
+/// file name and reports its own errors on stderr.
+pub struct Video {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl Video {
+    /// Starts ffmpeg writing `path` from `bounds` sized frames at `fps`.
+    pub fn start(path: &str, bounds: (u32, u32), fps: u32) -> io::Result<Video> {
+        let mut child = Command::new("ffmpeg")
+            .args(ffmpeg_args(path, bounds, fps))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => io::Error::new(
+                    e.kind(),
+                    "ffmpeg was not found; install it or write image frames instead",
+                ),
+                _ => e,
+            })?;
+        let stdin = child.stdin.take();
+        Ok(Video { child, stdin })
+    }
+
+    /// Sends one frame of three bytes (red, green, blue) per pixel.
+    pub fn write_frame(&mut self, pixels: &[u8]) -> io::Result<()> {
+        match &mut self.stdin {
+            Some(stdin) => stdin.write_all(pixels),
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    /// Closes the stream and waits for ffmpeg to finish the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("ffmpeg failed ({})", status)))
+        }
+    }
+}
+
+/// The ffmpeg command line reading raw frames from stdin. Most players only
+/// take 4:2:0 chroma, which needs even dimensions, so odd sizes are padded
+/// by a pixel.
+fn ffmpeg_args(path: &str, bounds: (u32, u32), fps: u32) -> Vec<String> {
+    [
+        "-y",
+        "-loglevel",
+        "error",
+        "-f",
+        "rawvideo",
+        "-pixel_format",
+        "rgb24",
+        "-video_size",
+        &format!("{}x{}", bounds.0, bounds.1),
+        "-framerate",
+        &fps.to_string(),
+        "-i",
+        "-",
+        "-vf",
+        "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+        "-pix_fmt",
+        "yuv420p",
+        path,
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect()
+}
+
+#[test]
+fn test_ffmpeg_args() {
+    let args = ffmpeg_args("zoom.mp4", (641, 480), 24);
+    let after = |flag: &str| {
+        let i = args.iter().position(|arg| arg == flag).unwrap();
+        args[i + 1].as_str()
+    };
+    assert_eq!(after("-video_size"), "641x480");
+    assert_eq!(after("-framerate"), "24");
+    assert_eq!(after("-i"), "-");
+    assert_eq!(args.last().map(String::as_str), Some("zoom.mp4"));
+}
@@ -0,0 +1,863 @@
+//! A WebP encoder, for renders headed for the web. Images are written in
+//! WebP's lossless format: green is subtracted from red and blue, every
+//! pixel is predicted from its neighbors by whichever of the format's
+//! predictors suits its 16×16 block best, and the residuals are coded with
+//! backward references to repeats and prefix codes fitted to the image.
+//!
+//! Below quality 100 the residuals are rounded to coarser steps before they
+//! are coded, as WebP's near lossless mode does, which leaves no channel
+//! more than a few levels off but makes for far fewer distinct residuals.
+//! Predictions are made from the pixels as they will be decoded, so the
+//! error doesn't build up across the image. WebP's lossy format, VP8, isn't
+//! written at any quality.
+
+use std::io::{self, Write};
+
+/// The widest and tallest image WebP can hold.
+pub const MAX_SIZE: u32 = 1 << 14;
+
+/// The quality at which images come out exactly as they are.
+pub const LOSSLESS_QUALITY: u8 = 100;
+
+/// How many pixels wide and tall a block sharing a predictor is, as a power
+/// of 2.
+const BLOCK_BITS: u32 = 4;
+
+/// The longest backward reference.
+const MAX_LENGTH: usize = 4096;
+
+/// How many earlier positions with the same hash are tried for a match.
+const MAX_CHAIN: usize = 32;
+
+/// The longest code of the prefix codes, and of the code their code
+/// lengths are coded with.
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_LENGTH_CODE_LENGTH: u8 = 7;
+
+/// The order the code lengths of the code length code are written in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// How many symbols the prefix code of green, lengths of backward
+/// references, has beyond the 256 values, and how many distances have.
+const LENGTH_PREFIXES: usize = 24;
+const DISTANCE_PREFIXES: usize = 40;
+
+/// Transform types.
+const PREDICTOR_TRANSFORM: u32 = 0;
+const SUBTRACT_GREEN: u32 = 2;
+
+/// Encodes an RGB pixel buffer as a lossless WebP at quality 100, and as a
+/// near lossless one, smaller the lower it goes, down to 1.
+pub fn encode<W: Write>(
+    mut w: W,
+    pixels: &[u8],
+    bounds: (u32, u32),
+    quality: u8,
+) -> io::Result<()> {
+    let (width, height) = bounds;
+    if !(1..=MAX_SIZE).contains(&width) || !(1..=MAX_SIZE).contains(&height) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "WebP images are 1 to {} pixels a side, not {}x{}",
+                MAX_SIZE, width, height
+            ),
+        ));
+    }
+    let argb = pixels
+        .chunks_exact(3)
+        .map(|p| 0xff00_0000 | (p[0] as u32) << 16 | (p[1] as u32) << 8 | p[2] as u32)
+        .collect::<Vec<_>>();
+    let mut bits = BitWriter::default();
+    bits.write(0x2f, 8);
+    bits.write(width - 1, 14);
+    bits.write(height - 1, 14);
+    // Alpha isn't used, and the version is 0.
+    bits.write(0, 1);
+    bits.write(0, 3);
+
+    bits.write(1, 1);
+    bits.write(SUBTRACT_GREEN, 2);
+    bits.write(1, 1);
+    bits.write(PREDICTOR_TRANSFORM, 2);
+    bits.write(BLOCK_BITS - 2, 3);
+    let (modes, residuals) = predict(&argb, width as usize, step(quality));
+    write_image(
+        &mut bits,
+        &modes,
+        width.div_ceil(1 << BLOCK_BITS) as usize,
+        false,
+    );
+    bits.write(0, 1);
+    write_image(&mut bits, &residuals, width as usize, true);
+
+    let data = bits.finish();
+    let padded = data.len() + data.len() % 2;
+    w.write_all(b"RIFF")?;
+    w.write_all(&(12 + padded as u32).to_le_bytes())?;
+    w.write_all(b"WEBPVP8L")?;
+    w.write_all(&(data.len() as u32).to_le_bytes())?;
+    w.write_all(&data)?;
+    w.write_all(&vec![0; padded - data.len()])
+}
+
+/// The step residuals are rounded to at `quality`: 1, doubling every 20
+/// below 100.
+fn step(quality: u8) -> i32 {
+    1 << ((LOSSLESS_QUALITY.saturating_sub(quality) as i32 + 19) / 20)
+}
+
+/// Bits written from the low bit of each byte up, as WebP packs them.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Writes the low `n` bits of `value`, up to 32.
+    fn write(&mut self, value: u32, n: u32) {
+        self.bits |= ((value as u64) & ((1 << n) - 1)) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+/// The channels of a pixel as alpha, red, green and blue.
+fn channels(pixel: u32) -> [i32; 4] {
+    [24, 16, 8, 0].map(|shift| (pixel >> shift & 0xff) as i32)
+}
+
+fn pack(channels: [i32; 4]) -> u32 {
+    channels
+        .iter()
+        .fold(0, |pixel, &c| pixel << 8 | (c & 0xff) as u32)
+}
+
+fn average2(a: u32, b: u32) -> u32 {
+    (((a ^ b) & 0xfefe_fefe) >> 1) + (a & b)
+}
+
+/// The prediction of the pixel at `i` of an image `width` pixels wide with
+/// the predictor `mode`, from the pixels before it.
+fn prediction(mode: u32, pixels: &[u32], i: usize, width: usize) -> u32 {
+    match (i % width, i / width) {
+        (0, 0) => return 0xff00_0000,
+        (_, 0) => return pixels[i - 1],
+        (0, _) => return pixels[i - width],
+        _ => {}
+    }
+    let (left, top) = (pixels[i - 1], pixels[i - width]);
+    // The pixel top right of the last in a row is the first of the row.
+    let (top_left, top_right) = (pixels[i - width - 1], pixels[i - width + 1]);
+    let clamped = |f: &dyn Fn(usize) -> i32| pack(std::array::from_fn(|c| f(c).clamp(0, 255)));
+    match mode {
+        0 => 0xff00_0000,
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average2(average2(left, top_right), top),
+        6 => average2(left, top_left),
+        7 => average2(left, top),
+        8 => average2(top_left, top),
+        9 => average2(top, top_right),
+        10 => average2(average2(left, top_left), average2(top, top_right)),
+        11 => {
+            let (l, t, tl) = (channels(left), channels(top), channels(top_left));
+            let distance =
+                |a: [i32; 4], b: [i32; 4]| -> i32 { (0..4).map(|c| (a[c] - b[c]).abs()).sum() };
+            // Whichever of left and top is nearer left + top - top left.
+            if distance(t, tl) < distance(l, tl) {
+                left
+            } else {
+                top
+            }
+        }
+        12 => {
+            let (l, t, tl) = (channels(left), channels(top), channels(top_left));
+            clamped(&|c| l[c] + t[c] - tl[c])
+        }
+        _ => {
+            let (a, tl) = (channels(average2(left, top)), channels(top_left));
+            clamped(&|c| a[c] + (a[c] - tl[c]) / 2)
+        }
+    }
+}
+
+/// Picks a predictor for every block of `pixels`, as the pixels of the
+/// predictor image, and gives the residuals of the image, with green taken
+/// from red and blue, when predicted by them. Residuals are rounded to
+/// multiples of `step`.
+fn predict(pixels: &[u32], width: usize, step: i32) -> (Vec<u32>, Vec<u32>) {
+    let height = pixels.len() / width;
+    let subtracted = pixels
+        .iter()
+        .map(|&p| {
+            let green = p >> 8 & 0xff;
+            let red = (p >> 16).wrapping_sub(green) & 0xff;
+            let blue = p.wrapping_sub(green) & 0xff;
+            p & 0xff00_ff00 | red << 16 | blue
+        })
+        .collect::<Vec<_>>();
+    let columns = width.div_ceil(1 << BLOCK_BITS);
+    let rows = height.div_ceil(1 << BLOCK_BITS);
+    let mut modes = Vec::with_capacity(columns * rows);
+    for block in 0..columns * rows {
+        let (left, top) = (
+            (block % columns) << BLOCK_BITS,
+            (block / columns) << BLOCK_BITS,
+        );
+        let cost = |mode: u32| -> i32 {
+            let mut cost = 0;
+            for y in top..(top + (1 << BLOCK_BITS)).min(height) {
+                for x in left..(left + (1 << BLOCK_BITS)).min(width) {
+                    let i = y * width + x;
+                    let predicted = channels(prediction(mode, &subtracted, i, width));
+                    let actual = channels(subtracted[i]);
+                    cost += (0..4)
+                        .map(|c| {
+                            let residual = (actual[c] - predicted[c]) & 0xff;
+                            residual.min(256 - residual)
+                        })
+                        .sum::<i32>();
+                }
+            }
+            cost
+        };
+        let mode = (0..14).min_by_key(|&mode| cost(mode)).unwrap();
+        modes.push(0xff00_0000 | mode << 8);
+    }
+
+    // The pixels as they will be decoded, green subtracted, to predict
+    // from.
+    let mut decoded = vec![0; pixels.len()];
+    let mut residuals = Vec::with_capacity(pixels.len());
+    for i in 0..pixels.len() {
+        let (x, y) = (i % width, i / width);
+        let mode = modes[(y >> BLOCK_BITS) * columns + (x >> BLOCK_BITS)] >> 8 & 0xff;
+        let predicted = channels(prediction(mode, &decoded, i, width));
+        let actual = channels(pixels[i]);
+        let mut residual = [0; 4];
+        let mut value = [0; 4];
+        // Green first, as red and blue are decoded with it added back.
+        for c in [2, 0, 1, 3] {
+            let added = if c == 1 || c == 3 { value[2] } else { 0 };
+            let base = (predicted[c] + added) & 0xff;
+            let step = if c == 0 { 1 } else { step };
+            residual[c] = quantize(actual[c] - base, base, step);
+            value[c] = base + residual[c];
+        }
+        decoded[i] = pack(std::array::from_fn(|c| predicted[c] + residual[c]));
+        residuals.push(pack(residual));
+    }
+    (modes, residuals)
+}
+
+/// The multiple of `step` nearest `difference` that keeps `base` plus it a
+/// channel value.
+fn quantize(difference: i32, base: i32, step: i32) -> i32 {
+    let mut rounded = (difference + step / 2).div_euclid(step) * step;
+    while base + rounded > 255 {
+        rounded -= step;
+    }
+    while base + rounded < 0 {
+        rounded += step;
+    }
+    rounded
+}
+
+/// A pixel as it is, or a copy of earlier ones.
+enum Token {
+    Literal(u32),
+    /// The number of pixels, and the distance code: 1 for the row above, 2
+    /// for the pixel to the left, and 120 more than the distance otherwise.
+    Copy(usize, usize),
+}
+
+/// Finds repeats of earlier pixels, taking the longest it can find at each
+/// position.
+fn backward_references(pixels: &[u32], width: usize) -> Vec<Token> {
+    const HASH_BITS: u32 = 16;
+    let hash = |i: usize| {
+        let h = pixels[i].wrapping_mul(0x9e37_79b1) ^ pixels[i + 1].wrapping_mul(0x85eb_ca6b);
+        (h >> (32 - HASH_BITS)) as usize
+    };
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; pixels.len()];
+    let insert = |i: usize, head: &mut [usize], previous: &mut [usize]| {
+        if i + 1 < pixels.len() {
+            let h = hash(i);
+            previous[i] = head[h];
+            head[h] = i;
+        }
+    };
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < pixels.len() {
+        let limit = MAX_LENGTH.min(pixels.len() - i);
+        let length = |start: usize| {
+            (0..limit)
+                .take_while(|&k| pixels[start + k] == pixels[i + k])
+                .count()
+        };
+        let mut best = (0, 0);
+        for distance in [1, width] {
+            if distance <= i {
+                best = best.max((length(i - distance), distance));
+            }
+        }
+        if i + 1 < pixels.len() {
+            let mut candidate = head[hash(i)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == usize::MAX || best.0 == limit {
+                    break;
+                }
+                best = best.max((length(candidate), i - candidate));
+                candidate = previous[candidate];
+            }
+        }
+        let (length, distance) = best;
+        if length >= 3 {
+            let code = match distance {
+                d if d == width => 1,
+                1 => 2,
+                d => d + 120,
+            };
+            tokens.push(Token::Copy(length, code));
+            for k in i..i + length {
+                insert(k, &mut head, &mut previous);
+            }
+            i += length;
+        } else {
+            tokens.push(Token::Literal(pixels[i]));
+            insert(i, &mut head, &mut previous);
+            i += 1;
+        }
+    }
+    tokens
+}
+
+/// The prefix of a length or distance code, and its extra bits and their
+/// count.
+fn prefix(value: usize) -> (usize, u32, u32) {
+    let value = value - 1;
+    if value < 4 {
+        return (value, 0, 0);
+    }
+    let high = value.ilog2();
+    let second = value >> (high - 1) & 1;
+    let extra = high - 1;
+    (
+        (2 * high as usize) + second,
+        (value & ((1 << extra) - 1)) as u32,
+        extra,
+    )
+}
+
+/// Writes `pixels`, an image `width` pixels wide, as backward references
+/// and prefix coded pixels, with the one group of prefix codes the main
+/// image says it has.
+fn write_image(bits: &mut BitWriter, pixels: &[u32], width: usize, main: bool) {
+    // No color cache.
+    bits.write(0, 1);
+    if main {
+        bits.write(0, 1);
+    }
+    let tokens = backward_references(pixels, width);
+    let mut counts = [
+        vec![0; 256 + LENGTH_PREFIXES],
+        vec![0; 256],
+        vec![0; 256],
+        vec![0; 256],
+        vec![0; DISTANCE_PREFIXES],
+    ];
+    for token in &tokens {
+        match *token {
+            Token::Literal(pixel) => {
+                let [a, r, g, b] = channels(pixel);
+                for (code, value) in [(0, g), (1, r), (2, b), (3, a)] {
+                    counts[code][value as usize] += 1;
+                }
+            }
+            Token::Copy(length, distance) => {
+                counts[0][256 + prefix(length).0] += 1;
+                counts[4][prefix(distance).0] += 1;
+            }
+        }
+    }
+    let codes = counts.map(|counts| PrefixCode::write(bits, &counts));
+    for token in &tokens {
+        match *token {
+            Token::Literal(pixel) => {
+                let [a, r, g, b] = channels(pixel);
+                for (code, value) in [(0, g), (1, r), (2, b), (3, a)] {
+                    codes[code].put(bits, value as usize);
+                }
+            }
+            Token::Copy(length, distance) => {
+                for (code, value, offset) in [(0, length, 256), (4, distance, 0)] {
+                    let (prefix, extra, extra_bits) = prefix(value);
+                    codes[code].put(bits, offset + prefix);
+                    bits.write(extra, extra_bits);
+                }
+            }
+        }
+    }
+}
+
+/// A canonical prefix code, its codes bit reversed to be written from the
+/// low bit.
+struct PrefixCode {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+}
+
+impl PrefixCode {
+    /// Writes a code fitted to symbols used `counts` times, and gives it.
+    fn write(bits: &mut BitWriter, counts: &[u32]) -> PrefixCode {
+        let used = (0..counts.len())
+            .filter(|&s| counts[s] > 0)
+            .collect::<Vec<_>>();
+        let mut lengths = vec![0; counts.len()];
+        // One or two symbols below 256 are written as they are; just one
+        // takes no bits at all.
+        if used.len() <= 2 && used.iter().all(|&s| s < 256) {
+            let symbols = if used.is_empty() { vec![0] } else { used };
+            bits.write(1, 1);
+            bits.write(symbols.len() as u32 - 1, 1);
+            match symbols[0] {
+                s @ (0 | 1) => {
+                    bits.write(0, 1);
+                    bits.write(s as u32, 1);
+                }
+                s => {
+                    bits.write(1, 1);
+                    bits.write(s as u32, 8);
+                }
+            }
+            if let [first, second] = symbols[..] {
+                bits.write(second as u32, 8);
+                lengths[first] = 1;
+                lengths[second] = 1;
+            }
+            return PrefixCode::new(lengths);
+        }
+        let lengths = code_lengths(counts, MAX_CODE_LENGTH);
+        bits.write(0, 1);
+        write_lengths(bits, &lengths);
+        PrefixCode::new(lengths)
+    }
+
+    fn new(lengths: Vec<u8>) -> PrefixCode {
+        let mut codes = vec![0; lengths.len()];
+        let mut code = 0u32;
+        for length in 1..=MAX_CODE_LENGTH {
+            for (symbol, _) in lengths.iter().enumerate().filter(|&(_, &l)| l == length) {
+                codes[symbol] = (code.reverse_bits() >> (32 - length)) as u16;
+                code += 1;
+            }
+            code <<= 1;
+        }
+        PrefixCode { lengths, codes }
+    }
+
+    fn put(&self, bits: &mut BitWriter, symbol: usize) {
+        bits.write(self.codes[symbol] as u32, self.lengths[symbol] as u32);
+    }
+}
+
+/// Writes the code lengths of a prefix code, run length coded with a code
+/// of their own.
+fn write_lengths(bits: &mut BitWriter, lengths: &[u8]) {
+    // Lengths 0 to 15, 16 to repeat the last length that isn't 0 3 to 6
+    // times, and 17 and 18 for 3 to 10 and 11 to 138 0s.
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == value).count();
+        if value == 0 && run >= 3 {
+            let run = run.min(138);
+            tokens.push(match run {
+                11.. => (18, run as u32 - 11, 7),
+                _ => (17, run as u32 - 3, 3),
+            });
+            i += run;
+        } else if value != 0 && run >= 4 {
+            let repeats = (run - 1).min(6);
+            tokens.push((value as usize, 0, 0));
+            tokens.push((16, repeats as u32 - 3, 2));
+            i += 1 + repeats;
+        } else {
+            tokens.push((value as usize, 0, 0));
+            i += 1;
+        }
+    }
+    let mut counts = [0; 19];
+    for &(symbol, _, _) in &tokens {
+        counts[symbol] += 1;
+    }
+    let code = PrefixCode::new(code_lengths(&counts, MAX_LENGTH_CODE_LENGTH));
+    let written = CODE_LENGTH_ORDER
+        .iter()
+        .rposition(|&s| code.lengths[s] > 0)
+        .map_or(0, |last| last + 1)
+        .max(4);
+    bits.write(written as u32 - 4, 4);
+    for &symbol in &CODE_LENGTH_ORDER[..written] {
+        bits.write(code.lengths[symbol] as u32, 3);
+    }
+    // Lengths for the whole alphabet follow.
+    bits.write(0, 1);
+    for (symbol, extra, extra_bits) in tokens {
+        code.put(bits, symbol);
+        bits.write(extra, extra_bits);
+    }
+}
+
+/// The lengths of a Huffman code for symbols used `counts` times, none
+/// longer than `limit`. A code has two symbols at least, as decoders take
+/// a lone one to take no bits.
+fn code_lengths(counts: &[u32], limit: u8) -> Vec<u8> {
+    let mut counts = counts.to_vec();
+    match counts.iter().filter(|&&c| c > 0).count() {
+        0 => counts[..2].fill(1),
+        1 => {
+            let spare = counts.iter().position(|&c| c == 0).unwrap();
+            counts[spare] = 1;
+        }
+        _ => {}
+    }
+    // Rare symbols are made less rare until the longest code fits.
+    let mut least = 1;
+    loop {
+        let raised = counts
+            .iter()
+            .map(|&c| if c > 0 { c.max(least) } else { 0 })
+            .collect::<Vec<_>>();
+        let lengths = huffman_lengths(&raised);
+        if lengths.iter().all(|&l| l <= limit) {
+            return lengths;
+        }
+        least *= 2;
+    }
+}
+
+/// The lengths of a Huffman code for symbols used `counts` times.
+fn huffman_lengths(counts: &[u32]) -> Vec<u8> {
+    use std::{cmp::Reverse, collections::BinaryHeap};
+    // Nodes are the symbols, then the ones merged from two others.
+    let mut parents = vec![usize::MAX; counts.len()];
+    let mut heap = counts
+        .iter()
+        .enumerate()
+        .filter(|&(_, &c)| c > 0)
+        .map(|(s, &c)| Reverse((c as u64, s)))
+        .collect::<BinaryHeap<_>>();
+    while heap.len() > 1 {
+        let Reverse((a, i)) = heap.pop().unwrap();
+        let Reverse((b, j)) = heap.pop().unwrap();
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[i] = node;
+        parents[j] = node;
+        heap.push(Reverse((a + b, node)));
+    }
+    (0..counts.len())
+        .map(|symbol| {
+            let mut depth = 0;
+            let mut node = symbol;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                depth += 1;
+            }
+            depth
+        })
+        .collect()
+}
+
+/// Decodes a lossless WebP with just the transforms and codes written here,
+/// into RGB pixels and bounds.
+#[cfg(test)]
+fn decode(bytes: &[u8]) -> (Vec<u8>, (u32, u32)) {
+    struct Bits<'a>(&'a [u8], usize);
+    impl Bits<'_> {
+        fn read(&mut self, n: u32) -> u32 {
+            let mut value = 0;
+            for k in 0..n {
+                let (byte, bit) = (self.1 / 8, self.1 % 8);
+                value |= ((self.0[byte] >> bit) as u32 & 1) << k;
+                self.1 += 1;
+            }
+            value
+        }
+    }
+    /// Symbols by code, as (length, code) from the first bit read.
+    type Code = std::collections::HashMap<(u8, u32), usize>;
+    fn canonical(lengths: &[u8]) -> Code {
+        let mut code = 0;
+        let mut symbols = Code::new();
+        for length in 1..=15 {
+            for symbol in (0..lengths.len()).filter(|&s| lengths[s] == length) {
+                symbols.insert((length, code), symbol);
+                code += 1;
+            }
+            code <<= 1;
+        }
+        if lengths.iter().filter(|&&l| l > 0).count() == 1 {
+            symbols = Code::from([((0, 0), lengths.iter().position(|&l| l > 0).unwrap())]);
+        }
+        symbols
+    }
+    fn symbol(bits: &mut Bits, code: &Code) -> usize {
+        let (mut length, mut value) = (0, 0);
+        loop {
+            if let Some(&symbol) = code.get(&(length, value)) {
+                return symbol;
+            }
+            assert!(length < 15, "no such code");
+            value = value << 1 | bits.read(1);
+            length += 1;
+        }
+    }
+    fn read_code(bits: &mut Bits, size: usize) -> Code {
+        let mut lengths = vec![0; size];
+        if bits.read(1) == 1 {
+            let count = bits.read(1) + 1;
+            let wide = bits.read(1);
+            let first = bits.read(1 + 7 * wide) as usize;
+            if count == 1 {
+                return Code::from([((0, 0), first)]);
+            }
+            lengths[first] = 1;
+            lengths[bits.read(8) as usize] = 1;
+            return canonical(&lengths);
+        }
+        let mut length_lengths = [0; 19];
+        for i in 0..bits.read(4) as usize + 4 {
+            length_lengths[CODE_LENGTH_ORDER[i]] = bits.read(3) as u8;
+        }
+        let length_code = canonical(&length_lengths);
+        assert_eq!(bits.read(1), 0);
+        let (mut i, mut previous) = (0, 8);
+        while i < size {
+            let (value, repeat) = match symbol(bits, &length_code) {
+                16 => (previous, 3 + bits.read(2)),
+                17 => (0, 3 + bits.read(3)),
+                18 => (0, 11 + bits.read(7)),
+                length => (length as u8, 1),
+            };
+            for _ in 0..repeat {
+                lengths[i] = value;
+                i += 1;
+            }
+            if value > 0 {
+                previous = value;
+            }
+        }
+        canonical(&lengths)
+    }
+    fn value(bits: &mut Bits, prefix: usize) -> usize {
+        if prefix < 4 {
+            return prefix + 1;
+        }
+        let extra = (prefix as u32 - 2) >> 1;
+        ((2 + (prefix & 1)) << extra) + bits.read(extra) as usize + 1
+    }
+    fn read_image(bits: &mut Bits, width: usize, size: usize, main: bool) -> Vec<u32> {
+        assert_eq!(bits.read(1), 0, "color cache");
+        if main {
+            assert_eq!(bits.read(1), 0, "meta prefix codes");
+        }
+        let sizes = [256 + LENGTH_PREFIXES, 256, 256, 256, DISTANCE_PREFIXES];
+        let codes = sizes.map(|size| read_code(bits, size));
+        let mut pixels = Vec::with_capacity(size);
+        while pixels.len() < size {
+            let green = symbol(bits, &codes[0]);
+            if green < 256 {
+                let [r, b, a] = [1, 2, 3].map(|c| symbol(bits, &codes[c]) as u32);
+                pixels.push(a << 24 | r << 16 | (green as u32) << 8 | b);
+                continue;
+            }
+            let length = value(bits, green - 256);
+            let prefix = symbol(bits, &codes[4]);
+            let distance = match value(bits, prefix) {
+                1 => width,
+                2 => 1,
+                code => code - 120,
+            };
+            for _ in 0..length {
+                pixels.push(pixels[pixels.len() - distance]);
+            }
+        }
+        pixels
+    }
+
+    assert_eq!(
+        (&bytes[..4], &bytes[8..16]),
+        (&b"RIFF"[..], &b"WEBPVP8L"[..])
+    );
+    let riff = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+    assert_eq!(riff + 8, bytes.len());
+    let bits = &mut Bits(&bytes[20..], 0);
+    assert_eq!(bits.read(8), 0x2f);
+    let width = bits.read(14) as usize + 1;
+    let height = bits.read(14) as usize + 1;
+    assert_eq!(bits.read(4), 0);
+    let mut transforms = Vec::new();
+    while bits.read(1) == 1 {
+        let transform = bits.read(2);
+        let modes = match transform {
+            SUBTRACT_GREEN => None,
+            PREDICTOR_TRANSFORM => {
+                let block_bits = bits.read(3) + 2;
+                let columns = width.div_ceil(1 << block_bits);
+                let rows = height.div_ceil(1 << block_bits);
+                Some((block_bits, read_image(bits, columns, columns * rows, false)))
+            }
+            _ => panic!("transform {}", transform),
+        };
+        transforms.push(modes);
+    }
+    let mut pixels = read_image(bits, width, width * height, true);
+    for transform in transforms.iter().rev() {
+        match transform {
+            Some((block_bits, modes)) => {
+                let columns = width.div_ceil(1 << block_bits);
+                for i in 0..pixels.len() {
+                    let (x, y) = (i % width, i / width);
+                    let mode = modes[(y >> block_bits) * columns + (x >> block_bits)] >> 8 & 0xff;
+                    let predicted = channels(prediction(mode, &pixels, i, width));
+                    let residual = channels(pixels[i]);
+                    pixels[i] = pack(std::array::from_fn(|c| predicted[c] + residual[c]));
+                }
+            }
+            None => {
+                for p in &mut pixels {
+                    let green = *p >> 8 & 0xff;
+                    let red = ((*p >> 16) + green) & 0xff;
+                    let blue = (*p + green) & 0xff;
+                    *p = *p & 0xff00_ff00 | red << 16 | blue;
+                }
+            }
+        }
+    }
+    let rgb = pixels
+        .iter()
+        .flat_map(|&p| [p >> 16, p >> 8, p].map(|c| c as u8))
+        .collect();
+    (rgb, (width as u32, height as u32))
+}
+
+#[test]
+fn test_prefix() {
+    assert_eq!(prefix(1), (0, 0, 0));
+    assert_eq!(prefix(4), (3, 0, 0));
+    assert_eq!(prefix(5), (4, 0, 1));
+    assert_eq!(prefix(6), (4, 1, 1));
+    assert_eq!(prefix(7), (5, 0, 1));
+    assert_eq!(prefix(9), (6, 0, 2));
+    assert_eq!(prefix(4096), (23, 1023, 10));
+}
+
+#[test]
+fn test_code_lengths() {
+    // Fibonacci counts make the deepest tree there is.
+    let mut counts = vec![1, 1];
+    while counts.len() < 30 {
+        counts.push(counts[counts.len() - 1] + counts[counts.len() - 2]);
+    }
+    let lengths = code_lengths(&counts, 15);
+    assert_eq!(lengths.iter().max(), Some(&15));
+    let kraft = lengths
+        .iter()
+        .map(|&l| 1.0 / (1u32 << l) as f64)
+        .sum::<f64>();
+    assert_eq!(kraft, 1.0);
+    assert_eq!(code_lengths(&[0, 0, 5, 0], 7), [1, 0, 1, 0]);
+}
+
+#[test]
+fn test_encode_lossless() {
+    let bounds = (45, 37);
+    let mut pixels = Vec::new();
+    for y in 0..37u32 {
+        for x in 0..45u32 {
+            // Gradients, a repeating pattern and noise.
+            let noise = (x * 7919 + y * 104729).wrapping_mul(2654435761) >> 24;
+            pixels.extend([
+                (x * 5) as u8,
+                (y * 6) as u8,
+                ((x / 4 + y / 4) % 2 * 200) as u8,
+            ]);
+            if x > 40 {
+                let n = pixels.len();
+                pixels[n - 1] = noise as u8;
+            }
+        }
+    }
+    let mut bytes = Vec::new();
+    encode(&mut bytes, &pixels, bounds, LOSSLESS_QUALITY).unwrap();
+    assert_eq!(decode(&bytes), (pixels, bounds));
+    for bounds in [(1, 1), (1, 5), (7, 1)] {
+        let pixels = (0..3 * bounds.0 * bounds.1)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+        let mut bytes = Vec::new();
+        encode(&mut bytes, &pixels, bounds, LOSSLESS_QUALITY).unwrap();
+        assert_eq!(decode(&bytes), (pixels, bounds));
+    }
+    assert!(encode(&mut Vec::new(), &[0; 3 * 16385], (16385, 1), 100).is_err());
+}
+
+#[test]
+fn test_encode_render() {
+    use crate::{Palette, RenderOptions, Renderer};
+    let options = RenderOptions {
+        bounds: (160, 120),
+        palette: Palette::named("fire").unwrap(),
+        ..RenderOptions::default()
+    };
+    let pixels = Renderer::new(options.clone()).render();
+    let mut png = Vec::new();
+    crate::encode_image(&mut png, &pixels, options.bounds, &[]).unwrap();
+    let mut lossless = Vec::new();
+    encode(&mut lossless, &pixels, options.bounds, LOSSLESS_QUALITY).unwrap();
+    assert_eq!(decode(&lossless).0, pixels);
+    assert!(
+        lossless.len() < png.len(),
+        "{} {}",
+        lossless.len(),
+        png.len()
+    );
+    let mut previous = lossless.len();
+    for quality in [90, 50, 10] {
+        let mut lossy = Vec::new();
+        encode(&mut lossy, &pixels, options.bounds, quality).unwrap();
+        assert!(lossy.len() < previous, "quality {}", quality);
+        previous = lossy.len();
+        let (decoded, _) = decode(&lossy);
+        let error = decoded
+            .iter()
+            .zip(&pixels)
+            .map(|(&a, &b)| a.abs_diff(b) as i32)
+            .max()
+            .unwrap();
+        assert!(error <= step(quality), "quality {}: {}", quality, error);
+    }
+}
@@ -0,0 +1,78 @@
+//! A render worker, taking jobs from a coordinator over TCP so that several
+//! machines can share one big render. Each job is a band of rows of an
+//! image, which is sent back as escape times for the coordinator to color;
+//! see `mandelbrot::distributed` for the protocol. A coordinator may send
+//! any number of jobs over one connection, one after the other.
+
+use crate::cli::Worker;
+use mandelbrot::{
+    distributed::{self, Job},
+    log::{self, Level},
+    MandelbrotError, Renderer,
+};
+use std::{
+    io::{self, BufReader, ErrorKind},
+    net::{TcpListener, TcpStream},
+    thread,
+    time::Instant,
+};
+
+/// Takes jobs on `worker.listen` until the process is stopped.
+pub fn work(worker: &Worker) -> Result<(), MandelbrotError> {
+    let listener = TcpListener::bind(&worker.listen)
+        .map_err(|e| MandelbrotError::Io(format!("{}: {}", worker.listen, e)))?;
+    if !worker.frame.quiet {
+        eprintln!("taking render jobs on {}", worker.listen);
+    }
+    // Each coordinator gets a thread, so that one gone quiet holds up no
+    // other.
+    thread::scope(|scope| {
+        for stream in listener.incoming().flatten() {
+            // A coordinator going away mid-job is its own business.
+            scope.spawn(move || take_jobs(stream, worker));
+        }
+    });
+    Ok(())
+}
+
+fn take_jobs(stream: TcpStream, worker: &Worker) -> io::Result<()> {
+    stream.set_read_timeout(Some(distributed::WORKER_TIMEOUT))?;
+    stream.set_write_timeout(Some(distributed::WORKER_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    loop {
+        let job = match Job::read(&mut reader) {
+            Ok(Some(job)) => job,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                // Nothing more on this connection can be trusted.
+                return distributed::write_escapes(&stream, Err(&e.to_string()));
+            }
+            Err(e) => return Err(e),
+        };
+        let start = Instant::now();
+        let _span = log::span(
+            Level::Debug,
+            "job",
+            format_args!("rows={:?} size={}x{}", job.rows, job.bounds.0, job.bounds.1),
+        );
+        let options = match job.options(&worker.frame.options) {
+            Ok(options) => options,
+            Err(message) => {
+                distributed::write_escapes(&stream, Err(&message))?;
+                continue;
+            }
+        };
+        let escapes = Renderer::new(options).render_row_escapes(job.rows.clone());
+        distributed::write_escapes(&stream, Ok(&escapes))?;
+        if !worker.frame.quiet {
+            eprintln!(
+                "rendered rows {}..{} of {}x{} in {:.2?}",
+                job.rows.start,
+                job.rows.end,
+                job.bounds.0,
+                job.bounds.1,
+                start.elapsed()
+            );
+        }
+    }
+}